@@ -0,0 +1,158 @@
+//! Writes the same 10k vectors into two databases on disk, one pushed at a
+//! time and the other as one batch, and prints how many calls each made to
+//! write to its file, every one of which is a system call, and how long it took.
+
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IoSlice, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, process};
+use vectoria::db::DatabaseOptions;
+use vectoria::vio::{SyncData, Truncate};
+
+const DIM_SIZE: u32 = 32;
+const VECTORS: usize = 10_000;
+
+/// Vectors of uniform components in `[-1, 1)`, the same for the same `seed`.
+fn synthetic(seed: u64, count: usize) -> Vec<Vec<f32>> {
+    let mut state = seed.max(1);
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 40) as f32 / (1u64 << 24) as f32 * 2f32 - 1f32
+    };
+    (0..count)
+        .map(|_| (0..DIM_SIZE).map(|_| next()).collect())
+        .collect()
+}
+
+/// File that counts the calls made to write to it.
+struct Counted {
+    file: File,
+    writes: Arc<AtomicUsize>,
+}
+
+impl Read for Counted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.file.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.file.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for Counted {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Truncate for Counted {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)
+    }
+}
+
+impl SyncData for Counted {
+    fn sync_data(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+/// Calls made to write the records and the time it took.
+struct Measured {
+    writes: usize,
+    elapsed: Duration,
+}
+
+/// Pushes `vectors` into a database at `path`, together if `batched`.
+fn measure(path: &Path, vectors: &[Vec<f32>], batched: bool) -> Result<Measured, Box<dyn Error>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    let writes = Arc::new(AtomicUsize::new(0));
+    let fd = Counted {
+        file,
+        writes: writes.clone(),
+    };
+    let mut db = DatabaseOptions::new(DIM_SIZE).create("batch", Box::new(fd))?;
+    let before = writes.load(Ordering::Relaxed);
+    let start = Instant::now();
+    if batched {
+        db.push_many(vectors)?;
+    } else {
+        for vector in vectors {
+            db.push(vector)?;
+        }
+    }
+    let elapsed = start.elapsed();
+    if db.count()? != vectors.len() as u64 {
+        return Err("some vectors went missing".into());
+    }
+    Ok(Measured {
+        writes: writes.load(Ordering::Relaxed) - before,
+        elapsed,
+    })
+}
+
+fn run(dir: &Path) -> Result<(Measured, Measured), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let vectors = synthetic(42, VECTORS);
+    let one_by_one = measure(&dir.join("one_by_one.db"), &vectors, false)?;
+    let batched = measure(&dir.join("batched.db"), &vectors, true)?;
+    if fs::read(dir.join("one_by_one.db"))? != fs::read(dir.join("batched.db"))? {
+        return Err("the files differ".into());
+    }
+    Ok((one_by_one, batched))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let dir = env::temp_dir().join(format!("vectoria-batch-write-{}", process::id()));
+    let result = run(&dir);
+    fs::remove_dir_all(&dir)?;
+    let (one_by_one, batched) = result?;
+    println!("{VECTORS} records of {DIM_SIZE} dimensions");
+    for (name, measured) in [("one by one", one_by_one), ("batched", batched)] {
+        println!(
+            "{name:>10}: {:>7} writes in {:?}",
+            measured.writes, measured.elapsed
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_write_works() {
+        let dir = env::temp_dir().join(format!("vectoria-batch-write-test-{}", process::id()));
+        let result = run(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let (one_by_one, batched) = result.unwrap();
+        assert!(one_by_one.writes >= VECTORS);
+        // a vectored write takes up to a thousand or so buffers at once
+        assert!(batched.writes * 100 < one_by_one.writes, "{}", batched.writes);
+    }
+}
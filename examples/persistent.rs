@@ -0,0 +1,120 @@
+//! Imports a generated fvecs file into a database on disk, searches it,
+//! then reopens it from scratch and checks that it answers the same.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+use vectoria::db::{DbIndex, SearchRequest};
+use vectoria::ms::{self, ManagementSystem};
+
+const DIM_SIZE: u32 = 8;
+const VECTORS: usize = 1000;
+const QUERIES: usize = 20;
+
+/// Vectors of uniform components in `[-1, 1)`, the same for the same `seed`.
+fn synthetic(seed: u64, count: usize) -> Vec<Vec<f32>> {
+    let mut state = seed.max(1);
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 40) as f32 / (1u64 << 24) as f32 * 2f32 - 1f32
+    };
+    (0..count)
+        .map(|_| (0..DIM_SIZE).map(|_| next()).collect())
+        .collect()
+}
+
+fn write_fvecs(path: &Path, vectors: &[Vec<f32>]) -> std::io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    for vector in vectors {
+        w.write_all(&(vector.len() as i32).to_le_bytes())?;
+        for x in vector {
+            w.write_all(&x.to_le_bytes())?;
+        }
+    }
+    w.flush()
+}
+
+/// Neighbors of each of the queries, closest first.
+type Answers = Vec<Vec<(DbIndex, f32)>>;
+
+/// Searches the database `name` of `system` for the 10 neighbors of every query.
+fn search<H: ms::DbHandle>(
+    system: &ManagementSystem<H>,
+    name: &str,
+    queries: &[Vec<f32>],
+) -> Result<Answers, ms::Error> {
+    let db = system.get(name)?.ok_or(ms::Error::NotFound(name.to_string()))?;
+    let mut db = db.lock().unwrap();
+    db.build_index()?;
+    queries
+        .iter()
+        .map(|query| Ok(db.query(&SearchRequest::new(query, 10))?.results))
+        .collect()
+}
+
+/// Creates a database under `dir`, which the system makes itself, imports a dataset into it and searches it,
+/// then opens it again with nothing in memory and searches it once more.
+/// Returns the answers before and after, which should be the same.
+fn run(dir: &Path) -> Result<(Answers, Answers), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let dataset = dir.join("dataset.fvecs");
+    write_fvecs(&dataset, &synthetic(42, VECTORS))?;
+    let queries = synthetic(7, QUERIES);
+
+    let root = dir.join("databases");
+    let mut system = ManagementSystem::new_fs(&root);
+    let db = system.create("vectors", DIM_SIZE)?;
+    let imported = {
+        let mut db = db.lock().unwrap();
+        let ids = db.import_fvecs(&mut BufReader::new(File::open(&dataset)?), None)?;
+        db.flush()?;
+        ids.len()
+    };
+    drop(db);
+    let before = search(&system, "vectors", &queries)?;
+    drop(system);
+
+    let system = ManagementSystem::new_fs(&root);
+    let db = system.get("vectors")?.ok_or("the database is gone")?;
+    let count = db.lock().unwrap().count()?;
+    if count != imported as u64 {
+        return Err(format!("imported {imported} vectors but found {count}").into());
+    }
+    let after = search(&system, "vectors", &queries)?;
+    Ok((before, after))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let dir = match env::args().nth(1) {
+        Some(dir) => PathBuf::from(dir),
+        None => env::temp_dir().join(format!("vectoria-persistent-{}", process::id())),
+    };
+    let result = run(&dir);
+    fs::remove_dir_all(&dir)?;
+    let (before, after) = result?;
+    if before != after {
+        return Err("the reopened database answers differently".into());
+    }
+    println!("{} queries answered the same after reopening", before.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persistent_works() {
+        let dir = env::temp_dir().join(format!("vectoria-persistent-test-{}", process::id()));
+        let result = run(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let (before, after) = result.unwrap();
+        assert_eq!(before.len(), QUERIES);
+        assert!(before.iter().all(|results| results.len() == 10));
+        assert_eq!(before, after);
+    }
+}
@@ -0,0 +1,55 @@
+//! Pushes a small synthetic dataset into an in-memory database,
+//! indexes it and prints the neighbors of one of its vectors.
+
+use std::io::Cursor;
+use vectoria::db::{Database, DatabaseOptions, DbIndex, Error, SearchRequest};
+
+const DIM_SIZE: u32 = 16;
+const VECTORS: usize = 500;
+
+/// Vectors of uniform components in `[-1, 1)`, the same for the same `seed`.
+fn synthetic(seed: u64, count: usize) -> Vec<Vec<f32>> {
+    let mut state = seed.max(1);
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 40) as f32 / (1u64 << 24) as f32 * 2f32 - 1f32
+    };
+    (0..count)
+        .map(|_| (0..DIM_SIZE).map(|_| next()).collect())
+        .collect()
+}
+
+/// Returns the `k` neighbors found for vector `of`, closest first.
+fn run(of: DbIndex, k: usize) -> Result<Vec<(DbIndex, f32)>, Error> {
+    let mut db: Database = DatabaseOptions::new(DIM_SIZE)
+        .create("quickstart", Box::new(Cursor::new(Vec::new())))?;
+    let dataset = synthetic(42, VECTORS);
+    db.push_many(&dataset)?;
+    db.build_index()?;
+    let response = db.query(&SearchRequest::new(&dataset[of as usize], k))?;
+    Ok(response.results)
+}
+
+fn main() -> Result<(), Error> {
+    let (of, k) = (7, 5);
+    println!("{k} nearest neighbors of vector {of} among {VECTORS}:");
+    for (id, distance) in run(of, k)? {
+        println!("{id:>6}  {distance:.4}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quickstart_works() {
+        let results = run(7, 5).unwrap();
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0], (7, 0f32));
+        assert!(results.windows(2).all(|w| w[0].1 <= w[1].1));
+    }
+}
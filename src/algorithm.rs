@@ -1,2 +1,3 @@
 pub(crate) mod construct;
+pub(crate) mod pca;
 pub(crate) mod search;
\ No newline at end of file
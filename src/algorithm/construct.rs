@@ -0,0 +1,185 @@
+use crate::ds::graph::{Graph, NdGraph};
+use crate::ext::rand::XorShift;
+use std::collections::HashMap;
+
+/// Pairs a [DistanceCache] remembers at most.
+const DISTANCE_CACHE_CAPACITY: usize = 4096;
+
+/// # Distance Cache
+/// Distances between pairs of nodes computed while inserting one vector,
+/// which visits many of the same nodes again on every layer down.
+/// Once full, further distances are computed but not remembered.
+#[derive(Default)]
+pub(crate) struct DistanceCache {
+    pairs: HashMap<(u32, u32), f32>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl DistanceCache {
+    pub(crate) fn get_or_compute(&mut self, a: u32, b: u32, compute: impl FnOnce() -> f32) -> f32 {
+        let key = (a.min(b), a.max(b));
+        if let Some(distance) = self.pairs.get(&key) {
+            self.hits += 1;
+            return *distance;
+        }
+        self.misses += 1;
+        let distance = compute();
+        if self.pairs.len() < DISTANCE_CACHE_CAPACITY {
+            self.pairs.insert(key, distance);
+        }
+        distance
+    }
+}
+
+/// Highest level [random_level] draws, which takes far more nodes than
+/// an index holds to be reached by chance at the usual multiplier.
+pub(crate) const MAX_LEVEL: u32 = 16;
+
+/// Draws the topmost layer a new node takes part in, each one up being
+/// `exp(1 / multiplier)` times less likely than the one below, which is
+/// `m` times for the usual `1 / ln(m)`, up to [MAX_LEVEL].
+pub(crate) fn random_level(rng: &mut XorShift, multiplier: f32) -> u32 {
+    let uniform = 1f32 - rng.next_f32();
+    ((-uniform.ln() * multiplier).floor() as u32).min(MAX_LEVEL)
+}
+
+/// Chance of [random_level] drawing `level` or above by `multiplier`.
+pub(crate) fn level_share(level: u32, multiplier: f32) -> f64 {
+    if level > MAX_LEVEL {
+        return 0f64;
+    }
+    (-(level as f64) / multiplier as f64).exp()
+}
+
+/// Picks up to `m` of `candidates`, which are sorted closest first by their
+/// distance to some base node. If `diverse`, a candidate is preferred if it's
+/// closer to the base than to any picked so far, so that the picks spread out
+/// in different directions. The rest fill up the remaining places, closest
+/// first. `distance` measures between two nodes.
+pub(crate) fn select_neighbors(
+    candidates: &[(u32, f32)],
+    m: usize,
+    diverse: bool,
+    distance: impl Fn(u32, u32) -> f32,
+) -> Vec<(u32, f32)> {
+    let (mut selected, mut discarded) = (Vec::<(u32, f32)>::with_capacity(m), vec![]);
+    for (candidate, to_base) in candidates {
+        if selected.len() >= m {
+            break;
+        }
+        if !diverse
+            || selected
+                .iter()
+                .all(|(picked, _)| distance(*candidate, *picked) > *to_base)
+        {
+            selected.push((*candidate, *to_base));
+        } else {
+            discarded.push((*candidate, *to_base));
+        }
+    }
+    let lacking = m.saturating_sub(selected.len());
+    selected.extend(discarded.into_iter().take(lacking));
+    selected
+}
+
+/// Connects `node` to `m` of `candidates`, which are sorted closest first,
+/// then trims the neighbors that end up with more than `max_degree`
+/// connections back down, both by [select_neighbors].
+pub(crate) fn link(
+    graph: &mut NdGraph,
+    node: u32,
+    candidates: &[(u32, f32)],
+    (m, max_degree): (usize, usize),
+    diverse: bool,
+    distance: impl Fn(u32, u32) -> f32,
+) {
+    let candidates = candidates
+        .iter()
+        .filter(|(other, _)| *other != node)
+        .copied()
+        .collect::<Vec<_>>();
+    let neighbors = select_neighbors(&candidates, m, diverse, &distance);
+    for (neighbor, to_node) in &neighbors {
+        graph.connect(node, *neighbor, *to_node).unwrap();
+    }
+    for (neighbor, _) in neighbors {
+        shrink(graph, neighbor, max_degree, diverse, &distance);
+    }
+}
+
+/// Trims the neighbors of `node` back down to `max_degree` by [select_neighbors]
+/// if it has more.
+pub(crate) fn shrink(
+    graph: &mut NdGraph,
+    node: u32,
+    max_degree: usize,
+    diverse: bool,
+    distance: impl Fn(u32, u32) -> f32,
+) {
+    if graph.degree(node) <= max_degree {
+        return;
+    }
+    let mut vertices = graph.get_vertices(node);
+    vertices.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+    let kept = select_neighbors(&vertices, max_degree, diverse, &distance);
+    for (far, _) in vertices {
+        if !kept.iter().any(|(k, _)| *k == far) {
+            graph.disconnect(node, far);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::construct::{level_share, link, random_level, select_neighbors, MAX_LEVEL};
+    use crate::ds::graph::{Graph, NdGraph};
+    use crate::ext::rand::XorShift;
+
+    #[test]
+    fn random_level_is_capped() {
+        let mut rng = XorShift::new(2);
+        let levels = (0..1000).map(|_| random_level(&mut rng, 1e6)).collect::<Vec<_>>();
+        assert!(levels.iter().all(|level| *level <= MAX_LEVEL));
+        assert!(levels.contains(&MAX_LEVEL));
+        assert_eq!(level_share(MAX_LEVEL + 1, 1e6), 0f64);
+        // the usual multiplier for m = 16 rarely leaves the base
+        let multiplier = 1f32 / 16f32.ln();
+        let above = (0..1000).filter(|_| random_level(&mut rng, multiplier) > 0).count();
+        assert!((30..100).contains(&above), "{above}");
+    }
+
+    #[test]
+    fn select_neighbors_works() {
+        // base at 0, candidates at 1, 1.1 and -2 on a line
+        let points = [0f32, 1f32, 1.1f32, -2f32];
+        let distance = |a: u32, b: u32| (points[a as usize] - points[b as usize]).abs();
+        let candidates = [(1, 1f32), (2, 1.1f32), (3, 2f32)];
+        // 2 is right behind 1, while 3 lies the other way
+        assert_eq!(
+            select_neighbors(&candidates, 2, true, distance),
+            vec![(1, 1f32), (3, 2f32)]
+        );
+        assert_eq!(select_neighbors(&candidates, 3, true, distance).len(), 3);
+        // or just the closest
+        assert_eq!(
+            select_neighbors(&candidates, 2, false, distance),
+            vec![(1, 1f32), (2, 1.1f32)]
+        );
+    }
+
+    #[test]
+    fn link_works() {
+        let points = [0f32, 3f32, -1f32, 0.5f32, 1.5f32];
+        let distance = |a: u32, b: u32| (points[a as usize] - points[b as usize]).abs();
+        let mut graph = NdGraph::new();
+        graph.push_many(5);
+        graph.connect(0, 1, 3f32).unwrap();
+        graph.connect(0, 2, 1f32).unwrap();
+
+        link(&mut graph, 3, &[(0, 0.5), (4, 1f32), (1, 2.5f32)], (2, 2), true, distance);
+        assert_eq!(graph.get_neighbors(3), vec![0, 4]);
+        // 0 kept 3 and 2 on either side of it, dropping 1 behind 3
+        assert_eq!(graph.get_neighbors(0), vec![2, 3]);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::ext::rand::XorShift;
+
+/// Power iterations a component may take before it's taken as it is.
+const MAX_ITERATIONS: usize = 1000;
+/// Change in the direction of a component, by the sum of its squares,
+/// below which it's taken as converged.
+const TOLERANCE: f64 = 1e-12;
+
+/// # Covariance
+/// Sums of vectors and of their outer products, accumulated one vector at a
+/// time in [f64] so that a single pass over them yields the covariance matrix.
+pub(crate) struct Covariance {
+    dim_size: usize,
+    count: u64,
+    sum: Vec<f64>,
+    /// Upper triangle of the sum of outer products, row by row.
+    products: Vec<f64>,
+}
+
+/// # Principal Component
+/// A direction of the data along with the variance it carries.
+#[derive(Debug, Clone)]
+pub(crate) struct Component {
+    pub variance: f64,
+    /// Of unit length.
+    pub direction: Vec<f64>,
+}
+
+impl Covariance {
+    pub(crate) fn new(dim_size: usize) -> Covariance {
+        Covariance {
+            dim_size,
+            count: 0,
+            sum: vec![0f64; dim_size],
+            products: vec![0f64; dim_size * (dim_size + 1) / 2],
+        }
+    }
+
+    pub(crate) fn add(&mut self, vector: &[f32]) {
+        debug_assert_eq!(vector.len(), self.dim_size);
+        self.count += 1;
+        let mut at = 0;
+        for (i, x) in vector.iter().enumerate() {
+            let x = *x as f64;
+            self.sum[i] += x;
+            for y in &vector[i..] {
+                self.products[at] += x * *y as f64;
+                at += 1;
+            }
+        }
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub(crate) fn mean(&self) -> Vec<f64> {
+        let count = self.count.max(1) as f64;
+        self.sum.iter().map(|s| s / count).collect()
+    }
+
+    /// The sample covariance in whole, row by row,
+    /// or all zeros with fewer than two vectors.
+    pub(crate) fn matrix(&self) -> Vec<f64> {
+        let d = self.dim_size;
+        let mut matrix = vec![0f64; d * d];
+        if self.count < 2 {
+            return matrix;
+        }
+        let (mean, n) = (self.mean(), self.count as f64);
+        let mut at = 0;
+        for i in 0..d {
+            for j in i..d {
+                let c = (self.products[at] - n * mean[i] * mean[j]) / (n - 1f64);
+                matrix[i * d + j] = c;
+                matrix[j * d + i] = c;
+                at += 1;
+            }
+        }
+        matrix
+    }
+
+    /// Sum of the variances along every axis, which the components split among them.
+    pub(crate) fn total_variance(&self) -> f64 {
+        let matrix = self.matrix();
+        (0..self.dim_size).map(|i| matrix[i * self.dim_size + i]).sum()
+    }
+
+    /// The `k` components of the most variance, most first, found by power
+    /// iteration, each taken out of the matrix before looking for the next.
+    /// Iterates are kept orthogonal to the components found so far, lest
+    /// rounding errors drift them back once there's little variance left.
+    pub(crate) fn top_components(&self, k: usize) -> Vec<Component> {
+        let d = self.dim_size;
+        let mut matrix = self.matrix();
+        let mut rng = XorShift::new(d as u64);
+        let mut components = Vec::with_capacity(k);
+        for _ in 0..k.min(d) {
+            let mut direction = (0..d).map(|_| rng.next_f32() as f64 - 0.5).collect::<Vec<_>>();
+            orthogonalize(&mut direction, &components);
+            normalize(&mut direction);
+            for _ in 0..MAX_ITERATIONS {
+                let mut next = multiply(&matrix, &direction);
+                orthogonalize(&mut next, &components);
+                if !normalize(&mut next) {
+                    // nothing left along any direction
+                    break;
+                }
+                let change = next
+                    .iter()
+                    .zip(&direction)
+                    .map(|(a, b)| (a - b) * (a - b))
+                    .sum::<f64>();
+                direction = next;
+                if change < TOLERANCE {
+                    break;
+                }
+            }
+            let variance = dot(&direction, &multiply(&matrix, &direction)).max(0f64);
+            for i in 0..d {
+                for j in 0..d {
+                    matrix[i * d + j] -= variance * direction[i] * direction[j];
+                }
+            }
+            components.push(Component {
+                variance,
+                direction,
+            });
+        }
+        components
+    }
+}
+
+/// Takes whatever `vector` has along any of `components` out of it.
+fn orthogonalize(vector: &mut [f64], components: &[Component]) {
+    for component in components {
+        let along = dot(vector, &component.direction);
+        for (x, c) in vector.iter_mut().zip(&component.direction) {
+            *x -= along * c;
+        }
+    }
+}
+
+fn multiply(matrix: &[f64], vector: &[f64]) -> Vec<f64> {
+    matrix
+        .chunks_exact(vector.len())
+        .map(|row| dot(row, vector))
+        .collect()
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Scales `vector` to unit length, or returns false if it's zero.
+fn normalize(vector: &mut [f64]) -> bool {
+    let norm = dot(vector, vector).sqrt();
+    if norm > 0f64 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    norm > 0f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::pca::Covariance;
+
+    #[test]
+    fn top_components_work() {
+        // eight times the variance along (1, 1, 0) as along (0, 0, 1)
+        let mut covariance = Covariance::new(3);
+        for t in [-2f32, -1f32, 1f32, 2f32] {
+            covariance.add(&[t + 3f32, t + 3f32, 1f32]);
+            covariance.add(&[3f32, 3f32, 1f32 + t / 2f32]);
+        }
+        assert_eq!(covariance.count(), 8);
+        assert_eq!(covariance.mean(), vec![3f64, 3f64, 1f64]);
+        let components = covariance.top_components(3);
+        assert_eq!(components.len(), 3);
+        let first = &components[0];
+        let half = 0.5f64.sqrt();
+        assert!((first.direction[0].abs() - half).abs() < 1e-6);
+        assert!((first.direction[1] - first.direction[0]).abs() < 1e-6);
+        assert!(first.direction[2].abs() < 1e-6);
+        assert!(components[1].direction[2].abs() > 1f64 - 1e-6);
+        let total = covariance.total_variance();
+        assert!((first.variance / components[1].variance - 8f64).abs() < 1e-6);
+        assert!((first.variance + components[1].variance - total).abs() < 1e-9);
+        assert!(components[2].variance < 1e-9);
+    }
+}
@@ -0,0 +1,500 @@
+use crate::db::DbIndex;
+use crate::ds::graph::{Graph, NdGraph};
+use crate::metric::Metric;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+/// A node along with its distance to the query and its rank, ordered by the
+/// distance, then the rank, then the node, so that ties always break the same way.
+#[derive(Debug, Clone, Copy)]
+struct Near(f32, u32, u32);
+
+impl PartialEq for Near {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Near {}
+
+impl PartialOrd for Near {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Near {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .total_cmp(&other.0)
+            .then(self.1.cmp(&other.1))
+            .then(self.2.cmp(&other.2))
+    }
+}
+
+/// # Frontier
+/// Candidates a search of a layer has scored but not expanded yet, along
+/// with how many of the closest nodes found it keeps, see [CandidateSource].
+/// Nodes are numbered the way the index stores them, in the order they
+/// were inserted.
+pub struct Frontier<'a> {
+    candidates: &'a mut BinaryHeap<Reverse<Near>>,
+    nearest: &'a BinaryHeap<Near>,
+    ef: usize,
+}
+
+#[cfg_attr(not(feature = "unstable-search"), allow(dead_code))]
+impl Frontier<'_> {
+    /// Closest candidate and its distance, left in.
+    pub fn peek(&self) -> Option<(u32, f32)> {
+        self.candidates
+            .peek()
+            .map(|Reverse(Near(distance, _, node))| (*node, *distance))
+    }
+
+    /// Takes the closest candidate out, along with its distance.
+    pub fn pop(&mut self) -> Option<(u32, f32)> {
+        self.candidates
+            .pop()
+            .map(|Reverse(Near(distance, _, node))| (node, distance))
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// Closest nodes found so far, of which no more than [Frontier::ef] are kept.
+    pub fn kept(&self) -> usize {
+        self.nearest.len()
+    }
+
+    pub fn ef(&self) -> usize {
+        self.ef
+    }
+
+    /// Whether as many nodes as kept are found and the closest candidate
+    /// is farther than all of them, so that expanding it is unlikely to
+    /// find closer ones. That's where [BestFirst] stops.
+    pub fn converged(&self) -> bool {
+        match (self.candidates.peek(), self.nearest.peek()) {
+            (Some(Reverse(closest)), Some(farthest)) => {
+                self.nearest.len() >= self.ef && closest > farthest
+            }
+            _ => false,
+        }
+    }
+}
+
+/// # Candidate Source
+/// Picks the nodes a search of a layer expands, their neighbors not seen
+/// before being scored and offered to the [Frontier] in turn.
+pub trait CandidateSource {
+    /// Takes the node to expand next out of `frontier`, or returns `None`
+    /// to end the search of the layer.
+    fn next(&mut self, frontier: &mut Frontier) -> Option<u32>;
+}
+
+/// # Best First
+/// Expands the closest candidate until the frontier [converged](Frontier::converged),
+/// which is how the index is searched unless told otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BestFirst;
+
+impl CandidateSource for BestFirst {
+    #[inline]
+    fn next(&mut self, frontier: &mut Frontier) -> Option<u32> {
+        if frontier.converged() {
+            return None;
+        }
+        frontier.pop().map(|(node, _)| node)
+    }
+}
+
+/// # Scorer
+/// Measures the vectors of the nodes a search comes across against the
+/// query, the lower the closer, as in quantized scoring or another metric.
+/// The [Metric] of the index is what it's measured by unless told otherwise.
+pub trait Scorer {
+    fn score(&self, query: &[f32], vector: &[f32]) -> f32;
+}
+
+impl Scorer for Metric {
+    #[inline]
+    fn score(&self, query: &[f32], vector: &[f32]) -> f32 {
+        self.distance(query, vector)
+    }
+}
+
+impl<S: Scorer + ?Sized> Scorer for &S {
+    #[inline]
+    fn score(&self, query: &[f32], vector: &[f32]) -> f32 {
+        (**self).score(query, vector)
+    }
+}
+
+/// Outcome of [search_layer].
+pub(crate) struct LayerSearch {
+    /// Up to `ef` nodes closest to the query, closest first.
+    pub nearest: Vec<(u32, f32)>,
+}
+
+/// # Seen
+/// Nodes a search has come across, forgotten on [Seen::reset].
+pub(crate) trait Seen {
+    /// Forgets everything before a search of a graph of `nodes` nodes.
+    fn reset(&mut self, nodes: u32);
+    /// Whether `node` wasn't seen before, remembering it from now on.
+    fn insert(&mut self, node: u32) -> bool;
+}
+
+impl Seen for HashSet<u32> {
+    fn reset(&mut self, _nodes: u32) {
+        self.clear();
+    }
+
+    fn insert(&mut self, node: u32) -> bool {
+        HashSet::insert(self, node)
+    }
+}
+
+/// # Stamp Set
+/// Nodes seen, each stamped with the generation of the search that saw it,
+/// so that forgetting them is moving on to the next generation rather
+/// than clearing them. Takes a stamp per node of the graph.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StampSet {
+    stamps: Vec<u32>,
+    generation: u32,
+}
+
+impl Seen for StampSet {
+    fn reset(&mut self, nodes: u32) {
+        if self.stamps.len() < nodes as usize {
+            self.stamps.resize(nodes as usize, 0);
+        }
+        self.generation = self.generation.wrapping_add(1);
+        if self.generation == 0 {
+            // stamps of the generation wrapped around to would be taken as seen
+            self.stamps.fill(0);
+            self.generation = 1;
+        }
+    }
+
+    fn insert(&mut self, node: u32) -> bool {
+        let stamp = &mut self.stamps[node as usize];
+        let fresh = *stamp != self.generation;
+        *stamp = self.generation;
+        fresh
+    }
+}
+
+/// # Layer Scratch
+/// What [search_layer_in] works in and leaves its outcome in, kept between
+/// searches so that the buffers only grow until they fit.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LayerScratch<S> {
+    seen: S,
+    candidates: BinaryHeap<Reverse<Near>>,
+    farthest: BinaryHeap<Near>,
+    /// Up to `ef` nodes closest to the query, closest first.
+    pub nearest: Vec<(u32, f32)>,
+    /// Nodes in the order they were compared against the query, entries first,
+    /// only recorded if `trace`.
+    pub visited: Vec<u32>,
+    pub trace: bool,
+}
+
+/// Best-first search of one layer from `entries`, whose distances are known,
+/// keeping the `ef` closest nodes found. `distance` measures a node against
+/// the query, and is called at most `budget` times if there's one, which
+/// is decreased accordingly.
+pub(crate) fn search_layer(
+    graph: &NdGraph,
+    entries: &[(u32, f32)],
+    ef: usize,
+    budget: &mut Option<u64>,
+    distance: impl Fn(u32) -> f32,
+) -> LayerSearch {
+    let mut scratch = LayerScratch::<HashSet<u32>>::default();
+    let source = &mut BestFirst;
+    search_layer_in(graph, entries, (ef, budget), distance, |node| node, source, &mut scratch);
+    LayerSearch {
+        nearest: scratch.nearest,
+    }
+}
+
+/// Same as [search_layer], breaking ties in distance by `rank` of the nodes,
+/// lowest first, rather than by the nodes themselves, expanding those `source`
+/// picks and working in `scratch`, where the outcome is left. Returns the
+/// number of nodes visited, entries included.
+pub(crate) fn search_layer_in<S: Seen, C: CandidateSource + ?Sized>(
+    graph: &NdGraph,
+    entries: &[(u32, f32)],
+    (ef, budget): (usize, &mut Option<u64>),
+    distance: impl Fn(u32) -> f32,
+    rank: impl Fn(u32) -> u32,
+    source: &mut C,
+    scratch: &mut LayerScratch<S>,
+) -> usize {
+    let ef = ef.max(1);
+    let near = |node: u32, distance: f32| Near(distance, rank(node), node);
+    let LayerScratch {
+        seen,
+        candidates,
+        farthest: nearest,
+        nearest: found,
+        visited,
+        trace,
+    } = scratch;
+    seen.reset(graph.len());
+    candidates.clear();
+    nearest.clear();
+    visited.clear();
+    for (node, distance) in entries {
+        seen.insert(*node);
+        candidates.push(Reverse(near(*node, *distance)));
+        nearest.push(near(*node, *distance));
+        if *trace {
+            visited.push(*node);
+        }
+    }
+    let mut count = entries.len();
+    while nearest.len() > ef {
+        nearest.pop();
+    }
+
+    'expand: loop {
+        let mut frontier = Frontier {
+            candidates: &mut *candidates,
+            nearest: &*nearest,
+            ef,
+        };
+        let Some(closest) = source.next(&mut frontier) else {
+            break;
+        };
+        for (neighbor, _) in graph.vertices_of(closest) {
+            let neighbor = *neighbor;
+            if !seen.insert(neighbor) {
+                continue;
+            }
+            if let Some(left) = budget {
+                if *left == 0 {
+                    break 'expand;
+                }
+                *left -= 1;
+            }
+            let near = near(neighbor, distance(neighbor));
+            count += 1;
+            if *trace {
+                visited.push(neighbor);
+            }
+            if nearest.len() < ef || nearest.peek().is_some_and(|farthest| near < *farthest) {
+                candidates.push(Reverse(near));
+                nearest.push(near);
+                if nearest.len() > ef {
+                    nearest.pop();
+                }
+            }
+        }
+    }
+
+    found.clear();
+    while let Some(Near(distance, _, node)) = nearest.pop() {
+        found.push((node, distance));
+    }
+    found.reverse();
+    count
+}
+
+/// The `k` closest across `lists` of `(id, distance)`, each sorted closest
+/// first with ties broken by id, sorted the same way, see [merge_top_k_iter].
+#[cfg_attr(not(feature = "rayon"), allow(dead_code))]
+pub(crate) fn merge_top_k(lists: &[&[(DbIndex, f32)]], k: usize) -> Vec<(DbIndex, f32)> {
+    merge_top_k_iter(lists.iter().map(|list| list.iter().copied()), k)
+}
+
+/// Same as [merge_top_k], taking the lists as they come rather than
+/// holding them, so that only their heads are kept at a time.
+///
+/// An id found in more than one list is kept once, at the smaller distance.
+/// Searches of different shards can tell the same vector apart by more than
+/// rounding, as when one of them was approximate or an older copy of it
+/// was left in the other, and the closer is the one more likely right.
+#[cfg_attr(not(feature = "rayon"), allow(dead_code))]
+pub(crate) fn merge_top_k_iter<I: Iterator<Item = (DbIndex, f32)>>(
+    lists: impl IntoIterator<Item = I>,
+    k: usize,
+) -> Vec<(DbIndex, f32)> {
+    let mut lists = lists.into_iter().collect::<Vec<_>>();
+    // ranked by id, the list it's the head of standing in for the node
+    let mut heads = BinaryHeap::with_capacity(lists.len());
+    for (index, list) in lists.iter_mut().enumerate() {
+        if let Some((id, distance)) = list.next() {
+            heads.push(Reverse(Near(distance, id, index as u32)));
+        }
+    }
+    let mut merged = Vec::with_capacity(k);
+    let mut taken = HashSet::with_capacity(k);
+    while merged.len() < k {
+        let Some(Reverse(Near(distance, id, index))) = heads.pop() else {
+            break;
+        };
+        // the first of an id is the closest, the later ones being farther
+        if taken.insert(id) {
+            merged.push((id, distance));
+        }
+        if let Some((id, distance)) = lists[index as usize].next() {
+            heads.push(Reverse(Near(distance, id, index)));
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::search::{
+        merge_top_k, merge_top_k_iter, search_layer, search_layer_in, BestFirst, CandidateSource,
+        Frontier, LayerScratch, StampSet,
+    };
+    use crate::ds::graph::{Graph, NdGraph};
+    use std::collections::BTreeSet;
+
+    /// Expands the closest candidate, no more than `left` of them.
+    struct Throttled {
+        left: usize,
+        expanded: Vec<u32>,
+    }
+
+    impl CandidateSource for Throttled {
+        fn next(&mut self, frontier: &mut Frontier) -> Option<u32> {
+            if self.left == 0 {
+                return None;
+            }
+            self.left -= 1;
+            let (node, _) = frontier.pop()?;
+            self.expanded.push(node);
+            Some(node)
+        }
+    }
+
+    #[test]
+    fn search_layer_works() {
+        // points on a line chained in order, searched from one end
+        let mut graph = NdGraph::new();
+        graph.push_many(10);
+        for node in 0..9 {
+            graph.connect(node, node + 1, 1f32).unwrap();
+        }
+        let distance = |node: u32| (node as f32 - 7.2).abs();
+
+        let found = search_layer(&graph, &[(0, distance(0))], 3, &mut None, distance);
+        let nearest = found.nearest.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+        assert_eq!(nearest, vec![7, 8, 6]);
+
+        let mut scratch = LayerScratch::<StampSet> {
+            trace: true,
+            ..LayerScratch::default()
+        };
+        let mut budget = Some(4);
+        let entries = [(0, distance(0))];
+        let source = &mut BestFirst;
+        let visited =
+            search_layer_in(&graph, &entries, (3, &mut budget), distance, |n| n, source, &mut scratch);
+        assert_eq!(budget, Some(0));
+        assert_eq!((visited, scratch.visited.len()), (5, 5));
+        assert_eq!(scratch.visited[0], 0);
+        assert_eq!(scratch.nearest[0].0, 4);
+
+        // what the first search saw is forgotten by the next,
+        // even once the generations wrap around
+        scratch.seen.generation = u32::MAX - 1;
+        for _ in 0..3 {
+            search_layer_in(&graph, &entries, (3, &mut None), distance, |n| n, source, &mut scratch);
+            assert_eq!(scratch.nearest, found.nearest);
+        }
+    }
+
+    #[test]
+    fn candidate_source_works() {
+        // a chain with chords every five nodes
+        let mut graph = NdGraph::new();
+        graph.push_many(20);
+        for node in 0..19 {
+            graph.connect(node, node + 1, 1f32).unwrap();
+            if node + 5 < 20 {
+                graph.connect(node, node + 5, 5f32).unwrap();
+            }
+        }
+        let distance = |node: u32| (node as f32 - 17.5).abs();
+        let entries = [(0, distance(0))];
+        let mut scratch = LayerScratch::<StampSet> {
+            trace: true,
+            ..LayerScratch::default()
+        };
+
+        let source = &mut Throttled {
+            left: 3,
+            expanded: vec![],
+        };
+        let ef = (10, &mut None);
+        let visited = search_layer_in(&graph, &entries, ef, distance, |n| n, source, &mut scratch);
+        // expanded 0, then 5, then 10, scoring the neighbors of each once
+        assert_eq!(source.expanded, [0, 5, 10]);
+        let mut dictated = BTreeSet::from([0]);
+        for node in &source.expanded {
+            dictated.extend(graph.vertices_of(*node).iter().map(|(n, _)| *n));
+        }
+        assert_eq!(BTreeSet::from_iter(scratch.visited.iter().copied()), dictated);
+        assert_eq!((visited, scratch.visited.len()), (dictated.len(), dictated.len()));
+        assert_eq!(scratch.nearest[0].0, 15);
+
+        // never stopping short, it finds what best first does, going past
+        // where that converged
+        let source = &mut Throttled {
+            left: usize::MAX,
+            expanded: vec![],
+        };
+        search_layer_in(&graph, &entries, (3, &mut None), distance, |n| n, source, &mut scratch);
+        let unthrottled = (scratch.nearest.clone(), scratch.visited.len());
+        let best_first = &mut BestFirst;
+        search_layer_in(&graph, &entries, (3, &mut None), distance, |n| n, best_first, &mut scratch);
+        assert_eq!(unthrottled.0, scratch.nearest);
+        assert!(unthrottled.1 >= scratch.visited.len());
+    }
+
+    #[test]
+    fn merge_top_k_works() {
+        assert!(merge_top_k(&[], 3).is_empty());
+        assert!(merge_top_k(&[&[], &[]], 3).is_empty());
+        assert!(merge_top_k(&[&[(1, 0.5)]], 0).is_empty());
+
+        // shorter than k in all
+        let merged = merge_top_k(&[&[(4, 0.1), (2, 0.7)], &[], &[(9, 0.3)]], 10);
+        assert_eq!(merged, vec![(4, 0.1), (9, 0.3), (2, 0.7)]);
+
+        // duplicates keep the smaller distance, wherever it comes from
+        let a = [(1, 0.2), (3, 0.4), (5, 0.9)];
+        let b = [(3, 0.1), (1, 0.6), (7, 0.8)];
+        assert_eq!(merge_top_k(&[&a, &b], 3), vec![(3, 0.1), (1, 0.2), (7, 0.8)]);
+        assert_eq!(merge_top_k(&[&b, &a], 4), vec![(3, 0.1), (1, 0.2), (7, 0.8), (5, 0.9)]);
+
+        // ties in distance break by id, whichever list they're in
+        let a = [(8, 0.5), (9, 0.5)];
+        let b = [(2, 0.5), (8, 0.5)];
+        let c = [(5, 0.5)];
+        assert_eq!(
+            merge_top_k(&[&a, &b, &c], 3),
+            vec![(2, 0.5), (5, 0.5), (8, 0.5)]
+        );
+        assert_eq!(merge_top_k(&[&c, &b, &a], 4), merge_top_k(&[&a, &b, &c], 4));
+
+        // streamed from lists too long to hold
+        let every_other = |from: u32| (from..).step_by(2).map(|id| (id, id as f32));
+        let merged = merge_top_k_iter([every_other(0), every_other(1)], 5);
+        assert_eq!(merged.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+    }
+}
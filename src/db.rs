@@ -1,25 +1,187 @@
 use crate::ds::layer::HnswLayer;
-use crate::ext::io::MoveContent;
-use crate::ext::semaphore::LockAutoClear;
+use crate::ext::io::{write_all_vectored, MoveContent};
+use crate::ext::semaphore::{lock_pair, LockAutoClear, LockRank};
+use crate::metric::Metric;
+use crate::ops;
 use crate::vio;
+use crate::db::amplification::Amplification;
+use crate::db::cache::{Scratch, VectorCache};
+use crate::db::deadline::Deadline;
+use crate::db::history::History;
+use crate::db::fingerprint::RecordDigest;
+use crate::db::id::IdAllocator;
+use crate::db::iter::IterLog;
+use crate::db::group::GroupTable;
+use crate::db::keys::KeyTable;
+use crate::db::latency::Latencies;
+use crate::db::levels::LevelTable;
+use crate::db::maintain::Maintenance;
+use crate::db::memo::ResultCache;
+use crate::db::normal::Normalizations;
+use crate::db::placeholders::PlaceholderTable;
+use crate::db::queue::WriteQueue;
+use crate::db::readiness::BuildProgress;
+use crate::db::stats::StatCounters;
+use crate::db::storage::{RecordLayout, StorageCoordinator, VectorReader, VectorWriter};
+use crate::db::trace::Tracer;
+use crate::db::view::Snapshot;
+use crate::db::watch::Watch;
+use crate::ds::bloom::BloomFilter;
+use crate::index::{HnswIndex, SearchScratch, ShortcutCache};
 use crate::vio::dbheader::DbHeader;
-use crate::vio::RandomAccess;
+use crate::vio::layer::Decoded;
+use crate::vio::format;
+use crate::vio::{RandomAccess, SkippedLayer};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::cmp::min;
-use std::collections::{HashMap, LinkedList};
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, HashMap, LinkedList};
 use std::fmt::Formatter;
-use std::io::{Seek, SeekFrom};
-use std::rc::Rc;
-use std::sync::Mutex;
+use std::io::{IoSlice, Seek, SeekFrom};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex, RwLock};
 use std::{fmt, io};
 
+mod amplification;
+mod anomaly;
+mod bloom;
+mod borrowed;
+mod cache;
+mod cancel;
+mod config;
+mod deadline;
+#[cfg(test)]
+mod degenerate;
+mod diag;
+mod eval;
+mod export;
+mod fingerprint;
+mod flush;
+mod forecast;
+mod group;
+pub(crate) mod history;
+mod id;
+mod import;
+mod index;
+mod inspect;
+mod integrity;
+mod iter;
+mod keys;
+mod latency;
+mod levels;
+mod maintain;
+mod memo;
+mod memory;
+mod mmr;
+mod normal;
+mod offset;
+mod options;
+mod placeholders;
+mod plan;
+mod portable;
+mod prefix;
+mod presence;
+mod query;
+mod queue;
+mod quota;
+mod readiness;
+mod replay;
+mod sanitize;
+mod scan;
+mod schema;
+mod search;
+mod stats;
+mod storage;
+mod store;
+mod sync;
+mod trace;
+mod typed;
+mod view;
+mod watch;
+
+#[cfg(feature = "unstable-search")]
+pub use crate::algorithm::search::{BestFirst, CandidateSource, Frontier, Scorer};
+pub use crate::ds::layer::LayerDiag;
+pub use amplification::{AdvisoryConfig, WriteAdvisory};
+pub use anomaly::{Anomaly, OpenReport, Severity, SuggestedAction};
+pub use borrowed::{BorrowedDatabase, BorrowedRecords};
+pub use cache::CacheMode;
+pub use cancel::CancelToken;
+pub use config::{GrowthPolicy, HnswConfig, SearchParams};
+pub use eval::{EvalOptions, EvalReport};
+pub use export::{ExportFormat, PcaReport};
+pub use fingerprint::Fingerprint;
+pub use forecast::Forecast;
+pub use group::{GroupHit, GroupId, GroupScore};
+pub use history::{Generation, GenerationView};
+pub use id::IdStrategy;
+pub use import::{ImportProgress, ImportSession};
+pub use inspect::LayerView;
+pub use integrity::VerifyLevel;
+pub use iter::RecordIter;
+pub use keys::KeyTableParams;
+pub use latency::{Clock, HistogramSnapshot, MonotonicClock, Operation};
+pub use maintain::{MaintenanceReport, MaintenanceTask};
+pub use normal::{QueryVector, NORM_TOLERANCE};
+pub use offset::OffsetOp;
+pub use options::{DatabaseOptions, OptionsError};
+pub use plan::{PlannerConfig, SearchPlan};
+pub use portable::IndexFingerprint;
+pub use prefix::PrefixResults;
+pub use queue::PendingId;
+pub use quota::Quota;
+pub use readiness::{IndexPolicy, IndexState};
+pub use replay::ReplayReport;
+pub use sanitize::{SanitizePolicy, SanitizeReport};
+pub use schema::{Element, Expectations, SchemaMismatch};
+pub use stats::DbStats;
+pub use store::{FileStore, ObjectFetcher, ObjectStore, VectorStore};
+pub use sync::SyncPolicy;
+pub use trace::{Trace, TraceEvent, TracedSearch};
+pub use typed::TypedDatabase;
+pub use view::DatabaseView;
+pub use watch::ExternalChangePolicy;
+pub use query::{LayerTrace, SearchCursor, SearchRequest, SearchResponse, SearchTrace};
+
 pub type DbVector = Vec<f32>;
 pub type DbVectorSlice<'a> = &'a [f32];
 pub type DbIndex = u32;
 
+/// Vectors a database without an index holds at most to be checked for
+/// [near duplicates](Database::near_duplicates) pair by pair.
+const PAIRWISE_DUPLICATES_MAX: u64 = 4096;
+
 struct VectorHandle {
     dim_size: u32,
     data_section: u64,
+    alignment: Option<u32>,
+    allocator: IdAllocator,
+    sync_policy: SyncPolicy,
+    unsynced_bytes: u64,
+    bloom: Option<BloomFilter>,
+    bloom_offset: u64,
+    /// Whether the filter block on file matches the records.
+    bloom_fresh: bool,
+    /// Present if the database is append-only.
+    history: Option<History>,
+    amplification: Amplification,
+    /// Counts the writes to the records, each accounted for by [VectorHandle::amplified].
+    revision: u64,
+    /// Whether components are read as they are, see [vio::format::COMPONENTS_VERBATIM_SINCE].
+    verbatim: bool,
+    /// Holds the records instead of the data section of `fd` if present.
+    store: Option<Box<dyn VectorStore>>,
+    /// What [Database::stats] reads, shared with the database.
+    counters: Arc<StatCounters>,
+    /// Live [iterators](Database::iter) and what they have yet to read.
+    iterators: IterLog,
+    /// Present if the file keeps a placeholder table, see [Database::reserve_ids].
+    placeholders: Option<PlaceholderTable>,
+    /// Present once [fingerprinted](Database::fingerprint), kept up to date from then on.
+    digest: Option<RecordDigest>,
+    /// Where the layers begin if the file reserves room for them up to
+    /// the data section, see [DatabaseOptions::layer_section].
+    layers_offset: Option<u64>,
+    writer: VectorWriter,
     fd: Box<dyn RandomAccess>,
 }
 
@@ -28,81 +190,220 @@ impl VectorHandle {
         VectorHandle {
             dim_size: header.dim_size,
             data_section: header.data_section,
+            alignment: header.alignment,
+            allocator: IdAllocator::new(header.id_strategy),
+            sync_policy: SyncPolicy::default(),
+            unsynced_bytes: 0,
+            bloom: header.bloom.map(BloomFilter::new),
+            bloom_offset: header.size(),
+            bloom_fresh: false,
+            history: header.history.map(|_| History::new(header)),
+            amplification: Amplification::default(),
+            revision: 0,
+            verbatim: header.version >= vio::format::COMPONENTS_VERBATIM_SINCE,
+            store: None,
+            counters: Arc::new(StatCounters::new(header)),
+            iterators: IterLog::default(),
+            placeholders: header.placeholders.map(|capacity| {
+                PlaceholderTable::new(capacity, header.placeholders_offset(), 0, vec![])
+            }),
+            digest: None,
+            layers_offset: header.layer_section.map(|_| header.prefix_end()),
+            writer: VectorWriter::default(),
             fd,
         }
     }
 
+    /// Takes over a file whose header and layers were read, along with its
+    /// bloom filter, which is rebuilt from the records if it was stale.
+    /// Unless `unchecked`, the records are first checked to be laid out
+    /// the way the header says, see [VectorHandle::check_layout].
+    fn open(
+        header: &DbHeader,
+        bloom: Option<BloomFilter>,
+        fd: Box<dyn RandomAccess>,
+        store: Option<Box<dyn VectorStore>>,
+        unchecked: bool,
+    ) -> Result<VectorHandle, Error> {
+        if store.is_some() && header.history.is_some() {
+            return Err(Error::Options(OptionsError::HistoryInStore));
+        }
+        let mut handle = VectorHandle::new(header, fd);
+        handle.store = store;
+        if !unchecked {
+            handle.check_layout()?;
+        }
+        if handle.history.is_some() {
+            handle.load_history()?;
+        }
+        if bloom.is_some() {
+            handle.bloom = bloom;
+            handle.bloom_fresh = true;
+        } else if handle.bloom.is_some() {
+            for (id, _) in handle.read_all()? {
+                handle.bloom.as_mut().unwrap().insert(id);
+            }
+        }
+        if header.id_strategy == IdStrategy::Reuse {
+            let ids = handle.read_all()?.into_iter().map(|(id, _)| id);
+            handle.allocator.recover(ids);
+        }
+        handle.publish();
+        Ok(handle)
+    }
+
     fn unit_size_bytes(&self) -> u64 {
-        (self.dim_size * (size_of::<f32>() as u32) + size_of::<DbIndex>() as u32) as u64
+        vio::dbheader::align(self.record_size_bytes(), self.alignment)
+    }
+
+    /// Bytes a record takes before padding.
+    fn record_size_bytes(&self) -> u64 {
+        format::record_size(self.dim_size, self.history.is_some())
+    }
+
+    /// Writes the zeros that fill up a record just written to its aligned size.
+    fn write_padding(&mut self) -> Result<(), Error> {
+        let padding = self.unit_size_bytes() - self.record_size_bytes();
+        if padding > 0 {
+            self.fd
+                .write_all(&vec![0u8; padding as usize])
+                .map_err(Error::IO)?;
+        }
+        Ok(())
+    }
+
+    /// Fails with [Error::Corrupted] if the records don't fill the data section
+    /// in whole, or the first two ids aren't ascending, either of which suggests
+    /// the header disagrees with the records on their size.
+    fn check_layout(&mut self) -> Result<(), Error> {
+        let len = self.len()?;
+        if len <= self.data_section {
+            return Ok(());
+        }
+        let unit = self.unit_size_bytes();
+        let remainder = (len - self.data_section) % unit;
+        if remainder != 0 {
+            return Err(Error::Corrupted(format!(
+                "{remainder} bytes left over past records of {unit} bytes"
+            )));
+        }
+        if self.history.is_none() && len - self.data_section >= 2 * unit {
+            let mut ids = [0; 2];
+            for (i, id) in ids.iter_mut().enumerate() {
+                self.fd
+                    .seek(SeekFrom::Start(offset::record(self.data_section, i as u64, unit)?))
+                    .map_err(Error::IO)?;
+                *id = self.fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+            }
+            if ids[0] >= ids[1] {
+                return Err(Error::Corrupted(format!(
+                    "record ids {0} and {1} are out of order",
+                    ids[0], ids[1]
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&mut self) -> Result<u64, Error> {
+        self.fd.seek(SeekFrom::End(0)).map_err(Error::IO)
+    }
+
+    /// Zero-fills whatever is missing up to the data section, so that
+    /// records appended to a header-only file land where they're expected.
+    fn fill_data_section(&mut self) -> Result<(), Error> {
+        let end = self.len()?;
+        if end < self.data_section {
+            let missing = offset::to_usize(self.data_section - end)?;
+            self.fd
+                .write_all(&vec![0u8; missing])
+                .map_err(Error::IO)?;
+        }
+        Ok(())
     }
 
     fn seek_count(&mut self) -> Result<u64, Error> {
         let unit = self.unit_size_bytes();
-        let available = self.fd.seek(SeekFrom::End(0)).map_err(|e| Error::IO(e))?;
-        Ok((available + 1 - self.data_section) / unit)
+        let available = self.fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+        // files cut off before the data section hold nothing,
+        // and a record cut off at the end isn't one
+        Ok(available.saturating_sub(self.data_section) / unit)
     }
 
     #[allow(invalid_reference_casting)]
     fn count(&self) -> Result<u64, Error> {
+        if let Some(history) = &self.history {
+            return Ok(history.count());
+        }
+        if let Some(store) = &self.store {
+            return Ok(store.len());
+        }
         let mut_self = unsafe {
             &mut *(self as *const Self as *mut Self)
         };
-        let pos = mut_self.fd.stream_position().map_err(|e| Error::IO(e))?;
+        let pos = mut_self.fd.stream_position().map_err(Error::IO)?;
         let count = mut_self.seek_count()?;
-        mut_self.fd.seek(SeekFrom::Start(pos)).map_err(|e| Error::IO(e))?;
-        Ok(count)
+        mut_self.fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+        Ok(count - self.unfilled_count())
     }
 
-    fn seek_item(&mut self, id: DbIndex) -> Result<Option<u64>, Error> {
+    /// Position of the first record whose id is greater than `id`.
+    fn seek_insertion(&mut self, id: DbIndex) -> Result<u64, Error> {
         let unit = self.unit_size_bytes();
-        let (mut head, mut tail) = (0u64, self.seek_count()? - 1);
-
-        // employ a binary search between [head] and [tail] in fd
-        loop {
+        let (mut head, mut tail) = (0u64, self.seek_count()?);
+        while head < tail {
+            let middle = head + (tail - head) / 2;
             self.fd
-                .seek(SeekFrom::Start(head * unit + self.data_section))
-                .map_err(|e| Error::IO(e))?;
-            let head_id = self.fd.read_u32::<BigEndian>().map_err(|e| Error::IO(e))?;
-
-            if head_id == id {
-                return Ok(Some(head * unit + self.data_section));
-            } else if head == tail {
-                return Ok(None);
+                .seek(SeekFrom::Start(offset::record(self.data_section, middle, unit)?))
+                .map_err(Error::IO)?;
+            if self.fd.read_u32::<BigEndian>().map_err(Error::IO)? > id {
+                tail = middle;
+            } else {
+                head = middle + 1;
             }
+        }
+        offset::record(self.data_section, head, unit)
+    }
 
-            self.fd
-                .seek(SeekFrom::Start(tail * unit + self.data_section))
-                .map_err(|e| Error::IO(e))?;
-            let tail_id = self.fd.read_u32::<BigEndian>().map_err(|e| Error::IO(e))?;
-
-            if tail_id == id {
-                return Ok(Some(tail * unit + self.data_section));
-            }
+    fn seek_item(&mut self, id: DbIndex) -> Result<Option<u64>, Error> {
+        if self.history.is_some() {
+            return self.seek_version(id, None);
+        }
+        self.reader().seek_item(id)
+    }
 
-            if head_id > tail_id {
-                panic!("Database is corrupted. Please report this issue.")
-            }
+    /// Reads the records of a database without history through the handle.
+    fn reader(&mut self) -> VectorReader<'_> {
+        VectorReader {
+            layout: self.layout(),
+            fd: self.fd.as_mut(),
+        }
+    }
 
-            if id < (head_id + tail_id) / 2 {
-                tail = (tail - head) / 2 + head;
-            } else {
-                head = (tail - head) / 2 + head;
-            }
+    fn layout(&self) -> RecordLayout {
+        RecordLayout {
+            dim_size: self.dim_size,
+            data_section: self.data_section,
+            unit: self.unit_size_bytes(),
+            verbatim: self.verbatim,
         }
     }
 
     fn get(&mut self, id: DbIndex) -> Result<Option<DbVector>, Error> {
-        if self.seek_item(id)?.is_none() {
+        if self.store.is_some() {
+            return self.store_get(id);
+        }
+        if !self.may_contain(id) || self.is_unfilled(id) || self.seek_item(id)?.is_none() {
             return Ok(None);
         }
 
         Ok(Some(
-            vio::vector::read(self.dim_size, &mut self.fd).map_err(|e| match e {
-                vio::Error::EOF => Error::IO(io::Error::new(
+            vio::vector::read(self.dim_size, &mut self.fd, self.verbatim).map_err(|e| match e {
+                vio::Error::Eof => Error::IO(io::Error::new(
                     io::ErrorKind::InvalidData,
                     format!(
                         "expecting {0} bytes of data, but got none",
-                        self.dim_size * size_of::<f32>() as u32
+                        self.dim_size as u64 * format::COMPONENT_WIDTH
                     ),
                 )),
                 vio::Error::IO(e) => Error::IO(e),
@@ -110,73 +411,446 @@ impl VectorHandle {
         ))
     }
 
-    fn seek_last_id(&mut self) -> Option<DbIndex> {
-        match self
-            .fd
-            .seek(SeekFrom::End(-(self.unit_size_bytes() as i64)))
-        {
-            Ok(pos) => {
-                if pos < self.data_section {
-                    None
-                } else {
-                    self.fd.read_u32::<BigEndian>().ok()
-                }
-            },
-            Err(_) => None,
+    /// Whether vector `id` is stored, taking failures to look it up for no.
+    fn contains(&mut self, id: DbIndex) -> bool {
+        match &self.store {
+            Some(store) => (id as u64) < store.len(),
+            None => {
+                self.may_contain(id)
+                    && !self.is_unfilled(id)
+                    && self.seek_item(id).is_ok_and(|pos| pos.is_some())
+            }
         }
     }
 
+    fn read_record(&mut self) -> Result<(DbIndex, DbVector), Error> {
+        let id = self.fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        let vector = vio::vector::read(self.dim_size, &mut self.fd, self.verbatim).map_err(|e| match e {
+            vio::Error::Eof => Error::Parse(),
+            vio::Error::IO(e) => Error::IO(e),
+        })?;
+        Ok((id, vector))
+    }
+
+    fn read_all(&mut self) -> Result<Vec<(DbIndex, DbVector)>, Error> {
+        if self.history.is_some() {
+            return self.read_at(None);
+        }
+        if self.store.is_some() {
+            return self.store_read_all();
+        }
+        let count = self.seek_count()?;
+        self.fd
+            .seek(SeekFrom::Start(self.data_section))
+            .map_err(Error::IO)?;
+        let padding = offset::to_i64(self.unit_size_bytes() - self.record_size_bytes())?;
+        let mut records = (0..count)
+            .map(|_| {
+                let record = self.read_record()?;
+                self.fd.seek(SeekFrom::Current(padding)).map_err(Error::IO)?;
+                Ok(record)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        records.retain(|(id, _)| !self.is_unfilled(*id));
+        Ok(records)
+    }
+
+    /// Same as [VectorHandle::read_all], decoding only the first `dims`
+    /// components of each vector and skipping over the rest.
+    fn read_all_prefix(&mut self, dims: u32) -> Result<Vec<(DbIndex, DbVector)>, Error> {
+        if self.history.is_some() || self.store.is_some() {
+            let mut records = self.read_all()?;
+            for (_, vector) in records.iter_mut() {
+                vector.truncate(dims as usize);
+            }
+            return Ok(records);
+        }
+        let count = self.seek_count()?;
+        self.fd
+            .seek(SeekFrom::Start(self.data_section))
+            .map_err(Error::IO)?;
+        let skipped = offset::to_i64(
+            self.unit_size_bytes() - format::RECORD_ID_WIDTH - dims as u64 * format::COMPONENT_WIDTH,
+        )?;
+        let mut records = (0..count)
+            .map(|_| {
+                let id = self.fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+                let prefix = vio::vector::read(dims, &mut self.fd, self.verbatim).map_err(|e| match e {
+                    vio::Error::Eof => Error::Parse(),
+                    vio::Error::IO(e) => Error::IO(e),
+                })?;
+                self.fd.seek(SeekFrom::Current(skipped)).map_err(Error::IO)?;
+                Ok((id, prefix))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        records.retain(|(id, _)| !self.is_unfilled(*id));
+        Ok(records)
+    }
+
+    /// Id of the last record, if there's any.
+    fn seek_last_id(&mut self) -> Result<Option<DbIndex>, Error> {
+        let count = self.seek_count()?;
+        if count == 0 {
+            return Ok(None);
+        }
+        let pos = offset::record(self.data_section, count - 1, self.unit_size_bytes())?;
+        self.fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+        self.fd.read_u32::<BigEndian>().map(Some).map_err(Error::IO)
+    }
+
+    /// Where the next record goes and the id of the last one, as the
+    /// [writer](VectorWriter) knows them or looked up if it doesn't.
+    fn append_state(&mut self) -> Result<(u64, Option<DbIndex>), Error> {
+        if let (Some(end), Some(last_id)) = (self.writer.end(), self.writer.last_id()) {
+            return Ok((end, last_id));
+        }
+        let last_id = self.seek_last_id()?;
+        let end = self.len()?;
+        self.writer.know(end, last_id);
+        Ok((end, last_id))
+    }
+
     fn push(&mut self, vector: DbVectorSlice) -> Result<DbIndex, Error> {
+        let before = self.revision;
+        let id = self.push_record(vector)?;
+        self.digest(before, &[], &[(id, vector)]);
+        Ok(id)
+    }
+
+    fn push_record(&mut self, vector: DbVectorSlice) -> Result<DbIndex, Error> {
         if vector.len() != self.dim_size as usize {
             return Err(Error::Dimension(self.dim_size, vector.len()));
         }
+        if self.store.is_some() {
+            return self.store_push(vector);
+        }
+        self.fill_data_section()?;
 
-        let new_id = match self.seek_last_id() {
-            None => 0,
-            Some(i) => i + 1,
-        };
+        if let Some(history) = &self.history {
+            let new_id = self.allocator.allocate(history.last_id())?;
+            self.insert_bloom(new_id)?;
+            self.append(new_id, Some(vector))?;
+            return Ok(new_id);
+        }
 
-        self.fd.seek(SeekFrom::End(0)).map_err(|e| Error::IO(e))?;
+        let (available, last_id) = self.append_state()?;
+        let end = offset::add(available, self.unit_size_bytes())?;
+        let new_id = self.allocator.allocate(last_id)?;
+        self.insert_bloom(new_id)?;
+
+        let mut moved = 0;
+        if last_id.is_some_and(|last| new_id < last) {
+            // keep records sorted by id when filling a hole
+            let pos = self.seek_insertion(new_id)?;
+            let offset = self.unit_size_bytes();
+            self.fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+            self.fd
+                .move_content(
+                    offset::to_usize(offset::sub(available, pos)?)?,
+                    offset::to_isize(offset)?,
+                    offset::move_buffer_size(offset),
+                )
+                .map_err(Error::IO)?;
+            moved = available - pos;
+            self.fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+        } else {
+            self.fd.seek(SeekFrom::Start(available)).map_err(Error::IO)?;
+        }
         self.fd
             .write_u32::<BigEndian>(new_id)
-            .map_err(|e| Error::IO(e))?;
-        vio::vector::write(vector, &mut self.fd).map_err(|e| Error::IO(e))?;
+            .map_err(Error::IO)?;
+        vio::vector::write(vector, &mut self.fd).map_err(Error::IO)?;
+        self.write_padding()?;
+        let last_id = max(last_id, Some(new_id));
+        self.writer.know(end, last_id);
+        self.written(self.unit_size_bytes())?;
+        self.amplified(self.unit_size_bytes(), moved);
+        self.iterators.record(self.revision, new_id, None);
         Ok(new_id)
     }
 
+    /// Appends all of `vectors` at once, each record a buffer of a single
+    /// [vectored write](write_all_vectored), or gives `None` back if they
+    /// wouldn't all go at the end, leaving them to be pushed one by one.
+    /// A write failing halfway is cut off again, so that none of them is kept.
+    fn push_batch(&mut self, vectors: &[DbVectorSlice]) -> Result<Option<Vec<DbIndex>>, Error> {
+        if self.store.is_some() || self.history.is_some() || !self.allocator.appends() {
+            return Ok(None);
+        }
+        if let Some(v) = vectors.iter().find(|v| v.len() != self.dim_size as usize) {
+            return Err(Error::Dimension(self.dim_size, v.len()));
+        }
+        self.fill_data_section()?;
+        let before = self.revision;
+        let (available, mut last_id) = self.append_state()?;
+        let unit = self.unit_size_bytes();
+        let end = offset::record(available, vectors.len() as u64, unit)?;
+        let mut ids = Vec::with_capacity(vectors.len());
+        let mut records = Vec::with_capacity(vectors.len());
+        for vector in vectors {
+            let id = self.allocator.allocate(last_id)?;
+            let mut record = Vec::with_capacity(offset::to_usize(unit)?);
+            record.extend(id.to_be_bytes());
+            vio::vector::write(vector, &mut record).map_err(Error::IO)?;
+            record.resize(offset::to_usize(unit)?, 0);
+            last_id = Some(id);
+            ids.push(id);
+            records.push(record);
+        }
+        for id in &ids {
+            self.insert_bloom(*id)?;
+        }
+
+        self.fd.seek(SeekFrom::Start(available)).map_err(Error::IO)?;
+        let mut slices = records.iter().map(|record| IoSlice::new(record)).collect::<Vec<_>>();
+        if let Err(e) = write_all_vectored(&mut self.fd, &mut slices) {
+            let _ = self.fd.set_len(available);
+            self.writer.forget();
+            return Err(Error::IO(e));
+        }
+        self.writer.know(end, last_id);
+        self.written(end - available)?;
+        self.amplified(end - available, 0);
+        for id in &ids {
+            self.iterators.record(self.revision, *id, None);
+        }
+        let added = ids.iter().copied().zip(vectors.iter().copied()).collect::<Vec<_>>();
+        self.digest(before, &[], &added);
+        Ok(Some(ids))
+    }
+
     fn remove(&mut self, id: DbIndex) -> Result<Option<DbVector>, Error> {
+        let before = self.revision;
+        let removed = self.remove_record(id)?;
+        match &removed {
+            Some(vector) => self.digest(before, &[(id, vector)], &[]),
+            None => self.digest(before, &[], &[]),
+        }
+        Ok(removed)
+    }
+
+    fn remove_record(&mut self, id: DbIndex) -> Result<Option<DbVector>, Error> {
+        if self.store.is_some() {
+            return Err(Error::Unsupported("remove"));
+        }
+        if !self.may_contain(id) {
+            return Ok(None);
+        }
+        if self.history.is_some() {
+            let removed = self.get(id)?;
+            if removed.is_some() {
+                self.append(id, None)?;
+                self.allocator.release(id);
+            }
+            return Ok(removed);
+        }
         match self.seek_item(id)? {
             None => Ok(None),
             Some(pos) => {
                 let vector =
-                    vio::vector::read(self.dim_size, &mut self.fd).map_err(|e| match e {
-                        vio::Error::EOF => Error::Parse(),
+                    vio::vector::read(self.dim_size, &mut self.fd, self.verbatim).map_err(|e| match e {
+                        vio::Error::Eof => Error::Parse(),
                         vio::Error::IO(e) => Error::IO(e),
                     })?;
-                let available = self.fd.seek(SeekFrom::End(0)).map_err(|e| Error::IO(e))?;
+                let available = self.fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
                 let offset = self.unit_size_bytes();
-                let pos = pos - size_of::<DbIndex>() as u64;
-                self.fd
-                    .seek(SeekFrom::Start(pos))
-                    .map_err(|e| Error::IO(e))?;
+                let next = offset::add(pos, offset)?;
+                let following = offset::sub(available, next)?;
+                self.fd.seek(SeekFrom::Start(next)).map_err(Error::IO)?;
                 self.fd
                     .move_content(
-                        (available - pos - offset) as usize,
-                        -(offset as isize),
-                        min(4096, 10 * (offset as usize)),
+                        offset::to_usize(following)?,
+                        -offset::to_isize(offset)?,
+                        offset::move_buffer_size(offset),
                     )
-                    .map_err(|e| Error::IO(e))?;
+                    .map_err(Error::IO)?;
+                self.fd.set_len(available - offset).map_err(Error::IO)?;
+                self.writer.forget();
+                self.written(following)?;
+                self.amplified(offset, following);
+                self.allocator.release(id);
+                // a placeholder is given up, having held nothing to read
+                if self.forget_placeholder(id)? {
+                    self.iterators.record(self.revision, id, None);
+                    return Ok(None);
+                }
+                self.iterators.record(self.revision, id, Some(&vector));
                 Ok(Some(vector))
             }
         }
     }
+
+    /// Same as removing each of `ids` in turn, but shifts every surviving
+    /// record at most once and truncates the file once at the end.
+    fn remove_many(&mut self, ids: &[DbIndex]) -> Result<Vec<Option<DbVector>>, Error> {
+        if self.store.is_some() {
+            return Err(Error::Unsupported("remove"));
+        }
+        if self.history.is_some() {
+            return ids.iter().map(|id| self.remove(*id)).collect();
+        }
+        let before = self.revision;
+        let mut removed = HashMap::new();
+        let mut positions = vec![];
+        for id in ids {
+            if removed.contains_key(id) || !self.may_contain(*id) {
+                continue;
+            }
+            if let Some(pos) = self.seek_item(*id)? {
+                let vector =
+                    vio::vector::read(self.dim_size, &mut self.fd, self.verbatim).map_err(|e| match e {
+                        vio::Error::Eof => Error::Parse(),
+                        vio::Error::IO(e) => Error::IO(e),
+                    })?;
+                removed.insert(*id, vector);
+                positions.push(pos);
+            }
+        }
+        positions.sort();
+
+        let available = self.len()?;
+        let unit = self.unit_size_bytes();
+        let mut moved = 0;
+        // survivors between two removed records close up the gap behind them
+        for (index, pos) in positions.iter().enumerate() {
+            let begin = offset::add(*pos, unit)?;
+            let end = positions.get(index + 1).copied().unwrap_or(available);
+            if begin < end {
+                // closed up behind every record removed so far
+                let gap = offset::mul(index as u64 + 1, unit)?;
+                self.fd.seek(SeekFrom::Start(begin)).map_err(Error::IO)?;
+                self.fd
+                    .move_content(
+                        offset::to_usize(end - begin)?,
+                        -offset::to_isize(gap)?,
+                        offset::move_buffer_size(unit),
+                    )
+                    .map_err(Error::IO)?;
+                moved += end - begin;
+            }
+        }
+        if !positions.is_empty() {
+            let freed = offset::mul(positions.len() as u64, unit)?;
+            self.fd
+                .set_len(offset::sub(available, freed)?)
+                .map_err(Error::IO)?;
+            self.writer.forget();
+            self.written(moved)?;
+            self.amplified(freed, moved);
+            for (id, vector) in &removed {
+                let held = (!self.is_unfilled(*id)).then_some(vector.as_slice());
+                self.iterators.record(self.revision, *id, held);
+            }
+            for id in removed.keys().copied().collect::<Vec<_>>() {
+                if self.forget_placeholder(id)? {
+                    removed.remove(&id);
+                    self.allocator.release(id);
+                }
+            }
+        }
+        let gone = removed
+            .iter()
+            .map(|(id, vector)| (*id, vector.as_slice()))
+            .collect::<Vec<_>>();
+        self.digest(before, &gone, &[]);
+
+        Ok(ids
+            .iter()
+            .map(|id| {
+                let vector = removed.remove(id);
+                if vector.is_some() {
+                    self.allocator.release(*id);
+                }
+                vector
+            })
+            .collect())
+    }
+
+    fn update(&mut self, id: DbIndex, vector: DbVectorSlice) -> Result<Option<DbVector>, Error> {
+        if vector.len() != self.dim_size as usize {
+            return Err(Error::Dimension(self.dim_size, vector.len()));
+        }
+        if self.store.is_some() {
+            return Err(Error::Unsupported("update"));
+        }
+        let Some(previous) = self.get(id)? else {
+            return Ok(None);
+        };
+        let before = self.revision;
+        if self.history.is_some() {
+            self.append(id, Some(vector))?;
+        } else {
+            let pos = self.seek_item(id)?.unwrap();
+            self.fd
+                .seek(SeekFrom::Start(offset::add(pos, format::RECORD_ID_WIDTH)?))
+                .map_err(Error::IO)?;
+            vio::vector::write(vector, &mut self.fd).map_err(Error::IO)?;
+            self.written(size_of_val(vector) as u64)?;
+            self.amplified(size_of_val(vector) as u64, 0);
+            self.iterators.record(self.revision, id, Some(&previous));
+        }
+        self.digest(before, &[(id, &previous)], &[(id, vector)]);
+        Ok(Some(previous))
+    }
 }
 
 pub struct Database {
     name: String,
+    metric: Metric,
+    config: HnswConfig,
+    search: RwLock<SearchParams>,
+    planner: RwLock<PlannerConfig>,
+    quota: Quota,
+    /// Present if the file keeps a group table, see [Database::push_grouped].
+    groups: Option<GroupTable>,
+    /// Present if the file keeps a key table, see [Database::push_keyed].
+    keys: Option<KeyTable>,
+    /// Present if the file keeps a level table, see [Database::node_level].
+    levels: Option<Mutex<LevelTable>>,
     layers: LinkedList<HnswLayer>,
-    loaded_vectors: Mutex<HashMap<u32, Rc<DbVector>>>,
-    handle: Mutex<VectorHandle>,
+    /// Node searches through the layers start from, found once they're read.
+    layer_entry: Option<u32>,
+    loaded_vectors: Mutex<VectorCache>,
+    read_only: bool,
+    queue: Mutex<WriteQueue>,
+    storage: StorageCoordinator,
+    /// Present once [built](Database::build_index).
+    index: Mutex<Option<HnswIndex>>,
+    /// Present if [enabled](DatabaseOptions::entry_shortcuts).
+    shortcuts: Option<Mutex<ShortcutCache>>,
+    /// Present if [enabled](DatabaseOptions::result_cache).
+    results: Option<Mutex<ResultCache>>,
+    /// Present if [recorded](DatabaseOptions::latency_stats).
+    latencies: Option<Arc<Latencies>>,
+    /// Tells [deadlines](SearchRequest::deadline), see [DatabaseOptions::deadline_clock].
+    clock: Arc<dyn Clock>,
+    /// Present while [tracing](Database::start_trace).
+    tracer: Option<Arc<Tracer>>,
+    /// See [Database::recorded_metric].
+    recorded_metric: Option<Metric>,
+    /// What the file says the index is built with, see [DatabaseOptions::hnsw].
+    recorded_hnsw: Option<HnswConfig>,
+    /// Layers of encodings newer than this build, left out of the index.
+    skipped_layers: Vec<SkippedLayer>,
+    /// Shared by the [views](Database::read_view) of the latest revision.
+    snapshot: Mutex<Option<Arc<Snapshot>>>,
+    /// Where [Database::maintain] left off.
+    maintenance: Maintenance,
+    /// Present if [watched](DatabaseOptions::watch_external_changes).
+    watch: Option<Watch>,
+    /// Reused by every [Database::query].
+    scratch: SearchScratch,
+    /// See [DatabaseOptions::normalize_on_insert].
+    normalize_on_insert: bool,
+    normalizations: Arc<Normalizations>,
+    /// See [DatabaseOptions::index_policy].
+    index_policy: IndexPolicy,
+    /// Present while the index is being built, see [Database::index_state].
+    building: BuildProgress,
+    /// Shared with the records, see [Database::stats].
+    counters: Arc<StatCounters>,
+    /// Shared with the [iterators](Database::iter).
+    handle: Arc<Mutex<VectorHandle>>,
 }
 
 #[derive(Debug)]
@@ -185,6 +859,88 @@ pub enum Error {
     IO(io::Error),
     Parse(),
     Dimension(u32, usize),
+    Missing(DbIndex),
+    RecallUnreachable(f32, f32),
+    IdSpaceExhausted,
+    QuotaExceeded { limit: u64, attempted: u64 },
+    QueueFull(usize),
+    NoHistory(Generation),
+    /// Names a parameter fixed once the index is built.
+    ImmutableParam(&'static str),
+    Options(OptionsError),
+    /// Written to a database opened read-only.
+    ReadOnly,
+    /// The file didn't pass verification on open, see [DatabaseOptions::verify].
+    Inconsistent(vio::Inconsistency),
+    /// Opened a file of zero bytes, which has yet to be created into a database.
+    EmptyFile,
+    /// The records don't match the header, see [Database::read_unchecked].
+    Corrupted(String),
+    /// The file isn't what the caller expected, see [DatabaseOptions::expect_dim].
+    SchemaMismatch(Vec<SchemaMismatch>),
+    /// The records differ from those an index was exported with,
+    /// see [Database::import_index].
+    FingerprintMismatch(Vec<SchemaMismatch>),
+    /// The index isn't built or doesn't reach this level, see [Database::rebuild_layer].
+    NoLayer(u32),
+    /// Records are being iterated over, see [Database::iter].
+    IterationInProgress,
+    /// The file is of an older version than the one supported and opening
+    /// it would write to it, see [DatabaseOptions::migrate].
+    NeedsMigration(u8, u8),
+    /// Found opening in strict mode, see [DatabaseOptions::strict].
+    Anomaly(Anomaly),
+    /// No migrations lead from the first version to the second, see [vio::migrate].
+    NoMigration(u8, u8),
+    /// Names an operation the records can't go through
+    /// being in a [VectorStore].
+    Unsupported(&'static str),
+    /// Holds the length of a vector said to be [normalized](QueryVector::Normalized).
+    NotNormalized(f32),
+    /// Holds a key another record is already under, see [Database::push_keyed].
+    KeyConflict(String),
+    /// Holds the bytes of a key longer than the [key table](KeyTableParams) allows.
+    KeyTooLong(usize),
+    /// Holds the capacity of the key table, which is full.
+    KeyTableFull(u32),
+    /// Looked up or pushed a key without a key table,
+    /// see [DatabaseOptions::key_table].
+    NoKeyTable,
+    /// Holds the capacity of the level table, which the index outgrew,
+    /// see [DatabaseOptions::level_table].
+    LevelTableFull(u32),
+    /// Holds the capacity of the placeholder table, whose window can't take
+    /// in the ids reserved, see [DatabaseOptions::placeholder_table].
+    PlaceholderTableFull(u32),
+    /// Reserved ids without a placeholder table,
+    /// see [DatabaseOptions::placeholder_table].
+    NoPlaceholderTable,
+    /// Holds the capacity of the group table, which is full.
+    GroupTableFull(u32),
+    /// Pushed a vector into a group without a group table,
+    /// see [DatabaseOptions::group_table].
+    NoGroupTable,
+    /// Holds an id [filled](Database::fill) that holds a vector already.
+    AlreadyFilled(DbIndex),
+    /// Holds an id [filled](Database::fill) that was never reserved.
+    NotReserved(DbIndex),
+    /// Queried while the index isn't ready, see [IndexPolicy::Fail].
+    IndexNotReady(IndexState),
+    /// Arithmetic on file offsets overflowed, as for a header claiming
+    /// records too large for the file, see [OffsetOp].
+    OffsetOverflow(OffsetOp),
+    /// Resumed an import over a source that doesn't begin with what the
+    /// [session](ImportSession) committed, holding what that was.
+    ImportSourceChanged(ImportProgress),
+    /// Stopped by a [CancelToken], having gone through `progress` of what
+    /// it works through, as the operation it was passed to says.
+    Cancelled { progress: u64 },
+    /// The file was changed by someone else while open,
+    /// see [Database::check_external_changes].
+    ExternallyModified,
+    /// The [embedder](crate::embed::Embedder) failed to embed text.
+    #[cfg(feature = "embed")]
+    Embed(crate::embed::EmbedError),
 }
 
 impl fmt::Display for Error {
@@ -197,117 +953,970 @@ impl fmt::Display for Error {
                 f,
                 "dimension mismatch (expected {expected}, actual {actual})"
             ),
+            Error::Missing(id) => write!(f, "vector #{id} doesn't exist"),
+            Error::IdSpaceExhausted => write!(f, "no more ids to allocate"),
+            Error::QuotaExceeded { limit, attempted } => {
+                write!(f, "quota exceeded (limit {limit}, attempted {attempted})")
+            }
+            Error::QueueFull(capacity) => {
+                write!(f, "write queue is full ({capacity} pending)")
+            }
+            Error::NoHistory(generation) => {
+                write!(f, "no history at generation {generation}")
+            }
+            Error::ImmutableParam(name) => {
+                write!(f, "{name} can't be changed after construction")
+            }
+            Error::Options(e) => write!(f, "invalid options because {e}"),
+            Error::ReadOnly => write!(f, "database is read-only"),
+            Error::EmptyFile => write!(f, "file is empty"),
+            Error::Corrupted(reason) => write!(f, "corrupted file because {reason}"),
+            Error::SchemaMismatch(mismatches) => {
+                let mismatches = mismatches.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+                write!(f, "schema mismatch ({})", mismatches.join(", "))
+            }
+            Error::FingerprintMismatch(mismatches) => {
+                let mismatches = mismatches.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+                write!(f, "index fingerprint mismatch ({})", mismatches.join(", "))
+            }
+            Error::NoLayer(level) => write!(f, "index has no layer at level {level}"),
+            Error::IterationInProgress => write!(f, "records are being iterated over"),
+            Error::Anomaly(anomaly) => write!(f, "anomaly found on open ({anomaly})"),
+            Error::NeedsMigration(found, supported) => write!(
+                f,
+                "file of version {found} needs migrating to version {supported}"
+            ),
+            Error::NoMigration(from, to) => {
+                write!(f, "no migration from version {from} to {to}")
+            }
+            Error::Unsupported(operation) => {
+                write!(f, "{operation} isn't supported by the vector store")
+            }
+            Error::Inconsistent(at) => {
+                write!(f, "inconsistent file at {0} ({1})", at.offset, at.reason)
+            }
+            Error::NotNormalized(norm) => write!(f, "vector isn't normalized (norm {norm})"),
+            Error::KeyConflict(key) => write!(f, "key {key:?} is already taken"),
+            Error::KeyTooLong(len) => write!(f, "key of {len} bytes is too long"),
+            Error::KeyTableFull(capacity) => write!(f, "key table full ({capacity} keys)"),
+            Error::NoKeyTable => write!(f, "no key table"),
+            Error::LevelTableFull(capacity) => write!(f, "level table full ({capacity} levels)"),
+            Error::PlaceholderTableFull(capacity) => {
+                write!(f, "placeholder table full ({capacity} ids)")
+            }
+            Error::NoPlaceholderTable => write!(f, "no placeholder table"),
+            Error::GroupTableFull(capacity) => write!(f, "group table full ({capacity} groups)"),
+            Error::NoGroupTable => write!(f, "no group table"),
+            Error::AlreadyFilled(id) => write!(f, "id {id} is filled already"),
+            Error::NotReserved(id) => write!(f, "id {id} isn't reserved"),
+            Error::IndexNotReady(state) => write!(f, "index isn't ready ({state:?})"),
+            Error::OffsetOverflow(op) => write!(f, "file offset overflowed at {op}"),
+            Error::ImportSourceChanged(committed) => write!(
+                f,
+                "import source changed within the {} records committed",
+                committed.records
+            ),
+            Error::Cancelled { progress } => write!(f, "cancelled after {progress} done"),
+            Error::ExternallyModified => write!(f, "file was modified externally"),
+            #[cfg(feature = "embed")]
+            Error::Embed(e) => write!(f, "embedding failed for {e}"),
+            Error::RecallUnreachable(target, best) => write!(
+                f,
+                "recall of {target} is unreachable (best achieved {best})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Header(e) => Some(e),
+            Error::IO(e) => Some(e),
+            Error::Options(e) => Some(e),
+            #[cfg(feature = "embed")]
+            Error::Embed(e) => Some(e),
+            _ => None,
         }
     }
 }
 
 impl Database {
-    pub fn read(name: &String, mut fd: Box<dyn RandomAccess>) -> Result<Database, Error> {
-        let header = vio::dbheader::read(&mut fd).map_err(|e| Error::Header(e))?;
+    #[deprecated(note = "use DatabaseOptions::open")]
+    pub fn read(name: &str, fd: Box<dyn RandomAccess>) -> Result<Database, Error> {
+        DatabaseOptions::default().open(name, fd)
+    }
+
+    /// Reads a database without checking its records match the header,
+    /// for tools that recover what they can from corrupted files.
+    /// Reading one that doesn't match yields garbage.
+    pub fn read_unchecked(name: &str, fd: Box<dyn RandomAccess>) -> Result<Database, Error> {
+        Database::open(name, fd, None, true).map(|(db, _)| db)
+    }
+
+    /// Reads a database from `fd`, along with what's off about it but
+    /// didn't keep it from being read. A file holding nothing past its header
+    /// is an empty database, while one holding nothing at all isn't one yet.
+    fn open(
+        name: &str,
+        mut fd: Box<dyn RandomAccess>,
+        store: Option<Box<dyn VectorStore>>,
+        unchecked: bool,
+    ) -> Result<(Database, OpenReport), Error> {
+        let len = fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+        if len == 0 {
+            return Err(Error::EmptyFile);
+        }
+        fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        let header = vio::dbheader::read(&mut fd).map_err(Error::Header)?;
+        let header_end = fd.stream_position().map_err(Error::IO)?;
+        let mut report = OpenReport::default();
+        if header.version >= format::HEADER_SLOTS_SINCE {
+            report.anomalies = integrity::torn_slots(&mut fd, header.version).map_err(Error::IO)?;
+            fd.seek(SeekFrom::Start(header_end)).map_err(Error::IO)?;
+        }
+        let bloom = match header.bloom {
+            // the block is missing from header-only files, so it's rebuilt
+            Some(params) if header_end + vio::bloom::block_size(params) <= len => {
+                let bloom = vio::bloom::read(&mut fd, params).map_err(|e| match e {
+                    vio::Error::Eof => Error::Parse(),
+                    vio::Error::IO(e) => Error::IO(e),
+                })?;
+                if bloom.is_none() {
+                    report.anomalies.push(Anomaly::StaleBloomFilter);
+                }
+                bloom
+            }
+            _ => None,
+        };
+        let keys = match header.keys {
+            Some(params) => {
+                let offset = header.keys_offset();
+                // the block is missing from header-only files, which have no keys
+                let entries = if offset + vio::keys::block_size(params) <= len {
+                    fd.seek(SeekFrom::Start(offset)).map_err(Error::IO)?;
+                    vio::keys::read(&mut fd, params).map_err(|e| match e {
+                        vio::Error::Eof => Error::Parse(),
+                        vio::Error::IO(e) => Error::IO(e),
+                    })?
+                } else {
+                    vec![]
+                };
+                let table = KeyTable::new(params, offset, entries);
+                fd.seek(SeekFrom::Start(table.end())).map_err(Error::IO)?;
+                Some(table)
+            }
+            None => None,
+        };
+        let levels = match header.levels {
+            Some(capacity) => {
+                let offset = header.levels_offset();
+                // the block is missing from header-only files, which have no index
+                let entries = if offset + vio::levels::block_size(capacity) <= len {
+                    fd.seek(SeekFrom::Start(offset)).map_err(Error::IO)?;
+                    vio::levels::read(&mut fd, capacity).map_err(|e| match e {
+                        vio::Error::Eof => Error::Parse(),
+                        vio::Error::IO(e) => Error::IO(e),
+                    })?
+                } else {
+                    vec![]
+                };
+                let table = LevelTable::new(capacity, offset, entries);
+                fd.seek(SeekFrom::Start(table.end())).map_err(Error::IO)?;
+                Some(Mutex::new(table))
+            }
+            None => None,
+        };
+        let placeholders = match header.placeholders {
+            Some(capacity) => {
+                let offset = header.placeholders_offset();
+                // the block is missing from header-only files, which have no records
+                let (base, unfilled) = if offset + vio::placeholders::block_size(capacity) <= len {
+                    fd.seek(SeekFrom::Start(offset)).map_err(Error::IO)?;
+                    vio::placeholders::read(&mut fd, capacity).map_err(|e| match e {
+                        vio::Error::Eof => Error::Parse(),
+                        vio::Error::IO(e) => Error::IO(e),
+                    })?
+                } else {
+                    (0, vec![])
+                };
+                let table = PlaceholderTable::new(capacity, offset, base, unfilled);
+                fd.seek(SeekFrom::Start(table.end())).map_err(Error::IO)?;
+                Some(table)
+            }
+            None => None,
+        };
+        let groups = match header.groups {
+            Some(capacity) => {
+                let offset = header.groups_offset();
+                // the block is missing from header-only files, which have no records
+                let entries = if offset + vio::groups::block_size(capacity) <= len {
+                    fd.seek(SeekFrom::Start(offset)).map_err(Error::IO)?;
+                    vio::groups::read(&mut fd, capacity).map_err(|e| match e {
+                        vio::Error::Eof => Error::Parse(),
+                        vio::Error::IO(e) => Error::IO(e),
+                    })?
+                } else {
+                    vec![]
+                };
+                let table = GroupTable::new(capacity, offset, entries);
+                fd.seek(SeekFrom::Start(table.end())).map_err(Error::IO)?;
+                Some(table)
+            }
+            None => None,
+        };
 
         let mut layers = LinkedList::new();
+        let mut skipped_layers = vec![];
+        let end = min(header.data_section, len);
+        let mut section =
+            vio::layer::Section::begin(&mut fd, header.version, end).map_err(|e| match e {
+                vio::Error::Eof => Error::Parse(),
+                vio::Error::IO(e) => Error::IO(e),
+            })?;
         loop {
-            match vio::layer::read(&mut fd) {
-                Ok(layer) => layers.push_back(layer),
+            match section.next(&mut fd) {
+                Ok(Decoded::Layer(layer)) => layers.push_back(layer),
+                Ok(Decoded::Skipped(skipped)) => skipped_layers.push(skipped),
                 Err(vio::Error::IO(e)) => return Err(Error::IO(e)),
-                Err(vio::Error::EOF) => break,
+                Err(vio::Error::Eof) => break,
             }
         }
-        Ok(Database {
-            handle: Mutex::new(VectorHandle::new(&header, fd)),
-            name: name.clone(),
+        report
+            .anomalies
+            .extend(skipped_layers.iter().copied().map(Anomaly::SkippedLayer));
+        let mut handle = VectorHandle::open(&header, bloom, fd, store, unchecked)?;
+        if let Some(table) = placeholders {
+            handle.adopt_placeholders(table)?;
+        }
+        report
+            .anomalies
+            .extend(integrity::dangling_layer_nodes(&layers, &mut handle));
+        if let Some(history) = &handle.history {
+            let (records, superseded) = history.superseded();
+            report.anomalies.extend(Anomaly::superseded(records, superseded));
+        }
+        let db = Database {
+            counters: handle.counters.clone(),
+            handle: Arc::new(Mutex::new(handle)),
+            name: String::from(name),
+            metric: header.metric.unwrap_or_default(),
+            config: HnswConfig::default(),
+            search: RwLock::new(header.search.unwrap_or_default()),
+            planner: RwLock::new(PlannerConfig::default()),
+            quota: header.quota,
+            groups,
+            keys,
+            levels,
+            layer_entry: search::layer_entry(&layers),
             layers,
-            loaded_vectors: Mutex::new(HashMap::new()),
-        })
+            loaded_vectors: Mutex::new(VectorCache::default()),
+            read_only: false,
+            queue: Mutex::new(WriteQueue::default()),
+            storage: StorageCoordinator::default(),
+            index: Mutex::new(None),
+            shortcuts: None,
+            results: None,
+            latencies: None,
+            clock: Arc::new(MonotonicClock::default()),
+            tracer: None,
+            recorded_metric: header.metric,
+            recorded_hnsw: header.hnsw,
+            skipped_layers,
+            snapshot: Mutex::new(None),
+            maintenance: Maintenance::default(),
+            watch: None,
+            scratch: SearchScratch::new(),
+            normalize_on_insert: false,
+            index_policy: IndexPolicy::default(),
+            building: BuildProgress::default(),
+            normalizations: Arc::new(Normalizations::default()),
+        };
+        Ok((db, report))
+    }
+
+    #[deprecated(note = "use DatabaseOptions::create")]
+    pub fn new(name: &str, dim_size: u32, fd: Box<dyn RandomAccess>) -> Database {
+        DatabaseOptions::new(dim_size).create(name, fd).unwrap()
+    }
+
+    #[deprecated(note = "use DatabaseOptions::id_strategy")]
+    pub fn with_id_strategy(
+        name: &str,
+        dim_size: u32,
+        id_strategy: IdStrategy,
+        fd: Box<dyn RandomAccess>,
+    ) -> Database {
+        DatabaseOptions::new(dim_size)
+            .id_strategy(id_strategy)
+            .create(name, fd)
+            .unwrap()
     }
 
-    pub fn new(name: &str, dim_size: u32, mut fd: Box<dyn RandomAccess>) -> Database {
-        let header = DbHeader::new(dim_size);
-        header.write(&mut fd).unwrap();
-        Database {
-            handle: Mutex::new(VectorHandle::new(&header, fd)),
+    #[deprecated(note = "use DatabaseOptions::alignment")]
+    pub fn with_alignment(
+        name: &str,
+        dim_size: u32,
+        alignment: u32,
+        fd: Box<dyn RandomAccess>,
+    ) -> Database {
+        DatabaseOptions::new(dim_size)
+            .alignment(alignment)
+            .create(name, fd)
+            .unwrap()
+    }
+
+    #[deprecated(note = "use DatabaseOptions::quota")]
+    pub fn with_quota(
+        name: &str,
+        dim_size: u32,
+        quota: Quota,
+        fd: Box<dyn RandomAccess>,
+    ) -> Database {
+        DatabaseOptions::new(dim_size)
+            .quota(quota)
+            .create(name, fd)
+            .unwrap()
+    }
+
+    #[deprecated(note = "use DatabaseOptions::bloom_filter")]
+    pub fn with_bloom_filter(
+        name: &str,
+        dim_size: u32,
+        expected: u64,
+        false_positive_rate: f64,
+        fd: Box<dyn RandomAccess>,
+    ) -> Database {
+        DatabaseOptions::new(dim_size)
+            .bloom_filter(expected, false_positive_rate)
+            .create(name, fd)
+            .unwrap()
+    }
+
+    fn create(
+        name: &str,
+        header: DbHeader,
+        mut fd: Box<dyn RandomAccess>,
+        store: Option<Box<dyn VectorStore>>,
+    ) -> Result<Database, Error> {
+        header.write(&mut fd).map_err(Error::Header)?;
+        let mut handle = VectorHandle::new(&header, fd);
+        handle.store = store;
+        handle.flush_bloom()?;
+        handle.fill_data_section()?;
+        if handle.history.is_some() {
+            handle.load_history()?;
+        }
+        handle.publish();
+        Ok(Database {
+            counters: handle.counters.clone(),
+            handle: Arc::new(Mutex::new(handle)),
             name: String::from(name),
+            metric: header.metric.unwrap_or_default(),
+            config: HnswConfig::default(),
+            search: RwLock::new(header.search.unwrap_or_default()),
+            planner: RwLock::new(PlannerConfig::default()),
+            quota: header.quota,
+            groups: header
+                .groups
+                .map(|capacity| GroupTable::new(capacity, header.groups_offset(), vec![])),
+            keys: header
+                .keys
+                .map(|params| KeyTable::new(params, header.keys_offset(), vec![])),
+            levels: header
+                .levels
+                .map(|capacity| Mutex::new(LevelTable::new(capacity, header.levels_offset(), vec![]))),
             layers: LinkedList::new(),
-            loaded_vectors: Mutex::new(HashMap::new()),
+            layer_entry: None,
+            loaded_vectors: Mutex::new(VectorCache::default()),
+            read_only: false,
+            queue: Mutex::new(WriteQueue::default()),
+            storage: StorageCoordinator::default(),
+            index: Mutex::new(None),
+            shortcuts: None,
+            results: None,
+            latencies: None,
+            clock: Arc::new(MonotonicClock::default()),
+            tracer: None,
+            recorded_metric: header.metric,
+            recorded_hnsw: header.hnsw,
+            skipped_layers: vec![],
+            snapshot: Mutex::new(None),
+            maintenance: Maintenance::default(),
+            watch: None,
+            scratch: SearchScratch::new(),
+            normalize_on_insert: false,
+            index_policy: IndexPolicy::default(),
+            building: BuildProgress::default(),
+            normalizations: Arc::new(Normalizations::default()),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Layers the file holds in encodings this build doesn't know, written
+    /// by a newer one. The index is loaded without them.
+    pub fn skipped_layers(&self) -> &[SkippedLayer] {
+        &self.skipped_layers
+    }
+
+    pub fn dim_size(&self) -> u32 {
+        self.handle.lock_auto_clear_poison().dim_size
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    pub fn config(&self) -> HnswConfig {
+        HnswConfig {
+            ef_search: self.search_params().ef_search,
+            ..self.config
         }
     }
 
-    pub fn get(&mut self, id: DbIndex) -> Result<Option<Rc<DbVector>>, Error> {
-        let mut handle = self.handle.lock_auto_clear_poison();
-        let mut cache = self.loaded_vectors.lock_auto_clear_poison();
-        match cache.get(&id) {
-            None => match handle.get(id) {
-                Ok(Some(v)) => {
-                    let rc: Rc<DbVector> = Rc::new(v);
-                    cache.insert(id, rc.clone());
-                    Ok(Some(rc.clone()))
+    /// Number of vectors stored, leaving out the ids [reserved](Database::reserve_ids)
+    /// and not filled yet.
+    pub fn count(&self) -> Result<u64, Error> {
+        // looked up before checking out a reader, as in Database::get
+        let unfilled = self.handle.lock_auto_clear_poison().unfilled_count();
+        match self.storage.read() {
+            Some(mut pooled) => Ok(pooled.reader().count()? - unfilled),
+            None => self.handle.lock_auto_clear_poison().count(),
+        }
+    }
+
+    /// Fetches vector `id`, through one of the [read handles](Database::add_read_handles)
+    /// if there are any, so that concurrent readers don't wait for each other.
+    pub fn get(&self, id: DbIndex) -> Result<Option<Arc<DbVector>>, Error> {
+        let _timer = self.time(Operation::Get);
+        if let Some(v) = self.loaded_vectors.lock_auto_clear_poison().get(&id) {
+            return Ok(Some(v.clone()));
+        }
+        // checked before checking out a reader, which mustn't wait on the
+        // handle while writers holding it wait for the records to be let go
+        let handle = self.handle.lock_auto_clear_poison();
+        if !handle.may_contain(id) || handle.is_unfilled(id) {
+            return Ok(None);
+        }
+        drop(handle);
+        let read = match self.storage.read() {
+            Some(mut pooled) => pooled.reader().get(id)?,
+            None => self.handle.lock_auto_clear_poison().get(id)?,
+        };
+        Ok(read.map(|v| {
+            let rc: Arc<DbVector> = Arc::new(v);
+            self.loaded_vectors
+                .lock_auto_clear_poison()
+                .insert(id, rc.clone());
+            rc
+        }))
+    }
+
+    /// Appends `vector` after anything still waiting in the write queue.
+    /// It's taken as whatever holds its components, an array, a [Vec] or a slice alike.
+    pub fn push<V: AsRef<[f32]>>(&mut self, vector: V) -> Result<DbIndex, Error> {
+        self.push_slice(vector.as_ref())
+    }
+
+    fn push_slice(&mut self, vector: DbVectorSlice) -> Result<DbIndex, Error> {
+        let vector = self.conform_insert(vector);
+        self.push_conformed(&vector)
+    }
+
+    fn push_conformed(&mut self, vector: DbVectorSlice) -> Result<DbIndex, Error> {
+        let _timer = self.time(Operation::Push);
+        self.check_writable()?;
+        self.drain_queue()?;
+        self.check_quota(1)?;
+        let index = {
+            let (mut handle, _records) = self.lock_records();
+            handle.push(vector)?
+        };
+        self.loaded_vectors
+            .lock_auto_clear_poison()
+            .insert(index, Arc::new(DbVector::from(vector)));
+        self.index_insert(index, vector)?;
+        Ok(index)
+    }
+
+    /// Fetches several vectors at once, in the order of `ids`.
+    pub fn get_many(&self, ids: &[DbIndex]) -> Result<Vec<Option<Arc<DbVector>>>, Error> {
+        ids.iter().map(|id| self.get(*id)).collect()
+    }
+
+    /// Pushes all `vectors` or none of them: dimensions and quota are checked
+    /// upfront, and whatever got written is rolled back if a write fails.
+    /// Takes anything that yields vectors the way [Database::push] takes them.
+    ///
+    /// Appended to the file, they're written together in as few calls as the
+    /// storage takes, rather than a record or less at a time. Those filling
+    /// in [reused](IdStrategy::Reuse) ids, appended to the history or kept in
+    /// a [VectorStore] are still pushed one by one.
+    pub fn push_many<I, V>(&mut self, vectors: I) -> Result<Vec<DbIndex>, Error>
+    where
+        I: IntoIterator<Item = V>,
+        V: AsRef<[f32]>,
+    {
+        let vectors = vectors.into_iter().collect::<Vec<_>>();
+        self.push_many_slices(&vectors.iter().map(V::as_ref).collect::<Vec<_>>())
+    }
+
+    fn push_many_slices(&mut self, vectors: &[DbVectorSlice]) -> Result<Vec<DbIndex>, Error> {
+        let dim_size = self.dim_size();
+        if let Some(v) = vectors.iter().find(|v| v.len() != dim_size as usize) {
+            return Err(Error::Dimension(dim_size, v.len()));
+        }
+        self.drain_queue()?;
+        self.check_quota(vectors.len() as u64)?;
+        self.index_reserve(vectors.len());
+        let conformed = vectors.iter().map(|v| self.conform_insert(v)).collect::<Vec<_>>();
+        let vectors = conformed.iter().map(|v| v.as_ref()).collect::<Vec<_>>();
+        if let Some(pushed) = self.push_batch(&vectors)? {
+            return Ok(pushed);
+        }
+
+        let mut pushed = Vec::with_capacity(vectors.len());
+        for vector in vectors {
+            match self.push_conformed(vector) {
+                Ok(index) => pushed.push(index),
+                Err(e) => {
+                    for index in pushed.into_iter().rev() {
+                        let _ = self.remove(index);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(pushed)
+    }
+
+    /// Same as [VectorHandle::push_batch], caching and indexing what it pushed.
+    fn push_batch(&mut self, vectors: &[DbVectorSlice]) -> Result<Option<Vec<DbIndex>>, Error> {
+        let _timer = self.time(Operation::Push);
+        self.check_writable()?;
+        let pushed = {
+            let (mut handle, _records) = self.lock_records();
+            handle.push_batch(vectors)?
+        };
+        let Some(pushed) = pushed else {
+            return Ok(None);
+        };
+        let mut loaded = self.loaded_vectors.lock_auto_clear_poison();
+        for (index, vector) in pushed.iter().zip(vectors) {
+            loaded.insert(*index, Arc::new(DbVector::from(*vector)));
+        }
+        drop(loaded);
+        for (index, vector) in pushed.iter().zip(vectors) {
+            if let Err(e) = self.index_insert(*index, vector) {
+                for index in pushed.iter().rev() {
+                    let _ = self.remove(*index);
                 }
-                Ok(None) => Ok(None),
-                Err(e) => Err(e),
-            },
-            Some(v) => Ok(Some(v.clone())),
+                return Err(e);
+            }
+        }
+        Ok(Some(pushed))
+    }
+
+    /// Builds a query vector out of stored ones, averaging `positive` and
+    /// subtracting the average of `negative`, e.g. `king - man + woman`.
+    /// The result is normalized if the metric only cares about direction.
+    pub fn compose_query(
+        &mut self,
+        positive: &[DbIndex],
+        negative: &[DbIndex],
+    ) -> Result<DbVector, Error> {
+        let mut scratch = Scratch::new();
+        let mut fetch = |ids: &[DbIndex]| -> Result<DbVector, Error> {
+            let vectors = ids
+                .iter()
+                .map(|id| self.get_scratch(*id, &mut scratch)?.ok_or(Error::Missing(*id)))
+                .collect::<Result<Vec<_>, Error>>()?;
+            Ok(ops::mean(&vectors.iter().map(|v| v.as_slice()).collect::<Vec<_>>()))
+        };
+        let (positive, negative) = (fetch(positive)?, fetch(negative)?);
+        let dim_size = self.handle.lock_auto_clear_poison().dim_size as usize;
+        let zeros = vec![0f32; dim_size];
+        let mut query = ops::sub(
+            if positive.is_empty() { &zeros } else { &positive },
+            if negative.is_empty() { &zeros } else { &negative },
+        );
+        if self.metric == Metric::Cosine {
+            ops::normalize(&mut query);
         }
+        Ok(query)
     }
 
-    pub fn flush(&self) -> Result<usize, Error> {
-        unimplemented!()
+    /// Ranks every stored vector by its distance to `query`, closest first
+    /// and ties broken by [DbIndex].
+    fn rank_exact(&self, query: DbVectorSlice) -> Result<Vec<(DbIndex, f32)>, Error> {
+        self.rank_exact_by(query, self.metric)
     }
 
-    pub fn push(&mut self, vector: DbVectorSlice) -> Result<DbIndex, Error> {
+    /// Same as [Database::rank_exact], by `metric` rather than the database's.
+    fn rank_exact_by(
+        &self,
+        query: DbVectorSlice,
+        metric: Metric,
+    ) -> Result<Vec<(DbIndex, f32)>, Error> {
+        let mut ranked = self.scan_exact(query, metric, None)?;
+        ranked.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+        Ok(ranked)
+    }
+
+    /// Distance by `metric` from `query` to every stored vector, in file order,
+    /// or to those compared against before `deadline` passed if there's one.
+    fn scan_exact(
+        &self,
+        query: DbVectorSlice,
+        metric: Metric,
+        mut deadline: Option<&mut Deadline>,
+    ) -> Result<Vec<(DbIndex, f32)>, Error> {
         let mut handle = self.handle.lock_auto_clear_poison();
-        match handle.push(vector) {
-            Ok(index) => {
-                let mut cache = self.loaded_vectors.lock_auto_clear_poison();
-                cache.insert(index, Rc::new(DbVector::from(vector)));
-                Ok(index)
-            }
-            Err(e) => Err(e),
+        if query.len() != handle.dim_size as usize {
+            return Err(Error::Dimension(handle.dim_size, query.len()));
         }
+        let mut distances = vec![];
+        handle.scan_while(|id, vector| {
+            if deadline.as_mut().is_some_and(|deadline| deadline.step()) {
+                return ControlFlow::Break(());
+            }
+            distances.push((id, metric.distance(query, vector)));
+            ControlFlow::Continue(())
+        })?;
+        Ok(distances)
     }
 
-    pub fn remove(&mut self, id: DbIndex) -> Result<Option<Rc<DbVector>>, Error> {
+    /// Reports pairs of stored vectors that lie within `threshold` of each other,
+    /// closest pairs first and at most `limit` of them. Each pair is reported once,
+    /// with the smaller [DbIndex] first.
+    ///
+    /// Each vector is searched for through the index for its `limit` nearest
+    /// neighbors, among which are all the pairs it takes part in that make the
+    /// cut, so a pair the search misses is missed. Without an index, vectors
+    /// are compared pair by pair, failing with [Error::IndexNotReady] if there
+    /// are more than 4096 of them.
+    pub fn near_duplicates(
+        &mut self,
+        threshold: f32,
+        limit: usize,
+    ) -> Result<Vec<(DbIndex, DbIndex, f32)>, Error> {
+        self.near_duplicates_with_progress(threshold, limit, |_, _| {})
+    }
+
+    /// Same as [Database::near_duplicates], calling `progress` with the number
+    /// of vectors checked so far and the total after each one.
+    pub fn near_duplicates_with_progress(
+        &mut self,
+        threshold: f32,
+        limit: usize,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<Vec<(DbIndex, DbIndex, f32)>, Error> {
+        let index = self.index.lock_auto_clear_poison();
         let mut handle = self.handle.lock_auto_clear_poison();
-        match handle.remove(id) {
-            Ok(Some(v)) => {
-                let mut cache = self.loaded_vectors.lock_auto_clear_poison();
-                cache.remove(&id);
-                Ok(Some(Rc::new(v)))
+        let total = handle.count()?;
+        let mut pairs = vec![];
+        match index.as_ref() {
+            Some(index) => {
+                // the vector itself comes first, and either of a pair may find the other
+                let k = limit.saturating_add(1);
+                let ef = max(k, self.search_params().ef_search as usize);
+                let mut scratch = SearchScratch::new();
+                let mut found = BTreeMap::new();
+                let (mut checked, mut failed) = (0, None);
+                handle.scan_while(|a, u| {
+                    let hooks = (&mut crate::algorithm::search::BestFirst, &index.metric());
+                    let bounds = (k, ef, None);
+                    let hits = match index.search_in(u, bounds, None, None, hooks, &mut scratch) {
+                        Ok(hits) => hits,
+                        Err(e) => {
+                            failed = Some(e);
+                            return ControlFlow::Break(());
+                        }
+                    };
+                    for (b, distance) in hits.results {
+                        if a != b && distance <= threshold {
+                            found.entry((min(a, b), max(a, b))).or_insert(distance);
+                        }
+                    }
+                    checked += 1;
+                    progress(checked, total);
+                    ControlFlow::Continue(())
+                })?;
+                if let Some(e) = failed {
+                    return Err(e);
+                }
+                pairs.extend(found.into_iter().map(|((a, b), distance)| (a, b, distance)));
             }
-            Ok(None) => Ok(None),
-            Err(e) => Err(e),
+            None if total <= PAIRWISE_DUPLICATES_MAX => {
+                let records = handle.read_all()?;
+                for (i, (a, u)) in records.iter().enumerate() {
+                    for (b, v) in records.iter().skip(i + 1) {
+                        let distance = self.metric.distance(u, v);
+                        if distance <= threshold {
+                            pairs.push((min(*a, *b), max(*a, *b), distance));
+                        }
+                    }
+                    progress(i as u64 + 1, total);
+                }
+            }
+            None => return Err(Error::IndexNotReady(IndexState::NotBuilt)),
         }
+        pairs.sort_by(|(a0, b0, d0), (a1, b1, d1)| {
+            d0.total_cmp(d1).then(a0.cmp(a1)).then(b0.cmp(b1))
+        });
+        pairs.truncate(limit);
+        Ok(pairs)
+    }
+
+    /// Removes several vectors at once, returning them in the order of `ids`.
+    /// Unlike removing them one by one, the file is rewritten only once.
+    pub fn remove_many(&mut self, ids: &[DbIndex]) -> Result<Vec<Option<Arc<DbVector>>>, Error> {
+        self.check_writable()?;
+        let removed = {
+            let (mut handle, _records) = self.lock_records();
+            handle.remove_many(ids)?
+        };
+        let (mut index, mut cache) = lock_pair(
+            (LockRank::Index, &self.index),
+            (LockRank::Cache, &self.loaded_vectors),
+        );
+        Ok(removed
+            .into_iter()
+            .zip(ids)
+            .map(|(vector, id)| {
+                let vector = vector?;
+                cache.remove(id);
+                if let Some(groups) = self.groups.as_mut() {
+                    groups.forget(*id);
+                }
+                if let Some(keys) = self.keys.as_mut() {
+                    keys.forget(*id);
+                }
+                if let Some(index) = index.as_mut() {
+                    index.remove(*id);
+                }
+                Some(Arc::new(vector))
+            })
+            .collect())
+    }
+
+    pub fn remove(&mut self, id: DbIndex) -> Result<Option<Arc<DbVector>>, Error> {
+        let _timer = self.time(Operation::Remove);
+        self.check_writable()?;
+        let removed = {
+            let (mut handle, _records) = self.lock_records();
+            handle.remove(id)?
+        };
+        let Some(v) = removed else {
+            return Ok(None);
+        };
+        self.loaded_vectors.lock_auto_clear_poison().remove(&id);
+        if let Some(groups) = self.groups.as_mut() {
+            groups.forget(id);
+        }
+        if let Some(keys) = self.keys.as_mut() {
+            keys.forget(id);
+        }
+        self.index_remove(id);
+        Ok(Some(Arc::new(v)))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::db::Database;
-    use std::io::Cursor;
+    use crate::db::{Database, DatabaseOptions, Error, IdStrategy, IndexState, Quota};
+    use crate::ds::bloom::BloomParams;
+    use crate::ext::mem::{CountingAccess, FaultyAccess, SharedCursor};
+    use crate::ext::rand::XorShift;
+    use crate::vio::dbheader::DbHeader;
+    use crate::vio::format::HEADER_SLOT_SIZE;
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+
+    #[test]
+    fn empty_files_work() {
+        assert!(matches!(
+            DatabaseOptions::default().open("mem", Box::new(Cursor::new(Vec::new()))),
+            Err(Error::EmptyFile)
+        ));
+
+        // the bloom block and alignment put the data section past the header
+        let header = DbHeader::new(4, IdStrategy::default(), Quota::default())
+            .with_bloom(BloomParams::for_rate(100, 0.01))
+            .with_alignment(64);
+        let mut bytes = Cursor::new(Vec::new());
+        header.write(&mut bytes).unwrap();
+        let bytes = bytes.into_inner();
+        assert!((bytes.len() as u64) < header.data_section);
+
+        // into the first slot, the second one not being in use
+        let short = Vec::from(&bytes[..HEADER_SLOT_SIZE as usize - 1]);
+        assert!(matches!(
+            DatabaseOptions::default().open("mem", Box::new(Cursor::new(short))),
+            Err(Error::Header(_))
+        ));
+
+        let file = SharedCursor::new();
+        let mut fd = file.reopen();
+        fd.write_all(&bytes).unwrap();
+        let mut db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.count().unwrap(), 0);
+        assert!(db.get(0).unwrap().is_none());
+        assert!(db.layers.is_empty());
+        assert_eq!(db.push([1f32; 4]).unwrap(), 0);
+        assert_eq!(file.len(), header.data_section + 64);
+
+        let db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.count().unwrap(), 1);
+        assert_eq!(*db.get(0).unwrap().unwrap(), vec![1f32; 4]);
+    }
+
+    #[test]
+    fn layout_check_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..3 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        // as if the header claimed a dimension of five
+        let mut fd = file.reopen();
+        fd.seek(SeekFrom::End(0)).unwrap();
+        fd.write_all(&[0u8; 4]).unwrap();
+        match DatabaseOptions::default().open("mem", Box::new(file.reopen())) {
+            Err(Error::Corrupted(reason)) => {
+                assert_eq!(reason, "4 bytes left over past records of 20 bytes")
+            }
+            _ => panic!("expected corruption"),
+        }
+        let db = Database::read_unchecked("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(*db.get(2).unwrap().unwrap(), vec![2f32; 4]);
+
+        // swapping the first two ids keeps the length right
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([0f32; 4]).unwrap();
+        db.push([1f32; 4]).unwrap();
+        let mut fd = file.reopen();
+        fd.seek(SeekFrom::End(-20)).unwrap();
+        fd.write_all(&0u32.to_be_bytes()).unwrap();
+        match DatabaseOptions::default().open("mem", Box::new(file.reopen())) {
+            Err(Error::Corrupted(reason)) => {
+                assert_eq!(reason, "record ids 0 and 0 are out of order")
+            }
+            _ => panic!("expected corruption"),
+        }
+    }
 
     #[test]
     fn append_works() {
         let fd = Box::new(Cursor::new(Vec::new()));
-        let mut db = Database::new("mem", 512, fd);
+        let mut db = DatabaseOptions::new(512).create("mem", fd).unwrap();
         let vector = Vec::from_iter((0..512).map(|i| i as f32));
-        let victim_id = db.push(&*vector).unwrap();
+        let victim_id = db.push(&vector).unwrap();
         assert_eq!(victim_id, 0);
 
         assert_eq!(db.handle.lock().unwrap().count().unwrap(), 1);
         assert_eq!(db.get(victim_id).unwrap().unwrap(), vector.into());
     }
 
+    #[test]
+    fn push_takes_any_vector() {
+        let mut db = DatabaseOptions::new(3)
+            .history(true)
+            .group_table(1)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let array = [1f32, 2f32, 3f32];
+        let boxed: Box<[f32]> = Box::new([4f32, 5f32, 6f32]);
+        assert_eq!(db.push(array).unwrap(), 0);
+        assert_eq!(db.push(array.map(|x| -x)).unwrap(), 1);
+        assert_eq!(db.push(vec![0f32; 3]).unwrap(), 2);
+        assert_eq!(db.push(&boxed).unwrap(), 3);
+        assert_eq!(db.push(&array[..]).unwrap(), 4);
+        assert!(matches!(db.push([0f32; 2]), Err(Error::Dimension(3, 2))));
+        assert!(matches!(db.push(Vec::new()), Err(Error::Dimension(3, 0))));
+
+        // every row of a matrix, or none if one doesn't fit
+        let matrix = ndarray::Array2::from_shape_fn((4, 3), |(i, j)| (i * 3 + j) as f32);
+        let rows = matrix.rows().into_iter().map(|row| row.to_vec());
+        assert_eq!(db.push_many(rows).unwrap(), [5, 6, 7, 8]);
+        assert_eq!(db.get(7).unwrap().unwrap().as_slice(), matrix.row(2).as_slice().unwrap());
+        let slices = matrix.as_slice().unwrap().chunks(3);
+        assert_eq!(db.push_many(slices).unwrap(), [9, 10, 11, 12]);
+        assert_eq!(db.push_many([[0f32; 3], [1f32; 3]]).unwrap(), [13, 14]);
+        assert!(db.push_many(Vec::<Vec<f32>>::new()).unwrap().is_empty());
+        let ragged = [vec![0f32; 3], vec![0f32; 4]];
+        assert!(matches!(db.push_many(&ragged), Err(Error::Dimension(3, 4))));
+        assert_eq!(db.count().unwrap(), 15);
+
+        assert_eq!(db.update(0, [7f32; 3]).unwrap().unwrap().as_slice(), array);
+        assert_eq!(db.update(0, vec![8f32; 3]).unwrap().unwrap().as_slice(), [7f32; 3]);
+        assert!(matches!(db.update(0, [0f32; 4]), Err(Error::Dimension(3, 4))));
+        assert!(db.update(99, boxed).unwrap().is_none());
+        assert_eq!(db.push_grouped(1, [9f32; 3]).unwrap(), 15);
+        let pending = db.push_async_queued(vec![9f32; 3]).unwrap();
+        assert_eq!(db.resolve(pending).unwrap(), 16);
+    }
+
+    #[test]
+    fn push_many_writes_at_once() {
+        let mut rng = XorShift::new(23);
+        let vectors = (0..1000).map(|_| rng.vector(8)).collect::<Vec<_>>();
+        let one_by_one = SharedCursor::new();
+        let mut db = DatabaseOptions::new(8)
+            .alignment(64)
+            .create("mem", Box::new(one_by_one.reopen()))
+            .unwrap();
+        for vector in &vectors {
+            db.push(vector).unwrap();
+        }
+
+        let file = CountingAccess::new(SharedCursor::new());
+        let mut db = DatabaseOptions::new(8)
+            .alignment(64)
+            .create("mem", Box::new(file.clone()))
+            .unwrap();
+        let writes = file.writes();
+        assert_eq!(db.push_many(&vectors).unwrap(), Vec::from_iter(0..1000));
+        // the bloom filter aside, the records go in a single call
+        assert!(file.writes() - writes <= 3, "{} writes", file.writes() - writes);
+        assert_eq!(file.bytes(), one_by_one.bytes());
+        assert_eq!(db.get(999).unwrap().unwrap().as_slice(), vectors[999].as_slice());
+
+        // cut off if the write fails halfway
+        let file = FaultyAccess::new();
+        let mut db = DatabaseOptions::new(8).create("mem", Box::new(file.reopen())).unwrap();
+        db.push_many(&vectors[..10]).unwrap();
+        file.crash_after(1000);
+        assert!(matches!(db.push_many(&vectors[10..]), Err(Error::IO(_))));
+        assert_eq!(db.count().unwrap(), 10);
+        assert!(db.get(10).unwrap().is_none());
+        let reopened = Database::read_unchecked("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(reopened.count().unwrap(), 10);
+    }
+
     #[test]
     fn index_works() {
         let fd = Box::new(Cursor::new(Vec::new()));
-        let mut db = Database::new("mem", 512, fd);
+        let mut db = DatabaseOptions::new(512).create("mem", fd).unwrap();
         let vector = Vec::from_iter((0..512).map(|i| i as f32));
         for _ in 0..200 {
-            db.push(&*vector).unwrap();
+            db.push(&vector).unwrap();
         }
         let victim_vect = Vec::from_iter((0..512).map(|_| 0f32));
-        let victim_id = db.push(&*victim_vect).unwrap();
+        let victim_id = db.push(&victim_vect).unwrap();
         for _ in 0..200 {
-            db.push(&*vector).unwrap();
+            db.push(&vector).unwrap();
         }
 
         let indexed = db.get(victim_id).unwrap().unwrap();
@@ -317,14 +1926,129 @@ mod tests {
     #[test]
     fn remove_works() {
         let fd = Box::new(Cursor::new(Vec::new()));
-        let mut db = Database::new("mem", 4, fd);
+        let mut db = DatabaseOptions::new(4).create("mem", fd).unwrap();
         for i in 1..=200 {
             let v = vec![i as f32, i as f32, i as f32, i as f32];
-            db.push(&*v).unwrap();
+            db.push(&v).unwrap();
         }
         assert_eq!(200, db.handle.lock().unwrap().count().unwrap());
 
         let removed = db.remove(198).unwrap().unwrap();
         assert_eq!(removed, vec![199f32, 199f32, 199f32, 199f32].into());
     }
+
+    #[test]
+    fn remove_many_works() {
+        let fill = |file: &CountingAccess| {
+            let mut db = DatabaseOptions::new(4).create("mem", Box::new(file.clone())).unwrap();
+            for i in 0..500 {
+                db.push([i as f32; 4]).unwrap();
+            }
+            db
+        };
+        let mut rng = XorShift::new(3);
+        let mut victims = vec![];
+        while victims.len() < 50 {
+            let id = (rng.next_u64() % 500) as u32;
+            if !victims.contains(&id) {
+                victims.push(id);
+            }
+        }
+
+        let sequential = CountingAccess::new(SharedCursor::new());
+        let mut db = fill(&sequential);
+        let before = sequential.written();
+        for id in &victims {
+            db.remove(*id).unwrap().unwrap();
+        }
+        let sequential_moved = sequential.written() - before;
+
+        let batched = CountingAccess::new(SharedCursor::new());
+        let mut db = fill(&batched);
+        let before = batched.written();
+        let mut ids = victims.clone();
+        ids.push(victims[0]);
+        ids.push(1000);
+        let removed = db.remove_many(&ids).unwrap();
+        let batched_moved = batched.written() - before;
+
+        for (id, vector) in victims.iter().zip(&removed) {
+            assert_eq!(**vector.as_ref().unwrap(), vec![*id as f32; 4]);
+        }
+        assert!(removed[50].is_none() && removed[51].is_none());
+        assert_eq!(db.count().unwrap(), 450);
+        assert_eq!(batched.bytes(), sequential.bytes());
+        assert!(batched_moved * 10 < sequential_moved);
+    }
+
+    #[test]
+    fn compose_query_works() {
+        let fd = Box::new(Cursor::new(Vec::new()));
+        let mut db = DatabaseOptions::new(2).create("mem", fd).unwrap();
+        let king = db.push([4f32, 4f32]).unwrap();
+        let man = db.push([3f32, 0f32]).unwrap();
+        let woman = db.push([1f32, 2f32]).unwrap();
+
+        let query = db.compose_query(&[king, woman], &[man]).unwrap();
+        assert_eq!(query, vec![-0.5f32, 3f32]);
+        assert_eq!(db.compose_query(&[], &[man]).unwrap(), vec![-3f32, 0f32]);
+        assert!(matches!(
+            db.compose_query(&[king, 42], &[man]),
+            Err(Error::Missing(42))
+        ));
+    }
+
+    #[test]
+    fn near_duplicates_works() {
+        let fd = Box::new(Cursor::new(Vec::new()));
+        let mut db = DatabaseOptions::new(8).create("mem", fd).unwrap();
+        let mut rng = XorShift::new(42);
+        for _ in 0..50 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        let original = rng.vector(8);
+        let a = db.push(&original).unwrap();
+        let b = db.push(&original).unwrap();
+        for _ in 0..50 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        let near = rng.vector(8);
+        let c = db.push(&near).unwrap();
+        let d = db.push(near.iter().map(|x| x + 0.001).collect::<Vec<_>>()).unwrap();
+
+        let mut checked = 0;
+        let pairs = db
+            .near_duplicates_with_progress(0.01, 10, |done, total| {
+                assert_eq!(total, 104);
+                checked = done;
+            })
+            .unwrap();
+        assert_eq!(checked, 104);
+        assert_eq!(pairs.len(), 2);
+        assert_eq!((pairs[0].0, pairs[0].1, pairs[0].2), (a, b, 0f32));
+        assert_eq!((pairs[1].0, pairs[1].1), (c, d));
+        assert_eq!(db.near_duplicates(0.01, 1).unwrap().len(), 1);
+
+        // the same through the index
+        db.build_index().unwrap();
+        checked = 0;
+        let indexed = db
+            .near_duplicates_with_progress(0.01, 10, |done, _| checked = done)
+            .unwrap();
+        assert_eq!((indexed, checked), (pairs.clone(), 104));
+        assert_eq!(db.near_duplicates(0.01, 1).unwrap(), pairs[..1]);
+        assert!(db.near_duplicates(0.01, 0).unwrap().is_empty());
+
+        // too many to compare pair by pair without one
+        db.drop_index();
+        for _ in 0..4000 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        assert!(matches!(
+            db.near_duplicates(0.01, 10),
+            Err(Error::IndexNotReady(IndexState::NotBuilt))
+        ));
+        db.build_index().unwrap();
+        assert_eq!(db.near_duplicates(0.01, 10).unwrap(), pairs);
+    }
 }
@@ -0,0 +1,193 @@
+use crate::db::{Database, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// # Advisory Config
+/// When removals are deemed to rewrite too much of the file,
+/// see [Database::write_advisory].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdvisoryConfig {
+    /// Number of most recent writes the amplification is judged over.
+    pub window: usize,
+    /// Amplification over the window beyond which the advisory is given.
+    pub threshold: f64,
+}
+
+impl Default for AdvisoryConfig {
+    fn default() -> Self {
+        AdvisoryConfig {
+            window: 64,
+            threshold: 8f64,
+        }
+    }
+}
+
+/// # Write Advisory
+/// Recent writes, mostly removals, rewrote many times the bytes they changed,
+/// because every record behind a removed one is moved to close the gap.
+/// Append-only databases, see [DatabaseOptions::history](crate::db::DatabaseOptions::history),
+/// leave a tombstone instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WriteAdvisory {
+    pub recent_amplification: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for WriteAdvisory {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "recent writes amplified {0:.1} times (beyond {1}), consider an append-only database",
+            self.recent_amplification, self.threshold
+        )
+    }
+}
+
+/// Bytes written to the file against bytes of records changed, in total
+/// and over the most recent writes.
+#[derive(Debug, Default)]
+pub(crate) struct Amplification {
+    pub(crate) logical: u64,
+    pub(crate) physical: u64,
+    /// `(logical, physical)` of each recent write, oldest first.
+    recent: VecDeque<(u64, u64)>,
+    pub(crate) config: AdvisoryConfig,
+}
+
+impl Amplification {
+    /// Accounts for a write that changed `logical` bytes of records and
+    /// moved `moved` bytes of others out of the way.
+    pub(crate) fn record(&mut self, logical: u64, moved: u64) {
+        let physical = logical + moved;
+        self.logical += logical;
+        self.physical += physical;
+        self.recent.push_back((logical, physical));
+        while self.recent.len() > self.config.window {
+            self.recent.pop_front();
+        }
+    }
+
+    /// `(logical, physical)` summed over the window.
+    pub(crate) fn recent(&self) -> (u64, u64) {
+        self.recent
+            .iter()
+            .fold((0, 0), |(l, p), (logical, physical)| (l + logical, p + physical))
+    }
+
+    pub(crate) fn set_config(&mut self, config: AdvisoryConfig) {
+        self.config = config;
+        while self.recent.len() > config.window {
+            self.recent.pop_front();
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.logical = 0;
+        self.physical = 0;
+        self.recent.clear();
+    }
+
+    fn advisory(&self) -> Option<WriteAdvisory> {
+        let (logical, physical) = self.recent();
+        let recent_amplification = ratio(logical, physical);
+        (recent_amplification > self.config.threshold).then_some(WriteAdvisory {
+            recent_amplification,
+            threshold: self.config.threshold,
+        })
+    }
+}
+
+/// Physical over logical bytes, one if nothing was written.
+pub(crate) fn ratio(logical: u64, physical: u64) -> f64 {
+    if logical == 0 {
+        1f64
+    } else {
+        physical as f64 / logical as f64
+    }
+}
+
+impl VectorHandle {
+    pub(crate) fn amplified(&mut self, logical: u64, moved: u64) {
+        self.amplification.record(logical, moved);
+        self.revision += 1;
+        self.publish();
+    }
+}
+
+impl Database {
+    /// Advises on the way the database is written if recent writes amplified
+    /// beyond the [threshold](AdvisoryConfig), which takes removing records
+    /// from the front of a large file. Meant to be checked after [Database::remove]
+    /// and [Database::remove_many], it clears once writes calm down.
+    pub fn write_advisory(&self) -> Option<WriteAdvisory> {
+        self.handle.lock_auto_clear_poison().amplification.advisory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{AdvisoryConfig, DatabaseOptions};
+    use crate::ext::mem::SharedCursor;
+
+    #[test]
+    fn write_amplification_works() {
+        let file = SharedCursor::new();
+        // records take 4 bytes of id and 2 * 4 bytes of vector
+        let mut db = DatabaseOptions::new(2)
+            .write_advisory(AdvisoryConfig {
+                window: 3,
+                threshold: 4f64,
+            })
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..10 {
+            db.push([i as f32; 2]).unwrap();
+        }
+        let stats = db.stats().unwrap();
+        assert_eq!((stats.logical_bytes, stats.physical_bytes), (120, 120));
+        assert_eq!(stats.write_amplification(), 1f64);
+        assert!(db.write_advisory().is_none());
+
+        // the 9 records behind it move
+        db.remove(0).unwrap();
+        // 2 records move behind id 2, then 4 behind id 5
+        db.remove_many(&[2, 5]).unwrap();
+        // nothing behind the last one
+        db.remove(9).unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.logical_bytes, 120 + 12 + 24 + 12);
+        assert_eq!(stats.physical_bytes, 120 + (12 + 108) + (24 + 72) + 12);
+        assert_eq!(stats.write_amplification(), 348f64 / 168f64);
+        assert_eq!((stats.recent_logical_bytes, stats.recent_physical_bytes), (48, 228));
+        assert_eq!(stats.recent_write_amplification(), 4.75);
+        let advisory = db.write_advisory().unwrap();
+        assert_eq!(advisory.recent_amplification, 4.75);
+
+        // the window moves past the first removal
+        db.push([10f32; 2]).unwrap();
+        assert_eq!(db.stats().unwrap().recent_write_amplification(), 2.5);
+        assert!(db.write_advisory().is_none());
+
+        db.reset_stats();
+        let stats = db.stats().unwrap();
+        assert_eq!((stats.logical_bytes, stats.physical_bytes), (0, 0));
+        assert_eq!(stats.write_amplification(), 1f64);
+    }
+
+    #[test]
+    fn append_only_not_amplified() {
+        let mut db = DatabaseOptions::new(2)
+            .history(true)
+            .create("mem", Box::new(std::io::Cursor::new(Vec::new())))
+            .unwrap();
+        for i in 0..10 {
+            db.push([i as f32; 2]).unwrap();
+        }
+        db.remove(0).unwrap();
+        db.update(1, [0f32; 2]).unwrap();
+        assert_eq!(db.stats().unwrap().write_amplification(), 1f64);
+        assert!(db.write_advisory().is_none());
+    }
+}
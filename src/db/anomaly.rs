@@ -0,0 +1,334 @@
+use crate::db::DbIndex;
+use crate::vio::SkippedLayer;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// Fraction of the records an append-only file holds that have to be
+/// superseded for [Anomaly::MostlySuperseded] to be reported.
+const SUPERSEDED_RATIO: f64 = 0.5;
+
+/// # Severity
+/// How much an [Anomaly] is worth the caller's attention, least first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Costs some time or space, but nothing read is affected.
+    Info,
+    /// Something was left out or stands for what's gone.
+    Warning,
+    /// Some of what's read may be wrong.
+    Error,
+}
+
+/// # Suggested Action
+/// What would make an [Anomaly] go away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SuggestedAction {
+    /// Nothing needs to be done, the next write takes care of it.
+    None,
+    /// [Build the index](crate::db::Database::build_index) anew.
+    Reindex,
+    /// [Rebuild the layer](crate::db::Database::rebuild_layer) of this level.
+    RebuildLayer(u32),
+    /// [Compact](crate::db::Database::compact) the history.
+    Compact,
+    /// Cut the file down to this many bytes, before the record cut off at its end.
+    Truncate(u64),
+    /// [Sanitize](crate::db::Database::sanitize) the vectors.
+    Sanitize,
+    /// Restore the file from a backup, as it can't be put right in place.
+    Restore,
+    /// [Fill](crate::db::Database::fill) the ids, or [remove](crate::db::Database::remove)
+    /// those no longer awaited.
+    Fill,
+}
+
+/// # Anomaly
+/// Something off about a file that opening it got past, see
+/// [DatabaseOptions::open_with_report](crate::db::DatabaseOptions::open_with_report),
+/// or that [verifying](crate::db::Database::verify_level) it found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// The bloom filter block was flagged outdated, as when the database wasn't
+    /// [flushed](crate::db::Database::flush) before it was closed. It was rebuilt from the records.
+    StaleBloomFilter,
+    /// The header slot at `offset` didn't pass its checksum, as when a rewrite
+    /// was cut short, and the other one was read, see [crate::vio::format::HEADER_SLOT_SIZE].
+    TornHeaderSlot { offset: u64 },
+    /// A layer in an encoding this build doesn't know was left out.
+    SkippedLayer(SkippedLayer),
+    /// Nodes of a stored layer whose records are gone, ascending.
+    DanglingLayerNodes { level: u32, nodes: Vec<DbIndex> },
+    /// Most of the records of an append-only file were superseded
+    /// by later writes to their ids.
+    MostlySuperseded { records: u64, superseded: u64 },
+    /// `len` bytes at the end of the file don't make up a record, as when
+    /// an append was cut short. Only files read [unchecked](crate::db::Database::read_unchecked)
+    /// get this far, the next record being appended right after them.
+    TrailingBytes { offset: u64, len: u64 },
+    /// The record at `offset` doesn't come after the ones before it, by id
+    /// or by generation in append-only files, so lookups may miss it.
+    RecordOutOfOrder { offset: u64, id: DbIndex },
+    /// The stored layer at `offset` doesn't parse, or runs `len` bytes
+    /// from there into the data section.
+    MalformedLayer { offset: u64, len: u64 },
+    /// The record at `offset` has NaN or infinite components, which were
+    /// either pushed that way or got damaged.
+    NonFiniteRecord { offset: u64, id: DbIndex },
+    /// Nodes of the index whose records are gone, ascending, see
+    /// [Database::verify](crate::db::Database::verify).
+    DanglingIndexNodes { nodes: Vec<DbIndex> },
+    /// Ids [reserved](crate::db::Database::reserve_ids) before the database
+    /// was opened and still not filled, ascending, as when what was to fill
+    /// them never came.
+    UnfilledSlots { ids: Vec<DbIndex> },
+}
+
+impl Anomaly {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Anomaly::StaleBloomFilter
+            | Anomaly::MostlySuperseded { .. }
+            | Anomaly::UnfilledSlots { .. } => Severity::Info,
+            Anomaly::TornHeaderSlot { .. }
+            | Anomaly::SkippedLayer(_)
+            | Anomaly::DanglingLayerNodes { .. }
+            | Anomaly::TrailingBytes { .. }
+            | Anomaly::MalformedLayer { .. }
+            | Anomaly::NonFiniteRecord { .. }
+            | Anomaly::DanglingIndexNodes { .. } => Severity::Warning,
+            Anomaly::RecordOutOfOrder { .. } => Severity::Error,
+        }
+    }
+
+    pub fn action(&self) -> SuggestedAction {
+        match self {
+            Anomaly::StaleBloomFilter | Anomaly::TornHeaderSlot { .. } => SuggestedAction::None,
+            Anomaly::SkippedLayer(_)
+            | Anomaly::MalformedLayer { .. }
+            | Anomaly::DanglingIndexNodes { .. } => SuggestedAction::Reindex,
+            Anomaly::DanglingLayerNodes { level, .. } => SuggestedAction::RebuildLayer(*level),
+            Anomaly::MostlySuperseded { .. } => SuggestedAction::Compact,
+            Anomaly::TrailingBytes { offset, .. } => SuggestedAction::Truncate(*offset),
+            Anomaly::NonFiniteRecord { .. } => SuggestedAction::Sanitize,
+            Anomaly::RecordOutOfOrder { .. } => SuggestedAction::Restore,
+            Anomaly::UnfilledSlots { .. } => SuggestedAction::Fill,
+        }
+    }
+
+    /// Where in the file what it's about begins, if it's about some bytes in particular.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Anomaly::TornHeaderSlot { offset }
+            | Anomaly::TrailingBytes { offset, .. }
+            | Anomaly::RecordOutOfOrder { offset, .. }
+            | Anomaly::MalformedLayer { offset, .. }
+            | Anomaly::NonFiniteRecord { offset, .. } => Some(*offset),
+            Anomaly::SkippedLayer(layer) => Some(layer.offset),
+            Anomaly::StaleBloomFilter
+            | Anomaly::DanglingLayerNodes { .. }
+            | Anomaly::MostlySuperseded { .. }
+            | Anomaly::DanglingIndexNodes { .. }
+            | Anomaly::UnfilledSlots { .. } => None,
+        }
+    }
+
+    /// [Anomaly::MostlySuperseded] if it's the case.
+    pub(crate) fn superseded(records: u64, superseded: u64) -> Option<Anomaly> {
+        (records > 0 && superseded as f64 > records as f64 * SUPERSEDED_RATIO)
+            .then_some(Anomaly::MostlySuperseded { records, superseded })
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Anomaly::StaleBloomFilter => write!(f, "stale bloom filter"),
+            Anomaly::TornHeaderSlot { offset } => write!(f, "torn header slot at {offset}"),
+            Anomaly::SkippedLayer(layer) => write!(
+                f,
+                "skipped layer {} of unknown encoding {:#x}",
+                layer.level, layer.encoding
+            ),
+            Anomaly::DanglingLayerNodes { level, nodes } => {
+                write!(f, "{} dangling nodes on layer {level}", nodes.len())
+            }
+            Anomaly::MostlySuperseded {
+                records,
+                superseded,
+            } => write!(f, "{superseded} of {records} records superseded"),
+            Anomaly::TrailingBytes { offset, len } => {
+                write!(f, "{len} trailing bytes at {offset}")
+            }
+            Anomaly::RecordOutOfOrder { offset, id } => {
+                write!(f, "record of id {id} out of order at {offset}")
+            }
+            Anomaly::MalformedLayer { offset, len } => {
+                write!(f, "malformed layer of {len} bytes at {offset}")
+            }
+            Anomaly::NonFiniteRecord { offset, id } => {
+                write!(f, "non-finite components in record of id {id} at {offset}")
+            }
+            Anomaly::DanglingIndexNodes { nodes } => {
+                write!(f, "{} dangling nodes in the index", nodes.len())
+            }
+            Anomaly::UnfilledSlots { ids } => write!(f, "{} ids reserved but never filled", ids.len()),
+        }
+    }
+}
+
+/// # Open Report
+/// Anomalies found opening a database, in the order they were come across,
+/// among them what the [quick](crate::db::VerifyLevel::Quick) verification
+/// finds but doesn't fail opening over.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpenReport {
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl OpenReport {
+    pub fn is_clean(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+
+    /// Severity of the most severe anomaly, if any.
+    pub fn worst(&self) -> Option<Severity> {
+        self.anomalies.iter().map(Anomaly::severity).max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        Anomaly, DatabaseOptions, Error, IdStrategy, Quota, SearchParams, Severity,
+        SuggestedAction,
+    };
+    use crate::ds::graph::{Graph, NdGraph};
+    use crate::ds::layer::HnswLayer;
+    use crate::ext::mem::SharedCursor;
+    use crate::vio::dbheader::DbHeader;
+    use crate::vio::layer;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+
+    fn report(file: &SharedCursor) -> Vec<(Anomaly, Severity, SuggestedAction)> {
+        let (_, report) = DatabaseOptions::default()
+            .open_with_report("mem", Box::new(file.reopen()))
+            .unwrap();
+        report
+            .anomalies
+            .into_iter()
+            .map(|a| (a.clone(), a.severity(), a.action()))
+            .collect()
+    }
+
+    #[test]
+    fn open_report_works() {
+        // pushed to without a flush, then rewritten halfway
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .bloom_filter(100, 0.01)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert!(report(&file).is_empty());
+        db.set_search_params(SearchParams {
+            ef_search: 7,
+            ..db.search_params()
+        });
+        db.flush().unwrap();
+        db.push([1f32; 4]).unwrap();
+        drop(db);
+        // the first slot was superseded by the second
+        let mut fd = file.reopen();
+        fd.seek(SeekFrom::Start(200)).unwrap();
+        fd.write_all(&[!file.bytes()[200]]).unwrap();
+        assert_eq!(
+            report(&file),
+            [
+                (
+                    Anomaly::TornHeaderSlot { offset: 0 },
+                    Severity::Warning,
+                    SuggestedAction::None
+                ),
+                (Anomaly::StaleBloomFilter, Severity::Info, SuggestedAction::None),
+            ]
+        );
+        let strict = DatabaseOptions::default()
+            .strict(Severity::Warning)
+            .open("mem", Box::new(file.reopen()));
+        assert!(matches!(strict, Err(Error::Anomaly(Anomaly::TornHeaderSlot { .. }))));
+
+        // a layer of some newer build and one of nodes long gone
+        let mut dense = NdGraph::with_capacity(3);
+        dense.push_many(3);
+        dense.connect(0, 2, 1f32).unwrap();
+        let mut layers = Cursor::new(Vec::new());
+        layers.write_u32::<BigEndian>(2).unwrap();
+        layer::write(&HnswLayer::new(dense, 1), &mut layers).unwrap();
+        layers.write_u32::<BigEndian>(2).unwrap();
+        layers.write_u8(0x7f).unwrap();
+        layers.write_u32::<BigEndian>(0).unwrap();
+        let layers = layers.into_inner();
+        let mut header = DbHeader::new(4, IdStrategy::default(), Quota::default());
+        header.data_section = header.size() + layers.len() as u64;
+        let file = SharedCursor::new();
+        let mut fd = file.reopen();
+        header.write(&mut fd).unwrap();
+        fd.write_all(&layers).unwrap();
+        let mut db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([0f32; 4]).unwrap();
+        db.flush().unwrap();
+        let skipped = db.skipped_layers()[0];
+        let reported = report(&file);
+        assert_eq!(
+            reported,
+            [
+                (
+                    Anomaly::SkippedLayer(skipped),
+                    Severity::Warning,
+                    SuggestedAction::Reindex
+                ),
+                (
+                    Anomaly::DanglingLayerNodes {
+                        level: 1,
+                        nodes: vec![2]
+                    },
+                    Severity::Warning,
+                    SuggestedAction::RebuildLayer(1)
+                ),
+            ]
+        );
+        assert!(DatabaseOptions::default()
+            .strict(Severity::Warning)
+            .open("mem", Box::new(file.reopen()))
+            .is_err());
+
+        // updated over and over
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .history(true)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([0f32; 4]).unwrap();
+        db.push([1f32; 4]).unwrap();
+        for i in 0..3 {
+            db.update(0, [i as f32; 4]).unwrap();
+        }
+        drop(db);
+        let superseded = Anomaly::MostlySuperseded {
+            records: 5,
+            superseded: 3,
+        };
+        assert_eq!(
+            report(&file),
+            [(superseded, Severity::Info, SuggestedAction::Compact)]
+        );
+        let (db, report) = DatabaseOptions::default()
+            .strict(Severity::Warning)
+            .open_with_report("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(report.worst(), Some(Severity::Info));
+        assert_eq!(db.count().unwrap(), 2);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::db::{Database, DbIndex, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use crate::vio;
+use std::io::SeekFrom;
+
+impl VectorHandle {
+    /// False only if `id` is definitely not stored.
+    pub(crate) fn may_contain(&self, id: DbIndex) -> bool {
+        self.bloom.as_ref().is_none_or(|bloom| bloom.may_contain(id))
+    }
+
+    /// Adds `id` to the filter, flagging the block on file as stale first
+    /// so a crash before the next flush doesn't leave it missing ids.
+    pub(crate) fn insert_bloom(&mut self, id: DbIndex) -> Result<(), Error> {
+        let Some(bloom) = self.bloom.as_mut() else {
+            return Ok(());
+        };
+        bloom.insert(id);
+        if self.bloom_fresh {
+            self.fd
+                .seek(SeekFrom::Start(self.bloom_offset))
+                .map_err(Error::IO)?;
+            vio::bloom::mark_stale(&mut self.fd).map_err(Error::IO)?;
+            self.bloom_fresh = false;
+        }
+        Ok(())
+    }
+
+    /// Writes the filter into its block, returning the bytes written.
+    pub(crate) fn flush_bloom(&mut self) -> Result<u64, Error> {
+        let Some(bloom) = self.bloom.as_ref() else {
+            return Ok(0);
+        };
+        self.fd
+            .seek(SeekFrom::Start(self.bloom_offset))
+            .map_err(Error::IO)?;
+        let written = vio::bloom::write(&mut self.fd, bloom).map_err(Error::IO)?;
+        self.bloom_fresh = true;
+        self.written(written)?;
+        Ok(written)
+    }
+}
+
+impl Database {
+    /// Whether vector `id` is stored. With a bloom filter, most absent
+    /// ids are told apart without reading the file.
+    pub fn contains(&self, id: DbIndex) -> Result<bool, Error> {
+        if self.loaded_vectors.lock_auto_clear_poison().contains_key(&id) {
+            return Ok(true);
+        }
+        let mut handle = self.handle.lock_auto_clear_poison();
+        Ok(handle.may_contain(id) && !handle.is_unfilled(id) && handle.seek_item(id)?.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::DatabaseOptions;
+    use crate::ext::mem::{CountingAccess, SharedCursor};
+
+    #[test]
+    fn absent_lookup_skips_file() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .bloom_filter(1000, 0.001)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..100 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        db.flush().unwrap();
+        drop(db);
+
+        let counting = CountingAccess::new(file.reopen());
+        let db = DatabaseOptions::default().open("mem", Box::new(counting.clone())).unwrap();
+        let opened = counting.seeks();
+        let missed = (1000..1100).filter(|id| db.get(*id).unwrap().is_none()).count();
+        assert_eq!(missed, 100);
+        assert!(!db.contains(1234).unwrap());
+        assert_eq!(counting.seeks(), opened);
+
+        for id in 0..100 {
+            assert!(db.contains(id).unwrap());
+            assert_eq!(*db.get(id).unwrap().unwrap(), vec![id as f32; 4]);
+        }
+        assert!(counting.seeks() > opened);
+    }
+
+    #[test]
+    fn stale_filter_is_rebuilt() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(2)
+            .bloom_filter(100, 0.01)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([0f32; 2]).unwrap();
+        db.flush().unwrap();
+        db.push([1f32; 2]).unwrap();
+        drop(db);
+
+        let mut db = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(*db.get(1).unwrap().unwrap(), vec![1f32; 2]);
+        assert!(db.contains(0).unwrap());
+        assert!(db.remove(2).unwrap().is_none());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::db::{
+    Database, DatabaseOptions, DbIndex, DbStats, DbVector, Error, RecordIter, SearchRequest,
+    SearchResponse,
+};
+use crate::ext::mem::ReadOnly;
+use crate::index::BuildStats;
+use crate::metric::Metric;
+use std::io::Cursor;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// # Borrowed Database
+/// A database read straight from bytes borrowed for `'a`, such as a file
+/// baked into the binary, see [Database::from_slice]. Only what reads it
+/// is there, so writing to it doesn't compile:
+/// ```compile_fail
+/// use vectoria::db::Database;
+///
+/// static PREBUILT: &[u8] = &[];
+/// let mut db = Database::from_static("prebuilt", PREBUILT).unwrap();
+/// db.push([0f32; 4]).unwrap();
+/// ```
+pub struct BorrowedDatabase<'a> {
+    db: Database,
+    bytes: PhantomData<&'a [u8]>,
+}
+
+/// # Borrowed Records
+/// Same as [RecordIter], over the records of a [BorrowedDatabase].
+pub struct BorrowedRecords<'a> {
+    records: RecordIter,
+    bytes: PhantomData<&'a [u8]>,
+}
+
+impl Iterator for BorrowedRecords<'_> {
+    type Item = Result<(DbIndex, DbVector), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.records.next()
+    }
+}
+
+impl Database {
+    /// Opens the database whose file is `bytes`, read in place rather than
+    /// copied, as [included](include_bytes) in the binary.
+    pub fn from_static(
+        name: &str,
+        bytes: &'static [u8],
+    ) -> Result<BorrowedDatabase<'static>, Error> {
+        Database::from_slice(name, bytes)
+    }
+
+    /// Opens the database whose file is `bytes`, read in place rather than
+    /// copied, for as long as they're borrowed.
+    pub fn from_slice<'a>(name: &str, bytes: &'a [u8]) -> Result<BorrowedDatabase<'a>, Error> {
+        // SAFETY: the database reads the bytes through its handles alone,
+        // which stay inside it and the iterators of its records, and
+        // neither outlives 'a as a BorrowedDatabase or BorrowedRecords
+        let bytes = unsafe { std::mem::transmute::<&'a [u8], &'static [u8]>(bytes) };
+        let db = DatabaseOptions::default()
+            .read_only(true)
+            .open(name, Box::new(ReadOnly(Cursor::new(bytes))))?;
+        Ok(BorrowedDatabase {
+            db,
+            bytes: PhantomData,
+        })
+    }
+}
+
+impl BorrowedDatabase<'_> {
+    pub fn name(&self) -> &str {
+        self.db.name()
+    }
+
+    pub fn dim_size(&self) -> u32 {
+        self.db.dim_size()
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.db.metric()
+    }
+
+    /// See [Database::count].
+    pub fn count(&self) -> Result<u64, Error> {
+        self.db.count()
+    }
+
+    /// See [Database::get].
+    pub fn get(&self, id: DbIndex) -> Result<Option<Arc<DbVector>>, Error> {
+        self.db.get(id)
+    }
+
+    /// See [Database::get_many].
+    pub fn get_many(&self, ids: &[DbIndex]) -> Result<Vec<Option<Arc<DbVector>>>, Error> {
+        self.db.get_many(ids)
+    }
+
+    /// See [Database::contains].
+    pub fn contains(&self, id: DbIndex) -> Result<bool, Error> {
+        self.db.contains(id)
+    }
+
+    /// See [Database::iter].
+    pub fn iter(&self) -> Result<BorrowedRecords<'_>, Error> {
+        Ok(BorrowedRecords {
+            records: self.db.iter()?,
+            bytes: PhantomData,
+        })
+    }
+
+    /// Builds the index in memory, the bytes left as they are,
+    /// see [Database::build_index].
+    pub fn build_index(&self) -> Result<BuildStats, Error> {
+        self.db.build_index()
+    }
+
+    /// See [Database::query].
+    pub fn query(&mut self, request: &SearchRequest) -> Result<SearchResponse, Error> {
+        self.db.query(request)
+    }
+
+    /// See [Database::stats].
+    pub fn stats(&self) -> Result<DbStats, Error> {
+        self.db.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, SearchPlan, SearchRequest};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+
+    #[test]
+    fn from_slice_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut rng = XorShift::new(9);
+        for _ in 0..300 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        db.remove(5).unwrap();
+        db.flush().unwrap();
+        let query = rng.vector(8);
+        let expected = db.query(&SearchRequest::new(&query, 10)).unwrap().results;
+
+        let bytes = file.bytes();
+        let mut borrowed = Database::from_slice("borrowed", &bytes).unwrap();
+        assert_eq!((borrowed.name(), borrowed.dim_size()), ("borrowed", 8));
+        assert_eq!(borrowed.count().unwrap(), 299);
+        for id in [0, 7, 299] {
+            assert_eq!(borrowed.get(id).unwrap(), db.get(id).unwrap());
+        }
+        assert!(borrowed.get(5).unwrap().is_none());
+        assert!(!borrowed.contains(5).unwrap());
+        let records = borrowed.iter().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 299);
+        assert_eq!(records[0].1, *db.get(0).unwrap().unwrap());
+
+        let found = borrowed.query(&SearchRequest::new(&query, 10)).unwrap();
+        assert_eq!(found.results, expected);
+        borrowed.build_index().unwrap();
+        let request = SearchRequest::new(&query, 10).plan(SearchPlan::Graph);
+        let found = borrowed.query(&request).unwrap();
+        assert_eq!(found.plan, SearchPlan::Graph);
+        assert_eq!(found.results[0], expected[0]);
+        assert_eq!(borrowed.stats().unwrap().vectors, 299);
+        // the bytes are read in place, never written
+        drop(borrowed);
+        assert_eq!(bytes, file.bytes());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::db::{Database, DbIndex, DbVector, Error};
+use crate::ext::semaphore::LockAutoClear;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// # Cache Mode
+/// How many of the vectors read or written are kept in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheMode {
+    /// Every vector ever read or written.
+    #[default]
+    Unbounded,
+    /// The most recent ones, taking at most this many bytes together.
+    Bounded(u64),
+    /// None at all, for devices too small for them or when the page cache
+    /// of the OS already does the job. Each query keeps the vectors it reads
+    /// until it's done.
+    None,
+}
+
+/// # Vector Cache
+/// Vectors read or written recently, kept in memory to spare the file.
+/// With a budget, the oldest ones make room for new ones once the
+/// vectors together would take more bytes than it allows.
+#[derive(Default)]
+pub(crate) struct VectorCache {
+    vectors: HashMap<DbIndex, Arc<DbVector>>,
+    /// Ids in the order they were cached, including some no longer cached.
+    /// Only kept with a budget.
+    order: VecDeque<DbIndex>,
+    bytes: u64,
+    mode: CacheMode,
+}
+
+impl VectorCache {
+    pub(crate) fn set_mode(&mut self, mode: CacheMode) {
+        self.mode = mode;
+        if mode == CacheMode::None {
+            self.vectors.clear();
+            self.bytes = 0;
+        }
+        self.order = match mode {
+            CacheMode::Bounded(_) => self.vectors.keys().copied().collect(),
+            _ => VecDeque::new(),
+        };
+        self.shrink(0);
+    }
+
+    pub(crate) fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    /// Bytes the vectors cached take together.
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub(crate) fn get(&self, id: &DbIndex) -> Option<&Arc<DbVector>> {
+        self.vectors.get(id)
+    }
+
+    pub(crate) fn contains_key(&self, id: &DbIndex) -> bool {
+        self.vectors.contains_key(id)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub(crate) fn insert(&mut self, id: DbIndex, vector: Arc<DbVector>) {
+        let size = Self::size_of(&vector);
+        match self.mode {
+            CacheMode::None => return,
+            CacheMode::Bounded(budget) if size > budget => {
+                self.remove(&id);
+                return;
+            }
+            _ => {}
+        }
+        self.shrink(size);
+        if let Some(replaced) = self.vectors.insert(id, vector) {
+            self.bytes -= Self::size_of(&replaced);
+        }
+        if let CacheMode::Bounded(_) = self.mode {
+            self.order.push_back(id);
+        }
+        self.bytes += size;
+    }
+
+    pub(crate) fn remove(&mut self, id: &DbIndex) -> Option<Arc<DbVector>> {
+        let removed = self.vectors.remove(id)?;
+        self.bytes -= Self::size_of(&removed);
+        Some(removed)
+    }
+
+    /// Evicts the oldest vectors until `incoming` more bytes fit into the budget.
+    fn shrink(&mut self, incoming: u64) {
+        let CacheMode::Bounded(budget) = self.mode else {
+            return;
+        };
+        while self.bytes + incoming > budget {
+            let Some(oldest) = self.order.pop_front() else {
+                return;
+            };
+            self.remove(&oldest);
+        }
+    }
+
+    fn size_of(vector: &DbVector) -> u64 {
+        (vector.len() * size_of::<f32>()) as u64
+    }
+}
+
+/// Vectors a single query has read, dropped along with it.
+pub(crate) type Scratch = HashMap<DbIndex, Arc<DbVector>>;
+
+impl Database {
+    pub fn cache_mode(&self) -> CacheMode {
+        self.loaded_vectors.lock_auto_clear_poison().mode()
+    }
+
+    /// Same as [Database::get], but keeps what it reads in `scratch` while
+    /// the cache is off, so that a query reading a vector twice reads the file once.
+    pub(crate) fn get_scratch(
+        &self,
+        id: DbIndex,
+        scratch: &mut Scratch,
+    ) -> Result<Option<Arc<DbVector>>, Error> {
+        if let Some(vector) = scratch.get(&id) {
+            return Ok(Some(vector.clone()));
+        }
+        let vector = self.get(id)?;
+        if let Some(vector) = &vector {
+            if self.cache_mode() == CacheMode::None {
+                scratch.insert(id, vector.clone());
+            }
+        }
+        Ok(vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{CacheMode, Database, DatabaseOptions, SearchRequest};
+    use crate::ext::mem::{CountingAccess, SharedCursor};
+    use crate::ext::semaphore::LockAutoClear;
+
+    fn cached(db: &Database) -> usize {
+        db.loaded_vectors.lock_auto_clear_poison().len()
+    }
+
+    #[test]
+    fn no_cache_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(2)
+            .cache_mode(CacheMode::None)
+            .history(true)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.cache_mode(), CacheMode::None);
+        for i in 0..6 {
+            db.push([i as f32, 1f32]).unwrap();
+        }
+        db.push_many([&[6f32, 1f32], &[7f32, 1f32]]).unwrap();
+        assert_eq!(cached(&db), 0);
+
+        assert_eq!(*db.get(2).unwrap().unwrap(), vec![2f32, 1f32]);
+        assert!(db.contains(2).unwrap());
+        assert_eq!(cached(&db), 0);
+
+        // nothing stale is left behind to be read after a removal or update
+        assert_eq!(*db.remove(2).unwrap().unwrap(), vec![2f32, 1f32]);
+        assert!(db.get(2).unwrap().is_none());
+        assert!(!db.contains(2).unwrap());
+        db.update(3, [30f32, 1f32]).unwrap();
+        assert_eq!(*db.get(3).unwrap().unwrap(), vec![30f32, 1f32]);
+        db.remove_many(&[4, 5]).unwrap();
+        assert!(db.get_many(&[4, 5]).unwrap().iter().all(Option::is_none));
+        assert_eq!(cached(&db), 0);
+
+        let found = db.query(&SearchRequest::new(&[0f32, 1f32], 2)).unwrap();
+        assert_eq!(
+            found.results.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        db.build_index().unwrap();
+        let found = db.query(&SearchRequest::new(&[7f32, 1f32], 1)).unwrap();
+        assert_eq!(found.results[0].0, 7);
+        assert_eq!(
+            db.compose_query(&[0, 1], &[]).unwrap(),
+            vec![0.5f32, 1f32]
+        );
+        db.search_prefix_dims_reranked(&[0f32, 1f32], 1, 2, 4)
+            .unwrap();
+        assert_eq!(cached(&db), 0);
+    }
+
+    #[test]
+    fn scratch_spares_reads() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([1f32, 0f32]).unwrap();
+        db.push([0f32, 1f32]).unwrap();
+
+        let counting = CountingAccess::new(file.reopen());
+        let mut db = DatabaseOptions::default()
+            .cache_mode(CacheMode::None)
+            .open("mem", Box::new(counting.clone()))
+            .unwrap();
+        let read = counting.read();
+        db.compose_query(&[0], &[1]).unwrap();
+        let distinct = counting.read() - read;
+        // repeated ids are read once per query, and not at all across queries
+        let read = counting.read();
+        db.compose_query(&[0, 0, 1], &[1]).unwrap();
+        assert_eq!(counting.read() - read, distinct);
+        assert_eq!(cached(&db), 0);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::db::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// # Cancel Token
+/// Asks a long-running operation to stop, from whichever thread holds a
+/// clone of it. Operations check it as they go, finishing what they're in
+/// the middle of so that the database is left consistent, and fail with
+/// [Error::Cancelled] telling how far they got.
+///
+/// A [child](CancelToken::child) is cancelled along with its parent, but
+/// not the other way round, for stopping one of several operations alone.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    parent: Option<Box<CancelToken>>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// A token cancelled by either itself or this one.
+    pub fn child(&self) -> CancelToken {
+        CancelToken {
+            cancelled: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self.parent.as_ref().is_some_and(|parent| parent.is_cancelled())
+    }
+
+    /// Fails with [Error::Cancelled] at `progress` if it's cancelled.
+    pub(crate) fn check(&self, progress: u64) -> Result<(), Error> {
+        match self.is_cancelled() {
+            true => Err(Error::Cancelled { progress }),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{CancelToken, DatabaseOptions, Error, IndexState, VerifyLevel};
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn cancel_works() {
+        let parent = CancelToken::new();
+        let (child, sibling) = (parent.child(), parent.child());
+        let grandchild = child.child();
+        child.cancel();
+        assert!(child.is_cancelled() && grandchild.is_cancelled());
+        assert!(!parent.is_cancelled() && !sibling.is_cancelled());
+        parent.cancel();
+        assert!(sibling.is_cancelled());
+        assert!(matches!(sibling.check(3), Err(Error::Cancelled { progress: 3 })));
+        assert!(CancelToken::new().check(3).is_ok());
+    }
+
+    #[test]
+    fn cancelled_build_keeps_the_index() {
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(17);
+        for _ in 0..2000 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        db.build_index().unwrap();
+        let db = Arc::new(db);
+
+        // cancelled from another thread once a fifth of the records are in
+        let token = CancelToken::new();
+        let canceller = {
+            let (db, token) = (db.clone(), token.clone());
+            thread::spawn(move || {
+                while !token.is_cancelled() {
+                    match db.index_state() {
+                        IndexState::Building { progress } if progress >= 0.2 => token.cancel(),
+                        _ => thread::yield_now(),
+                    }
+                }
+            })
+        };
+        let built = db.build_index_cancellable(&token.child());
+        // lets the canceller go should the build have finished first
+        token.cancel();
+        canceller.join().unwrap();
+        let Err(Error::Cancelled { progress }) = built else {
+            panic!("{built:?}");
+        };
+        assert!((400..2000).contains(&progress), "{progress}");
+
+        // the index built before stays, and the records are intact
+        assert_eq!(db.index_state(), IndexState::Ready);
+        assert!(db.node_level(1999).is_some());
+        assert!(db.verify_level(VerifyLevel::Standard).unwrap().is_empty());
+        assert!(matches!(
+            db.verify_level_cancellable(VerifyLevel::Deep, &token),
+            Err(Error::Cancelled { progress: 0 })
+        ));
+    }
+}
@@ -0,0 +1,340 @@
+use crate::db::{Database, Error, OptionsError, VectorHandle};
+use crate::vio;
+use std::cmp::max;
+use std::io::{Seek, SeekFrom};
+
+/// # HNSW Configuration
+/// Parameters of the hierarchical navigable small world index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HnswConfig {
+    /// Maximum number of neighbors per node on upper layers.
+    pub m: u32,
+    /// Maximum number of neighbors per node on the base layer, which is
+    /// searched most and so allowed denser. `2 * m` if absent.
+    pub m0: Option<u32>,
+    /// Candidate pool size while inserting.
+    pub ef_construction: u32,
+    /// Candidate pool size while querying, unless overridden per request.
+    pub ef_search: u32,
+    /// Remembers distances while inserting a vector instead of computing
+    /// them again. Disable it to save the memory on tight builds.
+    pub distance_cache: bool,
+    /// How the layers make room for nodes beyond what they hold.
+    pub growth: GrowthPolicy,
+    /// Scales the levels drawn for new nodes, one making it to level `l`
+    /// or above with a chance of `exp(-l / level_multiplier)`, so that
+    /// larger ones stack up more layers, up to 16. `1 / ln(m)` if absent,
+    /// and finite and positive if not.
+    pub level_multiplier: Option<f32>,
+    /// Prefers neighbors closer to a node than to the ones picked before,
+    /// spreading them out in different directions, when connecting it or
+    /// trimming its neighbors down. Otherwise the closest are kept.
+    pub diverse_neighbors: bool,
+}
+
+/// # Growth Policy
+/// How much room a graph makes once it runs out, trading memory
+/// for fewer reallocations during incremental builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GrowthPolicy {
+    /// Just enough for the nodes added, reallocating on every one.
+    Exact,
+    /// Half as much again as what's held, or what's needed if that's more.
+    #[default]
+    Geometric,
+    /// Whole chunks of this many nodes.
+    Chunked(u32),
+}
+
+impl GrowthPolicy {
+    /// Room made from `capacity` for `len` nodes.
+    pub(crate) fn grown(self, capacity: u32, len: u32) -> u32 {
+        match self {
+            GrowthPolicy::Exact => len,
+            GrowthPolicy::Geometric => max(len, capacity.saturating_add(capacity / 2)),
+            GrowthPolicy::Chunked(chunk) => len.div_ceil(chunk.max(1)).saturating_mul(chunk.max(1)),
+        }
+    }
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfig {
+            m: 16,
+            m0: None,
+            ef_construction: 200,
+            ef_search: 64,
+            distance_cache: true,
+            growth: GrowthPolicy::default(),
+            level_multiplier: None,
+            diverse_neighbors: true,
+        }
+    }
+}
+
+impl HnswConfig {
+    /// Fails with [OptionsError::InvalidParam] naming a parameter out of its range.
+    pub(crate) fn check(&self) -> Result<(), OptionsError> {
+        let valid = |multiplier: f32| multiplier.is_finite() && multiplier > 0f32;
+        if !self.level_multiplier.is_none_or(valid) {
+            return Err(OptionsError::InvalidParam("level_multiplier"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn level_multiplier(&self) -> f32 {
+        self.level_multiplier
+            .unwrap_or_else(|| 1f32 / (self.m.max(2) as f32).ln())
+    }
+
+    /// Neighbors a node keeps at most on layer `level`.
+    pub(crate) fn max_degree(&self, level: u32) -> usize {
+        match level {
+            0 => self.m0.unwrap_or(2 * self.m) as usize,
+            _ => self.m as usize,
+        }
+    }
+
+    /// Same as this one, but built like `recorded`.
+    pub(crate) fn built_like(self, recorded: HnswConfig) -> HnswConfig {
+        HnswConfig {
+            m: recorded.m,
+            m0: recorded.m0,
+            ef_construction: recorded.ef_construction,
+            level_multiplier: recorded.level_multiplier,
+            diverse_neighbors: recorded.diverse_neighbors,
+            ..self
+        }
+    }
+}
+
+/// # Search Parameters
+/// Knobs of a query that may be turned while the database is open,
+/// see [Database::set_search_params](crate::db::Database::set_search_params).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchParams {
+    /// Candidate pool size while querying, unless overridden per request.
+    pub ef_search: u32,
+    /// Stored vectors a query compares against at most, or all of them if absent.
+    pub max_visited: Option<u64>,
+    /// Candidates re-scored at full precision per result wanted.
+    /// Exact scans compare at full precision anyway and ignore it.
+    pub rerank_factor: u32,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        SearchParams {
+            ef_search: HnswConfig::default().ef_search,
+            max_visited: None,
+            rerank_factor: 1,
+        }
+    }
+}
+
+impl VectorHandle {
+    /// Rewrites the header with `params` if it has room for them, returning
+    /// the bytes written. Files from before version 7 don't, and are left alone.
+    pub(crate) fn persist_search(&mut self, params: SearchParams) -> Result<u64, Error> {
+        self.fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        let mut header = vio::dbheader::read(&mut self.fd).map_err(Error::Header)?;
+        if header.search.is_none_or(|persisted| persisted == params) {
+            return Ok(0);
+        }
+        header.search = Some(params);
+        let written = header.rewrite(&mut self.fd).map_err(Error::Header)?;
+        self.written(written)?;
+        Ok(written)
+    }
+
+    /// Same as [VectorHandle::persist_search] for what the index is built
+    /// with, left alone in files from before version 16.
+    pub(crate) fn persist_hnsw(&mut self, config: HnswConfig) -> Result<u64, Error> {
+        self.fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        let mut header = vio::dbheader::read(&mut self.fd).map_err(Error::Header)?;
+        if header.hnsw.is_none_or(|persisted| config.built_like(persisted) == config) {
+            return Ok(0);
+        }
+        header.hnsw = Some(config);
+        let written = header.rewrite(&mut self.fd).map_err(Error::Header)?;
+        self.written(written)?;
+        Ok(written)
+    }
+}
+
+impl Database {
+    pub fn search_params(&self) -> SearchParams {
+        *self.search.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes effect from the next query on, while queries underway finish
+    /// with what they started with. Persisted on [Database::flush].
+    pub fn set_search_params(&self, params: SearchParams) {
+        *self.search.write().unwrap_or_else(|e| e.into_inner()) = params;
+    }
+
+    /// Applies the search parameters of `config` like [Database::set_search_params].
+    /// Fails with [Error::ImmutableParam] if it differs in any of the others
+    /// the index is built with, and with [OptionsError::InvalidParam] wrapped
+    /// in [Error::Options] if any is out of range.
+    pub fn set_config(&self, config: HnswConfig) -> Result<(), Error> {
+        config.check().map_err(Error::Options)?;
+        if config.m != self.config.m {
+            return Err(Error::ImmutableParam("m"));
+        }
+        if config.m0 != self.config.m0 {
+            return Err(Error::ImmutableParam("m0"));
+        }
+        if config.ef_construction != self.config.ef_construction {
+            return Err(Error::ImmutableParam("ef_construction"));
+        }
+        if config.level_multiplier != self.config.level_multiplier {
+            return Err(Error::ImmutableParam("level_multiplier"));
+        }
+        if config.diverse_neighbors != self.config.diverse_neighbors {
+            return Err(Error::ImmutableParam("diverse_neighbors"));
+        }
+        self.set_search_params(SearchParams {
+            ef_search: config.ef_search,
+            ..self.search_params()
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        DatabaseOptions, Error, GrowthPolicy, HnswConfig, OptionsError, SearchParams, SearchRequest,
+    };
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+
+    #[test]
+    fn hot_reload_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4).create("mem", Box::new(file.reopen())).unwrap();
+        let mut rng = XorShift::new(3);
+        for _ in 0..100 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        let request = SearchRequest::new(&[0.5f32; 4], 5).explain(true);
+        let before = db.query(&request).unwrap();
+        assert_eq!(before.visited, 100);
+        assert_eq!(before.trace.unwrap().layers[0].candidates.len(), 64);
+
+        db.set_search_params(SearchParams {
+            ef_search: 10,
+            max_visited: Some(40),
+            rerank_factor: 2,
+        });
+        let after = db.query(&request).unwrap();
+        assert_eq!(after.visited, 40);
+        assert_eq!(after.trace.unwrap().layers[0].candidates.len(), 10);
+        assert_eq!(db.config().ef_search, 10);
+
+        db.flush().unwrap();
+        let reopened = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(reopened.search_params(), db.search_params());
+    }
+
+    #[test]
+    fn build_params_persisted() {
+        let file = SharedCursor::new();
+        let config = HnswConfig {
+            m: 8,
+            m0: Some(12),
+            ef_construction: 50,
+            level_multiplier: Some(0.7),
+            diverse_neighbors: false,
+            ..HnswConfig::default()
+        };
+        let db = DatabaseOptions::new(4)
+            .hnsw(config)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        drop(db);
+        // what the file says wins over the options
+        let db = DatabaseOptions::default()
+            .hnsw(HnswConfig {
+                growth: GrowthPolicy::Exact,
+                ..HnswConfig::default()
+            })
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        let reopened = db.config();
+        assert_eq!(reopened.growth, GrowthPolicy::Exact);
+        assert_eq!(reopened.level_multiplier, Some(0.7));
+        assert_eq!(reopened.built_like(config), reopened);
+        assert!(matches!(
+            db.set_config(HnswConfig { m0: None, ..reopened }),
+            Err(Error::ImmutableParam("m0"))
+        ));
+    }
+
+    #[test]
+    fn immutable_params_rejected() {
+        let db = DatabaseOptions::new(4).create("mem", Box::new(SharedCursor::new())).unwrap();
+        let config = db.config();
+        assert!(matches!(
+            db.set_config(HnswConfig { m: 32, ..config }),
+            Err(Error::ImmutableParam("m"))
+        ));
+        assert!(matches!(
+            db.set_config(HnswConfig {
+                ef_construction: 10,
+                ..config
+            }),
+            Err(Error::ImmutableParam("ef_construction"))
+        ));
+        db.set_config(HnswConfig {
+            ef_search: 128,
+            ..config
+        })
+        .unwrap();
+        assert_eq!(db.config().ef_search, 128);
+        assert_eq!(db.config().m, config.m);
+    }
+
+    #[test]
+    fn level_multiplier_checked() {
+        let file = SharedCursor::new();
+        let db = DatabaseOptions::new(4).create("mem", Box::new(file.reopen())).unwrap();
+        let config = db.config();
+        for multiplier in [0f32, -1f32, f32::NAN, f32::INFINITY] {
+            let invalid = HnswConfig {
+                level_multiplier: Some(multiplier),
+                ..config
+            };
+            let rejected = |result| {
+                matches!(
+                    result,
+                    Err(Error::Options(OptionsError::InvalidParam("level_multiplier")))
+                )
+            };
+            assert!(rejected(db.set_config(invalid)), "{multiplier}");
+            let created = DatabaseOptions::new(4)
+                .hnsw(invalid)
+                .create("mem", Box::new(SharedCursor::new()));
+            assert!(rejected(created.map(|_| ())), "{multiplier}");
+            let opened = DatabaseOptions::default()
+                .hnsw(invalid)
+                .open("mem", Box::new(file.reopen()));
+            assert!(rejected(opened.map(|_| ())), "{multiplier}");
+        }
+        let large = HnswConfig {
+            level_multiplier: Some(100f32),
+            ..config
+        };
+        let mut db = DatabaseOptions::new(4)
+            .hnsw(large)
+            .create("mem", Box::new(SharedCursor::new()))
+            .unwrap();
+        let mut rng = XorShift::new(4);
+        for _ in 0..50 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        db.build_index().unwrap();
+        assert!(db.layers().count() <= 17);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::algorithm::search::{CandidateSource, Frontier};
+use crate::db::Clock;
+use std::time::Duration;
+
+/// Steps taken between looks at the clock, few enough for a search to
+/// overrun its deadline by little, many enough for the looks to cost next
+/// to nothing against the distances measured in between.
+const CHECK_EVERY: u32 = 32;
+
+/// A query's time to answer, see [SearchRequest::deadline](crate::db::SearchRequest::deadline),
+/// as told by `clock` every [CHECK_EVERY] steps of the search.
+pub(crate) struct Deadline<'a> {
+    clock: &'a dyn Clock,
+    started: Duration,
+    until: Duration,
+    steps: u32,
+    passed: bool,
+}
+
+impl<'a> Deadline<'a> {
+    /// Starts the clock, the deadline passing `budget` from now.
+    pub(crate) fn new(clock: &'a dyn Clock, budget: Duration) -> Deadline<'a> {
+        let started = clock.now();
+        Deadline {
+            clock,
+            started,
+            until: started.saturating_add(budget),
+            steps: 0,
+            passed: false,
+        }
+    }
+
+    /// Counts one more step, such as a node expanded or a vector compared,
+    /// and whether the deadline had passed as of it. Once passed, it stays so.
+    pub(crate) fn step(&mut self) -> bool {
+        if !self.passed {
+            self.steps += 1;
+            if self.steps.is_multiple_of(CHECK_EVERY) {
+                self.passed = self.clock.now() >= self.until;
+            }
+        }
+        self.passed
+    }
+
+    /// Whether the deadline was found passed, cutting the search short.
+    pub(crate) fn passed(&self) -> bool {
+        self.passed
+    }
+
+    /// Since the clock was started.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_sub(self.started)
+    }
+}
+
+/// Expands the nodes `inner` picks until the deadline passes.
+pub(crate) struct Timed<'a, 'b, C: ?Sized> {
+    pub(crate) inner: &'a mut C,
+    pub(crate) deadline: &'a mut Deadline<'b>,
+}
+
+impl<C: CandidateSource + ?Sized> CandidateSource for Timed<'_, '_, C> {
+    #[inline]
+    fn next(&mut self, frontier: &mut Frontier) -> Option<u32> {
+        if self.deadline.step() {
+            return None;
+        }
+        self.inner.next(frontier)
+    }
+}
@@ -0,0 +1,141 @@
+use crate::db::{
+    Database, DatabaseOptions, DbIndex, Error, ExportFormat, SearchPlan, SearchRequest,
+};
+use crate::ext::mem::SharedCursor;
+use crate::index::SearchScratch;
+use crate::metric::Metric;
+use std::io::Cursor;
+use std::time::Duration;
+
+const QUERY: [f32; 4] = [0.5, -1f32, 2f32, 0f32];
+const VECTOR: [f32; 4] = [1f32, 2f32, 3f32, 4f32];
+
+/// Goes through every operation that reads `db`, which holds `VECTOR`
+/// under `stored` if anything.
+fn check_reads(db: &mut Database, stored: Option<DbIndex>) {
+    let count = stored.is_some() as u64;
+    let found = |k: usize| match stored {
+        Some(id) if k > 0 => vec![(id, Metric::default().distance(&QUERY, &VECTOR))],
+        _ => vec![],
+    };
+    let absent = stored.map_or(0, |id| id + 1);
+    assert_eq!(db.count().unwrap(), count);
+    assert_eq!(db.get(absent).unwrap(), None);
+    let got = stored.map(|id| db.get(id).unwrap().unwrap().to_vec());
+    assert_eq!(got, stored.map(|_| VECTOR.to_vec()));
+    assert_eq!(db.get_many(&[absent]).unwrap(), [None]);
+
+    for k in [1, 5] {
+        for plan in [None, Some(SearchPlan::Flat), Some(SearchPlan::Graph)] {
+            let mut request = SearchRequest::new(&QUERY, k).explain(true);
+            if let Some(plan) = plan {
+                request = request.plan(plan);
+            }
+            let response = db.query(&request).unwrap();
+            assert_eq!(response.results, found(k));
+            assert_eq!(response.dangling, 0);
+            if let Some(cursor) = &response.cursor {
+                assert!(db.query_next(cursor).unwrap().results.is_empty());
+            }
+            let scratched = db.query_with_scratch(&request, &mut SearchScratch::new());
+            assert_eq!(scratched.unwrap().results, found(k));
+            let view = db.read_view().unwrap();
+            assert_eq!(view.count(), count);
+            assert_eq!(view.query(&request).unwrap().results, found(k));
+        }
+        assert_eq!(db.search_exact(&QUERY, k).unwrap(), found(k));
+        let prefix = db.search_prefix_dims(&QUERY, 4, k).unwrap();
+        assert_eq!(prefix.results, found(k));
+        // pushed without a group
+        assert!(db.search_grouped(&QUERY, k, k).unwrap().is_empty());
+    }
+    assert_eq!(db.search_exact(&QUERY, 0).unwrap(), found(0));
+
+    let scanned = db.scan_map(|id, vector| (id, vector.to_vec())).unwrap();
+    assert_eq!(scanned, stored.map(|id| (id, VECTOR.to_vec())).into_iter().collect::<Vec<_>>());
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.vectors, count);
+    assert_eq!(stats.dangling_nodes, 0);
+    assert!(db.verify().is_empty());
+    assert!(db.near_duplicates(0.1, 10).unwrap().is_empty());
+    db.index_diagnostics(10, 0).unwrap();
+    match db.evaluate(&[QUERY.to_vec()], 1) {
+        Ok(report) => assert_eq!(report.recall, 1f32),
+        Err(e) => assert!(!db.is_indexed() && matches!(e, Error::IndexNotReady(_))),
+    }
+    let pca = db
+        .export_pca(2, &mut vec![], ExportFormat::Jsonl)
+        .unwrap();
+    assert_eq!(pca.count, count);
+    assert!(pca.explained_variance_ratio.iter().all(|r| *r == 0f64));
+    if db.is_indexed() {
+        let mut exported = vec![];
+        db.export_index(&mut exported).unwrap();
+        db.import_index(&mut exported.as_slice(), false).unwrap();
+    }
+    assert!(db.maintain(Duration::from_secs(60)).unwrap().is_done());
+    db.flush().unwrap();
+}
+
+/// Checks `db` as it is, indexed, and reopened after a flush.
+fn check_all(mut db: Database, file: &SharedCursor, stored: Option<DbIndex>) {
+    check_reads(&mut db, stored);
+    db.build_index().unwrap();
+    check_reads(&mut db, stored);
+    db.drop_index();
+    db.flush().unwrap();
+    drop(db);
+    let mut reopened = DatabaseOptions::default()
+        .open("mem", Box::new(file.reopen()))
+        .unwrap();
+    check_reads(&mut reopened, stored);
+}
+
+#[test]
+fn empty_database_works() {
+    let file = SharedCursor::new();
+    let db = DatabaseOptions::new(4)
+        .create("mem", Box::new(file.reopen()))
+        .unwrap();
+    check_all(db, &file, None);
+
+    // emptied out rather than never written to
+    let file = SharedCursor::new();
+    let mut db = DatabaseOptions::new(4)
+        .bloom_filter(10, 0.01)
+        .create("mem", Box::new(file.reopen()))
+        .unwrap();
+    let id = db.push(VECTOR).unwrap();
+    assert_eq!(db.remove(id).unwrap().unwrap().to_vec(), VECTOR);
+    assert!(db.remove(id).unwrap().is_none());
+    assert!(matches!(db.compose_query(&[id], &[]), Err(Error::Missing(_))));
+    check_all(db, &file, None);
+}
+
+#[test]
+fn singleton_database_works() {
+    let file = SharedCursor::new();
+    let mut db = DatabaseOptions::new(4)
+        .create("mem", Box::new(file.reopen()))
+        .unwrap();
+    let id = db.push(VECTOR).unwrap();
+    assert_eq!(db.compose_query(&[id], &[]).unwrap(), VECTOR);
+    check_all(db, &file, Some(id));
+
+    let file = SharedCursor::new();
+    let mut db = DatabaseOptions::new(4)
+        .history(true)
+        .create("mem", Box::new(file.reopen()))
+        .unwrap();
+    let id = db.push([0f32; 4]).unwrap();
+    db.update(id, VECTOR).unwrap();
+    check_all(db, &file, Some(id));
+
+    // the index is built before the one vector is pushed
+    let mut db = DatabaseOptions::new(4)
+        .create("mem", Box::new(Cursor::new(Vec::new())))
+        .unwrap();
+    db.build_index().unwrap();
+    let id = db.push(VECTOR).unwrap();
+    check_reads(&mut db, Some(id));
+}
@@ -0,0 +1,29 @@
+use crate::db::{Database, Error, LayerDiag};
+use crate::ext::semaphore::LockAutoClear;
+use std::collections::HashMap;
+
+impl Database {
+    /// Structural diagnostics of every index layer, in the order they are stored,
+    /// or from the base up if the index was [built](Database::build_index) in memory,
+    /// measured against the exact distances between the vectors the nodes stand for.
+    /// See [LayerDiag] for what `sample` and `seed` are used for.
+    pub fn index_diagnostics(&mut self, sample: usize, seed: u64) -> Result<Vec<LayerDiag>, Error> {
+        if let Some(index) = self.index.lock_auto_clear_poison().as_ref() {
+            return Ok(index
+                .layers()
+                .iter()
+                .map(|layer| layer.diagnostics(|a, b| index.node_distance(a, b), sample, seed))
+                .collect());
+        }
+        let vectors = HashMap::<_, _>::from_iter(self.handle.lock_auto_clear_poison().read_all()?);
+        let distance = |a, b| match (vectors.get(&a), vectors.get(&b)) {
+            (Some(u), Some(v)) => self.metric.distance(u, v),
+            _ => f32::INFINITY,
+        };
+        Ok(self
+            .layers
+            .iter()
+            .map(|layer| layer.diagnostics(distance, sample, seed))
+            .collect())
+    }
+}
@@ -0,0 +1,295 @@
+use crate::db::{Database, DbIndex, DbVector, Error, SearchParams, SearchPlan, SearchRequest};
+use crate::ext::rand::XorShift;
+use crate::ext::semaphore::LockAutoClear;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// Knobs of [Database::evaluate_with].
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalOptions {
+    /// Number of stored vectors drawn as queries when none are given.
+    pub sample_size: usize,
+    pub seed: u64,
+    /// Candidate pool sizes to measure, one report each.
+    /// The default of the database is measured if empty.
+    pub ef_sweep: Vec<usize>,
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        EvalOptions {
+            sample_size: 100,
+            seed: 0,
+            ef_sweep: vec![],
+        }
+    }
+}
+
+/// # Evaluation Report
+/// How well approximate queries did against the exact ground truth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalReport {
+    /// Candidate pool size measured, or `None` for the default.
+    pub ef: Option<usize>,
+    pub queries: usize,
+    /// Average fraction of the true k nearest neighbors returned, in `[0, 1]`.
+    pub recall: f32,
+    pub mean_latency: Duration,
+    pub median_latency: Duration,
+    pub p99_latency: Duration,
+    pub mean_visited: f32,
+}
+
+/// Neighbors per query when measuring recall for tuning.
+const TUNE_K: usize = 10;
+/// Upper bound of candidate pool sizes tried while tuning.
+const TUNE_MAX_EF: usize = 1024;
+
+impl Database {
+    /// Measures recall@`k` and latency of queries through the index with
+    /// the current parameters. Fails with [Error::IndexNotReady] if there's
+    /// no index, as scanning the records would always find them all.
+    pub fn evaluate(&mut self, queries: &[DbVector], k: usize) -> Result<EvalReport, Error> {
+        let mut reports = self.evaluate_with(queries, k, &EvalOptions::default())?;
+        Ok(reports.remove(0))
+    }
+
+    /// Measures recall@`k` and latency for each candidate pool size in the
+    /// sweep. If `queries` is empty, they are sampled from the stored vectors.
+    /// Fails the way [Database::evaluate] does.
+    pub fn evaluate_with(
+        &mut self,
+        queries: &[DbVector],
+        k: usize,
+        options: &EvalOptions,
+    ) -> Result<Vec<EvalReport>, Error> {
+        if self.index.lock_auto_clear_poison().is_none() {
+            return Err(Error::IndexNotReady(self.index_state()));
+        }
+        let sampled;
+        let queries = if queries.is_empty() {
+            sampled = self.sample_vectors(options.sample_size, options.seed)?;
+            &sampled
+        } else {
+            queries
+        };
+
+        let truth = queries
+            .iter()
+            .map(|q| {
+                Ok(HashSet::<DbIndex>::from_iter(
+                    self.rank_exact(q)?.into_iter().take(k).map(|(id, _)| id),
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let efs = if options.ef_sweep.is_empty() {
+            vec![None]
+        } else {
+            options.ef_sweep.iter().map(|ef| Some(*ef)).collect()
+        };
+        efs.into_iter()
+            .map(|ef| self.measure(queries, &truth, k, ef))
+            .collect()
+    }
+
+    fn measure(
+        &mut self,
+        queries: &[DbVector],
+        truth: &[HashSet<DbIndex>],
+        k: usize,
+        ef: Option<usize>,
+    ) -> Result<EvalReport, Error> {
+        let (mut recall, mut visited, mut latencies) = (0f32, 0usize, vec![]);
+        for (query, truth) in queries.iter().zip(truth) {
+            let mut request = SearchRequest::new(query, k).plan(SearchPlan::Graph);
+            if let Some(ef) = ef {
+                request = request.ef(ef);
+            }
+            let begin = Instant::now();
+            let response = self.query(&request)?;
+            latencies.push(begin.elapsed());
+
+            visited += response.visited;
+            if !truth.is_empty() {
+                let found = response
+                    .results
+                    .iter()
+                    .filter(|(id, _)| truth.contains(id))
+                    .count();
+                recall += found as f32 / truth.len() as f32;
+            } else {
+                recall += 1f32;
+            }
+        }
+
+        latencies.sort();
+        let count = queries.len().max(1);
+        let quantile = |q: f32| {
+            latencies
+                .get(((latencies.len() as f32 * q) as usize).min(latencies.len().max(1) - 1))
+                .copied()
+                .unwrap_or_default()
+        };
+        Ok(EvalReport {
+            ef,
+            queries: queries.len(),
+            recall: recall / count as f32,
+            mean_latency: latencies.iter().sum::<Duration>() / count as u32,
+            median_latency: quantile(0.5),
+            p99_latency: quantile(0.99),
+            mean_visited: visited as f32 / count as f32,
+        })
+    }
+
+    /// Finds the smallest `ef_search` whose recall@10 through the index
+    /// reaches `target_recall` over `sample_queries` stored vectors, and makes
+    /// it the default. Fails the way [Database::evaluate] does.
+    pub fn tune_ef(&mut self, target_recall: f32, sample_queries: usize) -> Result<u32, Error> {
+        self.tune_ef_seeded(target_recall, sample_queries, 0)
+    }
+
+    /// Same as [Database::tune_ef], sampling queries reproducibly by `seed`.
+    pub fn tune_ef_seeded(
+        &mut self,
+        target_recall: f32,
+        sample_queries: usize,
+        seed: u64,
+    ) -> Result<u32, Error> {
+        let queries = self.sample_vectors(sample_queries, seed)?;
+        let recall_at = |db: &mut Database, ef: usize| -> Result<f32, Error> {
+            // drawn the same way again if the sample came out empty
+            let options = EvalOptions {
+                sample_size: sample_queries,
+                seed,
+                ef_sweep: vec![ef],
+            };
+            Ok(db.evaluate_with(&queries, TUNE_K, &options)?[0].recall)
+        };
+
+        let best = recall_at(self, TUNE_MAX_EF)?;
+        if best < target_recall {
+            return Err(Error::RecallUnreachable(target_recall, best));
+        }
+        let (mut low, mut high) = (TUNE_K, TUNE_MAX_EF);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if recall_at(self, middle)? >= target_recall {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+
+        self.set_search_params(SearchParams {
+            ef_search: low as u32,
+            ..self.search_params()
+        });
+        Ok(low as u32)
+    }
+
+    /// Draws up to `count` distinct stored vectors, reproducibly for a given `seed`.
+    /// Only their ids are gathered from the file, and the vectors drawn read after.
+    fn sample_vectors(&mut self, count: usize, seed: u64) -> Result<Vec<DbVector>, Error> {
+        let mut ids = vec![];
+        self.handle.lock_auto_clear_poison().scan(|id, _| ids.push(id))?;
+        let mut rng = XorShift::new(seed);
+        let mut sampled = vec![];
+        while sampled.len() < count && !ids.is_empty() {
+            let picked = (rng.next_u64() % ids.len() as u64) as usize;
+            if let Some(vector) = self.get(ids.swap_remove(picked))? {
+                sampled.push(DbVector::clone(&vector));
+            }
+        }
+        Ok(sampled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::eval::TUNE_K;
+    use crate::db::{DatabaseOptions, Error, EvalOptions, IndexState};
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+
+    #[test]
+    fn evaluate_works() {
+        let mut db = DatabaseOptions::new(32)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(11);
+        for _ in 0..2000 {
+            db.push(rng.vector(32)).unwrap();
+        }
+        let options = EvalOptions {
+            sample_size: 20,
+            seed: 3,
+            ef_sweep: vec![1, 4, 16, 64],
+        };
+        assert!(matches!(
+            db.evaluate_with(&[], 10, &options),
+            Err(Error::IndexNotReady(IndexState::NotBuilt))
+        ));
+
+        db.build_index().unwrap();
+        let reports = db.evaluate_with(&[], 10, &options).unwrap();
+        assert_eq!(reports.len(), 4);
+        for report in &reports {
+            assert_eq!(report.queries, 20);
+            assert!((0f32..=1f32).contains(&report.recall));
+            assert!(report.median_latency <= report.p99_latency);
+        }
+        for pair in reports.windows(2) {
+            assert!(pair[0].recall <= pair[1].recall);
+        }
+        // measured through the index, which misses some at a small pool
+        assert!(reports[0].recall < 1f32, "{}", reports[0].recall);
+
+        let report = db.evaluate(&[rng.vector(32)], 5).unwrap();
+        assert_eq!(report.ef, None);
+        assert_eq!(report.queries, 1);
+    }
+
+    #[test]
+    fn tune_ef_works() {
+        let mut db = DatabaseOptions::new(32)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(5);
+        for _ in 0..1000 {
+            db.push(rng.vector(32)).unwrap();
+        }
+        assert!(matches!(
+            db.tune_ef(0.9, 10),
+            Err(Error::IndexNotReady(IndexState::NotBuilt))
+        ));
+        db.build_index().unwrap();
+
+        // a higher target takes a larger pool, beyond the least tried
+        let lower = db.tune_ef_seeded(0.8, 40, 1).unwrap();
+        let higher = db.tune_ef_seeded(0.999, 40, 1).unwrap();
+        assert!(lower <= higher, "{lower} > {higher}");
+        assert!(higher as usize > TUNE_K, "{higher}");
+
+        let ef = db.tune_ef_seeded(0.9, 20, 1).unwrap();
+        assert_eq!(db.config().ef_search, ef);
+        assert_eq!(db.tune_ef_seeded(0.9, 20, 1).unwrap(), ef);
+        let options = EvalOptions {
+            sample_size: 20,
+            seed: 1,
+            ef_sweep: vec![ef as usize],
+        };
+        assert!(db.evaluate_with(&[], 10, &options).unwrap()[0].recall >= 0.9);
+
+        assert!(matches!(
+            db.tune_ef(1.5, 10),
+            Err(Error::RecallUnreachable(1.5, _))
+        ));
+        // no queries to measure with, rather than 100 drawn behind its back
+        assert!(matches!(
+            db.tune_ef(0.9, 0),
+            Err(Error::RecallUnreachable(0.9, 0f32))
+        ));
+        assert_eq!(db.config().ef_search, ef);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::algorithm::pca::Covariance;
+use crate::db::{Database, DbIndex, DbVectorSlice, Error};
+use crate::ext::semaphore::LockAutoClear;
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io;
+use std::io::Write;
+
+/// # Export Format
+/// How vectors are written out one after another, see [Database::export_pca].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// A JSON object per line, `{"id":0,"vector":[0.5,1]}`,
+    /// with non-finite components as `null`.
+    #[default]
+    Jsonl,
+    /// Per vector, its dimension as a little-endian `i32` followed by its
+    /// components as little-endian `f32`, like the TEXMEX datasets.
+    /// Ids are left out, the vectors going in file order.
+    Fvecs,
+    /// A row of column names, `id,0,1,…`, then a row per vector.
+    Csv,
+}
+
+/// # PCA Report
+/// What [Database::export_pca] projected the vectors onto.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PcaReport {
+    /// Vectors exported.
+    pub count: u64,
+    /// Subtracted from every vector before it's projected.
+    pub mean: Vec<f32>,
+    /// Unit directions projected onto, most variance first.
+    pub components: Vec<Vec<f32>>,
+    /// Fraction of the total variance each of the components explains,
+    /// which add up to one if they explain it all.
+    pub explained_variance_ratio: Vec<f64>,
+}
+
+/// Writes vectors in an [ExportFormat].
+struct Exporter<'a> {
+    w: &'a mut dyn Write,
+    format: ExportFormat,
+}
+
+impl Exporter<'_> {
+    fn begin(&mut self, dim_size: u32) -> io::Result<()> {
+        if self.format == ExportFormat::Csv {
+            write!(self.w, "id")?;
+            for column in 0..dim_size {
+                write!(self.w, ",{column}")?;
+            }
+            writeln!(self.w)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, id: DbIndex, vector: DbVectorSlice) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Jsonl => {
+                write!(self.w, "{{\"id\":{id},\"vector\":[")?;
+                for (i, x) in vector.iter().enumerate() {
+                    let separator = if i > 0 { "," } else { "" };
+                    match x.is_finite() {
+                        true => write!(self.w, "{separator}{x}")?,
+                        false => write!(self.w, "{separator}null")?,
+                    }
+                }
+                writeln!(self.w, "]}}")
+            }
+            ExportFormat::Fvecs => {
+                self.w.write_i32::<LittleEndian>(vector.len() as i32)?;
+                for x in vector {
+                    self.w.write_f32::<LittleEndian>(*x)?;
+                }
+                Ok(())
+            }
+            ExportFormat::Csv => {
+                write!(self.w, "{id}")?;
+                for x in vector {
+                    write!(self.w, ",{x}")?;
+                }
+                writeln!(self.w)
+            }
+        }
+    }
+}
+
+impl Database {
+    /// Writes every stored vector to `w` in `format`, projected onto the
+    /// `target_dims` principal components of them all, e.g. to plot them or to
+    /// feed a nonlinear reduction. Takes two passes over the records, one for
+    /// their covariance and another to project them, holding none in memory.
+    /// Fails with [Error::Dimension] unless `target_dims` is between one and
+    /// the dimension of the database.
+    pub fn export_pca(
+        &mut self,
+        target_dims: u32,
+        w: &mut dyn Write,
+        format: ExportFormat,
+    ) -> Result<PcaReport, Error> {
+        let dim_size = self.dim_size();
+        if target_dims == 0 || target_dims > dim_size {
+            return Err(Error::Dimension(dim_size, target_dims as usize));
+        }
+        let mut handle = self.handle.lock_auto_clear_poison();
+        let mut covariance = Covariance::new(dim_size as usize);
+        handle.scan(|_, vector| covariance.add(vector))?;
+        let components = covariance.top_components(target_dims as usize);
+        let total = covariance.total_variance();
+        let report = PcaReport {
+            count: covariance.count(),
+            mean: covariance.mean().into_iter().map(|x| x as f32).collect(),
+            components: components
+                .iter()
+                .map(|c| c.direction.iter().map(|x| *x as f32).collect())
+                .collect(),
+            explained_variance_ratio: components
+                .iter()
+                .map(|c| if total > 0f64 { c.variance / total } else { 0f64 })
+                .collect(),
+        };
+
+        let mean = covariance.mean();
+        let mut exporter = Exporter { w, format };
+        exporter.begin(target_dims).map_err(Error::IO)?;
+        let mut projected = vec![0f32; target_dims as usize];
+        let mut failed = None;
+        handle.scan(|id, vector| {
+            if failed.is_some() {
+                return;
+            }
+            for (x, component) in projected.iter_mut().zip(&components) {
+                *x = vector
+                    .iter()
+                    .zip(&mean)
+                    .zip(&component.direction)
+                    .map(|((v, m), c)| (*v as f64 - m) * c)
+                    .sum::<f64>() as f32;
+            }
+            failed = exporter.write(id, &projected).err();
+        })?;
+        match failed {
+            Some(e) => Err(Error::IO(e)),
+            None => Ok(report),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error, ExportFormat};
+    use crate::ext::rand::XorShift;
+    use byteorder::{LittleEndian, ReadBytesExt};
+    use std::io::Cursor;
+
+    #[test]
+    fn export_pca_works() {
+        let dim_size = 32;
+        let mut rng = XorShift::new(71);
+        // two directions spanning the plane the vectors lie on
+        let basis = [rng.vector(dim_size), rng.vector(dim_size)];
+        let offset = rng.vector(dim_size);
+        let mut db = DatabaseOptions::new(dim_size)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        for _ in 0..500 {
+            let (a, b) = (rng.next_f32() * 4f32 - 2f32, rng.next_f32() - 0.5);
+            let vector = (0..dim_size as usize)
+                .map(|i| offset[i] + a * basis[0][i] + b * basis[1][i])
+                .collect::<Vec<_>>();
+            db.push(&vector).unwrap();
+        }
+
+        let mut jsonl = vec![];
+        let report = db.export_pca(2, &mut jsonl, ExportFormat::Jsonl).unwrap();
+        assert_eq!(report.count, 500);
+        let explained = report.explained_variance_ratio.iter().sum::<f64>();
+        assert!((explained - 1f64).abs() < 1e-4, "{explained}");
+        assert!(report.explained_variance_ratio[0] > report.explained_variance_ratio[1]);
+        let jsonl = String::from_utf8(jsonl).unwrap();
+        assert_eq!(jsonl.lines().count(), 500);
+        assert!(jsonl.starts_with("{\"id\":0,\"vector\":["));
+
+        let mut fvecs = vec![];
+        db.export_pca(3, &mut fvecs, ExportFormat::Fvecs).unwrap();
+        assert_eq!(fvecs.len(), 500 * (4 + 3 * 4));
+        let mut fvecs = Cursor::new(fvecs);
+        let mut third = 0f32;
+        for _ in 0..500 {
+            assert_eq!(fvecs.read_i32::<LittleEndian>().unwrap(), 3);
+            fvecs.read_f32::<LittleEndian>().unwrap();
+            fvecs.read_f32::<LittleEndian>().unwrap();
+            third = third.max(fvecs.read_f32::<LittleEndian>().unwrap().abs());
+        }
+        // nothing lies off the plane
+        assert!(third < 1e-3, "{third}");
+
+        let mut csv = vec![];
+        db.export_pca(1, &mut csv, ExportFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().next(), Some("id,0"));
+        assert_eq!(csv.lines().count(), 501);
+        assert!(matches!(
+            db.export_pca(33, &mut vec![], ExportFormat::Csv),
+            Err(Error::Dimension(32, 33))
+        ));
+    }
+}
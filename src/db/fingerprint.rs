@@ -0,0 +1,200 @@
+use crate::db::{Database, DbIndex, DbVectorSlice, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use crate::metric::Metric;
+use crate::vio;
+use crate::vio::crc::crc32_continue;
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// # Fingerprint
+/// What the content of a database comes down to, for telling copies of it
+/// apart without comparing their files, see [Database::fingerprint]. Only
+/// what's read from it counts: records are taken by id, wherever they are
+/// in the file and however it's laid out, padded or compacted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub dim_size: u32,
+    pub metric: Metric,
+    pub count: u64,
+    /// Sum of a 64-bit hash of the id and components of every record,
+    /// which doesn't depend on their order, so that it's kept up to date
+    /// by adding and subtracting the records written.
+    pub records: u64,
+    /// CRC-32 of the layers stored in the file, serialized one after another.
+    pub layers: u32,
+}
+
+impl Fingerprint {
+    /// All of it folded into a single number, for logs and comparing at a glance.
+    pub fn digest(&self) -> u64 {
+        let mut bytes = Vec::with_capacity(29);
+        bytes.extend(self.dim_size.to_be_bytes());
+        bytes.push(self.metric.to_byte());
+        bytes.extend(self.count.to_be_bytes());
+        bytes.extend(self.records.to_be_bytes());
+        bytes.extend(self.layers.to_be_bytes());
+        fnv(bytes)
+    }
+}
+
+fn fnv(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes
+        .into_iter()
+        .fold(FNV_OFFSET, |hash, byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Hash of a record, spread over every bit since they're summed up.
+fn record_hash(id: DbIndex, vector: DbVectorSlice) -> u64 {
+    let components = vector.iter().flat_map(|x| x.to_bits().to_be_bytes());
+    let hash = fnv(id.to_be_bytes().into_iter().chain(components));
+    let hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+/// [Fingerprint::records] as of a revision of the records.
+pub(crate) struct RecordDigest {
+    revision: u64,
+    sum: u64,
+}
+
+impl VectorHandle {
+    /// Takes the records `removed` and `added` by a write that began at
+    /// revision `before` into the digest if there's one. It's dropped if
+    /// the records were written in between without being accounted for,
+    /// to be read through again.
+    pub(crate) fn digest(
+        &mut self,
+        before: u64,
+        removed: &[(DbIndex, DbVectorSlice)],
+        added: &[(DbIndex, DbVectorSlice)],
+    ) {
+        let revision = self.revision;
+        let Some(digest) = self.digest.as_mut().filter(|digest| digest.revision == before) else {
+            self.digest = None;
+            return;
+        };
+        for (id, vector) in removed {
+            digest.sum = digest.sum.wrapping_sub(record_hash(*id, vector));
+        }
+        for (id, vector) in added {
+            digest.sum = digest.sum.wrapping_add(record_hash(*id, vector));
+        }
+        digest.revision = revision;
+    }
+
+    /// [Fingerprint::records], reading through the records
+    /// unless the digest is up to date with them.
+    fn records_digest(&mut self) -> Result<u64, Error> {
+        if let Some(digest) = self.digest.as_ref().filter(|d| d.revision == self.revision) {
+            return Ok(digest.sum);
+        }
+        let mut sum = 0u64;
+        self.scan(|id, vector| sum = sum.wrapping_add(record_hash(id, vector)))?;
+        self.digest = Some(RecordDigest {
+            revision: self.revision,
+            sum,
+        });
+        Ok(sum)
+    }
+}
+
+impl Database {
+    /// Digest of the content of the database, equal for two databases that
+    /// hold the same records and layers with the same dimensions and metric,
+    /// whatever their files look like. The records are read through the
+    /// first time, and kept track of as they're written from then on, so that
+    /// it's taken again in no time.
+    pub fn fingerprint(&mut self) -> Result<Fingerprint, Error> {
+        self.drain_queue()?;
+        let mut layers = 0;
+        let mut bytes = vec![];
+        for layer in &self.layers {
+            bytes.clear();
+            vio::layer::write(layer, &mut bytes).map_err(Error::IO)?;
+            layers = crc32_continue(layers, &bytes);
+        }
+        let mut handle = self.handle.lock_auto_clear_poison();
+        Ok(Fingerprint {
+            dim_size: handle.dim_size,
+            metric: self.metric,
+            count: handle.count()?,
+            records: handle.records_digest()?,
+            layers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+
+    fn pushed(db: &mut Database, vectors: &[Vec<f32>]) {
+        for vector in vectors {
+            db.push(vector).unwrap();
+        }
+    }
+
+    #[test]
+    fn fingerprint_works() {
+        let mut rng = XorShift::new(31);
+        let vectors = (0..200).map(|_| rng.vector(6)).collect::<Vec<_>>();
+        let mut plain = DatabaseOptions::new(6)
+            .alignment(64)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        pushed(&mut plain, &vectors);
+        let expected = plain.fingerprint().unwrap();
+        assert_eq!(expected.count, 200);
+
+        // the same records, written over and over before being compacted
+        let file = SharedCursor::new();
+        let mut history = DatabaseOptions::new(6)
+            .history(true)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        pushed(&mut history, &vectors);
+        for id in 0..50 {
+            history.update(id, rng.vector(6)).unwrap();
+            history.update(id, &vectors[id as usize]).unwrap();
+        }
+        assert_eq!(history.fingerprint().unwrap(), expected);
+        let generation = history.generation().unwrap();
+        assert!(history.compact(generation).unwrap() > 0);
+        assert_eq!(history.fingerprint().unwrap(), expected);
+        assert_eq!(history.fingerprint().unwrap().digest(), expected.digest());
+
+        // a single component apart, then alike again
+        let mut changed = vectors[7].clone();
+        changed[3] += 1e-3;
+        plain.update(7, &changed).unwrap();
+        let differing = plain.fingerprint().unwrap();
+        assert_ne!(differing.records, expected.records);
+        assert_ne!(differing.digest(), expected.digest());
+        plain.update(7, &vectors[7]).unwrap();
+        assert_eq!(plain.fingerprint().unwrap(), expected);
+
+        // kept track of as records come and go, the same as read through anew
+        plain.remove(3).unwrap();
+        let id = plain.push(rng.vector(6)).unwrap();
+        let tracked = plain.fingerprint().unwrap();
+        assert_ne!(tracked, expected);
+        plain.handle.lock().unwrap().digest = None;
+        assert_eq!(plain.fingerprint().unwrap(), tracked);
+        plain.remove(id).unwrap();
+        plain.push(&vectors[3]).unwrap();
+        assert_ne!(plain.fingerprint().unwrap(), expected);
+        assert_eq!(plain.fingerprint().unwrap().count, 200);
+
+        // reopened, the file reads the same
+        drop(history);
+        let mut reopened = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(reopened.fingerprint().unwrap(), expected);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::db::{offset, Database, Error, Operation, SyncPolicy, VectorHandle};
+use crate::ds::layer::HnswLayer;
+use crate::ext::io::MoveContent;
+use crate::ext::semaphore::LockAutoClear;
+use crate::index::HnswIndex;
+use crate::vio;
+use crate::vio::format::LAYERS_COUNTED_SINCE;
+use std::cmp::max;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::sync::MutexGuard;
+
+/// Bytes encoded for a block of the file ahead of the data section,
+/// and where they go.
+pub(crate) struct Block {
+    offset: u64,
+    bytes: Vec<u8>,
+}
+
+impl Block {
+    pub(crate) fn new(offset: u64, bytes: Vec<u8>) -> Block {
+        Block { offset, bytes }
+    }
+
+    /// Writes the bytes in place, returning how many there were.
+    fn write(&self, handle: &mut VectorHandle) -> Result<u64, Error> {
+        write_at(handle, self.offset, &self.bytes)
+    }
+}
+
+/// What a [flush](Database::flush) writes, encoded with no lock held.
+struct Staged {
+    /// Revision of the records the bloom filter was copied at.
+    revision: u64,
+    bloom: Option<Block>,
+    /// The layers of the index if it's built, led by their number.
+    layers: Option<Vec<u8>>,
+    blocks: Vec<Block>,
+}
+
+impl Staged {
+    /// Writes the blocks, returning the bytes written. The bloom filter
+    /// is left for the next flush if the records changed since it was
+    /// copied, its block having been flagged stale if it needs to be.
+    /// So are the layers if their section has no room for them.
+    fn write(&self, handle: &mut VectorHandle) -> Result<u64, Error> {
+        let mut written = 0;
+        let room = handle.layer_room();
+        if let (Some(layers), Some(offset)) = (&self.layers, handle.layers_offset) {
+            if layers.len() as u64 <= room {
+                written += write_at(handle, offset, layers)?;
+            }
+        }
+        for block in &self.blocks {
+            written += block.write(handle)?;
+        }
+        if let Some(bloom) = self.bloom.as_ref().filter(|_| handle.revision == self.revision) {
+            written += bloom.write(handle)?;
+            handle.bloom_fresh = true;
+        }
+        Ok(written)
+    }
+}
+
+/// Writes `bytes` at `offset`, returning how many there were.
+fn write_at(handle: &mut VectorHandle, offset: u64, bytes: &[u8]) -> Result<u64, Error> {
+    handle.fd.seek(SeekFrom::Start(offset)).map_err(Error::IO)?;
+    handle.fd.write_all(bytes).map_err(Error::IO)?;
+    let written = bytes.len() as u64;
+    handle.written(written)?;
+    Ok(written)
+}
+
+impl VectorHandle {
+    /// Bytes the layer section has room for, none if there isn't one.
+    fn layer_room(&self) -> u64 {
+        self.layers_offset.map_or(0, |offset| self.data_section - offset)
+    }
+
+    /// Makes room for `needed` bytes of layers, at least doubling the
+    /// section, by moving the records further into the file and rewriting
+    /// the header to say where they are. Returns the bytes written, none if
+    /// there's room already or the file is from before version 17, whose
+    /// layers are laid out otherwise.
+    ///
+    /// Fails with [Error::IterationInProgress] while an [iterator](Database::iter)
+    /// lives, as it reads the records where they were.
+    fn grow_layer_section(&mut self, needed: u64) -> Result<u64, Error> {
+        if self.layer_room() >= needed {
+            return Ok(0);
+        }
+        if self.iterators.is_iterating() {
+            return Err(Error::IterationInProgress);
+        }
+        self.fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        let header = vio::dbheader::read(&mut self.fd).map_err(Error::Header)?;
+        if header.version < LAYERS_COUNTED_SINCE {
+            return Ok(0);
+        }
+        let reserved = max(needed, header.layer_section.unwrap_or(0).saturating_mul(2));
+        let header = header.with_layer_section(reserved);
+        let shift = offset::sub(header.data_section, self.data_section)?;
+        let following = self.len()?.saturating_sub(self.data_section);
+        self.fd
+            .seek(SeekFrom::Start(self.data_section))
+            .map_err(Error::IO)?;
+        self.fd
+            .move_content(
+                offset::to_usize(following)?,
+                offset::to_isize(shift)?,
+                offset::move_buffer_size(shift),
+            )
+            .map_err(Error::IO)?;
+        // the records are moved for good before the header points at them
+        if self.sync_policy != SyncPolicy::Never {
+            self.sync()?;
+        }
+        let written = following + header.rewrite(&mut self.fd).map_err(Error::Header)?;
+        self.written(written)?;
+        self.data_section = header.data_section;
+        self.layers_offset = Some(header.prefix_end());
+        self.writer.forget();
+        if let Some(history) = self.history.as_mut() {
+            history.shift(shift);
+        }
+        Ok(written)
+    }
+}
+
+impl Database {
+    /// Persists what is only kept in memory, which is the vectors in the
+    /// write queue, the bloom filter, the key, level and group tables if
+    /// there are any, the layers of the index if it's built, and the search
+    /// parameters, and syncs unless the policy says never. Returns the number
+    /// of bytes written.
+    ///
+    /// The layers go in their [section](crate::db::DatabaseOptions::layer_section),
+    /// which is made room for first if it has none for them, moving the
+    /// records further into the file. That fails with
+    /// [Error::IterationInProgress], writing nothing, while an
+    /// [iterator](Database::iter) lives.
+    ///
+    /// Searches meanwhile wait no longer than it takes to copy out the bloom
+    /// filter and the levels of the index, and later to write what was
+    /// encoded from them, which happens in between with neither held.
+    /// Whatever changes in between is left for the next flush.
+    pub fn flush(&self) -> Result<usize, Error> {
+        let _timer = self.time(Operation::Flush);
+        if self.read_only {
+            return Ok(0);
+        }
+        self.drain_queue()?;
+        let staged = self.stage()?;
+        let needed = staged.layers.as_ref().map_or(0, |layers| layers.len() as u64);
+        let (mut handle, mut written) = self.lock_with_layer_room(needed)?;
+        written += staged.write(&mut handle)?
+            + handle.persist_search(self.search_params())?
+            + handle.persist_hnsw(self.config)?;
+        if handle.sync_policy != SyncPolicy::Never {
+            handle.sync()?;
+        }
+        Ok(written as usize)
+    }
+
+    /// Locks the handle, growing the layer section first if it has no room
+    /// for `needed` bytes, keeping readers out while the records move. The
+    /// room is made under the lock the layers are then written under, so
+    /// nothing takes it in between. Returns the bytes written growing it.
+    fn lock_with_layer_room(
+        &self,
+        needed: u64,
+    ) -> Result<(MutexGuard<'_, VectorHandle>, u64), Error> {
+        let handle = self.handle.lock_auto_clear_poison();
+        if handle.layer_room() >= needed {
+            return Ok((handle, 0));
+        }
+        drop(handle);
+        let (mut handle, mut layout) = self.lock_records();
+        let written = handle.grow_layer_section(needed)?;
+        if let Some(layout) = layout.as_mut() {
+            *layout = handle.layout();
+        }
+        Ok((handle, written))
+    }
+
+    /// Copies out what a flush writes, holding the handle and the index
+    /// only as long as that takes, then encodes it into blocks.
+    fn stage(&self) -> Result<Staged, Error> {
+        let (revision, bloom, bloom_offset) = {
+            let handle = self.handle.lock_auto_clear_poison();
+            (handle.revision, handle.bloom.clone(), handle.bloom_offset)
+        };
+        let levels = self.index_levels();
+        let layers = self
+            .index
+            .lock_auto_clear_poison()
+            .as_ref()
+            .map(HnswIndex::stored_layers);
+
+        let bloom = match bloom {
+            Some(bloom) => {
+                let mut bytes = Cursor::new(Vec::new());
+                vio::bloom::write(&mut bytes, &bloom).map_err(Error::IO)?;
+                Some(Block::new(bloom_offset, bytes.into_inner()))
+            }
+            None => None,
+        };
+        let layers = match layers {
+            Some(layers) => Some(encode_layers(&layers)?),
+            None => None,
+        };
+        let blocks = [self.encode_keys()?, self.encode_levels(levels)?, self.encode_groups()?];
+        Ok(Staged {
+            revision,
+            bloom,
+            layers,
+            blocks: blocks.into_iter().flatten().collect(),
+        })
+    }
+}
+
+/// Encodes `layers` for their section, led by their number.
+fn encode_layers(layers: &[HnswLayer]) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![];
+    vio::layer::write_section(layers, &mut bytes).map_err(Error::IO)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error, LayerView, SearchPlan, SearchRequest};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::index::SearchScratch;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn flush_lets_searches_through() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .bloom_filter(2_000_000, 0.001)
+            .level_table(3000)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut rng = XorShift::new(12);
+        for _ in 0..3000 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        db.build_index().unwrap();
+        let db = Arc::new(db);
+
+        let flushing = Arc::new(AtomicBool::new(true));
+        let searcher = {
+            let (db, flushing) = (db.clone(), flushing.clone());
+            thread::spawn(move || {
+                let mut rng = XorShift::new(13);
+                let mut scratch = SearchScratch::new();
+                let (mut searches, mut slowest) = (0, Duration::ZERO);
+                while flushing.load(Ordering::Relaxed) || searches == 0 {
+                    let query = rng.vector(4);
+                    let request = SearchRequest::new(&query, 10).plan(SearchPlan::Graph);
+                    let began = Instant::now();
+                    db.query_with_scratch(&request, &mut scratch).unwrap();
+                    slowest = slowest.max(began.elapsed());
+                    searches += 1;
+                }
+                slowest
+            })
+        };
+        for _ in 0..5 {
+            assert!(db.flush().unwrap() > 0);
+        }
+        flushing.store(false, Ordering::Relaxed);
+        let slowest = searcher.join().unwrap();
+        assert!(slowest < Duration::from_secs(2), "{slowest:?}");
+
+        // the file is as of the last flush, the bloom filter up to date
+        let (reopened, report) = DatabaseOptions::default()
+            .verify(true)
+            .open_with_report("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert!(report.is_clean(), "{:?}", report.anomalies);
+        for id in [0, 1500, 2999] {
+            assert_eq!(reopened.node_level(id), db.node_level(id));
+            assert_eq!(reopened.get(id).unwrap(), db.get(id).unwrap());
+        }
+        assert!(!reopened.contains(3000).unwrap());
+    }
+
+    #[test]
+    fn flush_persists_layers() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .alignment(64)
+            .layer_section(1 << 20)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        // nothing to write yet, and nothing breaks
+        db.flush().unwrap();
+        let reopen = || {
+            DatabaseOptions::default()
+                .verify(true)
+                .open_with_report("mem", Box::new(file.reopen()))
+                .unwrap()
+        };
+        assert_eq!(reopen().0.layers().count(), 0);
+
+        let mut rng = XorShift::new(14);
+        for _ in 0..2000 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        db.build_index().unwrap();
+        assert!(db.flush().unwrap() > 0);
+        let built = db.layers().collect::<Vec<_>>();
+        assert!(built.len() > 1);
+        let (reopened, report) = reopen();
+        assert!(report.is_clean(), "{:?}", report.anomalies);
+        assert_eq!(reopened.layers().collect::<Vec<LayerView>>(), built);
+
+        // fewer layers over fewer records, the rest of the records untouched
+        db.drop_index();
+        let removed = (0..2000).step_by(2).collect::<Vec<_>>();
+        db.remove_many(&removed).unwrap();
+        db.build_index().unwrap();
+        db.flush().unwrap();
+        let shrunk = db.layers().collect::<Vec<_>>();
+        let (reopened, report) = reopen();
+        assert!(report.is_clean(), "{:?}", report.anomalies);
+        assert_eq!(reopened.layers().collect::<Vec<_>>(), shrunk);
+        assert_eq!(reopened.count().unwrap(), 1000);
+        for id in [1, 999, 1999] {
+            assert_eq!(reopened.get(id).unwrap(), db.get(id).unwrap());
+        }
+        assert!(reopened.get(0).unwrap().is_none());
+    }
+
+    #[test]
+    fn flush_makes_room_for_layers() {
+        for history in [false, true] {
+            let file = SharedCursor::new();
+            let mut db = DatabaseOptions::new(4)
+                .alignment(64)
+                .history(history)
+                .create("mem", Box::new(file.reopen()))
+                .unwrap();
+            if !history {
+                db.add_read_handles((0..2).map(|_| Box::new(file.reopen()) as _).collect());
+            }
+            let reopen = || {
+                DatabaseOptions::default()
+                    .verify(true)
+                    .open_with_report("mem", Box::new(file.reopen()))
+                    .unwrap()
+            };
+            let mut rng = XorShift::new(15);
+            for round in 0..2u32 {
+                for _ in 0..300 {
+                    db.push(rng.vector(4)).unwrap();
+                }
+                db.update(7, rng.vector(4)).unwrap();
+                db.drop_index();
+                db.build_index().unwrap();
+
+                // the records can't move from under an iterator
+                let iter = db.iter().unwrap();
+                let before = file.bytes();
+                assert!(matches!(db.flush(), Err(Error::IterationInProgress)));
+                assert_eq!(file.bytes(), before, "round {round}");
+                drop(iter);
+
+                assert!(db.flush().unwrap() > 0);
+                let (reopened, report) = reopen();
+                assert!(report.is_clean(), "{:?}", report.anomalies);
+                let built = db.layers().collect::<Vec<_>>();
+                assert_eq!(reopened.layers().collect::<Vec<LayerView>>(), built);
+                assert_eq!(reopened.count().unwrap(), 300 * (round as u64 + 1));
+                for id in [0, 7, 299 * (round + 1)] {
+                    assert!(db.get(id).unwrap().is_some());
+                    assert_eq!(reopened.get(id).unwrap(), db.get(id).unwrap());
+                }
+                // writes go on where the records are now
+                let pushed = db.push(rng.vector(4)).unwrap();
+                assert!(db.remove(pushed).unwrap().is_some());
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::algorithm::construct::level_share;
+use crate::db::stats::StatCounters;
+use crate::db::{Database, DbIndex, DbVector, HnswConfig, IdStrategy, Quota};
+use crate::ds::graph::NdGraph;
+use crate::ext::semaphore::LockAutoClear;
+use crate::index::HnswIndex;
+use crate::vio;
+use crate::vio::dbheader::DbHeader;
+use crate::vio::format::LAYER_COUNT_WIDTH;
+use std::cmp::min;
+
+/// # Forecast
+/// Sizes a database is expected to grow to, for provisioning ahead of
+/// bulk operations, see [Database::forecast]. Those of the index are
+/// upper bounds, taking every node as having all the neighbors it may.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forecast {
+    /// Vectors forecast for, those stored and those to come.
+    pub vectors: u64,
+    /// Bytes of the records, padding included.
+    pub data_bytes: u64,
+    /// Bytes of the whole file, those before the records included, but not
+    /// the room [flushing](Database::flush) layers that outgrow their
+    /// [section](crate::db::DatabaseOptions::layer_section) makes for them.
+    pub file_bytes: u64,
+    /// Nodes expected on each layer of the index, base first.
+    pub layer_nodes: Vec<u64>,
+    /// Bytes each layer of a [built](Database::build_index) index takes in memory, base first.
+    pub layer_bytes: Vec<u64>,
+    /// Bytes an index built over all of them takes in memory, layers included.
+    pub index_bytes: u64,
+    /// Bytes the layers take once [flushed](Database::flush) into a layer section.
+    pub stored_layer_bytes: u64,
+    /// Bytes held at once while building the index, being the index and
+    /// the records read for it.
+    pub peak_build_bytes: u64,
+}
+
+impl Forecast {
+    /// Sizes of `vectors` records `unit` bytes apart from `data_section` on,
+    /// of `dim_size` components, indexed by `config`.
+    pub(crate) fn new(
+        (dim_size, vectors): (u32, u64),
+        (data_section, unit): (u64, u64),
+        config: HnswConfig,
+    ) -> Forecast {
+        let multiplier = config.level_multiplier();
+        let mut layer_nodes = vec![];
+        loop {
+            let level = layer_nodes.len() as u32;
+            let nodes = (vectors as f64 * level_share(level, multiplier)).round() as u64;
+            if nodes == 0 {
+                break;
+            }
+            layer_nodes.push(nodes);
+        }
+
+        let capacity = min(vectors, u32::MAX as u64) as u32;
+        let mut layer_bytes = vec![];
+        // the number of layers leading them
+        let mut stored_layer_bytes = LAYER_COUNT_WIDTH;
+        for (level, nodes) in layer_nodes.iter().copied().enumerate() {
+            let max_degree = config.max_degree(level as u32);
+            // the base layer makes room for every vector at once, those above
+            // grow one by one from whenever their first node came along,
+            // ending up with no more room than grown for the last of them
+            let rows = match level {
+                0 => capacity,
+                _ => config.growth.grown(capacity.saturating_sub(1), capacity),
+            };
+            // a row takes one more neighbor before it's trimmed
+            let vertices = nodes * NdGraph::row_capacity(max_degree + 1) as u64;
+            layer_bytes.push(NdGraph::projected_bytes(rows as u64, vertices));
+            let edges = nodes * max_degree as u64 / 2;
+            stored_layer_bytes += vio::layer::anycast_len(nodes, edges, vectors);
+        }
+
+        let index_bytes =
+            HnswIndex::projected_table_bytes(vectors, dim_size) + layer_bytes.iter().sum::<u64>();
+        let record = size_of::<(DbIndex, DbVector)>() + dim_size as usize * size_of::<f32>();
+        let data_bytes = vectors * unit;
+        Forecast {
+            vectors,
+            data_bytes,
+            file_bytes: data_section + data_bytes,
+            layer_nodes,
+            layer_bytes,
+            index_bytes,
+            stored_layer_bytes,
+            peak_build_bytes: index_bytes + vectors * record as u64,
+        }
+    }
+
+    /// Same as [Database::forecast] for a database of `dim_size` yet to be
+    /// created with nothing but `config` set, holding `vectors`.
+    pub(crate) fn create(dim_size: u32, vectors: u64, config: HnswConfig) -> Forecast {
+        let header = DbHeader::new(dim_size, IdStrategy::default(), Quota::default());
+        let unit = StatCounters::new(&header).record_bytes();
+        Forecast::new((dim_size, vectors), (header.data_section, unit), config)
+    }
+}
+
+impl Database {
+    /// Sizes the database is expected to grow to once `additional_vectors`
+    /// more are pushed, and what indexing them all takes, by the records
+    /// laid out as they are and the [index parameters](crate::db::DatabaseOptions::hnsw)
+    /// in effect.
+    pub fn forecast(&self, additional_vectors: u64) -> Forecast {
+        let data_section = self.handle.lock_auto_clear_poison().data_section;
+        let vectors = self.counters.vectors() + additional_vectors;
+        let unit = self.counters.record_bytes();
+        Forecast::new(
+            (self.dim_size(), vectors),
+            (data_section, unit),
+            self.config(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, HnswConfig};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::ms::ManagementSystem;
+    use crate::vio::layer::write_section;
+
+    #[test]
+    fn forecast_bounds_what_it_takes() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(16)
+            .layer_section(8 << 20)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let forecast = db.forecast(3_000);
+        assert_eq!(forecast.vectors, 3_000);
+        assert_eq!(forecast.layer_nodes.first(), Some(&3_000));
+        assert!(forecast.layer_nodes.windows(2).all(|pair| pair[0] > pair[1]));
+
+        let mut rng = XorShift::new(13);
+        let vectors = (0..3_000).map(|_| rng.vector(16)).collect::<Vec<_>>();
+        db.push_many(&vectors).unwrap();
+        db.build_index().unwrap();
+        db.flush().unwrap();
+        // the records are laid out exactly as forecast
+        assert_eq!(forecast.data_bytes, 3_000 * (4 + 16 * 4));
+        assert_eq!(forecast.file_bytes, file.len());
+        assert_eq!(db.forecast(0), forecast);
+
+        // the index is bounded from above, by no more than twice what it takes
+        let index = db.index.lock().unwrap();
+        let index = index.as_ref().unwrap();
+        let layers = index.layers();
+        assert!(layers.len().abs_diff(forecast.layer_nodes.len()) <= 1);
+        for (layer, bytes) in layers.iter().zip(&forecast.layer_bytes) {
+            let actual = layer.heap_bytes();
+            assert!(actual <= *bytes && *bytes <= 2 * actual, "{actual} for {bytes}");
+        }
+        let actual = index.heap_bytes();
+        assert!(actual <= forecast.index_bytes, "{actual}");
+        assert!(forecast.index_bytes <= 2 * actual, "{actual}");
+        let mut stored = vec![];
+        let actual = write_section(&index.stored_layers(), &mut stored).unwrap() as u64;
+        assert!(actual <= forecast.stored_layer_bytes, "{actual}");
+        assert!(forecast.stored_layer_bytes <= 2 * actual, "{actual}");
+
+        // those yet to be created by the same numbers
+        let ms = ManagementSystem::new_mem();
+        let created = ms.forecast_create(16, 3_000, HnswConfig::default());
+        assert_eq!(created.index_bytes, forecast.index_bytes);
+        assert_eq!(created.data_bytes, forecast.data_bytes);
+        assert!(created.file_bytes < forecast.file_bytes);
+    }
+}
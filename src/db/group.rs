@@ -0,0 +1,295 @@
+use crate::db::flush::Block;
+use crate::db::{Database, DbIndex, DbVectorSlice, Error};
+use crate::vio;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+pub type GroupId = u64;
+
+/// How many times `k_groups * per_group` members a grouped search pulls
+/// through the index, as some are of groups left out or over `per_group`.
+const GROUP_OVERSAMPLING: usize = 4;
+
+/// How member distances are folded into the score of a group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GroupScore {
+    /// A group is as close as its closest member.
+    #[default]
+    Best,
+    /// A group is as close as the average of its returned members.
+    Mean,
+}
+
+/// # Group Hit
+/// One logical record of a grouped search, e.g. a document
+/// whose chunks were pushed under the same [GroupId].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupHit {
+    pub group_id: GroupId,
+    /// Aggregated distance the groups are ordered by.
+    pub score: f32,
+    /// The closest member and its distance.
+    pub best: (DbIndex, f32),
+    /// Closest members first, at most `per_group` of them.
+    pub members: Vec<(DbIndex, f32)>,
+}
+
+/// # Group Table
+/// Group of each record pushed into one, read from the block after the
+/// placeholder table's on open and written back on flush.
+pub(crate) struct GroupTable {
+    capacity: u32,
+    /// Where its block begins.
+    offset: u64,
+    groups: BTreeMap<DbIndex, GroupId>,
+}
+
+impl GroupTable {
+    pub(crate) fn new(
+        capacity: u32,
+        offset: u64,
+        entries: Vec<(DbIndex, GroupId)>,
+    ) -> GroupTable {
+        GroupTable {
+            capacity,
+            offset,
+            groups: entries.into_iter().collect(),
+        }
+    }
+
+    /// Where its block ends.
+    pub(crate) fn end(&self) -> u64 {
+        self.offset + vio::groups::block_size(self.capacity)
+    }
+
+    /// Bytes its groups take on the heap, roughly.
+    pub(crate) fn heap_bytes(&self) -> u64 {
+        (self.groups.len() * size_of::<(DbIndex, GroupId)>()) as u64
+    }
+
+    /// Drops the group of `id`, which was removed.
+    pub(crate) fn forget(&mut self, id: DbIndex) {
+        self.groups.remove(&id);
+    }
+
+    /// Encodes every group into the block.
+    fn encode(&self) -> Result<Block, Error> {
+        let entries = self.groups.iter().map(|(id, group)| (*id, *group));
+        let mut bytes = Cursor::new(Vec::new());
+        vio::groups::write(&mut bytes, entries).map_err(Error::IO)?;
+        Ok(Block::new(self.offset, bytes.into_inner()))
+    }
+}
+
+impl Database {
+    /// Appends a vector that belongs to the logical record `group_id`,
+    /// which is kept in the file and persisted on [flush](Database::flush).
+    /// Fails with [Error::NoGroupTable] without a
+    /// [group table](crate::db::DatabaseOptions::group_table),
+    /// and with [Error::GroupTableFull] once it holds as many as it can.
+    pub fn push_grouped<V: AsRef<[f32]>>(
+        &mut self,
+        group_id: GroupId,
+        vector: V,
+    ) -> Result<DbIndex, Error> {
+        let table = self.groups.as_ref().ok_or(Error::NoGroupTable)?;
+        if table.groups.len() >= table.capacity as usize {
+            return Err(Error::GroupTableFull(table.capacity));
+        }
+        let id = self.push(vector)?;
+        self.groups.as_mut().unwrap().groups.insert(id, group_id);
+        Ok(id)
+    }
+
+    pub fn group_of(&self, id: DbIndex) -> Option<GroupId> {
+        self.groups.as_ref()?.groups.get(&id).copied()
+    }
+
+    /// Encodes the group table into its block if there's one.
+    pub(super) fn encode_groups(&self) -> Result<Option<Block>, Error> {
+        self.groups.as_ref().map(GroupTable::encode).transpose()
+    }
+
+    /// Searches for the `k_groups` closest groups, each carrying up to
+    /// `per_group` of its closest members, taken as 1 if it's zero. Vectors
+    /// pushed without a group are left out.
+    ///
+    /// Goes through the index if it's [built](Database::build_index), pulling
+    /// a few times as many members as asked for, so groups whose members
+    /// are all beyond those may be missed. Otherwise every vector is ranked.
+    pub fn search_grouped(
+        &self,
+        query: DbVectorSlice,
+        k_groups: usize,
+        per_group: usize,
+    ) -> Result<Vec<GroupHit>, Error> {
+        self.search_grouped_by(query, k_groups, per_group, GroupScore::default())
+    }
+
+    /// Same as [Database::search_grouped], with groups scored by `score`.
+    pub fn search_grouped_by(
+        &self,
+        query: DbVectorSlice,
+        k_groups: usize,
+        per_group: usize,
+        score: GroupScore,
+    ) -> Result<Vec<GroupHit>, Error> {
+        let per_group = per_group.max(1);
+        let ranked = if self.is_indexed() {
+            let k = k_groups.saturating_mul(per_group).saturating_mul(GROUP_OVERSAMPLING);
+            self.search(query, k)?
+        } else {
+            self.rank_exact(query)?
+        };
+        let mut members = BTreeMap::<GroupId, Vec<(DbIndex, f32)>>::new();
+        for (id, distance) in ranked {
+            if let Some(group_id) = self.group_of(id) {
+                let list = members.entry(group_id).or_default();
+                if list.len() < per_group {
+                    list.push((id, distance));
+                }
+            }
+        }
+
+        let mut hits = members
+            .into_iter()
+            .filter(|(_, members)| !members.is_empty())
+            .map(|(group_id, members)| GroupHit {
+                group_id,
+                score: match score {
+                    GroupScore::Best => members[0].1,
+                    GroupScore::Mean => {
+                        members.iter().map(|(_, d)| d).sum::<f32>() / members.len() as f32
+                    }
+                },
+                best: members[0],
+                members,
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| {
+            a.score
+                .total_cmp(&b.score)
+                .then(a.group_id.cmp(&b.group_id))
+        });
+        hits.truncate(k_groups);
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, Error, GroupScore};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+
+    fn chunked_db(file: &SharedCursor) -> Database {
+        let mut db = DatabaseOptions::new(2)
+            .group_table(12)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for (group, base) in [(7u64, 0f32), (8, 10f32), (9, 20f32)] {
+            for offset in 0..4 {
+                db.push_grouped(group, [base + offset as f32, 0f32])
+                    .unwrap();
+            }
+        }
+        db.push([1f32, 0f32]).unwrap();
+        db
+    }
+
+    #[test]
+    fn search_grouped_works() {
+        let db = chunked_db(&SharedCursor::new());
+        let hits = db.search_grouped(&[11f32, 0f32], 3, 2).unwrap();
+        assert_eq!(
+            hits.iter().map(|h| h.group_id).collect::<Vec<_>>(),
+            vec![8, 7, 9]
+        );
+        assert_eq!(hits[0].best, (5, 0f32));
+        assert_eq!(hits[0].members, vec![(5, 0f32), (4, 1f32)]);
+        assert_eq!(hits[1].best, (3, 8f32));
+        assert!(hits.iter().all(|h| h.members.len() == 2));
+
+        let hits = db.search_grouped(&[11f32, 0f32], 1, 4).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].members.len(), 4);
+
+        let hits = db.search_grouped(&[11f32, 0f32], 2, 0).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].members, vec![(5, 0f32)]);
+    }
+
+    #[test]
+    fn search_grouped_goes_through_index() {
+        let mut rng = XorShift::new(11);
+        let mut db = DatabaseOptions::new(8)
+            .group_table(2000)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        for id in 0..2000u64 {
+            db.push_grouped(id / 10, rng.vector(8)).unwrap();
+        }
+        let query = rng.vector(8);
+        let exact = db.search_grouped(&query, 5, 3).unwrap();
+        db.build_index().unwrap();
+        let indexed = db.search_grouped(&query, 5, 3).unwrap();
+        assert_eq!(indexed.len(), 5);
+        assert_eq!(indexed[0].best, exact[0].best);
+        for hit in &indexed {
+            assert!(hit.members.len() <= 3);
+            assert!(hit.members.iter().all(|(id, _)| (id / 10) as u64 == hit.group_id));
+        }
+    }
+
+    #[test]
+    fn search_grouped_by_mean_works() {
+        let db = chunked_db(&SharedCursor::new());
+        let hits = db
+            .search_grouped_by(&[13f32, 0f32], 2, 4, GroupScore::Mean)
+            .unwrap();
+        assert_eq!(hits[0].group_id, 8);
+        assert_eq!(hits[0].score, 1.5f32);
+        assert_eq!(hits[1].group_id, 9);
+    }
+
+    #[test]
+    fn groups_survive_reopen() {
+        let file = SharedCursor::new();
+        let mut db = chunked_db(&file);
+        db.remove(0).unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        let reopened = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(reopened.group_of(0), None);
+        assert_eq!(reopened.group_of(1), Some(7));
+        assert_eq!(reopened.group_of(11), Some(9));
+        assert_eq!(reopened.group_of(12), None);
+        let hits = reopened.search_grouped(&[11f32, 0f32], 3, 2).unwrap();
+        assert_eq!(
+            hits.iter().map(|h| h.group_id).collect::<Vec<_>>(),
+            vec![8, 7, 9]
+        );
+    }
+
+    #[test]
+    fn push_grouped_needs_room() {
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        assert!(matches!(db.push_grouped(1, [0f32; 2]), Err(Error::NoGroupTable)));
+        assert_eq!(db.count().unwrap(), 0);
+
+        let mut db = chunked_db(&SharedCursor::new());
+        assert!(matches!(
+            db.push_grouped(1, [0f32; 2]),
+            Err(Error::GroupTableFull(12))
+        ));
+        db.remove(3).unwrap();
+        assert_eq!(db.push_grouped(1, [0f32; 2]).unwrap(), 13);
+        assert_eq!(db.group_of(13), Some(1));
+    }
+}
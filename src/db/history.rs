@@ -0,0 +1,625 @@
+use crate::db::{Database, DatabaseOptions, DbIndex, DbVector, DbVectorSlice, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use crate::vio;
+use crate::vio::dbheader::DbHeader;
+use crate::vio::format;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{BTreeSet, HashMap};
+use std::io::SeekFrom;
+use std::sync::Arc;
+
+/// Counts writes to an append-only database, starting from 1 for the first.
+pub type Generation = u64;
+
+/// Set in the generation of a record that removes its id.
+pub(crate) const REMOVED: Generation = format::GENERATION_REMOVED;
+
+/// Bytes between the start of a record and its vector in append-only mode.
+pub(crate) const RECORD_PREFIX: u64 = format::RECORD_ID_WIDTH + format::RECORD_GENERATION_WIDTH;
+
+#[derive(Debug, Clone, Copy)]
+struct Version {
+    generation: Generation,
+    offset: u64,
+    removed: bool,
+}
+
+/// # History
+/// Where every version of every id lives in an append-only database.
+///
+/// Records are appended and never rewritten, each tagged with the generation
+/// of the write. An id read at generation `g` resolves to its record with the
+/// greatest generation not above `g`; it's absent if there's no such record or
+/// that record is a removal. Without a generation, the newest record counts.
+///
+/// If the database [reuses slots](crate::db::DatabaseOptions::reuse_slots),
+/// only the newest record of each id is kept, the slots of the others being
+/// [free](History::free) to write into, and so is that of a removal.
+pub(crate) struct History {
+    header: DbHeader,
+    generation: Generation,
+    /// Versions of each id, oldest first.
+    versions: HashMap<DbIndex, Vec<Version>>,
+    /// Offsets of the records no longer read, if reusing slots.
+    free: Vec<u64>,
+    /// Ids written to while an [iterator](Database::iter) lived, whose
+    /// older versions are freed on the first write after.
+    pending: BTreeSet<DbIndex>,
+    /// Greatest id whose versions were all freed, still counting
+    /// towards the [last id](History::last_id).
+    forgotten: Option<DbIndex>,
+}
+
+impl History {
+    pub(crate) fn new(header: &DbHeader) -> History {
+        History {
+            header: header.clone(),
+            generation: header.history.unwrap_or_default(),
+            versions: HashMap::new(),
+            free: vec![],
+            pending: BTreeSet::new(),
+            forgotten: None,
+        }
+    }
+
+    /// Oldest generation that can be read, the latest if reusing slots.
+    fn horizon(&self) -> Generation {
+        if self.header.reuse_slots {
+            return self.generation;
+        }
+        self.header.history.unwrap_or_default()
+    }
+
+    pub(crate) fn resolve(&self, id: DbIndex, at: Option<Generation>) -> Option<u64> {
+        let versions = self.versions.get(&id)?;
+        let version = match at {
+            None => versions.last(),
+            Some(at) => versions.iter().rev().find(|v| v.generation <= at),
+        }?;
+        (!version.removed).then_some(version.offset)
+    }
+
+    /// Offsets of every id present at `at`, ordered by id.
+    pub(crate) fn live(&self, at: Option<Generation>) -> Vec<(DbIndex, u64)> {
+        let mut live = self
+            .versions
+            .keys()
+            .filter_map(|id| Some((*id, self.resolve(*id, at)?)))
+            .collect::<Vec<_>>();
+        live.sort_by_key(|(id, _)| *id);
+        live
+    }
+
+    /// Generation of the latest write.
+    pub(crate) fn generation(&self) -> Generation {
+        self.generation
+    }
+
+    /// Follows the records moved `by` bytes further into the file.
+    pub(crate) fn shift(&mut self, by: u64) {
+        let versions = self.versions.values_mut().flatten();
+        for offset in versions.map(|v| &mut v.offset).chain(&mut self.free) {
+            *offset += by;
+        }
+    }
+
+    /// Greatest id ever written, even if removed since,
+    /// so that history never mixes up two vectors.
+    pub(crate) fn last_id(&self) -> Option<DbIndex> {
+        self.versions.keys().max().copied().max(self.forgotten)
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.versions
+            .values()
+            .filter(|v| v.last().is_some_and(|v| !v.removed))
+            .count() as u64
+    }
+
+    /// Records held, removals included, and how many of them aren't what's
+    /// [count](History::count)ed at the latest generation.
+    pub(crate) fn superseded(&self) -> (u64, u64) {
+        let records = self.versions.values().map(|v| v.len() as u64).sum::<u64>()
+            + self.free.len() as u64;
+        (records, records - self.count())
+    }
+
+    /// Drops every version of `id` but the newest, and that one too if
+    /// it's a removal, returning the ones dropped to be freed.
+    fn forget(&mut self, id: DbIndex) -> Vec<Version> {
+        let Some(versions) = self.versions.get_mut(&id) else {
+            return vec![];
+        };
+        let newest = versions.pop().unwrap();
+        let mut stale = std::mem::take(versions);
+        if newest.removed {
+            self.versions.remove(&id);
+            self.forgotten = self.forgotten.max(Some(id));
+            stale.push(newest);
+        } else {
+            versions.push(newest);
+        }
+        stale
+    }
+}
+
+impl VectorHandle {
+    /// Indexes the records of an append-only file.
+    pub(crate) fn load_history(&mut self) -> Result<(), Error> {
+        let mut history = History::new(&self.history.as_ref().unwrap().header);
+        let unit = self.unit_size_bytes();
+        for index in 0..self.seek_count()? {
+            let offset = self.data_section + index * unit;
+            self.fd.seek(SeekFrom::Start(offset)).map_err(Error::IO)?;
+            let id = self.fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+            let raw = self.fd.read_u64::<BigEndian>().map_err(Error::IO)?;
+            let version = Version {
+                generation: raw & !REMOVED,
+                offset,
+                removed: raw & REMOVED != 0,
+            };
+            history.generation = history.generation.max(version.generation);
+            history.versions.entry(id).or_default().push(version);
+        }
+        if history.header.reuse_slots {
+            for versions in history.versions.values_mut() {
+                versions.sort_by_key(|v| v.generation);
+                let newest = versions.pop().unwrap();
+                // older vectors left untagged by a write cut short
+                // are kept for the next write to the id to tag
+                let (removals, mut untagged) = std::mem::take(versions)
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|v| v.removed);
+                history.free.extend(removals.iter().map(|v| v.offset));
+                untagged.push(newest);
+                *versions = untagged;
+            }
+            history.versions.retain(|id, versions| {
+                let removed = matches!(versions.as_slice(), [v] if v.removed);
+                if removed {
+                    history.free.push(versions[0].offset);
+                    history.forgotten = history.forgotten.max(Some(*id));
+                }
+                !removed
+            });
+        }
+        self.history = Some(history);
+        Ok(())
+    }
+
+    /// Frees the slots of the versions of `id` no longer read, tagging those
+    /// of its vectors removed so that none of them is read on the next open.
+    fn reclaim(&mut self, id: DbIndex) -> Result<(), Error> {
+        let stale = self.history.as_mut().unwrap().forget(id);
+        for version in &stale {
+            if !version.removed {
+                self.fd
+                    .seek(SeekFrom::Start(version.offset + format::RECORD_ID_WIDTH))
+                    .map_err(Error::IO)?;
+                self.fd
+                    .write_u64::<BigEndian>(version.generation | REMOVED)
+                    .map_err(Error::IO)?;
+                self.written(format::RECORD_GENERATION_WIDTH)?;
+            }
+        }
+        let history = self.history.as_mut().unwrap();
+        history.free.extend(stale.iter().map(|v| v.offset));
+        Ok(())
+    }
+
+    /// Appends a record superseding whatever `id` was, or removing it
+    /// if there's no `vector`. Returns the generation of the write.
+    ///
+    /// If reusing slots, the record goes into a free one if there's any,
+    /// and a removal tags the vector removed where it is instead, unless an
+    /// [iterator](Database::iter) still reads what's being superseded.
+    pub(crate) fn append(
+        &mut self,
+        id: DbIndex,
+        vector: Option<DbVectorSlice>,
+    ) -> Result<Generation, Error> {
+        let history = self.history.as_mut().unwrap();
+        let generation = history.generation + 1;
+        let removed = vector.is_none();
+        let tag = if removed { generation | REMOVED } else { generation };
+        let reclaiming = history.header.reuse_slots && !self.iterators.is_iterating();
+        let in_place = match reclaiming && removed {
+            true => history.versions.get_mut(&id).and_then(|v| v.pop_if(|v| !v.removed)),
+            false => None,
+        };
+        let offset = if let Some(version) = in_place {
+            self.fd
+                .seek(SeekFrom::Start(version.offset + format::RECORD_ID_WIDTH))
+                .map_err(Error::IO)?;
+            self.fd.write_u64::<BigEndian>(tag).map_err(Error::IO)?;
+            self.written(format::RECORD_GENERATION_WIDTH)?;
+            version.offset
+        } else {
+            let offset = match history.free.pop() {
+                Some(offset) => self.fd.seek(SeekFrom::Start(offset)),
+                None => self.fd.seek(SeekFrom::End(0)),
+            }
+            .map_err(Error::IO)?;
+            self.fd.write_u32::<BigEndian>(id).map_err(Error::IO)?;
+            self.fd.write_u64::<BigEndian>(tag).map_err(Error::IO)?;
+            let zeros;
+            let vector = match vector {
+                Some(vector) => vector,
+                None => {
+                    zeros = vec![0f32; self.dim_size as usize];
+                    &zeros
+                }
+            };
+            vio::vector::write(vector, &mut self.fd).map_err(Error::IO)?;
+            self.write_padding()?;
+            self.written(self.unit_size_bytes())?;
+            offset
+        };
+
+        let history = self.history.as_mut().unwrap();
+        history.generation = generation;
+        history.versions.entry(id).or_default().push(Version {
+            generation,
+            offset,
+            removed,
+        });
+        if history.header.reuse_slots {
+            history.pending.insert(id);
+            if reclaiming {
+                for id in std::mem::take(&mut history.pending) {
+                    self.reclaim(id)?;
+                }
+            }
+        }
+        self.amplified(self.unit_size_bytes(), 0);
+        Ok(generation)
+    }
+
+    /// Position of the vector of `id` at generation `at`, the newest if `None`.
+    pub(crate) fn seek_version(
+        &mut self,
+        id: DbIndex,
+        at: Option<Generation>,
+    ) -> Result<Option<u64>, Error> {
+        match self.history.as_ref().unwrap().resolve(id, at) {
+            None => Ok(None),
+            Some(offset) => {
+                self.fd
+                    .seek(SeekFrom::Start(offset + RECORD_PREFIX))
+                    .map_err(Error::IO)?;
+                Ok(Some(offset))
+            }
+        }
+    }
+
+    pub(crate) fn get_at(
+        &mut self,
+        id: DbIndex,
+        at: Option<Generation>,
+    ) -> Result<Option<DbVector>, Error> {
+        if self.seek_version(id, at)?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(self.read_vector()?))
+    }
+
+    /// Every vector present at generation `at`, ordered by id.
+    pub(crate) fn read_at(
+        &mut self,
+        at: Option<Generation>,
+    ) -> Result<Vec<(DbIndex, DbVector)>, Error> {
+        let live = self.history.as_ref().unwrap().live(at);
+        live.into_iter()
+            .map(|(id, offset)| {
+                self.fd
+                    .seek(SeekFrom::Start(offset + RECORD_PREFIX))
+                    .map_err(Error::IO)?;
+                Ok((id, self.read_vector()?))
+            })
+            .collect()
+    }
+
+    fn read_vector(&mut self) -> Result<DbVector, Error> {
+        vio::vector::read(self.dim_size, &mut self.fd, self.verbatim).map_err(|e| match e {
+            vio::Error::Eof => Error::Parse(),
+            vio::Error::IO(e) => Error::IO(e),
+        })
+    }
+
+    /// Rewrites the records so that only the state at `horizon` is left of
+    /// everything up to it, returning how many records were dropped.
+    fn compact(&mut self, horizon: Generation) -> Result<u64, Error> {
+        let history = self.history.as_ref().unwrap();
+        let horizon = horizon.clamp(history.horizon(), history.generation);
+        let mut kept = history
+            .versions
+            .iter()
+            .flat_map(|(id, versions)| {
+                let base = versions.iter().rposition(|v| v.generation <= horizon);
+                versions
+                    .iter()
+                    .enumerate()
+                    .filter(move |(i, v)| match base {
+                        Some(base) => *i > base || *i == base && !v.removed,
+                        None => true,
+                    })
+                    .map(move |(_, v)| (*id, *v))
+            })
+            .collect::<Vec<_>>();
+        kept.sort_by_key(|(_, v)| (v.generation, v.offset));
+        let total = self.seek_count()?;
+
+        let unit = self.unit_size_bytes();
+        let mut buf = vec![0u8; unit as usize];
+        let mut pos = self.data_section;
+        for (_, version) in &kept {
+            if version.offset != pos {
+                self.fd
+                    .seek(SeekFrom::Start(version.offset))
+                    .map_err(Error::IO)?;
+                self.fd.read_exact(&mut buf).map_err(Error::IO)?;
+                self.fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+                self.fd.write_all(&buf).map_err(Error::IO)?;
+            }
+            pos += unit;
+        }
+        self.fd.set_len(pos).map_err(Error::IO)?;
+
+        let header = &mut self.history.as_mut().unwrap().header;
+        header.history = Some(horizon);
+        header.rewrite(&mut self.fd).map_err(Error::Header)?;
+        self.written(pos - self.data_section)?;
+        self.load_history()?;
+        self.publish();
+        Ok(total - kept.len() as u64)
+    }
+}
+
+impl Database {
+    #[deprecated(note = "use DatabaseOptions::history")]
+    pub fn with_history(name: &str, dim_size: u32, fd: Box<dyn vio::RandomAccess>) -> Database {
+        DatabaseOptions::new(dim_size)
+            .history(true)
+            .create(name, fd)
+            .unwrap()
+    }
+
+    pub fn is_append_only(&self) -> bool {
+        self.handle.lock_auto_clear_poison().history.is_some()
+    }
+
+    /// Generation of the latest write, or `None` if the database isn't append-only.
+    pub fn generation(&self) -> Option<Generation> {
+        let handle = self.handle.lock_auto_clear_poison();
+        handle.history.as_ref().map(|h| h.generation)
+    }
+
+    /// Read-only view of the database as it was right after write `generation`.
+    /// Fails with [Error::NoHistory] if the database isn't append-only,
+    /// the history before `generation` was compacted away, or it
+    /// [reuses slots](DatabaseOptions::reuse_slots) and `generation` isn't the latest.
+    pub fn at_generation(&self, generation: Generation) -> Result<GenerationView<'_>, Error> {
+        let handle = self.handle.lock_auto_clear_poison();
+        match &handle.history {
+            Some(history) if generation >= history.horizon() => Ok(GenerationView {
+                db: self,
+                generation,
+            }),
+            _ => Err(Error::NoHistory(generation)),
+        }
+    }
+
+    /// Drops the history up to `horizon`, keeping only what's visible at it
+    /// and everything written since. Returns the number of records dropped.
+    /// Does nothing but return zero if the database isn't append-only, and
+    /// fails with [Error::IterationInProgress] while an [iterator](Database::iter) lives.
+    pub fn compact(&mut self, horizon: Generation) -> Result<u64, Error> {
+        self.check_writable()?;
+        let mut handle = self.handle.lock_auto_clear_poison();
+        if handle.history.is_none() {
+            return Ok(0);
+        }
+        if handle.iterators.is_iterating() {
+            return Err(Error::IterationInProgress);
+        }
+        handle.compact(horizon)
+    }
+
+    /// Replaces vector `id`, returning the previous one,
+    /// or `None` with nothing written if it doesn't exist.
+    /// Takes `vector` the way [Database::push] does.
+    pub fn update<V: AsRef<[f32]>>(
+        &mut self,
+        id: DbIndex,
+        vector: V,
+    ) -> Result<Option<Arc<DbVector>>, Error> {
+        self.update_slice(id, vector.as_ref())
+    }
+
+    fn update_slice(
+        &mut self,
+        id: DbIndex,
+        vector: DbVectorSlice,
+    ) -> Result<Option<Arc<DbVector>>, Error> {
+        self.check_writable()?;
+        let vector = &*self.conform_insert(vector);
+        let previous = self.handle.lock_auto_clear_poison().update(id, vector)?;
+        if previous.is_some() {
+            self.loaded_vectors
+                .lock_auto_clear_poison()
+                .insert(id, Arc::new(DbVector::from(vector)));
+            self.index_insert(id, vector)?;
+        }
+        Ok(previous.map(Arc::new))
+    }
+}
+
+/// # Generation View
+/// A database as it was at a past [Generation], see [Database::at_generation].
+pub struct GenerationView<'a> {
+    db: &'a Database,
+    generation: Generation,
+}
+
+impl GenerationView<'_> {
+    pub fn generation(&self) -> Generation {
+        self.generation
+    }
+
+    pub fn get(&self, id: DbIndex) -> Result<Option<DbVector>, Error> {
+        let mut handle = self.db.handle.lock_auto_clear_poison();
+        handle.get_at(id, Some(self.generation))
+    }
+
+    pub fn count(&self) -> Result<u64, Error> {
+        let handle = self.db.handle.lock_auto_clear_poison();
+        Ok(handle
+            .history
+            .as_ref()
+            .unwrap()
+            .live(Some(self.generation))
+            .len() as u64)
+    }
+
+    /// The `k` vectors closest to `query` at this generation, closest first.
+    pub fn search(&self, query: DbVectorSlice, k: usize) -> Result<Vec<(DbIndex, f32)>, Error> {
+        let mut handle = self.db.handle.lock_auto_clear_poison();
+        if query.len() != handle.dim_size as usize {
+            return Err(Error::Dimension(handle.dim_size, query.len()));
+        }
+        let mut ranked = handle
+            .read_at(Some(self.generation))?
+            .into_iter()
+            .map(|(id, v)| (id, self.db.metric.distance(query, &v)))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+        ranked.truncate(k);
+        Ok(ranked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, DbIndex, Error, OptionsError};
+    use crate::ext::mem::SharedCursor;
+
+    #[test]
+    fn time_travel_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(2)
+            .history(true)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let id = db.push([1f32; 2]).unwrap();
+        let other = db.push([9f32; 2]).unwrap();
+        let first = db.generation().unwrap();
+        db.update(id, [2f32; 2]).unwrap().unwrap();
+        let second = db.generation().unwrap();
+        db.update(id, [3f32; 2]).unwrap().unwrap();
+        db.remove(other).unwrap().unwrap();
+        let third = db.generation().unwrap();
+        assert_eq!((first, second, third), (2, 3, 5));
+
+        assert_eq!(*db.get(id).unwrap().unwrap(), vec![3f32; 2]);
+        for (generation, expected) in [(first, 1f32), (second, 2f32), (third - 1, 3f32)] {
+            let view = db.at_generation(generation).unwrap();
+            assert_eq!(view.get(id).unwrap().unwrap(), vec![expected; 2]);
+            assert_eq!(view.get(other).unwrap().unwrap(), vec![9f32; 2]);
+        }
+        let view = db.at_generation(third).unwrap();
+        assert!(view.get(other).unwrap().is_none());
+        assert_eq!(view.count().unwrap(), 1);
+        assert_eq!(view.search(&[0f32; 2], 5).unwrap()[0].0, id);
+        assert!(db.at_generation(1).unwrap().get(id).unwrap().is_some());
+        assert!(db.at_generation(0).unwrap().get(id).unwrap().is_none());
+        drop(db);
+
+        let mut db = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(db.generation(), Some(third));
+        assert_eq!(db.count().unwrap(), 1);
+        assert_eq!(db.at_generation(second).unwrap().get(id).unwrap().unwrap(), vec![2f32; 2]);
+
+        assert_eq!(db.compact(second).unwrap(), 1);
+        assert!(matches!(db.at_generation(first), Err(Error::NoHistory(_))));
+        assert_eq!(db.at_generation(second).unwrap().get(id).unwrap().unwrap(), vec![2f32; 2]);
+        assert_eq!(*db.get(id).unwrap().unwrap(), vec![3f32; 2]);
+        assert_eq!(db.push([4f32; 2]).unwrap(), 2);
+    }
+
+    #[test]
+    fn update_in_place_works() {
+        let mut db = DatabaseOptions::new(2).create("mem", Box::new(SharedCursor::new())).unwrap();
+        db.push([0f32; 2]).unwrap();
+        db.push([1f32; 2]).unwrap();
+        assert_eq!(*db.update(0, [5f32; 2]).unwrap().unwrap(), vec![0f32; 2]);
+        assert!(db.update(7, [5f32; 2]).unwrap().is_none());
+        assert_eq!(db.count().unwrap(), 2);
+        assert!(db.at_generation(0).is_err());
+    }
+
+    #[test]
+    fn slot_reuse_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .history(true)
+            .reuse_slots(true)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..100 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        let full = file.bytes().len();
+        // id, generation and four components
+        let unit = 4 + 8 + 4 * 4;
+        for id in (0..100).step_by(2) {
+            db.remove(id).unwrap().unwrap();
+        }
+        assert_eq!(file.bytes().len(), full);
+        for i in 100..150 {
+            assert_eq!(db.push([i as f32; 4]).unwrap(), i);
+        }
+        assert_eq!(file.bytes().len(), full);
+        // the first update takes a new slot, freeing the one it supersedes
+        for i in 0..10 {
+            db.update(1, [i as f32; 4]).unwrap().unwrap();
+        }
+        let grown = file.bytes().len();
+        assert_eq!(grown, full + unit);
+
+        let check = |db: &mut Database| {
+            assert_eq!(db.count().unwrap(), 100);
+            for id in 0..150 as DbIndex {
+                let expected = match id {
+                    1 => Some(9f32),
+                    id if id < 100 && id % 2 == 0 => None,
+                    id => Some(id as f32),
+                };
+                let found = db.get(id).unwrap().map(|v| v[0]);
+                assert_eq!(found, expected, "id {id}");
+            }
+            let generation = db.generation().unwrap();
+            assert!(db.at_generation(generation).is_ok());
+            assert!(matches!(db.at_generation(generation - 1), Err(Error::NoHistory(_))));
+        };
+        check(&mut db);
+        drop(db);
+
+        let mut db = DatabaseOptions::default()
+            .verify(true)
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        check(&mut db);
+        db.remove(3).unwrap().unwrap();
+        assert_eq!(db.push([0f32; 4]).unwrap(), 150);
+        assert_eq!(file.bytes().len(), grown);
+        assert!(db.compact(0).unwrap() > 0);
+        assert_eq!(file.bytes().len(), full);
+
+        assert!(matches!(
+            DatabaseOptions::new(4)
+                .reuse_slots(true)
+                .create("mem", Box::new(SharedCursor::new())),
+            Err(Error::Options(OptionsError::ReuseWithoutHistory))
+        ));
+    }
+}
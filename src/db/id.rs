@@ -0,0 +1,157 @@
+use crate::db::{DbIndex, Error};
+use std::collections::BTreeSet;
+
+/// # ID Strategy
+/// How pushed vectors are assigned their [DbIndex]. It's fixed when a
+/// database is created, since it decides whether external references
+/// to removed vectors may end up pointing at new ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdStrategy {
+    /// One past the last stored id, failing once `u32::MAX` is taken.
+    #[default]
+    Monotonic,
+    /// The smallest id freed by a removal first, then one past the last.
+    Reuse,
+}
+
+impl IdStrategy {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            IdStrategy::Monotonic => 0,
+            IdStrategy::Reuse => 1,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<IdStrategy> {
+        match byte {
+            0 => Some(IdStrategy::Monotonic),
+            1 => Some(IdStrategy::Reuse),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) enum IdAllocator {
+    Monotonic,
+    Reuse { free: BTreeSet<DbIndex> },
+}
+
+impl IdAllocator {
+    pub(crate) fn new(strategy: IdStrategy) -> IdAllocator {
+        match strategy {
+            IdStrategy::Monotonic => IdAllocator::Monotonic,
+            IdStrategy::Reuse => IdAllocator::Reuse {
+                free: BTreeSet::new(),
+            },
+        }
+    }
+
+    /// Recovers the free ids from the gaps between the stored ones,
+    /// given in ascending order.
+    pub(crate) fn recover(&mut self, stored: impl Iterator<Item = DbIndex>) {
+        if let IdAllocator::Reuse { free } = self {
+            let mut expected = 0;
+            for id in stored {
+                free.extend(expected..id);
+                expected = id + 1;
+            }
+        }
+    }
+
+    pub(crate) fn allocate(&mut self, last: Option<DbIndex>) -> Result<DbIndex, Error> {
+        if let IdAllocator::Reuse { free } = self {
+            if let Some(id) = free.pop_first() {
+                return Ok(id);
+            }
+        }
+        match last {
+            None => Ok(0),
+            Some(last) => last.checked_add(1).ok_or(Error::IdSpaceExhausted),
+        }
+    }
+
+    /// Whether the ids allocated next each follow the last one, none being free.
+    pub(crate) fn appends(&self) -> bool {
+        match self {
+            IdAllocator::Monotonic => true,
+            IdAllocator::Reuse { free } => free.is_empty(),
+        }
+    }
+
+    pub(crate) fn release(&mut self, id: DbIndex) {
+        if let IdAllocator::Reuse { free } = self {
+            free.insert(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, Error, IdStrategy};
+    use crate::ext::mem::SharedCursor;
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn filled(strategy: IdStrategy) -> (Database, SharedCursor) {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(2)
+            .id_strategy(strategy)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..10 {
+            db.push([i as f32; 2]).unwrap();
+        }
+        (db, file)
+    }
+
+    #[test]
+    fn monotonic_works() {
+        let (mut db, _) = filled(IdStrategy::Monotonic);
+        db.remove(3).unwrap().unwrap();
+        db.remove(7).unwrap().unwrap();
+        assert_eq!(db.push([10f32; 2]).unwrap(), 10);
+        assert_eq!(db.count().unwrap(), 9);
+        assert!(db.get(3).unwrap().is_none());
+    }
+
+    #[test]
+    fn reuse_works() {
+        let (mut db, file) = filled(IdStrategy::Reuse);
+        db.remove(7).unwrap().unwrap();
+        db.remove(3).unwrap().unwrap();
+        assert_eq!(db.push([30f32; 2]).unwrap(), 3);
+        assert_eq!(db.push([70f32; 2]).unwrap(), 7);
+        assert_eq!(db.push([100f32; 2]).unwrap(), 10);
+        assert_eq!(*db.get(3).unwrap().unwrap(), vec![30f32; 2]);
+        assert_eq!(*db.get(7).unwrap().unwrap(), vec![70f32; 2]);
+        assert_eq!(*db.get(8).unwrap().unwrap(), vec![8f32; 2]);
+        db.remove(5).unwrap().unwrap();
+        drop(db);
+
+        let mut db = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(db.push([50f32; 2]).unwrap(), 5);
+        assert_eq!(db.push([110f32; 2]).unwrap(), 11);
+        let ids = db
+            .handle
+            .lock()
+            .unwrap()
+            .read_all()
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, Vec::from_iter(0..12));
+    }
+
+    #[test]
+    fn exhausted_works() {
+        let (db, mut file) = filled(IdStrategy::Monotonic);
+        let last = db.handle.lock().unwrap().seek_insertion(8).unwrap();
+        drop(db);
+        file.seek(SeekFrom::Start(last)).unwrap();
+        file.write_all(&u32::MAX.to_be_bytes()).unwrap();
+
+        let mut db = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert!(matches!(db.push([0f32; 2]), Err(Error::IdSpaceExhausted)));
+        assert_eq!(db.count().unwrap(), 10);
+    }
+}
@@ -0,0 +1,424 @@
+use crate::db::{CancelToken, Database, DbIndex, DbVector, Error};
+use crate::vio::crc::{crc32, crc32_continue};
+use crate::vio::RandomAccess;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::io::{Read, SeekFrom, Write};
+
+/// Vectors read before they're pushed together, see [Database::import_fvecs].
+const IMPORT_BATCH: usize = 1024;
+const MAGIC: &[u8; 4] = b"VIMP";
+
+/// # Import Progress
+/// How much of a source an [ImportSession] has committed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ImportProgress {
+    pub records: u64,
+    /// Read from the source for those records.
+    pub bytes: u64,
+    /// CRC-32 of those bytes.
+    pub crc: u32,
+}
+
+/// # Import Session
+/// Progress of an import kept in a sidecar next to the database, for one cut
+/// off to be resumed by running it again over the same source with the same
+/// session, see [Database::import_fvecs_resumable].
+///
+/// The sidecar is written once a batch is pushed and synced. If the process
+/// dies in between, the batch is imported again on resume.
+pub struct ImportSession {
+    sidecar: Box<dyn RandomAccess>,
+    progress: ImportProgress,
+    batch: usize,
+    cancel: CancelToken,
+}
+
+impl ImportSession {
+    /// Picks up the progress kept in `sidecar`, or starts from the
+    /// beginning if it's empty.
+    pub fn open(mut sidecar: Box<dyn RandomAccess>) -> Result<ImportSession, Error> {
+        let len = sidecar.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+        let progress = if len == 0 {
+            ImportProgress::default()
+        } else {
+            sidecar.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+            read_progress(&mut sidecar).map_err(Error::IO)?
+        };
+        Ok(ImportSession {
+            sidecar,
+            progress,
+            batch: IMPORT_BATCH,
+            cancel: CancelToken::new(),
+        })
+    }
+
+    /// Records pushed together, and so committed, at a time.
+    /// Defaults to 1024.
+    pub fn batch_size(mut self, records: usize) -> ImportSession {
+        self.batch = records.max(1);
+        self
+    }
+
+    /// Stops the import once the batch underway is committed after `cancel`
+    /// is, failing with [Error::Cancelled] holding the records committed,
+    /// for it to be resumed later.
+    pub fn cancel_token(mut self, cancel: CancelToken) -> ImportSession {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn progress(&self) -> ImportProgress {
+        self.progress
+    }
+
+    fn commit(&mut self, progress: ImportProgress) -> Result<(), Error> {
+        let mut bytes = Vec::from(*MAGIC);
+        bytes.write_u64::<BigEndian>(progress.records).unwrap();
+        bytes.write_u64::<BigEndian>(progress.bytes).unwrap();
+        bytes.write_u32::<BigEndian>(progress.crc).unwrap();
+        let check = crc32(&[&bytes]);
+        bytes.write_u32::<BigEndian>(check).unwrap();
+        self.sidecar.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        self.sidecar.write_all(&bytes).map_err(Error::IO)?;
+        self.sidecar.flush().map_err(Error::IO)?;
+        self.sidecar.sync_data().map_err(Error::IO)?;
+        self.progress = progress;
+        Ok(())
+    }
+}
+
+fn read_progress(r: &mut impl Read) -> io::Result<ImportProgress> {
+    let mut bytes = [0u8; 28];
+    r.read_exact(&mut bytes)?;
+    let check = u32::from_be_bytes(bytes[24..].try_into().unwrap());
+    if &bytes[..4] != MAGIC || crc32(&[&bytes[..24]]) != check {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "sidecar isn't an import session",
+        ));
+    }
+    let mut r = &bytes[4..24];
+    Ok(ImportProgress {
+        records: r.read_u64::<BigEndian>()?,
+        bytes: r.read_u64::<BigEndian>()?,
+        crc: r.read_u32::<BigEndian>()?,
+    })
+}
+
+/// Keeps the length and CRC-32 of the bytes read through it.
+struct Hashed<'a> {
+    r: &'a mut dyn Read,
+    bytes: u64,
+    crc: u32,
+}
+
+impl Read for Hashed<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.r.read(buf)?;
+        self.bytes += read as u64;
+        self.crc = crc32_continue(self.crc, &buf[..read]);
+        Ok(read)
+    }
+}
+
+impl Database {
+    /// Appends the vectors of `r` in the [Fvecs](crate::db::ExportFormat::Fvecs)
+    /// format, such as the TEXMEX datasets, returning their ids in file order.
+    /// They're pushed [together](Database::push_many) a batch at a time,
+    /// so if one is of another dimension or cut off, which fails with
+    /// [Error::Dimension] or an unexpected end of file, the batches before
+    /// it stay imported.
+    ///
+    /// With `mapping_out`, a line of `source_row,assigned_id` is written to
+    /// it for every vector as its batch is pushed, rows counting records of
+    /// `r` from zero, so that the mapping is streamed rather than kept.
+    /// Vectors of a batch that failed aren't in it.
+    pub fn import_fvecs(
+        &mut self,
+        r: &mut dyn Read,
+        mapping_out: Option<&mut dyn Write>,
+    ) -> Result<Vec<DbIndex>, Error> {
+        self.import_fvecs_batches(r, (IMPORT_BATCH, 0), mapping_out, |_, _| Ok(()))
+    }
+
+    /// Same as [Database::import_fvecs], committing each batch to `session`
+    /// once it's synced, and skipping what the session has committed of `r`
+    /// already. Returns the ids of the vectors imported this time.
+    ///
+    /// Rows of the mapping count from the beginning of `r`, skipped records
+    /// included. It's written before the batch is committed, so rows of a
+    /// batch imported again on resume appear again with their new ids.
+    ///
+    /// Fails with [Error::ImportSourceChanged] before importing anything if
+    /// `r` doesn't begin with the bytes committed.
+    pub fn import_fvecs_resumable(
+        &mut self,
+        r: &mut dyn Read,
+        session: &mut ImportSession,
+        mapping_out: Option<&mut dyn Write>,
+    ) -> Result<Vec<DbIndex>, Error> {
+        let committed = session.progress;
+        let mut hashed = Hashed { r, bytes: 0, crc: 0 };
+        let skipped = io::copy(&mut (&mut hashed).take(committed.bytes), &mut io::sink())
+            .map_err(Error::IO)?;
+        let record_len = 4 + 4 * self.dim_size() as u64;
+        if skipped != committed.bytes
+            || hashed.crc != committed.crc
+            || committed.records * record_len != committed.bytes
+        {
+            return Err(Error::ImportSourceChanged(committed));
+        }
+        let batch = session.batch;
+        let first_row = committed.records;
+        self.import_fvecs_batches(&mut hashed, (batch, first_row), mapping_out, |db, hashed| {
+            db.sync()?;
+            let records = hashed.bytes / record_len;
+            session.commit(ImportProgress {
+                records,
+                bytes: hashed.bytes,
+                crc: hashed.crc,
+            })?;
+            session.cancel.check(records)
+        })
+    }
+
+    /// Reads and pushes `batch` vectors at a time out of `r`, the first
+    /// being row `first_row` of the source, writing their mapping if asked
+    /// to and calling `committed` with `r` after each batch is pushed.
+    fn import_fvecs_batches<R: Read>(
+        &mut self,
+        mut r: R,
+        (batch, first_row): (usize, u64),
+        mut mapping_out: Option<&mut dyn Write>,
+        mut committed: impl FnMut(&Database, &R) -> Result<(), Error>,
+    ) -> Result<Vec<DbIndex>, Error> {
+        let dim_size = self.dim_size();
+        let mut ids = vec![];
+        let mut vectors = Vec::<DbVector>::with_capacity(batch);
+        let mut pushed = |db: &mut Database, vectors: &mut Vec<DbVector>, ids: &mut Vec<DbIndex>| {
+            let first = ids.len();
+            ids.extend(db.push_many(vectors.drain(..))?);
+            if let Some(out) = mapping_out.as_mut() {
+                write_mapping(out, first_row + first as u64, &ids[first..]).map_err(Error::IO)?;
+            }
+            Ok::<_, Error>(())
+        };
+        loop {
+            let len = match r.read_i32::<LittleEndian>() {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(Error::IO(e)),
+            };
+            if len != dim_size as i32 {
+                return Err(Error::Dimension(dim_size, len.max(0) as usize));
+            }
+            let mut vector = vec![0f32; dim_size as usize];
+            r.read_f32_into::<LittleEndian>(&mut vector)
+                .map_err(Error::IO)?;
+            vectors.push(vector);
+            if vectors.len() == batch {
+                pushed(self, &mut vectors, &mut ids)?;
+                committed(self, &r)?;
+            }
+        }
+        if !vectors.is_empty() {
+            pushed(self, &mut vectors, &mut ids)?;
+            committed(self, &r)?;
+        }
+        Ok(ids)
+    }
+}
+
+/// Writes `source_row,assigned_id` for each of `ids`, rows counting up
+/// from `first_row`, then flushes.
+fn write_mapping(out: &mut dyn Write, first_row: u64, ids: &[DbIndex]) -> io::Result<()> {
+    for (row, id) in (first_row..).zip(ids) {
+        writeln!(out, "{row},{id}")?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{CancelToken, DatabaseOptions, Error, ImportProgress, ImportSession};
+    use crate::ext::mem::SharedCursor;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use std::io;
+    use std::io::{Cursor, ErrorKind, Read};
+
+    /// Reads through `left` bytes of `r` and fails from then on,
+    /// as if the import was cancelled.
+    struct Cancelled<R> {
+        r: R,
+        left: usize,
+    }
+
+    impl<R: Read> Read for Cancelled<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::other("cancelled"));
+            }
+            let len = buf.len().min(self.left);
+            let read = self.r.read(&mut buf[..len])?;
+            self.left -= read;
+            Ok(read)
+        }
+    }
+
+    fn fvecs(vectors: &[Vec<f32>]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for vector in vectors {
+            bytes.write_i32::<LittleEndian>(vector.len() as i32).unwrap();
+            for x in vector {
+                bytes.write_f32::<LittleEndian>(*x).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn import_fvecs_works() {
+        let mut db = DatabaseOptions::new(3)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let vectors = (0..2500).map(|i| vec![i as f32; 3]).collect::<Vec<_>>();
+        let ids = db.import_fvecs(&mut Cursor::new(fvecs(&vectors)), None).unwrap();
+        assert_eq!(ids, (0..2500).collect::<Vec<_>>());
+        assert_eq!(*db.get(2499).unwrap().unwrap(), vectors[2499]);
+        assert!(db.import_fvecs(&mut Cursor::new(vec![]), None).unwrap().is_empty());
+
+        let wrong = fvecs(&[vec![0f32; 3], vec![0f32; 4]]);
+        assert!(matches!(
+            db.import_fvecs(&mut Cursor::new(wrong), None),
+            Err(Error::Dimension(3, 4))
+        ));
+        let mut cut = fvecs(&[vec![0f32; 3]]);
+        cut.pop();
+        assert!(matches!(
+            db.import_fvecs(&mut Cursor::new(cut), None),
+            Err(Error::IO(e)) if e.kind() == ErrorKind::UnexpectedEof
+        ));
+        assert_eq!(db.count().unwrap(), 2500);
+    }
+
+    #[test]
+    fn resumable_import_works() {
+        let mut db = DatabaseOptions::new(3)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let vectors = (0..2500).map(|i| vec![i as f32; 3]).collect::<Vec<_>>();
+        let source = fvecs(&vectors);
+        let sidecar = SharedCursor::new();
+
+        // cut off halfway through record 1234
+        let mut session = ImportSession::open(Box::new(sidecar.reopen()))
+            .unwrap()
+            .batch_size(100);
+        let mut cancelled = Cancelled {
+            r: Cursor::new(&source),
+            left: 1234 * 16 + 8,
+        };
+        assert!(matches!(
+            db.import_fvecs_resumable(&mut cancelled, &mut session, None),
+            Err(Error::IO(e)) if e.kind() == ErrorKind::Other
+        ));
+        assert_eq!(session.progress().records, 1200);
+        assert_eq!(db.count().unwrap(), 1200);
+        drop(session);
+
+        // a source other than the one cut off is refused
+        let mut changed = source.clone();
+        changed[4 * 16 + 5] ^= 1;
+        let mut session = ImportSession::open(Box::new(sidecar.reopen())).unwrap();
+        assert!(matches!(
+            db.import_fvecs_resumable(&mut Cursor::new(&changed), &mut session, None),
+            Err(Error::ImportSourceChanged(ImportProgress { records: 1200, .. }))
+        ));
+        assert!(matches!(
+            db.import_fvecs_resumable(&mut Cursor::new(&source[..1000]), &mut session, None),
+            Err(Error::ImportSourceChanged(_))
+        ));
+        assert_eq!(db.count().unwrap(), 1200);
+
+        let ids = db
+            .import_fvecs_resumable(&mut Cursor::new(&source), &mut session, None)
+            .unwrap();
+        assert_eq!(ids, (1200..2500).collect::<Vec<_>>());
+        assert_eq!(session.progress().records, 2500);
+        assert_eq!(db.count().unwrap(), 2500);
+        for (id, vector) in vectors.iter().enumerate() {
+            assert_eq!(*db.get(id as u32).unwrap().unwrap(), *vector);
+        }
+        // nothing's left to import
+        let mut session = ImportSession::open(Box::new(sidecar.reopen())).unwrap();
+        let ids = db
+            .import_fvecs_resumable(&mut Cursor::new(&source), &mut session, None)
+            .unwrap();
+        assert!(ids.is_empty());
+        assert_eq!(db.count().unwrap(), 2500);
+
+        // cancelled, it commits the batch underway and stops
+        let mut db = DatabaseOptions::new(3)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let sidecar = SharedCursor::new();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let mut session = ImportSession::open(Box::new(sidecar.reopen()))
+            .unwrap()
+            .batch_size(100)
+            .cancel_token(cancel);
+        assert!(matches!(
+            db.import_fvecs_resumable(&mut Cursor::new(&source), &mut session, None),
+            Err(Error::Cancelled { progress: 100 })
+        ));
+        assert_eq!(db.count().unwrap(), 100);
+        let mut session = ImportSession::open(Box::new(sidecar.reopen())).unwrap();
+        let ids = db
+            .import_fvecs_resumable(&mut Cursor::new(&source), &mut session, None)
+            .unwrap();
+        assert_eq!(ids.len(), 2400);
+
+        assert!(ImportSession::open(Box::new(Cursor::new(vec![1u8; 28]))).is_err());
+    }
+
+    #[test]
+    fn import_mapping_works() {
+        let mut db = DatabaseOptions::new(3)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        db.push([9f32; 3]).unwrap();
+        let mut vectors = (0..7).map(|i| vec![i as f32; 3]).collect::<Vec<_>>();
+        vectors[5] = vec![0f32; 4];
+        let sidecar = SharedCursor::new();
+        let mut session = ImportSession::open(Box::new(sidecar.reopen()))
+            .unwrap()
+            .batch_size(2);
+
+        // rows 4 and 5 are pushed together, so the failing one takes 4 with it
+        let mut mapping = vec![];
+        let source = fvecs(&vectors);
+        assert!(matches!(
+            db.import_fvecs_resumable(&mut Cursor::new(source), &mut session, Some(&mut mapping)),
+            Err(Error::Dimension(3, 4))
+        ));
+        assert_eq!(String::from_utf8(mapping).unwrap(), "0,1\n1,2\n2,3\n3,4\n");
+
+        // resumed past the committed rows, numbered from the beginning
+        let mut source = fvecs(&vectors[..5]);
+        source.extend(fvecs(&[vec![5f32; 3], vec![6f32; 3]]));
+        let mut mapping = vec![];
+        db.import_fvecs_resumable(&mut Cursor::new(source), &mut session, Some(&mut mapping))
+            .unwrap();
+        assert_eq!(String::from_utf8(mapping).unwrap(), "4,5\n5,6\n6,7\n");
+        assert_eq!(*db.get(7).unwrap().unwrap(), vec![6f32; 3]);
+
+        let mut mapping = vec![];
+        let ids = db
+            .import_fvecs(&mut Cursor::new(fvecs(&vectors[..2])), Some(&mut mapping))
+            .unwrap();
+        assert_eq!(ids, vec![8, 9]);
+        assert_eq!(String::from_utf8(mapping).unwrap(), "0,8\n1,9\n");
+    }
+}
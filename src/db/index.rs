@@ -0,0 +1,146 @@
+#[cfg(feature = "rayon")]
+use crate::db::{HnswConfig, SearchParams};
+use crate::db::{CancelToken, Database, DbIndex, DbVectorSlice, Error};
+use crate::ext::semaphore::LockAutoClear;
+use crate::index::{BuildStats, HnswIndex};
+
+impl Database {
+    /// Builds an [HnswIndex] over the stored vectors, which queries go through
+    /// from then on instead of scanning the file. It's kept in memory and up to
+    /// date with later writes, but not persisted, so it's built again once opened.
+    /// Returns the work it took.
+    pub fn build_index(&self) -> Result<BuildStats, Error> {
+        self.build_index_cancellable(&CancelToken::new())
+    }
+
+    /// Same as [Database::build_index], checking `cancel` before each
+    /// vector inserted. Once it's cancelled, the index being built is let
+    /// go, leaving the one there was if any, and it fails with
+    /// [Error::Cancelled] holding the vectors inserted up to then.
+    pub fn build_index_cancellable(&self, cancel: &CancelToken) -> Result<BuildStats, Error> {
+        let mut handle = self.handle.lock_auto_clear_poison();
+        let mut index = HnswIndex::new(handle.dim_size, self.metric, self.config());
+        let records = handle.read_all()?;
+        index.reserve(records.len());
+        self.building.start(records.len() as u64);
+        for (inserted, (id, vector)) in records.into_iter().enumerate() {
+            let inserted = cancel
+                .check(inserted as u64)
+                .and_then(|_| index.insert(id, &vector));
+            if let Err(e) = inserted {
+                self.building.finish();
+                return Err(e);
+            }
+            self.building.advance();
+        }
+        let stats = index.build_stats();
+        *self.index.lock_auto_clear_poison() = Some(index);
+        self.building.finish();
+        self.forget_shortcuts();
+        self.invalidate_views();
+        self.forget_results();
+        Ok(stats)
+    }
+
+    /// Same as [Database::build_index] on `threads` threads, building with
+    /// `config` from then on, see [HnswIndex::build_parallel].
+    #[cfg(feature = "rayon")]
+    pub fn build_index_parallel(
+        &mut self,
+        config: HnswConfig,
+        threads: usize,
+    ) -> Result<BuildStats, Error> {
+        let mut handle = self.handle.lock_auto_clear_poison();
+        let records = handle.read_all()?;
+        self.building.start(records.len() as u64);
+        let index =
+            HnswIndex::build_parallel(handle.dim_size, self.metric, config, &records, threads);
+        self.building.finish();
+        let index = index?;
+        self.config = config;
+        self.set_search_params(SearchParams {
+            ef_search: config.ef_search,
+            ..self.search_params()
+        });
+        let stats = index.build_stats();
+        *self.index.lock_auto_clear_poison() = Some(index);
+        self.forget_shortcuts();
+        self.invalidate_views();
+        self.forget_results();
+        Ok(stats)
+    }
+
+    /// Goes back to scanning the file on every query.
+    pub fn drop_index(&self) {
+        *self.index.lock_auto_clear_poison() = None;
+        self.forget_shortcuts();
+        self.invalidate_views();
+        self.forget_results();
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.index.lock_auto_clear_poison().is_some()
+    }
+
+    /// Reconnects layer `level` of the index alone, for when it's been damaged,
+    /// see [HnswIndex::rebuild_layer].
+    pub fn rebuild_layer(&mut self, level: u32) -> Result<(), Error> {
+        let rebuilt = match self.index.lock_auto_clear_poison().as_mut() {
+            Some(index) => index.rebuild_layer(level),
+            None => Err(Error::NoLayer(level)),
+        };
+        self.invalidate_views();
+        self.forget_results();
+        rebuilt
+    }
+
+    /// Ids the index holds whose vectors aren't stored, in ascending order,
+    /// as when records were removed behind its back or cut off the file.
+    /// Queries leave them out of the results, while [building](Database::build_index)
+    /// the index anew gets rid of them. Along with them go the ids the
+    /// [level table](crate::db::DatabaseOptions::level_table) puts on another
+    /// layer than the index does, which the next [flush](Database::flush)
+    /// writes over. Empty if there's no index.
+    pub fn verify(&self) -> Vec<DbIndex> {
+        let (ids, misplaced) = match self.index.lock_auto_clear_poison().as_ref() {
+            Some(index) => (index.ids().collect::<Vec<_>>(), self.misplaced_levels(index)),
+            None => return vec![],
+        };
+        let mut handle = self.handle.lock_auto_clear_poison();
+        let mut found = ids
+            .into_iter()
+            .filter(|id| !handle.contains(*id))
+            .chain(misplaced)
+            .collect::<Vec<_>>();
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    /// Nodes are numbered anew along with the index.
+    pub(crate) fn forget_shortcuts(&self) {
+        if let Some(shortcuts) = &self.shortcuts {
+            shortcuts.lock_auto_clear_poison().clear();
+        }
+    }
+
+    pub(crate) fn index_insert(&self, id: DbIndex, vector: DbVectorSlice) -> Result<(), Error> {
+        match self.index.lock_auto_clear_poison().as_mut() {
+            Some(index) => index.insert(id, vector),
+            None => Ok(()),
+        }
+    }
+
+    /// Lets the index make room for `additional` vectors about to be pushed.
+    pub(crate) fn index_reserve(&self, additional: usize) {
+        if let Some(index) = self.index.lock_auto_clear_poison().as_mut() {
+            index.reserve(additional);
+        }
+    }
+
+    pub(crate) fn index_remove(&self, id: DbIndex) {
+        if let Some(index) = self.index.lock_auto_clear_poison().as_mut() {
+            index.remove(id);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use crate::db::{Database, DbIndex};
+use crate::ds::layer::HnswLayer;
+use crate::ext::semaphore::LockAutoClear;
+use std::collections::BTreeMap;
+
+/// # Layer View
+/// Read-only copy of one index layer, its nodes being the ids of the
+/// vectors they stand for, see [Database::layers].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerView {
+    level: u32,
+    /// Neighbors of every node on the layer, ascending by id.
+    adjacency: BTreeMap<DbIndex, Vec<(DbIndex, f32)>>,
+}
+
+impl LayerView {
+    /// Copies `layer`, keeping the nodes `id` maps to an id, those left out
+    /// of the layer or removed being mapped to `None`.
+    fn new(layer: &HnswLayer, id: impl Fn(u32) -> Option<DbIndex>) -> LayerView {
+        let mut adjacency = BTreeMap::new();
+        for node in layer.nodes() {
+            let Some(from) = id(node) else {
+                continue;
+            };
+            let mut neighbors = layer
+                .vertices(node)
+                .into_iter()
+                .filter(|(neighbor, _)| *neighbor != node)
+                .filter_map(|(neighbor, distance)| Some((id(neighbor)?, distance)))
+                .collect::<Vec<_>>();
+            neighbors.sort_by_key(|(neighbor, _)| *neighbor);
+            adjacency.insert(from, neighbors);
+        }
+        LayerView {
+            level: layer.level(),
+            adjacency,
+        }
+    }
+
+    /// Copies the part of `layer` within `hops` links of `root`, keeping the
+    /// nodes `id` maps to an id like [LayerView::new]. Links between two nodes
+    /// `hops` away from it are kept too.
+    fn around(
+        layer: &HnswLayer,
+        (root, hops): (u32, u32),
+        id: impl Fn(u32) -> Option<DbIndex>,
+    ) -> LayerView {
+        let (edges, nodes) = layer.neighborhood(root, hops);
+        let mut adjacency = BTreeMap::<_, Vec<_>>::from_iter(
+            nodes.into_iter().filter_map(|node| Some((id(node)?, vec![]))),
+        );
+        for (a, b, distance) in edges {
+            let (Some(a), Some(b)) = (id(a), id(b)) else {
+                continue;
+            };
+            if a != b {
+                adjacency.entry(a).or_default().push((b, distance));
+                adjacency.entry(b).or_default().push((a, distance));
+            }
+        }
+        for neighbors in adjacency.values_mut() {
+            neighbors.sort_by_key(|(neighbor, _)| *neighbor);
+        }
+        LayerView {
+            level: layer.level(),
+            adjacency,
+        }
+    }
+
+    /// Of the layer, zero being the base.
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.adjacency.len()
+    }
+
+    /// Every node on the layer, ascending.
+    pub fn nodes(&self) -> impl Iterator<Item = DbIndex> + '_ {
+        self.adjacency.keys().copied()
+    }
+
+    /// Nodes linked to `id` and their distances, ascending by id,
+    /// or none if it's not on the layer.
+    pub fn neighbors(&self, id: DbIndex) -> Vec<(DbIndex, f32)> {
+        self.adjacency.get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Every link once, as the lesser id, the greater one and their distance,
+    /// links going both ways.
+    pub fn edges(&self) -> impl Iterator<Item = (DbIndex, DbIndex, f32)> + '_ {
+        self.adjacency.iter().flat_map(|(from, neighbors)| {
+            neighbors
+                .iter()
+                .filter(move |(to, _)| from < to)
+                .map(move |(to, distance)| (*from, *to, *distance))
+        })
+    }
+}
+
+impl Database {
+    /// Copies of the index layers from the base up, or in the order they are
+    /// stored if the index isn't [built](Database::build_index) in memory.
+    /// They're taken when called, so they may be held while the database is
+    /// read and written, which they don't follow.
+    ///
+    /// For statistics not in [Database::index_diagnostics], such as
+    /// a histogram of degrees:
+    /// ```
+    /// use std::io::Cursor;
+    /// use vectoria::db::DatabaseOptions;
+    ///
+    /// let mut db = DatabaseOptions::new(2)
+    ///     .create("mem", Box::new(Cursor::new(Vec::new())))
+    ///     .unwrap();
+    /// for i in 0..100 {
+    ///     db.push([i as f32, (i * i % 17) as f32]).unwrap();
+    /// }
+    /// db.build_index().unwrap();
+    ///
+    /// let base = db.layers().next().unwrap();
+    /// let mut degrees = vec![0usize; 1];
+    /// for id in base.nodes() {
+    ///     let degree = base.neighbors(id).len();
+    ///     if degrees.len() <= degree {
+    ///         degrees.resize(degree + 1, 0);
+    ///     }
+    ///     degrees[degree] += 1;
+    /// }
+    /// assert_eq!(degrees.iter().sum::<usize>(), base.node_count());
+    /// let ends = degrees.iter().enumerate().map(|(d, n)| d * n).sum::<usize>();
+    /// assert_eq!(ends, 2 * base.edges().count());
+    /// ```
+    pub fn layers(&self) -> impl Iterator<Item = LayerView> {
+        let views = match self.index.lock_auto_clear_poison().as_ref() {
+            Some(index) => index
+                .layers()
+                .iter()
+                .map(|layer| {
+                    LayerView::new(layer, |node| {
+                        let id = index.id_at(node)?;
+                        (index.level_of(node) >= layer.level()).then_some(id)
+                    })
+                })
+                .collect::<Vec<_>>(),
+            None => self
+                .layers
+                .iter()
+                .map(|layer| LayerView::new(layer, Some))
+                .collect(),
+        };
+        views.into_iter()
+    }
+
+    /// Copy of the part of the layer at `level` within `hops` links of `id`,
+    /// e.g. to draw the index around it, or `None` if it's not on the layer.
+    /// Taken from the index [built](Database::build_index) in memory, or from
+    /// the stored layers otherwise, like [Database::layers].
+    pub fn neighborhood(&self, level: u32, id: DbIndex, hops: u32) -> Option<LayerView> {
+        match self.index.lock_auto_clear_poison().as_ref() {
+            Some(index) => {
+                let node = index.node_of(id).filter(|node| index.level_of(*node) >= level)?;
+                let layer = index.layers().iter().find(|layer| layer.level() == level)?;
+                Some(LayerView::around(layer, (node, hops), |node| {
+                    let id = index.id_at(node)?;
+                    (index.level_of(node) >= level).then_some(id)
+                }))
+            }
+            None => {
+                let layer = self.layers.iter().find(|layer| layer.level() == level)?;
+                layer
+                    .nodes()
+                    .contains(&id)
+                    .then(|| LayerView::around(layer, (id, hops), Some))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, HnswConfig};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::ext::semaphore::LockAutoClear;
+    use std::collections::BTreeSet;
+    use std::io::Cursor;
+
+    #[test]
+    fn layers_work() {
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        assert_eq!(db.layers().count(), 0);
+        let mut rng = XorShift::new(41);
+        for _ in 0..300 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        db.remove(7).unwrap();
+        db.build_index().unwrap();
+        let views = db.layers().collect::<Vec<_>>();
+
+        let guard = db.index.lock_auto_clear_poison();
+        let index = guard.as_ref().unwrap();
+        assert_eq!(views.len(), index.layers().len());
+        for (view, layer) in views.iter().zip(index.layers()) {
+            assert_eq!(view.level(), layer.level());
+            let mut expected = BTreeSet::new();
+            for node in layer.nodes() {
+                for (neighbor, _) in layer.vertices(node) {
+                    let (a, b) = (index.id_at(node).unwrap(), index.id_at(neighbor).unwrap());
+                    expected.insert((a.min(b), a.max(b)));
+                }
+            }
+            let edges = view.edges().map(|(a, b, _)| (a, b)).collect::<BTreeSet<_>>();
+            assert_eq!(edges, expected);
+            assert_eq!(view.edges().count(), expected.len());
+            for (a, b, distance) in view.edges() {
+                assert!(view.neighbors(b).contains(&(a, distance)));
+            }
+        }
+        drop(guard);
+        assert_eq!(views[0].node_count(), 299);
+        assert!(views[0].neighbors(7).is_empty());
+        // the views stay as they were
+        db.push([0f32; 4]).unwrap();
+        assert_eq!(views[0].node_count(), 299);
+        assert_eq!(db.layers().next().unwrap().node_count(), 300);
+    }
+
+    #[test]
+    fn neighborhood_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4).create("mem", Box::new(file.reopen())).unwrap();
+        let mut rng = XorShift::new(42);
+        for _ in 0..300 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        assert_eq!(db.neighborhood(0, 5, 1), None);
+        db.remove(7).unwrap();
+        db.build_index().unwrap();
+        let base = db.layers().next().unwrap();
+
+        let near = db.neighborhood(0, 5, 1).unwrap();
+        let mut expected = base.neighbors(5).iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        expected.push(5);
+        expected.sort();
+        assert_eq!(near.nodes().collect::<Vec<_>>(), expected);
+        assert_eq!(near.neighbors(5), base.neighbors(5));
+        // every link among them, those between two neighbors of 5 included
+        for (a, b, distance) in base.edges() {
+            let inside = expected.contains(&a) && expected.contains(&b);
+            assert_eq!(near.neighbors(a).contains(&(b, distance)), inside);
+        }
+        let alone = db.neighborhood(0, 5, 0).unwrap();
+        assert_eq!(alone.nodes().collect::<Vec<_>>(), vec![5]);
+        assert!(alone.neighbors(5).is_empty());
+        assert!(db.neighborhood(0, 5, 300).unwrap().node_count() == base.node_count());
+        assert_eq!(db.neighborhood(0, 7, 1), None);
+        let top = db.layers().last().unwrap();
+        let above = (0..300).find(|id| !top.nodes().any(|node| node == *id)).unwrap();
+        assert_eq!(db.neighborhood(top.level(), above, 1), None);
+
+        // the same out of the stored layers
+        db.flush().unwrap();
+        let reopened = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(reopened.neighborhood(0, 5, 1), Some(near));
+    }
+
+    #[test]
+    fn pushes_grow_the_layers() {
+        let build = |level_multiplier| {
+            let mut db = DatabaseOptions::new(4)
+                .hnsw(HnswConfig {
+                    m: 4,
+                    level_multiplier,
+                    ..HnswConfig::default()
+                })
+                .create("mem", Box::new(Cursor::new(Vec::new())))
+                .unwrap();
+            db.build_index().unwrap();
+            let mut rng = XorShift::new(43);
+            for _ in 0..400 {
+                db.push(rng.vector(4)).unwrap();
+            }
+            db.layers().map(|view| view.node_count()).collect::<Vec<_>>()
+        };
+        let counts = build(None);
+        assert_eq!(counts[0], 400);
+        assert!(counts.len() > 2, "{counts:?}");
+        assert!(counts.windows(2).all(|pair| pair[0] > pair[1]), "{counts:?}");
+        // about a quarter make it up each level with m of 4
+        assert!((60..140).contains(&counts[1]), "{counts:?}");
+
+        let taller = build(Some(2.0));
+        assert_eq!(taller[0], 400);
+        assert!(taller.len() > counts.len(), "{taller:?}");
+        assert!(build(Some(1e-6)).len() == 1);
+    }
+}
@@ -0,0 +1,387 @@
+use crate::db::history::{RECORD_PREFIX, REMOVED};
+use crate::db::{Anomaly, CancelToken, Database, Error, VectorHandle};
+use crate::ds::layer::HnswLayer;
+use crate::ext::semaphore::LockAutoClear;
+use crate::vio;
+use crate::vio::dbheader::DbHeader;
+use crate::vio::format::HEADER_SLOTS_SINCE;
+use crate::vio::RandomAccess;
+use std::cmp::min;
+use std::io;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+/// # Verify Level
+/// How far [Database::verify_level] goes through the file, each level
+/// checking what the ones before it do and more, the slower for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VerifyLevel {
+    /// Reads the header, checking its slots against their checksums and the
+    /// records against the length of the file. It's run on every open,
+    /// which fails with [Error::Corrupted] over records that don't fill
+    /// the data section in whole unless [unchecked](Database::read_unchecked).
+    Quick,
+    /// Reads the prefix of every record, checking their ids ascend,
+    /// or their generations in append-only files, and parses the stored layers.
+    Standard,
+    /// Reads every record in whole, checking their components are finite,
+    /// and looks up the nodes of the stored layers and the index among them.
+    /// Records carry no checksums, so damage to their components is only
+    /// found where it leaves them non-finite.
+    Deep,
+}
+
+/// [Anomaly::TornHeaderSlot] for the slots of a `version` header that
+/// didn't pass their checksums, reading them from `fd`.
+pub(crate) fn torn_slots(fd: &mut dyn RandomAccess, version: u8) -> io::Result<Vec<Anomaly>> {
+    if version < HEADER_SLOTS_SINCE {
+        return Ok(vec![]);
+    }
+    Ok(vio::dbheader::read_slots(fd)?
+        .into_iter()
+        // the second slot is left zeroed until the first rewrite
+        .filter(|slot| !slot.valid && slot.bytes.iter().any(|b| *b != 0))
+        .map(|slot| Anomaly::TornHeaderSlot {
+            offset: slot.offset,
+        })
+        .collect())
+}
+
+/// [Anomaly::DanglingLayerNodes] for each of `layers` with nodes
+/// whose records `handle` doesn't hold.
+pub(super) fn dangling_layer_nodes<'a>(
+    layers: impl IntoIterator<Item = &'a HnswLayer>,
+    handle: &mut VectorHandle,
+) -> Vec<Anomaly> {
+    let mut anomalies = vec![];
+    for layer in layers {
+        let nodes = layer
+            .nodes()
+            .into_iter()
+            .filter(|node| !layer.vertices(*node).is_empty() && !handle.contains(*node))
+            .collect::<Vec<_>>();
+        if !nodes.is_empty() {
+            anomalies.push(Anomaly::DanglingLayerNodes {
+                level: layer.level(),
+                nodes,
+            });
+        }
+    }
+    anomalies
+}
+
+impl VectorHandle {
+    /// [Anomaly::TrailingBytes] if the records don't fill the data section in whole.
+    fn trailing_bytes(&mut self) -> Result<Option<Anomaly>, Error> {
+        let len = self.len()?;
+        if self.store.is_some() || len <= self.data_section {
+            return Ok(None);
+        }
+        let trailing = (len - self.data_section) % self.unit_size_bytes();
+        Ok((trailing > 0).then_some(Anomaly::TrailingBytes {
+            offset: len - trailing,
+            len: trailing,
+        }))
+    }
+
+    /// Goes through the records in file order, checking they ascend,
+    /// and if `deep`, that their components are finite.
+    fn scan_records(
+        &mut self,
+        header: &DbHeader,
+        deep: bool,
+        cancel: &CancelToken,
+    ) -> Result<Vec<Anomaly>, Error> {
+        if self.store.is_some() {
+            return Ok(vec![]);
+        }
+        let unit = self.unit_size_bytes();
+        let count = self.len()?.saturating_sub(self.data_section) / unit;
+        let prefix = match self.history {
+            Some(_) => RECORD_PREFIX,
+            None => size_of::<u32>() as u64,
+        };
+        // components are left alone unless deep
+        let checked = if deep { self.record_size_bytes() } else { prefix };
+        self.fd
+            .seek(SeekFrom::Start(self.data_section))
+            .map_err(Error::IO)?;
+        let mut reader = BufReader::new(&mut self.fd);
+        let mut record = vec![0u8; unit as usize];
+        let mut anomalies = vec![];
+        let mut last = None;
+        for index in 0..count {
+            cancel.check(index)?;
+            let offset = self.data_section + index * unit;
+            reader.read_exact(&mut record).map_err(Error::IO)?;
+            let id = u32::from_be_bytes(record[..4].try_into().unwrap());
+            let key = match self.history {
+                Some(_) => u64::from_be_bytes(record[4..12].try_into().unwrap()) & !REMOVED,
+                None => id as u64,
+            };
+            // slots reused are written into with generations out of order
+            if last.is_some_and(|last| key <= last) && !header.reuse_slots {
+                anomalies.push(Anomaly::RecordOutOfOrder { offset, id });
+            } else {
+                last = Some(key);
+            }
+            let finite = record[prefix as usize..checked as usize]
+                .chunks_exact(size_of::<f32>())
+                .all(|c| f32::from_be_bytes(c.try_into().unwrap()).is_finite());
+            if !finite {
+                anomalies.push(Anomaly::NonFiniteRecord { offset, id });
+            }
+        }
+        Ok(anomalies)
+    }
+}
+
+/// [Anomaly::MalformedLayer] for the first stored layer of the file
+/// `header` heads that doesn't parse or runs into the data section.
+fn malformed_layer(fd: &mut dyn RandomAccess, header: &DbHeader) -> Result<Option<Anomaly>, Error> {
+    let len = fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+    let end = min(header.data_section, len);
+    let begin = fd
+        .seek(SeekFrom::Start(header.prefix_end()))
+        .map_err(Error::IO)?;
+    let mut section = match vio::layer::Section::begin(fd, header.version, end) {
+        Ok(section) => section,
+        Err(vio::Error::IO(e)) if e.kind() == io::ErrorKind::InvalidData => {
+            return Ok(Some(Anomaly::MalformedLayer {
+                offset: begin,
+                len: end - begin,
+            }));
+        }
+        Err(vio::Error::IO(e)) => return Err(Error::IO(e)),
+        Err(vio::Error::Eof) => return Ok(None),
+    };
+    let mut offset = fd.stream_position().map_err(Error::IO)?;
+    loop {
+        match section.next(fd) {
+            Ok(_) => {
+                let next = fd.stream_position().map_err(Error::IO)?;
+                if next > header.data_section {
+                    return Ok(Some(Anomaly::MalformedLayer {
+                        offset,
+                        len: next - offset,
+                    }));
+                }
+                offset = next;
+            }
+            Err(vio::Error::Eof) => break,
+            Err(vio::Error::IO(e))
+                if matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof) =>
+            {
+                return Ok(Some(Anomaly::MalformedLayer {
+                    offset,
+                    len: end - offset,
+                }));
+            }
+            Err(vio::Error::IO(e)) => return Err(Error::IO(e)),
+        }
+    }
+    Ok(None)
+}
+
+impl Database {
+    /// Goes through the file as far as `level` has it, returning the
+    /// anomalies found in the order they were come across. Unlike opening,
+    /// it doesn't stop at the first one, each carrying its
+    /// [severity](Anomaly::severity) and [where it is](Anomaly::offset).
+    pub fn verify_level(&self, level: VerifyLevel) -> Result<Vec<Anomaly>, Error> {
+        self.verify_level_cancellable(level, &CancelToken::new())
+    }
+
+    /// Same as [Database::verify_level], checking `cancel` before each
+    /// record gone through, and failing with [Error::Cancelled] holding
+    /// how many were once it's cancelled.
+    pub fn verify_level_cancellable(
+        &self,
+        level: VerifyLevel,
+        cancel: &CancelToken,
+    ) -> Result<Vec<Anomaly>, Error> {
+        // looked up before the handle is held, which the index is locked after
+        let dangling = match level {
+            VerifyLevel::Deep => self.verify(),
+            _ => vec![],
+        };
+        let mut handle = self.handle.lock_auto_clear_poison();
+        handle.fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        let header = vio::dbheader::read(&mut handle.fd).map_err(Error::Header)?;
+        let mut anomalies = torn_slots(&mut handle.fd, header.version).map_err(Error::IO)?;
+        anomalies.extend(handle.trailing_bytes()?);
+        if level == VerifyLevel::Quick {
+            return Ok(anomalies);
+        }
+        anomalies.extend(malformed_layer(&mut handle.fd, &header)?);
+        anomalies.extend(handle.scan_records(&header, level == VerifyLevel::Deep, cancel)?);
+        let unfilled = handle.inherited_unfilled();
+        if !unfilled.is_empty() {
+            anomalies.push(Anomaly::UnfilledSlots { ids: unfilled });
+        }
+        if level == VerifyLevel::Deep {
+            anomalies.extend(dangling_layer_nodes(&self.layers, &mut handle));
+            if !dangling.is_empty() {
+                anomalies.push(Anomaly::DanglingIndexNodes { nodes: dangling });
+            }
+        }
+        Ok(anomalies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        Anomaly, Database, DatabaseOptions, Error, IdStrategy, Quota, SearchParams, Severity,
+        SuggestedAction,
+        VerifyLevel,
+    };
+    use crate::ext::mem::SharedCursor;
+    use crate::vio::dbheader::DbHeader;
+    use crate::vio::format;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use std::io::{Seek, SeekFrom, Write};
+
+    /// Anomalies found at each level, least thorough first.
+    fn levels(db: &Database) -> [Vec<Anomaly>; 3] {
+        [VerifyLevel::Quick, VerifyLevel::Standard, VerifyLevel::Deep]
+            .map(|level| db.verify_level(level).unwrap())
+    }
+
+    fn five_records() -> SharedCursor {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..5 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        db.flush().unwrap();
+        file
+    }
+
+    /// Overwrites the bytes at `offset` from the end of `file`.
+    fn damage(file: &SharedCursor, offset: i64, bytes: &[u8]) {
+        let mut fd = file.reopen();
+        fd.seek(SeekFrom::End(offset)).unwrap();
+        fd.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn verify_levels_work() {
+        let file = five_records();
+        let db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(levels(&db), [vec![], vec![], vec![]]);
+        // the first slot is superseded by the second
+        db.set_search_params(SearchParams {
+            ef_search: 7,
+            ..db.search_params()
+        });
+        db.flush().unwrap();
+        drop(db);
+
+        // only quick on: a record cut off and a header slot torn
+        let len = file.len();
+        damage(&file, 0, &[0; 3]);
+        let mut fd = file.reopen();
+        fd.seek(SeekFrom::Start(200)).unwrap();
+        fd.write_all(&[!file.bytes()[200]]).unwrap();
+        assert!(matches!(
+            DatabaseOptions::default().open("mem", Box::new(file.reopen())),
+            Err(Error::Corrupted(_))
+        ));
+        let db = Database::read_unchecked("mem", Box::new(file.reopen())).unwrap();
+        let quick = vec![
+            Anomaly::TornHeaderSlot { offset: 0 },
+            Anomaly::TrailingBytes { offset: len, len: 3 },
+        ];
+        assert_eq!(levels(&db), [quick.clone(), quick.clone(), quick.clone()]);
+        assert_eq!(quick[1].action(), SuggestedAction::Truncate(len));
+        assert_eq!(quick.iter().map(|a| a.offset()).collect::<Vec<_>>(), [Some(0), Some(len)]);
+
+        // only standard on: an id of a record in the middle out of order,
+        // missed by opening, which looks at the first two
+        let file = five_records();
+        damage(&file, -3 * 20, &1u32.to_be_bytes());
+        let (db, report) = DatabaseOptions::default()
+            .open_with_report("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert!(report.is_clean());
+        let misplaced = Anomaly::RecordOutOfOrder {
+            offset: file.len() - 3 * 20,
+            id: 1,
+        };
+        assert_eq!(misplaced.severity(), Severity::Error);
+        let [quick, standard, deep] = levels(&db);
+        assert!(quick.is_empty());
+        assert_eq!(standard, [misplaced]);
+        assert_eq!(deep, standard);
+
+        // only deep on: a component gone NaN and records gone behind
+        // the index's back
+        let file = five_records();
+        damage(&file, -4, &f32::NAN.to_be_bytes());
+        let db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.build_index().unwrap();
+        db.handle.lock().unwrap().remove(2).unwrap().unwrap();
+        let [quick, standard, deep] = levels(&db);
+        assert!(quick.is_empty() && standard.is_empty());
+        let non_finite = Anomaly::NonFiniteRecord {
+            offset: file.len() - 20,
+            id: 4,
+        };
+        assert_eq!(
+            deep,
+            [non_finite, Anomaly::DanglingIndexNodes { nodes: vec![2] }]
+        );
+    }
+
+    #[test]
+    fn verify_layers_works() {
+        // the second edge of a dense layer lies over the first record,
+        // which parses as one since records are twelve bytes as well
+        let mut header = DbHeader::new(2, IdStrategy::default(), Quota::default());
+        let mut layer = vec![];
+        layer.write_u32::<BigEndian>(1).unwrap();
+        layer.write_u32::<BigEndian>(1).unwrap();
+        layer.write_u8(format::LAYER_ENCODING_DENSE).unwrap();
+        let len = format::DENSE_COUNT_WIDTH + 2 * format::DENSE_EDGE_WIDTH;
+        layer.write_u32::<BigEndian>(len as u32).unwrap();
+        layer.write_u32::<BigEndian>(2).unwrap();
+        layer.write_u32::<BigEndian>(1).unwrap();
+        layer.write_u32::<BigEndian>(2).unwrap();
+        layer.write_f32::<BigEndian>(1f32).unwrap();
+        header.data_section = header.size() + layer.len() as u64;
+        let file = SharedCursor::new();
+        let mut fd = file.reopen();
+        header.write(&mut fd).unwrap();
+        fd.write_all(&layer).unwrap();
+        fd.write_u32::<BigEndian>(0).unwrap();
+        fd.write_f32::<BigEndian>(f32::from_bits(1)).unwrap();
+        fd.write_f32::<BigEndian>(1f32).unwrap();
+        let mut db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([1f32; 2]).unwrap();
+        db.push([2f32; 2]).unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        let (db, report) = DatabaseOptions::default()
+            .open_with_report("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert!(report.is_clean());
+        let [quick, standard, deep] = levels(&db);
+        assert!(quick.is_empty());
+        let malformed = Anomaly::MalformedLayer {
+            offset: header.size() + format::LAYER_COUNT_WIDTH,
+            len: format::LAYER_PREFIX_WIDTH + len,
+        };
+        assert_eq!(malformed.action(), SuggestedAction::Reindex);
+        assert_eq!(standard, [malformed]);
+        assert_eq!(deep, standard);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::db::history::Generation;
+use crate::db::{Database, DbIndex, DbVector, DbVectorSlice, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use byteorder::{BigEndian, ReadBytesExt};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use std::vec;
+
+/// Records read per lock of the database.
+const BATCH: usize = 64;
+
+/// # Iteration Log
+/// Iterators live on the records of a database, and what the records held
+/// before every write since the oldest of them started, see [Database::iter].
+#[derive(Default)]
+pub(crate) struct IterLog {
+    /// Revision of the records each live iterator started at.
+    readers: Vec<u64>,
+    /// Revision of each write while any iterator was live, with the id
+    /// written and what it held before if anything, oldest first.
+    changes: Vec<(u64, DbIndex, Option<DbVector>)>,
+}
+
+impl IterLog {
+    pub(crate) fn is_iterating(&self) -> bool {
+        !self.readers.is_empty()
+    }
+
+    /// Notes what `id` held before the write of `revision`, if anyone is reading.
+    pub(crate) fn record(&mut self, revision: u64, id: DbIndex, prior: Option<DbVectorSlice>) {
+        if self.is_iterating() {
+            self.changes.push((revision, id, prior.map(DbVector::from)));
+        }
+    }
+
+    fn enter(&mut self, revision: u64) {
+        self.readers.push(revision);
+    }
+
+    fn leave(&mut self, revision: u64) {
+        if let Some(at) = self.readers.iter().position(|r| *r == revision) {
+            self.readers.swap_remove(at);
+        }
+        // nobody reads past the writes before the oldest reader started
+        match self.readers.iter().min() {
+            Some(oldest) => self.changes.retain(|(r, _, _)| r > oldest),
+            None => self.changes.clear(),
+        }
+    }
+
+    /// What each id in `(after, until]` written since `revision` held as of it.
+    fn as_of(
+        &self,
+        revision: u64,
+        after: Option<DbIndex>,
+        until: DbIndex,
+    ) -> HashMap<DbIndex, Option<&DbVector>> {
+        let mut held = HashMap::new();
+        for (_, id, prior) in self.changes.iter().filter(|(r, _, _)| *r > revision) {
+            if after.is_none_or(|after| *id > after) && *id <= until {
+                held.entry(*id).or_insert(prior.as_ref());
+            }
+        }
+        held
+    }
+}
+
+/// Where a [RecordIter] goes on from.
+enum Position {
+    /// Ids above this one, if any, of a data section sorted by id.
+    Sorted(Option<DbIndex>),
+    /// Records of an append-only file from `offset` to `end`,
+    /// those present at `generation` that is.
+    Appended {
+        offset: u64,
+        end: u64,
+        generation: Generation,
+    },
+    /// Ids of a [VectorStore](crate::db::VectorStore) from `next` to `len`.
+    Stored { next: DbIndex, len: u64 },
+}
+
+/// # Record Iterator
+/// Every record a database held when [Database::iter] was called, and only
+/// those, however it's written to in the meantime. It's read a batch at a
+/// time, holding the database only for as long as a batch takes.
+pub struct RecordIter {
+    handle: Arc<Mutex<VectorHandle>>,
+    /// Of the records it was created at.
+    revision: u64,
+    position: Position,
+    batch: vec::IntoIter<(DbIndex, DbVector)>,
+    done: bool,
+}
+
+impl Database {
+    /// Iterates over the records as they are now, the write queue drained
+    /// first, in ascending order of id, or the order they were written in if
+    /// the database is append-only. Records pushed afterwards are left out,
+    /// and those updated or removed afterwards are yielded as they were.
+    ///
+    /// [Compacting](Database::compact) fails with [Error::IterationInProgress]
+    /// as long as the iterator lives, as it would take away what's left to read.
+    pub fn iter(&self) -> Result<RecordIter, Error> {
+        self.drain_queue()?;
+        let mut handle = self.handle.lock_auto_clear_poison();
+        let position = if let Some(history) = &handle.history {
+            let generation = history.generation();
+            Position::Appended {
+                offset: handle.data_section,
+                end: handle.data_section + handle.seek_count()? * handle.unit_size_bytes(),
+                generation,
+            }
+        } else if let Some(store) = &handle.store {
+            Position::Stored {
+                next: 0,
+                len: store.len(),
+            }
+        } else {
+            Position::Sorted(None)
+        };
+        let revision = handle.revision;
+        handle.iterators.enter(revision);
+        Ok(RecordIter {
+            handle: self.handle.clone(),
+            revision,
+            position,
+            batch: vec![].into_iter(),
+            done: false,
+        })
+    }
+}
+
+impl RecordIter {
+    /// Reads the next batch, marking the iterator done along with the last one.
+    fn fill(&mut self) -> Result<(), Error> {
+        let mut handle = self.handle.lock_auto_clear_poison();
+        let handle = &mut *handle;
+        let batch = match &mut self.position {
+            Position::Sorted(after) => {
+                let unit = handle.unit_size_bytes();
+                let end = handle.data_section + handle.seek_count()? * unit;
+                let mut pos = match after {
+                    Some(after) => handle.seek_insertion(*after)?,
+                    None => handle.data_section,
+                };
+                let mut records = BTreeMap::new();
+                let mut last = None;
+                while pos < end && records.len() < BATCH {
+                    handle.fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+                    let (id, vector) = handle.read_record()?;
+                    // placeholders are passed over, but read up to all the same
+                    if !handle.is_unfilled(id) {
+                        records.insert(id, vector);
+                    }
+                    last = Some(id);
+                    pos += unit;
+                }
+                self.done = pos >= end;
+                let until = match last {
+                    Some(last) if !self.done => last,
+                    _ => DbIndex::MAX,
+                };
+                for (id, held) in handle.iterators.as_of(self.revision, *after, until) {
+                    match held {
+                        Some(vector) => records.insert(id, vector.clone()),
+                        None => records.remove(&id),
+                    };
+                }
+                *after = Some(until);
+                records.into_iter().collect()
+            }
+            Position::Appended {
+                offset,
+                end,
+                generation,
+            } => {
+                let unit = handle.unit_size_bytes();
+                let history = handle.history.as_ref().unwrap();
+                let mut ids = vec![];
+                while *offset < *end && ids.len() < BATCH {
+                    handle.fd.seek(SeekFrom::Start(*offset)).map_err(Error::IO)?;
+                    let id = handle.fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+                    // left out if superseded as of the generation, or written after it
+                    if history.resolve(id, Some(*generation)) == Some(*offset) {
+                        ids.push(id);
+                    }
+                    *offset += unit;
+                }
+                self.done = *offset >= *end;
+                let mut records = Vec::with_capacity(ids.len());
+                for id in ids {
+                    if let Some(vector) = handle.get_at(id, Some(*generation))? {
+                        records.push((id, vector));
+                    }
+                }
+                records
+            }
+            Position::Stored { next, len } => {
+                let until = (*next as u64 + BATCH as u64).min(*len) as DbIndex;
+                let mut records = Vec::with_capacity((until - *next) as usize);
+                for id in *next..until {
+                    if let Some(vector) = handle.store_get(id)? {
+                        records.push((id, vector));
+                    }
+                }
+                *next = until;
+                self.done = *next as u64 >= *len;
+                records
+            }
+        };
+        self.batch = batch.into_iter();
+        Ok(())
+    }
+}
+
+impl Iterator for RecordIter {
+    type Item = Result<(DbIndex, DbVector), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.batch.next() {
+                return Some(Ok(record));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.fill() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl Drop for RecordIter {
+    fn drop(&mut self) {
+        self.handle
+            .lock_auto_clear_poison()
+            .iterators
+            .leave(self.revision);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, DbIndex, DbVector, Error, IdStrategy};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+    use std::thread;
+
+    fn records(db: &mut Database) -> Vec<(DbIndex, DbVector)> {
+        db.scan_map(|id, vector| (id, vector.to_vec())).unwrap()
+    }
+
+    /// Iterates over `db` while another thread writes to it.
+    fn iterate_while_writing(mut db: Database) -> Database {
+        let mut rng = XorShift::new(37);
+        for _ in 0..300 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        let expected = records(&mut db);
+        let mut iter = db.iter().unwrap();
+        let mut yielded = iter.by_ref().take(100).collect::<Result<Vec<_>, _>>().unwrap();
+        thread::scope(|s| {
+            let db = &mut db;
+            s.spawn(move || {
+                for i in 0..50 {
+                    db.remove(i * 5 + 1).unwrap();
+                    db.push(rng.vector(4)).unwrap();
+                    db.update(i * 5 + 3, [i as f32; 4]).unwrap();
+                }
+            });
+            for record in iter.by_ref() {
+                yielded.push(record.unwrap());
+                thread::yield_now();
+            }
+        });
+        assert_eq!(yielded.len(), expected.len());
+        yielded.sort_by_key(|(id, _)| *id);
+        assert_eq!(yielded, expected);
+        assert_eq!(db.count().unwrap(), 300);
+        drop(iter);
+        db
+    }
+
+    #[test]
+    fn iteration_is_isolated() {
+        let db = DatabaseOptions::new(4)
+            .id_strategy(IdStrategy::Reuse)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut db = iterate_while_writing(db);
+        // removed ids were filled again in between the others
+        assert!(db.get(1).unwrap().is_some());
+        let mut iter = db.iter().unwrap();
+        db.push([0f32; 4]).unwrap();
+        assert_eq!(iter.by_ref().count(), 300);
+        assert!(iter.next().is_none());
+        drop(iter);
+        assert_eq!(db.iter().unwrap().count(), 301);
+        assert!(db.handle.lock().unwrap().iterators.changes.is_empty());
+    }
+
+    #[test]
+    fn append_only_iteration_is_isolated() {
+        let db = DatabaseOptions::new(4)
+            .history(true)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut db = iterate_while_writing(db);
+        let generation = db.generation().unwrap();
+        let iter = db.iter().unwrap();
+        assert!(matches!(db.compact(generation), Err(Error::IterationInProgress)));
+        drop(iter);
+        assert!(db.compact(generation).unwrap() > 0);
+        assert_eq!(db.iter().unwrap().count(), 300);
+    }
+
+    #[test]
+    fn reused_slot_iteration_is_isolated() {
+        let file = SharedCursor::new();
+        let db = DatabaseOptions::new(4)
+            .history(true)
+            .reuse_slots(true)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut db = iterate_while_writing(db);
+        // what was superseded while iterating is freed by the next write
+        db.push([0f32; 4]).unwrap();
+        let len = file.bytes().len();
+        for _ in 0..99 {
+            db.push([0f32; 4]).unwrap();
+        }
+        assert_eq!(file.bytes().len(), len);
+        assert_eq!(db.iter().unwrap().count(), 400);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::db::flush::Block;
+use crate::db::{Database, DbIndex, DbVector, Error};
+use crate::vio;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+/// # Key Table Parameters
+/// Room reserved in the file for the external keys of the records,
+/// see [DatabaseOptions::key_table](crate::db::DatabaseOptions::key_table).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyTableParams {
+    /// Keys the table holds at most.
+    pub capacity: u32,
+    /// Bytes the longest key takes in UTF-8.
+    pub max_key_bytes: u8,
+}
+
+/// # Key Table
+/// External keys of the records and the other way around, read from
+/// the block after the bloom filter's on open and written back on flush.
+pub(crate) struct KeyTable {
+    params: KeyTableParams,
+    /// Where its block begins.
+    offset: u64,
+    ids: HashMap<String, DbIndex>,
+    keys: HashMap<DbIndex, String>,
+}
+
+impl KeyTable {
+    pub(crate) fn new(
+        params: KeyTableParams,
+        offset: u64,
+        entries: Vec<(DbIndex, String)>,
+    ) -> KeyTable {
+        let mut table = KeyTable {
+            params,
+            offset,
+            ids: HashMap::new(),
+            keys: HashMap::new(),
+        };
+        for (id, key) in entries {
+            table.insert(id, key);
+        }
+        table
+    }
+
+    fn insert(&mut self, id: DbIndex, key: String) {
+        self.ids.insert(key.clone(), id);
+        self.keys.insert(id, key);
+    }
+
+    /// Bytes its keys take on the heap both ways, roughly.
+    pub(crate) fn heap_bytes(&self) -> u64 {
+        let keys = self.keys.values().map(String::capacity).sum::<usize>();
+        let entries = (self.ids.capacity() + self.keys.capacity())
+            * size_of::<(String, DbIndex)>();
+        (keys * 2 + entries) as u64
+    }
+
+    /// Drops the key of `id`, which was removed.
+    pub(crate) fn forget(&mut self, id: DbIndex) {
+        if let Some(key) = self.keys.remove(&id) {
+            self.ids.remove(&key);
+        }
+    }
+
+    /// Where its block ends.
+    pub(crate) fn end(&self) -> u64 {
+        self.offset + vio::keys::block_size(self.params)
+    }
+
+    /// Encodes every key into the block, in ascending order of id.
+    fn encode(&self) -> Result<Block, Error> {
+        let mut entries = self.keys.iter().collect::<Vec<_>>();
+        entries.sort();
+        let entries = entries.into_iter().map(|(id, key)| (*id, key.as_str()));
+        let mut bytes = Cursor::new(Vec::new());
+        vio::keys::write(&mut bytes, entries).map_err(Error::IO)?;
+        Ok(Block::new(self.offset, bytes.into_inner()))
+    }
+}
+
+impl Database {
+    /// Appends `vector` under the external `key`, such as a UUID or the id
+    /// of a document, which is kept in the file and persisted on
+    /// [flush](Database::flush). Fails with [Error::KeyConflict] if another
+    /// record has the key already, see [Database::upsert_keyed] instead.
+    pub fn push_keyed<V: AsRef<[f32]>>(&mut self, key: &str, vector: V) -> Result<DbIndex, Error> {
+        let table = self.key_table(key)?;
+        if table.ids.contains_key(key) {
+            return Err(Error::KeyConflict(String::from(key)));
+        }
+        if table.keys.len() >= table.params.capacity as usize {
+            return Err(Error::KeyTableFull(table.params.capacity));
+        }
+        let id = self.push(vector)?;
+        self.keys.as_mut().unwrap().insert(id, String::from(key));
+        Ok(id)
+    }
+
+    /// Same as [Database::push_keyed], updating the record that has the key
+    /// already in place instead of failing.
+    pub fn upsert_keyed<V: AsRef<[f32]>>(&mut self, key: &str, vector: V) -> Result<DbIndex, Error> {
+        match self.key_table(key)?.ids.get(key).copied() {
+            Some(id) => {
+                self.update(id, vector)?.ok_or(Error::Missing(id))?;
+                Ok(id)
+            }
+            None => self.push_keyed(key, vector),
+        }
+    }
+
+    pub fn get_by_key(&self, key: &str) -> Result<Option<Arc<DbVector>>, Error> {
+        match self.id_of(key) {
+            Some(id) => self.get(id),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the record under `key` along with the key.
+    pub fn remove_by_key(&mut self, key: &str) -> Result<Option<Arc<DbVector>>, Error> {
+        match self.id_of(key) {
+            Some(id) => self.remove(id),
+            None => Ok(None),
+        }
+    }
+
+    /// Id of the record under `key`, if any.
+    pub fn id_of(&self, key: &str) -> Option<DbIndex> {
+        self.keys.as_ref()?.ids.get(key).copied()
+    }
+
+    /// Key record `id` was pushed under, if any.
+    pub fn key_of(&self, id: DbIndex) -> Option<&str> {
+        self.keys.as_ref()?.keys.get(&id).map(String::as_str)
+    }
+
+    /// The key table, failing with [Error::NoKeyTable] if there's none,
+    /// or [Error::KeyTooLong] if `key` doesn't fit in it.
+    fn key_table(&self, key: &str) -> Result<&KeyTable, Error> {
+        let table = self.keys.as_ref().ok_or(Error::NoKeyTable)?;
+        if key.len() > table.params.max_key_bytes as usize {
+            return Err(Error::KeyTooLong(key.len()));
+        }
+        Ok(table)
+    }
+
+    /// Encodes the key table into its block if there's one.
+    pub(super) fn encode_keys(&self) -> Result<Option<Block>, Error> {
+        self.keys.as_ref().map(KeyTable::encode).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error};
+    use crate::ext::mem::SharedCursor;
+    use std::io::Cursor;
+
+    const KEYS: [&str; 3] = ["5f0c6a1e-8f3b-4c2d-9e7a-0b1c2d3e4f50", "日本語の文書", "émoji 🦀"];
+
+    #[test]
+    fn keys_work() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .key_table(3, 40)
+            .bloom_filter(10, 0.01)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([9f32; 4]).unwrap();
+        for (i, key) in KEYS.iter().enumerate() {
+            let id = db.push_keyed(key, [i as f32; 4]).unwrap();
+            assert_eq!(db.key_of(id), Some(*key));
+        }
+        assert_eq!(db.key_of(0), None);
+        db.flush().unwrap();
+        drop(db);
+
+        let mut db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        for (i, key) in KEYS.iter().enumerate() {
+            assert_eq!(*db.get_by_key(key).unwrap().unwrap(), [i as f32; 4]);
+            assert_eq!(db.key_of(db.id_of(key).unwrap()), Some(*key));
+        }
+        assert_eq!(db.get_by_key("absent").unwrap(), None);
+
+        assert!(matches!(
+            db.push_keyed(KEYS[0], [0f32; 4]),
+            Err(Error::KeyConflict(key)) if key == KEYS[0]
+        ));
+        let id = db.id_of(KEYS[0]).unwrap();
+        assert_eq!(db.upsert_keyed(KEYS[0], [7f32; 4]).unwrap(), id);
+        assert_eq!(*db.get_by_key(KEYS[0]).unwrap().unwrap(), [7f32; 4]);
+        assert!(matches!(db.push_keyed("fourth", [0f32; 4]), Err(Error::KeyTableFull(3))));
+        assert!(matches!(
+            db.push_keyed(&"x".repeat(41), [0f32; 4]),
+            Err(Error::KeyTooLong(41))
+        ));
+        assert_eq!(db.count().unwrap(), 4);
+
+        // removing either way forgets the key both ways
+        let id = db.id_of(KEYS[1]).unwrap();
+        assert_eq!(*db.remove_by_key(KEYS[1]).unwrap().unwrap(), [1f32; 4]);
+        assert_eq!((db.id_of(KEYS[1]), db.key_of(id)), (None, None));
+        assert_eq!(db.remove_by_key(KEYS[1]).unwrap(), None);
+        let id = db.id_of(KEYS[2]).unwrap();
+        db.remove(id).unwrap();
+        assert_eq!((db.id_of(KEYS[2]), db.key_of(id)), (None, None));
+        let id = db.push_keyed(KEYS[1], [3f32; 4]).unwrap();
+        db.remove_many(&[id]).unwrap();
+        assert_eq!((db.id_of(KEYS[1]), db.key_of(id)), (None, None));
+        db.flush().unwrap();
+        drop(db);
+
+        let db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.id_of(KEYS[0]), Some(1));
+        assert_eq!((db.id_of(KEYS[1]), db.id_of(KEYS[2])), (None, None));
+        assert_eq!(db.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn keys_need_table() {
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        assert!(matches!(db.push_keyed("a", [0f32; 4]), Err(Error::NoKeyTable)));
+        assert!(matches!(db.upsert_keyed("a", [0f32; 4]), Err(Error::NoKeyTable)));
+        assert_eq!(db.get_by_key("a").unwrap(), None);
+        assert_eq!(db.count().unwrap(), 0);
+    }
+}
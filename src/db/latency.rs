@@ -0,0 +1,300 @@
+use crate::db::trace::Tracer;
+use crate::db::{Database, TracedSearch};
+use crate::ext::semaphore::LockAutoClear;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// # Operation
+/// Public operations whose latency is recorded, see [DatabaseOptions::latency_stats](crate::db::DatabaseOptions::latency_stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Get,
+    Push,
+    /// [Database::query] and [Database::query_next].
+    Search,
+    Remove,
+    Flush,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 5] = [
+        Operation::Get,
+        Operation::Push,
+        Operation::Search,
+        Operation::Remove,
+        Operation::Flush,
+    ];
+}
+
+/// # Clock
+/// Monotonic source of time for latencies and search deadlines,
+/// counted from any fixed origin.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Duration;
+}
+
+/// # Monotonic Clock
+/// [Clock] backed by [Instant], counting from its creation.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    origin: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        MonotonicClock {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// Bucket `i` holds latencies below `2^i` nanoseconds and at least
+/// half that, the first one zero and the last one everything beyond.
+const BUCKETS: usize = 64;
+
+fn bucket_of(latency: Duration) -> usize {
+    let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+    (64 - nanos.leading_zeros() as usize).min(BUCKETS - 1)
+}
+
+/// # Histogram Snapshot
+/// Latencies of one [Operation] recorded so far, in log-scale buckets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    buckets: Vec<u64>,
+}
+
+impl HistogramSnapshot {
+    fn empty() -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: vec![0; BUCKETS],
+        }
+    }
+
+    /// Number of latencies recorded in each bucket, see [HistogramSnapshot::bound].
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Exclusive upper bound of bucket `i`, the last one being unbounded.
+    pub fn bound(i: usize) -> Duration {
+        match i {
+            i if i >= BUCKETS - 1 => Duration::MAX,
+            i => Duration::from_nanos(1 << i),
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Latency at or below which a fraction `q` of the recorded ones fall,
+    /// rounded up to the bound of its bucket, or `None` if nothing was recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0f64, 1f64) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets.iter().enumerate().find_map(|(i, n)| {
+            seen += n;
+            (seen >= rank).then(|| HistogramSnapshot::bound(i))
+        })
+    }
+
+    pub fn p50(&self) -> Option<Duration> {
+        self.quantile(0.5)
+    }
+
+    pub fn p95(&self) -> Option<Duration> {
+        self.quantile(0.95)
+    }
+
+    pub fn p99(&self) -> Option<Duration> {
+        self.quantile(0.99)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Latencies {
+    clock: Arc<dyn Clock>,
+    histograms: [[AtomicU64; BUCKETS]; Operation::ALL.len()],
+}
+
+impl Latencies {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Latencies {
+        Latencies {
+            clock,
+            histograms: std::array::from_fn(|_| std::array::from_fn(|_| AtomicU64::new(0))),
+        }
+    }
+
+    fn record(&self, op: Operation, latency: Duration) {
+        self.histograms[op as usize][bucket_of(latency)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, op: Operation) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self.histograms[op as usize]
+                .iter()
+                .map(|n| n.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for n in self.histograms.iter().flatten() {
+            n.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Records the time from its creation to its drop, errors included, into
+/// the latencies and the trace, whichever are on, each by its own clock.
+pub(crate) struct Timer {
+    op: Operation,
+    latencies: Option<(Arc<Latencies>, Duration)>,
+    tracer: Option<(Arc<Tracer>, Duration)>,
+    /// What a search asked for, for the trace.
+    pub(crate) search: Option<TracedSearch>,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some((latencies, started)) = &self.latencies {
+            latencies.record(self.op, latencies.clock.now().saturating_sub(*started));
+        }
+        if let Some((tracer, started)) = &self.tracer {
+            let elapsed = tracer.clock.now().saturating_sub(*started);
+            tracer.record(self.op, elapsed, self.search);
+        }
+    }
+}
+
+impl Database {
+    /// Starts timing `op` if latencies are recorded or a trace is, to be
+    /// held until it's done.
+    pub(crate) fn time(&self, op: Operation) -> Option<Timer> {
+        if self.latencies.is_none() && self.tracer.is_none() {
+            return None;
+        }
+        Some(Timer {
+            op,
+            latencies: self.latencies.as_ref().map(|l| (l.clone(), l.clock.now())),
+            tracer: self.tracer.as_ref().map(|t| (t.clone(), t.clock.now())),
+            search: None,
+        })
+    }
+
+    /// Latencies of every [Operation], all empty if they aren't recorded.
+    pub(crate) fn latency_snapshots(&self) -> Vec<HistogramSnapshot> {
+        Operation::ALL
+            .iter()
+            .map(|op| {
+                self.latencies
+                    .as_ref()
+                    .map_or_else(HistogramSnapshot::empty, |l| l.snapshot(*op))
+            })
+            .collect()
+    }
+
+    /// Forgets every latency, written byte, shortcut taken and vector
+    /// normalized recorded so far.
+    pub fn reset_stats(&self) {
+        if let Some(latencies) = &self.latencies {
+            latencies.reset();
+        }
+        self.counters.shortcuts.reset();
+        self.counters.results.reset();
+        self.counters.dangling.store(0, Ordering::Relaxed);
+        self.normalizations.reset();
+        let mut handle = self.handle.lock_auto_clear_poison();
+        handle.amplification.reset();
+        handle.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Clock, DatabaseOptions, HistogramSnapshot, Operation, SearchRequest};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Moves on by `step` every time it's read, so that each operation takes `step`.
+    #[derive(Debug, Default)]
+    struct StepClock {
+        nanos: AtomicU64,
+        step: AtomicU64,
+    }
+
+    impl Clock for StepClock {
+        fn now(&self) -> Duration {
+            let step = self.step.load(Ordering::Relaxed);
+            Duration::from_nanos(self.nanos.fetch_add(step, Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn latency_histograms_work() {
+        let clock = Arc::new(StepClock::default());
+        let mut db = DatabaseOptions::new(2)
+            .latency_clock(clock.clone())
+            .create("mem", Box::new(std::io::Cursor::new(Vec::new())))
+            .unwrap();
+        let step = |nanos: u64| clock.step.store(nanos, Ordering::Relaxed);
+
+        step(100);
+        for i in 0..10 {
+            db.push([i as f32; 2]).unwrap();
+        }
+        step(1000);
+        for id in 0..9 {
+            db.get(id).unwrap();
+        }
+        // the tail
+        step(1_000_000);
+        db.get(9).unwrap();
+        step(10);
+        db.query(&SearchRequest::new(&[0f32; 2], 3)).unwrap();
+        db.remove(0).unwrap();
+        db.flush().unwrap();
+
+        let stats = db.stats().unwrap();
+        let push = stats.latency(Operation::Push);
+        // 100ns lies within [64, 128)
+        assert_eq!(push.buckets()[7], 10);
+        assert_eq!(push.count(), 10);
+        let get = stats.latency(Operation::Get);
+        assert_eq!(get.buckets()[10], 9);
+        assert_eq!(get.buckets()[20], 1);
+        assert_eq!(get.p50(), Some(Duration::from_nanos(1024)));
+        assert_eq!(get.p95(), Some(HistogramSnapshot::bound(20)));
+        for op in [Operation::Search, Operation::Remove, Operation::Flush] {
+            assert_eq!(stats.latency(op).buckets()[4], 1, "{op:?}");
+        }
+
+        db.reset_stats();
+        let stats = db.stats().unwrap();
+        assert!(Operation::ALL.iter().all(|op| stats.latency(*op).count() == 0));
+        assert_eq!(stats.latency(Operation::Get).p99(), None);
+    }
+
+    #[test]
+    fn latency_off_by_default() {
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(std::io::Cursor::new(Vec::new())))
+            .unwrap();
+        db.push([1f32; 2]).unwrap();
+        assert_eq!(db.stats().unwrap().latency(Operation::Push).count(), 0);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::db::flush::Block;
+use crate::db::{Database, DbIndex, Error};
+use crate::ext::semaphore::LockAutoClear;
+use crate::index::HnswIndex;
+use crate::vio;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+/// # Level Table
+/// Top layer of the node of each vector in the index, read from the block
+/// after the key table's on open and written from the index on flush, so
+/// that a node left off a layer in between isn't taken for a lower one.
+pub(crate) struct LevelTable {
+    capacity: u32,
+    /// Where its block begins.
+    offset: u64,
+    levels: BTreeMap<DbIndex, u8>,
+}
+
+impl LevelTable {
+    pub(crate) fn new(capacity: u32, offset: u64, entries: Vec<(DbIndex, u8)>) -> LevelTable {
+        LevelTable {
+            capacity,
+            offset,
+            levels: entries.into_iter().collect(),
+        }
+    }
+
+    /// Where its block ends.
+    pub(crate) fn end(&self) -> u64 {
+        self.offset + vio::levels::block_size(self.capacity)
+    }
+
+    /// Bytes its levels take on the heap, roughly.
+    pub(crate) fn heap_bytes(&self) -> u64 {
+        (self.levels.len() * size_of::<(DbIndex, u8)>()) as u64
+    }
+
+    /// Takes `levels` in place of those it had, failing
+    /// with [Error::LevelTableFull] if they're too many.
+    fn refresh(&mut self, levels: Vec<(DbIndex, u8)>) -> Result<(), Error> {
+        if levels.len() > self.capacity as usize {
+            return Err(Error::LevelTableFull(self.capacity));
+        }
+        self.levels = levels.into_iter().collect();
+        Ok(())
+    }
+
+    /// Encodes every level into the block.
+    fn encode(&self) -> Result<Block, Error> {
+        let entries = self.levels.iter().map(|(id, level)| (*id, *level));
+        let mut bytes = Cursor::new(Vec::new());
+        vio::levels::write(&mut bytes, entries).map_err(Error::IO)?;
+        Ok(Block::new(self.offset, bytes.into_inner()))
+    }
+}
+
+impl Database {
+    /// Top layer of the node of `id` in the index, zero being the base, or
+    /// as the [level table](crate::db::DatabaseOptions::level_table) has it
+    /// if the index isn't built, which is as of the last [flush](Database::flush).
+    /// `None` if neither holds `id`.
+    pub fn node_level(&self, id: DbIndex) -> Option<u8> {
+        if let Some(index) = self.index.lock_auto_clear_poison().as_ref() {
+            return index
+                .level(id)
+                .map(|level| u8::try_from(level).unwrap_or(u8::MAX));
+        }
+        let table = self.levels.as_ref()?.lock_auto_clear_poison();
+        table.levels.get(&id).copied()
+    }
+
+    /// Ids whose level in the level table differs from the top layer the
+    /// index has their nodes on, in ascending order, as when the table was
+    /// damaged or the index drew other levels since the last flush. Those
+    /// only one of them holds are left out.
+    pub(super) fn misplaced_levels(&self, index: &HnswIndex) -> Vec<DbIndex> {
+        let Some(table) = &self.levels else {
+            return vec![];
+        };
+        let table = table.lock_auto_clear_poison();
+        table
+            .levels
+            .iter()
+            .filter(|(id, level)| {
+                index
+                    .level(**id)
+                    .is_some_and(|actual| actual != **level as u32)
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// The level of every node of the index if there's both the index and a
+    /// level table, copied out while holding the index no longer than that takes.
+    pub(super) fn index_levels(&self) -> Option<Vec<(DbIndex, u8)>> {
+        self.levels.as_ref()?;
+        let index = self.index.lock_auto_clear_poison();
+        let index = index.as_ref()?;
+        Some(
+            index
+                .ids()
+                .filter_map(|id| Some((id, u8::try_from(index.level(id)?).unwrap_or(u8::MAX))))
+                .collect(),
+        )
+    }
+
+    /// Takes `levels` copied out of the index into the level table, if
+    /// there are any, and encodes the table into its block if there's one.
+    pub(super) fn encode_levels(
+        &self,
+        levels: Option<Vec<(DbIndex, u8)>>,
+    ) -> Result<Option<Block>, Error> {
+        let Some(table) = &self.levels else {
+            return Ok(None);
+        };
+        let mut table = table.lock_auto_clear_poison();
+        if let Some(levels) = levels {
+            table.refresh(levels)?;
+        }
+        table.encode().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, DbIndex, Error};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::ext::semaphore::LockAutoClear;
+    use std::collections::BTreeMap;
+    use std::io::{Seek, SeekFrom, Write};
+
+    /// The top layer each id is on, as the layers of the index have it.
+    fn membership(db: &Database) -> BTreeMap<DbIndex, u8> {
+        let mut levels = BTreeMap::new();
+        for view in db.layers() {
+            for id in view.nodes() {
+                levels.insert(id, view.level() as u8);
+            }
+        }
+        levels
+    }
+
+    fn reopen(file: &SharedCursor) -> Database {
+        DatabaseOptions::default()
+            .verify(true)
+            .open("mem", Box::new(file.reopen()))
+            .unwrap()
+    }
+
+    #[test]
+    fn level_table_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .level_table(500)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut rng = XorShift::new(3);
+        for _ in 0..300 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        db.remove(7).unwrap();
+        db.build_index().unwrap();
+        let expected = membership(&db);
+        assert_eq!(expected.len(), 299);
+        assert!(expected.values().any(|level| *level > 0));
+        db.flush().unwrap();
+
+        let db = reopen(&file);
+        assert!(!db.is_indexed());
+        let table = (0..300)
+            .filter_map(|id| Some((id, db.node_level(id)?)))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(table, expected);
+        db.build_index().unwrap();
+        assert_eq!(membership(&db), expected);
+        assert!(db.verify().is_empty());
+
+        // the first entry is id 0 in a byte, followed by its level
+        let offset = db.levels.as_ref().unwrap().lock_auto_clear_poison().offset;
+        let mut fd = file.reopen();
+        fd.seek(SeekFrom::Start(offset + 4 + 1)).unwrap();
+        fd.write_all(&[expected[&0] + 1]).unwrap();
+        let db = reopen(&file);
+        assert_eq!(db.node_level(0), Some(expected[&0] + 1));
+        db.build_index().unwrap();
+        assert_eq!(db.node_level(0), Some(expected[&0]));
+        assert_eq!(db.verify(), vec![0]);
+        db.flush().unwrap();
+        let db = reopen(&file);
+        db.build_index().unwrap();
+        assert!(db.verify().is_empty());
+
+        let mut small = DatabaseOptions::new(4)
+            .level_table(100)
+            .create("mem", Box::new(SharedCursor::new()))
+            .unwrap();
+        for _ in 0..101 {
+            small.push(rng.vector(4)).unwrap();
+        }
+        small.flush().unwrap();
+        small.build_index().unwrap();
+        assert!(matches!(small.flush(), Err(Error::LevelTableFull(100))));
+    }
+}
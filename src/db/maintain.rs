@@ -0,0 +1,291 @@
+use crate::db::{Database, Error, SyncPolicy};
+use crate::ext::semaphore::{lock_pair, LockAutoClear, LockRank};
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+/// Vectors taken off the write queue per step.
+const DRAIN_STEP: usize = 64;
+/// Index nodes checked for a record per step.
+const PRUNE_STEP: u32 = 256;
+
+/// # Maintenance Task
+/// Work [Database::maintain] gets done bit by bit, in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MaintenanceTask {
+    /// Checks the file for changes made by someone else and deals with them,
+    /// see [Database::check_external_changes].
+    CheckExternalChanges,
+    /// Commits vectors waiting in the write queue, see [Database::push_async_queued].
+    DrainQueue,
+    /// Takes the nodes out of the index whose records are gone, see [Database::verify].
+    PruneIndex,
+    /// Drops the cached responses answered before the records last changed,
+    /// see [DatabaseOptions::result_cache](crate::db::DatabaseOptions::result_cache).
+    EvictResults,
+    /// Writes the bloom filter and search parameters and syncs, see [Database::flush].
+    Flush,
+}
+
+const TASKS: [MaintenanceTask; 5] = [
+    MaintenanceTask::CheckExternalChanges,
+    MaintenanceTask::DrainQueue,
+    MaintenanceTask::PruneIndex,
+    MaintenanceTask::EvictResults,
+    MaintenanceTask::Flush,
+];
+
+/// # Maintenance Report
+/// What a call to [Database::maintain] got done.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Steps taken across the tasks.
+    pub steps: usize,
+    /// Vectors taken off the write queue, including those that failed to be pushed.
+    pub drained: usize,
+    /// Index nodes checked for a record.
+    pub checked: usize,
+    /// Nodes taken out of the index for their records were gone.
+    pub pruned: usize,
+    /// Cached responses dropped.
+    pub evicted: usize,
+    /// Whether the database was flushed.
+    pub flushed: bool,
+    /// Whether the database was read anew for its file had changed.
+    pub reloaded: bool,
+    /// Tasks with work left once the budget ran out, for a later call to pick up.
+    pub pending: Vec<MaintenanceTask>,
+}
+
+impl MaintenanceReport {
+    /// Whether nothing was left to do.
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Adds up the work of `other`, taking its pending tasks as the latest.
+    pub fn merge(&mut self, other: MaintenanceReport) {
+        self.steps += other.steps;
+        self.drained += other.drained;
+        self.checked += other.checked;
+        self.pruned += other.pruned;
+        self.evicted += other.evicted;
+        self.flushed |= other.flushed;
+        self.reloaded |= other.reloaded;
+        self.pending = other.pending;
+    }
+}
+
+/// Where maintenance left off, carried over between calls.
+#[derive(Default)]
+pub(crate) struct Maintenance {
+    /// Task to take the next step of, by its position in [TASKS].
+    next: usize,
+    /// Node the pass over the index resumes at, if one is underway,
+    /// along with the revision of the records it started at.
+    prune_cursor: Option<(u32, u64)>,
+    /// Revision of the records the last full pass over the index started at.
+    pruned_at: Option<u64>,
+}
+
+impl Database {
+    /// Takes steps of the [pending](Database::maintenance_pending) tasks in turn
+    /// until none is left or `budget` is spent, resuming where the last call left
+    /// off. A step is small enough to keep the database responsive, and the first
+    /// one is taken whatever the budget, so every call makes progress.
+    pub fn maintain(&mut self, budget: Duration) -> Result<MaintenanceReport, Error> {
+        let deadline = Instant::now() + budget;
+        let mut report = MaintenanceReport::default();
+        loop {
+            let pending = self.maintenance_pending();
+            let Some(task) = (0..TASKS.len())
+                .map(|offset| TASKS[(self.maintenance.next + offset) % TASKS.len()])
+                .find(|task| pending.contains(task))
+            else {
+                return Ok(report);
+            };
+            if report.steps > 0 && Instant::now() >= deadline {
+                report.pending = pending;
+                return Ok(report);
+            }
+            self.maintenance_step(task, &mut report)?;
+            report.steps += 1;
+            let position = TASKS.iter().position(|t| *t == task).unwrap();
+            self.maintenance.next = (position + 1) % TASKS.len();
+        }
+    }
+
+    /// Tasks [Database::maintain] has work left for, in the order of [MaintenanceTask].
+    pub fn maintenance_pending(&self) -> Vec<MaintenanceTask> {
+        TASKS
+            .into_iter()
+            .filter(|task| self.is_pending(*task))
+            .collect()
+    }
+
+    fn is_pending(&self, task: MaintenanceTask) -> bool {
+        match task {
+            MaintenanceTask::CheckExternalChanges => self.is_check_due(),
+            MaintenanceTask::DrainQueue => !self.read_only && self.queued() > 0,
+            MaintenanceTask::PruneIndex => {
+                self.is_indexed()
+                    && (self.maintenance.prune_cursor.is_some()
+                        || self.maintenance.pruned_at != Some(self.revision()))
+            }
+            MaintenanceTask::EvictResults => match &self.results {
+                Some(results) => results.lock_auto_clear_poison().has_stale(self.revision()),
+                None => false,
+            },
+            MaintenanceTask::Flush => {
+                let handle = self.handle.lock_auto_clear_poison();
+                !self.read_only
+                    && (handle.bloom.is_some() && !handle.bloom_fresh
+                        || handle.sync_policy != SyncPolicy::Never && handle.unsynced_bytes > 0)
+            }
+        }
+    }
+
+    fn maintenance_step(
+        &mut self,
+        task: MaintenanceTask,
+        report: &mut MaintenanceReport,
+    ) -> Result<(), Error> {
+        match task {
+            MaintenanceTask::CheckExternalChanges => {
+                report.reloaded |= self.check_external_changes()?;
+            }
+            MaintenanceTask::DrainQueue => {
+                report.drained += self.drain_queue_at_most(DRAIN_STEP)?;
+            }
+            MaintenanceTask::PruneIndex => self.prune_step(report),
+            MaintenanceTask::EvictResults => {
+                if let Some(results) = &self.results {
+                    let revision = self.revision();
+                    report.evicted += results.lock_auto_clear_poison().evict_stale(revision);
+                }
+            }
+            MaintenanceTask::Flush => {
+                // flushing writes what's queued, which is counted as drained
+                report.drained += self.drain_queue()?;
+                self.flush()?;
+                report.flushed = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the next nodes of the index for their records, like
+    /// [Database::verify] does all at once, and removes those without.
+    fn prune_step(&mut self, report: &mut MaintenanceReport) {
+        let (mut index, mut handle) =
+            lock_pair((LockRank::Index, &self.index), (LockRank::Handle, &self.handle));
+        let Some(index) = index.as_mut() else {
+            self.maintenance.prune_cursor = None;
+            return;
+        };
+        let (start, started_at) = self.maintenance.prune_cursor.unwrap_or((0, handle.revision));
+        let end = min(start.saturating_add(PRUNE_STEP), index.slots());
+        let mut pruned = 0;
+        for node in start..end {
+            if let Some(id) = index.id_at(node) {
+                if !handle.contains(id) {
+                    index.remove(id);
+                    pruned += 1;
+                }
+            }
+        }
+        report.checked += end.saturating_sub(start) as usize;
+        report.pruned += pruned;
+        if end < index.slots() {
+            self.maintenance.prune_cursor = Some((end, started_at));
+        } else {
+            // whatever was removed since the pass started is for another one to find
+            self.maintenance.prune_cursor = None;
+            self.maintenance.pruned_at = Some(started_at);
+        }
+        drop(handle);
+        if pruned > 0 {
+            self.invalidate_views();
+            self.forget_results();
+        }
+    }
+
+    fn revision(&self) -> u64 {
+        self.handle.lock_auto_clear_poison().revision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, MaintenanceTask, SearchRequest};
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    #[test]
+    fn maintenance_makes_progress() {
+        let mut db = DatabaseOptions::new(4)
+            .bloom_filter(1000, 0.01)
+            .result_cache(4)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(29);
+        for _ in 0..600 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        db.build_index().unwrap();
+        db.flush().unwrap();
+        let request = SearchRequest::new(&[0.5f32; 4], 5);
+        db.query(&request).unwrap();
+        let removed = [7, 300, 598];
+        for id in removed {
+            db.handle.lock().unwrap().remove(id).unwrap().unwrap();
+        }
+        let pending = (0..100)
+            .map(|_| db.push_async_queued(rng.vector(4)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            db.maintenance_pending(),
+            [
+                MaintenanceTask::DrainQueue,
+                MaintenanceTask::PruneIndex,
+                MaintenanceTask::EvictResults,
+                MaintenanceTask::Flush
+            ]
+        );
+
+        // a spent budget still takes a step
+        let first = db.maintain(Duration::ZERO).unwrap();
+        assert_eq!((first.steps, first.drained), (1, 64));
+        assert_eq!(db.queued(), 36);
+        assert!(!first.is_done());
+        let second = db.maintain(Duration::ZERO).unwrap();
+        assert_eq!((second.steps, second.checked), (1, 256));
+        assert_eq!(second.pruned, 1);
+        assert_eq!(second.pending.first(), Some(&MaintenanceTask::DrainQueue));
+
+        let mut total = first;
+        total.merge(second);
+        for _ in 0..20 {
+            if total.is_done() {
+                break;
+            }
+            total.merge(db.maintain(Duration::ZERO).unwrap());
+        }
+        assert!(total.is_done());
+        assert_eq!(total.drained, 100);
+        assert_eq!(total.pruned, removed.len());
+        assert!(total.flushed);
+        assert!(pending.into_iter().all(|id| db.resolve(id).is_ok()));
+        assert!(db.verify().is_empty());
+        assert!(db.maintenance_pending().is_empty());
+        assert_eq!(db.maintain(Duration::ZERO).unwrap().steps, 0);
+
+        // with time to spare, it's done in one go
+        db.query(&request).unwrap();
+        db.push_async_queued(rng.vector(4)).unwrap();
+        let report = db.maintain(Duration::from_secs(60)).unwrap();
+        assert!(report.is_done());
+        assert_eq!((report.drained, report.pruned, report.evicted), (1, 0, 1));
+        assert!(report.flushed);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::db::{Database, SearchPlan, SearchResponse};
+use crate::ext::counter::HitCounts;
+use crate::ext::semaphore::LockAutoClear;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// What a response depends on besides the records, see [ResultCache].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ResultKey {
+    /// Bits of the components, for a NaN to match itself.
+    pub query: Vec<u32>,
+    pub k: usize,
+    /// Requested or else of the search parameters.
+    pub ef: usize,
+    pub explain: bool,
+    pub metric: Option<u8>,
+    pub plan: Option<SearchPlan>,
+    pub max_visited: Option<u64>,
+    pub rerank_factor: u32,
+    /// Bits of the planner configuration.
+    pub planner: (u64, u32),
+}
+
+struct Entry {
+    response: SearchResponse,
+    /// Of the records when it was answered.
+    revision: u64,
+    used: u64,
+}
+
+/// # Result Cache
+/// Responses to recent queries by what they asked, each along with the
+/// revision of the records it was answered at, so that none is served
+/// once they changed. The least recently used response makes room for
+/// new ones once it holds `capacity`.
+pub(crate) struct ResultCache {
+    capacity: usize,
+    entries: HashMap<ResultKey, Entry>,
+    clock: u64,
+    /// Shared with the [stats](Database::stats) of the database.
+    counts: Arc<HitCounts>,
+}
+
+impl ResultCache {
+    pub(crate) fn new(capacity: usize, counts: Arc<HitCounts>) -> ResultCache {
+        ResultCache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            clock: 0,
+            counts,
+        }
+    }
+
+    /// The response to `key` if it was answered at `revision`,
+    /// counted as a hit, or a miss otherwise.
+    pub(crate) fn get(&mut self, key: &ResultKey, revision: u64) -> Option<SearchResponse> {
+        self.clock += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.revision == revision => {
+                entry.used = self.clock;
+                self.counts.hit();
+                Some(entry.response.clone())
+            }
+            _ => {
+                self.counts.miss();
+                None
+            }
+        }
+    }
+
+    /// Whether any response was answered before `revision`.
+    pub(crate) fn has_stale(&self, revision: u64) -> bool {
+        self.entries.values().any(|entry| entry.revision != revision)
+    }
+
+    /// Drops the responses answered before `revision`, returning how many.
+    pub(crate) fn evict_stale(&mut self, revision: u64) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| entry.revision == revision);
+        before - self.entries.len()
+    }
+
+    pub(crate) fn insert(&mut self, key: ResultKey, revision: u64, response: SearchResponse) {
+        self.clock += 1;
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self.entries.iter().min_by_key(|(_, entry)| entry.used);
+            if let Some(oldest) = oldest.map(|(key, _)| key.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+        let entry = Entry {
+            response,
+            revision,
+            used: self.clock,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Forgets every response, for changes that don't write any records.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Forgets the responses answered after `revision`.
+    pub(crate) fn forget_after(&mut self, revision: u64) {
+        self.entries.retain(|_, entry| entry.revision <= revision);
+    }
+}
+
+impl Database {
+    /// Caches the responses to the last `capacity` distinct requests to
+    /// [Database::query], which identical ones get back until the records,
+    /// the index, the [search parameters](Database::set_search_params) or
+    /// the [planner](Database::set_planner) change, see
+    /// [DbStats::result_cache_hit_rate](crate::db::DbStats::result_cache_hit_rate).
+    /// Zero turns it off, forgetting what was cached.
+    pub fn set_result_cache(&mut self, capacity: usize) {
+        let counts = self.counters.results.clone();
+        counts.reset();
+        self.results = (capacity > 0).then(|| Mutex::new(ResultCache::new(capacity, counts)));
+    }
+
+    /// The index changed while the records didn't.
+    pub(crate) fn forget_results(&self) {
+        if let Some(results) = &self.results {
+            results.lock_auto_clear_poison().clear();
+        }
+    }
+
+    /// The index caught up with records written after `revision`.
+    pub(crate) fn forget_results_after(&self, revision: u64) {
+        if let Some(results) = &self.results {
+            results.lock_auto_clear_poison().forget_after(revision);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, SearchParams, SearchRequest};
+    use crate::ext::rand::XorShift;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+
+    #[test]
+    fn result_cache_works() {
+        let mut db = DatabaseOptions::new(4)
+            .result_cache(2)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(47);
+        for _ in 0..100 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        let misses = |db: &crate::db::Database| db.stats().unwrap().result_cache_misses;
+        let request = SearchRequest::new(&[0.5f32; 4], 5);
+        let first = db.query(&request).unwrap();
+        assert_eq!(db.query(&request).unwrap(), first);
+        assert_eq!(misses(&db), 1);
+        assert_eq!(db.stats().unwrap().result_cache_hits, 1);
+
+        // anything else asked is another request
+        let others = [
+            SearchRequest::new(&[0.5f32; 4], 6),
+            request.clone().metric_override(Metric::Cosine),
+        ];
+        for other in &others {
+            db.query(other).unwrap();
+        }
+        assert_eq!(misses(&db), 3);
+        db.set_search_params(SearchParams {
+            max_visited: Some(10),
+            ..db.search_params()
+        });
+        assert_eq!(db.query(&request).unwrap().visited, 10);
+        db.set_search_params(SearchParams::default());
+
+        // the original was the least recently used
+        assert_eq!(db.query(&request).unwrap(), first);
+        assert_eq!(misses(&db), 5);
+        assert_eq!(db.query(&request).unwrap(), first);
+        assert_eq!(misses(&db), 5);
+
+        let id = db.push([0.5f32; 4]).unwrap();
+        let pushed = db.query(&request).unwrap();
+        assert_eq!(pushed.results[0], (id, 0f32));
+        assert_eq!(misses(&db), 6);
+        db.remove(id).unwrap();
+        assert_eq!(db.query(&request).unwrap(), first);
+        assert_eq!(misses(&db), 7);
+        db.build_index().unwrap();
+        db.query(&request).unwrap();
+        assert_eq!(misses(&db), 8);
+
+        db.reset_stats();
+        db.set_result_cache(0);
+        db.query(&request).unwrap();
+        db.query(&request).unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!((stats.result_cache_hits, stats.result_cache_misses), (0, 0));
+        assert_eq!(stats.result_cache_hit_rate(), 0f64);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::db::{CacheMode, Database};
+use crate::ds::layer::HnswLayer;
+use crate::ext::semaphore::LockAutoClear;
+
+impl Database {
+    /// Bytes the database holds in memory, roughly: the vectors cached, the
+    /// layers read from the file, the index if built, and the key, level
+    /// and group tables. Buffers of the file handles and cached responses aren't counted.
+    pub fn memory_usage(&self) -> u64 {
+        let cache = self.loaded_vectors.lock_auto_clear_poison().bytes();
+        let layers = self.layers.iter().map(HnswLayer::heap_bytes).sum::<u64>();
+        let index = self
+            .index
+            .lock_auto_clear_poison()
+            .as_ref()
+            .map_or(0, |index| index.heap_bytes());
+        let keys = self.keys.as_ref().map_or(0, |keys| keys.heap_bytes());
+        let groups = self.groups.as_ref().map_or(0, |groups| groups.heap_bytes());
+        let levels = self
+            .levels
+            .as_ref()
+            .map_or(0, |levels| levels.lock_auto_clear_poison().heap_bytes());
+        cache + layers + index + keys + levels + groups
+    }
+
+    /// Bounds the cache to `budget` bytes from now on, see [CacheMode::Bounded],
+    /// evicting the oldest vectors beyond it. Caches bounded tighter already,
+    /// or off, are left as they are. Returns the bytes freed.
+    pub fn shrink_cache(&self, budget: u64) -> u64 {
+        let mut cache = self.loaded_vectors.lock_auto_clear_poison();
+        match cache.mode() {
+            CacheMode::None => return 0,
+            CacheMode::Bounded(bound) if bound <= budget => return 0,
+            _ => {}
+        }
+        let before = cache.bytes();
+        cache.set_mode(CacheMode::Bounded(budget));
+        before - cache.bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{CacheMode, DatabaseOptions};
+    use std::io::Cursor;
+
+    #[test]
+    fn memory_usage_works() {
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        assert_eq!(db.memory_usage(), 0);
+        for i in 0..100 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        assert_eq!(db.memory_usage(), 100 * 16);
+        db.build_index().unwrap();
+        let indexed = db.memory_usage();
+        // its own copy of every vector, and the graph on top
+        assert!(indexed > 2 * 100 * 16, "{indexed}");
+
+        assert_eq!(db.shrink_cache(960), 1600 - 960);
+        assert_eq!(db.cache_mode(), CacheMode::Bounded(960));
+        assert_eq!(db.memory_usage(), indexed - (1600 - 960));
+        assert_eq!(db.shrink_cache(2000), 0);
+        assert_eq!(db.cache_mode(), CacheMode::Bounded(960));
+        // what's evicted is still read from the file
+        assert_eq!(*db.get(0).unwrap().unwrap(), vec![0f32; 4]);
+        assert_eq!(db.shrink_cache(0), 960);
+        assert_eq!(db.memory_usage(), indexed - 1600);
+    }
+}
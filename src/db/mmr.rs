@@ -0,0 +1,103 @@
+use crate::db::{DbIndex, DbVector, Error, SearchResponse};
+use crate::metric::Metric;
+use std::sync::Arc;
+
+/// Knobs of [SearchRequest::mmr](crate::db::SearchRequest::mmr).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Mmr {
+    pub lambda: f32,
+    pub pool_factor: usize,
+}
+
+/// Picks `k` of the results of `pool`, each the one maximizing
+/// `lambda * relevance - (1 - lambda) * redundancy`, relevance being its
+/// distance to the query negated and redundancy that to the closest picked
+/// before negated, both measured by `metric` on the vectors `vector` fetches.
+/// Ties go to the closer to the query, so the closest is always picked first.
+/// The results come in the order they were picked, with their distances to
+/// the query, and can't be paged through.
+pub(crate) fn diversify(
+    pool: SearchResponse,
+    (k, lambda): (usize, f32),
+    metric: Metric,
+    vector: impl Fn(DbIndex) -> Result<Option<Arc<DbVector>>, Error>,
+) -> Result<SearchResponse, Error> {
+    let mut candidates = vec![];
+    for (id, distance) in &pool.results {
+        if let Some(vector) = vector(*id)? {
+            candidates.push((*id, *distance, vector));
+        }
+    }
+    // distance of each candidate left to the closest picked
+    let mut redundancy = vec![f32::INFINITY; candidates.len()];
+    let mut results = Vec::with_capacity(k.min(candidates.len()));
+    while results.len() < k && !candidates.is_empty() {
+        let score = |(i, (_, distance, _)): (usize, &(DbIndex, f32, Arc<DbVector>))| {
+            match results.is_empty() {
+                true => -distance,
+                false => -lambda * distance + (1f32 - lambda) * redundancy[i],
+            }
+        };
+        let mut best = 0;
+        for (i, candidate) in candidates.iter().enumerate().skip(1) {
+            if score((i, candidate)) > score((best, &candidates[best])) {
+                best = i;
+            }
+        }
+        let (id, distance, picked) = candidates.swap_remove(best);
+        redundancy.swap_remove(best);
+        for (i, (_, _, vector)) in candidates.iter().enumerate() {
+            redundancy[i] = redundancy[i].min(metric.distance(&picked, vector));
+        }
+        results.push((id, distance));
+    }
+    Ok(SearchResponse {
+        results,
+        cursor: None,
+        ..pool
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, SearchPlan, SearchRequest};
+    use std::io::Cursor;
+
+    #[test]
+    fn mmr_works() {
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        // five near-duplicates next to the query, then points around it
+        for i in 0..5 {
+            db.push([1f32 + i as f32 * 1e-3, 0f32]).unwrap();
+        }
+        for vector in [[0.7f32, 0.7f32], [0.7f32, -0.7f32], [0f32, 1f32], [-1f32, 0f32]] {
+            db.push(vector).unwrap();
+        }
+        let query = [1.1f32, 0f32];
+        let duplicates = |results: &[(u32, f32)]| results.iter().filter(|(id, _)| *id < 5).count();
+
+        let plain = db.query(&SearchRequest::new(&query, 3)).unwrap();
+        assert_eq!(duplicates(&plain.results), 3);
+        let diverse = db.query(&SearchRequest::new(&query, 3).mmr(0.3, 3)).unwrap();
+        assert_eq!(diverse.results[0], plain.results[0]);
+        assert_eq!(duplicates(&diverse.results), 1);
+        assert_eq!(diverse.results.len(), 3);
+        assert!(diverse.cursor.is_none());
+
+        // relevance alone is the plain ranking, diversity alone still starts closest
+        let relevant = db.query(&SearchRequest::new(&query, 3).mmr(1.0, 3)).unwrap();
+        assert_eq!(relevant.results, plain.results);
+        let spread = db.query(&SearchRequest::new(&query, 3).mmr(0.0, 3)).unwrap();
+        assert_eq!(spread.results[0], plain.results[0]);
+        assert_eq!(duplicates(&spread.results), 1);
+
+        // the same through the index and a view
+        db.build_index().unwrap();
+        let graph = SearchRequest::new(&query, 3).plan(SearchPlan::Graph).mmr(0.3, 3);
+        assert_eq!(db.query(&graph).unwrap().results, diverse.results);
+        let view = db.read_view().unwrap();
+        assert_eq!(view.query(&graph).unwrap().results, diverse.results);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::db::{Database, DbIndex, DbVector, DbVectorSlice, Error};
+use crate::metric::Metric;
+use crate::ops;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How far from one the length of a [QueryVector::Normalized] may be.
+pub const NORM_TOLERANCE: f32 = 1e-3;
+
+/// # Query Vector
+/// A vector that says whether it's of unit length, for databases
+/// ranking by [Cosine](Metric::Cosine) or [DotProduct](Metric::DotProduct)
+/// to take it the way they expect, see [SearchRequest::from_vector](crate::db::SearchRequest::from_vector)
+/// and [Database::push_vector].
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryVector {
+    /// Of any length, normalized on the fly where it matters.
+    Raw(DbVector),
+    /// Of unit length, or [Error::NotNormalized] if it's off by more than [NORM_TOLERANCE].
+    Normalized(DbVector),
+}
+
+impl QueryVector {
+    pub fn as_slice(&self) -> DbVectorSlice<'_> {
+        match self {
+            QueryVector::Raw(vector) | QueryVector::Normalized(vector) => vector,
+        }
+    }
+
+    pub fn into_vector(self) -> DbVector {
+        match self {
+            QueryVector::Raw(vector) | QueryVector::Normalized(vector) => vector,
+        }
+    }
+
+    /// The vector the way a database ranking by `metric` takes it, along
+    /// with whether it was normalized to be, which only raw ones are if
+    /// the metric is by direction.
+    pub(crate) fn conform(self, metric: Metric) -> Result<(DbVector, bool), Error> {
+        match self {
+            QueryVector::Raw(mut vector) if by_direction(metric) => {
+                ops::normalize(&mut vector);
+                Ok((vector, true))
+            }
+            QueryVector::Raw(vector) => Ok((vector, false)),
+            QueryVector::Normalized(vector) => {
+                let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+                if (norm - 1f32).abs() > NORM_TOLERANCE || norm.is_nan() {
+                    return Err(Error::NotNormalized(norm));
+                }
+                Ok((vector, false))
+            }
+        }
+    }
+}
+
+/// Whether rankings by `metric` go wrong on vectors of differing length.
+fn by_direction(metric: Metric) -> bool {
+    matches!(metric, Metric::Cosine | Metric::DotProduct)
+}
+
+/// Counts the vectors normalized on the fly, shared with the
+/// [views](Database::read_view) of a database.
+#[derive(Debug, Default)]
+pub(crate) struct Normalizations {
+    pub(crate) queries: AtomicU64,
+    pub(crate) inserts: AtomicU64,
+}
+
+impl Normalizations {
+    pub(crate) fn reset(&self) {
+        self.queries.store(0, Ordering::Relaxed);
+        self.inserts.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Database {
+    /// Same as [Database::push] if [normalizing on insert](crate::db::DatabaseOptions::normalize_on_insert)
+    /// is off. Otherwise a [raw](QueryVector::Raw) `vector` is normalized
+    /// before it's stored if the metric is by direction, and a
+    /// [normalized](QueryVector::Normalized) one is checked to be.
+    pub fn push_vector(&mut self, vector: QueryVector) -> Result<DbIndex, Error> {
+        let dim_size = self.dim_size();
+        if !self.normalize_on_insert || vector.as_slice().len() != dim_size as usize {
+            return self.push_conformed(vector.as_slice());
+        }
+        let (vector, normalized) = vector.conform(self.metric)?;
+        self.normalizations
+            .inserts
+            .fetch_add(normalized as u64, Ordering::Relaxed);
+        self.push_conformed(&vector)
+    }
+
+    /// `vector` the way it's stored, normalized if normalizing on insert
+    /// and the metric is by direction. Left for the write to refuse if it's
+    /// of another dimension.
+    pub(crate) fn conform_insert<'a>(&self, vector: DbVectorSlice<'a>) -> Cow<'a, [f32]> {
+        if !self.normalize_on_insert
+            || !by_direction(self.metric)
+            || vector.len() != self.dim_size() as usize
+        {
+            return Cow::Borrowed(vector);
+        }
+        let mut vector = DbVector::from(vector);
+        ops::normalize(&mut vector);
+        self.normalizations.inserts.fetch_add(1, Ordering::Relaxed);
+        Cow::Owned(vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error, QueryVector, SearchRequest, NORM_TOLERANCE};
+    use crate::metric::Metric;
+    use std::io::Cursor;
+
+    const RAW: [f32; 4] = [3f32, 4f32, 0f32, 0f32];
+    const UNIT: [f32; 4] = [0.6, 0.8, 0f32, 0f32];
+
+    #[test]
+    fn query_vector_works() {
+        for metric in [Metric::Euclidean, Metric::Cosine, Metric::DotProduct] {
+            let mut db = DatabaseOptions::new(4)
+                .metric(metric)
+                .create("mem", Box::new(Cursor::new(Vec::new())))
+                .unwrap();
+            db.push([1f32, 0f32, 0f32, 0f32]).unwrap();
+            db.push([0f32, 2f32, 0f32, 0f32]).unwrap();
+            let by_direction = metric != Metric::Euclidean;
+
+            let raw = SearchRequest::from_vector(QueryVector::Raw(RAW.to_vec()), 2);
+            let expected = match by_direction {
+                true => db.query(&SearchRequest::new(&UNIT, 2)).unwrap(),
+                false => db.query(&SearchRequest::new(&RAW, 2)).unwrap(),
+            };
+            assert_eq!(db.query(&raw).unwrap().results, expected.results);
+            let view = db.read_view().unwrap();
+            assert_eq!(view.query(&raw).unwrap().results, expected.results);
+            assert_eq!(db.stats().unwrap().normalized_queries, 2 * by_direction as u64);
+
+            let unit = SearchRequest::from_vector(QueryVector::Normalized(UNIT.to_vec()), 2);
+            let expected = db.query(&SearchRequest::new(&UNIT, 2)).unwrap();
+            assert_eq!(db.query(&unit).unwrap().results, expected.results);
+            let off = SearchRequest::from_vector(QueryVector::Normalized(RAW.to_vec()), 2);
+            assert!(matches!(db.query(&off), Err(Error::NotNormalized(5f32))));
+            assert!(matches!(view.query(&off), Err(Error::NotNormalized(5f32))));
+            assert_eq!(db.stats().unwrap().normalized_queries, 2 * by_direction as u64);
+        }
+    }
+
+    #[test]
+    fn norm_tolerance_works() {
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        db.push([1f32; 4]).unwrap();
+        let scaled = |by: f32| {
+            let vector = UNIT.iter().map(|x| x * by).collect();
+            SearchRequest::from_vector(QueryVector::Normalized(vector), 1)
+        };
+        for within in [1f32 - NORM_TOLERANCE / 2f32, 1f32 + NORM_TOLERANCE / 2f32] {
+            assert!(db.query(&scaled(within)).is_ok());
+        }
+        for beyond in [1f32 - NORM_TOLERANCE * 2f32, 1f32 + NORM_TOLERANCE * 2f32] {
+            assert!(matches!(db.query(&scaled(beyond)), Err(Error::NotNormalized(_))));
+        }
+        let nan = SearchRequest::from_vector(QueryVector::Normalized(vec![f32::NAN; 4]), 1);
+        assert!(matches!(db.query(&nan), Err(Error::NotNormalized(_))));
+    }
+
+    #[test]
+    fn normalize_on_insert_works() {
+        for metric in [Metric::Euclidean, Metric::Cosine, Metric::DotProduct] {
+            let by_direction = metric != Metric::Euclidean;
+            let mut db = DatabaseOptions::new(4)
+                .metric(metric)
+                .normalize_on_insert(true)
+                .create("mem", Box::new(Cursor::new(Vec::new())))
+                .unwrap();
+            let stored = if by_direction { UNIT } else { RAW };
+            let id = db.push(RAW).unwrap();
+            assert_eq!(*db.get(id).unwrap().unwrap(), stored);
+            let id = db.push_vector(QueryVector::Raw(RAW.to_vec())).unwrap();
+            assert_eq!(*db.get(id).unwrap().unwrap(), stored);
+            let id = db.push_vector(QueryVector::Normalized(UNIT.to_vec())).unwrap();
+            assert_eq!(*db.get(id).unwrap().unwrap(), UNIT);
+            assert!(matches!(
+                db.push_vector(QueryVector::Normalized(RAW.to_vec())),
+                Err(Error::NotNormalized(5f32))
+            ));
+            db.update(id, RAW).unwrap();
+            assert_eq!(*db.get(id).unwrap().unwrap(), stored);
+            assert_eq!(db.count().unwrap(), 3);
+            assert_eq!(db.stats().unwrap().normalized_inserts, 3 * by_direction as u64);
+        }
+
+        // stored as pushed otherwise
+        let mut db = DatabaseOptions::new(4)
+            .metric(Metric::Cosine)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let id = db.push_vector(QueryVector::Normalized(RAW.to_vec())).unwrap();
+        assert_eq!(*db.get(id).unwrap().unwrap(), RAW);
+        assert_eq!(db.stats().unwrap().normalized_inserts, 0);
+    }
+}
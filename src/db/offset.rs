@@ -0,0 +1,104 @@
+use crate::db::Error;
+use std::cmp::min;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// # Offset Operation
+/// Arithmetic on positions and lengths in the file that overflowed, along
+/// with its operands, see [Error::OffsetOverflow]. Headers claiming records
+/// or sections too large for the file get there before any seek does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetOp {
+    Add(u64, u64),
+    Sub(u64, u64),
+    Mul(u64, u64),
+    /// A length too long to be held in memory or moved by on this target.
+    Cast(u64),
+}
+
+impl fmt::Display for OffsetOp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OffsetOp::Add(a, b) => write!(f, "{a} + {b}"),
+            OffsetOp::Sub(a, b) => write!(f, "{a} - {b}"),
+            OffsetOp::Mul(a, b) => write!(f, "{a} * {b}"),
+            OffsetOp::Cast(a) => write!(f, "{a} as a length in memory"),
+        }
+    }
+}
+
+pub(crate) fn add(a: u64, b: u64) -> Result<u64, Error> {
+    a.checked_add(b)
+        .ok_or(Error::OffsetOverflow(OffsetOp::Add(a, b)))
+}
+
+pub(crate) fn sub(a: u64, b: u64) -> Result<u64, Error> {
+    a.checked_sub(b)
+        .ok_or(Error::OffsetOverflow(OffsetOp::Sub(a, b)))
+}
+
+pub(crate) fn mul(a: u64, b: u64) -> Result<u64, Error> {
+    a.checked_mul(b)
+        .ok_or(Error::OffsetOverflow(OffsetOp::Mul(a, b)))
+}
+
+/// Where record `index` of `unit` bytes begins past `data_section`.
+pub(crate) fn record(data_section: u64, index: u64, unit: u64) -> Result<u64, Error> {
+    add(data_section, mul(index, unit)?)
+}
+
+/// Bytes moved at a time as records of `unit` bytes shift, ten of them up to a page.
+pub(crate) fn move_buffer_size(unit: u64) -> usize {
+    min(4096, unit.saturating_mul(10)) as usize
+}
+
+pub(crate) fn to_usize(a: u64) -> Result<usize, Error> {
+    a.try_into().map_err(|_| Error::OffsetOverflow(OffsetOp::Cast(a)))
+}
+
+/// `a` bytes to move or seek by, which only fit half the range of lengths.
+pub(crate) fn to_isize(a: u64) -> Result<isize, Error> {
+    a.try_into().map_err(|_| Error::OffsetOverflow(OffsetOp::Cast(a)))
+}
+
+pub(crate) fn to_i64(a: u64) -> Result<i64, Error> {
+    a.try_into().map_err(|_| Error::OffsetOverflow(OffsetOp::Cast(a)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::offset::{add, mul, record, sub, to_i64, to_isize};
+    use crate::db::{DatabaseOptions, Error, OffsetOp};
+    use crate::ext::mem::SparseAccess;
+
+    #[test]
+    fn offset_overflow_works() {
+        assert_eq!(record(100, 3, 20).unwrap(), 160);
+        assert!(matches!(
+            add(u64::MAX, 2),
+            Err(Error::OffsetOverflow(OffsetOp::Add(u64::MAX, 2)))
+        ));
+        assert!(matches!(sub(3, 4), Err(Error::OffsetOverflow(OffsetOp::Sub(3, 4)))));
+        let unit = 4 + (u32::MAX / 2) as u64 * 4;
+        assert!(matches!(
+            record(4096, u64::MAX / unit + 1, unit),
+            Err(Error::OffsetOverflow(OffsetOp::Mul(_, u))) if u == unit
+        ));
+        assert!(mul(u32::MAX as u64, u32::MAX as u64).is_ok());
+        assert!(matches!(to_i64(1 << 63), Err(Error::OffsetOverflow(OffsetOp::Cast(_)))));
+        assert!(to_isize(u64::MAX).is_err());
+    }
+
+    #[test]
+    fn appending_past_the_end_fails() {
+        // as if the file were as long as offsets go, so that the record
+        // appended would end past it
+        let fd = SparseAccess::new(u64::MAX - 5);
+        let mut db = DatabaseOptions::new(4).create("mem", Box::new(fd)).unwrap();
+        let pushed = db.push([1f32; 4]);
+        assert!(matches!(
+            pushed,
+            Err(Error::OffsetOverflow(OffsetOp::Add(a, 20))) if a == u64::MAX - 5
+        ));
+    }
+}
@@ -0,0 +1,702 @@
+use crate::db::{
+    AdvisoryConfig, CacheMode, Clock, Database, Element, Error, Expectations,
+    ExternalChangePolicy, HnswConfig,
+    IdStrategy, IndexPolicy, KeyTableParams, MonotonicClock, OpenReport, PlannerConfig, Quota, SearchParams,
+    Severity, SyncPolicy, VectorStore,
+};
+use crate::db::latency::Latencies;
+use crate::ds::bloom::BloomParams;
+use crate::ext::semaphore::LockAutoClear;
+use crate::index::ShortcutCache;
+use crate::metric::Metric;
+use crate::vio;
+use crate::vio::dbheader::DbHeader;
+use crate::vio::format::CURRENT_VERSION;
+use crate::vio::RandomAccess;
+use std::fmt;
+use std::fmt::Formatter;
+use std::io::{Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// # Database Options
+/// Everything a database is opened or created with. Start from
+/// [DatabaseOptions::new] and finish with [DatabaseOptions::create]
+/// or [DatabaseOptions::open], e.g.
+/// `DatabaseOptions::new(768).metric(Metric::Cosine).cache_bytes(64 << 20).create(name, fd)`.
+///
+/// Options that shape the file only apply when creating it,
+/// afterwards they're read from the header.
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseOptions {
+    dim_size: Option<u32>,
+    metric: Option<Metric>,
+    config: HnswConfig,
+    planner: PlannerConfig,
+    sync_policy: SyncPolicy,
+    cache_mode: CacheMode,
+    read_only: bool,
+    verify: bool,
+    migrate: bool,
+    strict: Option<Severity>,
+    id_strategy: Option<IdStrategy>,
+    quota: Option<Quota>,
+    alignment: Option<u32>,
+    bloom: Option<BloomParams>,
+    history: bool,
+    reuse_slots: bool,
+    keys: Option<KeyTableParams>,
+    levels: Option<u32>,
+    placeholders: Option<u32>,
+    groups: Option<u32>,
+    layer_section: Option<u64>,
+    expectations: Expectations,
+    advisory: AdvisoryConfig,
+    latency: Option<Arc<dyn Clock>>,
+    deadline_clock: Option<Arc<dyn Clock>>,
+    watch: Option<(ExternalChangePolicy, Option<Duration>)>,
+    shortcuts: usize,
+    results: usize,
+    normalize_on_insert: bool,
+    index_policy: IndexPolicy,
+}
+
+/// # Options Error
+/// Why a set of [DatabaseOptions] doesn't make sense.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionsError {
+    /// Creating a database writes its header.
+    ReadOnlyCreate,
+    /// Creating a database takes a dimension, see [DatabaseOptions::new].
+    MissingDimension,
+    /// Names an option that shapes the file, which an existing one already has.
+    CreateOnly(&'static str),
+    /// Append-only databases keep removed ids in their history.
+    HistoryReusesIds,
+    /// Append-only databases keep their history along with the records
+    /// in the file, not in a [VectorStore].
+    HistoryInStore,
+    /// Only append-only databases have slots to reuse.
+    ReuseWithoutHistory,
+    /// Names a parameter of the [HnswConfig] out of its range.
+    InvalidParam(&'static str),
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OptionsError::ReadOnlyCreate => write!(f, "can't create a read-only database"),
+            OptionsError::MissingDimension => write!(f, "no dimension to create with"),
+            OptionsError::CreateOnly(name) => write!(f, "{name} only applies when creating"),
+            OptionsError::HistoryReusesIds => {
+                write!(f, "append-only databases can't reuse ids")
+            }
+            OptionsError::HistoryInStore => {
+                write!(f, "append-only databases can't keep records in a store")
+            }
+            OptionsError::ReuseWithoutHistory => {
+                write!(f, "only append-only databases can reuse slots")
+            }
+            OptionsError::InvalidParam(name) => write!(f, "{name} is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for OptionsError {}
+
+impl DatabaseOptions {
+    /// Options for databases of `dim_size` dimensions. Opening one of any
+    /// dimension takes [DatabaseOptions::default] instead.
+    pub fn new(dim_size: u32) -> DatabaseOptions {
+        DatabaseOptions {
+            dim_size: Some(dim_size),
+            ..DatabaseOptions::default()
+        }
+    }
+
+    /// Recorded in the file when creating. When opening, overrides the
+    /// recorded one, or the default for files older than version 8.
+    pub fn metric(mut self, metric: Metric) -> Self {
+        self.metric = Some(metric);
+        self
+    }
+
+    /// Index parameters. When opening, those persisted in the file take
+    /// precedence, which are the search parameters over `ef_search`, and
+    /// since version 16 what the index is built with over `m`, `m0`,
+    /// `ef_construction` and `diverse_neighbors`.
+    pub fn hnsw(mut self, config: HnswConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// When queries go through the index rather than scanning, see [Database::set_planner].
+    pub fn planner(mut self, config: PlannerConfig) -> Self {
+        self.planner = config;
+        self
+    }
+
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Bounds the memory vectors are cached in, evicting the oldest ones beyond it.
+    /// Same as [CacheMode::Bounded].
+    pub fn cache_bytes(self, budget: u64) -> Self {
+        self.cache_mode(CacheMode::Bounded(budget))
+    }
+
+    pub fn cache_mode(mut self, mode: CacheMode) -> Self {
+        self.cache_mode = mode;
+        self
+    }
+
+    /// Refuses writes with [Error::ReadOnly], so that the file may be opened read-only.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Checks the structure of the file before opening it, see
+    /// [inspect](crate::vio::inspect), and fails with
+    /// [Error::Inconsistent] at the first problem.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Upgrades files of older versions to the current one on open, see
+    /// [migrate](crate::vio::migrate). Without it, they fail to open with
+    /// [Error::NeedsMigration] unless opened read-only, which reads them as they are.
+    pub fn migrate(mut self, migrate: bool) -> Self {
+        self.migrate = migrate;
+        self
+    }
+
+    /// Fails opening with [Error::Anomaly] at the first anomaly of at least
+    /// `severity`, see [DatabaseOptions::open_with_report]. Otherwise, they're
+    /// only reported.
+    pub fn strict(mut self, severity: Severity) -> Self {
+        self.strict = Some(severity);
+        self
+    }
+
+    pub fn id_strategy(mut self, strategy: IdStrategy) -> Self {
+        self.id_strategy = Some(strategy);
+        self
+    }
+
+    pub fn quota(mut self, quota: Quota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Pads every record to a multiple of `alignment` bytes, so none straddles
+    /// a block of that size, at the cost of the padding, see [DbStats](crate::db::DbStats).
+    /// Zero means no padding.
+    pub fn alignment(mut self, alignment: u32) -> Self {
+        self.alignment = Some(alignment);
+        self
+    }
+
+    /// Keeps a bloom filter of the stored ids so that looking up absent ones
+    /// rarely touches the file. It's sized for `expected` vectors to be falsely
+    /// reported as present at `false_positive_rate`, and persisted on [Database::flush].
+    pub fn bloom_filter(mut self, expected: u64, false_positive_rate: f64) -> Self {
+        self.bloom = Some(BloomParams::for_rate(expected, false_positive_rate));
+        self
+    }
+
+    /// Keeps up to `capacity` external keys of at most `max_key_bytes` bytes
+    /// along with the records, reserving room for as many of the longest ones
+    /// in the file, see [Database::push_keyed]. They're persisted on [Database::flush].
+    pub fn key_table(mut self, capacity: u32, max_key_bytes: u8) -> Self {
+        self.keys = Some(KeyTableParams {
+            capacity,
+            max_key_bytes,
+        });
+        self
+    }
+
+    /// Keeps the top layer of each node of the index for up to `capacity`
+    /// vectors in the file, written on [Database::flush] and read on open,
+    /// see [Database::node_level].
+    pub fn level_table(mut self, capacity: u32) -> Self {
+        self.levels = Some(capacity);
+        self
+    }
+
+    /// Keeps a table of the ids [reserved](Database::reserve_ids) and not
+    /// filled yet in the file, over a window of `capacity` ids beginning
+    /// at the lowest of them.
+    pub fn placeholder_table(mut self, capacity: u32) -> Self {
+        self.placeholders = Some(capacity);
+        self
+    }
+
+    /// Keeps the group of up to `capacity` records in the file, written on
+    /// [Database::flush] and read on open, see [Database::push_grouped].
+    pub fn group_table(mut self, capacity: u32) -> Self {
+        self.groups = Some(capacity);
+        self
+    }
+
+    /// Reserves `bytes` in the file for the layers of the index ahead of the
+    /// records, which [Database::flush] writes them into from then on.
+    /// Without it, or once they outgrow it, the first flush to write them
+    /// makes room by moving the records, which reserving enough up front spares.
+    pub fn layer_section(mut self, bytes: u64) -> Self {
+        self.layer_section = Some(bytes);
+        self
+    }
+
+    /// Makes the database append-only, where removals and updates are
+    /// written as new records instead of rewriting old ones, so that it can be
+    /// read as it was at any [Generation](crate::db::Generation), see [Database::at_generation].
+    pub fn history(mut self, history: bool) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Lets an append-only database write new records into the slots of
+    /// those no longer read instead of appending them, so that the file
+    /// stops growing once as many are removed as are pushed. The price is
+    /// its history: only the latest [Generation](crate::db::Generation) can
+    /// be read, even though the records still carry one. Takes [DatabaseOptions::history].
+    pub fn reuse_slots(mut self, reuse: bool) -> Self {
+        self.reuse_slots = reuse;
+        self
+    }
+
+    /// Records how long [get](Database::get), [push](Database::push), [query](Database::query),
+    /// [remove](Database::remove) and [flush](Database::flush) take, see [DbStats::latency](crate::db::DbStats::latency).
+    /// Left off, it costs a branch per operation.
+    pub fn latency_stats(mut self, enabled: bool) -> Self {
+        self.latency = enabled.then(|| Arc::new(MonotonicClock::default()) as Arc<dyn Clock>);
+        self
+    }
+
+    /// Records latencies as told by `clock` rather than [MonotonicClock].
+    pub fn latency_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.latency = Some(clock);
+        self
+    }
+
+    /// Tells whether [deadlines](crate::db::SearchRequest::deadline) have passed,
+    /// and times the operations [traced](Database::start_trace), by `clock`
+    /// rather than [MonotonicClock].
+    pub fn deadline_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.deadline_clock = Some(clock);
+        self
+    }
+
+    /// Watches for the file being changed by someone else while it's open,
+    /// such as another process or a backup restored over it, and deals with
+    /// it by `policy`, see [Database::check_external_changes]. [Database::maintain]
+    /// checks every `interval` if there's one, as told by the
+    /// [deadline clock](DatabaseOptions::deadline_clock).
+    pub fn watch_external_changes(
+        mut self,
+        policy: ExternalChangePolicy,
+        interval: Option<Duration>,
+    ) -> Self {
+        self.watch = Some((policy, interval));
+        self
+    }
+
+    /// Remembers where on layer 1 of the index the last `capacity` distinct
+    /// kinds of queries landed, so that similar ones skip the layers above,
+    /// see [DbStats::shortcut_hit_rate](crate::db::DbStats::shortcut_hit_rate).
+    /// Queries are told apart by the signs of their first 64 dimensions.
+    /// Off unless `capacity` is positive.
+    pub fn entry_shortcuts(mut self, capacity: usize) -> Self {
+        self.shortcuts = capacity;
+        self
+    }
+
+    /// Caches the responses to the last `capacity` distinct queries,
+    /// see [Database::set_result_cache]. Off unless `capacity` is positive.
+    pub fn result_cache(mut self, capacity: usize) -> Self {
+        self.results = capacity;
+        self
+    }
+
+    /// Normalizes the vectors pushed or updated if the metric ranks by
+    /// direction, and checks those pushed as [normalized](crate::db::QueryVector::Normalized)
+    /// to be, see [Database::push_vector].
+    pub fn normalize_on_insert(mut self, enabled: bool) -> Self {
+        self.normalize_on_insert = enabled;
+        self
+    }
+
+    /// How queries are answered while the index isn't ready,
+    /// see [Database::set_index_policy].
+    pub fn index_policy(mut self, policy: IndexPolicy) -> Self {
+        self.index_policy = policy;
+        self
+    }
+
+    /// When to advise against the way the database is written, see [Database::write_advisory].
+    pub fn write_advisory(mut self, config: AdvisoryConfig) -> Self {
+        self.advisory = config;
+        self
+    }
+
+    /// Fails opening with [Error::SchemaMismatch] unless the database has
+    /// `dim` dimensions. Unlike [DatabaseOptions::new], the error lists
+    /// every other expectation that isn't met too.
+    pub fn expect_dim(mut self, dim: u32) -> Self {
+        self.expectations.dim = Some(dim);
+        self
+    }
+
+    /// Same as [DatabaseOptions::expect_dim] for the metric recorded at creation.
+    pub fn expect_metric(mut self, metric: Metric) -> Self {
+        self.expectations.metric = Some(metric);
+        self
+    }
+
+    pub fn expect_element(mut self, element: Element) -> Self {
+        self.expectations.element = Some(element);
+        self
+    }
+
+    /// Sets every expectation at once, see [DatabaseOptions::expect_dim].
+    pub fn expect(mut self, expectations: Expectations) -> Self {
+        self.expectations = expectations;
+        self
+    }
+
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Writes a new database into `fd`.
+    pub fn create(self, name: &str, fd: Box<dyn RandomAccess>) -> Result<Database, Error> {
+        self.create_in(name, fd, None)
+    }
+
+    /// Same as [DatabaseOptions::create], keeping the records in `store`
+    /// while `fd` only holds the rest.
+    pub fn create_with_store(
+        self,
+        name: &str,
+        fd: Box<dyn RandomAccess>,
+        store: Box<dyn VectorStore>,
+    ) -> Result<Database, Error> {
+        if self.history {
+            return Err(Error::Options(OptionsError::HistoryInStore));
+        }
+        self.create_in(name, fd, Some(store))
+    }
+
+    fn create_in(
+        self,
+        name: &str,
+        fd: Box<dyn RandomAccess>,
+        store: Option<Box<dyn VectorStore>>,
+    ) -> Result<Database, Error> {
+        let dim_size = self
+            .dim_size
+            .ok_or(Error::Options(OptionsError::MissingDimension))?;
+        self.config.check().map_err(Error::Options)?;
+        if self.read_only {
+            return Err(Error::Options(OptionsError::ReadOnlyCreate));
+        }
+        if self.history && self.id_strategy == Some(IdStrategy::Reuse) {
+            return Err(Error::Options(OptionsError::HistoryReusesIds));
+        }
+        if self.reuse_slots && !self.history {
+            return Err(Error::Options(OptionsError::ReuseWithoutHistory));
+        }
+        let mut header = DbHeader::new(
+            dim_size,
+            self.id_strategy.unwrap_or_default(),
+            self.quota.unwrap_or_default(),
+        )
+        .with_metric(self.metric.unwrap_or_default());
+        header.search = Some(SearchParams {
+            ef_search: self.config.ef_search,
+            ..SearchParams::default()
+        });
+        header.hnsw = Some(self.config);
+        if let Some(params) = self.bloom {
+            header = header.with_bloom(params);
+        }
+        if let Some(params) = self.keys {
+            header = header.with_keys(params);
+        }
+        if let Some(capacity) = self.levels {
+            header = header.with_levels(capacity);
+        }
+        if let Some(capacity) = self.placeholders {
+            header = header.with_placeholders(capacity);
+        }
+        if let Some(capacity) = self.groups {
+            header = header.with_groups(capacity);
+        }
+        if let Some(bytes) = self.layer_section {
+            header = header.with_layer_section(bytes);
+        }
+        if self.history {
+            header = header.with_history();
+        }
+        if self.reuse_slots {
+            header = header.with_reuse_slots();
+        }
+        if let Some(alignment) = self.alignment {
+            header = header.with_alignment(alignment);
+        }
+        let db = Database::create(name, header, fd, store)?;
+        self.apply(db)
+    }
+
+    /// Reads an existing database from `fd`.
+    pub fn open(self, name: &str, fd: Box<dyn RandomAccess>) -> Result<Database, Error> {
+        self.open_in(name, fd, None).map(|(db, _)| db)
+    }
+
+    /// Same as [DatabaseOptions::open], also reporting the anomalies
+    /// the file was read past, each with what to do about it.
+    pub fn open_with_report(
+        self,
+        name: &str,
+        fd: Box<dyn RandomAccess>,
+    ) -> Result<(Database, OpenReport), Error> {
+        self.open_in(name, fd, None)
+    }
+
+    /// Same as [DatabaseOptions::open] for a database created with
+    /// [DatabaseOptions::create_with_store], whose records are in `store`.
+    pub fn open_with_store(
+        self,
+        name: &str,
+        fd: Box<dyn RandomAccess>,
+        store: Box<dyn VectorStore>,
+    ) -> Result<Database, Error> {
+        self.open_in(name, fd, Some(store)).map(|(db, _)| db)
+    }
+
+    fn open_in(
+        self,
+        name: &str,
+        mut fd: Box<dyn RandomAccess>,
+        store: Option<Box<dyn VectorStore>>,
+    ) -> Result<(Database, OpenReport), Error> {
+        let create_only = [
+            ("id_strategy", self.id_strategy.is_some()),
+            ("quota", self.quota.is_some()),
+            ("alignment", self.alignment.is_some()),
+            ("bloom_filter", self.bloom.is_some()),
+            ("history", self.history),
+            ("reuse_slots", self.reuse_slots),
+            ("key_table", self.keys.is_some()),
+            ("level_table", self.levels.is_some()),
+            ("placeholder_table", self.placeholders.is_some()),
+            ("group_table", self.groups.is_some()),
+            ("layer_section", self.layer_section.is_some()),
+        ];
+        if let Some((option, _)) = create_only.iter().find(|(_, set)| *set) {
+            return Err(Error::Options(OptionsError::CreateOnly(option)));
+        }
+        self.config.check().map_err(Error::Options)?;
+        if !self.read_only {
+            self.upgrade(&mut fd)?;
+        }
+        if self.verify {
+            let layout = vio::inspect(&mut fd).map_err(|e| match e {
+                vio::Error::Eof => Error::Parse(),
+                vio::Error::IO(e) => Error::IO(e),
+            })?;
+            if let Some(first) = layout.inconsistencies.into_iter().next() {
+                return Err(Error::Inconsistent(first));
+            }
+            fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        }
+        let (db, report) = Database::open(name, fd, store, false)?;
+        if let Some(strict) = self.strict {
+            let severe = report.anomalies.iter().find(|a| a.severity() >= strict);
+            if let Some(anomaly) = severe {
+                return Err(Error::Anomaly(anomaly.clone()));
+            }
+        }
+        self.expectations.check(&db)?;
+        if let Some(dim_size) = self.dim_size {
+            if db.dim_size() != dim_size {
+                return Err(Error::Dimension(dim_size, db.dim_size() as usize));
+            }
+        }
+        Ok((self.apply(db)?, report))
+    }
+
+    /// Migrates the file if it's of an older version and that's allowed.
+    fn upgrade(&self, fd: &mut Box<dyn RandomAccess>) -> Result<(), Error> {
+        if fd.seek(SeekFrom::End(0)).map_err(Error::IO)? == 0 {
+            return Ok(());
+        }
+        fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        let version = vio::dbheader::read(fd).map_err(Error::Header)?.version;
+        if version < CURRENT_VERSION {
+            if !self.migrate {
+                return Err(Error::NeedsMigration(version, CURRENT_VERSION));
+            }
+            vio::migrate(fd.as_mut(), CURRENT_VERSION)?;
+        }
+        fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+        Ok(())
+    }
+
+    fn apply(self, mut db: Database) -> Result<Database, Error> {
+        if let Some(metric) = self.metric {
+            db.metric = metric;
+        }
+        db.config = match db.recorded_hnsw {
+            Some(recorded) => self.config.built_like(recorded),
+            None => self.config,
+        };
+        db.set_planner(self.planner);
+        db.read_only = self.read_only;
+        db.normalize_on_insert = self.normalize_on_insert;
+        db.index_policy = self.index_policy;
+        db.loaded_vectors
+            .lock_auto_clear_poison()
+            .set_mode(self.cache_mode);
+        let mut handle = db.handle.lock_auto_clear_poison();
+        handle.sync_policy = self.sync_policy;
+        handle.amplification.set_config(self.advisory);
+        drop(handle);
+        db.latencies = self.latency.map(|clock| Arc::new(Latencies::new(clock)));
+        if let Some(clock) = self.deadline_clock {
+            db.clock = clock;
+        }
+        let counts = db.counters.shortcuts.clone();
+        db.shortcuts = (self.shortcuts > 0).then(|| Mutex::new(ShortcutCache::new(self.shortcuts, counts)));
+        db.set_result_cache(self.results);
+        if let Some((policy, interval)) = self.watch {
+            db.watch(policy, interval)?;
+        }
+        Ok(db)
+    }
+}
+
+impl Database {
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fails with [Error::ReadOnly] if the database was opened read-only.
+    pub(crate) fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            Err(Error::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error, IdStrategy, OptionsError, SyncPolicy};
+    use crate::ext::mem::{CountingAccess, SharedCursor};
+    use crate::metric::Metric;
+    use std::io::{Seek, SeekFrom, Write};
+
+    #[test]
+    fn options_apply() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .metric(Metric::Cosine)
+            .sync_policy(SyncPolicy::Never)
+            .alignment(32)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.metric(), Metric::Cosine);
+        assert_eq!(db.sync_policy(), SyncPolicy::Never);
+        for i in 0..4 {
+            db.push([i as f32; 4]).unwrap();
+        }
+
+        let counting = CountingAccess::new(file.reopen());
+        let mut db = DatabaseOptions::new(4)
+            .cache_bytes(32)
+            .read_only(true)
+            .verify(true)
+            .open("mem", Box::new(counting.clone()))
+            .unwrap();
+        assert_eq!(db.stats().unwrap().padding_per_record, 12);
+        assert!(matches!(db.remove(0), Err(Error::ReadOnly)));
+        assert!(matches!(db.push([0f32; 4]), Err(Error::ReadOnly)));
+        assert_eq!(db.flush().unwrap(), 0);
+
+        // two vectors fit into the cache, so the first is gone by the third
+        for id in 0..3 {
+            db.get(id).unwrap();
+        }
+        let seeks = counting.seeks();
+        db.get(2).unwrap();
+        assert_eq!(counting.seeks(), seeks);
+        db.get(0).unwrap();
+        assert!(counting.seeks() > seeks);
+        assert_eq!(counting.written(), 0);
+    }
+
+    #[test]
+    fn incompatible_options_rejected() {
+        let file = SharedCursor::new();
+        let create = |options: DatabaseOptions| options.create("mem", Box::new(file.reopen()));
+        assert!(matches!(
+            create(DatabaseOptions::new(4).read_only(true)),
+            Err(Error::Options(OptionsError::ReadOnlyCreate))
+        ));
+        assert!(matches!(
+            create(DatabaseOptions::default()),
+            Err(Error::Options(OptionsError::MissingDimension))
+        ));
+        assert!(matches!(
+            create(
+                DatabaseOptions::new(4)
+                    .history(true)
+                    .id_strategy(IdStrategy::Reuse)
+            ),
+            Err(Error::Options(OptionsError::HistoryReusesIds))
+        ));
+
+        let mut db = create(DatabaseOptions::new(4)).unwrap();
+        db.push([1f32; 4]).unwrap();
+        let open = |options: DatabaseOptions| options.open("mem", Box::new(file.reopen()));
+        assert!(matches!(
+            open(DatabaseOptions::new(4).alignment(64)),
+            Err(Error::Options(OptionsError::CreateOnly("alignment")))
+        ));
+        assert!(matches!(
+            open(DatabaseOptions::new(8)),
+            Err(Error::Dimension(8, 4))
+        ));
+        assert_eq!(open(DatabaseOptions::default()).unwrap().dim_size(), 4);
+
+        // half a record dangling off the end
+        let mut fd = file.reopen();
+        fd.seek(SeekFrom::End(0)).unwrap();
+        fd.write_all(&[0u8; 6]).unwrap();
+        assert!(matches!(
+            open(DatabaseOptions::default()),
+            Err(Error::Corrupted(_))
+        ));
+        assert!(matches!(
+            open(DatabaseOptions::default().verify(true)),
+            Err(Error::Inconsistent(_))
+        ));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_constructors_work() {
+        use crate::db::Database;
+        let file = SharedCursor::new();
+        let mut db = Database::new("mem", 2, Box::new(file.reopen()));
+        db.push([1f32; 2]).unwrap();
+        let db = Database::read("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(*db.get(0).unwrap().unwrap(), vec![1f32; 2]);
+    }
+}
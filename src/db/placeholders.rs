@@ -0,0 +1,380 @@
+use crate::db::{offset, Database, DbIndex, DbVector, DbVectorSlice, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use crate::vio;
+use crate::vio::format;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::cmp::min;
+use std::collections::BTreeSet;
+use std::io::{Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// # Placeholder Table
+/// Ids [reserved](Database::reserve_ids) whose records hold zeros until
+/// they're [filled](Database::fill), read from the block after the level
+/// table's on open and written as they change. Reads pass them over as if
+/// they weren't stored.
+pub(crate) struct PlaceholderTable {
+    capacity: u32,
+    /// Where its block begins.
+    offset: u64,
+    /// First id of the window of `capacity` ids the block has a bit for.
+    base: DbIndex,
+    unfilled: BTreeSet<DbIndex>,
+    /// Those already unfilled when the database was opened,
+    /// see [Anomaly::UnfilledSlots](crate::db::Anomaly::UnfilledSlots).
+    inherited: BTreeSet<DbIndex>,
+}
+
+impl PlaceholderTable {
+    pub(crate) fn new(
+        capacity: u32,
+        offset: u64,
+        base: DbIndex,
+        unfilled: Vec<DbIndex>,
+    ) -> PlaceholderTable {
+        let unfilled = unfilled.into_iter().collect::<BTreeSet<_>>();
+        PlaceholderTable {
+            capacity,
+            offset,
+            base,
+            inherited: unfilled.clone(),
+            unfilled,
+        }
+    }
+
+    /// Where its block ends.
+    pub(crate) fn end(&self) -> u64 {
+        self.offset + vio::placeholders::block_size(self.capacity)
+    }
+
+    /// Moves the window to begin at the lowest of `ids` and those unfilled
+    /// and flags `ids`, failing with [Error::PlaceholderTableFull] if it
+    /// can't hold them all.
+    fn take_in(&mut self, ids: Range<DbIndex>) -> Result<(), Error> {
+        let low = self
+            .unfilled
+            .first()
+            .map_or(ids.start, |first| min(*first, ids.start));
+        if ids.end - low > self.capacity {
+            return Err(Error::PlaceholderTableFull(self.capacity));
+        }
+        self.base = low;
+        self.unfilled.extend(ids);
+        Ok(())
+    }
+
+    /// Unflags `id`, returning whether it was flagged.
+    fn settle(&mut self, id: DbIndex) -> bool {
+        self.inherited.remove(&id);
+        self.unfilled.remove(&id)
+    }
+}
+
+impl VectorHandle {
+    /// Whether `id` was reserved and not filled yet.
+    pub(crate) fn is_unfilled(&self, id: DbIndex) -> bool {
+        self.placeholders
+            .as_ref()
+            .is_some_and(|table| table.unfilled.contains(&id))
+    }
+
+    pub(crate) fn unfilled_count(&self) -> u64 {
+        self.placeholders
+            .as_ref()
+            .map_or(0, |table| table.unfilled.len() as u64)
+    }
+
+    /// The ids reserved and not filled yet, for scans that can't
+    /// ask the handle while they read through it.
+    pub(crate) fn unfilled(&self) -> BTreeSet<DbIndex> {
+        self.placeholders
+            .as_ref()
+            .map_or_else(BTreeSet::new, |table| table.unfilled.clone())
+    }
+
+    /// Ids unfilled since before the database was opened, ascending.
+    pub(super) fn inherited_unfilled(&self) -> Vec<DbIndex> {
+        self.placeholders
+            .as_ref()
+            .map_or_else(Vec::new, |table| table.inherited.iter().copied().collect())
+    }
+
+    /// Takes over the table read on open, leaving out the ids flagged whose
+    /// records never made it to the file, as when reserving was cut short.
+    pub(super) fn adopt_placeholders(&mut self, mut table: PlaceholderTable) -> Result<(), Error> {
+        let mut missing = vec![];
+        for id in &table.unfilled {
+            if self.seek_item(*id)?.is_none() {
+                missing.push(*id);
+            }
+        }
+        for id in missing {
+            table.settle(id);
+        }
+        self.placeholders = Some(table);
+        Ok(())
+    }
+
+    /// Writes the whole block of the table.
+    fn write_placeholders(&mut self) -> Result<(), Error> {
+        let table = self.placeholders.as_ref().unwrap();
+        self.fd
+            .seek(SeekFrom::Start(table.offset))
+            .map_err(Error::IO)?;
+        let written = vio::placeholders::write(
+            &mut self.fd,
+            table.base,
+            table.capacity,
+            table.unfilled.iter().copied(),
+        )
+        .map_err(Error::IO)?;
+        self.written(written)
+    }
+
+    /// Writes the byte of the block holding the bit of `id` alone.
+    fn write_placeholder_bit(&mut self, id: DbIndex) -> Result<(), Error> {
+        let table = self.placeholders.as_ref().unwrap();
+        let index = id - table.base;
+        let first = id - index % 8;
+        let byte = table
+            .unfilled
+            .range(first..=first.saturating_add(7))
+            .fold(0u8, |byte, id| byte | (1 << (id - first)));
+        self.fd
+            .seek(SeekFrom::Start(table.offset + vio::placeholders::byte_offset(index)))
+            .map_err(Error::IO)?;
+        self.fd.write_u8(byte).map_err(Error::IO)?;
+        self.written(1)
+    }
+
+    fn reserve(&mut self, n: u32) -> Result<Range<DbIndex>, Error> {
+        let before = self.revision;
+        if self.history.is_some() || self.store.is_some() {
+            return Err(Error::Unsupported("reserve_ids"));
+        }
+        if self.placeholders.is_none() {
+            return Err(Error::NoPlaceholderTable);
+        }
+        self.fill_data_section()?;
+        let (available, last_id) = self.append_state()?;
+        let start = match last_id {
+            Some(last) => last.checked_add(1).ok_or(Error::IdSpaceExhausted)?,
+            None => 0,
+        };
+        let end = start.checked_add(n).ok_or(Error::IdSpaceExhausted)?;
+        if n == 0 {
+            return Ok(start..end);
+        }
+        self.placeholders.as_mut().unwrap().take_in(start..end)?;
+        // flagged before the records are written, so that none is ever read as zeros
+        self.write_placeholders()?;
+
+        let unit = self.unit_size_bytes();
+        let padding = offset::to_usize(unit - self.record_size_bytes())?;
+        let zeros = vec![0f32; self.dim_size as usize];
+        let mut records = Vec::with_capacity(offset::to_usize(offset::mul(n as u64, unit)?)?);
+        for id in start..end {
+            self.insert_bloom(id)?;
+            records.write_u32::<BigEndian>(id).map_err(Error::IO)?;
+            vio::vector::write(&zeros, &mut records).map_err(Error::IO)?;
+            records.resize(records.len() + padding, 0);
+        }
+        self.fd.seek(SeekFrom::Start(available)).map_err(Error::IO)?;
+        self.fd.write_all(&records).map_err(Error::IO)?;
+        let bytes = records.len() as u64;
+        self.writer.know(offset::add(available, bytes)?, Some(end - 1));
+        self.written(bytes)?;
+        self.amplified(bytes, 0);
+        for id in start..end {
+            self.iterators.record(self.revision, id, None);
+        }
+        self.digest(before, &[], &[]);
+        Ok(start..end)
+    }
+
+    fn fill(&mut self, id: DbIndex, vector: DbVectorSlice) -> Result<(), Error> {
+        let before = self.revision;
+        if vector.len() != self.dim_size as usize {
+            return Err(Error::Dimension(self.dim_size, vector.len()));
+        }
+        if self.placeholders.is_none() {
+            return Err(Error::NoPlaceholderTable);
+        }
+        if !self.is_unfilled(id) {
+            return Err(if self.contains(id) {
+                Error::AlreadyFilled(id)
+            } else {
+                Error::NotReserved(id)
+            });
+        }
+        let pos = self.seek_item(id)?.ok_or(Error::NotReserved(id))?;
+        self.fd
+            .seek(SeekFrom::Start(offset::add(pos, format::RECORD_ID_WIDTH)?))
+            .map_err(Error::IO)?;
+        vio::vector::write(vector, &mut self.fd).map_err(Error::IO)?;
+        self.written(size_of_val(vector) as u64)?;
+        self.amplified(size_of_val(vector) as u64, 0);
+        // absent before, as far as the iterators go
+        self.iterators.record(self.revision, id, None);
+        self.placeholders.as_mut().unwrap().settle(id);
+        self.write_placeholder_bit(id)?;
+        self.digest(before, &[], &[(id, vector)]);
+        Ok(())
+    }
+
+    /// Unflags `id` once its record is removed, returning whether it was flagged.
+    pub(super) fn forget_placeholder(&mut self, id: DbIndex) -> Result<bool, Error> {
+        let Some(table) = self.placeholders.as_mut() else {
+            return Ok(false);
+        };
+        if !table.settle(id) {
+            return Ok(false);
+        }
+        self.write_placeholder_bit(id)?;
+        Ok(true)
+    }
+}
+
+impl Database {
+    /// Sets aside `n` ids past the last one stored for vectors yet to come,
+    /// each taking a record of zeros flagged in the
+    /// [placeholder table](crate::db::DatabaseOptions::placeholder_table),
+    /// so that ids can be handed out before the vectors are at hand. Until
+    /// [filled](Database::fill), reads and searches pass them over as if they
+    /// weren't stored, and [removing](Database::remove) one gives it up.
+    /// Fails with [Error::PlaceholderTableFull] if the table can't hold them
+    /// along with those still unfilled.
+    pub fn reserve_ids(&mut self, n: u32) -> Result<Range<DbIndex>, Error> {
+        self.check_writable()?;
+        self.drain_queue()?;
+        self.check_quota(n as u64)?;
+        let (mut handle, _records) = self.lock_records();
+        handle.reserve(n)
+    }
+
+    /// Writes `vector` into the record of the [reserved](Database::reserve_ids)
+    /// id `id` in place, which reads and searches see from then on. Fails with
+    /// [Error::AlreadyFilled] if `id` holds a vector already, whether filled
+    /// or pushed, and with [Error::NotReserved] if it holds nothing.
+    pub fn fill<V: AsRef<[f32]>>(&mut self, id: DbIndex, vector: V) -> Result<(), Error> {
+        self.check_writable()?;
+        let vector = &*self.conform_insert(vector.as_ref());
+        self.drain_queue()?;
+        {
+            let (mut handle, _records) = self.lock_records();
+            handle.fill(id, vector)?;
+        }
+        self.loaded_vectors
+            .lock_auto_clear_poison()
+            .insert(id, Arc::new(DbVector::from(vector)));
+        self.index_insert(id, vector)
+    }
+
+    /// Ids [reserved](Database::reserve_ids) and not filled yet, ascending.
+    pub fn unfilled_ids(&self) -> Vec<DbIndex> {
+        let handle = self.handle.lock_auto_clear_poison();
+        handle.unfilled().into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        Anomaly, Database, DatabaseOptions, Error, SearchPlan, SearchRequest, SuggestedAction,
+        VerifyLevel,
+    };
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+
+    fn reopen(file: &SharedCursor) -> Database {
+        DatabaseOptions::default()
+            .verify(true)
+            .open("mem", Box::new(file.reopen()))
+            .unwrap()
+    }
+
+    #[test]
+    fn placeholders_work() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .placeholder_table(128)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut rng = XorShift::new(8);
+        for _ in 0..10 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        let reserved = db.reserve_ids(100).unwrap();
+        assert_eq!(reserved, 10..110);
+        assert_eq!(db.count().unwrap(), 10);
+        assert_eq!(db.unfilled_ids().len(), 100);
+        // pushed past the reserved ones
+        assert_eq!(db.push(rng.vector(4)).unwrap(), 110);
+
+        // odd ones first, then even ones
+        let mut order = reserved.clone().filter(|id| id % 2 == 1).collect::<Vec<_>>();
+        order.extend(reserved.clone().filter(|id| id % 2 == 0).rev());
+        let vectors = order.iter().map(|_| rng.vector(4)).collect::<Vec<_>>();
+        for (filled, (id, vector)) in order.iter().zip(&vectors).enumerate() {
+            if filled == 50 {
+                db.flush().unwrap();
+                db = reopen(&file);
+                assert_eq!(db.count().unwrap(), 61);
+                let anomalies = db.verify_level(VerifyLevel::Standard).unwrap();
+                let Anomaly::UnfilledSlots { ids } = &anomalies[0] else {
+                    panic!("{anomalies:?}");
+                };
+                assert_eq!(ids.len(), 50);
+                assert_eq!(anomalies[0].action(), SuggestedAction::Fill);
+            }
+            assert!(db.get(*id).unwrap().is_none());
+            assert!(!db.contains(*id).unwrap());
+            db.fill(*id, vector).unwrap();
+            assert_eq!(*db.get(*id).unwrap().unwrap(), *vector);
+            assert!(db.contains(*id).unwrap());
+        }
+        assert!(db.unfilled_ids().is_empty());
+        assert_eq!(db.count().unwrap(), 111);
+        assert!(matches!(db.fill(10, [0f32; 4]), Err(Error::AlreadyFilled(10))));
+        assert!(matches!(db.fill(0, [0f32; 4]), Err(Error::AlreadyFilled(0))));
+        assert!(matches!(db.fill(200, [0f32; 4]), Err(Error::NotReserved(200))));
+
+        db.flush().unwrap();
+        let mut db = reopen(&file);
+        assert!(db.verify_level(VerifyLevel::Deep).unwrap().is_empty());
+        for (id, vector) in order.iter().zip(&vectors) {
+            assert_eq!(*db.get(*id).unwrap().unwrap(), *vector);
+        }
+
+        // unfilled ones stay out of scans, searches and the index alike
+        let reserved = db.reserve_ids(3).unwrap();
+        assert_eq!(reserved, 111..114);
+        assert!(matches!(
+            db.reserve_ids(126),
+            Err(Error::PlaceholderTableFull(128))
+        ));
+        db.fill(112, [9f32; 4]).unwrap();
+        assert_eq!(db.filter_existing(&[111, 112, 113]).unwrap(), [false, true, false]);
+        let records = db.iter().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 112);
+        assert!(db.scan_map(|id, _| id).unwrap().iter().all(|id| *id != 111));
+        let request = SearchRequest::new(&[0f32; 4], 200).plan(SearchPlan::Flat);
+        assert_eq!(db.query(&request).unwrap().results.len(), 112);
+        db.build_index().unwrap();
+        let request = SearchRequest::new(&[0f32; 4], 200).plan(SearchPlan::Graph);
+        let found = db.query(&request).unwrap().results;
+        assert!(found.iter().all(|(id, _)| *id != 111 && *id != 113));
+        // removing one gives it up
+        assert!(db.remove(111).unwrap().is_none());
+        assert_eq!(db.unfilled_ids(), [113]);
+        assert!(matches!(db.fill(111, [0f32; 4]), Err(Error::NotReserved(111))));
+        db.flush().unwrap();
+        assert_eq!(reopen(&file).unfilled_ids(), [113]);
+
+        let mut plain = DatabaseOptions::new(4)
+            .create("mem", Box::new(SharedCursor::new()))
+            .unwrap();
+        assert!(matches!(plain.reserve_ids(1), Err(Error::NoPlaceholderTable)));
+    }
+}
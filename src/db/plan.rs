@@ -0,0 +1,136 @@
+use crate::db::Database;
+use crate::index::HnswIndex;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// # Search Plan
+/// How a query is answered, see [SearchResponse::plan](crate::db::SearchResponse::plan).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SearchPlan {
+    /// Compares the query against every stored vector, finding the exact results.
+    Flat,
+    /// Traverses the [index](Database::build_index), finding approximate results.
+    Graph,
+}
+
+/// # Planner Configuration
+/// When a query goes through the index rather than scanning, which is
+/// decided per query by comparing what each would cost, see [Database::set_planner].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlannerConfig {
+    /// Databases of fewer vectors are always scanned.
+    pub scan_below: u64,
+    /// Cost of comparing against a vector while traversing the graph relative
+    /// to scanning one, accounting for the hops in between. Raising it
+    /// leaves more queries to the scan.
+    pub graph_cost_factor: f32,
+}
+
+impl Default for PlannerConfig {
+    fn default() -> Self {
+        PlannerConfig {
+            scan_below: 1000,
+            graph_cost_factor: 1.0,
+        }
+    }
+}
+
+impl PlannerConfig {
+    /// Picks the cheaper way to query `count` vectors of `dim_size` dimensions,
+    /// the cost of a scan being `count × dim_size` and that of the graph
+    /// `expected_visited × dim_size`, scaled by the factor.
+    pub fn choose(&self, count: u64, dim_size: u32, expected_visited: u64) -> SearchPlan {
+        let flat = count as f64 * dim_size as f64;
+        let graph = expected_visited as f64 * dim_size as f64 * self.graph_cost_factor as f64;
+        if count < self.scan_below || flat <= graph {
+            SearchPlan::Flat
+        } else {
+            SearchPlan::Graph
+        }
+    }
+
+    /// Same as [PlannerConfig::choose] for a query of `index`.
+    pub(crate) fn choose_for(&self, index: &HnswIndex, k: usize, ef: usize) -> SearchPlan {
+        let expected = index.expected_visited(k, ef);
+        self.choose(index.len() as u64, index.dim_size(), expected)
+    }
+}
+
+impl Database {
+    pub fn planner(&self) -> PlannerConfig {
+        *self.planner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes effect from the next query on, like [Database::set_search_params].
+    /// It's not persisted.
+    pub fn set_planner(&self, config: PlannerConfig) {
+        *self.planner.write().unwrap_or_else(|e| e.into_inner()) = config;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, PlannerConfig, SearchPlan, SearchRequest};
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+
+    #[test]
+    fn planner_works() {
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(17);
+        for _ in 0..100 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        let request = SearchRequest::new(&[0.5f32; 8], 5);
+        let unindexed = db.query(&request).unwrap();
+        assert_eq!(unindexed.plan, SearchPlan::Flat);
+        assert!(unindexed.exact);
+
+        db.build_index().unwrap();
+        let planned = db.query(&request).unwrap();
+        assert_eq!(planned.plan, SearchPlan::Flat);
+        assert!(planned.exact);
+        assert_eq!(planned.visited, 100);
+        assert_eq!(planned.results, unindexed.results);
+
+        let forced = db.query(&request.clone().plan(SearchPlan::Graph)).unwrap();
+        assert_eq!(forced.plan, SearchPlan::Graph);
+        assert!(!forced.exact);
+        assert_eq!(forced.results.len(), 5);
+        let forced = db.query(&request.clone().plan(SearchPlan::Flat)).unwrap();
+        assert_eq!(forced.results, unindexed.results);
+
+        // thresholds low enough let the graph through
+        db.set_planner(PlannerConfig {
+            scan_below: 0,
+            graph_cost_factor: 0.1,
+        });
+        assert_eq!(db.query(&request).unwrap().plan, SearchPlan::Graph);
+        db.set_planner(PlannerConfig {
+            scan_below: 0,
+            ..PlannerConfig::default()
+        });
+        assert_eq!(db.query(&request).unwrap().plan, SearchPlan::Flat);
+        db.drop_index();
+        let unindexed = db.query(&request.plan(SearchPlan::Graph)).unwrap();
+        assert_eq!(unindexed.plan, SearchPlan::Flat);
+    }
+
+    #[test]
+    fn planner_prefers_graph_at_scale() {
+        let planner = PlannerConfig::default();
+        // a query keeping 64 candidates on a base layer of 32 neighbors each
+        let expected = 64 * 32 + 4 * 16;
+        assert_eq!(planner.choose(100, 8, expected), SearchPlan::Flat);
+        assert_eq!(planner.choose(3000, 8, 128 * 32), SearchPlan::Flat);
+        assert_eq!(planner.choose(100_000, 8, expected), SearchPlan::Graph);
+        let reluctant = PlannerConfig {
+            graph_cost_factor: 100.0,
+            ..planner
+        };
+        assert_eq!(reluctant.choose(100_000, 8, expected), SearchPlan::Flat);
+    }
+}
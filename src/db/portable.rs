@@ -0,0 +1,221 @@
+use crate::db::{Database, Error, SchemaMismatch, SearchParams, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use crate::index::HnswIndex;
+use crate::vio::crc::crc32;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 4] = b"VIDX";
+/// Records hashed into a fingerprint at most, spread evenly over the file.
+const SAMPLES: u64 = 256;
+
+/// # Index Fingerprint
+/// What the records looked like when an index was
+/// [exported](Database::export_index), for the database it's
+/// [imported](Database::import_index) into to be checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexFingerprint {
+    pub count: u64,
+    /// CRC-32 of the ids and components of the records sampled,
+    /// every so many in file order.
+    pub sampled: u32,
+}
+
+impl IndexFingerprint {
+    fn of(handle: &mut VectorHandle) -> Result<IndexFingerprint, Error> {
+        let count = handle.count()?;
+        let stride = (count / SAMPLES).max(1);
+        let (mut position, mut sampled, mut bytes) = (0u64, 0u32, vec![]);
+        handle.scan(|id, vector| {
+            if position % stride == 0 {
+                bytes.clear();
+                bytes.extend(vector.iter().flat_map(|x| x.to_be_bytes()));
+                sampled = crc32(&[&sampled.to_be_bytes(), &id.to_be_bytes(), &bytes]);
+            }
+            position += 1;
+        })?;
+        Ok(IndexFingerprint { count, sampled })
+    }
+
+    /// What differs from `local`, named the way [SchemaMismatch] does.
+    fn mismatches(&self, local: &IndexFingerprint) -> Vec<SchemaMismatch> {
+        let mut mismatches = vec![];
+        if self.count != local.count {
+            mismatches.push(SchemaMismatch {
+                field: "count",
+                expected: self.count.to_string(),
+                found: local.count.to_string(),
+            });
+        }
+        if self.sampled != local.sampled {
+            mismatches.push(SchemaMismatch {
+                field: "sampled records",
+                expected: format!("{:#010x}", self.sampled),
+                found: format!("{:#010x}", local.sampled),
+            });
+        }
+        mismatches
+    }
+}
+
+/// Counts the bytes that go through to `w`.
+struct Counted<'a> {
+    w: &'a mut dyn Write,
+    written: u64,
+}
+
+impl Write for Counted<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.w.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+impl Database {
+    /// Writes the index to `w` without the vectors it was built over, for
+    /// another copy of the same records to [import](Database::import_index)
+    /// instead of building it again. Along go the layers, the entry point, the
+    /// [HnswConfig](crate::db::HnswConfig) and a [fingerprint](IndexFingerprint)
+    /// of the records. Returns the number of bytes written, or fails with
+    /// [Error::NoLayer] if the index isn't built.
+    pub fn export_index(&mut self, w: &mut dyn Write) -> Result<u64, Error> {
+        let fingerprint = IndexFingerprint::of(&mut self.handle.lock_auto_clear_poison())?;
+        let index = self.index.lock_auto_clear_poison();
+        let Some(index) = index.as_ref() else {
+            return Err(Error::NoLayer(0));
+        };
+        let mut w = Counted { w, written: 0 };
+        w.write_all(MAGIC).map_err(Error::IO)?;
+        w.write_u64::<BigEndian>(fingerprint.count)
+            .map_err(Error::IO)?;
+        w.write_u32::<BigEndian>(fingerprint.sampled)
+            .map_err(Error::IO)?;
+        index.serialize_graph(&mut w).map_err(Error::IO)?;
+        Ok(w.written)
+    }
+
+    /// Takes the place of the index with one [exported](Database::export_index)
+    /// from another copy of the records, looking up the vectors it was built
+    /// over in this one. Unless `force`d, fails with [Error::FingerprintMismatch]
+    /// if the records differ from those it was exported with. A forced import
+    /// leaves the records it was exported without out of the index, and still
+    /// fails with [Error::Missing] if one it holds is gone. Its config is taken
+    /// as well, and the search parameters that come with it are persisted on
+    /// the next [flush](Database::flush).
+    pub fn import_index(&mut self, r: &mut dyn Read, force: bool) -> Result<(), Error> {
+        let mut magic = [0u8; MAGIC.len()];
+        r.read_exact(&mut magic).map_err(Error::IO)?;
+        if &magic != MAGIC {
+            return Err(Error::Parse());
+        }
+        let fingerprint = IndexFingerprint {
+            count: r.read_u64::<BigEndian>().map_err(Error::IO)?,
+            sampled: r.read_u32::<BigEndian>().map_err(Error::IO)?,
+        };
+        let mut handle = self.handle.lock_auto_clear_poison();
+        if !force {
+            let mismatches = fingerprint.mismatches(&IndexFingerprint::of(&mut handle)?);
+            if !mismatches.is_empty() {
+                return Err(Error::FingerprintMismatch(mismatches));
+            }
+        }
+        let mut records = handle.read_all()?.into_iter().collect::<HashMap<_, _>>();
+        let dim_size = handle.dim_size;
+        drop(handle);
+        let index = HnswIndex::deserialize_graph(&mut io::BufReader::new(r), &mut |id| {
+            records.remove(&id).ok_or(Error::Missing(id))
+        })?;
+        if index.dim_size() != dim_size {
+            return Err(Error::Dimension(dim_size, index.dim_size() as usize));
+        }
+        if index.metric() != self.metric {
+            return Err(Error::SchemaMismatch(vec![SchemaMismatch {
+                field: "metric",
+                expected: format!("{:?}", self.metric),
+                found: format!("{:?}", index.metric()),
+            }]));
+        }
+        let config = index.config();
+        self.config = config;
+        self.set_search_params(SearchParams {
+            ef_search: config.ef_search,
+            ..self.search_params()
+        });
+        *self.index.lock_auto_clear_poison() = Some(index);
+        self.forget_shortcuts();
+        self.invalidate_views();
+        self.forget_results();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, Error, HnswConfig, SearchPlan, SearchRequest};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+
+    fn copy(file: &SharedCursor) -> Database {
+        DatabaseOptions::default()
+            .open("mem", Box::new(Cursor::new(file.bytes())))
+            .unwrap()
+    }
+
+    #[test]
+    fn index_import_works() {
+        let file = SharedCursor::new();
+        let mut built = DatabaseOptions::new(8)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut rng = XorShift::new(83);
+        for _ in 0..400 {
+            built.push(rng.vector(8)).unwrap();
+        }
+        assert!(matches!(built.export_index(&mut vec![]), Err(Error::NoLayer(0))));
+        built.build_index().unwrap();
+        // a removed vector the records no longer hold comes along
+        built.remove(42).unwrap();
+        built.flush().unwrap();
+        let mut serving = copy(&file);
+        let mut grown = copy(&file);
+        grown.push(rng.vector(8)).unwrap();
+
+        let mut exported = vec![];
+        let written = built.export_index(&mut exported).unwrap();
+        assert_eq!(written, exported.len() as u64);
+
+        serving
+            .import_index(&mut exported.as_slice(), false)
+            .unwrap();
+        assert!(serving.is_indexed());
+        assert_eq!(serving.config(), built.config());
+        for _ in 0..50 {
+            let request = SearchRequest::new(&rng.vector(8), 10).plan(SearchPlan::Graph);
+            assert_eq!(
+                serving.query(&request).unwrap().results,
+                built.query(&request).unwrap().results
+            );
+        }
+
+        match grown.import_index(&mut exported.as_slice(), false) {
+            Err(Error::FingerprintMismatch(mismatches)) => {
+                assert_eq!(mismatches[0].field, "count");
+                assert_eq!(mismatches[0].expected, "399");
+                assert_eq!(mismatches[0].found, "400");
+            }
+            _ => panic!("expecting a fingerprint mismatch"),
+        }
+        assert!(!grown.is_indexed());
+        grown.import_index(&mut exported.as_slice(), true).unwrap();
+        assert!(grown.is_indexed());
+        assert_eq!(grown.config(), HnswConfig::default());
+    }
+}
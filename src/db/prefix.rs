@@ -0,0 +1,137 @@
+use crate::db::cache::Scratch;
+use crate::db::{Database, DbIndex, DbVectorSlice, Error};
+use crate::ext::semaphore::LockAutoClear;
+
+/// # Prefix Results
+/// Results of a search over leading components only, closest first,
+/// see [Database::search_prefix_dims].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixResults {
+    pub results: Vec<(DbIndex, f32)>,
+    /// Number of leading components the distances were computed over.
+    pub dims: u32,
+}
+
+impl Database {
+    /// Finds the `k` vectors closest to `query` over their first `dims`
+    /// components only, which is what matryoshka embeddings are trained for.
+    /// Only the prefix of each record is decoded.
+    ///
+    /// `query` may be either the prefix or a whole vector, anything
+    /// past `dims` is ignored.
+    pub fn search_prefix_dims(
+        &self,
+        query: DbVectorSlice,
+        dims: u32,
+        k: usize,
+    ) -> Result<PrefixResults, Error> {
+        let mut results = self.rank_prefix(query, dims)?;
+        results.truncate(k);
+        Ok(PrefixResults { results, dims })
+    }
+
+    /// Shortlists `candidates` vectors by their first `dims` components,
+    /// then ranks the shortlist by the whole vectors and keeps the `k` closest.
+    /// Unlike [Database::search_prefix_dims], `query` must be a whole vector.
+    pub fn search_prefix_dims_reranked(
+        &self,
+        query: DbVectorSlice,
+        dims: u32,
+        k: usize,
+        candidates: usize,
+    ) -> Result<PrefixResults, Error> {
+        let dim_size = self.dim_size();
+        if query.len() != dim_size as usize {
+            return Err(Error::Dimension(dim_size, query.len()));
+        }
+        let shortlist = self.rank_prefix(query, dims)?;
+        let mut scratch = Scratch::new();
+        let mut results = shortlist
+            .into_iter()
+            .take(candidates.max(k))
+            .filter_map(|(id, _)| match self.get_scratch(id, &mut scratch) {
+                Ok(Some(vector)) => Some(Ok((id, self.metric.distance(query, &vector)))),
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        results.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+        results.truncate(k);
+        Ok(PrefixResults {
+            results,
+            dims: dim_size,
+        })
+    }
+
+    /// Ranks every stored vector by the distance of its first `dims` components
+    /// to those of `query`, closest first and ties broken by [DbIndex].
+    fn rank_prefix(&self, query: DbVectorSlice, dims: u32) -> Result<Vec<(DbIndex, f32)>, Error> {
+        let dim_size = self.dim_size();
+        if dims == 0 || dims > dim_size {
+            return Err(Error::Dimension(dim_size, dims as usize));
+        }
+        if query.len() < dims as usize {
+            return Err(Error::Dimension(dims, query.len()));
+        }
+        let query = &query[..dims as usize];
+        let records = self.handle.lock_auto_clear_poison().read_all_prefix(dims)?;
+        let mut ranked = records
+            .into_iter()
+            .map(|(id, prefix)| (id, self.metric.distance(query, &prefix)))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+        Ok(ranked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error};
+    use crate::ext::mem::{CountingAccess, SharedCursor};
+
+    #[test]
+    fn prefix_search_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        // closest by the first two components comes last by all four, and vice versa
+        db.push([0f32, 0f32, 9f32, 9f32]).unwrap();
+        db.push([1f32, 0f32, 1f32, 0f32]).unwrap();
+        db.push([2f32, 0f32, 0f32, 0f32]).unwrap();
+        let query = [0f32; 4];
+
+        let counting = CountingAccess::new(file.reopen());
+        let db = DatabaseOptions::default()
+            .open("mem", Box::new(counting.clone()))
+            .unwrap();
+        let read = counting.read();
+        let prefix = db.search_prefix_dims(&query[..2], 2, 3).unwrap();
+        // ids and first halves only
+        assert_eq!(counting.read() - read, 3 * (4 + 2 * 4));
+        assert_eq!(prefix.dims, 2);
+        assert_eq!(
+            prefix.results.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+
+        let reranked = db.search_prefix_dims_reranked(&query, 2, 3, 3).unwrap();
+        assert_eq!(reranked.dims, 4);
+        assert_eq!(
+            reranked.results.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![1, 2, 0]
+        );
+        // reranking can't recover what the shortlist missed
+        let shortlisted = db.search_prefix_dims_reranked(&query, 1, 1, 1).unwrap();
+        assert_eq!(shortlisted.results[0].0, 0);
+
+        assert!(matches!(
+            db.search_prefix_dims(&query, 5, 3),
+            Err(Error::Dimension(4, 5))
+        ));
+        assert!(matches!(
+            db.search_prefix_dims(&query[..1], 2, 3),
+            Err(Error::Dimension(2, 1))
+        ));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::db::scan::SCAN_BLOCK;
+use crate::db::{Database, DbIndex, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use crate::vio::format::RECORD_ID_WIDTH;
+use std::ops::ControlFlow;
+
+impl VectorHandle {
+    /// Calls `f` with the id of every stored vector in ascending order. Files
+    /// with history are walked by their offsets in memory, others by reading
+    /// the records through in blocks.
+    fn walk_ids(&mut self, mut f: impl FnMut(DbIndex)) -> Result<(), Error> {
+        if let Some(store) = &self.store {
+            (0..store.len() as DbIndex).for_each(f);
+            return Ok(());
+        }
+        if let Some(history) = &self.history {
+            history.live(None).into_iter().for_each(|(id, _)| f(id));
+            return Ok(());
+        }
+        let unit = self.unit_size_bytes() as usize;
+        let unfilled = self.unfilled();
+        self.scan_blocks(SCAN_BLOCK, |block| {
+            for begin in (0..block.len()).step_by(unit) {
+                let id = &block[begin..begin + RECORD_ID_WIDTH as usize];
+                let id = DbIndex::from_be_bytes(id.try_into().unwrap());
+                if !unfilled.contains(&id) {
+                    f(id);
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        })
+    }
+}
+
+impl Database {
+    /// Whether each of `ids` is stored, in the same order, the same as
+    /// [Database::contains] for each but in one pass over the ids stored
+    /// rather than a lookup each, which pays off for many ids at once.
+    /// Removed ids are absent whether the file keeps history or not.
+    pub fn filter_existing(&mut self, ids: &[DbIndex]) -> Result<Vec<bool>, Error> {
+        let mut existing = vec![false; ids.len()];
+        if ids.is_empty() {
+            return Ok(existing);
+        }
+        let mut order = (0..ids.len()).collect::<Vec<_>>();
+        order.sort_unstable_by_key(|i| ids[*i]);
+        let mut pending = order.into_iter().peekable();
+        self.handle.lock_auto_clear_poison().walk_ids(|stored| {
+            while pending.next_if(|i| ids[*i] < stored).is_some() {}
+            while let Some(i) = pending.next_if(|i| ids[*i] == stored) {
+                existing[i] = true;
+            }
+        })?;
+        Ok(existing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::DatabaseOptions;
+    use crate::ext::mem::{CountingAccess, SharedCursor};
+
+    #[test]
+    fn filter_existing_works() {
+        for history in [false, true] {
+            let counting = CountingAccess::new(SharedCursor::new());
+            let mut db = DatabaseOptions::new(4)
+                .history(history)
+                .create("mem", Box::new(counting.clone()))
+                .unwrap();
+            for i in 0..3000 {
+                db.push([i as f32; 4]).unwrap();
+            }
+            let removed = (0..3000).step_by(7).collect::<Vec<_>>();
+            db.remove_many(&removed).unwrap();
+            db.remove(2999).unwrap();
+            assert!(db.filter_existing(&[]).unwrap().is_empty());
+
+            // out of order, repeated and beyond what was ever stored
+            let ids = (0..500u32)
+                .map(|i| i * 37 % 3100)
+                .chain([14, 14, 2999, 5000, 0])
+                .collect::<Vec<_>>();
+            let before = counting.seeks();
+            let existing = db.filter_existing(&ids).unwrap();
+            let filtering = counting.seeks() - before;
+
+            let before = counting.seeks();
+            let expected = ids
+                .iter()
+                .map(|id| db.contains(*id).unwrap())
+                .collect::<Vec<_>>();
+            let looking_up = counting.seeks() - before;
+            assert_eq!(existing, expected);
+            assert!(existing.contains(&true) && existing.contains(&false));
+            if history {
+                // the offsets in memory are all it takes
+                assert_eq!(filtering, 0);
+            } else {
+                assert!(filtering * 50 < looking_up, "{filtering} vs {looking_up}");
+            }
+        }
+    }
+}
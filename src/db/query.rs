@@ -0,0 +1,876 @@
+use crate::db::deadline::{Deadline, Timed};
+use crate::db::memo::ResultKey;
+use crate::db::mmr::{diversify, Mmr};
+use crate::db::normal::Normalizations;
+use crate::db::{
+    Clock, Database, DbIndex, DbVector, DbVectorSlice, Error, IndexState, Operation,
+    PlannerConfig, QueryVector, SearchParams, SearchPlan, TracedSearch,
+};
+use crate::algorithm::search::{BestFirst, CandidateSource, Scorer};
+use crate::index::{HnswIndex, SearchScratch, ShortcutCache};
+use crate::ext::semaphore::LockAutoClear;
+use crate::metric::Metric;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::{max, min};
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// # Search Request
+/// Describes a k nearest neighbors query. Build it with [SearchRequest::new]
+/// and hand it over to [Database::query].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchRequest {
+    query: DbVector,
+    k: usize,
+    ef: Option<usize>,
+    explain: bool,
+    metric: Option<Metric>,
+    plan: Option<SearchPlan>,
+    deadline: Option<Duration>,
+    /// The query as it was handed over, if [from_vector](SearchRequest::from_vector).
+    declared: Option<QueryVector>,
+    mmr: Option<Mmr>,
+}
+
+impl SearchRequest {
+    pub fn new(query: DbVectorSlice, k: usize) -> SearchRequest {
+        SearchRequest {
+            query: DbVector::from(query),
+            k,
+            ef: None,
+            explain: false,
+            metric: None,
+            plan: None,
+            deadline: None,
+            declared: None,
+            mmr: None,
+        }
+    }
+
+    /// Same as [SearchRequest::new], with the query taken the way the
+    /// metric it's ranked by expects, see [QueryVector].
+    pub fn from_vector(query: QueryVector, k: usize) -> SearchRequest {
+        SearchRequest {
+            declared: Some(query.clone()),
+            ..SearchRequest::new(&query.into_vector(), k)
+        }
+    }
+
+    /// Size of the candidate pool kept while traversing the index.
+    /// Larger values trade speed for recall.
+    pub fn ef(mut self, ef: usize) -> SearchRequest {
+        self.ef = Some(ef);
+        self
+    }
+
+    /// Records a [SearchTrace] of how the results were reached.
+    pub fn explain(mut self, explain: bool) -> SearchRequest {
+        self.explain = explain;
+        self
+    }
+
+    /// Ranks the results by `metric` instead of the database's. Exact scans
+    /// rank everything by it, while the index is still traversed by the
+    /// database's metric and only its candidates are ranked by `metric`,
+    /// which is flagged by [SearchResponse::caveat].
+    pub fn metric_override(mut self, metric: Metric) -> SearchRequest {
+        self.metric = Some(metric);
+        self
+    }
+
+    /// Answers by `plan` rather than the one the [planner](Database::set_planner)
+    /// would pick. Without an index, queries scan regardless.
+    pub fn plan(mut self, plan: SearchPlan) -> SearchRequest {
+        self.plan = Some(plan);
+        self
+    }
+
+    /// Answers with the best results found within `deadline`, cutting the
+    /// traversal of the index or the exact scan short once it passes, which
+    /// is flagged by [SearchResponse::complete]. The clock is looked at every
+    /// few dozen nodes expanded or vectors compared, so the deadline may be
+    /// overrun by as much. Such requests skip the result cache and entry shortcuts.
+    pub fn deadline(mut self, deadline: Duration) -> SearchRequest {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Picks the results for being unlike each other as well as close to
+    /// the query, by maximal marginal relevance, out of `pool_factor` times
+    /// as many candidates as asked for. Each next one maximizes
+    /// `lambda * sim(query, c) - (1 - lambda) * max_sim(c, picked)`,
+    /// similarity being the distance negated, so that a `lambda` of one is
+    /// the plain ranking and zero spreads them out the most. The results come
+    /// in the order they were picked, and can't be paged through.
+    pub fn mmr(mut self, lambda: f32, pool_factor: usize) -> SearchRequest {
+        self.mmr = Some(Mmr {
+            lambda: lambda.clamp(0f32, 1f32),
+            pool_factor: pool_factor.max(1),
+        });
+        self
+    }
+
+    pub fn query(&self) -> DbVectorSlice<'_> {
+        &self.query
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// The request with its query conformed to the metric it's ranked by,
+    /// `metric` being the database's, counting it in `normalizations` if
+    /// it's normalized on the fly.
+    pub(crate) fn conform(
+        &self,
+        metric: Metric,
+        normalizations: &Normalizations,
+    ) -> Result<Cow<'_, SearchRequest>, Error> {
+        let Some(declared) = &self.declared else {
+            return Ok(Cow::Borrowed(self));
+        };
+        let (query, normalized) = declared.clone().conform(self.metric.unwrap_or(metric))?;
+        normalizations
+            .queries
+            .fetch_add(normalized as u64, Ordering::Relaxed);
+        Ok(Cow::Owned(SearchRequest {
+            query,
+            declared: None,
+            ..self.clone()
+        }))
+    }
+
+    /// The metric it's ranked by, `metric` being the database's.
+    pub(crate) fn metric_or(&self, metric: Metric) -> Metric {
+        self.metric.unwrap_or(metric)
+    }
+
+    /// The request for the candidates [mmr](SearchRequest::mmr) picks from,
+    /// along with its knobs, unless it ranks them plainly.
+    pub(crate) fn mmr_pool(&self) -> Option<(SearchRequest, Mmr)> {
+        let mmr = self.mmr.filter(|mmr| mmr.lambda < 1f32)?;
+        let pool = SearchRequest {
+            k: self.k.saturating_mul(mmr.pool_factor),
+            mmr: None,
+            ..self.clone()
+        };
+        Some((pool, mmr))
+    }
+
+    /// Tells it apart from other requests to the [result cache](Database::set_result_cache).
+    fn cache_key(&self, params: SearchParams, planner: PlannerConfig) -> ResultKey {
+        ResultKey {
+            query: self.query.iter().map(|x| x.to_bits()).collect(),
+            k: self.k,
+            ef: self.ef.unwrap_or(params.ef_search as usize),
+            explain: self.explain,
+            metric: self.metric.map(|metric| metric.to_byte()),
+            plan: self.plan,
+            max_visited: params.max_visited,
+            rerank_factor: params.rerank_factor,
+            planner: (planner.scan_below, planner.graph_cost_factor.to_bits()),
+        }
+    }
+}
+
+/// # Search Response
+/// Results of a query, closest first. Vectors as close as each other come
+/// by ascending id, whichever [plan](SearchResponse::plan) found them,
+/// so the same query on the same data always answers the same.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResponse {
+    pub results: Vec<(DbIndex, f32)>,
+    /// Number of stored vectors compared against the query.
+    pub visited: usize,
+    /// Continues where this page stopped, see [Database::query_next].
+    /// Absent if the database ran out of results.
+    pub cursor: Option<SearchCursor>,
+    /// Present if the request asked to [explain](SearchRequest::explain).
+    pub trace: Option<SearchTrace>,
+    /// Whether the results were ranked by a [metric](SearchRequest::metric_override)
+    /// other than the one the index was traversed by, so they may miss
+    /// vectors closer by that metric.
+    pub caveat: bool,
+    /// How the query was answered.
+    pub plan: SearchPlan,
+    /// Whether the results are the exact closest vectors, which only a
+    /// [flat](SearchPlan::Flat) scan comparing against all of them finds.
+    pub exact: bool,
+    /// Nodes of the index that would have made the results but whose vectors
+    /// are gone from the records, which were left out, see [Database::verify].
+    pub dangling: usize,
+    /// Where the index was at if it wasn't ready and the query was answered
+    /// anyway, in which case the results may miss what's yet to be indexed,
+    /// see [IndexPolicy::Partial](crate::db::IndexPolicy::Partial).
+    pub unready: Option<IndexState>,
+    /// Whether the search ran its course rather than being cut short by
+    /// the [deadline](SearchRequest::deadline), in which case the results are
+    /// the best found by then and not [exact](SearchResponse::exact).
+    pub complete: bool,
+    /// How long the search took, if it had a [deadline](SearchRequest::deadline).
+    pub elapsed: Option<Duration>,
+}
+
+/// # Search Trace
+/// Everything a query visited on its way to the results,
+/// from the topmost layer down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchTrace {
+    pub layers: Vec<LayerTrace>,
+    /// Candidates that made it to the last layer but were cut from the results.
+    pub pruned: Vec<(DbIndex, f32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerTrace {
+    pub level: u32,
+    /// Nodes in the order they were compared against the query.
+    pub visited: Vec<DbIndex>,
+    /// Closest candidates, closest first, at the moment the layer was left.
+    pub candidates: Vec<(DbIndex, f32)>,
+}
+
+/// # Search Cursor
+/// Opaque token that remembers what a query has returned so far,
+/// so the next page neither repeats nor skips results.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SearchCursor {
+    query: DbVector,
+    k: usize,
+    ef: Option<usize>,
+    metric: Option<Metric>,
+    /// How the first page was answered, which the next ones are answered by too.
+    plan: SearchPlan,
+    /// Sorted for binary search.
+    returned: Vec<DbIndex>,
+}
+
+impl SearchCursor {
+    fn new(
+        request: &SearchRequest,
+        plan: SearchPlan,
+        results: &[(DbIndex, f32)],
+        returned: &[DbIndex],
+    ) -> Option<SearchCursor> {
+        if results.is_empty() || results.len() < request.k {
+            return None;
+        }
+        let mut returned = returned
+            .iter()
+            .copied()
+            .chain(results.iter().map(|(id, _)| *id))
+            .collect::<Vec<_>>();
+        returned.sort();
+        Some(SearchCursor {
+            query: request.query.clone(),
+            k: request.k,
+            ef: request.ef,
+            metric: request.metric,
+            plan,
+            returned,
+        })
+    }
+
+    fn has_returned(&self, id: DbIndex) -> bool {
+        self.returned.binary_search(&id).is_ok()
+    }
+}
+
+impl Database {
+    /// Finds the `k` vectors closest to the request's query, through
+    /// the [index](Database::build_index) if there's one and the
+    /// [planner](Database::set_planner) finds it cheaper, or exactly otherwise.
+    /// Identical requests are answered from the [result cache](Database::set_result_cache)
+    /// if it's on and nothing changed in between.
+    pub fn query(&mut self, request: &SearchRequest) -> Result<SearchResponse, Error> {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let response = self.query_with_scratch(request, &mut scratch);
+        self.scratch = scratch;
+        response
+    }
+
+    /// Same as [Database::query], searching the index in `scratch`, for
+    /// callers that query from several threads to keep one each. Once it
+    /// has grown to fit the index, the search allocates little but the response.
+    pub fn query_with_scratch(
+        &self,
+        request: &SearchRequest,
+        scratch: &mut SearchScratch,
+    ) -> Result<SearchResponse, Error> {
+        self.query_hooked(request, scratch, None)
+    }
+
+    /// Same as [Database::query], searching the index by expanding the nodes
+    /// `source` picks and measuring them by `scorer` rather than best first
+    /// by the metric, to experiment with either. Only searches through the
+    /// index go by them, so the request may [plan](SearchRequest::plan) for
+    /// [SearchPlan::Graph]. Neither the result cache nor entry shortcuts are
+    /// read or written.
+    #[cfg(feature = "unstable-search")]
+    pub fn query_with(
+        &self,
+        request: &SearchRequest,
+        source: &mut dyn CandidateSource,
+        scorer: &dyn Scorer,
+    ) -> Result<SearchResponse, Error> {
+        self.query_hooked(request, &mut SearchScratch::new(), Some((source, scorer)))
+    }
+
+    fn query_hooked(
+        &self,
+        request: &SearchRequest,
+        scratch: &mut SearchScratch,
+        hooks: Option<SearchHooks>,
+    ) -> Result<SearchResponse, Error> {
+        if let Some((pool, mmr)) = request.mmr_pool() {
+            let metric = request.metric_or(self.metric);
+            let pool = self.query_hooked(&pool, scratch, hooks)?;
+            return diversify(pool, (request.k, mmr.lambda), metric, |id| self.get(id));
+        }
+        let mut timer = self.time(Operation::Search);
+        let request = request.conform(self.metric, &self.normalizations)?;
+        let (request, unready) = self.apply_index_policy(&request)?;
+        let request = &*request;
+        // later changes to the parameters don't affect a query underway
+        let params = self.search_params();
+        let planner = self.planner();
+        let mut traced = |response: &SearchResponse, cached| {
+            if let Some(timer) = timer.as_mut() {
+                timer.search = Some(TracedSearch {
+                    k: request.k,
+                    ef: request.ef.unwrap_or(params.ef_search as usize),
+                    cached,
+                    graph: response.plan == SearchPlan::Graph,
+                });
+            }
+        };
+        let untimed = hooks.is_none() && request.deadline.is_none();
+        let cached = self.results.as_ref().filter(|_| untimed).map(|results| {
+            let revision = self.handle.lock_auto_clear_poison().revision;
+            (results, request.cache_key(params, planner), revision)
+        });
+        if let Some((results, key, revision)) = &cached {
+            if let Some(response) = results.lock_auto_clear_poison().get(key, *revision) {
+                traced(&response, true);
+                return Ok(SearchResponse { unready, ..response });
+            }
+        }
+        let index = self.index.lock_auto_clear_poison();
+        let shortcuts = self.shortcuts.as_ref().filter(|_| untimed);
+        let mut present = |id| {
+            let mut handle = self.handle.lock_auto_clear_poison();
+            let present = handle.contains(id);
+            handle.counters.dangling.fetch_add(!present as u64, Ordering::Relaxed);
+            present
+        };
+        let response = answer(
+            request,
+            (params, planner, &*self.clock),
+            self.metric,
+            index.as_ref().map(|index| (index, scratch, hooks)),
+            shortcuts,
+            &mut present,
+            |metric, deadline| self.scan_exact(&request.query, metric, deadline),
+        )?;
+        traced(&response, false);
+        if let Some((results, key, revision)) = cached {
+            results
+                .lock_auto_clear_poison()
+                .insert(key, revision, response.clone());
+        }
+        Ok(SearchResponse { unready, ..response })
+    }
+
+    /// Fetches the page following the one `cursor` was handed out with.
+    /// Answers by the same [plan](SearchResponse::plan) as the first page,
+    /// asking for as many more results as were already returned and
+    /// skipping those, even if the database has changed in between.
+    pub fn query_next(&self, cursor: &SearchCursor) -> Result<SearchResponse, Error> {
+        let request = SearchRequest {
+            ef: cursor.ef,
+            metric: cursor.metric,
+            plan: Some(cursor.plan),
+            ..SearchRequest::new(&cursor.query, cursor.returned.len().saturating_add(cursor.k))
+        };
+        let response = self.query_with_scratch(&request, &mut SearchScratch::new())?;
+        let results = response
+            .results
+            .iter()
+            .filter(|(id, _)| !cursor.has_returned(*id))
+            .take(cursor.k)
+            .copied()
+            .collect::<Vec<_>>();
+        let page = SearchRequest {
+            k: cursor.k,
+            ..request
+        };
+        Ok(SearchResponse {
+            cursor: SearchCursor::new(&page, cursor.plan, &results, &cursor.returned),
+            results,
+            ..response
+        })
+    }
+}
+
+/// Answers `request` through `index` if there's one and `planner` finds it
+/// cheaper, searching it in the scratch along with it, or exactly otherwise by
+/// the distances `scan` measures to every stored vector by a metric, `metric`
+/// being the database's, until the deadline if given. The index takes
+/// `shortcuts` to its upper layers if given, and only returns the ids whose
+/// vectors are `present`. Deadlines are told by `clock`.
+/// Custom [CandidateSource] and [Scorer] a search of the index goes by,
+/// see [Database::query_with].
+pub(crate) type SearchHooks<'a> = (&'a mut dyn CandidateSource, &'a dyn Scorer);
+
+pub(crate) fn answer(
+    request: &SearchRequest,
+    (params, planner, clock): (SearchParams, PlannerConfig, &dyn Clock),
+    metric: Metric,
+    index: Option<(&HnswIndex, &mut SearchScratch, Option<SearchHooks>)>,
+    shortcuts: Option<&Mutex<ShortcutCache>>,
+    present: &mut dyn FnMut(DbIndex) -> bool,
+    scan: impl FnOnce(Metric, Option<&mut Deadline>) -> Result<Vec<(DbIndex, f32)>, Error>,
+) -> Result<SearchResponse, Error> {
+    let ef = request.ef.unwrap_or(params.ef_search as usize);
+    let mut deadline = request.deadline.map(|budget| Deadline::new(clock, budget));
+    let index = index.filter(|(index, _, _)| {
+        let plan = request.plan.unwrap_or_else(|| planner.choose_for(index, request.k, ef));
+        plan == SearchPlan::Graph
+    });
+    if let Some((index, scratch, hooks)) = index {
+        let mut shortcuts = shortcuts.map(|cache| cache.lock_auto_clear_poison());
+        // the candidates of the base layer are ranked again by another metric
+        scratch.layer.trace = request.explain || request.metric.is_some();
+        let bounds = (request.k, ef, params.max_visited);
+        let shortcuts = shortcuts.as_deref_mut();
+        let query = &request.query;
+        let mut found = match (hooks, deadline.as_mut()) {
+            (Some(hooks), None) => {
+                index.search_in(query, bounds, shortcuts, Some(&mut *present), hooks, scratch)
+            }
+            (None, None) => {
+                let hooks = (&mut BestFirst, &index.metric());
+                index.search_in(query, bounds, shortcuts, Some(&mut *present), hooks, scratch)
+            }
+            (Some((inner, scorer)), Some(deadline)) => {
+                let hooks = (&mut Timed { inner, deadline }, scorer);
+                index.search_in(query, bounds, shortcuts, Some(&mut *present), hooks, scratch)
+            }
+            (None, Some(deadline)) => {
+                let timed = &mut Timed {
+                    inner: &mut BestFirst,
+                    deadline,
+                };
+                let hooks = (timed, &index.metric());
+                index.search_in(query, bounds, shortcuts, Some(&mut *present), hooks, scratch)
+            }
+        }?;
+        let caveat = request.metric.is_some_and(|metric| metric != index.metric());
+        if let (Some(metric), Some(base)) = (request.metric, found.layers.last()) {
+            // every candidate of the base layer is scored again, not just the k kept
+            found.dangling = 0;
+            let mut rescored = base
+                .candidates
+                .iter()
+                .filter_map(|(id, _)| {
+                    let vector = index.get(*id)?;
+                    if !present(*id) {
+                        found.dangling += 1;
+                        return None;
+                    }
+                    Some((*id, metric.distance(&request.query, vector)))
+                })
+                .collect::<Vec<_>>();
+            rescored.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+            rescored.truncate(request.k);
+            found.results = rescored;
+        }
+        let trace = request.explain.then(|| {
+            let base = found.layers.last();
+            SearchTrace {
+                pruned: base.map_or(vec![], |base| {
+                    Vec::from(&base.candidates[min(base.candidates.len(), request.k)..])
+                }),
+                layers: found.layers,
+            }
+        });
+        return Ok(SearchResponse {
+            cursor: SearchCursor::new(request, SearchPlan::Graph, &found.results, &[]),
+            results: found.results,
+            visited: found.visited,
+            trace,
+            caveat,
+            plan: SearchPlan::Graph,
+            exact: false,
+            dangling: found.dangling,
+            unready: None,
+            complete: deadline.as_ref().is_none_or(|deadline| !deadline.passed()),
+            elapsed: deadline.map(|deadline| deadline.elapsed()),
+        });
+    }
+    let mut results = scan(request.metric.unwrap_or(metric), deadline.as_mut())?;
+    let complete = deadline.as_ref().is_none_or(|deadline| !deadline.passed());
+    let exact = complete
+        && params
+            .max_visited
+            .is_none_or(|max_visited| results.len() as u64 <= max_visited);
+    if let Some(max_visited) = params.max_visited {
+        results.truncate(max_visited.try_into().unwrap_or(usize::MAX));
+    }
+    let visited = results.len();
+    let scanned = request
+        .explain
+        .then(|| results.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+    results.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+
+    let trace = scanned.map(|visited| {
+        let candidates = Vec::from(&results[..min(results.len(), max(ef, request.k))]);
+        SearchTrace {
+            pruned: Vec::from(&candidates[min(candidates.len(), request.k)..]),
+            layers: vec![LayerTrace {
+                level: 0,
+                visited,
+                candidates,
+            }],
+        }
+    });
+    results.truncate(request.k);
+    let cursor = SearchCursor::new(request, SearchPlan::Flat, &results, &[]);
+    Ok(SearchResponse {
+        results,
+        visited,
+        cursor,
+        trace,
+        caveat: false,
+        plan: SearchPlan::Flat,
+        exact,
+        dangling: 0,
+        unready: None,
+        complete,
+        elapsed: deadline.map(|deadline| deadline.elapsed()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        Clock, Database, DatabaseOptions, PlannerConfig, SearchPlan, SearchRequest, SearchResponse,
+    };
+    use crate::ext::rand::XorShift;
+    use crate::index::SearchScratch;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn random_db(count: usize) -> Database {
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(7);
+        for _ in 0..count {
+            db.push(rng.vector(8)).unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn query_next_works() {
+        let mut db = random_db(120);
+        let query = vec![0.5f32; 8];
+        let whole = db.query(&SearchRequest::new(&query, 50)).unwrap();
+
+        let mut page = db.query(&SearchRequest::new(&query, 10)).unwrap();
+        let mut paged = page.results.clone();
+        for _ in 1..5 {
+            page = db.query_next(page.cursor.as_ref().unwrap()).unwrap();
+            paged.extend(page.results.iter());
+        }
+        assert_eq!(paged, whole.results);
+    }
+
+    #[test]
+    fn query_next_takes_the_graph() {
+        let db = random_db(3000);
+        db.build_index().unwrap();
+        let query = vec![0.5f32; 8];
+        let mut scratch = SearchScratch::new();
+        let first = SearchRequest::new(&query, 10);
+        let mut page = db.query_with_scratch(&first, &mut scratch).unwrap();
+        let mut paged = page.results.clone();
+        for _ in 1..3 {
+            page = db.query_next(page.cursor.as_ref().unwrap()).unwrap();
+            assert_eq!(page.plan, SearchPlan::Graph);
+            assert_eq!(page.results.len(), 10);
+            assert!(page.visited < 3000);
+            paged.extend(page.results.iter());
+        }
+        let mut ids = paged.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 30);
+
+        let whole = db.query_with_scratch(&SearchRequest::new(&query, 30), &mut scratch).unwrap();
+        assert_eq!(whole.plan, SearchPlan::Graph);
+        assert!(page.results.iter().all(|hit| whole.results.contains(hit)));
+    }
+
+    #[test]
+    fn cursor_runs_out() {
+        let mut db = random_db(15);
+        let page = db.query(&SearchRequest::new(&[0f32; 8], 10)).unwrap();
+        let last = db.query_next(&page.cursor.unwrap()).unwrap();
+        assert_eq!(last.results.len(), 5);
+        assert!(last.cursor.is_none());
+    }
+
+    #[test]
+    fn explain_works() {
+        let mut db = random_db(100);
+        let request = SearchRequest::new(&[0.5f32; 8], 5).ef(20);
+        let plain = db.query(&request).unwrap();
+        assert!(plain.trace.is_none());
+
+        let explained = db.query(&request.explain(true)).unwrap();
+        assert_eq!(explained.results, plain.results);
+        let trace = explained.trace.unwrap();
+        assert_eq!(trace.layers.len(), 1);
+        let base = &trace.layers[0];
+        assert_eq!(base.level, 0);
+        assert_eq!(base.visited.len(), explained.visited);
+        assert!(base.visited.contains(&explained.results[0].0));
+        assert_eq!(base.candidates.len(), 20);
+        assert_eq!(&base.candidates[..5], &explained.results[..]);
+        assert_eq!(&trace.pruned[..], &base.candidates[5..]);
+    }
+
+    #[test]
+    fn metric_override_works() {
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        // closest first by euclidean distance, but largest inner product last
+        for vector in [[1f32, 0f32], [0.9, 0.1], [10f32, 0f32]] {
+            db.push(vector).unwrap();
+        }
+        let ids = |response: &SearchResponse| {
+            response.results.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        };
+        let request = SearchRequest::new(&[1f32, 0f32], 3);
+        let overridden = request.clone().metric_override(Metric::DotProduct);
+
+        let plain = db.query(&request).unwrap();
+        assert_eq!(ids(&plain), vec![0, 1, 2]);
+        let exact = db.query(&overridden).unwrap();
+        assert_eq!(ids(&exact), vec![2, 0, 1]);
+        assert_eq!(exact.results[0].1, -10f32);
+        assert!(!exact.caveat);
+
+        db.build_index().unwrap();
+        // through the index however small
+        db.set_planner(PlannerConfig {
+            scan_below: 0,
+            graph_cost_factor: 0.0,
+        });
+        let indexed = db.query(&overridden).unwrap();
+        assert_eq!(ids(&indexed), vec![2, 0, 1]);
+        assert!(indexed.caveat);
+        assert!(!db.query(&request).unwrap().caveat);
+        let same = request.metric_override(Metric::Euclidean);
+        assert!(!db.query(&same).unwrap().caveat);
+    }
+
+    #[test]
+    fn ties_break_by_id() {
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        // points of a lattice, many of which lie exactly as far from a query
+        let points = (-7..=7)
+            .flat_map(|x| (-7..=7).map(move |y| [x as f32, y as f32]))
+            .collect::<Vec<_>>();
+        for point in &points {
+            db.push(point).unwrap();
+        }
+        db.build_index().unwrap();
+        // nodes of updated vectors come after the others in the index
+        for id in (0..points.len() as u32).rev().step_by(3) {
+            db.update(id, points[id as usize]).unwrap();
+        }
+        for (query, k) in [([0f32, 0f32], 15), ([0.5, 0.5], 30), ([3f32, -2f32], 50)] {
+            let request = SearchRequest::new(&query, k).ef(200);
+            let exact = db.query(&request.clone().plan(SearchPlan::Flat)).unwrap();
+            let ranked = exact.results.windows(2).all(|pair| {
+                let [(a, da), (b, db)] = [pair[0], pair[1]];
+                da < db || da == db && a < b
+            });
+            assert!(ranked);
+            for _ in 0..5 {
+                for plan in [SearchPlan::Flat, SearchPlan::Graph] {
+                    let again = db.query(&request.clone().plan(plan)).unwrap();
+                    assert_eq!(again.plan, plan);
+                    assert_eq!(again.results, exact.results, "{query:?} by {plan:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dangling_nodes_are_skipped() {
+        let mut db = random_db(200);
+        db.build_index().unwrap();
+        db.flush().unwrap();
+        let query = db.get(42).unwrap().unwrap();
+        // records gone behind the index's back
+        let removed = [3, 42, 150];
+        for id in removed {
+            db.handle.lock().unwrap().remove(id).unwrap().unwrap();
+        }
+        db.loaded_vectors.lock().unwrap().remove(&42);
+
+        let request = SearchRequest::new(&query, 10).plan(SearchPlan::Graph);
+        let response = db.query(&request).unwrap();
+        assert!(response.dangling > 0);
+        assert_eq!(response.results.len(), 10);
+        assert!(response.results.iter().all(|(id, _)| !removed.contains(id)));
+        let exact = db.query(&request.clone().plan(SearchPlan::Flat)).unwrap();
+        assert_eq!(exact.dangling, 0);
+        assert_eq!(db.stats().unwrap().dangling_nodes, response.dangling as u64);
+        assert_eq!(db.verify(), removed);
+
+        db.build_index().unwrap();
+        assert!(db.verify().is_empty());
+        assert_eq!(db.query(&request).unwrap().dangling, 0);
+        db.reset_stats();
+        assert_eq!(db.stats().unwrap().dangling_nodes, 0);
+    }
+
+    /// Moves on by a millisecond every time it's read.
+    #[derive(Debug, Default)]
+    struct TickClock(AtomicU64);
+
+    impl Clock for TickClock {
+        fn now(&self) -> Duration {
+            Duration::from_millis(self.0.fetch_add(1, Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn deadline_works() {
+        let mut db = DatabaseOptions::new(8)
+            .deadline_clock(Arc::new(TickClock::default()))
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(7);
+        for _ in 0..1000 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        db.build_index().unwrap();
+        let request = SearchRequest::new(&[0.5f32; 8], 10).ef(200);
+        // the clock is read once the search starts and every 32 steps after,
+        // so that the fourth look finds 4ms gone, at the 128th step
+        let timed = request.clone().deadline(Duration::from_millis(4));
+
+        for plan in [SearchPlan::Flat, SearchPlan::Graph] {
+            let whole = db.query(&request.clone().plan(plan)).unwrap();
+            assert!(whole.complete);
+            assert_eq!(whole.elapsed, None);
+            let cut = db.query(&timed.clone().plan(plan)).unwrap();
+            assert!(!cut.complete, "{plan:?}");
+            assert!(!cut.exact);
+            assert_eq!(cut.elapsed, Some(Duration::from_millis(5)));
+            assert_eq!(cut.results.len(), 10);
+            assert!(cut.visited < whole.visited, "{plan:?}");
+            if plan == SearchPlan::Flat {
+                assert_eq!(cut.visited, 127);
+            }
+            // no closer than the best there is
+            assert!(cut.results[0].1 >= whole.results[0].1);
+
+            let roomy = request.clone().plan(plan).deadline(Duration::from_secs(60));
+            let roomy = db.query(&roomy).unwrap();
+            assert!(roomy.complete);
+            assert_eq!(roomy.results, whole.results);
+            assert_eq!(roomy.visited, whole.visited);
+            assert!(roomy.elapsed.is_some());
+        }
+        let view = db.read_view().unwrap();
+        let cut = view.query(&timed.clone().plan(SearchPlan::Flat)).unwrap();
+        assert!(!cut.complete);
+        assert_eq!(cut.visited, 127);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn cursor_serialization_works() {
+        use crate::db::SearchCursor;
+        let mut db = random_db(30);
+        let page = db.query(&SearchRequest::new(&[0f32; 8], 10)).unwrap();
+        let cursor = page.cursor.unwrap();
+        let json = serde_json::to_string(&cursor).unwrap();
+        let decoded: SearchCursor = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, cursor);
+        assert_eq!(
+            db.query_next(&decoded).unwrap(),
+            db.query_next(&cursor).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-search")]
+    fn query_with_works() {
+        use crate::db::{BestFirst, CandidateSource, Frontier, Scorer};
+        use std::collections::BTreeSet;
+
+        /// Expands the entry of the top layer and nothing else.
+        struct EntryOnly(bool);
+
+        impl CandidateSource for EntryOnly {
+            fn next(&mut self, frontier: &mut Frontier) -> Option<u32> {
+                let expanded = std::mem::replace(&mut self.0, true);
+                (!expanded).then(|| frontier.pop().map(|(node, _)| node))?
+            }
+        }
+
+        struct Chebyshev;
+
+        impl Scorer for Chebyshev {
+            fn score(&self, query: &[f32], vector: &[f32]) -> f32 {
+                query.iter().zip(vector).map(|(a, b)| (a - b).abs()).fold(0f32, f32::max)
+            }
+        }
+
+        let mut db = random_db(300);
+        db.build_index().unwrap();
+        let query = vec![0.5f32; 8];
+        let request = SearchRequest::new(&query, 10).plan(SearchPlan::Graph);
+        let hooked = db.query_with(&request, &mut BestFirst, &Metric::Euclidean).unwrap();
+        assert_eq!(hooked, db.query(&request).unwrap());
+
+        let explained = request.clone().explain(true);
+        let response = db.query_with(&explained, &mut EntryOnly(false), &Metric::Euclidean).unwrap();
+        let layers = response.trace.unwrap().layers;
+        let top = db.layers().last().unwrap();
+        let entry = layers[0].visited[0];
+        let mut dictated = BTreeSet::from([entry]);
+        dictated.extend(top.neighbors(entry).iter().map(|(id, _)| *id));
+        assert_eq!(BTreeSet::from_iter(layers[0].visited.iter().copied()), dictated);
+        // the layers below only score where they're entered
+        assert!(layers[1..].iter().all(|layer| layer.visited.len() == 1));
+        assert_eq!(response.visited, dictated.len());
+
+        // with as many candidates as vectors, the graph is searched through
+        let wide = request.clone().ef(300);
+        let response = db.query_with(&wide, &mut BestFirst, &Chebyshev).unwrap();
+        let mut exact = (0..300u32)
+            .map(|id| (id, Chebyshev.score(&query, &db.get(id).unwrap().unwrap())))
+            .collect::<Vec<_>>();
+        exact.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+        assert_eq!(response.results, exact[..10]);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::db::{Database, DbIndex, DbVector, DbVectorSlice, Error};
+use crate::ext::semaphore::LockAutoClear;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Records the write queue holds before pushing back with [Error::QueueFull].
+const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+/// # Pending ID
+/// A vector waiting in the write queue, see [Database::push_async_queued].
+/// Hand it to [Database::resolve] to learn the [DbIndex] it was committed under.
+pub struct PendingId {
+    slot: Arc<Mutex<Option<Result<DbIndex, Error>>>>,
+}
+
+impl PendingId {
+    /// Whether the writer got to this vector, successfully or not.
+    pub fn is_settled(&self) -> bool {
+        self.slot.lock_auto_clear_poison().is_some()
+    }
+}
+
+pub(crate) struct WriteQueue {
+    capacity: usize,
+    pending: VecDeque<(DbVector, PendingId)>,
+}
+
+impl Default for WriteQueue {
+    fn default() -> Self {
+        WriteQueue {
+            capacity: DEFAULT_QUEUE_CAPACITY,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl Database {
+    /// Enqueues `vector` to be pushed by whichever caller drains the queue
+    /// next, so concurrent producers only contend for the queue itself.
+    ///
+    /// Vectors are committed in the order they were enqueued, so those of a
+    /// single producer are assigned ascending ids. Nothing is ordered between
+    /// producers beyond who got hold of the queue first.
+    ///
+    /// Fails with [Error::QueueFull] once the queue holds its capacity,
+    /// leaving it to the caller to back off or drain.
+    pub fn push_async_queued<V: AsRef<[f32]>>(&self, vector: V) -> Result<PendingId, Error> {
+        self.enqueue(vector.as_ref())
+    }
+
+    fn enqueue(&self, vector: DbVectorSlice) -> Result<PendingId, Error> {
+        self.check_writable()?;
+        let dim_size = self.dim_size();
+        if vector.len() != dim_size as usize {
+            return Err(Error::Dimension(dim_size, vector.len()));
+        }
+        let vector = self.conform_insert(vector);
+        let mut queue = self.queue.lock_auto_clear_poison();
+        if queue.pending.len() >= queue.capacity {
+            return Err(Error::QueueFull(queue.capacity));
+        }
+        let slot = Arc::new(Mutex::new(None));
+        queue.pending.push_back((
+            vector.into_owned(),
+            PendingId { slot: slot.clone() },
+        ));
+        Ok(PendingId { slot })
+    }
+
+    /// Blocks until `pending` is committed, draining the queue if
+    /// nobody else is, and returns its id or why it couldn't be pushed.
+    pub fn resolve(&self, pending: PendingId) -> Result<DbIndex, Error> {
+        if !pending.is_settled() {
+            self.drain_queue()?;
+        }
+        let settled = pending.slot.lock_auto_clear_poison().take();
+        settled.expect("drained queue left a vector unsettled")
+    }
+
+    /// Commits everything in the write queue, oldest first, returning how many
+    /// vectors were taken off it. A vector that fails, for instance by exceeding
+    /// the quota, settles its [PendingId] with the error and doesn't stop the rest.
+    pub fn drain_queue(&self) -> Result<usize, Error> {
+        self.drain_queue_at_most(usize::MAX)
+    }
+
+    /// Same as [Database::drain_queue], stopping after `limit` vectors.
+    pub(crate) fn drain_queue_at_most(&self, limit: usize) -> Result<usize, Error> {
+        let mut written = vec![];
+        let revision = {
+            // holding the handle throughout keeps drainers from interleaving
+            let (mut handle, _records) = self.lock_records();
+            let revision = handle.revision;
+            while written.len() < limit {
+                let Some((vector, pending)) =
+                    self.queue.lock_auto_clear_poison().pending.pop_front()
+                else {
+                    break;
+                };
+                let result = self
+                    .quota
+                    .check(&mut handle, 1)
+                    .and_then(|_| handle.push(&vector));
+                written.push((vector, pending, result));
+            }
+            revision
+        };
+        // searches hold the index while they look the records up, so it's
+        // updated after the handle is let go, see LockRank
+        let drained = written.len();
+        let mut indexed = Ok(());
+        for (vector, pending, result) in written {
+            if let Ok(index) = result {
+                if indexed.is_ok() {
+                    indexed = self.index_insert(index, &vector);
+                }
+                self.loaded_vectors
+                    .lock_auto_clear_poison()
+                    .insert(index, Arc::new(vector));
+            }
+            *pending.slot.lock_auto_clear_poison() = Some(result);
+        }
+        if drained > 0 {
+            // views and responses taken meanwhile lack them in their index
+            self.invalidate_views();
+            self.forget_results_after(revision);
+        }
+        indexed.map(|_| drained)
+    }
+
+    /// Number of vectors waiting in the write queue.
+    pub fn queued(&self) -> usize {
+        self.queue.lock_auto_clear_poison().pending.len()
+    }
+
+    pub fn queue_capacity(&self) -> usize {
+        self.queue.lock_auto_clear_poison().capacity
+    }
+
+    /// Bounds the write queue. Vectors already queued beyond
+    /// the new capacity stay there until drained.
+    pub fn set_queue_capacity(&mut self, capacity: usize) {
+        self.queue.lock_auto_clear_poison().capacity = capacity;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error};
+    use std::collections::HashSet;
+    use std::io::Cursor;
+    use std::thread;
+
+    #[test]
+    fn concurrent_producers_work() {
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        db.set_queue_capacity(16);
+
+        let committed = thread::scope(|scope| {
+            let producers = (0..4)
+                .map(|producer| {
+                    let db = &db;
+                    scope.spawn(move || {
+                        let mut pending = vec![];
+                        for i in 0..50 {
+                            let vector = [producer as f32, i as f32, 0f32, 0f32];
+                            let queued = loop {
+                                match db.push_async_queued(vector) {
+                                    Err(Error::QueueFull(_)) => {
+                                        db.drain_queue().unwrap();
+                                    }
+                                    queued => break queued.unwrap(),
+                                }
+                            };
+                            pending.push((vector, queued));
+                        }
+                        pending
+                            .into_iter()
+                            .map(|(vector, queued)| (vector, db.resolve(queued).unwrap()))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            producers
+                .into_iter()
+                .map(|p| p.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        assert_eq!(db.queued(), 0);
+        assert_eq!(db.count().unwrap(), 200);
+        let mut ids = HashSet::new();
+        for records in committed {
+            // first in, first out per producer
+            assert!(records.windows(2).all(|pair| pair[0].1 < pair[1].1));
+            for (vector, id) in records {
+                assert!(ids.insert(id));
+                assert_eq!(*db.get(id).unwrap().unwrap(), vector);
+            }
+        }
+    }
+
+    #[test]
+    fn queue_full_works() {
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        db.set_queue_capacity(2);
+        let first = db.push_async_queued([0f32; 2]).unwrap();
+        let second = db.push_async_queued([1f32; 2]).unwrap();
+        assert!(matches!(
+            db.push_async_queued([2f32; 2]),
+            Err(Error::QueueFull(2))
+        ));
+        assert!(matches!(
+            db.push_async_queued([0f32; 3]),
+            Err(Error::Dimension(2, 3))
+        ));
+        assert_eq!(db.count().unwrap(), 0);
+
+        assert_eq!(db.resolve(second).unwrap(), 1);
+        assert!(first.is_settled());
+        assert_eq!(db.resolve(first).unwrap(), 0);
+        assert_eq!(db.push([3f32; 2]).unwrap(), 2);
+    }
+}
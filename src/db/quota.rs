@@ -0,0 +1,115 @@
+use crate::db::{Database, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+
+/// # Quota
+/// Limits a database may not grow beyond, recorded in its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quota {
+    pub max_vectors: Option<u64>,
+    /// Limit of the whole file, header and index included.
+    pub max_bytes: Option<u64>,
+}
+
+impl Database {
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Fails with [Error::QuotaExceeded] if `additional` more vectors
+    /// would not fit into the quota.
+    pub(crate) fn check_quota(&self, additional: u64) -> Result<(), Error> {
+        if self.quota == Quota::default() {
+            return Ok(());
+        }
+        self.quota
+            .check(&mut self.handle.lock_auto_clear_poison(), additional)
+    }
+}
+
+impl Quota {
+    /// Same as [Database::check_quota], for callers already holding the handle.
+    pub(super) fn check(&self, handle: &mut VectorHandle, additional: u64) -> Result<(), Error> {
+        if let Some(limit) = self.max_vectors {
+            let attempted = handle.count()? + additional;
+            if attempted > limit {
+                return Err(Error::QuotaExceeded { limit, attempted });
+            }
+        }
+        if let Some(limit) = self.max_bytes {
+            let attempted = handle.len()? + additional * handle.unit_size_bytes();
+            if attempted > limit {
+                return Err(Error::QuotaExceeded { limit, attempted });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error, IdStrategy, Quota};
+    use crate::ext::mem::SharedCursor;
+
+    #[test]
+    fn vector_quota_works() {
+        let file = SharedCursor::new();
+        let quota = Quota {
+            max_vectors: Some(10),
+            max_bytes: None,
+        };
+        let mut db = DatabaseOptions::new(4)
+            .quota(quota)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..10 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        let full = file.bytes();
+        assert!(matches!(
+            db.push([10f32; 4]),
+            Err(Error::QuotaExceeded {
+                limit: 10,
+                attempted: 11
+            })
+        ));
+        assert_eq!(file.bytes(), full);
+
+        db.remove(4).unwrap().unwrap();
+        db.push([10f32; 4]).unwrap();
+        drop(db);
+
+        let mut db = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(db.quota(), quota);
+        assert!(db.push([11f32; 4]).is_err());
+    }
+
+    #[test]
+    fn byte_quota_works() {
+        let file = SharedCursor::new();
+        let db =
+            DatabaseOptions::new(4)
+                .id_strategy(IdStrategy::Monotonic)
+                .create("mem", Box::new(file.reopen()))
+                .unwrap();
+        let header = file.bytes().len() as u64;
+        drop(db);
+
+        let file = SharedCursor::new();
+        let quota = Quota {
+            max_vectors: None,
+            // the limit takes a property, which the header slots have room for
+            max_bytes: Some(header + 2 * 20),
+        };
+        let mut db = DatabaseOptions::new(4)
+            .quota(quota)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([0f32; 4]).unwrap();
+        db.push([1f32; 4]).unwrap();
+        assert!(matches!(
+            db.push([2f32; 4]),
+            Err(Error::QuotaExceeded { .. })
+        ));
+        assert_eq!(file.bytes().len() as u64, header + 2 * 20);
+    }
+}
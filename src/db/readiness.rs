@@ -0,0 +1,210 @@
+use crate::db::{Database, Error, SearchPlan, SearchRequest};
+use crate::ext::semaphore::LockAutoClear;
+use std::borrow::Cow;
+use std::sync::Mutex;
+
+/// # Index State
+/// Whether queries can go through the index in full, see [Database::index_state].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexState {
+    /// There's no index, so queries scan the records.
+    NotBuilt,
+    /// The index is being [built](Database::build_index), `progress` being
+    /// the fraction of the records in it so far.
+    Building { progress: f64 },
+    /// The index holds every record and nothing is waiting to be written.
+    Ready,
+    /// Vectors are waiting in the [write queue](Database::push_async_queued),
+    /// which neither the index nor the records hold yet.
+    Stale { pending_ops: usize },
+}
+
+/// # Index Policy
+/// How queries are answered while the index isn't [ready](IndexState::Ready),
+/// see [DatabaseOptions::index_policy](crate::db::DatabaseOptions::index_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexPolicy {
+    /// Fails with [Error::IndexNotReady].
+    Fail,
+    /// Writes whatever is queued and scans the records, finding the exact
+    /// results however long it takes.
+    Exact,
+    /// Answers with what there is, flagged by [SearchResponse::unready](crate::db::SearchResponse::unready).
+    #[default]
+    Partial,
+}
+
+/// Records inserted into an index being built out of all of them.
+#[derive(Debug, Default)]
+pub(crate) struct BuildProgress(Mutex<Option<(u64, u64)>>);
+
+impl BuildProgress {
+    pub(crate) fn start(&self, total: u64) {
+        *self.0.lock_auto_clear_poison() = Some((0, total));
+    }
+
+    pub(crate) fn advance(&self) {
+        if let Some((done, _)) = self.0.lock_auto_clear_poison().as_mut() {
+            *done += 1;
+        }
+    }
+
+    pub(crate) fn finish(&self) {
+        *self.0.lock_auto_clear_poison() = None;
+    }
+
+    fn get(&self) -> Option<f64> {
+        self.0
+            .lock_auto_clear_poison()
+            .map(|(done, total)| if total > 0 { done as f64 / total as f64 } else { 1f64 })
+    }
+}
+
+impl Database {
+    /// Where the index is at, which changes as vectors are queued and
+    /// written, and as the index is built or dropped. It's not persisted,
+    /// the index being built again once opened.
+    pub fn index_state(&self) -> IndexState {
+        if let Some(progress) = self.building.get() {
+            return IndexState::Building { progress };
+        }
+        if self.index.lock_auto_clear_poison().is_none() {
+            return IndexState::NotBuilt;
+        }
+        match self.queued() {
+            0 => IndexState::Ready,
+            pending_ops => IndexState::Stale { pending_ops },
+        }
+    }
+
+    pub fn index_policy(&self) -> IndexPolicy {
+        self.index_policy
+    }
+
+    /// Takes effect from the next query on. It's not persisted.
+    pub fn set_index_policy(&mut self, policy: IndexPolicy) {
+        self.index_policy = policy;
+    }
+
+    /// `request` the way the [policy](IndexPolicy) has it answered as the
+    /// index is, along with the state to flag the response with if it's not
+    /// ready and served anyway.
+    pub(crate) fn apply_index_policy<'a>(
+        &self,
+        request: &'a SearchRequest,
+    ) -> Result<(Cow<'a, SearchRequest>, Option<IndexState>), Error> {
+        let state = self.index_state();
+        if state == IndexState::Ready {
+            return Ok((Cow::Borrowed(request), None));
+        }
+        match self.index_policy {
+            IndexPolicy::Fail => Err(Error::IndexNotReady(state)),
+            IndexPolicy::Exact => {
+                self.drain_queue()?;
+                Ok((Cow::Owned(request.clone().plan(SearchPlan::Flat)), None))
+            }
+            IndexPolicy::Partial => Ok((Cow::Borrowed(request), Some(state))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        Database, DatabaseOptions, Error, IndexPolicy, IndexState, SearchPlan, SearchRequest,
+    };
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::metric::Metric;
+
+    const QUERY: [f32; 4] = [0.5; 4];
+
+    /// Imports 200 vectors without an index, builds it and queues 50 more,
+    /// returning them all along with the database.
+    fn deferred_import(policy: IndexPolicy) -> (SharedCursor, Database, Vec<Vec<f32>>) {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .index_policy(policy)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut rng = XorShift::new(23);
+        let vectors = (0..250).map(|_| rng.vector(4)).collect::<Vec<_>>();
+        db.push_many(&vectors[..200]).unwrap();
+        assert_eq!(db.index_state(), IndexState::NotBuilt);
+        db.build_index().unwrap();
+        assert_eq!(db.index_state(), IndexState::Ready);
+        for vector in &vectors[200..] {
+            db.push_async_queued(vector).unwrap();
+        }
+        (file, db, vectors)
+    }
+
+    #[test]
+    fn fail_policy_works() {
+        let (_, mut db, _) = deferred_import(IndexPolicy::Fail);
+        let request = SearchRequest::new(&QUERY, 5);
+        assert!(matches!(
+            db.query(&request),
+            Err(Error::IndexNotReady(IndexState::Stale { pending_ops: 50 }))
+        ));
+        db.drain_queue().unwrap();
+        assert!(db.query(&request).unwrap().unready.is_none());
+        db.drop_index();
+        assert!(matches!(
+            db.query(&request),
+            Err(Error::IndexNotReady(IndexState::NotBuilt))
+        ));
+    }
+
+    #[test]
+    fn exact_policy_works() {
+        let (_, mut db, vectors) = deferred_import(IndexPolicy::Exact);
+        let request = SearchRequest::new(&QUERY, 5);
+        let response = db.query(&request).unwrap();
+        // the queued vectors are written first and found along with the rest
+        assert_eq!(db.index_state(), IndexState::Ready);
+        assert_eq!(db.count().unwrap(), 250);
+        assert_eq!((response.plan, response.exact), (SearchPlan::Flat, true));
+        assert_eq!(response.unready, None);
+        let mut closest = (0..250u32).collect::<Vec<_>>();
+        closest.sort_by(|a, b| {
+            let distance = |id: &u32| Metric::Euclidean.distance(&QUERY, &vectors[*id as usize]);
+            distance(a).total_cmp(&distance(b))
+        });
+        let ids = response.results.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, closest[..5]);
+    }
+
+    #[test]
+    fn partial_policy_works() {
+        let (_, mut db, _) = deferred_import(IndexPolicy::default());
+        let request = SearchRequest::new(&QUERY, 5);
+        let response = db.query(&request).unwrap();
+        assert_eq!(response.unready, Some(IndexState::Stale { pending_ops: 50 }));
+        // what's queued is left out
+        assert!(response.results.iter().all(|(id, _)| *id < 200));
+        assert_eq!(db.queued(), 50);
+        db.set_index_policy(IndexPolicy::Fail);
+        assert!(db.query(&request).is_err());
+    }
+
+    #[test]
+    fn flush_settles_index_state() {
+        let (file, db, _) = deferred_import(IndexPolicy::Fail);
+        db.flush().unwrap();
+        assert_eq!(db.index_state(), IndexState::Ready);
+        drop(db);
+
+        // the queued vectors made it, the index didn't
+        let mut db = DatabaseOptions::default()
+            .index_policy(IndexPolicy::Fail)
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.count().unwrap(), 250);
+        assert_eq!(db.index_state(), IndexState::NotBuilt);
+        db.build_index().unwrap();
+        assert_eq!(db.index_state(), IndexState::Ready);
+        let response = db.query(&SearchRequest::new(&QUERY, 5)).unwrap();
+        assert_eq!(response.unready, None);
+    }
+}
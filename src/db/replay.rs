@@ -0,0 +1,182 @@
+use crate::db::{
+    DatabaseOptions, DbIndex, Error, HistogramSnapshot, Operation, SearchPlan, SearchRequest,
+    Trace,
+};
+use crate::ext::rand::XorShift;
+use std::io::Cursor;
+
+/// Responses a replay caches if the trace had any answered from the cache.
+const REPLAY_CACHE: usize = 256;
+
+/// # Replay Report
+/// Latencies of the operations of a [Trace] as [replayed](Trace::replay).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayReport {
+    /// By [Operation], in the order of [Operation::ALL].
+    latencies: Vec<HistogramSnapshot>,
+}
+
+impl ReplayReport {
+    pub fn latency(&self, op: Operation) -> &HistogramSnapshot {
+        &self.latencies[op as usize]
+    }
+}
+
+impl Trace {
+    /// Runs the operations of the trace again on a database in memory of its
+    /// dimensions and metric, filled with as many random vectors drawn from
+    /// `seed` as it started with and indexed if it was or any search went
+    /// through the index. Gets and removes go for random ids stored, pushes
+    /// and searches for random vectors, each search asking for the same `k`,
+    /// `ef` and plan, and repeating the one before if the cache answered it.
+    pub fn replay(&self, seed: u64) -> Result<ReplayReport, Error> {
+        let searches = || self.events.iter().filter_map(|event| event.search);
+        let mut db = DatabaseOptions::new(self.dim_size)
+            .metric(self.metric)
+            .latency_stats(true)
+            .result_cache(if searches().any(|search| search.cached) {
+                REPLAY_CACHE
+            } else {
+                0
+            })
+            .create("replay", Box::new(Cursor::new(Vec::new())))?;
+        let mut rng = XorShift::new(seed);
+        let mut live = (0..self.count)
+            .map(|_| db.push(rng.vector(self.dim_size)))
+            .collect::<Result<Vec<DbIndex>, Error>>()?;
+        if self.indexed || searches().any(|search| search.graph) {
+            db.build_index()?;
+        }
+        db.reset_stats();
+
+        let mut query = rng.vector(self.dim_size);
+        for event in &self.events {
+            let i = match live.len() {
+                0 => None,
+                len => Some((rng.next_u64() % len as u64) as usize),
+            };
+            match event.op {
+                Operation::Get => {
+                    db.get(i.map_or(0, |i| live[i]))?;
+                }
+                Operation::Push => live.push(db.push(rng.vector(self.dim_size))?),
+                Operation::Search => {
+                    let search = event.search.unwrap();
+                    if !search.cached {
+                        query = rng.vector(self.dim_size);
+                    }
+                    let plan = if search.graph {
+                        SearchPlan::Graph
+                    } else {
+                        SearchPlan::Flat
+                    };
+                    let request = SearchRequest::new(&query, search.k).ef(search.ef).plan(plan);
+                    db.query(&request)?;
+                }
+                Operation::Remove => {
+                    db.remove(i.map_or(0, |i| live.swap_remove(i)))?;
+                }
+                Operation::Flush => {
+                    db.flush()?;
+                }
+            }
+        }
+        let stats = db.stats()?;
+        Ok(ReplayReport {
+            latencies: Operation::ALL.iter().map(|op| stats.latency(*op)).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Operation, SearchPlan, SearchRequest, Trace};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+
+    #[test]
+    fn trace_replay_works() {
+        let mut db = DatabaseOptions::new(8)
+            .metric(Metric::Cosine)
+            .result_cache(16)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(5);
+        for _ in 0..200 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        db.build_index().unwrap();
+
+        let trace = SharedCursor::new();
+        db.start_trace(Box::new(trace.reopen())).unwrap();
+        assert!(db.is_tracing());
+        for _ in 0..30 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        for id in 0..20 {
+            db.get(id).unwrap();
+        }
+        let query = rng.vector(8);
+        let request = SearchRequest::new(&query, 5).ef(40).plan(SearchPlan::Graph);
+        db.query(&request).unwrap();
+        db.query(&request).unwrap();
+        for _ in 0..5 {
+            let query = rng.vector(8);
+            let response = db.query(&SearchRequest::new(&query, 3).plan(SearchPlan::Flat)).unwrap();
+            db.query_next(&response.cursor.unwrap()).unwrap();
+        }
+        for id in [3, 4, 5] {
+            db.remove(id).unwrap();
+        }
+        db.flush().unwrap();
+        db.stop_trace().unwrap();
+        assert!(!db.is_tracing());
+        // nothing more once stopped
+        db.push(rng.vector(8)).unwrap();
+
+        let trace = Trace::read(&mut trace.reopen()).unwrap();
+        assert_eq!((trace.dim_size, trace.metric), (8, Metric::Cosine));
+        assert_eq!((trace.count, trace.indexed), (200, true));
+        let searches = trace
+            .events
+            .iter()
+            .filter_map(|event| event.search)
+            .collect::<Vec<_>>();
+        assert_eq!(searches.len(), 12);
+        assert!(searches[0].graph && !searches[0].cached);
+        assert!(searches[1].graph && searches[1].cached);
+        assert_eq!((searches[1].k, searches[1].ef), (5, 40));
+        assert!(searches[2..].iter().all(|search| !search.graph && !search.cached));
+
+        let report = trace.replay(1).unwrap();
+        for op in Operation::ALL {
+            let captured = trace.events.iter().filter(|event| event.op == op).count();
+            assert_eq!(report.latency(op).count(), captured as u64, "{op:?}");
+        }
+        assert_eq!(report.latency(Operation::Push).count(), 30);
+        assert_eq!(report.latency(Operation::Remove).count(), 3);
+        assert!(report.latency(Operation::Search).p99().is_some());
+    }
+
+    #[test]
+    fn trace_rejects_garbage() {
+        assert!(Trace::read(&mut &b"VTRX\x01"[..]).is_err());
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let trace = SharedCursor::new();
+        db.start_trace(Box::new(trace.reopen())).unwrap();
+        db.push([1f32; 2]).unwrap();
+        db.stop_trace().unwrap();
+        let mut bytes = trace.bytes();
+        // cut off within the elapsed time
+        bytes.push(Operation::Get as u8);
+        bytes.push(0x80);
+        assert!(Trace::read(&mut &bytes[..]).is_err());
+        bytes.pop();
+        bytes.pop();
+        assert_eq!(Trace::read(&mut &bytes[..]).unwrap().events.len(), 1);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::db::{Database, DbIndex, Error};
+use crate::ext::semaphore::LockAutoClear;
+
+/// # Sanitize Policy
+/// What [Database::sanitize] does to a vector with components that are NaN,
+/// infinite or subnormal. Zeros of either sign are left as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanitizePolicy {
+    /// Every offending component becomes `0`.
+    Zero,
+    /// Infinities become [f32::MAX] of the same sign,
+    /// while NaN and subnormal components become `0`.
+    Clamp,
+    /// The whole vector is removed.
+    Drop,
+}
+
+impl SanitizePolicy {
+    fn apply(self, component: f32) -> f32 {
+        match self {
+            SanitizePolicy::Clamp if component.is_infinite() => f32::MAX.copysign(component),
+            _ => 0f32,
+        }
+    }
+}
+
+/// # Sanitize Report
+/// What [Database::sanitize] changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SanitizeReport {
+    /// Vectors rewritten in place, in file order.
+    pub rewritten: Vec<DbIndex>,
+    /// Vectors removed under [SanitizePolicy::Drop], in file order.
+    pub dropped: Vec<DbIndex>,
+    /// Offending components found across all of them.
+    pub components: u64,
+}
+
+fn offends(component: f32) -> bool {
+    !component.is_finite() || component.is_subnormal()
+}
+
+impl Database {
+    /// Rewrites the vectors with NaN, infinite or subnormal components
+    /// according to `policy`, keeping the cache, the index and the bloom
+    /// filter up to date as [Database::update] and [Database::remove] do.
+    ///
+    /// Files from before [COMPONENTS_VERBATIM_SINCE](crate::vio::format::COMPONENTS_VERBATIM_SINCE)
+    /// can't hold infinities, which were taken for the end of the data,
+    /// while newer ones keep every component as it was pushed, so this
+    /// is the way to get rid of them.
+    pub fn sanitize(&mut self, policy: SanitizePolicy) -> Result<SanitizeReport, Error> {
+        self.check_writable()?;
+        let mut offending = vec![];
+        let mut report = SanitizeReport::default();
+        self.handle.lock_auto_clear_poison().scan(|id, vector| {
+            let count = vector.iter().filter(|c| offends(**c)).count();
+            if count > 0 {
+                report.components += count as u64;
+                offending.push((id, vector.to_vec()));
+            }
+        })?;
+        if policy == SanitizePolicy::Drop {
+            report.dropped = offending.into_iter().map(|(id, _)| id).collect();
+            self.remove_many(&report.dropped)?;
+            return Ok(report);
+        }
+        for (id, mut vector) in offending {
+            for component in vector.iter_mut().filter(|c| offends(**c)) {
+                *component = policy.apply(*component);
+            }
+            self.update(id, &vector)?;
+            report.rewritten.push(id);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, SanitizePolicy, SanitizeReport};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+
+    const SPECIAL: [f32; 8] = [
+        -0f32,
+        f32::MIN_POSITIVE / 2.0,
+        -f32::from_bits(1),
+        f32::MAX,
+        f32::MIN,
+        f32::INFINITY,
+        f32::NEG_INFINITY,
+        f32::NAN,
+    ];
+
+    #[test]
+    fn special_components_round_trip() {
+        let mut rng = XorShift::new(31);
+        for history in [false, true] {
+            let file = SharedCursor::default();
+            let mut db = DatabaseOptions::new(8)
+                .history(history)
+                .create("special", Box::new(file.clone()))
+                .unwrap();
+            let mut vectors = vec![SPECIAL.to_vec()];
+            for _ in 0..200 {
+                // any bits at all, special values included
+                let bits = (0..8).map(|_| f32::from_bits(rng.next_u64() as u32));
+                vectors.push(bits.collect());
+            }
+            for vector in &vectors {
+                db.push(vector).unwrap();
+            }
+            drop(db);
+
+            let mut db = DatabaseOptions::default()
+                .open("special", Box::new(file.reopen()))
+                .unwrap();
+            let bits = |v: &[f32]| v.iter().map(|c| c.to_bits()).collect::<Vec<_>>();
+            for (id, vector) in vectors.iter().enumerate() {
+                let read = db.get(id as u32).unwrap().unwrap();
+                assert_eq!(bits(&read), bits(vector), "{id}");
+            }
+            let mut scanned = vec![];
+            db.scan_map(|_, v| scanned.push(bits(v))).unwrap();
+            assert_eq!(scanned, vectors.iter().map(|v| bits(v)).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn nan_ranks_last() {
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        db.push([f32::NAN, 0f32]).unwrap();
+        db.push([1f32, 1f32]).unwrap();
+        db.push([-f32::NAN, 0f32]).unwrap();
+        let found = db.search_exact(&[0f32, 0f32], 3).unwrap();
+        assert_eq!(found.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 0, 2]);
+        assert!(Metric::Euclidean.distance(&[-f32::NAN], &[0f32]).is_sign_positive());
+    }
+
+    #[test]
+    fn sanitize_policies_work() {
+        let sane = [1f32, -0f32, 0.5, f32::MAX, f32::MIN, 0f32, 2f32, -3f32];
+        let expected = [
+            (SanitizePolicy::Zero, [-0f32, 0f32, 0f32, f32::MAX, f32::MIN, 0f32, 0f32, 0f32]),
+            (
+                SanitizePolicy::Clamp,
+                [-0f32, 0f32, 0f32, f32::MAX, f32::MIN, f32::MAX, f32::MIN, 0f32],
+            ),
+        ];
+        for (policy, sanitized) in expected {
+            let mut db = DatabaseOptions::new(8)
+                .create("mem", Box::new(Cursor::new(Vec::new())))
+                .unwrap();
+            db.push(sane).unwrap();
+            db.push(SPECIAL).unwrap();
+            db.build_index().unwrap();
+            let report = db.sanitize(policy).unwrap();
+            assert_eq!(
+                report,
+                SanitizeReport {
+                    rewritten: vec![1],
+                    dropped: vec![],
+                    components: 5,
+                }
+            );
+            assert_eq!(*db.get(0).unwrap().unwrap(), sane);
+            let read = db.get(1).unwrap().unwrap();
+            assert_eq!(
+                read.iter().map(|c| c.to_bits()).collect::<Vec<_>>(),
+                sanitized.iter().map(|c| c.to_bits()).collect::<Vec<_>>()
+            );
+            assert_eq!(db.sanitize(policy).unwrap(), SanitizeReport::default());
+        }
+
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        db.push(sane).unwrap();
+        db.push(SPECIAL).unwrap();
+        db.push(sane).unwrap();
+        let report = db.sanitize(SanitizePolicy::Drop).unwrap();
+        assert_eq!(report.dropped, vec![1]);
+        assert!(report.rewritten.is_empty());
+        assert_eq!(db.count().unwrap(), 2);
+        assert_eq!(db.get(1).unwrap(), None);
+    }
+}
@@ -0,0 +1,403 @@
+use crate::db::history::RECORD_PREFIX;
+use crate::db::{Database, DbIndex, DbVectorSlice, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+use crate::vio;
+use crate::vio::format::RECORD_ID_WIDTH;
+use std::cmp::{min, Ordering};
+use std::collections::BinaryHeap;
+use std::ops::ControlFlow;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Bytes of records read at once while scanning.
+pub(super) const SCAN_BLOCK: usize = 64 << 10;
+
+/// A stored vector along with its distance to the query,
+/// ordered by the distance and then by id.
+#[derive(Debug, Clone, Copy)]
+struct Ranked(f32, DbIndex);
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+/// The `k` closest of the vectors offered, kept in a max-heap of
+/// no more than `k` entries however many there are.
+pub(crate) struct TopK {
+    k: usize,
+    heap: BinaryHeap<Ranked>,
+}
+
+impl TopK {
+    pub(crate) fn new(k: usize) -> TopK {
+        TopK {
+            k,
+            heap: BinaryHeap::with_capacity(k),
+        }
+    }
+
+    pub(crate) fn offer(&mut self, id: DbIndex, distance: f32) {
+        let ranked = Ranked(distance, id);
+        if self.heap.len() < self.k {
+            self.heap.push(ranked);
+        } else if self.heap.peek().is_some_and(|farthest| ranked < *farthest) {
+            self.heap.pop();
+            self.heap.push(ranked);
+        }
+    }
+
+    /// Closest first, ties broken by id.
+    pub(crate) fn into_sorted_vec(self) -> Vec<(DbIndex, f32)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Ranked(distance, id)| (id, distance))
+            .collect()
+    }
+}
+
+/// Splits one record of `bytes`, its padding left out, into its id and encoded components.
+fn split_record(bytes: &[u8]) -> (DbIndex, &[u8]) {
+    let (id, components) = bytes.split_at(RECORD_ID_WIDTH as usize);
+    (DbIndex::from_be_bytes(id.try_into().unwrap()), components)
+}
+
+/// Decodes one record of `bytes`, its padding left out, into `vector`.
+#[cfg(feature = "rayon")]
+fn decode_record(bytes: &[u8], vector: &mut crate::db::DbVector, verbatim: bool) -> Result<DbIndex, Error> {
+    let (id, components) = split_record(bytes);
+    vio::vector::decode(components, vector, verbatim).map_err(|_| Error::Parse())?;
+    Ok(id)
+}
+
+impl VectorHandle {
+    /// Calls `f` with every stored vector in file order, reading the records
+    /// a block at a time into one buffer and decoding each into another, so
+    /// that a scan takes the same memory however many there are.
+    /// The vector cache is left alone.
+    pub(crate) fn scan(&mut self, mut f: impl FnMut(DbIndex, DbVectorSlice)) -> Result<(), Error> {
+        self.scan_while(|id, vector| {
+            f(id, vector);
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// Same as [VectorHandle::scan], stopping once `f` breaks. A [VectorStore](crate::db::VectorStore)
+    /// is read through all the same, `f` being called no more.
+    pub(crate) fn scan_while(
+        &mut self,
+        mut f: impl FnMut(DbIndex, DbVectorSlice) -> ControlFlow<()>,
+    ) -> Result<(), Error> {
+        if let Some(store) = self.store.as_mut() {
+            let mut stopped = false;
+            return store.scan(&mut |id, vector| {
+                stopped = stopped || f(id, vector).is_break();
+            });
+        }
+        let mut vector = Vec::with_capacity(self.dim_size as usize);
+        let verbatim = self.verbatim;
+        self.scan_encoded(|id, components| {
+            vio::vector::decode(components, &mut vector, verbatim).map_err(|_| Error::Parse())?;
+            Ok(f(id, &vector))
+        })
+    }
+
+    /// Same as [VectorHandle::scan_while] with the components still encoded,
+    /// for callers to decode them their own way. Not for a [VectorStore](crate::db::VectorStore).
+    pub(crate) fn scan_encoded(
+        &mut self,
+        mut f: impl FnMut(DbIndex, &[u8]) -> Result<ControlFlow<()>, Error>,
+    ) -> Result<(), Error> {
+        if let Some(history) = &self.history {
+            let mut bytes = vec![0u8; self.dim_size as usize * size_of::<f32>()];
+            let live = history.live(None);
+            for (id, offset) in live {
+                self.fd
+                    .seek(SeekFrom::Start(offset + RECORD_PREFIX))
+                    .map_err(Error::IO)?;
+                self.fd.read_exact(&mut bytes).map_err(Error::IO)?;
+                if f(id, &bytes)?.is_break() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        let (unit, record) = (self.unit_size_bytes() as usize, self.record_size_bytes() as usize);
+        let unfilled = self.unfilled();
+        self.scan_blocks(SCAN_BLOCK, |block| {
+            for begin in (0..block.len()).step_by(unit) {
+                let (id, components) = split_record(&block[begin..begin + record]);
+                if unfilled.contains(&id) {
+                    continue;
+                }
+                if f(id, components)?.is_break() {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        })
+    }
+
+    /// Fails with [Error::Parse] if the file ends partway into a record,
+    /// which [VectorHandle::scan_blocks] would otherwise leave out.
+    fn check_tail(&mut self) -> Result<(), Error> {
+        if self.history.is_some() || self.store.is_some() {
+            return Ok(());
+        }
+        let count = self.seek_count()?;
+        let tail = self.len()?.saturating_sub(self.data_section) - count * self.unit_size_bytes();
+        if tail > 0 {
+            return Err(Error::Parse());
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with the records of the file a block of about `block_bytes`
+    /// at a time, whole records each, the padding of the last one possibly cut off,
+    /// until it breaks. Only for files without history, whose records are all live,
+    /// [placeholders](crate::db::Database::reserve_ids) aside.
+    pub(super) fn scan_blocks(
+        &mut self,
+        block_bytes: usize,
+        mut f: impl FnMut(&[u8]) -> Result<ControlFlow<()>, Error>,
+    ) -> Result<(), Error> {
+        let count = self.seek_count()? as usize;
+        let end = self.len()?;
+        self.fd
+            .seek(SeekFrom::Start(self.data_section))
+            .map_err(Error::IO)?;
+        let (unit, record) = (self.unit_size_bytes() as usize, self.record_size_bytes() as usize);
+        let per_block = (block_bytes / unit).max(1);
+        let mut block = vec![0u8; per_block * unit];
+        let mut read = 0;
+        while read < count {
+            let records = min(per_block, count - read);
+            let offset = self.data_section + (read * unit) as u64;
+            let size = min(records * unit, (end - offset) as usize);
+            self.fd.read_exact(&mut block[..size]).map_err(Error::IO)?;
+            // the last record needs no more than itself
+            debug_assert!(size >= (records - 1) * unit + record);
+            if f(&block[..size])?.is_break() {
+                break;
+            }
+            read += records;
+        }
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Calls `f` with every stored vector in file order and collects what it
+    /// returns, for full scans of one's own. Records are streamed from the
+    /// file without going through the vector cache, so memory only grows
+    /// with what `f` returns, which may as well be nothing.
+    pub fn scan_map<F, T>(&mut self, mut f: F) -> Result<Vec<T>, Error>
+    where
+        F: FnMut(DbIndex, DbVectorSlice) -> T,
+    {
+        let mut mapped = vec![];
+        self.handle
+            .lock_auto_clear_poison()
+            .scan(|id, vector| mapped.push(f(id, vector)))?;
+        Ok(mapped)
+    }
+
+    /// Exactly the `k` vectors closest to `query`, closest first and ties
+    /// broken by [DbIndex], in a single pass over the file that keeps
+    /// no more than `k` of them at once, see [Database::scan_map].
+    /// A record cut off at the end of the file fails the search with
+    /// [Error::Parse] rather than being left out.
+    pub fn search_exact(
+        &self,
+        query: DbVectorSlice,
+        k: usize,
+    ) -> Result<Vec<(DbIndex, f32)>, Error> {
+        let mut handle = self.handle.lock_auto_clear_poison();
+        if query.len() != handle.dim_size as usize {
+            return Err(Error::Dimension(handle.dim_size, query.len()));
+        }
+        handle.check_tail()?;
+        let mut top = TopK::new(k);
+        handle.scan(|id, vector| top.offer(id, self.metric.distance(query, vector)))?;
+        Ok(top.into_sorted_vec())
+    }
+
+    /// Same as [Database::search_exact] on `threads` threads, which split
+    /// each block read among them and merge what they found. Memory grows
+    /// with the threads, by a block and `k` vectors each. Append-only
+    /// databases and those over a [VectorStore](crate::db::VectorStore)
+    /// are scanned on the calling thread alone.
+    #[cfg(feature = "rayon")]
+    pub fn search_exact_parallel(
+        &self,
+        query: DbVectorSlice,
+        k: usize,
+        threads: usize,
+    ) -> Result<Vec<(DbIndex, f32)>, Error> {
+        use crate::algorithm::search::merge_top_k;
+        use rayon::prelude::*;
+
+        let mut handle = self.handle.lock_auto_clear_poison();
+        if query.len() != handle.dim_size as usize {
+            return Err(Error::Dimension(handle.dim_size, query.len()));
+        }
+        if handle.history.is_some() || handle.store.is_some() {
+            drop(handle);
+            return self.search_exact(query, k);
+        }
+        handle.check_tail()?;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| Error::IO(std::io::Error::other(e)))?;
+        let (unit, record) = (handle.unit_size_bytes() as usize, handle.record_size_bytes() as usize);
+        let threads = threads.max(1);
+        let (metric, verbatim) = (self.metric, handle.verbatim);
+        let unfilled = handle.unfilled();
+        let mut top = vec![];
+        handle.scan_blocks(SCAN_BLOCK * threads, |block| {
+            let per_range = block.len().div_ceil(unit).div_ceil(threads) * unit;
+            let found = pool.install(|| {
+                block
+                    .par_chunks(per_range)
+                    .map(|range| {
+                        let mut top = TopK::new(k);
+                        let mut vector = Vec::with_capacity(query.len());
+                        for begin in (0..range.len()).step_by(unit) {
+                            let id = decode_record(&range[begin..begin + record], &mut vector, verbatim)?;
+                            if unfilled.contains(&id) {
+                                continue;
+                            }
+                            top.offer(id, metric.distance(query, &vector));
+                        }
+                        Ok(top.into_sorted_vec())
+                    })
+                    .collect::<Result<Vec<_>, Error>>()
+            })?;
+            let lists = found.iter().chain([&top]).map(Vec::as_slice).collect::<Vec<_>>();
+            top = merge_top_k(&lists, k);
+            Ok(ControlFlow::Continue(()))
+        })?;
+        Ok(top)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, Error};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+
+    fn reference(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<(u32, f32)> {
+        let mut ranked = vectors
+            .iter()
+            .enumerate()
+            .map(|(id, v)| (id as u32, Metric::Euclidean.distance(query, v)))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+        ranked.truncate(k);
+        ranked
+    }
+
+    #[test]
+    fn search_exact_streams() {
+        let mut db = DatabaseOptions::new(8)
+            .cache_bytes(4096)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(23);
+        let vectors = (0..50_000).map(|_| rng.vector(8)).collect::<Vec<_>>();
+        db.push_many(vectors.iter().map(|v| v.as_slice()).collect::<Vec<_>>())
+            .unwrap();
+        let cached = |db: &Database| db.loaded_vectors.lock().unwrap().len();
+        let before = cached(&db);
+
+        let query = rng.vector(8);
+        let found = db.search_exact(&query, 10).unwrap();
+        assert_eq!(cached(&db), before);
+        assert_eq!(found, reference(&vectors, &query, 10));
+        assert_eq!(db.search_exact(&query, 0).unwrap(), vec![]);
+        assert_eq!(db.search_exact(&query, 60_000).unwrap().len(), 50_000);
+
+        let norms = db.scan_map(|_, v| v.iter().map(|x| x * x).sum::<f32>()).unwrap();
+        assert_eq!(norms.len(), 50_000);
+        assert_eq!(norms[7], vectors[7].iter().map(|x| x * x).sum::<f32>());
+        assert_eq!(cached(&db), before);
+
+        #[cfg(feature = "rayon")]
+        for threads in [1, 3] {
+            let found = db.search_exact_parallel(&query, 10, threads).unwrap();
+            assert_eq!(found, reference(&vectors, &query, 10));
+        }
+    }
+
+    #[test]
+    fn search_exact_breaks_ties_and_fails_on_cut_off_records() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for vector in [[1f32, 0f32], [0f32, 1f32], [0f32, 0f32], [-1f32, 0f32], [0f32, -1f32]] {
+            db.push(vector).unwrap();
+        }
+        let found = db.search_exact(&[0f32, 0f32], 3).unwrap();
+        assert_eq!(found, vec![(2, 0f32), (0, 1f32), (1, 1f32)]);
+        drop(db);
+
+        let mut bytes = file.bytes();
+        bytes.truncate(bytes.len() - 3);
+        // opening fails on the cut, and so does searching a file read anyway
+        assert!(matches!(
+            DatabaseOptions::default().open("mem", Box::new(Cursor::new(bytes.clone()))),
+            Err(Error::Corrupted(_))
+        ));
+        let db = Database::read_unchecked("mem", Box::new(Cursor::new(bytes))).unwrap();
+        assert!(matches!(db.search_exact(&[0f32, 0f32], 5), Err(Error::Parse())));
+        #[cfg(feature = "rayon")]
+        assert!(matches!(
+            db.search_exact_parallel(&[0f32, 0f32], 5, 2),
+            Err(Error::Parse())
+        ));
+    }
+
+    #[test]
+    fn search_exact_works_aligned_and_with_history() {
+        let mut rng = XorShift::new(29);
+        let vectors = (0..300).map(|_| rng.vector(3)).collect::<Vec<_>>();
+        let query = rng.vector(3);
+        for options in [
+            DatabaseOptions::new(3).alignment(64),
+            DatabaseOptions::new(3).history(true),
+        ] {
+            let mut db = options
+                .create("mem", Box::new(Cursor::new(Vec::new())))
+                .unwrap();
+            for v in &vectors {
+                db.push(v).unwrap();
+            }
+            assert_eq!(db.search_exact(&query, 5).unwrap(), reference(&vectors, &query, 5));
+            #[cfg(feature = "rayon")]
+            assert_eq!(
+                db.search_exact_parallel(&query, 5, 2).unwrap(),
+                reference(&vectors, &query, 5)
+            );
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::db::{Database, Error};
+use crate::metric::Metric;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// # Element
+/// Type each component of a vector is stored as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Element {
+    #[default]
+    F32,
+}
+
+/// # Schema Expectations
+/// What a caller takes a database to be, checked on open,
+/// see [DatabaseOptions::expect_dim](crate::db::DatabaseOptions::expect_dim).
+/// Anything left unset goes unchecked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Expectations {
+    pub dim: Option<u32>,
+    pub metric: Option<Metric>,
+    pub element: Option<Element>,
+}
+
+/// # Schema Mismatch
+/// A property of a database that differs from its [Expectations].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaMismatch {
+    pub field: &'static str,
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{0} is {1} rather than {2}",
+            self.field, self.found, self.expected
+        )
+    }
+}
+
+impl Expectations {
+    pub fn dim(mut self, dim: u32) -> Self {
+        self.dim = Some(dim);
+        self
+    }
+
+    pub fn metric(mut self, metric: Metric) -> Self {
+        self.metric = Some(metric);
+        self
+    }
+
+    pub fn element(mut self, element: Element) -> Self {
+        self.element = Some(element);
+        self
+    }
+
+    /// Fails with [Error::SchemaMismatch] listing every field of `db` that
+    /// isn't as expected. Files from before the metric was recorded
+    /// can't have theirs checked.
+    pub(crate) fn check(&self, db: &Database) -> Result<(), Error> {
+        let mut mismatches = vec![];
+        let mut compare = |field, expected: Option<String>, found: Option<String>| {
+            if let (Some(expected), Some(found)) = (expected, found) {
+                if expected != found {
+                    mismatches.push(SchemaMismatch {
+                        field,
+                        expected,
+                        found,
+                    });
+                }
+            }
+        };
+        compare(
+            "dim",
+            self.dim.map(|d| d.to_string()),
+            Some(db.dim_size().to_string()),
+        );
+        compare(
+            "metric",
+            self.metric.map(|m| format!("{m:?}")),
+            db.recorded_metric().map(|m| format!("{m:?}")),
+        );
+        compare(
+            "element",
+            self.element.map(|e| format!("{e:?}")),
+            Some(format!("{:?}", db.element())),
+        );
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::SchemaMismatch(mismatches))
+        }
+    }
+}
+
+impl Database {
+    pub fn element(&self) -> Element {
+        Element::F32
+    }
+
+    /// Metric the database was created with, absent from files
+    /// older than version 8.
+    pub fn recorded_metric(&self) -> Option<Metric> {
+        self.recorded_metric
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Element, Error, SchemaMismatch};
+    use crate::ext::mem::SharedCursor;
+    use crate::metric::Metric;
+
+    #[test]
+    fn schema_mismatches_reported() {
+        let file = SharedCursor::new();
+        DatabaseOptions::new(512)
+            .metric(Metric::Euclidean)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+
+        let opened = DatabaseOptions::default()
+            .expect_dim(768)
+            .expect_metric(Metric::Cosine)
+            .expect_element(Element::F32)
+            .open("mem", Box::new(file.reopen()));
+        let Err(Error::SchemaMismatch(mismatches)) = opened else {
+            panic!("opened despite the mismatches");
+        };
+        assert_eq!(
+            mismatches,
+            vec![
+                SchemaMismatch {
+                    field: "dim",
+                    expected: String::from("768"),
+                    found: String::from("512"),
+                },
+                SchemaMismatch {
+                    field: "metric",
+                    expected: String::from("Cosine"),
+                    found: String::from("Euclidean"),
+                },
+            ]
+        );
+
+        let db = DatabaseOptions::default()
+            .expect_dim(512)
+            .expect_metric(Metric::Euclidean)
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.recorded_metric(), Some(Metric::Euclidean));
+    }
+
+    #[test]
+    fn metric_recorded() {
+        let file = SharedCursor::new();
+        DatabaseOptions::new(2)
+            .metric(Metric::Cosine)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.metric(), Metric::Cosine);
+        // the options still take precedence
+        let db = DatabaseOptions::default()
+            .metric(Metric::DotProduct)
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        assert_eq!(db.metric(), Metric::DotProduct);
+        assert_eq!(db.recorded_metric(), Some(Metric::Cosine));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::algorithm::search::BestFirst;
+use crate::db::{Database, DbIndex, DbVectorSlice, Error};
+use crate::ds::layer::HnswLayer;
+use crate::ext::semaphore::LockAutoClear;
+use crate::index::SearchScratch;
+use std::cell::RefCell;
+use std::cmp::{max, Reverse};
+use std::collections::{HashMap, LinkedList};
+
+/// Node a search through `layers` starts from, the [default](HnswLayer::default_entry)
+/// of the topmost one that isn't empty, which takes looking at every node of it.
+pub(super) fn layer_entry(layers: &LinkedList<HnswLayer>) -> Option<u32> {
+    layers
+        .iter()
+        .filter(|layer| !layer.is_empty())
+        .max_by_key(|layer| layer.level())
+        .and_then(HnswLayer::default_entry)
+}
+
+impl Database {
+    /// Approximately the `k` vectors closest to `query`, closest first,
+    /// keeping as many candidates as the [search parameters](Database::search_params)
+    /// say. Goes through the index if it's [built](Database::build_index),
+    /// and otherwise through the layers read from the file: greedily from the
+    /// node with the most neighbors on the top one down, each handing the node
+    /// it stops at to the next, then best first on the base. Vectors are read
+    /// on demand, through the cache.
+    ///
+    /// Only what the layers were written with is reached through them, so
+    /// vectors pushed since aren't. Without either, every vector is compared
+    /// against, see [Database::search_exact].
+    pub fn search(&self, query: DbVectorSlice, k: usize) -> Result<Vec<(DbIndex, f32)>, Error> {
+        let dim_size = self.dim_size();
+        if query.len() != dim_size as usize {
+            return Err(Error::Dimension(dim_size, query.len()));
+        }
+        if k == 0 {
+            return Ok(vec![]);
+        }
+        let ef = max(k, self.search_params().ef_search as usize);
+        if let Some(index) = self.index.lock_auto_clear_poison().as_ref() {
+            let present = &mut |id| self.handle.lock_auto_clear_poison().contains(id);
+            let hooks = (&mut BestFirst, &index.metric());
+            let mut scratch = SearchScratch::new();
+            let bounds = (k, ef, None);
+            let found = index.search_in(query, bounds, None, Some(present), hooks, &mut scratch)?;
+            return Ok(found.results);
+        }
+        let mut layers = self.layers.iter().filter(|layer| !layer.is_empty()).collect::<Vec<_>>();
+        layers.sort_by_key(|layer| Reverse(layer.level()));
+        let Some((base, upper)) = layers.split_last() else {
+            return self.search_exact(query, k);
+        };
+
+        // removed vectors are left behind in the layers, measured as infinitely
+        // far, and the first failure to read one is kept to be returned
+        let measured = RefCell::new(HashMap::new());
+        let failed = RefCell::new(None);
+        let distance = |id: DbIndex| -> f32 {
+            if let Some(distance) = measured.borrow().get(&id) {
+                return *distance;
+            }
+            let distance = match self.get(id) {
+                Ok(Some(vector)) => self.metric.distance(query, &vector),
+                Ok(None) => f32::INFINITY,
+                Err(e) => {
+                    failed.borrow_mut().get_or_insert(e);
+                    f32::INFINITY
+                }
+            };
+            measured.borrow_mut().insert(id, distance);
+            distance
+        };
+        let Some(mut entry) = self.layer_entry else {
+            return Ok(vec![]);
+        };
+        for layer in upper {
+            entry = layer.descend_from(entry, distance);
+        }
+        let mut found = base.search(entry, ef, distance);
+        if let Some(e) = failed.take() {
+            return Err(e);
+        }
+        found.retain(|(_, distance)| distance.is_finite());
+        found.truncate(k);
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use std::io::Cursor;
+
+    #[test]
+    fn search_works() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(8)
+            .layer_section(1 << 20)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        // nothing stored, nothing to descend
+        assert!(db.search(&[0f32; 8], 5).unwrap().is_empty());
+        assert!(matches!(db.search(&[0f32; 3], 5), Err(Error::Dimension(8, 3))));
+
+        let mut rng = XorShift::new(41);
+        for _ in 0..1000 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        db.build_index().unwrap();
+        db.flush().unwrap();
+        drop(db);
+        let mut db = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        // the base read back at level zero, with the first vector on it
+        let base = db.layers().find(|view| view.level() == 0).unwrap();
+        assert_eq!(base.node_count(), 1000);
+        assert!(!base.neighbors(0).is_empty());
+        // found once on open, from the topmost layer
+        let top = db.layers.iter().max_by_key(|layer| layer.level()).unwrap();
+        assert!(top.level() > 0);
+        assert_eq!(db.layer_entry, top.default_entry());
+
+        let (mut hits, mut total) = (0, 0);
+        for _ in 0..20 {
+            let query = rng.vector(8);
+            let found = db.search(&query, 10).unwrap();
+            assert_eq!(found.len(), 10);
+            assert!(found.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+            let exact = db.search_exact(&query, 10).unwrap();
+            hits += found.iter().filter(|hit| exact.contains(hit)).count();
+            total += exact.len();
+        }
+        assert!(hits * 10 >= total * 9, "{hits} of {total}");
+
+        // more than there are, and the removed left out
+        db.remove(0).unwrap();
+        let all = db.search(&[0f32; 8], 5000).unwrap();
+        assert_eq!(all.len(), 999);
+        assert!(all.iter().all(|(id, _)| *id != 0));
+        assert!(db.search(&[0f32; 8], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_goes_through_the_built_index() {
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(43);
+        for _ in 0..200 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        // neither an index nor layers, so every vector is compared against
+        let query = rng.vector(8);
+        assert_eq!(db.search(&query, 10).unwrap(), db.search_exact(&query, 10).unwrap());
+
+        db.build_index().unwrap();
+        let (mut hits, mut total) = (0, 0);
+        for _ in 0..20 {
+            let query = rng.vector(8);
+            let found = db.search(&query, 10).unwrap();
+            assert_eq!(found.len(), 10);
+            let exact = db.search_exact(&query, 10).unwrap();
+            hits += found.iter().filter(|hit| exact.contains(hit)).count();
+            total += exact.len();
+        }
+        assert!(hits * 10 >= total * 9, "{hits} of {total}");
+        db.remove(0).unwrap();
+        let all = db.search(&[0f32; 8], 500).unwrap();
+        assert!(all.iter().all(|(id, _)| *id != 0));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::db::amplification::ratio;
+use crate::db::{Database, Error, HistogramSnapshot, Operation, VectorHandle};
+use crate::ext::counter::HitCounts;
+use crate::vio;
+use crate::vio::dbheader::DbHeader;
+use crate::vio::format;
+use std::io::{Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// # Database Statistics
+/// Where the bytes of a database go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbStats {
+    pub vectors: u64,
+    /// Size of the whole file, header and index included.
+    pub file_bytes: u64,
+    /// Bytes each record takes, padding included.
+    pub record_bytes: u64,
+    /// Bytes of each record spent on [alignment](Database::with_alignment).
+    pub padding_per_record: u64,
+    /// Bytes of the file spent on alignment, before and between records.
+    pub padding_bytes: u64,
+    /// Bytes of records pushed, removed or updated since the stats were reset.
+    pub logical_bytes: u64,
+    /// Bytes written to the file for them, those of other records
+    /// moved out of the way included.
+    pub physical_bytes: u64,
+    /// Same as [DbStats::logical_bytes] over the most recent writes only,
+    /// see [AdvisoryConfig](crate::db::AdvisoryConfig).
+    pub recent_logical_bytes: u64,
+    pub recent_physical_bytes: u64,
+    /// Queries that started at an [entry shortcut](crate::db::DatabaseOptions::entry_shortcuts),
+    /// and those that went through the index without one.
+    pub shortcut_hits: u64,
+    pub shortcut_misses: u64,
+    /// Queries answered from the [result cache](Database::set_result_cache),
+    /// and those answered anew while it was on.
+    pub result_cache_hits: u64,
+    pub result_cache_misses: u64,
+    /// Ids the index would have returned that weren't stored,
+    /// see [SearchResponse::dangling](crate::db::SearchResponse::dangling).
+    pub dangling_nodes: u64,
+    /// Queries and vectors pushed or updated that were normalized on the fly,
+    /// see [QueryVector](crate::db::QueryVector).
+    pub normalized_queries: u64,
+    pub normalized_inserts: u64,
+    latencies: Vec<HistogramSnapshot>,
+}
+
+impl DbStats {
+    /// Latencies of `op` since the database was opened or its stats
+    /// [reset](Database::reset_stats), empty unless they're
+    /// [recorded](crate::db::DatabaseOptions::latency_stats).
+    pub fn latency(&self, op: Operation) -> HistogramSnapshot {
+        self.latencies[op as usize].clone()
+    }
+
+    /// Bytes written per byte of records changed, one if nothing was.
+    pub fn write_amplification(&self) -> f64 {
+        ratio(self.logical_bytes, self.physical_bytes)
+    }
+
+    /// Same as [DbStats::write_amplification] over the most recent writes only.
+    pub fn recent_write_amplification(&self) -> f64 {
+        ratio(self.recent_logical_bytes, self.recent_physical_bytes)
+    }
+
+    /// Fraction of the queries through the index that took a shortcut,
+    /// zero if there were none.
+    pub fn shortcut_hit_rate(&self) -> f64 {
+        match self.shortcut_hits + self.shortcut_misses {
+            0 => 0f64,
+            queries => self.shortcut_hits as f64 / queries as f64,
+        }
+    }
+
+    /// Fraction of the queries answered from the result cache,
+    /// zero if there were none while it was on.
+    pub fn result_cache_hit_rate(&self) -> f64 {
+        match self.result_cache_hits + self.result_cache_misses {
+            0 => 0f64,
+            queries => self.result_cache_hits as f64 / queries as f64,
+        }
+    }
+}
+
+/// # Stat Counters
+/// What [Database::stats] reports, kept in atomics that the operations
+/// changing it update before they return, so that stats are read without
+/// waiting on the records or the caches. Those of the records are
+/// [published](VectorHandle::publish) by each write.
+pub(crate) struct StatCounters {
+    record_bytes: u64,
+    padding_per_record: u64,
+    /// Between the end of everything before the data section and its start.
+    data_padding: u64,
+    vectors: AtomicU64,
+    file_bytes: AtomicU64,
+    /// Slots in the data section, superseded records included.
+    records: AtomicU64,
+    logical_bytes: AtomicU64,
+    physical_bytes: AtomicU64,
+    recent_logical_bytes: AtomicU64,
+    recent_physical_bytes: AtomicU64,
+    pub(crate) dangling: AtomicU64,
+    pub(crate) shortcuts: Arc<HitCounts>,
+    pub(crate) results: Arc<HitCounts>,
+}
+
+impl StatCounters {
+    /// Counters of nothing yet, for records laid out the way `header` says.
+    pub(crate) fn new(header: &DbHeader) -> StatCounters {
+        let record_size = format::record_size(header.dim_size, header.history.is_some());
+        let record_bytes = vio::dbheader::align(record_size, header.alignment);
+        let data_padding = match header.alignment {
+            Some(_) => header.data_section.saturating_sub(header.prefix_end()),
+            None => 0,
+        };
+        StatCounters {
+            record_bytes,
+            padding_per_record: record_bytes - record_size,
+            data_padding,
+            vectors: AtomicU64::new(0),
+            file_bytes: AtomicU64::new(0),
+            records: AtomicU64::new(0),
+            logical_bytes: AtomicU64::new(0),
+            physical_bytes: AtomicU64::new(0),
+            recent_logical_bytes: AtomicU64::new(0),
+            recent_physical_bytes: AtomicU64::new(0),
+            dangling: AtomicU64::new(0),
+            shortcuts: Arc::default(),
+            results: Arc::default(),
+        }
+    }
+
+    /// Bytes each record takes, padding included.
+    pub(crate) fn record_bytes(&self) -> u64 {
+        self.record_bytes
+    }
+
+    pub(crate) fn vectors(&self) -> u64 {
+        self.vectors.load(Ordering::Relaxed)
+    }
+}
+
+impl VectorHandle {
+    /// Updates the [counters](StatCounters) of the records after a write,
+    /// leaving those it fails to measure as they were.
+    pub(crate) fn publish(&mut self) {
+        let counters = self.counters.clone();
+        let (recent_logical, recent_physical) = self.amplification.recent();
+        counters
+            .logical_bytes
+            .store(self.amplification.logical, Ordering::Relaxed);
+        counters
+            .physical_bytes
+            .store(self.amplification.physical, Ordering::Relaxed);
+        counters
+            .recent_logical_bytes
+            .store(recent_logical, Ordering::Relaxed);
+        counters
+            .recent_physical_bytes
+            .store(recent_physical, Ordering::Relaxed);
+        let Ok(pos) = self.fd.stream_position() else {
+            return;
+        };
+        if let Ok(vectors) = self.count() {
+            counters.vectors.store(vectors, Ordering::Relaxed);
+        }
+        if let Ok(records) = self.seek_count() {
+            counters.records.store(records, Ordering::Relaxed);
+        }
+        if let Ok(len) = self.len() {
+            counters.file_bytes.store(len, Ordering::Relaxed);
+        }
+        let _ = self.fd.seek(SeekFrom::Start(pos));
+    }
+}
+
+impl Database {
+    /// Reads the counters without locking the records or the caches, so that
+    /// polling it doesn't hold up writes and queries. Each value is as of the
+    /// last operation that changed it, and may lag behind the one underway
+    /// by that operation, so values read while writing needn't agree with
+    /// each other.
+    pub fn stats(&self) -> Result<DbStats, Error> {
+        let counters = &self.counters;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let (shortcut_hits, shortcut_misses) = counters.shortcuts.get();
+        let (result_cache_hits, result_cache_misses) = counters.results.get();
+        Ok(DbStats {
+            vectors: load(&counters.vectors),
+            file_bytes: load(&counters.file_bytes),
+            record_bytes: counters.record_bytes,
+            padding_per_record: counters.padding_per_record,
+            padding_bytes: counters.data_padding
+                + load(&counters.records) * counters.padding_per_record,
+            logical_bytes: load(&counters.logical_bytes),
+            physical_bytes: load(&counters.physical_bytes),
+            recent_logical_bytes: load(&counters.recent_logical_bytes),
+            recent_physical_bytes: load(&counters.recent_physical_bytes),
+            shortcut_hits,
+            shortcut_misses,
+            result_cache_hits,
+            result_cache_misses,
+            dangling_nodes: load(&counters.dangling),
+            normalized_queries: load(&self.normalizations.queries),
+            normalized_inserts: load(&self.normalizations.inserts),
+            latencies: self.latency_snapshots(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, SearchRequest};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::index::SearchScratch;
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn aligned_records_work() {
+        let file = SharedCursor::new();
+        // 4 bytes of id and 5 * 4 bytes of vector, padded to 32
+        let mut db = DatabaseOptions::new(5)
+            .alignment(32)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..10 {
+            db.push([i as f32; 5]).unwrap();
+        }
+        let data_section = {
+            let handle = db.handle.lock().unwrap();
+            handle.data_section
+        };
+        assert_eq!(data_section % 32, 0);
+        assert_eq!(file.len(), data_section + 10 * 32);
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.vectors, 10);
+        assert_eq!(stats.record_bytes, 32);
+        assert_eq!(stats.padding_per_record, 8);
+        assert!(stats.padding_bytes >= 80);
+
+        assert_eq!(*db.remove(3).unwrap().unwrap(), vec![3f32; 5]);
+        assert_eq!(file.len(), data_section + 9 * 32);
+        drop(db);
+
+        let mut db = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        for i in [0, 4, 9] {
+            assert_eq!(*db.get(i).unwrap().unwrap(), vec![i as f32; 5]);
+        }
+        assert!(db.get(3).unwrap().is_none());
+        let offsets = crate::vio::inspect(&mut file.reopen()).unwrap().records;
+        assert_eq!(offsets.len(), 9);
+        assert!(offsets.iter().all(|r| r.offset % 32 == 0));
+        assert_eq!(db.push([10f32; 5]).unwrap(), 10);
+    }
+
+    #[test]
+    fn stats_under_load_work() {
+        const PUSHES: u64 = 300;
+        let db = DatabaseOptions::new(4)
+            .result_cache(16)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let done = AtomicBool::new(false);
+        let mut queries = 0u64;
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut last = 0;
+                while !done.load(Ordering::Relaxed) {
+                    // never waits on the writer, and never goes back
+                    let stats = db.stats().unwrap();
+                    assert!(stats.vectors >= last && stats.vectors <= PUSHES);
+                    last = stats.vectors;
+                }
+            });
+            let mut rng = XorShift::new(7);
+            let mut scratch = SearchScratch::new();
+            for i in 0..PUSHES {
+                let pending = db.push_async_queued(rng.vector(4)).unwrap();
+                db.resolve(pending).unwrap();
+                if i % 3 == 0 {
+                    let request = SearchRequest::new(&[1f32; 4], 3);
+                    db.query_with_scratch(&request, &mut scratch).unwrap();
+                    queries += 1;
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.vectors, PUSHES);
+        assert_eq!(stats.logical_bytes, PUSHES * stats.record_bytes);
+        let data_section = db.handle.lock().unwrap().data_section;
+        assert_eq!(stats.file_bytes, data_section + PUSHES * stats.record_bytes);
+        assert_eq!(stats.result_cache_hits + stats.result_cache_misses, queries);
+    }
+}
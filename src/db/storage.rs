@@ -0,0 +1,374 @@
+use crate::db::{Database, DbIndex, DbVector, Error, VectorHandle};
+use crate::ext::semaphore::{lock_in_order, LockAutoClear, LockRank};
+use crate::vio;
+use crate::vio::RandomAccess;
+use byteorder::{BigEndian, ReadBytesExt};
+use std::cmp::Ordering;
+use std::io;
+use std::io::SeekFrom;
+use std::sync::{Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Where the records of a database without history are,
+/// enough to look one up through any handle to the file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RecordLayout {
+    pub(crate) dim_size: u32,
+    pub(crate) data_section: u64,
+    pub(crate) unit: u64,
+    pub(crate) verbatim: bool,
+}
+
+/// # Vector Reader
+/// Looks up the records, sorted by id, through a handle to the file
+/// from wherever it was left, moving nothing.
+pub(crate) struct VectorReader<'a> {
+    pub(crate) layout: RecordLayout,
+    pub(crate) fd: &'a mut dyn RandomAccess,
+}
+
+impl VectorReader<'_> {
+    /// Records in the file, a record cut off at the end not being one.
+    pub(crate) fn count(&mut self) -> Result<u64, Error> {
+        let available = self.fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+        Ok(available.saturating_sub(self.layout.data_section) / self.layout.unit)
+    }
+
+    /// Binary searches the records sorted by id, leaving the handle
+    /// right after the id of the one found.
+    pub(crate) fn seek_item(&mut self, id: DbIndex) -> Result<Option<u64>, Error> {
+        let RecordLayout {
+            data_section, unit, ..
+        } = self.layout;
+        let available = self.fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+        let (mut head, mut tail) = (0u64, (available + 1 - data_section) / unit);
+
+        // employ a binary search between [head] and [tail) in fd
+        while head < tail {
+            let middle = head + (tail - head) / 2;
+            let pos = middle * unit + data_section;
+            self.fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+            let middle_id = self.fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+
+            match middle_id.cmp(&id) {
+                Ordering::Equal => return Ok(Some(pos)),
+                Ordering::Less => head = middle + 1,
+                Ordering::Greater => tail = middle,
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn get(&mut self, id: DbIndex) -> Result<Option<DbVector>, Error> {
+        if self.seek_item(id)?.is_none() {
+            return Ok(None);
+        }
+        let dim_size = self.layout.dim_size;
+        Ok(Some(vio::vector::read(dim_size, self.fd, self.layout.verbatim).map_err(|e| match e {
+            vio::Error::Eof => Error::IO(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "expecting {0} bytes of data, but got none",
+                    dim_size * size_of::<f32>() as u32
+                ),
+            )),
+            vio::Error::IO(e) => Error::IO(e),
+        })?))
+    }
+}
+
+/// # Vector Writer
+/// Where the next record is appended and the id of the last one, kept
+/// between pushes so they don't seek for them. Forgotten whenever
+/// records are moved or cut off, and looked up again on the next push.
+#[derive(Debug, Default)]
+pub(crate) struct VectorWriter {
+    end: Option<u64>,
+    /// `Some(None)` if there are no records.
+    last_id: Option<Option<DbIndex>>,
+}
+
+impl VectorWriter {
+    pub(crate) fn end(&self) -> Option<u64> {
+        self.end
+    }
+
+    pub(crate) fn last_id(&self) -> Option<Option<DbIndex>> {
+        self.last_id
+    }
+
+    pub(crate) fn know(&mut self, end: u64, last_id: Option<DbIndex>) {
+        self.end = Some(end);
+        self.last_id = Some(last_id);
+    }
+
+    pub(crate) fn forget(&mut self) {
+        *self = VectorWriter::default();
+    }
+}
+
+/// # Storage Coordinator
+/// Hands out the extra handles to the file of a database that readers
+/// check out, so they don't queue up behind the one writes go through,
+/// and keeps them out while records are moved around.
+#[derive(Default)]
+pub(crate) struct StorageCoordinator {
+    size: usize,
+    idle: Mutex<Vec<Box<dyn RandomAccess>>>,
+    returned: Condvar,
+    /// Where the records are, held shared by readers and exclusively by
+    /// writers while they move or cut off records.
+    records: RwLock<Option<RecordLayout>>,
+}
+
+pub(crate) struct PooledReader<'a> {
+    pool: &'a StorageCoordinator,
+    fd: Option<Box<dyn RandomAccess>>,
+    layout: RwLockReadGuard<'a, Option<RecordLayout>>,
+}
+
+impl StorageCoordinator {
+    /// Waits for an idle handle and for records to stay in place,
+    /// or returns `None` right away if there are no handles at all.
+    pub(crate) fn read(&self) -> Option<PooledReader<'_>> {
+        if self.size == 0 {
+            return None;
+        }
+        let mut idle = self.idle.lock_auto_clear_poison();
+        let fd = loop {
+            match idle.pop() {
+                Some(fd) => break fd,
+                None => idle = self.returned.wait(idle).unwrap_or_else(|e| e.into_inner()),
+            }
+        };
+        drop(idle);
+        let reader = PooledReader {
+            pool: self,
+            fd: Some(fd),
+            layout: self.records.read().unwrap_or_else(|e| e.into_inner()),
+        };
+        reader.layout.is_some().then_some(reader)
+    }
+
+    /// Keeps readers out while records are moved around. Taken after
+    /// the vector handle, which readers never lock while they hold a
+    /// [PooledReader], so the two can't wait on each other, see
+    /// [Database::lock_records].
+    /// The layout is to be updated through the guard if the records move
+    /// as a whole.
+    pub(crate) fn rewrite(&self) -> RwLockWriteGuard<'_, Option<RecordLayout>> {
+        self.records.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes in the records being laid out anew, as on a
+    /// [reload](Database::check_external_changes), reading them through
+    /// the main handle alone if they no longer can be otherwise.
+    pub(crate) fn relayout(&mut self, layout: Option<RecordLayout>) {
+        let current = self.records.get_mut().unwrap_or_else(|e| e.into_inner());
+        if current.is_some() {
+            *current = layout;
+        }
+    }
+}
+
+impl PooledReader<'_> {
+    pub(crate) fn reader(&mut self) -> VectorReader<'_> {
+        VectorReader {
+            layout: self.layout.unwrap(),
+            fd: self.fd.as_mut().unwrap().as_mut(),
+        }
+    }
+}
+
+impl Drop for PooledReader<'_> {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd.take() {
+            self.pool.idle.lock_auto_clear_poison().push(fd);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+impl Database {
+    /// The vector handle and the records, held by writes that may move
+    /// records around, taken in [LockRank] order.
+    pub(super) fn lock_records(
+        &self,
+    ) -> (MutexGuard<'_, VectorHandle>, RwLockWriteGuard<'_, Option<RecordLayout>>) {
+        lock_in_order(
+            (LockRank::Handle, &*self.handle),
+            (LockRank::Records, &self.storage),
+            Mutex::lock_auto_clear_poison,
+            StorageCoordinator::rewrite,
+        )
+    }
+
+    /// Hands over more handles to the file of this database for reads to go
+    /// through in parallel, e.g. one per core. Writes keep using the handle the
+    /// database was opened with. Append-only databases and those over a
+    /// [VectorStore](crate::db::VectorStore) ignore them and read through that one too.
+    pub fn add_read_handles(&mut self, handles: Vec<Box<dyn RandomAccess>>) {
+        let handle = self.handle.lock_auto_clear_poison();
+        if handle.history.is_some() || handle.store.is_some() {
+            return;
+        }
+        *self
+            .storage
+            .records
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner()) = Some(handle.layout());
+        self.storage.size += handles.len();
+        self.storage
+            .idle
+            .lock_auto_clear_poison()
+            .extend(handles);
+    }
+
+    /// Number of handles reads may go through at once besides the main one.
+    pub fn read_handles(&self) -> usize {
+        self.storage.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{CacheMode, DatabaseOptions, IdStrategy};
+    use crate::ext::mem::SharedCursor;
+    use crate::vio::RandomAccess;
+    use std::collections::HashSet;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn pooled_reads_work() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(8).create("mem", Box::new(file.reopen())).unwrap();
+        for i in 0..500 {
+            db.push([i as f32; 8]).unwrap();
+        }
+        drop(db);
+
+        let mut db = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        db.add_read_handles((0..4).map(|_| Box::new(file.reopen()) as _).collect());
+        assert_eq!(db.read_handles(), 4);
+        thread::scope(|scope| {
+            for reader in 0..16u32 {
+                let db = &db;
+                scope.spawn(move || {
+                    for i in (reader..500).step_by(16) {
+                        assert_eq!(*db.get(i).unwrap().unwrap(), vec![i as f32; 8]);
+                    }
+                    assert!(db.get(500 + reader).unwrap().is_none());
+                });
+            }
+        });
+
+        let cursor_only = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        assert_eq!(cursor_only.read_handles(), 0);
+        assert!(cursor_only.get(0).unwrap().is_none());
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    /// to compare read throughput with and without a pool.
+    #[test]
+    #[ignore]
+    fn pooled_reads_scale() {
+        let path = std::env::temp_dir().join("vectoria-read-pool.db");
+        let open = || -> Box<dyn RandomAccess> {
+            Box::new(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&path)
+                    .unwrap(),
+            )
+        };
+        let _ = fs::remove_file(&path);
+        let mut db = DatabaseOptions::new(128).create("bench", open()).unwrap();
+        for i in 0..20000 {
+            db.push([i as f32; 128]).unwrap();
+        }
+        drop(db);
+
+        let threads = thread::available_parallelism().map_or(4, |n| n.get());
+        for pooled in [false, true] {
+            let mut db = DatabaseOptions::default().open("bench", open()).unwrap();
+            if pooled {
+                db.add_read_handles((0..threads).map(|_| open()).collect());
+            }
+            let begin = Instant::now();
+            thread::scope(|scope| {
+                for reader in 0..threads as u32 {
+                    let db = &db;
+                    scope.spawn(move || {
+                        for i in (reader..20000).step_by(threads) {
+                            db.get(i).unwrap().unwrap();
+                        }
+                    });
+                }
+            });
+            println!("{threads} readers, pooled: {pooled}, took {:?}", begin.elapsed());
+        }
+        let _ = File::open(&path).map(|_| fs::remove_file(&path));
+    }
+
+    #[test]
+    fn readers_never_see_moving_records() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(8)
+            .id_strategy(IdStrategy::Reuse)
+            .cache_mode(CacheMode::None)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push_many((0..400).map(|i| [i as f32; 8])).unwrap();
+        let holes = (0..400).step_by(4).collect::<Vec<_>>();
+        db.remove_many(&holes).unwrap();
+        db.add_read_handles((0..4).map(|_| Box::new(file.reopen()) as _).collect());
+        let holes = holes.into_iter().collect::<HashSet<_>>();
+
+        // every push fills a hole, moving the records after it
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for reader in 0..4u32 {
+                let (db, holes, done) = (&db, &holes, &done);
+                scope.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        for id in (reader..400).step_by(4).filter(|id| !holes.contains(id)) {
+                            assert_eq!(*db.get(id).unwrap().unwrap(), [id as f32; 8]);
+                        }
+                        assert!(db.count().unwrap() >= 300);
+                    }
+                });
+            }
+            for _ in 0..holes.len() {
+                let pending = db.push_async_queued([-1f32; 8]).unwrap();
+                db.resolve(pending).unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(db.count().unwrap(), 400);
+    }
+
+    #[test]
+    fn writer_forgets_rewritten_records() {
+        let mut db = DatabaseOptions::new(4)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        for i in 0..5 {
+            assert_eq!(db.push([i as f32; 4]).unwrap(), i);
+        }
+        // the last id goes along with the last record
+        db.remove(4).unwrap();
+        assert_eq!(db.push([4f32; 4]).unwrap(), 4);
+        db.remove_many(&[3, 4]).unwrap();
+        assert_eq!(db.push([3f32; 4]).unwrap(), 3);
+        assert_eq!(db.count().unwrap(), 4);
+        assert_eq!(*db.get(3).unwrap().unwrap(), [3f32; 4]);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::db::{DbIndex, DbVector, DbVectorSlice, Error, VectorHandle};
+use crate::vio;
+use crate::vio::format;
+use crate::vio::RandomAccess;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+/// # Vector Store
+/// Where the vectors of a database are kept if not in the data section of
+/// its file, which still holds the header, the bloom filter and the index,
+/// see [DatabaseOptions::open_with_store](crate::db::DatabaseOptions::open_with_store).
+///
+/// Vectors are appended with consecutive ids from `0` on and never removed
+/// or replaced, so databases over a store can't [remove](crate::db::Database::remove)
+/// or [update](crate::db::Database::update) any.
+pub trait VectorStore: Send {
+    /// Vector `id`, or [Error::Missing] if it was never appended.
+    fn read_record(&mut self, id: DbIndex) -> Result<DbVector, Error>;
+
+    /// Stores `vector` as `id`, which is the current [VectorStore::len].
+    fn append(&mut self, id: DbIndex, vector: DbVectorSlice) -> Result<(), Error>;
+
+    /// Number of vectors stored.
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Makes everything appended so far durable.
+    fn sync(&mut self) -> Result<(), Error>;
+
+    /// Calls `f` with every vector in order of id. Stores that can fetch
+    /// many at once should, as full scans and index builds go through this.
+    fn scan(&mut self, f: &mut dyn FnMut(DbIndex, DbVectorSlice)) -> Result<(), Error> {
+        for id in 0..self.len() as DbIndex {
+            f(id, &self.read_record(id)?);
+        }
+        Ok(())
+    }
+}
+
+/// # File Store
+/// Vectors in a file of their own, laid out like the data section of
+/// a database: records of an id followed by the components, sorted by id.
+pub struct FileStore {
+    dim_size: u32,
+    len: u64,
+    fd: Box<dyn RandomAccess>,
+}
+
+impl FileStore {
+    /// Takes over `fd`, holding records of `dim_size` components if any.
+    pub fn new(dim_size: u32, mut fd: Box<dyn RandomAccess>) -> Result<FileStore, Error> {
+        let record = format::record_size(dim_size, false);
+        let size = fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+        if size % record != 0 {
+            return Err(Error::Corrupted(format!(
+                "{0} bytes left over past records of {record} bytes",
+                size % record
+            )));
+        }
+        Ok(FileStore {
+            dim_size,
+            len: size / record,
+            fd,
+        })
+    }
+}
+
+impl VectorStore for FileStore {
+    fn read_record(&mut self, id: DbIndex) -> Result<DbVector, Error> {
+        if id as u64 >= self.len {
+            return Err(Error::Missing(id));
+        }
+        let record = format::record_size(self.dim_size, false);
+        self.fd
+            .seek(SeekFrom::Start(id as u64 * record))
+            .map_err(Error::IO)?;
+        let stored = self.fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        if stored != id {
+            return Err(Error::Corrupted(format!("record of {id} holds {stored}")));
+        }
+        vio::vector::read(self.dim_size, &mut self.fd, true).map_err(|e| match e {
+            vio::Error::Eof => Error::Parse(),
+            vio::Error::IO(e) => Error::IO(e),
+        })
+    }
+
+    fn append(&mut self, id: DbIndex, vector: DbVectorSlice) -> Result<(), Error> {
+        self.fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+        self.fd.write_u32::<BigEndian>(id).map_err(Error::IO)?;
+        vio::vector::write(vector, &mut self.fd).map_err(Error::IO)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.fd.flush().map_err(Error::IO)?;
+        self.fd.sync_data().map_err(Error::IO)
+    }
+}
+
+/// # Object Fetcher
+/// Access to a bucket of an object storage, e.g. one compatible with S3,
+/// for an [ObjectStore] to keep vectors in. It's left to the user to wire
+/// it to their client of choice.
+pub trait ObjectFetcher: Send {
+    /// `len` bytes of object `key` from `offset` on.
+    fn get_range(&self, key: &str, offset: u64, len: u64) -> io::Result<Vec<u8>>;
+
+    /// Size of object `key`, or `None` if there's no such object.
+    fn size(&self, key: &str) -> io::Result<Option<u64>>;
+
+    /// Uploads object `key`, which doesn't exist yet.
+    fn put(&self, key: &str, bytes: Vec<u8>) -> io::Result<()>;
+}
+
+/// # Object Store
+/// Vectors in objects fetched through an [ObjectFetcher], so that they
+/// don't take up local storage while the index does.
+///
+/// Vectors appended are held in memory until [synced](VectorStore::sync),
+/// which uploads them as one segment, an object of their components named
+/// after the prefix and the number of the segment. Each vector read is
+/// one ranged fetch, and each segment one on a [scan](VectorStore::scan).
+pub struct ObjectStore<F: ObjectFetcher> {
+    fetcher: F,
+    prefix: String,
+    dim_size: u32,
+    /// First id of each segment uploaded, followed by the id after the last.
+    bounds: Vec<u64>,
+    /// Components appended since the last sync.
+    pending: Vec<u8>,
+}
+
+impl<F: ObjectFetcher> ObjectStore<F> {
+    /// Picks up the segments under `prefix` there already are,
+    /// looking up the size of each in turn.
+    pub fn open(fetcher: F, prefix: &str, dim_size: u32) -> Result<ObjectStore<F>, Error> {
+        let mut store = ObjectStore {
+            fetcher,
+            prefix: String::from(prefix),
+            dim_size,
+            bounds: vec![0],
+            pending: vec![],
+        };
+        while let Some(size) = store
+            .fetcher
+            .size(&store.segment_key(store.bounds.len() - 1))
+            .map_err(Error::IO)?
+        {
+            let last = *store.bounds.last().unwrap();
+            store.bounds.push(last + size / store.vector_size());
+        }
+        Ok(store)
+    }
+
+    pub fn fetcher(&self) -> &F {
+        &self.fetcher
+    }
+
+    fn segment_key(&self, segment: usize) -> String {
+        format!("{0}{segment:08}", self.prefix)
+    }
+
+    fn vector_size(&self) -> u64 {
+        self.dim_size as u64 * size_of::<f32>() as u64
+    }
+
+    fn uploaded(&self) -> u64 {
+        *self.bounds.last().unwrap()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<DbVector, Error> {
+        let mut vector = Vec::with_capacity(self.dim_size as usize);
+        vio::vector::decode(bytes, &mut vector, true).map_err(|_| Error::Parse())?;
+        Ok(vector)
+    }
+}
+
+impl<F: ObjectFetcher> VectorStore for ObjectStore<F> {
+    fn read_record(&mut self, id: DbIndex) -> Result<DbVector, Error> {
+        let (id64, size) = (id as u64, self.vector_size());
+        if id64 >= self.len() {
+            return Err(Error::Missing(id));
+        }
+        if id64 >= self.uploaded() {
+            let begin = ((id64 - self.uploaded()) * size) as usize;
+            return self.decode(&self.pending[begin..begin + size as usize]);
+        }
+        let segment = self.bounds.partition_point(|first| *first <= id64) - 1;
+        let offset = (id64 - self.bounds[segment]) * size;
+        let bytes = self
+            .fetcher
+            .get_range(&self.segment_key(segment), offset, size)
+            .map_err(Error::IO)?;
+        self.decode(&bytes)
+    }
+
+    fn append(&mut self, id: DbIndex, vector: DbVectorSlice) -> Result<(), Error> {
+        if id as u64 != self.len() {
+            return Err(Error::Unsupported("appending out of order"));
+        }
+        vio::vector::write(vector, &mut self.pending).map_err(Error::IO)?;
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        self.uploaded() + self.pending.len() as u64 / self.vector_size()
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let key = self.segment_key(self.bounds.len() - 1);
+        let len = self.len();
+        self.fetcher
+            .put(&key, std::mem::take(&mut self.pending))
+            .map_err(Error::IO)?;
+        self.bounds.push(len);
+        Ok(())
+    }
+
+    fn scan(&mut self, f: &mut dyn FnMut(DbIndex, DbVectorSlice)) -> Result<(), Error> {
+        let size = self.vector_size() as usize;
+        let mut vector = Vec::with_capacity(self.dim_size as usize);
+        let mut visit = |first: u64, bytes: &[u8]| {
+            for (i, components) in bytes.chunks_exact(size).enumerate() {
+                vio::vector::decode(components, &mut vector, true).map_err(|_| Error::Parse())?;
+                f((first + i as u64) as DbIndex, &vector);
+            }
+            Ok::<_, Error>(())
+        };
+        for segment in 0..self.bounds.len() - 1 {
+            let (first, end) = (self.bounds[segment], self.bounds[segment + 1]);
+            let key = self.segment_key(segment);
+            let bytes = self
+                .fetcher
+                .get_range(&key, 0, (end - first) * size as u64)
+                .map_err(Error::IO)?;
+            visit(first, &bytes)?;
+        }
+        visit(self.uploaded(), &self.pending)
+    }
+}
+
+impl VectorHandle {
+    /// Same as [VectorHandle::get] for vectors in a [VectorStore].
+    pub(crate) fn store_get(&mut self, id: DbIndex) -> Result<Option<DbVector>, Error> {
+        let store = self.store.as_mut().unwrap();
+        match store.read_record(id) {
+            Err(Error::Missing(_)) => Ok(None),
+            read => read.map(Some),
+        }
+    }
+
+    /// Same as [VectorHandle::push] for vectors in a [VectorStore].
+    pub(crate) fn store_push(&mut self, vector: DbVectorSlice) -> Result<DbIndex, Error> {
+        let len = self.store.as_ref().unwrap().len();
+        let last = len.checked_sub(1).map(|last| last as DbIndex);
+        let new_id = self.allocator.allocate(last)?;
+        self.insert_bloom(new_id)?;
+        self.store.as_mut().unwrap().append(new_id, vector)?;
+        self.amplified(size_of_val(vector) as u64, 0);
+        self.written(size_of_val(vector) as u64)?;
+        Ok(new_id)
+    }
+
+    /// Same as [VectorHandle::read_all] for vectors in a [VectorStore].
+    pub(crate) fn store_read_all(&mut self) -> Result<Vec<(DbIndex, DbVector)>, Error> {
+        let mut records = vec![];
+        self.store
+            .as_mut()
+            .unwrap()
+            .scan(&mut |id, vector| records.push((id, vector.to_vec())))?;
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        DatabaseOptions, Error, FileStore, HnswConfig, ObjectFetcher, ObjectStore, PlannerConfig,
+        SearchPlan, SearchRequest, VectorStore,
+    };
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Objects in memory, answering every request after `latency`.
+    #[derive(Clone, Default)]
+    struct FakeBucket {
+        objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        fetches: Arc<AtomicUsize>,
+        latency: Duration,
+    }
+
+    impl ObjectFetcher for FakeBucket {
+        fn get_range(&self, key: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+            thread::sleep(self.latency);
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            let objects = self.objects.lock().unwrap();
+            let object = objects.get(key).ok_or(io::ErrorKind::NotFound)?;
+            Ok(object[offset as usize..(offset + len) as usize].to_vec())
+        }
+
+        fn size(&self, key: &str) -> io::Result<Option<u64>> {
+            thread::sleep(self.latency);
+            Ok(self.objects.lock().unwrap().get(key).map(|o| o.len() as u64))
+        }
+
+        fn put(&self, key: &str, bytes: Vec<u8>) -> io::Result<()> {
+            thread::sleep(self.latency);
+            self.objects.lock().unwrap().insert(String::from(key), bytes);
+            Ok(())
+        }
+    }
+
+    impl FakeBucket {
+        fn fetches(&self) -> usize {
+            self.fetches.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn object_store_works() {
+        let bucket = FakeBucket {
+            latency: Duration::from_micros(200),
+            ..FakeBucket::default()
+        };
+        let mut rng = XorShift::new(37);
+        let vectors = (0..300).map(|_| rng.vector(8)).collect::<Vec<_>>();
+        let file = SharedCursor::new();
+        let store = ObjectStore::open(bucket.clone(), "vectors/", 8).unwrap();
+        let mut db = DatabaseOptions::new(8)
+            .create_with_store("remote", Box::new(file.reopen()), Box::new(store))
+            .unwrap();
+        for chunk in vectors.chunks(100) {
+            for vector in chunk {
+                db.push(vector).unwrap();
+            }
+            db.sync().unwrap();
+        }
+        assert_eq!(bucket.objects.lock().unwrap().len(), 3);
+        assert_eq!(bucket.fetches(), 0);
+        // nothing but the header on local storage
+        assert!(file.len() < 1024);
+        drop(db);
+
+        let store = ObjectStore::open(bucket.clone(), "vectors/", 8).unwrap();
+        assert_eq!(store.len(), 300);
+        let mut db = DatabaseOptions::default()
+            .hnsw(HnswConfig {
+                m: 8,
+                ef_construction: 64,
+                ..HnswConfig::default()
+            })
+            .planner(PlannerConfig {
+                scan_below: 0,
+                graph_cost_factor: 0.0,
+            })
+            .open_with_store("remote", Box::new(file.reopen()), Box::new(store))
+            .unwrap();
+        assert_eq!(db.count().unwrap(), 300);
+
+        // a segment at a time
+        db.build_index().unwrap();
+        assert_eq!(bucket.fetches(), 3);
+        let query = &vectors[42];
+        let response = db.query(&SearchRequest::new(query, 5)).unwrap();
+        assert_eq!(response.plan, SearchPlan::Graph);
+        assert_eq!(response.results[0], (42, 0f32));
+        assert_eq!(bucket.fetches(), 3);
+        let exact = db.query(&SearchRequest::new(query, 5).plan(SearchPlan::Flat)).unwrap();
+        assert_eq!(exact.results, db.search_exact(query, 5).unwrap());
+        assert_eq!(exact.results[0], (42, 0f32));
+
+        // one ranged fetch per vector, then the cache
+        let before = bucket.fetches();
+        for id in [7, 150, 299] {
+            assert_eq!(*db.get(id).unwrap().unwrap(), vectors[id as usize]);
+        }
+        assert_eq!(bucket.fetches(), before + 3);
+        db.get(7).unwrap();
+        assert_eq!(bucket.fetches(), before + 3);
+        assert_eq!(db.get(300).unwrap(), None);
+
+        // pending vectors are read from memory
+        let pushed = db.push([1f32; 8]).unwrap();
+        assert_eq!(pushed, 300);
+        assert_eq!(db.count().unwrap(), 301);
+        assert!(matches!(db.remove(0), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn file_store_works() {
+        let records = SharedCursor::new();
+        let store = FileStore::new(4, Box::new(records.reopen())).unwrap();
+        let mut db = DatabaseOptions::new(4)
+            .create_with_store("local", Box::new(SharedCursor::new()), Box::new(store))
+            .unwrap();
+        for i in 0..10 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        assert_eq!(records.len(), 10 * (4 + 16));
+        let mut store = FileStore::new(4, Box::new(records.reopen())).unwrap();
+        assert_eq!(store.len(), 10);
+        assert_eq!(store.read_record(3).unwrap(), vec![3f32; 4]);
+        assert!(matches!(store.read_record(10), Err(Error::Missing(10))));
+        assert_eq!(db.search_exact(&[2.2f32; 4], 1).unwrap()[0].0, 2);
+
+        let history = DatabaseOptions::new(4).history(true).create_with_store(
+            "local",
+            Box::new(SharedCursor::new()),
+            Box::new(FileStore::new(4, Box::new(SharedCursor::new())).unwrap()),
+        );
+        assert!(matches!(history, Err(Error::Options(_))));
+    }
+}
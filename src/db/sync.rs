@@ -0,0 +1,139 @@
+use crate::db::{Database, Error, VectorHandle};
+use crate::ext::semaphore::LockAutoClear;
+
+/// # Sync Policy
+/// When written records are forced onto durable storage.
+/// Anything not yet synced may be lost if the machine goes down,
+/// even though the write was acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// Leave it to the operating system.
+    Never,
+    /// Sync when the database is flushed or synced explicitly.
+    #[default]
+    OnFlush,
+    /// Sync after each push or removal, before it returns.
+    EveryWrite,
+    /// Sync once at least this many bytes were written since the last one.
+    EveryNBytes(u64),
+}
+
+impl VectorHandle {
+    /// Accounts for `bytes` just written and syncs if the policy asks to.
+    pub(crate) fn written(&mut self, bytes: u64) -> Result<(), Error> {
+        self.unsynced_bytes += bytes;
+        match self.sync_policy {
+            SyncPolicy::EveryWrite => self.sync(),
+            SyncPolicy::EveryNBytes(n) if self.unsynced_bytes >= n => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn sync(&mut self) -> Result<(), Error> {
+        if let Some(store) = self.store.as_mut() {
+            store.sync()?;
+        }
+        self.fd.flush().map_err(Error::IO)?;
+        self.fd.sync_data().map_err(Error::IO)?;
+        self.unsynced_bytes = 0;
+        Ok(())
+    }
+}
+
+impl Database {
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.handle.lock_auto_clear_poison().sync_policy
+    }
+
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) {
+        self.handle.lock_auto_clear_poison().sync_policy = policy;
+    }
+
+    /// Forces everything written so far onto durable storage,
+    /// regardless of the policy.
+    pub fn sync(&self) -> Result<(), Error> {
+        self.handle.lock_auto_clear_poison().sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, SearchParams, SyncPolicy};
+    use crate::ext::mem::FaultyAccess;
+    use crate::vio::format::HEADER_SLOT_SIZE;
+    use crate::vio::inspect;
+
+    fn crash_after_pushes(policy: SyncPolicy) -> (Vec<u32>, Database) {
+        let file = FaultyAccess::new();
+        let mut db = DatabaseOptions::new(4).create("mem", Box::new(file.reopen())).unwrap();
+        db.sync().unwrap();
+        db.set_sync_policy(policy);
+        let acknowledged = (0..8).map(|i| db.push([i as f32; 4]).unwrap()).collect();
+        drop(db);
+
+        let survived = DatabaseOptions::default().open("mem", Box::new(file.crash())).unwrap();
+        (acknowledged, survived)
+    }
+
+    #[test]
+    fn every_write_keeps_acknowledged() {
+        let (acknowledged, db) = crash_after_pushes(SyncPolicy::EveryWrite);
+        assert_eq!(db.count().unwrap(), acknowledged.len() as u64);
+        for id in acknowledged {
+            assert_eq!(*db.get(id).unwrap().unwrap(), vec![id as f32; 4]);
+        }
+    }
+
+    #[test]
+    fn every_n_bytes_keeps_all_but_tail() {
+        // each record takes 20 bytes, so every third push syncs
+        let (_, db) = crash_after_pushes(SyncPolicy::EveryNBytes(60));
+        assert_eq!(db.count().unwrap(), 6);
+    }
+
+    #[test]
+    fn never_may_lose() {
+        let (_, db) = crash_after_pushes(SyncPolicy::Never);
+        assert_eq!(db.count().unwrap(), 0);
+    }
+
+    #[test]
+    fn torn_header_keeps_previous() {
+        let before = SearchParams::default();
+        let after = SearchParams {
+            ef_search: before.ef_search * 2,
+            ..before
+        };
+        // the first slot in use, then the second one
+        for flips in [0, 1] {
+            for cut in (0..=HEADER_SLOT_SIZE).step_by(4).chain([HEADER_SLOT_SIZE - 1]) {
+                let file = FaultyAccess::new();
+                let mut db = DatabaseOptions::new(4).create("mem", Box::new(file.reopen())).unwrap();
+                db.push([1f32; 4]).unwrap();
+                for flip in 0..flips {
+                    db.set_search_params(SearchParams {
+                        ef_search: flip + 1,
+                        ..before
+                    });
+                    db.flush().unwrap();
+                }
+                db.set_search_params(before);
+                db.flush().unwrap();
+
+                db.set_search_params(after);
+                file.crash_after(cut);
+                assert!(db.flush().is_err());
+                drop(db);
+
+                let mut crashed = file.crash();
+                let layout = inspect(&mut crashed).unwrap();
+                assert!(layout.inconsistencies.is_empty(), "cut after {cut}");
+                let db = DatabaseOptions::default().open("mem", Box::new(crashed)).unwrap();
+                // in use once its sequence number is written in whole
+                let expected = if cut == HEADER_SLOT_SIZE { after } else { before };
+                assert_eq!(db.search_params(), expected, "cut after {cut}");
+                assert_eq!(*db.get(0).unwrap().unwrap(), vec![1f32; 4]);
+            }
+        }
+    }
+}
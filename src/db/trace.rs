@@ -0,0 +1,203 @@
+use crate::db::{Clock, Database, Error, Operation};
+use crate::ext::semaphore::LockAutoClear;
+use crate::metric::Metric;
+use crate::vio;
+use crate::vio::varint;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const MAGIC: &[u8; 4] = b"VTRC";
+const VERSION: u8 = 1;
+
+const CACHED: u8 = 1;
+const GRAPH: u8 = 2;
+
+/// # Traced Search
+/// What a traced search asked for and how it was answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TracedSearch {
+    pub k: usize,
+    /// Size of the candidate list it went by, the default if not requested.
+    pub ef: usize,
+    /// Answered from the [result cache](crate::db::DatabaseOptions::result_cache).
+    pub cached: bool,
+    /// Searched through the index rather than scanning.
+    pub graph: bool,
+}
+
+/// # Trace Event
+/// One operation recorded by [Database::start_trace].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub op: Operation,
+    /// How long it took, to the microsecond.
+    pub elapsed: Duration,
+    /// Present for [Operation::Search].
+    pub search: Option<TracedSearch>,
+}
+
+/// # Trace
+/// A workload as [Database::start_trace] recorded it: the shape of the
+/// database it ran on and the operations in the order they finished.
+/// Neither vectors nor ids are in it, only what it takes to
+/// [replay](Trace::replay) something alike.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub dim_size: u32,
+    pub metric: Metric,
+    /// Vectors stored when tracing started.
+    pub count: u64,
+    /// Whether the index was built when tracing started.
+    pub indexed: bool,
+    pub events: Vec<TraceEvent>,
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+fn from_vio(e: vio::Error) -> Error {
+    match e {
+        vio::Error::Eof => Error::Parse(),
+        vio::Error::IO(e) => Error::IO(e),
+    }
+}
+
+impl Trace {
+    /// Reads a trace to its end.
+    pub fn read(fd: &mut dyn Read) -> Result<Trace, Error> {
+        let mut magic = [0u8; 4];
+        fd.read_exact(&mut magic).map_err(Error::IO)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a trace"));
+        }
+        if fd.read_u8().map_err(Error::IO)? != VERSION {
+            return Err(invalid("trace of an unknown version"));
+        }
+        let dim_size = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        let metric = Metric::from_byte(fd.read_u8().map_err(Error::IO)?)
+            .ok_or_else(|| invalid("trace of an unknown metric"))?;
+        let count = fd.read_u64::<BigEndian>().map_err(Error::IO)?;
+        let indexed = fd.read_u8().map_err(Error::IO)? != 0;
+        let mut events = Vec::new();
+        loop {
+            let op = match fd.read_u8() {
+                Ok(op) => op,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(Error::IO(e)),
+            };
+            let op = *Operation::ALL
+                .get(op as usize)
+                .ok_or_else(|| invalid("trace of an unknown operation"))?;
+            let elapsed = Duration::from_micros(varint::read(fd).map_err(from_vio)?);
+            let search = match op {
+                Operation::Search => {
+                    let k = varint::read(fd).map_err(from_vio)? as usize;
+                    let ef = varint::read(fd).map_err(from_vio)? as usize;
+                    let flags = fd.read_u8().map_err(Error::IO)?;
+                    Some(TracedSearch {
+                        k,
+                        ef,
+                        cached: flags & CACHED != 0,
+                        graph: flags & GRAPH != 0,
+                    })
+                }
+                _ => None,
+            };
+            events.push(TraceEvent {
+                op,
+                elapsed,
+                search,
+            });
+        }
+        Ok(Trace {
+            dim_size,
+            metric,
+            count,
+            indexed,
+            events,
+        })
+    }
+}
+
+struct TraceSink {
+    fd: Box<dyn Write + Send>,
+    /// The first write that failed, after which nothing more is written.
+    error: Option<io::Error>,
+}
+
+/// Writes the operations of a database as they finish, see [Database::start_trace].
+pub(crate) struct Tracer {
+    pub(crate) clock: Arc<dyn Clock>,
+    sink: Mutex<TraceSink>,
+}
+
+impl Tracer {
+    pub(crate) fn record(&self, op: Operation, elapsed: Duration, search: Option<TracedSearch>) {
+        let mut event = Vec::with_capacity(16);
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        event.push(op as u8);
+        // writing into memory doesn't fail
+        varint::write(micros, &mut event).unwrap();
+        if let Some(search) = search {
+            varint::write(search.k as u64, &mut event).unwrap();
+            varint::write(search.ef as u64, &mut event).unwrap();
+            event.push((search.cached as u8 * CACHED) | (search.graph as u8 * GRAPH));
+        }
+        let mut sink = self.sink.lock_auto_clear_poison();
+        if sink.error.is_none() {
+            if let Err(e) = sink.fd.write_all(&event) {
+                sink.error = Some(e);
+            }
+        }
+    }
+}
+
+impl Database {
+    /// Records every [Operation] from now on into `fd`, compact enough to
+    /// leave on in production: which one it was and how long it took, and
+    /// for searches, the `k` and `ef` asked for, whether they went through
+    /// the index and whether the result cache answered them. Neither vectors
+    /// nor ids are recorded. Read it back by [Trace::read]. A trace already
+    /// underway is [stopped](Database::stop_trace) first.
+    ///
+    /// Operations are timed by the [deadline clock](crate::db::DatabaseOptions::deadline_clock).
+    /// Writes failing later are reported by [Database::stop_trace].
+    pub fn start_trace(&mut self, mut fd: Box<dyn Write + Send>) -> Result<(), Error> {
+        self.stop_trace()?;
+        let mut header = Vec::with_capacity(19);
+        header.extend_from_slice(MAGIC);
+        header.push(VERSION);
+        header.write_u32::<BigEndian>(self.dim_size()).unwrap();
+        header.push(self.metric.to_byte());
+        header.write_u64::<BigEndian>(self.count()?).unwrap();
+        header.push(self.is_indexed() as u8);
+        fd.write_all(&header).map_err(Error::IO)?;
+        self.tracer = Some(Arc::new(Tracer {
+            clock: self.clock.clone(),
+            sink: Mutex::new(TraceSink { fd, error: None }),
+        }));
+        Ok(())
+    }
+
+    /// Stops recording the trace [started](Database::start_trace), if any,
+    /// flushing what's left of it and failing if any of it couldn't be written.
+    pub fn stop_trace(&mut self) -> Result<(), Error> {
+        let Some(tracer) = self.tracer.take() else {
+            return Ok(());
+        };
+        let mut sink = tracer.sink.lock_auto_clear_poison();
+        if let Some(e) = sink.error.take() {
+            return Err(Error::IO(e));
+        }
+        sink.fd.flush().map_err(Error::IO)
+    }
+
+    /// Whether a trace is being recorded, see [Database::start_trace].
+    pub fn is_tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+}
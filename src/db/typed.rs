@@ -0,0 +1,204 @@
+use crate::db::scan::TopK;
+use crate::db::{Database, DbIndex, Error};
+use crate::ext::semaphore::LockAutoClear;
+use crate::vio;
+use std::ops::ControlFlow;
+
+/// # Typed Database
+/// A [Database] of `D` dimensions known at compile time, for small ones
+/// such as coordinates or colors, whose vectors come and go as arrays.
+/// Distances are measured by [Metric::distance_array](crate::metric::Metric::distance_array),
+/// unrolled for `D`, and otherwise everything is the same as through the
+/// database itself, which is still there for the rest of its API.
+pub struct TypedDatabase<const D: usize> {
+    db: Database,
+}
+
+impl<const D: usize> TypedDatabase<D> {
+    /// Fails with [Error::Dimension] unless `db` is of `D` dimensions,
+    /// which is checked this once.
+    pub fn new(db: Database) -> Result<TypedDatabase<D>, Error> {
+        if db.dim_size() as usize != D {
+            return Err(Error::Dimension(D as u32, db.dim_size() as usize));
+        }
+        Ok(TypedDatabase { db })
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    pub fn database_mut(&mut self) -> &mut Database {
+        &mut self.db
+    }
+
+    pub fn into_database(self) -> Database {
+        self.db
+    }
+
+    /// Same as [Database::push].
+    pub fn push_array(&mut self, vector: &[f32; D]) -> Result<DbIndex, Error> {
+        self.db.push(vector)
+    }
+
+    /// Same as [Database::get], copied out of the cache.
+    pub fn get_array(&self, id: DbIndex) -> Result<Option<[f32; D]>, Error> {
+        Ok(self.db.get(id)?.map(|vector| {
+            let mut array = [0f32; D];
+            array.copy_from_slice(&vector);
+            array
+        }))
+    }
+
+    /// Same as [Database::search_exact].
+    pub fn search_array(&mut self, query: &[f32; D], k: usize) -> Result<Vec<(DbIndex, f32)>, Error> {
+        let metric = self.db.metric;
+        let mut top = TopK::new(k);
+        let mut handle = self.db.handle.lock_auto_clear_poison();
+        if handle.store.is_some() {
+            handle.scan(|id, vector| {
+                top.offer(id, metric.distance_array(query, vector.try_into().unwrap()));
+            })?;
+            return Ok(top.into_sorted_vec());
+        }
+        // decoded straight into arrays, sparing the vector each record goes through
+        let verbatim = handle.verbatim;
+        handle.scan_encoded(|id, components| {
+            let stored = vio::vector::decode_array(components, verbatim).map_err(|_| Error::Parse())?;
+            top.offer(id, metric.distance_array(query, &stored));
+            Ok(ControlFlow::Continue(()))
+        })?;
+        Ok(top.into_sorted_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error, TypedDatabase};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::metric::Metric;
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+
+    fn random_arrays<const D: usize>(rng: &mut XorShift, count: usize) -> Vec<[f32; D]> {
+        (0..count)
+            .map(|_| std::array::from_fn(|_| rng.next_f32()))
+            .collect()
+    }
+
+    #[test]
+    fn typed_database_works() {
+        let mut rng = XorShift::new(41);
+        for metric in [Metric::Euclidean, Metric::Cosine, Metric::DotProduct] {
+            let file = SharedCursor::new();
+            let db = DatabaseOptions::new(3)
+                .metric(metric)
+                .create("typed", Box::new(file.reopen()))
+                .unwrap();
+            let mut typed = TypedDatabase::<3>::new(db).unwrap();
+            let arrays = random_arrays::<3>(&mut rng, 500);
+            for array in &arrays {
+                typed.push_array(array).unwrap();
+            }
+            drop(typed);
+
+            let dynamic = DatabaseOptions::default()
+                .open("typed", Box::new(file.reopen()))
+                .unwrap();
+            let mut typed = TypedDatabase::<3>::new(
+                DatabaseOptions::default()
+                    .open("typed", Box::new(file.reopen()))
+                    .unwrap(),
+            )
+            .unwrap();
+            for id in [0, 7, 499] {
+                let array = typed.get_array(id).unwrap().unwrap();
+                assert_eq!(array, arrays[id as usize]);
+                assert_eq!(array.as_slice(), dynamic.get(id).unwrap().unwrap().as_slice());
+            }
+            assert_eq!(typed.get_array(500).unwrap(), None);
+            for query in random_arrays::<3>(&mut rng, 10) {
+                let bits = |found: Vec<(u32, f32)>| {
+                    found.into_iter().map(|(id, d)| (id, d.to_bits())).collect::<Vec<_>>()
+                };
+                assert_eq!(
+                    bits(typed.search_array(&query, 10).unwrap()),
+                    bits(dynamic.search_exact(&query, 10).unwrap())
+                );
+            }
+        }
+
+        let db = DatabaseOptions::new(4)
+            .create("mem", Box::new(SharedCursor::new()))
+            .unwrap();
+        assert!(matches!(TypedDatabase::<3>::new(db), Err(Error::Dimension(3, 4))));
+    }
+
+    /// Best of a few rounds of `dynamic` and `typed` taking turns,
+    /// for both to see the machine in the same state.
+    fn race(mut dynamic: impl FnMut(), mut typed: impl FnMut()) -> (Duration, Duration) {
+        let time = |run: &mut dyn FnMut()| {
+            let begin = Instant::now();
+            run();
+            begin.elapsed()
+        };
+        (0..10).fold((Duration::MAX, Duration::MAX), |(d, t), _| {
+            (d.min(time(&mut dynamic)), t.min(time(&mut typed)))
+        })
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    /// to compare the typed distances and search with the dynamic ones.
+    #[test]
+    #[ignore]
+    fn typed_search_is_faster() {
+        let mut rng = XorShift::new(43);
+        let arrays = random_arrays::<8>(&mut rng, 200_000);
+        let queries = random_arrays::<8>(&mut rng, 5);
+        let mut typed = TypedDatabase::<8>::new(
+            DatabaseOptions::new(8)
+                .create("mem", Box::new(SharedCursor::new()))
+                .unwrap(),
+        )
+        .unwrap();
+        for array in &arrays {
+            typed.push_array(array).unwrap();
+        }
+        let metric = black_box(Metric::Euclidean);
+        let (dynamic, fast) = race(
+            || {
+                for query in &queries {
+                    black_box(arrays.iter().map(|a| metric.distance(query, black_box(a))).sum::<f32>());
+                }
+            },
+            || {
+                for query in &queries {
+                    black_box(arrays.iter().map(|a| metric.distance_array(query, black_box(a))).sum::<f32>());
+                }
+            },
+        );
+        println!(
+            "distances: dynamic {dynamic:?}, typed {fast:?}, {:.2}x",
+            dynamic.as_secs_f64() / fast.as_secs_f64()
+        );
+
+        let typed = std::cell::RefCell::new(typed);
+        let (dynamic, fast) = race(
+            || {
+                for query in &queries {
+                    typed.borrow_mut().database_mut().search_exact(query, 10).unwrap();
+                }
+            },
+            || {
+                for query in &queries {
+                    typed.borrow_mut().search_array(query, 10).unwrap();
+                }
+            },
+        );
+        println!(
+            "search: dynamic {dynamic:?}, typed {fast:?}, {:.2}x",
+            dynamic.as_secs_f64() / fast.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::db::mmr::diversify;
+use crate::db::normal::Normalizations;
+use crate::db::query::answer;
+use crate::db::{
+    Clock, Database, DbIndex, DbVector, Error, PlannerConfig, SearchParams, SearchRequest, SearchResponse,
+};
+use crate::ext::semaphore::{lock_pair, LockAutoClear, LockRank};
+use crate::index::{HnswIndex, SearchScratch};
+use crate::metric::Metric;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Everything a [DatabaseView] reads, as of one revision of the records.
+pub(crate) struct Snapshot {
+    /// Of the records it was read at.
+    revision: u64,
+    dim_size: u32,
+    vectors: BTreeMap<DbIndex, Arc<DbVector>>,
+    index: Option<HnswIndex>,
+}
+
+/// # Database View
+/// Read-only handle to a [Database] as it was when the view was created,
+/// see [Database::read_view]. It's [Send] and cheap to clone, so each worker
+/// thread may take one of its own and read without locking the database.
+///
+/// A view never changes. Vectors pushed, updated or removed on the database
+/// afterwards, and indexes built or dropped, are only seen by views created
+/// after them, and so are the [search parameters](Database::set_search_params)
+/// and the [planner](Database::set_planner).
+#[derive(Clone)]
+pub struct DatabaseView {
+    snapshot: Arc<Snapshot>,
+    metric: Metric,
+    params: SearchParams,
+    planner: PlannerConfig,
+    /// Shared with the database the view is of.
+    normalizations: Arc<Normalizations>,
+    clock: Arc<dyn Clock>,
+}
+
+impl DatabaseView {
+    pub fn dim_size(&self) -> u32 {
+        self.snapshot.dim_size
+    }
+
+    /// Number of vectors stored when the view was created.
+    pub fn count(&self) -> u64 {
+        self.snapshot.vectors.len() as u64
+    }
+
+    pub fn get(&self, id: DbIndex) -> Option<Arc<DbVector>> {
+        self.snapshot.vectors.get(&id).cloned()
+    }
+
+    /// Fetches several vectors at once, in the order of `ids`.
+    pub fn get_many(&self, ids: &[DbIndex]) -> Vec<Option<Arc<DbVector>>> {
+        ids.iter().map(|id| self.get(*id)).collect()
+    }
+
+    /// Same as [Database::query], against the view, which never takes
+    /// [entry shortcuts](crate::db::DatabaseOptions::entry_shortcuts).
+    pub fn query(&self, request: &SearchRequest) -> Result<SearchResponse, Error> {
+        if let Some((pool, mmr)) = request.mmr_pool() {
+            let metric = request.metric_or(self.metric);
+            let pool = self.query(&pool)?;
+            let vector = |id| Ok(self.snapshot.vectors.get(&id).cloned());
+            return diversify(pool, (request.k(), mmr.lambda), metric, vector);
+        }
+        let request = &*request.conform(self.metric, &self.normalizations)?;
+        let snapshot = &self.snapshot;
+        let index = snapshot.index.as_ref();
+        let params = (self.params, self.planner, &*self.clock);
+        let mut present = |id| snapshot.vectors.contains_key(&id);
+        let mut scratch = SearchScratch::new();
+        let index = index.map(|index| (index, &mut scratch, None));
+        answer(request, params, self.metric, index, None, &mut present, |metric, mut deadline| {
+            if request.query().len() != snapshot.dim_size as usize {
+                return Err(Error::Dimension(snapshot.dim_size, request.query().len()));
+            }
+            Ok(snapshot
+                .vectors
+                .iter()
+                .take_while(|_| !deadline.as_mut().is_some_and(|deadline| deadline.step()))
+                .map(|(id, v)| (*id, metric.distance(request.query(), v)))
+                .collect())
+        })
+    }
+}
+
+impl Database {
+    /// Creates a [DatabaseView] of everything written so far, the write queue
+    /// drained first. The vectors and the index, if there's one, are copied
+    /// into memory once per revision of the database, which every view
+    /// created until the next write shares.
+    pub fn read_view(&self) -> Result<DatabaseView, Error> {
+        self.drain_queue()?;
+        let (index, mut handle) =
+            lock_pair((LockRank::Index, &self.index), (LockRank::Handle, &self.handle));
+        let mut cached = self.snapshot.lock_auto_clear_poison();
+        let snapshot = match cached.as_ref() {
+            Some(snapshot) if snapshot.revision == handle.revision => snapshot.clone(),
+            _ => {
+                let snapshot = Arc::new(Snapshot {
+                    revision: handle.revision,
+                    dim_size: handle.dim_size,
+                    vectors: handle
+                        .read_all()?
+                        .into_iter()
+                        .map(|(id, v)| (id, Arc::new(v)))
+                        .collect(),
+                    index: index.clone(),
+                });
+                *cached = Some(snapshot.clone());
+                snapshot
+            }
+        };
+        Ok(DatabaseView {
+            snapshot,
+            metric: self.metric,
+            params: self.search_params(),
+            planner: self.planner(),
+            normalizations: self.normalizations.clone(),
+            clock: self.clock.clone(),
+        })
+    }
+
+    /// Makes the next view read the database again, for changes to it
+    /// that don't write any records, such as building the index.
+    pub(crate) fn invalidate_views(&self) {
+        *self.snapshot.lock_auto_clear_poison() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, SearchRequest};
+    use crate::ext::mem::SharedCursor;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn read_views_work() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(2)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        for i in 0..10 {
+            db.push([i as f32, 0f32]).unwrap();
+        }
+        let old = db.read_view().unwrap();
+        // views created in between writes share what they read
+        assert!(Arc::ptr_eq(&old.snapshot, &db.read_view().unwrap().snapshot));
+
+        db.push([-1f32, 0f32]).unwrap();
+        db.remove(3).unwrap();
+        db.update(4, [4f32, 1f32]).unwrap();
+        let new = db.read_view().unwrap();
+
+        let workers = [old.clone(), new.clone()].map(|view| {
+            thread::spawn(move || {
+                let found = view.query(&SearchRequest::new(&[-1f32, 0f32], 1)).unwrap();
+                (view.count(), found.results[0].0)
+            })
+        });
+        let [old_seen, new_seen] = workers.map(|worker| worker.join().unwrap());
+        assert_eq!(old_seen, (10, 0));
+        assert_eq!(new_seen, (10, 10));
+        assert_eq!(*old.get(3).unwrap(), vec![3f32, 0f32]);
+        assert!(new.get(3).is_none());
+        assert_eq!(
+            old.get_many(&[4, 10]),
+            vec![Some(Arc::new(vec![4f32, 0f32])), None]
+        );
+        assert_eq!(*new.get(4).unwrap(), vec![4f32, 1f32]);
+
+        // the index is part of the view too
+        db.build_index().unwrap();
+        let indexed = db.read_view().unwrap();
+        assert!(indexed.snapshot.index.is_some());
+        assert!(new.snapshot.index.is_none());
+        let request = SearchRequest::new(&[9f32, 0f32], 2);
+        assert_eq!(
+            indexed.query(&request).unwrap().results,
+            db.query(&request).unwrap().results
+        );
+    }
+}
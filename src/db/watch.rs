@@ -0,0 +1,427 @@
+use crate::db::{Database, Error};
+use crate::ext::mem::ReadOnly;
+use crate::ext::semaphore::LockAutoClear;
+use crate::vio::format::HEADER_SLOT_SIZE;
+use crate::vio::{RandomAccess, SyncData, Truncate};
+use std::cmp::{max, min};
+use std::io::{self, IoSlice, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Bytes at the start of the file compared, which take in both header
+/// slots and with them the sequence number every header written bumps.
+const HEAD: u64 = 2 * HEADER_SLOT_SIZE;
+
+/// # External Change Policy
+/// What to do on finding the file of a database changed by someone else,
+/// such as another process or a backup restored over it, see
+/// [DatabaseOptions::watch_external_changes](crate::db::DatabaseOptions::watch_external_changes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExternalChangePolicy {
+    /// Fails with [Error::ExternallyModified], leaving the database as it is.
+    #[default]
+    Fail,
+    /// Drops the cached vectors and the index, and reads the records,
+    /// tables and layers anew as if the file was reopened.
+    Reload,
+}
+
+/// What the file looks like as far as staleness goes:
+/// how long it is and the bytes it begins with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileImage {
+    len: u64,
+    head: Vec<u8>,
+}
+
+impl FileImage {
+    /// Reads what `fd` looks like, leaving it at the end of the head.
+    fn read(fd: &mut dyn RandomAccess) -> io::Result<FileImage> {
+        let len = fd.seek(SeekFrom::End(0))?;
+        let mut head = vec![0u8; min(len, HEAD) as usize];
+        fd.seek(SeekFrom::Start(0))?;
+        fd.read_exact(&mut head)?;
+        Ok(FileImage { len, head })
+    }
+
+    /// Takes in `buf` written at `pos`, past the end or not.
+    fn wrote(&mut self, pos: u64, buf: &[u8]) {
+        let end = pos + buf.len() as u64;
+        self.len = max(self.len, end);
+        self.head.resize(min(self.len, HEAD) as usize, 0);
+        if pos < HEAD {
+            let overlap = (min(end, HEAD) - pos) as usize;
+            self.head[pos as usize..pos as usize + overlap].copy_from_slice(&buf[..overlap]);
+        }
+    }
+
+    fn resized(&mut self, len: u64) {
+        self.len = len;
+        self.head.resize(min(len, HEAD) as usize, 0);
+    }
+}
+
+struct WatchedFile {
+    fd: Box<dyn RandomAccess>,
+    pos: u64,
+    /// What the file looks like to the database, kept up to date with every write.
+    image: FileImage,
+}
+
+/// # Watched
+/// The file of a database watched for external changes, taking every write
+/// the database makes into what it expects the file to look like.
+/// Clones go through the same handle.
+#[derive(Clone)]
+pub(crate) struct Watched(Arc<Mutex<WatchedFile>>);
+
+impl Watched {
+    fn new(mut fd: Box<dyn RandomAccess>) -> io::Result<Watched> {
+        let pos = fd.stream_position()?;
+        let image = FileImage::read(fd.as_mut())?;
+        fd.seek(SeekFrom::Start(pos))?;
+        Ok(Watched(Arc::new(Mutex::new(WatchedFile { fd, pos, image }))))
+    }
+
+    /// What the file looks like if it differs from what's expected.
+    fn changed(&self) -> io::Result<Option<FileImage>> {
+        let mut file = self.0.lock_auto_clear_poison();
+        let observed = FileImage::read(file.fd.as_mut())?;
+        let pos = file.pos;
+        file.fd.seek(SeekFrom::Start(pos))?;
+        Ok((observed != file.image).then_some(observed))
+    }
+
+    /// Expects the file to look like `image` from now on.
+    fn accept(&self, image: FileImage) {
+        self.0.lock_auto_clear_poison().image = image;
+    }
+}
+
+impl Read for Watched {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.0.lock_auto_clear_poison();
+        let read = file.fd.read(buf)?;
+        file.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for Watched {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let file = &mut *self.0.lock_auto_clear_poison();
+        let written = file.fd.write(buf)?;
+        file.image.wrote(file.pos, &buf[..written]);
+        file.pos += written as u64;
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let file = &mut *self.0.lock_auto_clear_poison();
+        let written = file.fd.write_vectored(bufs)?;
+        let mut left = written;
+        for buf in bufs {
+            let taken = min(left, buf.len());
+            file.image.wrote(file.pos, &buf[..taken]);
+            file.pos += taken as u64;
+            left -= taken;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock_auto_clear_poison().fd.flush()
+    }
+}
+
+impl Seek for Watched {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let mut file = self.0.lock_auto_clear_poison();
+        file.pos = file.fd.seek(pos)?;
+        Ok(file.pos)
+    }
+}
+
+impl Truncate for Watched {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        let file = &mut *self.0.lock_auto_clear_poison();
+        file.fd.set_len(len)?;
+        // not every storage grows on being set longer, so it's asked how long it got
+        let len = file.fd.seek(SeekFrom::End(0))?;
+        file.fd.seek(SeekFrom::Start(file.pos))?;
+        file.image.resized(len);
+        Ok(())
+    }
+}
+
+impl SyncData for Watched {
+    fn sync_data(&mut self) -> io::Result<()> {
+        self.0.lock_auto_clear_poison().fd.sync_data()
+    }
+}
+
+/// Watch kept over the file, see [Database::check_external_changes].
+pub(crate) struct Watch {
+    policy: ExternalChangePolicy,
+    interval: Option<Duration>,
+    /// When the file was last checked, as told by the clock of the database.
+    checked_at: Duration,
+    file: Watched,
+}
+
+impl Database {
+    /// Starts watching the file for external changes from what it looks like now.
+    pub(crate) fn watch(
+        &mut self,
+        policy: ExternalChangePolicy,
+        interval: Option<Duration>,
+    ) -> Result<(), Error> {
+        let mut handle = self.handle.lock_auto_clear_poison();
+        let fd = mem::replace(&mut handle.fd, Box::new(ReadOnly(io::empty())));
+        let file = Watched::new(fd).map_err(Error::IO)?;
+        handle.fd = Box::new(file.clone());
+        drop(handle);
+        self.watch = Some(Watch {
+            policy,
+            interval,
+            checked_at: self.clock.now(),
+            file,
+        });
+        Ok(())
+    }
+
+    /// Whether [Database::maintain] is due to check the file for external changes.
+    pub(crate) fn is_check_due(&self) -> bool {
+        self.watch.as_ref().is_some_and(|watch| {
+            let elapsed = self.clock.now().saturating_sub(watch.checked_at);
+            watch.interval.is_some_and(|interval| elapsed >= interval)
+        })
+    }
+
+    /// Whether the file was changed by someone else since the database last
+    /// read or wrote it, dealt with as
+    /// [DatabaseOptions::watch_external_changes](crate::db::DatabaseOptions::watch_external_changes)
+    /// says: failing with [Error::ExternallyModified], or reloading and telling so.
+    /// Always false unless watched.
+    ///
+    /// The file is told apart by its length and its header, whose sequence
+    /// number goes up with every flush, so changes that leave both as they
+    /// were, like a record rewritten in place, go unnoticed.
+    pub fn check_external_changes(&mut self) -> Result<bool, Error> {
+        let now = self.clock.now();
+        let Some(watch) = self.watch.as_mut() else {
+            return Ok(false);
+        };
+        watch.checked_at = now;
+        let Some(observed) = watch.file.changed().map_err(Error::IO)? else {
+            return Ok(false);
+        };
+        let (policy, file) = (watch.policy, watch.file.clone());
+        match policy {
+            ExternalChangePolicy::Fail => Err(Error::ExternallyModified),
+            ExternalChangePolicy::Reload => {
+                self.reload(file.clone())?;
+                file.accept(observed);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Reads the database anew from `file` in place of what was read before,
+    /// keeping the options it was opened with. Files of another dimension
+    /// are refused, and so are databases over a [VectorStore](crate::db::VectorStore),
+    /// whose records the file doesn't hold.
+    fn reload(&mut self, file: Watched) -> Result<(), Error> {
+        let mut handle = self.handle.lock_auto_clear_poison();
+        if handle.store.is_some() {
+            return Err(Error::Unsupported("reloading"));
+        }
+        if handle.iterators.is_iterating() {
+            return Err(Error::IterationInProgress);
+        }
+        let (fresh, _) = Database::open(&self.name, Box::new(file), None, false)?;
+        let mut replaced = fresh.handle.lock_auto_clear_poison();
+        if replaced.dim_size != handle.dim_size {
+            return Err(Error::Dimension(handle.dim_size, replaced.dim_size as usize));
+        }
+        mem::swap(&mut *handle, &mut *replaced);
+        handle.sync_policy = replaced.sync_policy;
+        handle.amplification = mem::take(&mut replaced.amplification);
+        handle.counters = self.counters.clone();
+        // moves on from every revision views and cached responses were taken at
+        handle.revision = replaced.revision + 1;
+        handle.publish();
+        let layout = (handle.history.is_none()).then(|| handle.layout());
+        self.storage.relayout(layout);
+        drop(handle);
+        drop(replaced);
+
+        if let Some(recorded) = fresh.recorded_hnsw {
+            self.config = self.config.built_like(recorded);
+        }
+        self.search = fresh.search;
+        self.quota = fresh.quota;
+        self.groups = fresh.groups;
+        self.keys = fresh.keys;
+        self.levels = fresh.levels;
+        self.layers = fresh.layers;
+        self.layer_entry = fresh.layer_entry;
+        self.skipped_layers = fresh.skipped_layers;
+        self.recorded_metric = fresh.recorded_metric;
+        self.recorded_hnsw = fresh.recorded_hnsw;
+        self.maintenance = fresh.maintenance;
+        let mut cache = self.loaded_vectors.lock_auto_clear_poison();
+        let mode = cache.mode();
+        *cache = Default::default();
+        cache.set_mode(mode);
+        drop(cache);
+        self.drop_index();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Clock, DatabaseOptions, Error, ExternalChangePolicy, MaintenanceTask};
+    use crate::ext::mem::SharedCursor;
+    use crate::ext::rand::XorShift;
+    use crate::vio::Truncate;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Tells the time it's set to.
+    #[derive(Debug, Default)]
+    struct ManualClock(AtomicU64);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Duration {
+            Duration::from_millis(self.0.load(Ordering::Relaxed))
+        }
+    }
+
+    /// Bytes of a database of `count` vectors of `dim_size`,
+    /// with its layers written if `indexed`.
+    fn replacement(dim_size: u32, count: usize, indexed: bool) -> Vec<u8> {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(dim_size)
+            .layer_section(1 << 20)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut rng = XorShift::new(count as u64);
+        for _ in 0..count {
+            db.push(rng.vector(dim_size)).unwrap();
+        }
+        if indexed {
+            db.build_index().unwrap();
+        }
+        db.flush().unwrap();
+        file.bytes()
+    }
+
+    /// Swaps the bytes of `file` for `bytes`, as another process would.
+    fn replace(file: &SharedCursor, bytes: &[u8]) {
+        let mut other = file.reopen();
+        other.set_len(0).unwrap();
+        other.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn external_changes_detected() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .layer_section(1 << 20)
+            .watch_external_changes(ExternalChangePolicy::Fail, None)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        // what the database writes itself doesn't count
+        let mut rng = XorShift::new(5);
+        for _ in 0..20 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        db.push_many((0..20).map(|_| rng.vector(4)).collect::<Vec<_>>()).unwrap();
+        db.remove(3).unwrap();
+        db.update(5, rng.vector(4)).unwrap();
+        db.build_index().unwrap();
+        db.flush().unwrap();
+        assert!(!db.check_external_changes().unwrap());
+
+        replace(&file, &replacement(4, 10, false));
+        assert!(matches!(db.check_external_changes(), Err(Error::ExternallyModified)));
+        // and keeps being found until dealt with
+        assert!(matches!(db.check_external_changes(), Err(Error::ExternallyModified)));
+
+        // nothing is watched unless asked to
+        let mut unwatched = DatabaseOptions::default()
+            .open("mem", Box::new(file.reopen()))
+            .unwrap();
+        replace(&file, &replacement(4, 20, false));
+        assert!(!unwatched.check_external_changes().unwrap());
+    }
+
+    #[test]
+    fn external_changes_reloaded() {
+        let file = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .layer_section(1 << 20)
+            .watch_external_changes(ExternalChangePolicy::Reload, None)
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        let mut rng = XorShift::new(7);
+        for _ in 0..50 {
+            db.push(rng.vector(4)).unwrap();
+        }
+        db.build_index().unwrap();
+        db.flush().unwrap();
+        let stale = db.get(0).unwrap().unwrap();
+
+        let bytes = replacement(4, 200, true);
+        replace(&file, &bytes);
+        assert!(db.check_external_changes().unwrap());
+        assert!(!db.check_external_changes().unwrap());
+        let expected = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(db.count().unwrap(), 200);
+        assert_eq!(db.get(0).unwrap(), expected.get(0).unwrap());
+        assert_ne!(db.get(0).unwrap().unwrap(), stale);
+        // the layers are those of the new file, while the index built before is gone
+        assert!(!db.is_indexed());
+        let query = [0.5f32; 4];
+        assert_eq!(db.search(&query, 5).unwrap(), expected.search(&query, 5).unwrap());
+
+        // and it's written to as the new file
+        db.push(rng.vector(4)).unwrap();
+        db.flush().unwrap();
+        assert!(!db.check_external_changes().unwrap());
+        let reopened = DatabaseOptions::default().open("mem", Box::new(file.reopen())).unwrap();
+        assert_eq!(reopened.count().unwrap(), 201);
+
+        // a file of another dimension isn't taken for this one
+        replace(&file, &replacement(8, 10, false));
+        assert!(matches!(db.check_external_changes(), Err(Error::Dimension(4, 8))));
+        assert!(matches!(db.check_external_changes(), Err(Error::Dimension(4, 8))));
+    }
+
+    #[test]
+    fn external_changes_checked_on_maintenance() {
+        let file = SharedCursor::new();
+        let clock = Arc::new(ManualClock::default());
+        let mut db = DatabaseOptions::new(4)
+            .deadline_clock(clock.clone())
+            .watch_external_changes(ExternalChangePolicy::Reload, Some(Duration::from_secs(1)))
+            .create("mem", Box::new(file.reopen()))
+            .unwrap();
+        db.push([1f32; 4]).unwrap();
+        db.flush().unwrap();
+        replace(&file, &replacement(4, 30, false));
+        assert!(db.maintenance_pending().is_empty());
+
+        clock.0.store(1000, Ordering::Relaxed);
+        assert_eq!(db.maintenance_pending(), [MaintenanceTask::CheckExternalChanges]);
+        let report = db.maintain(Duration::ZERO).unwrap();
+        assert!(report.reloaded && report.is_done());
+        assert_eq!(db.count().unwrap(), 30);
+        assert!(db.maintenance_pending().is_empty());
+    }
+}
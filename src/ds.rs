@@ -1,2 +1,3 @@
+pub mod bloom;
 pub mod graph;
 pub mod layer;
\ No newline at end of file
@@ -0,0 +1,101 @@
+use crate::db::DbIndex;
+use std::f64::consts::LN_2;
+
+/// Size of a [BloomFilter], fixed once it's created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BloomParams {
+    /// Always a multiple of 64.
+    pub bits: u64,
+    pub hashes: u8,
+}
+
+impl BloomParams {
+    /// Smallest filter expected to answer falsely positive at `false_positive_rate`
+    /// once `expected` ids are in. It only gets worse beyond that.
+    pub(crate) fn for_rate(expected: u64, false_positive_rate: f64) -> BloomParams {
+        let expected = expected.max(1) as f64;
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 1f64);
+        let bits = (-expected * rate.ln() / (LN_2 * LN_2)).ceil().max(64f64) as u64;
+        let bits = bits.div_ceil(64) * 64;
+        let hashes = (bits as f64 / expected * LN_2).round().clamp(1f64, 16f64) as u8;
+        BloomParams { bits, hashes }
+    }
+
+    pub(crate) fn words(&self) -> usize {
+        (self.bits / 64) as usize
+    }
+}
+
+/// # Bloom Filter
+/// Set of [DbIndex] answering "definitely not in" or "maybe in",
+/// so that misses can be told apart without touching the file.
+/// Ids can't be taken out, so removed ones keep answering "maybe".
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BloomFilter {
+    params: BloomParams,
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    pub(crate) fn new(params: BloomParams) -> BloomFilter {
+        BloomFilter {
+            params,
+            words: vec![0; params.words()],
+        }
+    }
+
+    pub(crate) fn from_words(params: BloomParams, words: Vec<u64>) -> BloomFilter {
+        BloomFilter { params, words }
+    }
+
+    pub(crate) fn params(&self) -> BloomParams {
+        self.params
+    }
+
+    pub(crate) fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub(crate) fn insert(&mut self, id: DbIndex) {
+        for bit in self.bits_of(id) {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub(crate) fn may_contain(&self, id: DbIndex) -> bool {
+        self.bits_of(id)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Derives every hash from two halves of one mixed value,
+    /// which is as good as independent ones for a bloom filter.
+    fn bits_of(&self, id: DbIndex) -> impl Iterator<Item = u64> {
+        let mut x = (id as u64).wrapping_add(0x9E3779B97F4A7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+        x ^= x >> 31;
+        let (h1, h2) = (x & 0xFFFFFFFF, (x >> 32) | 1);
+        let bits = self.params.bits;
+        (0..self.params.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ds::bloom::{BloomFilter, BloomParams};
+
+    #[test]
+    fn bloom_works() {
+        let params = BloomParams::for_rate(1000, 0.01);
+        assert_eq!(params.bits % 64, 0);
+        assert_eq!(params.hashes, 7);
+
+        let mut filter = BloomFilter::new(params);
+        for id in (0..2000).step_by(2) {
+            filter.insert(id);
+        }
+        assert!((0..2000).step_by(2).all(|id| filter.may_contain(id)));
+        let false_positives = (1..2000).step_by(2).filter(|id| filter.may_contain(*id)).count();
+        assert!(false_positives < 30);
+    }
+}
@@ -1,5 +1,7 @@
-use std::cmp::max;
-use std::collections::{BTreeSet, HashMap};
+use crate::db::GrowthPolicy;
+use std::cmp::{max, Reverse};
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
 use std::fmt;
 use std::fmt::Formatter;
 
@@ -13,9 +15,17 @@ pub(crate) trait Graph<Error> {
     fn is_empty(&self) -> bool;
 
     fn connect(&mut self, a: u32, b: u32, distance: f32) -> Result<(), Error>;
-    fn get_neighbors(&self, query_node: u32) -> Vec<u32>;
     fn get_vertices(&self, query_node: u32) -> Vec<(u32, f32)>;
+    #[cfg(test)]
     fn get_vertice(&self, a: u32, b: u32) -> Result<Option<f32>, Error>;
+
+    #[cfg(test)]
+    fn get_neighbors(&self, query_node: u32) -> Vec<u32> {
+        self.get_vertices(query_node)
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect()
+    }
 }
 
 /// # Non-directional Graph
@@ -23,19 +33,24 @@ pub(crate) trait Graph<Error> {
 /// (the connection from one node to another) are considered the same
 /// as in the other direction, and the capacity is incremented automatically.
 ///
-/// The underlying implementation employs an adjacent matrix data structure,
-/// where space complexity is proportional to the square of the node numbers,
-/// and time complexity of querying is constant.
+/// The underlying implementation keeps a sorted adjacent list per node,
+/// so space is proportional to the number of vertices, which suits
+/// the sparse layers of an index, and querying one takes logarithmic time.
+#[derive(Clone)]
 pub(crate) struct NdGraph {
     len: u32,
     capacity: u32,
-    adjacent_matrix: Vec<Vec<f32>>,
+    adjacent_list: Vec<Vec<(u32, f32)>>,
+    growth: GrowthPolicy,
+    /// Times the adjacent list was moved to make room.
+    #[cfg(test)]
+    reallocations: u64,
 }
 
 /// # Adjacent List
 /// Using a list of tuples to represent the [NdGraph] structure.
 /// The first couple stands for nodes, the last being the distance.
-type AdjList = Vec<(u32, u32, f32)>;
+pub(crate) type AdjList = Vec<(u32, u32, f32)>;
 
 #[derive(Debug, PartialEq)]
 pub(crate) enum NdgError {
@@ -58,7 +73,10 @@ impl Graph<NdgError> for NdGraph {
         NdGraph {
             len: 0,
             capacity: 0,
-            adjacent_matrix: Vec::new(),
+            adjacent_list: Vec::new(),
+            growth: GrowthPolicy::default(),
+            #[cfg(test)]
+            reallocations: 0,
         }
     }
 
@@ -66,41 +84,26 @@ impl Graph<NdgError> for NdGraph {
         NdGraph {
             len: 0,
             capacity,
-            adjacent_matrix: (0..capacity)
-                .map(|row| (0..=row).map(|_| f32::INFINITY).collect())
-                .collect(),
+            adjacent_list: Vec::with_capacity(capacity as usize),
+            growth: GrowthPolicy::default(),
+            #[cfg(test)]
+            reallocations: 0,
         }
     }
 
     fn from_adj_list(adj_list: AdjList) -> NdGraph {
-        let len = *adj_list
+        let len = adj_list
             .iter()
             .flat_map(|(a, b, _)| [a, b])
             .max()
-            .unwrap_or(&0u32);
-
-        let adj_mat = (0..len)
-            .map(|row| {
-                (0..=row)
-                    .map(|col| {
-                        match adj_list
-                            .iter()
-                            .find(|(a, b, _)| *a == row && *b == col || *a == col && *b == row)
-                        // TODO: optimize this O(n^2) search by sorting in advance
-                        {
-                            None => f32::INFINITY,
-                            Some((_, _, d)) => *d,
-                        }
-                    })
-                    .collect()
-            })
-            .collect();
+            .map_or(0, |max| max + 1);
 
-        NdGraph {
-            len,
-            capacity: len,
-            adjacent_matrix: adj_mat,
+        let mut graph = NdGraph::with_capacity(len);
+        graph.push_many(len);
+        for (a, b, distance) in adj_list {
+            graph.connect(a, b, distance).unwrap();
         }
+        graph
     }
 
     fn len(&self) -> u32 {
@@ -112,79 +115,226 @@ impl Graph<NdgError> for NdGraph {
     }
 
     fn is_empty(&self) -> bool {
-        self.len() <= 0
+        self.len() == 0
     }
 
     fn connect(&mut self, a: u32, b: u32, distance: f32) -> Result<(), NdgError> {
         if a >= self.len() || b >= self.len() {
             Err(NdgError::ExceedBoundary(max(a, b) + 1, self.len()))
         } else {
-            let (a, b) = if a > b { (a, b) } else { (b, a) };
-            self.adjacent_matrix[a as usize][b as usize] = distance;
+            for (from, to) in [(a, b), (b, a)] {
+                let row = &mut self.adjacent_list[from as usize];
+                match row.binary_search_by_key(&to, |(node, _)| *node) {
+                    Ok(pos) => row[pos].1 = distance,
+                    Err(pos) => row.insert(pos, (to, distance)),
+                }
+                if a == b {
+                    break;
+                }
+            }
             Ok(())
         }
     }
 
-    fn get_neighbors(&self, query_node: u32) -> Vec<u32> {
-        if query_node >= self.len() {
-            return vec![];
-        }
-        self.adjacent_matrix[query_node as usize]
-            .iter()
-            .enumerate()
-            .filter_map(|(node, dist)| {
-                if *dist < f32::INFINITY {
-                    Some(node as u32)
-                } else {
-                    None
-                }
-            })
-            .collect()
-    }
-
     fn get_vertices(&self, query_node: u32) -> Vec<(u32, f32)> {
-        if query_node >= self.len() {
-            return vec![];
+        match self.adjacent_list.get(query_node as usize) {
+            Some(row) if query_node < self.len() => row.clone(),
+            _ => vec![],
         }
-        Vec::from_iter(
-            (0..self.len())
-                .map(|n| (n, self.adjacent_matrix[query_node as usize][n as usize]))
-                .filter(|n| {
-                    self.adjacent_matrix[query_node as usize][n.0 as usize] < f32::INFINITY
-                }),
-        )
     }
 
+    #[cfg(test)]
     fn get_vertice(&self, a: u32, b: u32) -> Result<Option<f32>, NdgError> {
         if a >= self.len() || b >= self.len() {
             Err(NdgError::ExceedBoundary(max(a, b) + 1, self.len()))
         } else {
-            let (a, b) = if a > b { (a, b) } else { (b, a) };
-            let dis = self.adjacent_matrix[a as usize][b as usize];
-            Ok(if dis < f32::INFINITY { Some(dis) } else { None })
+            let row = &self.adjacent_list[a as usize];
+            Ok(row
+                .binary_search_by_key(&b, |(node, _)| *node)
+                .ok()
+                .map(|pos| row[pos].1))
         }
     }
 }
 
 impl NdGraph {
-    pub(crate) fn push_many(&mut self, count: u32) -> u32 {
-        if self.capacity() < self.len() + count {
-            let lacking = self.len() + count - self.capacity();
-            for row in 1..=lacking {
-                self.adjacent_matrix.push(Vec::from_iter(
-                    (0..row + self.capacity()).map(|_| f32::INFINITY),
-                ))
+    /// Same as [Graph::get_vertices] without copying them.
+    pub(crate) fn vertices_of(&self, node: u32) -> &[(u32, f32)] {
+        match self.adjacent_list.get(node as usize) {
+            Some(row) if node < self.len() => row,
+            _ => &[],
+        }
+    }
+
+    /// Every vertice once, from the smaller node to the other, ascending.
+    pub(crate) fn edges(&self) -> impl Iterator<Item = (u32, u32, f32)> + '_ {
+        (0..self.len()).flat_map(move |a| {
+            self.vertices_of(a)
+                .iter()
+                .filter(move |(b, _)| a <= *b)
+                .map(move |(b, distance)| (a, *b, *distance))
+        })
+    }
+
+    pub(crate) fn with_growth(mut self, growth: GrowthPolicy) -> NdGraph {
+        self.growth = growth;
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn reallocations(&self) -> u64 {
+        self.reallocations
+    }
+
+    /// Adds every vertice of `other` with its nodes numbered `offset` up,
+    /// growing to hold them.
+    #[cfg(any(test, feature = "rayon"))]
+    pub(crate) fn merge(&mut self, other: &NdGraph, offset: u32) {
+        if offset + other.len() > self.len() {
+            self.push_many(offset + other.len() - self.len());
+        }
+        for (a, row) in other.adjacent_list.iter().enumerate() {
+            for (b, distance) in row {
+                self.connect(offset + a as u32, offset + b, *distance).unwrap();
             }
-            self.capacity = self.len() + count;
         }
+    }
+
+    /// Bytes its adjacent list takes on the heap, room reserved included.
+    pub(crate) fn heap_bytes(&self) -> u64 {
+        let vertices = self.adjacent_list.iter().map(Vec::capacity).sum::<usize>();
+        NdGraph::projected_bytes(self.adjacent_list.capacity() as u64, vertices as u64)
+    }
+
+    /// Bytes on the heap of an adjacent list with room for
+    /// `rows` nodes and `vertices` in their rows together.
+    pub(crate) fn projected_bytes(rows: u64, vertices: u64) -> u64 {
+        rows * size_of::<Vec<(u32, f32)>>() as u64 + vertices * size_of::<(u32, f32)>() as u64
+    }
+
+    /// Room a row makes to hold `vertices` connected one by one.
+    pub(crate) fn row_capacity(vertices: usize) -> usize {
+        let mut row = Vec::new();
+        for node in 0..vertices {
+            row.push((node as u32, 0f32));
+        }
+        row.capacity()
+    }
+
+    /// Makes room for at least `additional` more nodes at once,
+    /// regardless of the growth policy, as many as can be numbered.
+    pub(crate) fn reserve(&mut self, additional: u32) {
+        self.grow_exactly(self.len().saturating_add(additional));
+    }
+
+    fn grow_exactly(&mut self, capacity: u32) {
+        if self.capacity() < capacity {
+            self.adjacent_list
+                .reserve_exact(capacity as usize - self.adjacent_list.len());
+            self.capacity = capacity;
+            #[cfg(test)]
+            {
+                self.reallocations += 1;
+            }
+        }
+    }
+
+    /// Makes room for `len` nodes the way the growth policy says.
+    fn grow(&mut self, len: u32) {
+        if self.capacity() >= len {
+            return;
+        }
+        self.grow_exactly(self.growth.grown(self.capacity(), len));
+    }
+
+    /// Adds `count` nodes, returning the last node, which for none
+    /// pushed into an empty graph wraps around to [u32::MAX].
+    pub(crate) fn push_many(&mut self, count: u32) -> u32 {
+        self.grow(self.len() + count);
+        self.adjacent_list
+            .resize_with((self.len() + count) as usize, Vec::new);
 
         self.len += count;
-        self.len() - 1
+        self.len().wrapping_sub(1)
     }
 
     pub(crate) fn push_one(&mut self) -> u32 {
         self.push_many(1)
     }
+
+    /// Removes the vertice between `a` and `b`, if any.
+    pub(crate) fn disconnect(&mut self, a: u32, b: u32) {
+        for (from, to) in [(a, b), (b, a)] {
+            if let Some(row) = self.adjacent_list.get_mut(from as usize) {
+                if let Ok(pos) = row.binary_search_by_key(&to, |(node, _)| *node) {
+                    row.remove(pos);
+                }
+            }
+        }
+    }
+
+    /// Number of nodes connected to `node`.
+    pub(crate) fn degree(&self, node: u32) -> usize {
+        self.adjacent_list.get(node as usize).map_or(0, Vec::len)
+    }
+
+    /// Nodes within `hops` edges of any of `roots`, in the order a breadth-first
+    /// search discovers them, and every vertice among them, each once with the
+    /// smaller node first. Roots outside the graph are left out.
+    pub(crate) fn subgraph(&self, roots: &[u32], hops: u32) -> (AdjList, Vec<u32>) {
+        let mut nodes = vec![];
+        let mut discovered = HashSet::new();
+        let mut frontier = VecDeque::new();
+        for root in roots {
+            if *root < self.len() && discovered.insert(*root) {
+                nodes.push(*root);
+                frontier.push_back((*root, 0));
+            }
+        }
+        while let Some((node, depth)) = frontier.pop_front() {
+            if depth == hops {
+                continue;
+            }
+            for (neighbor, _) in &self.adjacent_list[node as usize] {
+                if discovered.insert(*neighbor) {
+                    nodes.push(*neighbor);
+                    frontier.push_back((*neighbor, depth + 1));
+                }
+            }
+        }
+
+        let mut edges = vec![];
+        for a in &nodes {
+            for (b, distance) in &self.adjacent_list[*a as usize] {
+                if a <= b && discovered.contains(b) {
+                    edges.push((*a, *b, *distance));
+                }
+            }
+        }
+        edges.sort_by_key(|(a, b, _)| (*a, *b));
+        (edges, nodes)
+    }
+}
+
+/// Length of the shortest path from `source` to every node reachable from it,
+/// weighing each edge by its distance, which must not be negative.
+pub(crate) fn shortest_paths<E>(graph: &impl Graph<E>, source: u32) -> HashMap<u32, f32> {
+    let mut settled = HashMap::new();
+    // bit patterns of non-negative floats sort the same as their values
+    let mut frontier = BinaryHeap::from([Reverse((0f32.to_bits(), source))]);
+    while let Some(Reverse((bits, node))) = frontier.pop() {
+        if settled.contains_key(&node) {
+            continue;
+        }
+        let length = f32::from_bits(bits);
+        settled.insert(node, length);
+        for (neighbor, distance) in graph.get_vertices(node) {
+            if !settled.contains_key(&neighbor) {
+                frontier.push(Reverse(((length + distance).to_bits(), neighbor)));
+            }
+        }
+    }
+    settled
 }
 
 /// # Cast Non-directional Graph
@@ -193,34 +343,114 @@ impl NdGraph {
 ///
 /// The underlying implementation is basically [NdGraph] and [HashMap],
 /// so efficiency should be alright.
-struct AnyCastNdGraph {
+#[derive(Clone)]
+pub(crate) struct AnyCastNdGraph {
     graph: NdGraph,
     mapping: HashMap<u32, u32>,
-}
-
-#[derive(Debug, PartialEq)]
-enum AcndgError {
-    NodeNonexistence(u32),
+    /// Original number of the node kept at each slot.
+    originals: Vec<u32>,
 }
 
 impl AnyCastNdGraph {
-    fn get_mapping_or_insert(&mut self, node: u32) -> u32 {
+    /// Puts back a graph taken apart by [AnyCastNdGraph::mapping] and
+    /// [AnyCastNdGraph::slots]. Every connected slot must be mapped to.
+    pub(crate) fn from_parts(mapping: HashMap<u32, u32>, slots: NdGraph) -> AnyCastNdGraph {
+        let mut originals = vec![0; slots.len() as usize];
+        for (node, slot) in &mapping {
+            originals[*slot as usize] = *node;
+        }
+        AnyCastNdGraph {
+            graph: slots,
+            mapping,
+            originals,
+        }
+    }
+
+    /// Slot in the inner graph each node is kept at.
+    pub(crate) fn mapping(&self) -> &HashMap<u32, u32> {
+        &self.mapping
+    }
+
+    /// The inner graph, whose nodes are slots rather than the original numbers.
+    pub(crate) fn slots(&self) -> &NdGraph {
+        &self.graph
+    }
+
+    /// Original number of the node kept at `slot`.
+    pub(crate) fn original(&self, slot: u32) -> u32 {
+        self.originals[slot as usize]
+    }
+
+    /// Same as [NdGraph::heap_bytes], the mapping included.
+    pub(crate) fn heap_bytes(&self) -> u64 {
+        let mapping = self.mapping.capacity() * size_of::<(u32, u32)>();
+        let originals = self.originals.capacity() * size_of::<u32>();
+        self.graph.heap_bytes() + (mapping + originals) as u64
+    }
+
+    /// Original numbers of every node, in ascending order.
+    pub(crate) fn nodes(&self) -> Vec<u32> {
+        let mut nodes = self.mapping.keys().copied().collect::<Vec<_>>();
+        nodes.sort();
+        nodes
+    }
+
+    /// Same as [NdGraph::edges], by the original numbers.
+    #[cfg(test)]
+    pub(crate) fn edges(&self) -> AdjList {
+        let mut edges = self
+            .graph
+            .edges()
+            .map(|(a, b, distance)| {
+                let (a, b) = (self.originals[a as usize], self.originals[b as usize]);
+                (a.min(b), a.max(b), distance)
+            })
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|(a, b, _)| (*a, *b));
+        edges
+    }
+
+    /// Same as [NdGraph::subgraph], by the original numbers.
+    pub(crate) fn subgraph(&self, roots: &[u32], hops: u32) -> (AdjList, Vec<u32>) {
+        let roots = roots
+            .iter()
+            .filter_map(|root| self.mapping.get(root).copied())
+            .collect::<Vec<_>>();
+        let (edges, slots) = self.graph.subgraph(&roots, hops);
+        let mut edges = edges
+            .into_iter()
+            .map(|(a, b, distance)| {
+                let (a, b) = (self.originals[a as usize], self.originals[b as usize]);
+                (a.min(b), a.max(b), distance)
+            })
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|(a, b, _)| (*a, *b));
+        let nodes = slots
+            .into_iter()
+            .map(|slot| self.originals[slot as usize])
+            .collect();
+        (edges, nodes)
+    }
+
+    pub(crate) fn get_mapping_or_insert(&mut self, node: u32) -> u32 {
         match self.mapping.get(&node) {
             Some(m) => *m,
             None => {
                 let pushed = self.graph.push_one();
                 self.mapping.insert(node, pushed);
+                self.originals.push(node);
                 pushed
             }
         }
     }
 }
 
-impl Graph<AcndgError> for AnyCastNdGraph {
+impl Graph<Infallible> for AnyCastNdGraph {
     fn new() -> Self {
         AnyCastNdGraph {
             graph: NdGraph::new(),
             mapping: HashMap::new(),
+            originals: vec![],
         }
     }
 
@@ -228,6 +458,7 @@ impl Graph<AcndgError> for AnyCastNdGraph {
         AnyCastNdGraph {
             graph: NdGraph::with_capacity(capacity),
             mapping: HashMap::with_capacity(capacity as usize),
+            originals: Vec::with_capacity(capacity as usize),
         }
     }
 
@@ -257,33 +488,34 @@ impl Graph<AcndgError> for AnyCastNdGraph {
         self.graph.is_empty()
     }
 
-    fn connect(&mut self, a: u32, b: u32, distance: f32) -> Result<(), AcndgError> {
+    fn connect(&mut self, a: u32, b: u32, distance: f32) -> Result<(), Infallible> {
         let a = self.get_mapping_or_insert(a);
         let b = self.get_mapping_or_insert(b);
-        Ok(self.graph.connect(a, b, distance).unwrap())
-    }
-
-    fn get_neighbors(&self, query_node: u32) -> Vec<u32> {
-        match self.mapping.get(&query_node) {
-            None => vec![],
-            Some(m) => self.graph.get_neighbors(*m),
-        }
+        self.graph.connect(a, b, distance).unwrap();
+        Ok(())
     }
 
     fn get_vertices(&self, query_node: u32) -> Vec<(u32, f32)> {
         match self.mapping.get(&query_node) {
             None => vec![],
-            Some(m) => self.graph.get_vertices(*m),
+            Some(m) => {
+                let mut vertices = self
+                    .graph
+                    .get_vertices(*m)
+                    .into_iter()
+                    .map(|(slot, distance)| (self.originals[slot as usize], distance))
+                    .collect::<Vec<_>>();
+                vertices.sort_by_key(|(node, _)| *node);
+                vertices
+            }
         }
     }
 
-    fn get_vertice(&self, a: u32, b: u32) -> Result<Option<f32>, AcndgError> {
-        match self.mapping.get(&a) {
-            None => Err(AcndgError::NodeNonexistence(a)),
-            Some(a) => match self.mapping.get(&b) {
-                None => Err(AcndgError::NodeNonexistence(b)),
-                Some(b) => Ok(self.graph.get_vertice(*a, *b).unwrap()),
-            },
+    #[cfg(test)]
+    fn get_vertice(&self, a: u32, b: u32) -> Result<Option<f32>, Infallible> {
+        match (self.mapping.get(&a), self.mapping.get(&b)) {
+            (Some(a), Some(b)) => Ok(self.graph.get_vertice(*a, *b).unwrap()),
+            _ => Ok(None),
         }
     }
 }
@@ -292,22 +524,19 @@ impl From<NdGraph> for AnyCastNdGraph {
     fn from(value: NdGraph) -> Self {
         let mapping = HashMap::from_iter(
             value
-                .adjacent_matrix
+                .adjacent_list
                 .iter()
                 .take(value.len() as usize)
                 .enumerate()
-                .filter_map(|(node, col)| {
-                    if col.iter().any(|over| *over < f32::INFINITY) {
+                .filter_map(|(node, row)| {
+                    if !row.is_empty() {
                         Some((node as u32, node as u32))
                     } else {
                         None
                     }
                 }),
         );
-        AnyCastNdGraph {
-            graph: value,
-            mapping,
-        }
+        AnyCastNdGraph::from_parts(mapping, value)
     }
 }
 
@@ -334,6 +563,46 @@ mod test {
         assert_eq!(graph.push_one(), 0);
     }
 
+    #[test]
+    fn ndg_growth_works() {
+        let reallocations = |growth| {
+            let mut graph = NdGraph::new().with_growth(growth);
+            for node in 0..10_000 {
+                graph.push_one();
+                if node > 0 {
+                    graph.connect(node - 1, node, node as f32).unwrap();
+                }
+            }
+            for node in 1..10_000 {
+                assert_eq!(graph.get_vertice(node - 1, node).unwrap(), Some(node as f32));
+            }
+            graph.reallocations()
+        };
+        assert_eq!(reallocations(GrowthPolicy::Exact), 10_000);
+        assert!(reallocations(GrowthPolicy::Geometric) < 30);
+        assert_eq!(reallocations(GrowthPolicy::Chunked(1000)), 10);
+
+        let mut graph = NdGraph::new().with_growth(GrowthPolicy::Exact);
+        graph.reserve(500);
+        graph.push_many(500);
+        graph.push_many(101);
+        graph.connect(3, 600, 1f32).unwrap();
+        assert_eq!(graph.len(), 601);
+        assert_eq!(graph.reallocations(), 2);
+        assert_eq!(graph.get_neighbors(600), vec![3]);
+    }
+
+    #[test]
+    fn ndg_merge_works() {
+        let mut graph = NdGraph::from_adj_list(vec![(0, 1, 1f32), (1, 2, 2f32)]);
+        let other = NdGraph::from_adj_list(vec![(0, 2, 3f32), (1, 3, 4f32)]);
+        graph.merge(&other, 2);
+        assert_eq!(graph.len(), 6);
+        assert_eq!(graph.get_vertices(2), vec![(1, 2f32), (4, 3f32)]);
+        assert_eq!(graph.get_vertice(3, 5).unwrap(), Some(4f32));
+        assert_eq!(graph.get_neighbors(0), vec![1]);
+    }
+
     #[test]
     fn ndg_connectivity_works() {
         let mut graph = NdGraph::with_capacity(10);
@@ -351,6 +620,52 @@ mod test {
         );
     }
 
+    #[test]
+    fn shortest_paths_works() {
+        let mut graph = NdGraph::with_capacity(5);
+        graph.push_many(5);
+        graph.connect(0, 1, 1f32).unwrap();
+        graph.connect(1, 2, 1f32).unwrap();
+        graph.connect(0, 2, 3f32).unwrap();
+        graph.connect(2, 3, 0.5).unwrap();
+        let paths = shortest_paths(&graph, 0);
+        assert_eq!(paths.len(), 4);
+        assert_eq!(paths[&2], 2f32);
+        assert_eq!(paths[&3], 2.5f32);
+        assert!(!paths.contains_key(&4));
+        assert_eq!(graph.get_neighbors(1), vec![0, 2]);
+    }
+
+    #[test]
+    fn subgraph_works() {
+        // 0-1-2-3-4-5 with a chord between 2 and 4
+        let mut graph = NdGraph::with_capacity(6);
+        graph.push_many(6);
+        for a in 0..5 {
+            graph.connect(a, a + 1, 1f32).unwrap();
+        }
+        graph.connect(2, 4, 2f32).unwrap();
+
+        let (edges, nodes) = graph.subgraph(&[3], 1);
+        assert_eq!(nodes, vec![3, 2, 4]);
+        // 2 and 4 are both on the frontier
+        assert_eq!(edges, vec![(2, 3, 1f32), (2, 4, 2f32), (3, 4, 1f32)]);
+
+        let (edges, nodes) = graph.subgraph(&[0], 2);
+        assert_eq!(nodes, vec![0, 1, 2]);
+        assert_eq!(edges, vec![(0, 1, 1f32), (1, 2, 1f32)]);
+        let (edges, nodes) = graph.subgraph(&[0, 9], 0);
+        assert_eq!(nodes, vec![0]);
+        assert!(edges.is_empty());
+
+        let mut cast = AnyCastNdGraph::new();
+        cast.connect(70, 10, 1f32).unwrap();
+        cast.connect(10, 40, 1f32).unwrap();
+        let (edges, nodes) = cast.subgraph(&[70], 1);
+        assert_eq!(nodes, vec![70, 10]);
+        assert_eq!(edges, vec![(10, 70, 1f32)]);
+    }
+
     #[test]
     fn acndg_constructors_works() {
         _ = AnyCastNdGraph::new();
@@ -364,6 +679,7 @@ mod test {
         let mut graph = AnyCastNdGraph::new();
         graph.connect(36, 69, 0.42).unwrap();
         assert_eq!(0.42, graph.get_vertice(36, 69).unwrap().unwrap());
+        assert_eq!(graph.get_vertice(36, 70), Ok(None));
     }
 
     #[test]
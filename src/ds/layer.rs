@@ -1,16 +1,393 @@
-use crate::ds::graph::{Graph, NdGraph};
+use crate::algorithm::search::search_layer;
+use crate::ds::graph::{shortest_paths, AdjList, AnyCastNdGraph, Graph, NdGraph};
+use crate::ext::rand::XorShift;
+use std::cmp::Reverse;
+use std::collections::HashMap;
 
-pub(crate) struct HnswLayer {
-    graph: NdGraph,
-    level: u32,
+/// # HNSW Layer
+/// One level of the index graph, stored in either of two shapes.
+#[derive(Clone)]
+pub(crate) enum HnswLayer {
+    /// Nodes are numbered from zero up, whether they're on this level or not.
+    Dense { graph: NdGraph, level: u32 },
+    /// Nodes keep their original, scattered numbers, which suits
+    /// the upper levels where only a fraction of them make it.
+    AnyCast { graph: AnyCastNdGraph, level: u32 },
+}
+
+/// # Layer Diagnostics
+/// Structural quality of one index layer, independent of any query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerDiag {
+    pub level: u32,
+    pub nodes: u32,
+    /// Number of nodes by degree, i.e. `degrees[d]` nodes have `d` neighbors.
+    pub degrees: Vec<u64>,
+    pub mean_degree: f32,
+    /// Fraction of sampled nodes directly connected to their true nearest neighbor.
+    pub nearest_connected: f32,
+    /// Average of shortest path length over direct distance among sampled pairs
+    /// that are connected at all, or `None` if there were none.
+    pub detour_ratio: Option<f32>,
+    /// Sampled pairs with no path in between.
+    pub unreachable: usize,
 }
 
 impl HnswLayer {
     pub(crate) fn new(graph: NdGraph, level: u32) -> HnswLayer {
-        HnswLayer { graph, level }
+        HnswLayer::Dense { graph, level }
+    }
+
+    pub(crate) fn anycast(graph: AnyCastNdGraph, level: u32) -> HnswLayer {
+        HnswLayer::AnyCast { graph, level }
     }
-    
+
     pub(crate) fn is_empty(&self) -> bool {
-        self.graph.is_empty()
+        match self {
+            HnswLayer::Dense { graph, .. } => graph.is_empty(),
+            HnswLayer::AnyCast { graph, .. } => graph.is_empty(),
+        }
+    }
+
+    pub(crate) fn level(&self) -> u32 {
+        match self {
+            HnswLayer::Dense { level, .. } | HnswLayer::AnyCast { level, .. } => *level,
+        }
+    }
+
+    /// Graph of a dense layer, or `None` for an anycast one.
+    pub(crate) fn dense(&self) -> Option<&NdGraph> {
+        match self {
+            HnswLayer::Dense { graph, .. } => Some(graph),
+            HnswLayer::AnyCast { .. } => None,
+        }
+    }
+
+    pub(crate) fn dense_mut(&mut self) -> Option<&mut NdGraph> {
+        match self {
+            HnswLayer::Dense { graph, .. } => Some(graph),
+            HnswLayer::AnyCast { .. } => None,
+        }
+    }
+
+    pub(crate) fn heap_bytes(&self) -> u64 {
+        match self {
+            HnswLayer::Dense { graph, .. } => graph.heap_bytes(),
+            HnswLayer::AnyCast { graph, .. } => graph.heap_bytes(),
+        }
+    }
+
+    /// Every node on this layer, ascending.
+    pub(crate) fn nodes(&self) -> Vec<u32> {
+        match self {
+            HnswLayer::Dense { graph, .. } => Vec::from_iter(0..graph.len()),
+            HnswLayer::AnyCast { graph, .. } => graph.nodes(),
+        }
+    }
+
+    /// Nodes connected to `node` and their distances, ascending by node.
+    pub(crate) fn vertices(&self, node: u32) -> Vec<(u32, f32)> {
+        match self {
+            HnswLayer::Dense { graph, .. } => graph.get_vertices(node),
+            HnswLayer::AnyCast { graph, .. } => graph.get_vertices(node),
+        }
+    }
+
+    /// Every vertice once, from the smaller node to the other, ascending.
+    #[cfg(test)]
+    pub(crate) fn edges(&self) -> AdjList {
+        match self {
+            HnswLayer::Dense { graph, .. } => graph.edges().collect(),
+            HnswLayer::AnyCast { graph, .. } => graph.edges(),
+        }
+    }
+
+    /// Node a search starts from when there's no layer above to hand one
+    /// over, being the one with the most neighbors, the lowest numbered of
+    /// them if there are several, so that it's the same for the same layer
+    /// wherever it was read from. `None` if the layer is empty.
+    pub(crate) fn default_entry(&self) -> Option<u32> {
+        self.nodes()
+            .into_iter()
+            .max_by_key(|node| (self.vertices(*node).len(), Reverse(*node)))
+    }
+
+    /// Moves from `start` to its closest neighbor as long as that's closer
+    /// by `distance`, returning the node where it stops, for the layer below
+    /// to start from. Unlike [HnswLayer::search], nothing but the way down
+    /// is kept.
+    pub(crate) fn descend_from(&self, start: u32, distance: impl Fn(u32) -> f32) -> u32 {
+        let (mut current, mut closest) = (start, distance(start));
+        loop {
+            let next = self
+                .vertices(current)
+                .into_iter()
+                .map(|(node, _)| (node, distance(node)))
+                .min_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+            match next {
+                Some((node, d)) if d < closest => (current, closest) = (node, d),
+                _ => return current,
+            }
+        }
+    }
+
+    /// Beam search of the layer from `entry`, returning the `ef` closest
+    /// nodes it comes across, closest first, see [search_layer]. `distance`
+    /// measures a node against the query, which leaves where the vectors
+    /// are to the caller. Empty if `entry` isn't on the layer.
+    pub(crate) fn search(
+        &self,
+        entry: u32,
+        ef: usize,
+        distance: impl Fn(u32) -> f32,
+    ) -> Vec<(u32, f32)> {
+        match self {
+            HnswLayer::Dense { graph, .. } => {
+                if entry >= graph.len() {
+                    return vec![];
+                }
+                let entries = [(entry, distance(entry))];
+                search_layer(graph, &entries, ef, &mut None, distance).nearest
+            }
+            HnswLayer::AnyCast { graph, .. } => {
+                let Some(slot) = graph.mapping().get(&entry) else {
+                    return vec![];
+                };
+                let entries = [(*slot, distance(entry))];
+                let distance = |slot| distance(graph.original(slot));
+                search_layer(graph.slots(), &entries, ef, &mut None, distance)
+                    .nearest
+                    .into_iter()
+                    .map(|(slot, distance)| (graph.original(slot), distance))
+                    .collect()
+            }
+        }
+    }
+
+    /// Nodes within `hops` edges of `id` and the vertices among them,
+    /// see [NdGraph::subgraph].
+    pub(crate) fn neighborhood(&self, id: u32, hops: u32) -> (AdjList, Vec<u32>) {
+        match self {
+            HnswLayer::Dense { graph, .. } => graph.subgraph(&[id], hops),
+            HnswLayer::AnyCast { graph, .. } => graph.subgraph(&[id], hops),
+        }
+    }
+
+    fn shortest_paths(&self, source: u32) -> HashMap<u32, f32> {
+        match self {
+            HnswLayer::Dense { graph, .. } => shortest_paths(graph, source),
+            HnswLayer::AnyCast { graph, .. } => shortest_paths(graph, source),
+        }
+    }
+
+    /// Measures the layer against `dist_fn`, the true distance between two nodes.
+    /// Up to `sample` nodes and `sample` pairs are drawn by `seed`, or all of them
+    /// if there aren't more, so small layers are measured exactly.
+    pub(crate) fn diagnostics(
+        &self,
+        dist_fn: impl Fn(u32, u32) -> f32,
+        sample: usize,
+        seed: u64,
+    ) -> LayerDiag {
+        let all = self.nodes();
+        let len = all.len() as u32;
+        let mut rng = XorShift::new(seed);
+        let neighbors = HashMap::<_, _>::from_iter(all.iter().map(|node| {
+            let mut neighbors = self
+                .vertices(*node)
+                .into_iter()
+                .map(|(neighbor, _)| neighbor)
+                .collect::<Vec<_>>();
+            neighbors.retain(|n| n != node);
+            (*node, neighbors)
+        }));
+
+        let mut degrees = vec![];
+        for node in &all {
+            let degree = neighbors[node].len();
+            if degrees.len() <= degree {
+                degrees.resize(degree + 1, 0);
+            }
+            degrees[degree] += 1;
+        }
+        let total_degree = neighbors.values().map(|l| l.len()).sum::<usize>();
+
+        let nodes = if sample >= len as usize {
+            all.clone()
+        } else {
+            (0..sample)
+                .map(|_| all[(rng.next_u64() % len as u64) as usize])
+                .collect()
+        };
+        let connected = nodes
+            .iter()
+            .filter(|node| {
+                let nearest = all
+                    .iter()
+                    .filter(|other| other != node)
+                    .min_by(|a, b| dist_fn(**node, **a).total_cmp(&dist_fn(**node, **b)));
+                nearest.is_some_and(|nearest| neighbors[*node].contains(nearest))
+            })
+            .count();
+
+        let pair_count = len as usize * (len as usize).saturating_sub(1) / 2;
+        let pairs = if sample >= pair_count {
+            (0..all.len())
+                .flat_map(|a| (a + 1..all.len()).map(move |b| (a, b)))
+                .map(|(a, b)| (all[a], all[b]))
+                .collect::<Vec<_>>()
+        } else {
+            (0..sample)
+                .map(|_| {
+                    let a = (rng.next_u64() % len as u64) as u32;
+                    let b = (a + 1 + (rng.next_u64() % (len as u64 - 1)) as u32) % len;
+                    (all[a as usize], all[b as usize])
+                })
+                .collect()
+        };
+        let mut paths = HashMap::new();
+        let (mut ratios, mut unreachable) = (vec![], 0);
+        for (a, b) in pairs {
+            let from_a = paths
+                .entry(a)
+                .or_insert_with(|| self.shortest_paths(a));
+            match from_a.get(&b) {
+                None => unreachable += 1,
+                Some(path) => {
+                    let direct = dist_fn(a, b);
+                    if direct > 0f32 {
+                        ratios.push(path / direct);
+                    }
+                }
+            }
+        }
+
+        LayerDiag {
+            level: self.level(),
+            nodes: len,
+            degrees,
+            mean_degree: total_degree as f32 / len.max(1) as f32,
+            nearest_connected: connected as f32 / nodes.len().max(1) as f32,
+            detour_ratio: (!ratios.is_empty())
+                .then(|| ratios.iter().sum::<f32>() / ratios.len() as f32),
+            unreachable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ds::graph::{AnyCastNdGraph, Graph, NdGraph};
+    use crate::ds::layer::HnswLayer;
+
+    #[test]
+    fn diagnostics_works() {
+        // five points on a line, chained 0-1-2-3 with 4 hanging off 0
+        let points = [0f32, 1f32, 2f32, 3f32, 10f32];
+        let mut graph = NdGraph::with_capacity(5);
+        graph.push_many(5);
+        for (a, b) in [(0, 1), (1, 2), (2, 3), (0, 4)] {
+            graph
+                .connect(a, b, (points[a as usize] - points[b as usize]).abs())
+                .unwrap();
+        }
+        let layer = HnswLayer::new(graph, 1);
+
+        let diag = layer.diagnostics(
+            |a, b| (points[a as usize] - points[b as usize]).abs(),
+            100,
+            0,
+        );
+        assert_eq!(diag.level, 1);
+        assert_eq!(diag.nodes, 5);
+        assert_eq!(diag.degrees, vec![0, 2, 3]);
+        assert_eq!(diag.mean_degree, 1.6f32);
+        // 4 is closest to 3 but only connected to 0
+        assert_eq!(diag.nearest_connected, 0.8f32);
+        assert_eq!(diag.unreachable, 0);
+        assert!(diag.detour_ratio.unwrap() > 1f32);
+    }
+
+    /// Every node reachable from `entry`, closest to `query` first,
+    /// found by walking the whole graph.
+    fn reachable(layer: &HnswLayer, entry: u32, query: f32, points: &[f32]) -> Vec<(u32, f32)> {
+        let (_, nodes) = layer.neighborhood(entry, u32::MAX);
+        let mut ranked = nodes
+            .into_iter()
+            .map(|node| (node, (points[node as usize] - query).abs()))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+        ranked
+    }
+
+    #[test]
+    fn search_works() {
+        // a ring of eight points on a line, 8 left on its own
+        let points = [0f32, 1f32, 2f32, 3f32, 4f32, 5f32, 6f32, 7f32, 3.5f32];
+        let mut graph = NdGraph::with_capacity(9);
+        graph.push_many(9);
+        for a in 0..8u32 {
+            let b = (a + 1) % 8;
+            graph
+                .connect(a, b, (points[a as usize] - points[b as usize]).abs())
+                .unwrap();
+        }
+        let mut scattered = AnyCastNdGraph::new();
+        for (a, b, distance) in graph.subgraph(&[0], u32::MAX).0 {
+            scattered.connect(a * 10, b * 10, distance).unwrap();
+        }
+        scattered.get_mapping_or_insert(80);
+        let dense = HnswLayer::new(graph, 0);
+        let anycast = HnswLayer::anycast(scattered, 1);
+
+        let query = 5.2f32;
+        let distance = |node: u32| (points[node as usize] - query).abs();
+        let expected = reachable(&dense, 0, query, &points);
+        assert_eq!(expected.len(), 8);
+        assert_eq!(dense.search(0, 8, distance), expected);
+        assert_eq!(dense.search(0, 3, distance), expected[..3]);
+        let found = anycast.search(0, 8, |node| distance(node / 10));
+        let expected_anycast = expected.iter().map(|(node, d)| (node * 10, *d)).collect::<Vec<_>>();
+        assert_eq!(found, expected_anycast);
+
+        // a single candidate stops at the closest point greedily reachable
+        assert_eq!(dense.search(0, 1, distance), vec![(5, distance(5))]);
+        // the entry alone, left on its own or not on the layer at all
+        assert_eq!(dense.search(8, 4, distance), vec![(8, distance(8))]);
+        assert_eq!(anycast.search(80, 4, |node| distance(node / 10)), vec![(80, distance(8))]);
+        assert!(dense.search(9, 4, distance).is_empty());
+        assert!(anycast.search(3, 4, distance).is_empty());
+    }
+
+    #[test]
+    fn descent_hands_off_the_greedy_optimum() {
+        // the base chains 0 to 9 along a line, the layer above skips
+        // between 0, 3, 6 and 9, with 6 its hub
+        let points = (0..10).map(|x| x as f32).collect::<Vec<_>>();
+        let span = |a: u32, b: u32| (points[a as usize] - points[b as usize]).abs();
+        let mut base = NdGraph::with_capacity(10);
+        base.push_many(10);
+        for a in 0..9 {
+            base.connect(a, a + 1, span(a, a + 1)).unwrap();
+        }
+        let mut upper = AnyCastNdGraph::new();
+        for (a, b) in [(0, 3), (3, 6), (6, 9), (0, 6)] {
+            upper.connect(a, b, span(a, b)).unwrap();
+        }
+        let (base, upper) = (HnswLayer::new(base, 0), HnswLayer::anycast(upper, 1));
+        assert_eq!(upper.default_entry(), Some(6));
+        assert_eq!(base.default_entry(), Some(1));
+        assert_eq!(HnswLayer::anycast(AnyCastNdGraph::new(), 1).default_entry(), None);
+
+        let query = 1.8f32;
+        let distance = |node: u32| (points[node as usize] - query).abs();
+        // 3 is the closest node of the layer above, to which it moves from 6
+        let handoff = upper.descend_from(upper.default_entry().unwrap(), distance);
+        let closest = upper.nodes().into_iter().min_by(|a, b| distance(*a).total_cmp(&distance(*b)));
+        assert_eq!(Some(handoff), closest);
+        assert_eq!(handoff, 3);
+        assert_eq!(base.descend_from(handoff, distance), 2);
+        // starting where nothing is closer stays put
+        assert_eq!(base.descend_from(2, distance), 2);
     }
 }
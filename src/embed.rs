@@ -0,0 +1,169 @@
+//! Text going in and out of a [Database] through an embedding function of
+//! one's own, which it's searched by. No model comes with the crate.
+
+use crate::db::{Database, DbIndex, Error, SearchRequest};
+use crate::index::SearchScratch;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// # Embedder
+/// Turns text into vectors of [Embedder::dim] dimensions, the way a sentence
+/// embedding model does, for [Database::push_text] and [Database::search_text].
+pub trait Embedder {
+    fn embed(&self, text: &str) -> Result<Vec<f32>, EmbedError>;
+    fn dim(&self) -> u32;
+}
+
+/// # Embed Error
+/// Why an [Embedder] failed, in its own words.
+#[derive(Debug)]
+pub struct EmbedError(pub Box<dyn std::error::Error + Send + Sync>);
+
+impl fmt::Display for EmbedError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for EmbedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// # Text Hit
+/// A vector found by [Database::search_text], along with the text it was
+/// pushed as, if it was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextHit {
+    pub id: DbIndex,
+    pub distance: f32,
+    pub text: Option<String>,
+}
+
+/// Embeds `text`, failing with [Error::Dimension] if `embedder` isn't of
+/// the dimensions of `db` or doesn't keep to its own.
+fn embed(db: &Database, embedder: &impl Embedder, text: &str) -> Result<Vec<f32>, Error> {
+    if embedder.dim() != db.dim_size() {
+        return Err(Error::Dimension(db.dim_size(), embedder.dim() as usize));
+    }
+    let vector = embedder.embed(text).map_err(Error::Embed)?;
+    if vector.len() != db.dim_size() as usize {
+        return Err(Error::Dimension(db.dim_size(), vector.len()));
+    }
+    Ok(vector)
+}
+
+impl Database {
+    /// Embeds `text` and pushes it, keeping the text as the
+    /// [key](Database::push_keyed) of the record for searches to return.
+    /// So the database needs a [key table](crate::db::DatabaseOptions::key_table)
+    /// long enough for the text, and pushing the same text again updates
+    /// its record rather than adding another.
+    pub fn push_text(&mut self, embedder: &impl Embedder, text: &str) -> Result<DbIndex, Error> {
+        let vector = embed(self, embedder, text)?;
+        self.upsert_keyed(text, vector)
+    }
+
+    /// The `k` vectors closest to `query` once embedded, closest first,
+    /// along with the text of those that were [pushed as text](Database::push_text).
+    pub fn search_text(
+        &self,
+        embedder: &impl Embedder,
+        query: &str,
+        k: usize,
+    ) -> Result<Vec<TextHit>, Error> {
+        let vector = embed(self, embedder, query)?;
+        let request = SearchRequest::new(&vector, k);
+        let response = self.query_with_scratch(&request, &mut SearchScratch::new())?;
+        Ok(response
+            .results
+            .into_iter()
+            .map(|(id, distance)| TextHit {
+                id,
+                distance,
+                text: self.key_of(id).map(String::from),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error};
+    use crate::embed::{EmbedError, Embedder};
+    use crate::ext::rand::XorShift;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+
+    const SENTENCES: [&str; 5] = [
+        "the cat sat on the mat",
+        "a dog chased the ball across the park",
+        "stock markets fell sharply on monday",
+        "fresh bread from the corner bakery",
+        "the rocket launch was delayed by weather",
+    ];
+
+    /// Sums a random projection seeded by the hash of each word, normalized,
+    /// so that texts sharing words come out close.
+    struct HashEmbedder(u32);
+
+    impl Embedder for HashEmbedder {
+        fn embed(&self, text: &str) -> Result<Vec<f32>, EmbedError> {
+            if text.is_empty() {
+                return Err(EmbedError("nothing to embed".into()));
+            }
+            let mut vector = vec![0f32; self.0 as usize];
+            for word in text.split_whitespace() {
+                let hash = word
+                    .bytes()
+                    .fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+                let projected = XorShift::new(hash).vector(self.0);
+                vector.iter_mut().zip(projected).for_each(|(x, p)| *x += p - 0.5);
+            }
+            let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            Ok(vector.into_iter().map(|x| x / norm).collect())
+        }
+
+        fn dim(&self) -> u32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn text_search_works() {
+        let embedder = HashEmbedder(64);
+        let mut db = DatabaseOptions::new(64)
+            .metric(Metric::Cosine)
+            .key_table(16, 64)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let ids = SENTENCES
+            .iter()
+            .map(|text| db.push_text(&embedder, text).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(db.push_text(&embedder, SENTENCES[2]).unwrap(), ids[2]);
+        db.push([1f32; 64]).unwrap();
+        assert_eq!(db.count().unwrap(), 6);
+
+        for (text, id) in SENTENCES.iter().zip(&ids) {
+            let hits = db.search_text(&embedder, text, 1).unwrap();
+            assert_eq!((hits[0].id, hits[0].text.as_deref()), (*id, Some(*text)));
+        }
+        let hits = db.search_text(&embedder, "bakery bread", 6).unwrap();
+        assert_eq!(hits[0].text.as_deref(), Some(SENTENCES[3]));
+        // what wasn't pushed as text is found without
+        assert_eq!(hits.iter().filter(|hit| hit.text.is_none()).count(), 1);
+
+        assert!(matches!(
+            db.search_text(&HashEmbedder(32), "the cat", 1),
+            Err(Error::Dimension(64, 32))
+        ));
+        assert!(matches!(db.push_text(&embedder, ""), Err(Error::Embed(_))));
+        assert!(matches!(
+            db.push_text(&embedder, &"long ".repeat(20)),
+            Err(Error::KeyTooLong(100))
+        ));
+        assert_eq!(db.count().unwrap(), 6);
+    }
+}
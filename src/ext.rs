@@ -1,2 +1,5 @@
 pub(crate) mod semaphore;
-pub(crate) mod io;
\ No newline at end of file
+pub(crate) mod counter;
+pub(crate) mod io;
+pub(crate) mod mem;
+pub(crate) mod rand;
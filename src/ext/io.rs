@@ -1,7 +1,10 @@
 use crate::vio::RandomAccess;
-use std::io::{SeekFrom, Error};
+use std::cmp::min;
+use std::io::{Error, ErrorKind, IoSlice, SeekFrom, Write};
 
 pub(crate) trait MoveContent {
+    /// Moves `content_len` bytes starting at the current position
+    /// by `offset` bytes, overwriting whatever is in the way.
     fn move_content(
         &mut self,
         content_len: usize,
@@ -16,57 +19,49 @@ fn cut_and_paste_forward(
     offset: usize,
     buffer_size: usize,
 ) -> Result<(), Error> {
-    let mut buf = Vec::with_capacity(buffer_size);
     let begin = fd.stream_position()?;
-    let mut remaining = content_len;
-    loop {
-        let read = if (fd.seek(SeekFrom::Current(remaining as i64))? - buffer_size as u64) < begin {
-            fd.seek(SeekFrom::Start(begin))?;
-            remaining
-        } else {
-            fd.seek_relative(-(buffer_size as i64))?;
-            buffer_size
-        };
-        
-        buf.resize(read, 0);
-        fd.read_exact(&mut *buf)?;
-        fd.seek_relative(offset as i64)?;
-        fd.write(&buf[0..read])?;
+    (content_len as u64)
+        .checked_add(offset as u64)
+        .and_then(|end| end.checked_add(begin))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "moving past the largest offset"))?;
+    let mut buf = vec![0u8; buffer_size];
+    let mut remaining = content_len as u64;
+    // copy from the back so nothing is overwritten before it's read
+    while remaining > 0 {
+        let read = min(remaining, buffer_size as u64);
         remaining -= read;
-        if remaining <= 0 { 
-            return Ok(())
-        }
-        fd.seek_relative(-((offset + buffer_size) as i64))?
+        fd.seek(SeekFrom::Start(begin + remaining))?;
+        fd.read_exact(&mut buf[..read as usize])?;
+        fd.seek(SeekFrom::Start(begin + remaining + offset as u64))?;
+        fd.write_all(&buf[..read as usize])?;
     }
+    Ok(())
 }
 
 fn cut_and_paste_backward(
     fd: &mut dyn RandomAccess,
     content_len: usize,
     offset: usize,
-    buffer_len: usize,
+    buffer_size: usize,
 ) -> Result<(), Error> {
-    let mut buf = Vec::with_capacity(buffer_len);
-    let mut remaining = content_len;
-    loop {
-        let read = if remaining > buffer_len {
-            buffer_len
-        } else {
-            remaining
-        };
-        buf.resize(read, 0);
-        fd.read_exact(&mut *buf)?;
-        fd.seek_relative(-(offset as i64))?;
-        fd.write(&*buf)?;
-        
-        remaining -= read;
-        if remaining <= 0 { 
-            return Ok(())
-        }
+    let begin = fd.stream_position()?;
+    if (offset as u64) > begin {
+        return Err(Error::new(ErrorKind::InvalidInput, "moving before the start"));
+    }
+    let mut buf = vec![0u8; buffer_size];
+    let mut moved = 0u64;
+    while moved < content_len as u64 {
+        let read = min(content_len as u64 - moved, buffer_size as u64);
+        fd.seek(SeekFrom::Start(begin + moved))?;
+        fd.read_exact(&mut buf[..read as usize])?;
+        fd.seek(SeekFrom::Start(begin + moved - offset as u64))?;
+        fd.write_all(&buf[..read as usize])?;
+        moved += read;
     }
+    Ok(())
 }
 
-impl MoveContent for dyn RandomAccess {
+impl MoveContent for dyn RandomAccess + '_ {
     fn move_content(
         &mut self,
         content_len: usize,
@@ -76,7 +71,128 @@ impl MoveContent for dyn RandomAccess {
         if offset >= 0 {
             cut_and_paste_forward(self, content_len, offset as usize, buffer_size)
         } else {
-            cut_and_paste_backward(self, content_len, (-offset) as usize, buffer_size)
+            cut_and_paste_backward(self, content_len, offset.unsigned_abs(), buffer_size)
         }
     }
-}
\ No newline at end of file
+}
+
+/// Writes the whole of `bufs` one after another like [Write::write_all] would
+/// their concatenation, through [Write::write_vectored] so that writers taking
+/// several buffers at once, like files, do it in as few calls as they can.
+/// Writers taking fewer bytes than asked, down to the first buffer alone as
+/// the default does, are called again from where they stopped.
+pub(crate) fn write_all_vectored(fd: &mut dyn Write, mut bufs: &mut [IoSlice]) -> Result<(), Error> {
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match fd.write_vectored(bufs) {
+            Ok(0) => return Err(Error::from(ErrorKind::WriteZero)),
+            Ok(written) => IoSlice::advance_slices(&mut bufs, written),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ext::io::{write_all_vectored, MoveContent};
+    use crate::vio::RandomAccess;
+    use std::io::{self, Cursor, ErrorKind, IoSlice, SeekFrom, Write};
+
+    /// Takes at most `limit` bytes a call, across buffers if `vectored`
+    /// or only from the first one otherwise, failing the first call
+    /// as interrupted.
+    struct Trickle {
+        out: Vec<u8>,
+        limit: usize,
+        vectored: bool,
+        calls: usize,
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls == 1 {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            let taken = if self.vectored { bufs.len() } else { 1 };
+            let mut left = self.limit;
+            for buf in bufs.iter().filter(|buf| !buf.is_empty()).take(taken) {
+                let written = buf.len().min(left);
+                self.out.extend_from_slice(&buf[..written]);
+                left -= written;
+            }
+            Ok(self.limit - left)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn moved(offset: isize) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::from_iter(0..100u8));
+        let fd: &mut dyn RandomAccess = &mut cursor;
+        fd.seek(SeekFrom::Start(10)).unwrap();
+        fd.move_content(90, offset, 7).unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn move_forward_works() {
+        let out = moved(5);
+        assert_eq!(out.len(), 105);
+        assert_eq!(&out[15..], Vec::from_iter(10..100u8));
+        assert_eq!(&out[..10], Vec::from_iter(0..10u8));
+    }
+
+    #[test]
+    fn move_backward_works() {
+        let out = moved(-5);
+        assert_eq!(&out[5..95], Vec::from_iter(10..100u8));
+        assert_eq!(&out[..5], Vec::from_iter(0..5u8));
+    }
+
+    #[test]
+    fn move_before_start_fails() {
+        let mut cursor = Cursor::new(Vec::from_iter(0..100u8));
+        let fd: &mut dyn RandomAccess = &mut cursor;
+        fd.seek(SeekFrom::Start(10)).unwrap();
+        let e = fd.move_content(90, -11, 7).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        // nothing's been touched
+        assert_eq!(cursor.into_inner(), Vec::from_iter(0..100u8));
+    }
+
+    #[test]
+    fn write_all_vectored_resumes() {
+        let chunks = [vec![1u8; 5], vec![], vec![2u8; 3], vec![3u8; 9]];
+        let expected = chunks.concat();
+        for (limit, vectored, calls) in [(100, true, 2), (4, true, 6), (4, false, 7)] {
+            let mut trickle = Trickle {
+                out: vec![],
+                limit,
+                vectored,
+                calls: 0,
+            };
+            let mut bufs = chunks.iter().map(|chunk| IoSlice::new(chunk)).collect::<Vec<_>>();
+            write_all_vectored(&mut trickle, &mut bufs).unwrap();
+            assert_eq!(trickle.out, expected);
+            assert_eq!(trickle.calls, calls, "limit {limit}, vectored {vectored}");
+        }
+
+        let mut full = Trickle {
+            out: vec![],
+            limit: 0,
+            vectored: true,
+            calls: 0,
+        };
+        let e = write_all_vectored(&mut full, &mut [IoSlice::new(&[1u8])]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WriteZero);
+    }
+}
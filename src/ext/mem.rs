@@ -0,0 +1,398 @@
+use crate::vio::{SyncData, Truncate};
+use std::io::{IoSlice, Read, Seek, SeekFrom, Write};
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{cmp, io};
+
+/// # Shared Cursor
+/// In-memory file that may be opened several times, like a path on disk.
+/// Clones share the bytes but keep their own position.
+#[derive(Clone, Default)]
+pub(crate) struct SharedCursor {
+    data: Arc<Mutex<Vec<u8>>>,
+    pos: u64,
+}
+
+impl SharedCursor {
+    pub(crate) fn new() -> SharedCursor {
+        SharedCursor::default()
+    }
+
+    /// Another handle to the same bytes, positioned at the start.
+    pub(crate) fn reopen(&self) -> SharedCursor {
+        SharedCursor {
+            data: self.data.clone(),
+            pos: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.data.lock().unwrap().len() as u64
+    }
+
+    pub(crate) fn bytes(&self) -> Vec<u8> {
+        self.data.lock().unwrap().clone()
+    }
+}
+
+impl Truncate for SharedCursor {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.data.lock().unwrap().truncate(len as usize);
+        Ok(())
+    }
+}
+
+impl SyncData for SharedCursor {}
+
+impl Read for SharedCursor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.lock().unwrap();
+        let begin = cmp::min(self.pos, data.len() as u64) as usize;
+        let read = cmp::min(buf.len(), data.len() - begin);
+        buf[..read].copy_from_slice(&data[begin..begin + read]);
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for SharedCursor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.data.lock().unwrap();
+        let begin = self.pos as usize;
+        if data.len() < begin + buf.len() {
+            data.resize(begin + buf.len(), 0);
+        }
+        data[begin..begin + buf.len()].copy_from_slice(buf);
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    /// All of them at once, like a file does.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let whole = bufs.iter().flat_map(|buf| buf.iter().copied()).collect::<Vec<_>>();
+        self.write(&whole)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SharedCursor {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.data.lock().unwrap().len() as i64;
+        let target = match pos {
+            SeekFrom::Start(p) => p as i64,
+            SeekFrom::End(d) => len + d,
+            SeekFrom::Current(d) => self.pos as i64 + d,
+        };
+        if target < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seeking before the start",
+            ));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+/// # Read Only
+/// Storage read through `R`, which only needs to [Read] and [Seek],
+/// failing every attempt to write or truncate it.
+pub(crate) struct ReadOnly<R>(pub(crate) R);
+
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "storage is read-only")
+}
+
+impl<R> Truncate for ReadOnly<R> {
+    fn set_len(&mut self, _: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl<R> SyncData for ReadOnly<R> {}
+
+impl<R: Read> Read for ReadOnly<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R> Write for ReadOnly<R> {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<R: Seek> Seek for ReadOnly<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// # Faulty Access
+/// In-memory file that only keeps what was synced when it crashes,
+/// like a disk losing its page cache on power loss.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct FaultyAccess {
+    volatile: SharedCursor,
+    durable: Arc<Mutex<Vec<u8>>>,
+    /// Bytes left to write before the machine goes down, if it's going to.
+    remaining: Arc<Mutex<Option<u64>>>,
+}
+
+#[cfg(test)]
+impl FaultyAccess {
+    pub(crate) fn new() -> FaultyAccess {
+        FaultyAccess::default()
+    }
+
+    pub(crate) fn reopen(&self) -> FaultyAccess {
+        FaultyAccess {
+            volatile: self.volatile.reopen(),
+            durable: self.durable.clone(),
+            remaining: self.remaining.clone(),
+        }
+    }
+
+    /// Lets `bytes` more bytes be written, all of which reach the disk,
+    /// and fails every write and sync after them, like a machine going
+    /// down in the middle of a write.
+    pub(crate) fn crash_after(&self, bytes: u64) {
+        *self.remaining.lock().unwrap() = Some(bytes);
+    }
+
+    fn crashed() -> io::Error {
+        io::Error::other("crashed")
+    }
+
+    /// What is left on disk after a crash, as a fresh file.
+    pub(crate) fn crash(&self) -> SharedCursor {
+        SharedCursor {
+            data: Arc::new(Mutex::new(self.durable.lock().unwrap().clone())),
+            pos: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+impl SyncData for FaultyAccess {
+    fn sync_data(&mut self) -> io::Result<()> {
+        if *self.remaining.lock().unwrap() == Some(0) {
+            return Err(FaultyAccess::crashed());
+        }
+        *self.durable.lock().unwrap() = SharedCursor::bytes(&self.volatile);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Truncate for FaultyAccess {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.volatile.set_len(len)
+    }
+}
+
+#[cfg(test)]
+impl Read for FaultyAccess {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.volatile.read(buf)
+    }
+}
+
+#[cfg(test)]
+impl Write for FaultyAccess {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut remaining = self.remaining.lock().unwrap();
+        let Some(left) = remaining.as_mut() else {
+            return self.volatile.write(buf);
+        };
+        if *left == 0 {
+            return Err(FaultyAccess::crashed());
+        }
+        let written = self.volatile.write(&buf[..cmp::min(buf.len() as u64, *left) as usize])?;
+        *left -= written as u64;
+        if *left == 0 {
+            *self.durable.lock().unwrap() = SharedCursor::bytes(&self.volatile);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.volatile.flush()
+    }
+}
+
+#[cfg(test)]
+impl Seek for FaultyAccess {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.volatile.seek(pos)
+    }
+}
+
+/// # Sparse Access
+/// File of zeros that claims to be `len` bytes long, taking writes without
+/// keeping them, for files too large to hold.
+#[cfg(test)]
+pub(crate) struct SparseAccess {
+    len: u64,
+    pos: u64,
+}
+
+#[cfg(test)]
+impl SparseAccess {
+    pub(crate) fn new(len: u64) -> SparseAccess {
+        SparseAccess { len, pos: 0 }
+    }
+}
+
+#[cfg(test)]
+impl SyncData for SparseAccess {}
+
+#[cfg(test)]
+impl Truncate for SparseAccess {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.len = len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Read for SparseAccess {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = cmp::min(buf.len() as u64, self.len.saturating_sub(self.pos)) as usize;
+        buf[..read].fill(0);
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+impl Write for SparseAccess {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.pos.checked_add(buf.len() as u64).ok_or(io::ErrorKind::InvalidInput)?;
+        self.len = cmp::max(self.len, end);
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Seek for SparseAccess {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(self.pos)
+    }
+}
+
+/// # Counting Access
+/// [SharedCursor] that counts how many times it was seeked and written to and
+/// how many bytes were read and written, for telling how much work went to the file.
+#[cfg(test)]
+#[derive(Clone)]
+pub(crate) struct CountingAccess {
+    inner: SharedCursor,
+    seeks: Arc<AtomicUsize>,
+    writes: Arc<AtomicUsize>,
+    read: Arc<AtomicUsize>,
+    written: Arc<AtomicUsize>,
+}
+
+#[cfg(test)]
+impl CountingAccess {
+    pub(crate) fn new(inner: SharedCursor) -> CountingAccess {
+        CountingAccess {
+            inner,
+            seeks: Arc::new(AtomicUsize::new(0)),
+            writes: Arc::new(AtomicUsize::new(0)),
+            read: Arc::new(AtomicUsize::new(0)),
+            written: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(crate) fn seeks(&self) -> usize {
+        self.seeks.load(Ordering::SeqCst)
+    }
+
+    /// Calls to write, one buffer or several at once alike.
+    pub(crate) fn writes(&self) -> usize {
+        self.writes.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn read(&self) -> usize {
+        self.read.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn written(&self) -> usize {
+        self.written.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn bytes(&self) -> Vec<u8> {
+        SharedCursor::bytes(&self.inner)
+    }
+}
+
+#[cfg(test)]
+impl SyncData for CountingAccess {}
+
+#[cfg(test)]
+impl Truncate for CountingAccess {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.inner.set_len(len)
+    }
+}
+
+#[cfg(test)]
+impl Read for CountingAccess {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.fetch_add(read, Ordering::SeqCst);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+impl Write for CountingAccess {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        let written = self.inner.write(buf)?;
+        self.written.fetch_add(written, Ordering::SeqCst);
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        let written = self.inner.write_vectored(bufs)?;
+        self.written.fetch_add(written, Ordering::SeqCst);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+impl Seek for CountingAccess {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.seeks.fetch_add(1, Ordering::SeqCst);
+        self.inner.seek(pos)
+    }
+}
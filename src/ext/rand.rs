@@ -0,0 +1,32 @@
+/// # XorShift
+/// A tiny seeded pseudo random generator, good enough for sampling
+/// and synthetic data while keeping results reproducible.
+#[derive(Clone)]
+pub(crate) struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub(crate) fn new(seed: u64) -> XorShift {
+        XorShift { state: seed.max(1) }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// Uniformly distributed in `[0, 1)`.
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Components uniformly distributed in `[0, 1)`.
+    pub(crate) fn vector(&mut self, dim_size: u32) -> Vec<f32> {
+        (0..dim_size).map(|_| self.next_f32()).collect()
+    }
+}
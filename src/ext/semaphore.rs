@@ -1,14 +1,387 @@
 use std::sync::{Mutex, MutexGuard};
 
 pub(crate) trait LockAutoClear<T> {
-    fn lock_auto_clear_poison<'a>(&mut self) -> MutexGuard<T>;
+    fn lock_auto_clear_poison(&self) -> MutexGuard<'_, T>;
 }
 
 impl<T> LockAutoClear<T> for Mutex<T> {
-    fn lock_auto_clear_poison<'a>(&mut self) -> MutexGuard<T> {
+    fn lock_auto_clear_poison(&self) -> MutexGuard<'_, T> {
         self.lock().unwrap_or_else(|_| {
             self.clear_poison();
             self.lock().unwrap()
         })
     }
 }
+
+/// # Lock Rank
+/// The locks a thread may hold more than one of at once, in the only order
+/// they're taken in, so that no two threads ever wait on each other: a
+/// lock is never waited on while one of a later rank is held.
+///
+/// Searches hold the index while they look up the records in the handle,
+/// and readers checked out of the pool hold the records, so writers let go
+/// of the handle and the records before they update the index or the caches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LockRank {
+    /// The database handle of a [ManagementSystem](crate::ms::ManagementSystem).
+    Registry,
+    /// The databases a [ManagementSystem](crate::ms::ManagementSystem) has loaded.
+    Loaded,
+    /// A database handed out by a [ManagementSystem](crate::ms::ManagementSystem).
+    /// Several of them are taken in order of address.
+    Database,
+    /// The index built in memory, and the level table read along with it.
+    Index,
+    /// The vector handle, through which the file is written.
+    Handle,
+    /// The records, held shared by pooled readers and exclusively by writers
+    /// moving them, see [StorageCoordinator](crate::db::storage::StorageCoordinator).
+    Records,
+    /// The vectors cached, cached responses and views, and the write queue.
+    Cache,
+}
+
+/// Takes the locks `a` and `b` by `lock_a` and `lock_b` in [LockRank] order,
+/// those of the same rank in order of address, and hands back their guards
+/// in the order of the arguments. Whatever two locks are taken at once go
+/// through here, so their order is decided in one place.
+pub(crate) fn lock_in_order<'a, A, B, GA, GB>(
+    (rank_a, a): (LockRank, &'a A),
+    (rank_b, b): (LockRank, &'a B),
+    lock_a: impl FnOnce(&'a A) -> GA,
+    lock_b: impl FnOnce(&'a B) -> GB,
+) -> (GA, GB) {
+    let address = |lock: *const ()| lock as usize;
+    let a_first = (rank_a, address((a as *const A).cast()))
+        <= (rank_b, address((b as *const B).cast()));
+    if a_first {
+        let a = lock_a(a);
+        (a, lock_b(b))
+    } else {
+        let b = lock_b(b);
+        (lock_a(a), b)
+    }
+}
+
+/// [lock_in_order] for two mutexes, clearing their poison.
+pub(crate) fn lock_pair<'a, A, B>(
+    a: (LockRank, &'a Mutex<A>),
+    b: (LockRank, &'a Mutex<B>),
+) -> (MutexGuard<'a, A>, MutexGuard<'a, B>) {
+    lock_in_order(
+        a,
+        b,
+        Mutex::lock_auto_clear_poison,
+        Mutex::lock_auto_clear_poison,
+    )
+}
+
+/// Models of the locks of a database and of a management system, taken the
+/// way they are in [Database](crate::db::Database) and
+/// [ManagementSystem](crate::ms::ManagementSystem), whose every interleaving
+/// loom explores. Run with
+/// `RUSTFLAGS="--cfg loom" cargo test --release --lib ext::semaphore`.
+#[cfg(all(test, loom))]
+mod tests {
+    use crate::ext::semaphore::{lock_in_order, LockRank};
+    use loom::model::Builder;
+    use loom::sync::atomic::{AtomicU64, Ordering};
+    use loom::sync::{Arc, Mutex, MutexGuard, RwLock};
+    use loom::thread;
+    use std::collections::BTreeMap;
+
+    fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+        mutex.lock().unwrap()
+    }
+
+    fn explore(model: impl Fn() + Sync + Send + 'static) {
+        let mut builder = Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(model);
+    }
+
+    /// What's left of a database: the ids written through the handle, the
+    /// same ids as pooled readers read them, the cache, the index and the
+    /// snapshot views share.
+    struct Db {
+        index: Mutex<Vec<u32>>,
+        handle: Mutex<Vec<u32>>,
+        records: RwLock<Vec<u32>>,
+        cache: Mutex<BTreeMap<u32, u32>>,
+        snapshot: Mutex<Option<(u64, Arc<Vec<u32>>)>>,
+        revision: AtomicU64,
+        hits: AtomicU64,
+    }
+
+    impl Db {
+        fn new() -> Db {
+            Db::open(vec![])
+        }
+
+        fn open(ids: Vec<u32>) -> Db {
+            Db {
+                index: Mutex::new(ids.clone()),
+                handle: Mutex::new(ids.clone()),
+                records: RwLock::new(ids),
+                cache: Mutex::new(BTreeMap::new()),
+                snapshot: Mutex::new(None),
+                revision: AtomicU64::new(0),
+                hits: AtomicU64::new(0),
+            }
+        }
+
+        /// Database::lock_records
+        fn write(&self, change: impl FnOnce(&mut Vec<u32>)) {
+            let (mut handle, mut records) = lock_in_order(
+                (LockRank::Handle, &self.handle),
+                (LockRank::Records, &self.records),
+                |handle| lock(handle),
+                |records| records.write().unwrap(),
+            );
+            change(&mut handle);
+            records.clone_from(&handle);
+            self.revision.fetch_add(1, Ordering::Relaxed);
+        }
+
+        /// Database::get through a pooled reader
+        fn get(&self, id: u32) -> Option<u32> {
+            if let Some(cached) = lock(&self.cache).get(&id) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(*cached);
+            }
+            let found = self.records.read().unwrap().contains(&id).then_some(id);
+            if let Some(found) = found {
+                lock(&self.cache).insert(id, found);
+            }
+            found
+        }
+
+        /// Database::drain_queue
+        fn push(&self, id: u32) {
+            self.write(|ids| ids.push(id));
+            lock(&self.cache).insert(id, id);
+            lock(&self.index).push(id);
+            // views taken in between lack it in their index
+            *lock(&self.snapshot) = None;
+        }
+
+        /// Database::remove_many
+        fn remove(&self, id: u32) {
+            self.write(|ids| ids.retain(|stored| *stored != id));
+            let (mut index, mut cache) = lock_in_order(
+                (LockRank::Index, &self.index),
+                (LockRank::Cache, &self.cache),
+                |index| lock(index),
+                |cache| lock(cache),
+            );
+            index.retain(|node| *node != id);
+            cache.remove(&id);
+        }
+
+        /// Database::query_with_scratch, looking up every node in the handle
+        fn search(&self) -> Vec<u32> {
+            let index = lock(&self.index);
+            index
+                .iter()
+                .copied()
+                .filter(|id| lock(&self.handle).contains(id))
+                .collect()
+        }
+
+        /// Database::flush, staged with neither held, then written
+        fn flush(&self) -> usize {
+            let revision = self.revision.load(Ordering::Relaxed);
+            let staged = lock(&self.index).len();
+            let mut handle = lock(&self.handle);
+            if self.revision.load(Ordering::Relaxed) == revision {
+                handle.sort();
+            }
+            staged
+        }
+
+        /// Database::read_view, swapping in a snapshot of a newer revision
+        fn read_view(&self) -> Arc<Vec<u32>> {
+            let (index, handle) = lock_in_order(
+                (LockRank::Index, &self.index),
+                (LockRank::Handle, &self.handle),
+                |index| lock(index),
+                |handle| lock(handle),
+            );
+            let revision = self.revision.load(Ordering::Relaxed);
+            let mut snapshot = lock(&self.snapshot);
+            match snapshot.as_ref() {
+                Some((taken, view)) if *taken == revision => view.clone(),
+                _ => {
+                    let view = index.iter().copied().filter(|id| handle.contains(id));
+                    let view = Arc::new(view.collect::<Vec<_>>());
+                    *snapshot = Some((revision, view.clone()));
+                    view
+                }
+            }
+        }
+    }
+
+    /// What's left of a management system, over files taken last
+    /// and on their own, like the bytes of a file are.
+    struct Ms {
+        registry: Mutex<()>,
+        loaded: Mutex<BTreeMap<&'static str, Arc<Mutex<Db>>>>,
+        files: Mutex<BTreeMap<&'static str, Vec<u32>>>,
+    }
+
+    impl Ms {
+        fn new() -> Ms {
+            Ms {
+                registry: Mutex::new(()),
+                loaded: Mutex::new(BTreeMap::new()),
+                files: Mutex::new(BTreeMap::new()),
+            }
+        }
+
+        /// ManagementSystem::get_with, loading the database if it isn't
+        fn get(&self, name: &'static str) -> Arc<Mutex<Db>> {
+            let (_registry, mut loaded) = lock_in_order(
+                (LockRank::Registry, &self.registry),
+                (LockRank::Loaded, &self.loaded),
+                |registry| lock(registry),
+                |loaded| lock(loaded),
+            );
+            loaded
+                .entry(name)
+                .or_insert_with(|| {
+                    let ids = lock(&self.files).get(name).cloned().unwrap_or_default();
+                    Arc::new(Mutex::new(Db::open(ids)))
+                })
+                .clone()
+        }
+
+        /// ManagementSystem::keep_within_budget unloading the database,
+        /// flushed first, unless it's referenced elsewhere
+        fn close(&self, name: &'static str) -> bool {
+            let mut loaded = lock(&self.loaded);
+            let Some(db) = loaded.get(name) else {
+                return false;
+            };
+            if Arc::strong_count(db) > 1 {
+                return false;
+            }
+            let db = lock(db);
+            db.flush();
+            lock(&self.files).insert(name, lock(&db.handle).clone());
+            drop(db);
+            loaded.remove(name);
+            true
+        }
+
+        /// ManagementSystem::copy_vectors
+        fn copy(&self, from: &'static str, to: &'static str, id: u32) {
+            let (source, target) = (self.get(from), self.get(to));
+            let (source, target) = lock_in_order(
+                (LockRank::Database, &*source),
+                (LockRank::Database, &*target),
+                |source| lock(source),
+                |target| lock(target),
+            );
+            if source.get(id).is_some() {
+                target.push(id);
+            }
+        }
+    }
+
+    #[test]
+    fn get_and_push_work() {
+        explore(|| {
+            let db = Arc::new(Db::new());
+            let pusher = {
+                let db = db.clone();
+                thread::spawn(move || {
+                    db.push(1);
+                    db.push(2);
+                })
+            };
+            let first = db.get(1);
+            let second = db.get(2);
+            assert!(first.is_none_or(|id| id == 1));
+            assert!(second.is_none_or(|id| id == 2));
+            pusher.join().unwrap();
+            assert_eq!((db.get(1), db.get(2)), (Some(1), Some(2)));
+            assert_eq!(db.revision.load(Ordering::Relaxed), 2);
+        });
+    }
+
+    #[test]
+    fn get_and_remove_work() {
+        explore(|| {
+            let db = Arc::new(Db::new());
+            db.push(1);
+            db.push(2);
+            let remover = {
+                let db = db.clone();
+                thread::spawn(move || db.remove(1))
+            };
+            assert_eq!(db.get(2), Some(2));
+            db.get(1);
+            remover.join().unwrap();
+            assert_eq!(*lock(&db.index), vec![2]);
+            assert_eq!(db.search(), vec![2]);
+            assert!(db.hits.load(Ordering::Relaxed) >= 1);
+        });
+    }
+
+    #[test]
+    fn flush_and_search_work() {
+        explore(|| {
+            let db = Arc::new(Db::new());
+            db.push(3);
+            let writer = {
+                let db = db.clone();
+                thread::spawn(move || {
+                    db.push(1);
+                    db.flush();
+                })
+            };
+            let found = db.search();
+            assert!(found.contains(&3));
+            let view = db.read_view();
+            assert!(view.contains(&3));
+            writer.join().unwrap();
+            let view = db.read_view();
+            assert_eq!(*view, db.search());
+            assert!(view.contains(&1));
+        });
+    }
+
+    #[test]
+    fn ms_get_and_close_work() {
+        explore(|| {
+            let ms = Arc::new(Ms::new());
+            lock(&ms.get("a")).push(1);
+            let closer = {
+                let ms = ms.clone();
+                thread::spawn(move || ms.close("a"))
+            };
+            let db = ms.get("a");
+            lock(&db).push(2);
+            drop(db);
+            closer.join().unwrap();
+            assert!(lock(&ms.get("a")).get(2).is_some());
+        });
+    }
+
+    #[test]
+    fn ms_copies_both_ways() {
+        explore(|| {
+            let ms = Arc::new(Ms::new());
+            lock(&ms.get("a")).push(1);
+            lock(&ms.get("b")).push(2);
+            let copier = {
+                let ms = ms.clone();
+                thread::spawn(move || ms.copy("a", "b", 1))
+            };
+            ms.copy("b", "a", 2);
+            copier.join().unwrap();
+            assert_eq!(lock(&ms.get("a")).search(), vec![1, 2]);
+            assert_eq!(lock(&ms.get("b")).search(), vec![2, 1]);
+        });
+    }
+}
@@ -0,0 +1,1020 @@
+use crate::algorithm::construct::{link, random_level, DistanceCache, MAX_LEVEL};
+use crate::algorithm::search::{search_layer, search_layer_in, BestFirst, CandidateSource, Scorer};
+use crate::db::{DbIndex, DbVector, DbVectorSlice, Error, GrowthPolicy, HnswConfig, LayerTrace};
+use crate::ds::graph::{AnyCastNdGraph, Graph, NdGraph};
+use crate::ds::layer::HnswLayer;
+use crate::ext::rand::XorShift;
+use crate::metric::Metric;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::max;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "rayon")]
+mod parallel;
+mod scratch;
+mod shortcut;
+
+pub use scratch::SearchScratch;
+pub(crate) use shortcut::ShortcutCache;
+
+const MAGIC: &[u8; 4] = b"HNS2";
+const GRAPH_MAGIC: &[u8; 4] = b"HNG2";
+/// Magic of indices written before the whole [HnswConfig] was,
+/// which are read with the rest of it left to the defaults.
+const LEGACY_MAGIC: &[u8; 4] = b"HNSW";
+const LEGACY_GRAPH_MAGIC: &[u8; 4] = b"HNSG";
+/// Nodes are numbered by insertion, so every layer the index builds is dense.
+const DENSE: &str = "index layers are dense";
+
+/// # HNSW Index
+/// Hierarchical navigable small world graph over vectors held in memory,
+/// answering approximate nearest neighbor queries without a file behind it.
+/// A [Database](crate::db::Database) builds on the same type once it's
+/// [indexed](crate::db::Database::build_index).
+///
+/// Removed vectors stay in the graph to keep it navigable,
+/// and are only left out of the results.
+#[derive(Clone)]
+pub struct HnswIndex {
+    dim_size: u32,
+    metric: Metric,
+    config: HnswConfig,
+    rng: XorShift,
+    /// By node, numbered in the order they were inserted.
+    vectors: Vec<DbVector>,
+    ids: Vec<DbIndex>,
+    levels: Vec<u32>,
+    /// Node each id present is stored at.
+    nodes: HashMap<DbIndex, u32>,
+    /// From the base layer up.
+    layers: Vec<HnswLayer>,
+    entry: Option<u32>,
+    stats: BuildStats,
+}
+
+/// # Build Statistics
+/// Work done inserting into an [HnswIndex] so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BuildStats {
+    /// Distances actually computed, cache misses included.
+    pub distance_evaluations: u64,
+    /// Both stay zero unless the [distance cache](HnswConfig::distance_cache) is on.
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+/// What a search found and how, see [HnswIndex::search_traced].
+pub(crate) struct IndexSearch {
+    pub results: Vec<(DbIndex, f32)>,
+    /// Number of vectors compared against the query.
+    pub visited: usize,
+    /// Closest nodes left out of the results for not being present.
+    pub dangling: usize,
+    /// From the topmost layer down.
+    pub layers: Vec<LayerTrace>,
+}
+
+impl HnswIndex {
+    pub fn new(dim_size: u32, metric: Metric, config: HnswConfig) -> HnswIndex {
+        HnswIndex {
+            dim_size,
+            metric,
+            config,
+            rng: XorShift::new(0),
+            vectors: vec![],
+            ids: vec![],
+            levels: vec![],
+            nodes: HashMap::new(),
+            layers: vec![],
+            entry: None,
+            stats: BuildStats::default(),
+        }
+    }
+
+    /// Draws the levels of inserted vectors by `seed`, so that the
+    /// same insertions always build the same graph.
+    pub fn with_seed(mut self, seed: u64) -> HnswIndex {
+        self.rng = XorShift::new(seed);
+        self
+    }
+
+    pub fn dim_size(&self) -> u32 {
+        self.dim_size
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    pub fn config(&self) -> HnswConfig {
+        self.config
+    }
+
+    /// Makes room for `additional` vectors on the base layer at once,
+    /// so that loading a known number of them doesn't grow it bit by bit.
+    /// No more is reserved than the nodes a layer can number.
+    pub fn reserve(&mut self, additional: usize) {
+        let additional = additional.min(u32::MAX as usize - self.len().min(u32::MAX as usize));
+        self.vectors.reserve(additional);
+        self.ids.reserve(additional);
+        self.levels.reserve(additional);
+        if self.layers.is_empty() {
+            let graph = NdGraph::new().with_growth(self.config.growth);
+            self.layers.push(HnswLayer::new(graph, 0));
+        }
+        self.layers[0]
+            .dense_mut()
+            .expect(DENSE)
+            .reserve(additional as u32);
+    }
+
+    /// Bytes its vectors, layers and tables take on the heap, roughly.
+    pub(crate) fn heap_bytes(&self) -> u64 {
+        let vectors = self
+            .vectors
+            .iter()
+            .map(|vector| vector.capacity() * size_of::<f32>())
+            .sum::<usize>();
+        let tables = self.vectors.capacity() * size_of::<DbVector>()
+            + (self.ids.capacity() + self.levels.capacity()) * size_of::<u32>()
+            + self.nodes.capacity() * size_of::<(DbIndex, u32)>();
+        let layers = self.layers.iter().map(HnswLayer::heap_bytes).sum::<u64>();
+        (vectors + tables) as u64 + layers
+    }
+
+    /// Number of vectors present, not counting removed ones.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, id: DbIndex) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    pub fn get(&self, id: DbIndex) -> Option<DbVectorSlice<'_>> {
+        self.nodes.get(&id).map(|node| &self.vectors[*node as usize][..])
+    }
+
+    pub fn build_stats(&self) -> BuildStats {
+        self.stats
+    }
+
+    pub(crate) fn layers(&self) -> &[HnswLayer] {
+        &self.layers
+    }
+
+    /// Copies of its layers the way the file keeps them, their nodes being
+    /// the ids of the vectors they stand for.
+    pub(crate) fn stored_layers(&self) -> Vec<HnswLayer> {
+        self.layers
+            .iter()
+            .map(|layer| {
+                let level = layer.level();
+                let id = |node| self.id_at(node).filter(|_| self.level_of(node) >= level);
+                let mut graph = AnyCastNdGraph::new();
+                for node in layer.nodes() {
+                    let Some(from) = id(node) else {
+                        continue;
+                    };
+                    graph.get_mapping_or_insert(from);
+                    for (neighbor, distance) in layer.vertices(node) {
+                        if let Some(to) = id(neighbor).filter(|to| from < *to) {
+                            graph.connect(from, to, distance).unwrap();
+                        }
+                    }
+                }
+                HnswLayer::anycast(graph, level)
+            })
+            .collect()
+    }
+
+    /// Distance between the vectors stored at nodes `a` and `b`.
+    pub(crate) fn node_distance(&self, a: u32, b: u32) -> f32 {
+        self.metric
+            .distance(&self.vectors[a as usize], &self.vectors[b as usize])
+    }
+
+    /// Bytes on the heap an index of `nodes` vectors of `dim_size` takes
+    /// outside its layers, its tables holding as many as there are.
+    pub(crate) fn projected_table_bytes(nodes: u64, dim_size: u32) -> u64 {
+        let vector = dim_size as usize * size_of::<f32>() + size_of::<DbVector>();
+        let tables = 2 * size_of::<u32>() + size_of::<(DbIndex, u32)>();
+        nodes * (vector + tables) as u64
+    }
+
+    /// Vectors a search keeping `ef` candidates for `k` results is expected
+    /// to compare against, being the neighbors of every candidate on the base
+    /// layer and those of one node on each layer above, but no more than there are.
+    pub(crate) fn expected_visited(&self, k: usize, ef: usize) -> u64 {
+        let (m, m0) = (self.config.m as u64, self.config.max_degree(0) as u64);
+        let upper = self.layers.len().saturating_sub(1) as u64 * m;
+        (max(ef, k) as u64 * m0 + upper).min(self.vectors.len() as u64)
+    }
+
+    fn is_live(&self, node: u32) -> bool {
+        self.nodes.get(&self.ids[node as usize]) == Some(&node)
+    }
+
+    /// Adds `vector` under `id`, replacing whatever was there.
+    pub fn insert(&mut self, id: DbIndex, vector: DbVectorSlice) -> Result<(), Error> {
+        if vector.len() != self.dim_size as usize {
+            return Err(Error::Dimension(self.dim_size, vector.len()));
+        }
+        self.remove(id);
+        let node = self.vectors.len() as u32;
+        let level = random_level(&mut self.rng, self.config.level_multiplier());
+        self.vectors.push(DbVector::from(vector));
+        self.ids.push(id);
+        self.levels.push(level);
+        self.nodes.insert(id, node);
+        for layer in &mut self.layers {
+            layer.dense_mut().expect(DENSE).push_one();
+        }
+        while self.layers.len() <= level as usize {
+            let mut graph = NdGraph::with_capacity(node + 1).with_growth(self.config.growth);
+            graph.push_many(node + 1);
+            self.layers
+                .push(HnswLayer::new(graph, self.layers.len() as u32));
+        }
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return Ok(());
+        };
+        let top = self.levels[entry as usize];
+        let (vectors, metric) = (&self.vectors, self.metric);
+        let evaluations = Cell::new(0);
+        let cache = RefCell::new(self.config.distance_cache.then(DistanceCache::default));
+        let between = |a: u32, b: u32| {
+            let compute = || {
+                evaluations.set(evaluations.get() + 1);
+                metric.distance(&vectors[a as usize], &vectors[b as usize])
+            };
+            match cache.borrow_mut().as_mut() {
+                Some(cache) => cache.get_or_compute(a, b, compute),
+                None => compute(),
+            }
+        };
+        let distance = |other: u32| between(node, other);
+        let mut nearest = vec![(entry, distance(entry))];
+        for level in (level + 1..=top).rev() {
+            let graph = self.layers[level as usize].dense().expect(DENSE);
+            nearest = search_layer(graph, &nearest, 1, &mut None, distance).nearest;
+        }
+        let (m, ef) = (self.config.m as usize, self.config.ef_construction as usize);
+        let diverse = self.config.diverse_neighbors;
+        for level in (0..=level.min(top)).rev() {
+            let max_degree = self.config.max_degree(level);
+            let graph = self.layers[level as usize].dense_mut().expect(DENSE);
+            let found = search_layer(graph, &nearest, ef, &mut None, distance).nearest;
+            link(graph, node, &found, (m, max_degree), diverse, between);
+            nearest = found;
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+        self.stats.distance_evaluations += evaluations.get();
+        if let Some(cache) = cache.into_inner() {
+            self.stats.cache_hits += cache.hits;
+            self.stats.cache_misses += cache.misses;
+        }
+        Ok(())
+    }
+
+    /// Takes vector `id` out of the results, returning whether it was there.
+    pub fn remove(&mut self, id: DbIndex) -> bool {
+        self.nodes.remove(&id).is_some()
+    }
+
+    /// Connects layer `level` anew as if it were lost, leaving the others as
+    /// they are. Nodes keep the level they were assigned on insertion, and
+    /// start from their neighbors on the layer below along with what a
+    /// search through the layers above finds.
+    pub fn rebuild_layer(&mut self, level: u32) -> Result<(), Error> {
+        if level as usize >= self.layers.len() {
+            return Err(Error::NoLayer(level));
+        }
+        let len = self.vectors.len() as u32;
+        let mut graph = NdGraph::with_capacity(len).with_growth(self.config.growth);
+        graph.push_many(len);
+        let members = (0..len)
+            .filter(|node| self.levels[*node as usize] >= level)
+            .collect::<Vec<_>>();
+        let (m, ef) = (self.config.m as usize, self.config.ef_construction as usize);
+        let (max_degree, diverse) = (self.config.max_degree(level), self.config.diverse_neighbors);
+        let between = |a: u32, b: u32| self.node_distance(a, b);
+        for node in members.iter().copied() {
+            let distance = |other: u32| between(node, other);
+            let mut seeds = vec![];
+            if let Some(entry) = self.entry {
+                let mut nearest = vec![(entry, distance(entry))];
+                for upper in self.layers[level as usize + 1..].iter().rev() {
+                    let graph = upper.dense().expect(DENSE);
+                    nearest = search_layer(graph, &nearest, 1, &mut None, distance).nearest;
+                }
+                seeds.extend(nearest.into_iter().map(|(n, _)| n));
+            }
+            if let Some(below) = level.checked_sub(1) {
+                seeds.extend(
+                    self.layers[below as usize]
+                        .vertices(node)
+                        .into_iter()
+                        .map(|(n, _)| n)
+                        .filter(|n| self.levels[*n as usize] >= level),
+                );
+            }
+            seeds.retain(|n| *n != node);
+            seeds.sort_unstable();
+            seeds.dedup();
+            if seeds.is_empty() {
+                continue;
+            }
+            let seeds = seeds.into_iter().map(|n| (n, distance(n))).collect::<Vec<_>>();
+            let found = search_layer(&graph, &seeds, ef, &mut None, distance).nearest;
+            link(&mut graph, node, &found, (m, max_degree), diverse, between);
+        }
+        self.layers[level as usize] = HnswLayer::new(graph, level);
+        Ok(())
+    }
+
+    /// Approximately the `k` vectors closest to `query`, closest first,
+    /// keeping as many candidates as the configured `ef_search`.
+    pub fn search(&self, query: DbVectorSlice, k: usize) -> Result<Vec<(DbIndex, f32)>, Error> {
+        self.search_with(query, k, self.config.ef_search as usize)
+    }
+
+    /// Same as [HnswIndex::search], keeping `ef` candidates instead.
+    /// Larger values trade speed for recall.
+    pub fn search_with(
+        &self,
+        query: DbVectorSlice,
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<(DbIndex, f32)>, Error> {
+        Ok(self.search_traced(query, k, ef, None, None, None)?.results)
+    }
+
+    /// Searches comparing against at most `max_visited` vectors if given,
+    /// and records what was visited on the way. With `shortcuts`, the search
+    /// starts on layer 1 where a similar query landed if that's closer than
+    /// the entry, leaving the layers above out of the trace, and remembers
+    /// where it lands itself. With `present`, the closest ids it's false for
+    /// are left out of the results and counted as dangling, in case the
+    /// vectors behind them are gone while the index wasn't told.
+    pub(crate) fn search_traced(
+        &self,
+        query: DbVectorSlice,
+        k: usize,
+        ef: usize,
+        max_visited: Option<u64>,
+        shortcuts: Option<&mut ShortcutCache>,
+        present: Option<&mut dyn FnMut(DbIndex) -> bool>,
+    ) -> Result<IndexSearch, Error> {
+        let mut scratch = SearchScratch::new();
+        scratch.layer.trace = true;
+        let hooks = (&mut BestFirst, &self.metric);
+        let bounds = (k, ef, max_visited);
+        self.search_in(query, bounds, shortcuts, present, hooks, &mut scratch)
+    }
+
+    /// Same as [HnswIndex::search_traced], expanding the nodes `source` picks
+    /// and measuring them by `scorer`, and working in `scratch`, which only
+    /// records what was visited if it's asked to trace.
+    pub(crate) fn search_in<C: CandidateSource + ?Sized, S: Scorer + ?Sized>(
+        &self,
+        query: DbVectorSlice,
+        (k, ef, max_visited): (usize, usize, Option<u64>),
+        mut shortcuts: Option<&mut ShortcutCache>,
+        mut present: Option<&mut dyn FnMut(DbIndex) -> bool>,
+        (source, scorer): (&mut C, &S),
+        scratch: &mut SearchScratch,
+    ) -> Result<IndexSearch, Error> {
+        if query.len() != self.dim_size as usize {
+            return Err(Error::Dimension(self.dim_size, query.len()));
+        }
+        let mut search = IndexSearch {
+            results: Vec::with_capacity(k),
+            visited: 0,
+            dangling: 0,
+            layers: vec![],
+        };
+        let Some(entry) = self.entry else {
+            return Ok(search);
+        };
+        if max_visited == Some(0) {
+            return Ok(search);
+        }
+        let mut budget = max_visited.map(|max| max - 1);
+        let distance = |node: u32| scorer.score(query, &self.vectors[node as usize]);
+        // ties break by id, the same as scanning
+        let rank = |node: u32| self.ids[node as usize];
+        let SearchScratch {
+            layer: scratch,
+            entries: nearest,
+        } = scratch;
+        nearest.clear();
+        nearest.push((entry, distance(entry)));
+        search.visited = 1;
+        let mut layers = &self.layers[..];
+        let key = ShortcutCache::key(query);
+        if let Some(cache) = shortcuts.as_deref_mut().filter(|_| layers.len() > 2) {
+            let shortcut = cache
+                .get(key)
+                .filter(|node| self.levels.get(*node as usize).is_some_and(|l| *l >= 1));
+            match shortcut.map(|node| (node, distance(node))) {
+                Some(shortcut) if budget != Some(0) => {
+                    search.visited += 1;
+                    budget = budget.map(|b| b - 1);
+                    if shortcut.1 <= nearest[0].1 {
+                        nearest[0] = shortcut;
+                        layers = &self.layers[..2];
+                        cache.counts.hit();
+                    } else {
+                        cache.counts.miss();
+                    }
+                }
+                _ => cache.counts.miss(),
+            }
+        }
+        for layer in layers.iter().rev() {
+            let ef = if layer.level() == 0 { max(ef, k) } else { 1 };
+            let graph = layer.dense().expect(DENSE);
+            let ef = (ef, &mut budget);
+            let visited = search_layer_in(graph, nearest, ef, distance, rank, source, scratch);
+            search.visited += visited - nearest.len();
+            if scratch.trace {
+                search.layers.push(LayerTrace {
+                    level: layer.level(),
+                    visited: scratch.visited.iter().map(|n| self.ids[*n as usize]).collect(),
+                    candidates: self.live_ids(&scratch.nearest).collect(),
+                });
+            }
+            std::mem::swap(nearest, &mut scratch.nearest);
+            if let (1, Some(cache), Some((landed, _))) =
+                (layer.level(), shortcuts.as_deref_mut(), nearest.first())
+            {
+                cache.insert(key, *landed);
+            }
+        }
+        for (id, distance) in self.live_ids(nearest) {
+            if search.results.len() == k {
+                break;
+            }
+            if present.as_mut().is_some_and(|present| !present(id)) {
+                search.dangling += 1;
+            } else {
+                search.results.push((id, distance));
+            }
+        }
+        Ok(search)
+    }
+
+    /// Ids of the nodes not removed from the index, whether their vectors
+    /// are still stored or not.
+    pub(crate) fn ids(&self) -> impl Iterator<Item = DbIndex> + '_ {
+        self.nodes.keys().copied()
+    }
+
+    /// Nodes ever inserted, removed ones included.
+    pub(crate) fn slots(&self) -> u32 {
+        self.ids.len() as u32
+    }
+
+    /// Top layer `node` is on.
+    pub(crate) fn level_of(&self, node: u32) -> u32 {
+        self.levels[node as usize]
+    }
+
+    /// Top layer the node of `id` is on, unless it's not in the index.
+    pub(crate) fn level(&self, id: DbIndex) -> Option<u32> {
+        self.nodes.get(&id).map(|node| self.levels[*node as usize])
+    }
+
+    /// Node standing for `id`, unless it's not in the index.
+    pub(crate) fn node_of(&self, id: DbIndex) -> Option<u32> {
+        self.nodes.get(&id).copied()
+    }
+
+    /// Id stored at `node` unless it's been removed or replaced since.
+    pub(crate) fn id_at(&self, node: u32) -> Option<DbIndex> {
+        self.is_live(node).then(|| self.ids[node as usize])
+    }
+
+    fn live_ids<'a>(
+        &'a self,
+        nodes: &'a [(u32, f32)],
+    ) -> impl Iterator<Item = (DbIndex, f32)> + 'a {
+        nodes
+            .iter()
+            .filter(|(node, _)| self.is_live(*node))
+            .map(|(node, distance)| (self.ids[*node as usize], *distance))
+    }
+
+    /// Writes the vectors and the graph, removed vectors included,
+    /// so that [HnswIndex::deserialize] gets the same index back.
+    pub fn serialize(&self, fd: &mut impl Write) -> io::Result<()> {
+        self.write(fd, MAGIC)
+    }
+
+    /// Same as [HnswIndex::serialize] but for the vectors of the nodes not
+    /// removed, which [HnswIndex::deserialize_graph] looks up elsewhere.
+    pub(crate) fn serialize_graph(&self, fd: &mut impl Write) -> io::Result<()> {
+        self.write(fd, GRAPH_MAGIC)
+    }
+
+    fn write(&self, fd: &mut impl Write, magic: &[u8; 4]) -> io::Result<()> {
+        fd.write_all(magic)?;
+        fd.write_u32::<BigEndian>(self.dim_size)?;
+        fd.write_u8(self.metric.to_byte())?;
+        write_config(fd, &self.config)?;
+        fd.write_u32::<BigEndian>(self.vectors.len() as u32)?;
+        fd.write_u32::<BigEndian>(self.entry.unwrap_or(u32::MAX))?;
+        for (node, vector) in self.vectors.iter().enumerate() {
+            fd.write_u32::<BigEndian>(self.ids[node])?;
+            fd.write_u32::<BigEndian>(self.levels[node])?;
+            let live = self.is_live(node as u32);
+            fd.write_u8(live as u8)?;
+            if live && magic == GRAPH_MAGIC {
+                continue;
+            }
+            for component in vector {
+                fd.write_f32::<BigEndian>(*component)?;
+            }
+        }
+        for layer in &self.layers {
+            for node in 0..self.vectors.len() as u32 {
+                if self.levels[node as usize] < layer.level() {
+                    continue;
+                }
+                let vertices = layer.vertices(node);
+                fd.write_u32::<BigEndian>(vertices.len() as u32)?;
+                for (neighbor, distance) in vertices {
+                    fd.write_u32::<BigEndian>(neighbor)?;
+                    fd.write_f32::<BigEndian>(distance)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn deserialize(fd: &mut impl Read) -> Result<HnswIndex, Error> {
+        HnswIndex::read(fd, None)
+    }
+
+    /// Reads what [HnswIndex::serialize_graph] wrote, taking the vector of
+    /// every node not removed from `lookup` by its id.
+    pub(crate) fn deserialize_graph(
+        fd: &mut impl Read,
+        lookup: &mut dyn FnMut(DbIndex) -> Result<DbVector, Error>,
+    ) -> Result<HnswIndex, Error> {
+        HnswIndex::read(fd, Some(lookup))
+    }
+
+    fn read(
+        fd: &mut impl Read,
+        mut lookup: Option<&mut dyn FnMut(DbIndex) -> Result<DbVector, Error>>,
+    ) -> Result<HnswIndex, Error> {
+        let mut magic = [0u8; MAGIC.len()];
+        fd.read_exact(&mut magic).map_err(parse_error)?;
+        let (current, legacy) = match lookup {
+            Some(_) => (GRAPH_MAGIC, LEGACY_GRAPH_MAGIC),
+            None => (MAGIC, LEGACY_MAGIC),
+        };
+        if &magic != current && &magic != legacy {
+            return Err(Error::Parse());
+        }
+        let dim_size = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+        let metric = Metric::from_byte(fd.read_u8().map_err(parse_error)?).ok_or(Error::Parse())?;
+        let config = read_config(fd, &magic == legacy)?;
+        let len = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+        let entry = Some(fd.read_u32::<BigEndian>().map_err(parse_error)?).filter(|e| *e < len);
+        if entry.is_none() && len > 0 {
+            return Err(Error::Parse());
+        }
+
+        let mut index = HnswIndex::new(dim_size, metric, config).with_seed(len as u64);
+        for node in 0..len {
+            let id = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+            let level = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+            if level > MAX_LEVEL {
+                return Err(Error::Parse());
+            }
+            let live = fd.read_u8().map_err(parse_error)? != 0;
+            if live {
+                index.nodes.insert(id, node);
+            }
+            let vector = match lookup.as_mut().filter(|_| live) {
+                Some(lookup) => {
+                    let vector = lookup(id)?;
+                    if vector.len() != dim_size as usize {
+                        return Err(Error::Dimension(dim_size, vector.len()));
+                    }
+                    vector
+                }
+                None => read_vector(fd, dim_size)?,
+            };
+            index.vectors.push(vector);
+            index.ids.push(id);
+            index.levels.push(level);
+        }
+        let top = entry.map_or(0, |entry| index.levels[entry as usize] + 1);
+        // the entry is where searches start, so it's on every layer
+        if index.levels.iter().any(|level| *level >= top) {
+            return Err(Error::Parse());
+        }
+        for level in 0..top {
+            let mut graph = NdGraph::with_capacity(len).with_growth(config.growth);
+            graph.push_many(len);
+            for node in 0..len {
+                if index.levels[node as usize] < level {
+                    continue;
+                }
+                for _ in 0..fd.read_u32::<BigEndian>().map_err(parse_error)? {
+                    let neighbor = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+                    let distance = fd.read_f32::<BigEndian>().map_err(parse_error)?;
+                    graph.connect(node, neighbor, distance).map_err(|_| Error::Parse())?;
+                }
+            }
+            index.layers.push(HnswLayer::new(graph, level));
+        }
+        index.entry = entry;
+        Ok(index)
+    }
+}
+
+/// Running out of bytes is a malformed index rather than a failed read.
+fn parse_error(e: io::Error) -> Error {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Parse(),
+        _ => Error::IO(e),
+    }
+}
+
+/// Reads `dim_size` components as they come, so that a size larger than
+/// what's left fails with [Error::Parse] before taking all that memory.
+fn read_vector(fd: &mut impl Read, dim_size: u32) -> Result<DbVector, Error> {
+    let len = dim_size as u64 * size_of::<f32>() as u64;
+    let mut bytes = vec![];
+    fd.by_ref().take(len).read_to_end(&mut bytes).map_err(Error::IO)?;
+    if bytes.len() as u64 != len {
+        return Err(Error::Parse());
+    }
+    Ok(bytes
+        .chunks_exact(size_of::<f32>())
+        .map(|component| f32::from_be_bytes(component.try_into().unwrap()))
+        .collect())
+}
+
+/// Every field of `config`, the absent ones as zeros.
+fn write_config(fd: &mut impl Write, config: &HnswConfig) -> io::Result<()> {
+    fd.write_u32::<BigEndian>(config.m)?;
+    fd.write_u32::<BigEndian>(config.m0.unwrap_or(0))?;
+    fd.write_u32::<BigEndian>(config.ef_construction)?;
+    fd.write_u32::<BigEndian>(config.ef_search)?;
+    fd.write_u8(config.distance_cache as u8)?;
+    let (growth, chunk) = match config.growth {
+        GrowthPolicy::Exact => (0, 0),
+        GrowthPolicy::Geometric => (1, 0),
+        GrowthPolicy::Chunked(chunk) => (2, chunk),
+    };
+    fd.write_u8(growth)?;
+    fd.write_u32::<BigEndian>(chunk)?;
+    fd.write_f32::<BigEndian>(config.level_multiplier.unwrap_or(0f32))?;
+    fd.write_u8(config.diverse_neighbors as u8)
+}
+
+/// Reads what [write_config] wrote, or only `m`, `ef_construction` and
+/// `ef_search` if `legacy`, leaving the rest to the defaults.
+fn read_config(fd: &mut impl Read, legacy: bool) -> Result<HnswConfig, Error> {
+    if legacy {
+        return Ok(HnswConfig {
+            m: fd.read_u32::<BigEndian>().map_err(parse_error)?,
+            ef_construction: fd.read_u32::<BigEndian>().map_err(parse_error)?,
+            ef_search: fd.read_u32::<BigEndian>().map_err(parse_error)?,
+            ..HnswConfig::default()
+        });
+    }
+    let m = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+    let m0 = Some(fd.read_u32::<BigEndian>().map_err(parse_error)?).filter(|m0| *m0 > 0);
+    let ef_construction = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+    let ef_search = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+    let distance_cache = fd.read_u8().map_err(parse_error)? != 0;
+    let growth = fd.read_u8().map_err(parse_error)?;
+    let chunk = fd.read_u32::<BigEndian>().map_err(parse_error)?;
+    let growth = match (growth, chunk) {
+        (0, _) => GrowthPolicy::Exact,
+        (1, _) => GrowthPolicy::Geometric,
+        (2, chunk) => GrowthPolicy::Chunked(chunk),
+        _ => return Err(Error::Parse()),
+    };
+    let level_multiplier = fd.read_f32::<BigEndian>().map_err(parse_error)?;
+    let level_multiplier = Some(level_multiplier).filter(|l| *l != 0f32);
+    let config = HnswConfig {
+        m,
+        m0,
+        ef_construction,
+        ef_search,
+        distance_cache,
+        growth,
+        level_multiplier,
+        diverse_neighbors: fd.read_u8().map_err(parse_error)? != 0,
+    };
+    config.check().map_err(|_| Error::Parse())?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error, GrowthPolicy, HnswConfig, SearchPlan, SearchRequest};
+    use crate::ds::graph::{Graph, NdGraph};
+    use crate::ds::layer::HnswLayer;
+    use crate::ext::rand::XorShift;
+    use crate::index::HnswIndex;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+
+    fn random_index(count: u32, rng: &mut XorShift) -> HnswIndex {
+        let mut index = HnswIndex::new(8, Metric::Euclidean, HnswConfig::default());
+        for id in 0..count {
+            index.insert(id, &rng.vector(8)).unwrap();
+        }
+        index
+    }
+
+    /// Same as [HnswIndex::serialize] with the config left to the defaults,
+    /// for comparing graphs built with different settings.
+    fn graph_bytes(index: &HnswIndex) -> Vec<u8> {
+        let mut index = index.clone();
+        index.config = HnswConfig::default();
+        let mut bytes = vec![];
+        index.serialize(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn standalone_index_works() {
+        let mut rng = XorShift::new(5);
+        let mut index = random_index(500, &mut rng);
+        assert_eq!(index.len(), 500);
+
+        let (mut hits, queries) = (0, 50);
+        for _ in 0..queries {
+            let query = rng.vector(8);
+            let mut exact = (0..500)
+                .map(|id| (id, Metric::Euclidean.distance(&query, index.get(id).unwrap())))
+                .collect::<Vec<_>>();
+            exact.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+            let found = index.search(&query, 10).unwrap();
+            assert_eq!(found.len(), 10);
+            hits += found.iter().filter(|hit| exact[..10].contains(hit)).count();
+        }
+        assert!(hits as f32 / (queries * 10) as f32 > 0.95);
+
+        let query = index.get(42).unwrap().to_vec();
+        assert_eq!(index.search(&query, 1).unwrap()[0], (42, 0f32));
+        assert!(index.remove(42));
+        assert!(!index.remove(42));
+        assert_eq!(index.len(), 499);
+        assert!(index.search(&query, 10).unwrap().iter().all(|(id, _)| *id != 42));
+    }
+
+    #[test]
+    fn serialization_works() {
+        let mut rng = XorShift::new(6);
+        let mut index = random_index(200, &mut rng);
+        index.remove(7);
+        let mut bytes = vec![];
+        index.serialize(&mut bytes).unwrap();
+        let decoded = HnswIndex::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(decoded.len(), 199);
+        assert!(!decoded.contains(7));
+        for _ in 0..10 {
+            let query = rng.vector(8);
+            assert_eq!(
+                decoded.search(&query, 5).unwrap(),
+                index.search(&query, 5).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn serialization_keeps_config() {
+        let config = HnswConfig {
+            m: 6,
+            m0: Some(20),
+            ef_construction: 40,
+            ef_search: 30,
+            distance_cache: false,
+            growth: GrowthPolicy::Chunked(32),
+            level_multiplier: Some(0.5),
+            diverse_neighbors: false,
+        };
+        let mut rng = XorShift::new(8);
+        let mut index = HnswIndex::new(8, Metric::Euclidean, config);
+        for id in 0..100 {
+            index.insert(id, &rng.vector(8)).unwrap();
+        }
+        let mut bytes = vec![];
+        index.serialize(&mut bytes).unwrap();
+        let decoded = HnswIndex::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(decoded.config(), config);
+
+        let mut bytes = vec![];
+        HnswIndex::new(8, Metric::Euclidean, HnswConfig::default())
+            .serialize(&mut bytes)
+            .unwrap();
+        // a config out of range doesn't make it back
+        let multiplier = 4 + 4 + 1 + 4 * 4 + 1 + 1 + 4;
+        bytes[multiplier..multiplier + 4].copy_from_slice(&(-1f32).to_be_bytes());
+        assert!(matches!(
+            HnswIndex::deserialize(&mut Cursor::new(bytes)),
+            Err(Error::Parse())
+        ));
+    }
+
+    #[test]
+    fn deserialize_rejects_malformed() {
+        let mut rng = XorShift::new(10);
+        let mut bytes = vec![];
+        random_index(50, &mut rng).serialize(&mut bytes).unwrap();
+        let parse = |bytes: &[u8]| HnswIndex::deserialize(&mut Cursor::new(bytes)).err();
+        for len in [3, 20, 40, 100, bytes.len() / 2, bytes.len() - 1] {
+            assert!(matches!(parse(&bytes[..len]), Some(Error::Parse())), "cut at {len}");
+        }
+        let (dim, len, level) = (4, 4 + 4 + 1 + 27, 4 + 4 + 1 + 27 + 4 + 4 + 4);
+        // a node above the entry being the last of these
+        let corruptions = [
+            (dim, u32::MAX),
+            (len, u32::MAX),
+            (level, u32::MAX),
+            (level, 17),
+            (level, 16),
+        ];
+        for (at, value) in corruptions {
+            let mut corrupted = bytes.clone();
+            corrupted[at..at + 4].copy_from_slice(&value.to_be_bytes());
+            assert!(matches!(parse(&corrupted), Some(Error::Parse())), "{value} at {at}");
+        }
+        assert!(parse(&bytes).is_none());
+    }
+
+    #[test]
+    fn reserve_works() {
+        let mut rng = XorShift::new(6);
+        let vectors = (0..300).map(|_| rng.vector(8)).collect::<Vec<_>>();
+        let build = |growth, reserve| {
+            let config = HnswConfig {
+                growth,
+                ..HnswConfig::default()
+            };
+            let mut index = HnswIndex::new(8, Metric::Euclidean, config);
+            index.reserve(reserve);
+            for (id, vector) in vectors.iter().enumerate() {
+                index.insert(id as u32, vector).unwrap();
+            }
+            (graph_bytes(&index), index.layers[0].dense().unwrap().reallocations())
+        };
+        let (exact, grown) = build(GrowthPolicy::Exact, 0);
+        let (reserved, once) = build(GrowthPolicy::Exact, 300);
+        assert_eq!(grown, 300);
+        assert_eq!(once, 1);
+        assert_eq!(exact, reserved);
+        assert_eq!(build(GrowthPolicy::Chunked(64), 0).0, exact);
+    }
+
+    #[test]
+    fn distance_cache_works() {
+        let build = |distance_cache| {
+            let config = HnswConfig {
+                m: 4,
+                ef_construction: 16,
+                distance_cache,
+                ..HnswConfig::default()
+            };
+            let mut rng = XorShift::new(9);
+            let mut index = HnswIndex::new(8, Metric::Euclidean, config);
+            // a dense cluster, so that every insertion goes through the same nodes
+            for id in 0..300 {
+                let vector = rng.vector(8).iter().map(|x| 0.5 + x * 0.01).collect::<Vec<_>>();
+                index.insert(id, &vector).unwrap();
+            }
+            index
+        };
+        let (cached, uncached) = (build(true), build(false));
+        let (stats, baseline) = (cached.build_stats(), uncached.build_stats());
+        assert_eq!(baseline.cache_hits + baseline.cache_misses, 0);
+        assert_eq!(stats.cache_misses, stats.distance_evaluations);
+        assert_eq!(stats.cache_hits + stats.cache_misses, baseline.distance_evaluations);
+        // over a tenth of the distances come up again within an insertion
+        assert!(stats.distance_evaluations * 10 < baseline.distance_evaluations * 9);
+
+        assert_eq!(graph_bytes(&cached), graph_bytes(&uncached));
+    }
+
+    #[test]
+    fn degrees_stay_bounded() {
+        for diverse_neighbors in [true, false] {
+            let config = HnswConfig {
+                m: 4,
+                m0: Some(6),
+                ef_construction: 32,
+                diverse_neighbors,
+                ..HnswConfig::default()
+            };
+            let mut rng = XorShift::new(13);
+            let mut index = HnswIndex::new(8, Metric::Euclidean, config);
+            for id in 0..1000 {
+                index.insert(id, &rng.vector(8)).unwrap();
+            }
+            assert!(index.layers.len() > 1);
+            for layer in &index.layers {
+                let max_degree = config.max_degree(layer.level());
+                let degrees = (0..1000).map(|node| layer.vertices(node).len());
+                assert!(degrees.clone().all(|degree| degree <= max_degree));
+                // the base layer fills up to m0 rather than m
+                if layer.level() == 0 {
+                    assert_eq!(degrees.max(), Some(6));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rebuild_layer_works() {
+        let mut rng = XorShift::new(11);
+        let config = HnswConfig {
+            m: 4,
+            ef_search: 16,
+            ..HnswConfig::default()
+        };
+        let mut index = HnswIndex::new(8, Metric::Euclidean, config);
+        for id in 0..1000 {
+            index.insert(id, &rng.vector(8)).unwrap();
+        }
+        let mut bytes = vec![];
+        index.serialize(&mut bytes).unwrap();
+        let mut index = HnswIndex::deserialize(&mut Cursor::new(bytes)).unwrap();
+        let queries = (0..50).map(|_| rng.vector(8)).collect::<Vec<_>>();
+        let recall = |index: &HnswIndex| {
+            let hits = queries.iter().map(|query| {
+                let mut exact = (0..1000)
+                    .map(|id| (id, Metric::Euclidean.distance(query, index.get(id).unwrap())))
+                    .collect::<Vec<_>>();
+                exact.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+                let found = index.search(query, 10).unwrap();
+                found.iter().filter(|hit| exact[..10].contains(hit)).count()
+            });
+            hits.sum::<usize>() as f32 / (queries.len() * 10) as f32
+        };
+        let intact = recall(&index);
+        let layers = index.layers.len();
+        assert!(layers > 3);
+
+        // a mid layer loses its edges
+        let level = layers as u32 / 2;
+        let mut graph = NdGraph::new();
+        graph.push_many(1000);
+        index.layers[level as usize] = HnswLayer::new(graph, level);
+        let damaged = recall(&index);
+
+        index.rebuild_layer(level).unwrap();
+        // only nodes assigned to the level or above are connected on it
+        let connected = (0..1000)
+            .filter(|node| !index.layers[level as usize].vertices(*node).is_empty())
+            .collect::<Vec<_>>();
+        assert!(!connected.is_empty());
+        assert!(connected.iter().all(|node| index.levels[*node as usize] >= level));
+        let rebuilt = recall(&index);
+        assert!(rebuilt >= intact - 0.02, "{rebuilt} against {intact}, damaged {damaged}");
+        assert_eq!(index.layers.len(), layers);
+        assert!(matches!(index.rebuild_layer(layers as u32), Err(Error::NoLayer(_))));
+    }
+
+    #[test]
+    fn database_parity_works() {
+        let mut rng = XorShift::new(8);
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut index = HnswIndex::new(8, db.metric(), db.config());
+        for _ in 0..300 {
+            let vector = rng.vector(8);
+            let id = db.push(&vector).unwrap();
+            index.insert(id, &vector).unwrap();
+        }
+        db.build_index().unwrap();
+        for _ in 0..20 {
+            let query = rng.vector(8);
+            let request = SearchRequest::new(&query, 10).plan(SearchPlan::Graph);
+            let response = db.query(&request).unwrap();
+            assert_eq!(response.results, index.search(&query, 10).unwrap());
+            assert!(response.visited < 300);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::algorithm::construct::{link, shrink};
+use crate::algorithm::search::search_layer;
+use crate::db::{DbIndex, DbVector, Error, HnswConfig};
+use crate::ds::graph::{Graph, NdGraph};
+use crate::ds::layer::HnswLayer;
+use crate::index::{HnswIndex, DENSE};
+use crate::metric::Metric;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::io;
+use std::ops::Range;
+
+impl HnswIndex {
+    /// Builds an index over `records` on `threads` threads. The records are
+    /// split into as many shards, each indexed on its own, whose layers are
+    /// then put together and linked across shards by searching the others
+    /// for every node. Recall comes close to that of inserting one by one,
+    /// though the graph isn't the same.
+    ///
+    /// The returned index counts the work of the shards in its
+    /// [build statistics](HnswIndex::build_stats), not that of linking them.
+    pub fn build_parallel(
+        dim_size: u32,
+        metric: Metric,
+        config: HnswConfig,
+        records: &[(DbIndex, DbVector)],
+        threads: usize,
+    ) -> Result<HnswIndex, Error> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| Error::IO(io::Error::other(e)))?;
+        let shard_size = records.len().div_ceil(threads.max(1)).max(1);
+        let shards = pool.install(|| {
+            records
+                .par_chunks(shard_size)
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let mut shard = HnswIndex::new(dim_size, metric, config).with_seed(i as u64);
+                    shard.reserve(chunk.len());
+                    for (id, vector) in chunk {
+                        shard.insert(*id, vector)?;
+                    }
+                    Ok(shard)
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        })?;
+
+        let mut index = HnswIndex::merge(dim_size, metric, config, &shards);
+        if shards.len() > 1 {
+            let spans = shards
+                .iter()
+                .zip(offsets(&shards))
+                .filter_map(|(shard, offset)| {
+                    let nodes = offset..offset + shard.vectors.len() as u32;
+                    shard.entry.map(|entry| (nodes, entry + offset))
+                })
+                .collect::<Vec<_>>();
+            let candidates = pool.install(|| {
+                (0..index.vectors.len() as u32)
+                    .into_par_iter()
+                    .map(|node| index.cross_candidates(node, &spans))
+                    .collect::<Vec<_>>()
+            });
+            index.cross_link(candidates);
+        }
+        Ok(index)
+    }
+
+    /// Union of the shards, each numbered after the ones before it,
+    /// with no edges between them yet.
+    fn merge(dim_size: u32, metric: Metric, config: HnswConfig, shards: &[HnswIndex]) -> HnswIndex {
+        let mut index = HnswIndex::new(dim_size, metric, config);
+        let len = shards.iter().map(|s| s.vectors.len() as u32).sum::<u32>();
+        let top = shards.iter().map(|s| s.layers.len()).max().unwrap_or(0);
+        for level in 0..top as u32 {
+            let mut graph = NdGraph::with_capacity(len).with_growth(config.growth);
+            graph.push_many(len);
+            for (shard, offset) in shards.iter().zip(offsets(shards)) {
+                if let Some(layer) = shard.layers.get(level as usize) {
+                    graph.merge(layer.dense().expect(DENSE), offset);
+                }
+            }
+            index.layers.push(HnswLayer::new(graph, level));
+        }
+        for (shard, offset) in shards.iter().zip(offsets(shards)) {
+            index.vectors.extend_from_slice(&shard.vectors);
+            index.ids.extend_from_slice(&shard.ids);
+            index.levels.extend_from_slice(&shard.levels);
+            index
+                .nodes
+                .extend(shard.nodes.iter().map(|(id, node)| (*id, node + offset)));
+            if let Some(entry) = shard.entry.map(|entry| entry + offset) {
+                let level = index.levels[entry as usize];
+                if index.entry.is_none_or(|e| level > index.levels[e as usize]) {
+                    index.entry = Some(entry);
+                }
+            }
+            index.stats.distance_evaluations += shard.stats.distance_evaluations;
+            index.stats.cache_hits += shard.stats.cache_hits;
+            index.stats.cache_misses += shard.stats.cache_misses;
+        }
+        index
+    }
+
+    /// Closest nodes to `node` on each layer it's on, from the base layer up,
+    /// searched through every shard but its own, given the nodes and the
+    /// entry of each.
+    fn cross_candidates(&self, node: u32, spans: &[(Range<u32>, u32)]) -> Vec<Vec<(u32, f32)>> {
+        let level = self.levels[node as usize];
+        let ef = self.config.ef_construction as usize;
+        let distance = |other: u32| self.node_distance(node, other);
+        let mut found = vec![vec![]; level as usize + 1];
+        for (nodes, entry) in spans.iter().cloned() {
+            if nodes.contains(&node) {
+                continue;
+            }
+            let top = self.levels[entry as usize];
+            let mut nearest = vec![(entry, distance(entry))];
+            for upper in (level + 1..=top).rev() {
+                let graph = self.layers[upper as usize].dense().expect(DENSE);
+                nearest = search_layer(graph, &nearest, 1, &mut None, distance).nearest;
+            }
+            for lower in (0..=level.min(top)).rev() {
+                let graph = self.layers[lower as usize].dense().expect(DENSE);
+                nearest = search_layer(graph, &nearest, ef, &mut None, distance).nearest;
+                found[lower as usize].extend_from_slice(&nearest);
+            }
+        }
+        found
+    }
+
+    /// Links every node to the closest of its own neighbors and the
+    /// `candidates` from other shards together, layer by layer.
+    fn cross_link(&mut self, candidates: Vec<Vec<Vec<(u32, f32)>>>) {
+        let (m, diverse) = (self.config.m as usize, self.config.diverse_neighbors);
+        let (vectors, metric) = (&self.vectors, self.metric);
+        let between = |a: u32, b: u32| metric.distance(&vectors[a as usize], &vectors[b as usize]);
+        for (node, by_level) in candidates.into_iter().enumerate() {
+            let node = node as u32;
+            for (level, mut found) in by_level.into_iter().enumerate() {
+                let max_degree = self.config.max_degree(level as u32);
+                let graph = self.layers[level].dense_mut().expect(DENSE);
+                found.extend(graph.get_vertices(node));
+                found.sort_by(|(a, da), (b, db)| da.total_cmp(db).then(a.cmp(b)));
+                found.dedup_by_key(|(n, _)| *n);
+                link(graph, node, &found, (m, max_degree), diverse, between);
+                shrink(graph, node, max_degree, diverse, between);
+            }
+        }
+    }
+}
+
+/// Number of the first node of each shard once merged.
+fn offsets(shards: &[HnswIndex]) -> impl Iterator<Item = u32> + '_ {
+    shards.iter().scan(0, |next, shard| {
+        let offset = *next;
+        *next += shard.vectors.len() as u32;
+        Some(offset)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, HnswConfig, SearchRequest};
+    use crate::ext::rand::XorShift;
+    use crate::index::HnswIndex;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+    use std::time::Instant;
+
+    fn recall(index: &HnswIndex, records: &[Vec<f32>], queries: &[Vec<f32>]) -> f32 {
+        let hits = queries.iter().map(|query| {
+            let mut exact = records
+                .iter()
+                .enumerate()
+                .map(|(id, v)| (id as u32, Metric::Euclidean.distance(query, v)))
+                .collect::<Vec<_>>();
+            exact.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+            let found = index.search(query, 10).unwrap();
+            found.iter().filter(|hit| exact[..10].contains(hit)).count()
+        });
+        hits.sum::<usize>() as f32 / (queries.len() * 10) as f32
+    }
+
+    #[test]
+    fn parallel_build_works() {
+        let mut rng = XorShift::new(13);
+        let config = HnswConfig {
+            m: 8,
+            ef_construction: 64,
+            ef_search: 32,
+            ..HnswConfig::default()
+        };
+        let records = (0..1000).map(|_| rng.vector(8)).collect::<Vec<_>>();
+        let queries = (0..50).map(|_| rng.vector(8)).collect::<Vec<_>>();
+        let mut sequential = HnswIndex::new(8, Metric::Euclidean, config);
+        for (id, vector) in records.iter().enumerate() {
+            sequential.insert(id as u32, vector).unwrap();
+        }
+        let expected = recall(&sequential, &records, &queries);
+
+        let numbered = records
+            .iter()
+            .enumerate()
+            .map(|(id, v)| (id as u32, v.clone()))
+            .collect::<Vec<_>>();
+        for threads in [1, 3, 4] {
+            let index =
+                HnswIndex::build_parallel(8, Metric::Euclidean, config, &numbered, threads).unwrap();
+            assert_eq!(index.len(), 1000);
+            let got = recall(&index, &records, &queries);
+            assert!(got >= expected - 0.05, "{got} against {expected} on {threads} threads");
+        }
+
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        for vector in &records {
+            db.push(vector).unwrap();
+        }
+        let stats = db.build_index_parallel(config, 4).unwrap();
+        assert!(stats.distance_evaluations > 0);
+        assert!(db.is_indexed());
+        assert_eq!(db.config(), config);
+        let response = db.query(&SearchRequest::new(&records[7], 1)).unwrap();
+        assert_eq!(response.results[0], (7, 0f32));
+        assert!(response.visited < 1000);
+    }
+
+    /// Not run by default as it takes a while, but run with `--ignored`
+    /// to compare build times across numbers of threads.
+    #[test]
+    #[ignore]
+    fn parallel_build_scales() {
+        let mut rng = XorShift::new(21);
+        let records = (0..20_000)
+            .map(|id| (id, rng.vector(32)))
+            .collect::<Vec<_>>();
+        let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+        let mut single = None;
+        for threads in (0..).map(|p| 1 << p).take_while(|n| *n <= threads) {
+            let begin = Instant::now();
+            HnswIndex::build_parallel(32, Metric::Euclidean, HnswConfig::default(), &records, threads)
+                .unwrap();
+            let elapsed = begin.elapsed();
+            let single = *single.get_or_insert(elapsed);
+            println!(
+                "{threads} threads: {elapsed:?}, {:.2}x",
+                single.as_secs_f64() / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::algorithm::search::{LayerScratch, StampSet};
+
+/// # Search Scratch
+/// Buffers a search through the index works in: the nodes it has seen, its
+/// candidates and the nearest ones carried from a layer to the next. Kept
+/// between searches, they grow to fit the index once and are reused from
+/// then on, so that searching allocates next to nothing but the results,
+/// see [Database::query_with_scratch](crate::db::Database::query_with_scratch).
+/// Nodes seen are forgotten in constant time from one search to the next.
+#[derive(Debug, Clone, Default)]
+pub struct SearchScratch {
+    pub(crate) layer: LayerScratch<StampSet>,
+    /// Where the search of the next layer starts.
+    pub(crate) entries: Vec<(u32, f32)>,
+}
+
+impl SearchScratch {
+    pub fn new() -> SearchScratch {
+        SearchScratch::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, SearchPlan, SearchRequest};
+    use crate::ext::rand::XorShift;
+    use crate::index::SearchScratch;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::io::Cursor;
+
+    /// Counts the allocations of each thread, leaving the others' tests alone.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> u64 {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    #[test]
+    fn search_scratch_works() {
+        let mut db = DatabaseOptions::new(8)
+            .create("mem", Box::new(Cursor::new(Vec::new())))
+            .unwrap();
+        let mut rng = XorShift::new(61);
+        for _ in 0..500 {
+            db.push(rng.vector(8)).unwrap();
+        }
+        db.build_index().unwrap();
+        let requests = (0..100)
+            .map(|i| SearchRequest::new(&rng.vector(8), 1 + i % 10).plan(SearchPlan::Graph))
+            .collect::<Vec<_>>();
+
+        let mut scratch = SearchScratch::new();
+        for i in 0..10_000 {
+            let request = &requests[i % requests.len()];
+            let reused = db.query_with_scratch(request, &mut scratch).unwrap();
+            if i < requests.len() {
+                let fresh = db.query_with_scratch(request, &mut SearchScratch::new()).unwrap();
+                assert_eq!(reused, fresh);
+                assert_eq!(reused, db.query(request).unwrap());
+            }
+        }
+
+        // the response is all that's left to allocate
+        let before = allocations();
+        for request in &requests {
+            db.query_with_scratch(request, &mut scratch).unwrap();
+        }
+        let reused = (allocations() - before) / requests.len() as u64;
+        let before = allocations();
+        for request in &requests {
+            db.query_with_scratch(request, &mut SearchScratch::new()).unwrap();
+        }
+        let fresh = (allocations() - before) / requests.len() as u64;
+        assert!(reused <= 4, "{reused} allocations per query");
+        assert!(fresh > reused);
+    }
+}
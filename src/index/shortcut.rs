@@ -0,0 +1,163 @@
+use crate::db::DbVectorSlice;
+use crate::ext::counter::HitCounts;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Dimensions of the query making up its key, one sign bit each.
+const KEY_DIMS: usize = 64;
+
+/// # Shortcut Cache
+/// Where on layer 1 the descent of recent queries landed, keyed by a coarse
+/// quantization of them, so that similar queries start there instead of
+/// walking down from the entry again. The least recently used shortcut
+/// makes room for new ones once it holds `capacity`.
+#[derive(Debug, Clone)]
+pub(crate) struct ShortcutCache {
+    capacity: usize,
+    /// Node and when it was last used by key.
+    shortcuts: HashMap<u64, (u32, u64)>,
+    clock: u64,
+    /// Shared with the [stats](crate::db::Database::stats) of the database.
+    pub(crate) counts: Arc<HitCounts>,
+}
+
+impl ShortcutCache {
+    pub(crate) fn new(capacity: usize, counts: Arc<HitCounts>) -> ShortcutCache {
+        ShortcutCache {
+            capacity,
+            shortcuts: HashMap::with_capacity(capacity),
+            clock: 0,
+            counts,
+        }
+    }
+
+    /// Sign bits of the first 64 dimensions of `query`.
+    pub(crate) fn key(query: DbVectorSlice) -> u64 {
+        query
+            .iter()
+            .take(KEY_DIMS)
+            .enumerate()
+            .fold(0, |key, (i, x)| key | ((x.is_sign_negative() as u64) << i))
+    }
+
+    pub(crate) fn get(&mut self, key: u64) -> Option<u32> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.shortcuts.get_mut(&key).map(|(node, used)| {
+            *used = clock;
+            *node
+        })
+    }
+
+    pub(crate) fn insert(&mut self, key: u64, node: u32) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        if self.shortcuts.len() >= self.capacity && !self.shortcuts.contains_key(&key) {
+            let oldest = self.shortcuts.iter().min_by_key(|(_, (_, used))| *used);
+            if let Some(oldest) = oldest.map(|(key, _)| *key) {
+                self.shortcuts.remove(&oldest);
+            }
+        }
+        self.shortcuts.insert(key, (node, self.clock));
+    }
+
+    /// Forgets every shortcut, for when the nodes are numbered anew.
+    pub(crate) fn clear(&mut self) {
+        self.shortcuts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, DatabaseOptions, HnswConfig, PlannerConfig, SearchRequest};
+    use crate::ext::rand::XorShift;
+    use crate::index::ShortcutCache;
+    use crate::metric::Metric;
+    use std::io::Cursor;
+
+    #[test]
+    fn shortcut_cache_works() {
+        let mut cache = ShortcutCache::new(2, Default::default());
+        assert_eq!(ShortcutCache::key(&[1f32, -1f32, -0f32]), 0b110);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        assert_eq!(cache.get(1), Some(10));
+        // 2 is the least recently used
+        cache.insert(3, 30);
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(1), Some(10));
+        assert_eq!(cache.get(3), Some(30));
+        cache.clear();
+        assert_eq!(cache.get(1), None);
+    }
+
+    #[test]
+    fn entry_shortcuts_work() {
+        let mut rng = XorShift::new(19);
+        let centered = |rng: &mut XorShift| rng.vector(8).iter().map(|x| x - 0.5).collect();
+        let records: Vec<Vec<f32>> = (0..1000).map(|_| centered(&mut rng)).collect();
+        // bursts of queries around a few topics
+        let mut queries = vec![];
+        for _ in 0..5 {
+            let topic: Vec<f32> = centered(&mut rng);
+            for _ in 0..40 {
+                let noise = topic.iter().map(|x| x + (rng.next_f32() - 0.5) * 0.01);
+                queries.push(noise.collect::<Vec<_>>());
+            }
+        }
+        let config = HnswConfig {
+            m: 4,
+            ef_construction: 64,
+            ..HnswConfig::default()
+        };
+        let build = |options: DatabaseOptions| {
+            let mut db = options
+                .hnsw(config)
+                .planner(PlannerConfig {
+                    scan_below: 0,
+                    graph_cost_factor: 0.0,
+                })
+                .create("mem", Box::new(Cursor::new(Vec::new())))
+                .unwrap();
+            for vector in &records {
+                db.push(vector).unwrap();
+            }
+            db.build_index().unwrap();
+            db
+        };
+        let replay = |db: &mut Database| {
+            let (mut hits, mut visited) = (0, 0);
+            for query in &queries {
+                let mut exact = records
+                    .iter()
+                    .enumerate()
+                    .map(|(id, v)| (id as u32, Metric::Euclidean.distance(query, v)))
+                    .collect::<Vec<_>>();
+                exact.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+                let response = db.query(&SearchRequest::new(query, 10)).unwrap();
+                hits += response.results.iter().filter(|hit| exact[..10].contains(hit)).count();
+                visited += response.visited;
+            }
+            (hits as f32 / (queries.len() * 10) as f32, visited)
+        };
+
+        let mut plain = build(DatabaseOptions::new(8));
+        let mut shortcut = build(DatabaseOptions::new(8).entry_shortcuts(16));
+        let (plain_recall, plain_visited) = replay(&mut plain);
+        let (recall, visited) = replay(&mut shortcut);
+        assert!(recall >= plain_recall - 0.01, "{recall} against {plain_recall}");
+        assert!(visited < plain_visited, "{visited} against {plain_visited}");
+
+        let explained = plain.query(&SearchRequest::new(&queries[0], 1).explain(true));
+        assert!(explained.unwrap().trace.unwrap().layers.len() > 2);
+
+        let stats = shortcut.stats().unwrap();
+        assert_eq!(stats.shortcut_hits + stats.shortcut_misses, queries.len() as u64);
+        assert!(stats.shortcut_hit_rate() > 0.9, "{}", stats.shortcut_hit_rate());
+        assert_eq!(plain.stats().unwrap().shortcut_hit_rate(), 0f64);
+        shortcut.reset_stats();
+        assert_eq!(shortcut.stats().unwrap().shortcut_hits, 0);
+    }
+}
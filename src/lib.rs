@@ -3,8 +3,13 @@ extern crate core;
 mod algorithm;
 mod ds;
 pub mod db;
+#[cfg(feature = "embed")]
+pub mod embed;
+pub mod index;
+pub mod metric;
 pub mod ms;
-mod vio;
+pub mod ops;
+pub mod vio;
 mod ext;
 
 pub fn add(left: u64, right: u64) -> u64 {
@@ -0,0 +1,105 @@
+use crate::db::DbVectorSlice;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// # Metric
+/// The distance function a database ranks its vectors by.
+/// Smaller distances always mean closer vectors, so similarity
+/// based metrics are negated or inverted accordingly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Metric {
+    /// Straight-line distance between two points.
+    #[default]
+    Euclidean,
+    /// One minus the cosine of the angle between two vectors.
+    Cosine,
+    /// Negated inner product.
+    DotProduct,
+}
+
+impl Metric {
+    /// Components are taken as they are, so infinities and NaN may lead to
+    /// a NaN distance, which is always the positive [f32::NAN] for it to
+    /// rank after every other distance, see [Database::sanitize](crate::db::Database::sanitize).
+    pub fn distance(&self, a: DbVectorSlice, b: DbVectorSlice) -> f32 {
+        self.kernel(a, b)
+    }
+
+    /// Same as [Metric::distance] for vectors of `D` dimensions known at
+    /// compile time, so that the kernels unroll for each. The components
+    /// are summed in the same order, so the distances are the same too.
+    #[inline]
+    pub fn distance_array<const D: usize>(&self, a: &[f32; D], b: &[f32; D]) -> f32 {
+        self.kernel(a, b)
+    }
+
+    #[inline]
+    fn kernel(&self, a: DbVectorSlice, b: DbVectorSlice) -> f32 {
+        let distance = match self {
+            Metric::Euclidean => euclidean(a, b),
+            Metric::Cosine => cosine(a, b),
+            Metric::DotProduct => -dot(a, b),
+        };
+        if distance.is_nan() {
+            f32::NAN
+        } else {
+            distance
+        }
+    }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Metric::Euclidean => 0,
+            Metric::Cosine => 1,
+            Metric::DotProduct => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<Metric> {
+        match byte {
+            0 => Some(Metric::Euclidean),
+            1 => Some(Metric::Cosine),
+            2 => Some(Metric::DotProduct),
+            _ => None,
+        }
+    }
+}
+
+#[inline]
+fn dot(a: DbVectorSlice, b: DbVectorSlice) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[inline]
+fn euclidean(a: DbVectorSlice, b: DbVectorSlice) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
+
+#[inline]
+fn cosine(a: DbVectorSlice, b: DbVectorSlice) -> f32 {
+    let norms = dot(a, a).sqrt() * dot(b, b).sqrt();
+    if norms == 0f32 {
+        1f32
+    } else {
+        1f32 - dot(a, b) / norms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metric::Metric;
+
+    #[test]
+    fn distances_work() {
+        let (a, b) = ([0f32, 3f32], [4f32, 0f32]);
+        assert_eq!(Metric::Euclidean.distance(&a, &b), 5f32);
+        assert_eq!(Metric::Cosine.distance(&a, &b), 1f32);
+        assert_eq!(Metric::Cosine.distance(&a, &a), 0f32);
+        assert_eq!(Metric::DotProduct.distance(&a, &[1f32, 2f32]), -6f32);
+    }
+}
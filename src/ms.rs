@@ -1,123 +1,807 @@
 use crate::db;
-use crate::db::Database;
-use crate::ext::semaphore::LockAutoClear;
+use crate::db::{
+    Database, DatabaseOptions, DbIndex, Expectations, Forecast, HnswConfig, MaintenanceReport,
+};
+use crate::ext::mem::SharedCursor;
+use crate::ext::semaphore::{lock_pair, LockAutoClear, LockRank};
+use crate::metric::Metric;
+use crate::vio::{dbheader, RandomAccess};
 use std::collections::HashMap;
 use std::fmt::Formatter;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{fmt, fs, io};
 
 pub struct ManagementSystem<H: DbHandle> {
     handle: Mutex<Arc<H>>,
-    loaded_db: Mutex<HashMap<String, Arc<Database>>>,
+    loaded_db: Mutex<HashMap<String, Loaded>>,
+    max_bytes: Option<u64>,
+    read_handles: usize,
+    /// See [ManagementSystem::with_memory_budget].
+    memory_budget: Option<u64>,
+    cache_floor: u64,
+    /// Counts every database handed out, telling which was used least recently.
+    uses: AtomicU64,
+    shrunk: AtomicU64,
+    unloaded: AtomicU64,
+}
+
+/// A database loaded into the system.
+struct Loaded {
+    db: Arc<Mutex<Database>>,
+    /// Of [ManagementSystem::uses] when it was last handed out.
+    used: u64,
+    /// Bytes it held in memory as of the last look, see [Database::memory_usage].
+    usage: u64,
+}
+
+/// # Management System Statistics
+/// Databases loaded and the memory they take, see [ManagementSystem::stats].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsStats {
+    pub loaded: usize,
+    /// Bytes the loaded databases hold in memory, see [Database::memory_usage].
+    /// Those in use elsewhere count as of the last look.
+    pub memory_usage: u64,
+    pub memory_budget: Option<u64>,
+    /// Caches shrunk to keep within the budget so far.
+    pub shrunk: u64,
+    /// Databases unloaded to keep within the budget so far.
+    pub unloaded: u64,
+}
+
+/// Locks `db` unless it's in use elsewhere.
+fn try_lock(db: &Mutex<Database>) -> Option<MutexGuard<'_, Database>> {
+    match db.try_lock() {
+        Ok(db) => Some(db),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
 }
 
 #[derive(Debug)]
 pub enum Error {
     NameConflict(String),
+    NotFound(String),
     IO(io::Error),
     Database(db::Error),
+    QuotaExceeded { limit: u64, attempted: u64 },
+    DimensionMismatch(u32, u32),
+    MetricMismatch(Metric, Metric),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             Error::NameConflict(name) => write!(f, "conflicting name of {name}"),
+            Error::NotFound(name) => write!(f, "no database named {name}"),
             Error::IO(e) => write!(f, "IO failed because {e}"),
             Error::Database(e) => write!(f, "database failed because {e}"),
+            Error::QuotaExceeded { limit, attempted } => {
+                write!(f, "quota exceeded (limit {limit}, attempted {attempted})")
+            }
+            Error::DimensionMismatch(from, to) => {
+                write!(f, "dimension mismatch ({from} into {to})")
+            }
+            Error::MetricMismatch(from, to) => {
+                write!(f, "metric mismatch ({from:?} into {to:?})")
+            }
         }
     }
 }
 
-struct FsDbHandle {
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IO(e) => Some(e),
+            Error::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<db::Error> for Error {
+    fn from(e: db::Error) -> Self {
+        match e {
+            db::Error::IO(e) => Error::IO(e),
+            e => Error::Database(e),
+        }
+    }
+}
+
+pub struct FsDbHandle {
     root_dir: Box<Path>,
 }
 
 impl FsDbHandle {
-    fn get_underlying_file(&self, db_name: &String) -> Box<Path> {
+    fn get_underlying_file(&self, db_name: &str) -> Box<Path> {
         Box::from(self.root_dir.join(format!("{db_name}.db")))
     }
 }
 
-trait DbHandle {
-    fn create(&self, name: &String, dim_size: u32) -> Result<Database, Error>;
-    fn get(&self, name: &String) -> Result<Option<Database>, Error>;
+/// # Memory Database Handle
+/// Keeps every database in memory, gone once the system is dropped.
+#[derive(Default)]
+pub struct MemDbHandle {
+    files: Mutex<HashMap<String, SharedCursor>>,
+}
+
+pub trait DbHandle {
+    fn create(&self, name: &str, options: DatabaseOptions) -> Result<Database, Error>;
+    fn get(&self, name: &str, options: DatabaseOptions) -> Result<Option<Database>, Error>;
+    /// Bytes taken by all databases behind this handle.
+    fn usage(&self) -> Result<u64, Error>;
+    /// Another handle to the file of an existing database, for reads to go through.
+    fn reopen_read(&self, name: &str) -> Result<Box<dyn RandomAccess>, Error>;
 }
 
 impl DbHandle for FsDbHandle {
-    fn create(&self, name: &String, dim_size: u32) -> Result<Database, Error> {
+    /// Creates the root directory first if it's missing.
+    fn create(&self, name: &str, options: DatabaseOptions) -> Result<Database, Error> {
+        fs::create_dir_all(&self.root_dir).map_err(Error::IO)?;
         let file = self.get_underlying_file(name);
-        if fs::exists(&file).map_err(|e| Error::IO(e))? {
-            Err(Error::NameConflict(name.clone()))
+        // empty files are left behind by crashes before the header got written,
+        // see [db::Error::EmptyFile], and are initialized in place
+        let empty = fs::metadata(&file).is_ok_and(|meta| meta.len() == 0);
+        if !empty && fs::exists(&file).map_err(Error::IO)? {
+            Err(Error::NameConflict(name.to_string()))
         } else {
-            let fd = File::open(file).map_err(|e| Error::IO(e))?;
-            Ok(Database::new(name, dim_size, Box::new(fd)))
+            let fd = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(!empty)
+                .open(file)
+                .map_err(Error::IO)?;
+            Ok(options.create(name, Box::new(fd))?)
         }
     }
 
-    fn get(&self, name: &String) -> Result<Option<Database>, Error> {
+    fn get(&self, name: &str, options: DatabaseOptions) -> Result<Option<Database>, Error> {
         let file = self.get_underlying_file(name);
         if fs::exists(&file).unwrap_or(false) {
-            let fd = File::open(file).unwrap();
-            return Ok(Some(Database::read(name, Box::new(fd)).map_err(
-                |e| match e {
-                    db::Error::Header(e) => Error::Database(db::Error::Header(e)),
-                    db::Error::IO(e) => Error::IO(e),
-                    db::Error::Parse() => Error::Database(e),
-                    db::Error::Dimension(_, _) => Error::Database(e),
-                },
-            )?));
+            let fd = OpenOptions::new()
+                .read(true)
+                .write(!options.is_read_only())
+                .open(file)
+                .map_err(Error::IO)?;
+            return Ok(Some(options.open(name, Box::new(fd))?));
         }
         Ok(None)
     }
+
+    fn usage(&self) -> Result<u64, Error> {
+        let mut total = 0;
+        if !fs::exists(&self.root_dir).map_err(Error::IO)? {
+            return Ok(0);
+        }
+        for entry in fs::read_dir(&self.root_dir).map_err(Error::IO)? {
+            let entry = entry.map_err(Error::IO)?;
+            if entry.path().extension().is_some_and(|ext| ext == "db") {
+                total += entry.metadata().map_err(Error::IO)?.len();
+            }
+        }
+        Ok(total)
+    }
+
+    fn reopen_read(&self, name: &str) -> Result<Box<dyn RandomAccess>, Error> {
+        let fd = File::open(self.get_underlying_file(name)).map_err(Error::IO)?;
+        Ok(Box::new(fd))
+    }
+}
+
+impl DbHandle for MemDbHandle {
+    fn create(&self, name: &str, options: DatabaseOptions) -> Result<Database, Error> {
+        let mut files = self.files.lock_auto_clear_poison();
+        if files.contains_key(name) {
+            return Err(Error::NameConflict(name.to_string()));
+        }
+        let file = SharedCursor::new();
+        let db = options.create(name, Box::new(file.reopen()))?;
+        files.insert(name.to_string(), file);
+        Ok(db)
+    }
+
+    fn get(&self, name: &str, options: DatabaseOptions) -> Result<Option<Database>, Error> {
+        match self.files.lock_auto_clear_poison().get(name) {
+            None => Ok(None),
+            Some(file) => Ok(Some(options.open(name, Box::new(file.reopen()))?)),
+        }
+    }
+
+    fn usage(&self) -> Result<u64, Error> {
+        Ok(self
+            .files
+            .lock_auto_clear_poison()
+            .values()
+            .map(|file| file.len())
+            .sum())
+    }
+
+    fn reopen_read(&self, name: &str) -> Result<Box<dyn RandomAccess>, Error> {
+        match self.files.lock_auto_clear_poison().get(name) {
+            None => Err(Error::NotFound(name.to_string())),
+            Some(file) => Ok(Box::new(file.reopen())),
+        }
+    }
 }
 
 impl ManagementSystem<FsDbHandle> {
     pub fn new_fs<P: AsRef<Path>>(root_dir: P) -> ManagementSystem<FsDbHandle> {
+        ManagementSystem::with_handle(FsDbHandle {
+            root_dir: Box::from(root_dir.as_ref()),
+        })
+    }
+}
+
+impl ManagementSystem<MemDbHandle> {
+    pub fn new_mem() -> ManagementSystem<MemDbHandle> {
+        ManagementSystem::with_handle(MemDbHandle::default())
+    }
+}
+
+impl<H: DbHandle> ManagementSystem<H> {
+    fn with_handle(handle: H) -> ManagementSystem<H> {
         ManagementSystem {
-            handle: Mutex::new(Arc::from(FsDbHandle {
-                root_dir: Box::from(root_dir.as_ref()),
-            })),
+            handle: Mutex::new(Arc::from(handle)),
             loaded_db: Mutex::new(HashMap::new()),
+            max_bytes: None,
+            read_handles: thread::available_parallelism().map_or(1, |n| n.get()),
+            memory_budget: None,
+            cache_floor: 0,
+            uses: AtomicU64::new(0),
+            shrunk: AtomicU64::new(0),
+            unloaded: AtomicU64::new(0),
         }
     }
-}
 
-impl<H: DbHandle> ManagementSystem<H> {
+    #[allow(dead_code)]
     fn gc(&mut self) {
         // TODO: implement garbage collector for DBMS
     }
 
-    pub fn create(&mut self, name: &String, dim_size: u32) -> Result<Arc<Database>, Error> {
-        let created = Arc::from(
-            self.handle
-                .lock_auto_clear_poison()
-                .create(name, dim_size)?,
-        );
-        self.loaded_db
-            .lock_auto_clear_poison()
-            .insert(name.clone(), created.clone());
-        Ok(created.clone())
+    /// Caps the bytes all databases may take together.
+    /// Checked whenever a database is created.
+    pub fn with_quota(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Number of extra handles every loaded database reads through,
+    /// one per core unless set. See [Database::add_read_handles].
+    pub fn with_read_handles(mut self, count: usize) -> Self {
+        self.read_handles = count;
+        self
+    }
+
+    /// Caps the bytes the loaded databases may hold in memory together, see
+    /// [Database::memory_usage]. Checked whenever a database is created or
+    /// loaded, and by [ManagementSystem::maintain_all] for those that grew
+    /// since. Once over it, the caches of the databases are
+    /// [shrunk](Database::shrink_cache) down to the
+    /// [floor](ManagementSystem::with_cache_floor) one by one, least recently
+    /// used first, until it's kept. If that isn't enough, the databases not
+    /// referenced outside the system are flushed and unloaded in the same order.
+    /// Those locked elsewhere at the time are passed over.
+    pub fn with_memory_budget(mut self, max_bytes: u64) -> Self {
+        self.memory_budget = Some(max_bytes);
+        self
+    }
+
+    /// Bytes a cache is left with when shrunk to keep within the
+    /// [memory budget](ManagementSystem::with_memory_budget), none unless set.
+    pub fn with_cache_floor(mut self, bytes: u64) -> Self {
+        self.cache_floor = bytes;
+        self
+    }
+
+    pub fn create(&mut self, name: &str, dim_size: u32) -> Result<Arc<Mutex<Database>>, Error> {
+        self.create_with(name, DatabaseOptions::new(dim_size))
     }
 
-    pub fn get(&mut self, name: &String) -> Result<Option<Arc<Database>>, Error> {
+    /// Same as [ManagementSystem::create], with the database set up by `options`.
+    pub fn create_with(
+        &mut self,
+        name: &str,
+        options: DatabaseOptions,
+    ) -> Result<Arc<Mutex<Database>>, Error> {
         let handle = self.handle.lock_auto_clear_poison();
-        let mut cache = self.loaded_db.lock_auto_clear_poison();
-        match cache.get(name) {
+        if let Some(limit) = self.max_bytes {
+            let attempted = handle.usage()? + dbheader::header_size();
+            if attempted > limit {
+                return Err(Error::QuotaExceeded { limit, attempted });
+            }
+        }
+        let created = Arc::new(Mutex::new(handle.create(name, options)?));
+        let mut loaded = self.loaded_db.lock_auto_clear_poison();
+        loaded.insert(name.to_string(), self.load(created.clone()));
+        self.keep_within_budget(&mut loaded)?;
+        Ok(created)
+    }
+
+    /// Same as [Database::forecast] for a database of `dim_size` holding
+    /// `vectors`, yet to be [created](ManagementSystem::create) and indexed by `config`.
+    pub fn forecast_create(&self, dim_size: u32, vectors: u64, config: HnswConfig) -> Forecast {
+        Forecast::create(dim_size, vectors, config)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Arc<Mutex<Database>>>, Error> {
+        self.get_with(name, DatabaseOptions::default())
+    }
+
+    /// Same as [ManagementSystem::get], opening the database by `options`
+    /// unless it's loaded already, in which case they're ignored.
+    pub fn get_with(
+        &self,
+        name: &str,
+        options: DatabaseOptions,
+    ) -> Result<Option<Arc<Mutex<Database>>>, Error> {
+        let (handle, mut cache) = lock_pair(
+            (LockRank::Registry, &self.handle),
+            (LockRank::Loaded, &self.loaded_db),
+        );
+        match cache.get_mut(name) {
             None => {
-                let load = handle.get(name);
+                let load = handle.get(name, options);
                 match load {
                     Ok(None) => Ok(None),
-                    Ok(Some(db)) => {
-                        let arc = Arc::from(db);
-                        cache.insert(name.clone(), arc.clone());
-                        Ok(Some(arc.clone()))
+                    Ok(Some(mut db)) => {
+                        let readers = (0..self.read_handles)
+                            .map(|_| handle.reopen_read(name))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        db.add_read_handles(readers);
+                        let arc = Arc::new(Mutex::new(db));
+                        cache.insert(name.to_string(), self.load(arc.clone()));
+                        self.keep_within_budget(&mut cache)?;
+                        Ok(Some(arc))
                     }
                     Err(e) => Err(e),
                 }
             }
-            Some(db) => Ok(Some(db.clone())),
+            Some(loaded) => {
+                loaded.used = self.uses.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(loaded.db.clone()))
+            }
+        }
+    }
+
+    /// Same as [ManagementSystem::get], but fails with [db::Error::SchemaMismatch]
+    /// unless the database meets `expectations`, whether it's loaded already or not.
+    pub fn get_checked(
+        &self,
+        name: &str,
+        expectations: Expectations,
+    ) -> Result<Option<Arc<Mutex<Database>>>, Error> {
+        let db = self.get_with(name, DatabaseOptions::default().expect(expectations))?;
+        if let Some(db) = &db {
+            expectations.check(&db.lock_auto_clear_poison())?;
+        }
+        Ok(db)
+    }
+
+    /// Copies vectors `ids` from database `from` into `to`, returning their
+    /// new ids in the same order. Either all of them land in `to` or none.
+    pub fn copy_vectors(
+        &self,
+        from: &str,
+        to: &str,
+        ids: &[DbIndex],
+    ) -> Result<Vec<DbIndex>, Error> {
+        let (source, target) = (self.require(from)?, self.require(to)?);
+        if Arc::ptr_eq(&source, &target) {
+            let mut db = source.lock_auto_clear_poison();
+            return Ok(Self::transfer(&mut db, None, ids)?);
+        }
+        // whichever way around the two are copied between
+        let (mut source, mut target) =
+            lock_pair((LockRank::Database, &*source), (LockRank::Database, &*target));
+        if source.dim_size() != target.dim_size() {
+            return Err(Error::DimensionMismatch(
+                source.dim_size(),
+                target.dim_size(),
+            ));
+        }
+        if source.metric() != target.metric() {
+            return Err(Error::MetricMismatch(source.metric(), target.metric()));
+        }
+        Ok(Self::transfer(&mut source, Some(&mut target), ids)?)
+    }
+
+    /// Like [ManagementSystem::copy_vectors], but also removes the vectors
+    /// from `from` once they are safely in `to`. The removal is best-effort:
+    /// failing to remove one doesn't undo the copy.
+    pub fn move_vectors(
+        &self,
+        from: &str,
+        to: &str,
+        ids: &[DbIndex],
+    ) -> Result<Vec<DbIndex>, Error> {
+        let moved = self.copy_vectors(from, to, ids)?;
+        let source = self.require(from)?;
+        let mut source = source.lock_auto_clear_poison();
+        for id in ids {
+            let _ = source.remove(*id);
+        }
+        Ok(moved)
+    }
+
+    /// Maintains the loaded databases within `budget` together, taking a step
+    /// of each in turn so that none is starved, see [Database::maintain].
+    /// Every database with work left takes at least one step. Returns what
+    /// got done in each of them by name.
+    pub fn maintain_all(
+        &self,
+        budget: Duration,
+    ) -> Result<HashMap<String, MaintenanceReport>, Error> {
+        let deadline = Instant::now() + budget;
+        let mut loaded = self
+            .loaded_db
+            .lock_auto_clear_poison()
+            .iter()
+            .map(|(name, loaded)| (name.clone(), loaded.db.clone()))
+            .collect::<Vec<_>>();
+        loaded.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut reports = HashMap::<String, MaintenanceReport>::new();
+        while !loaded.is_empty() {
+            let mut busy = vec![];
+            for (name, db) in loaded {
+                let step = db.lock_auto_clear_poison().maintain(Duration::ZERO)?;
+                let report = reports.entry(name.clone()).or_default();
+                report.merge(step);
+                if !report.is_done() {
+                    busy.push((name, db));
+                }
+            }
+            loaded = busy;
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        self.keep_within_budget(&mut self.loaded_db.lock_auto_clear_poison())?;
+        Ok(reports)
+    }
+
+    /// Databases loaded and the memory they take.
+    pub fn stats(&self) -> MsStats {
+        let mut loaded = self.loaded_db.lock_auto_clear_poison();
+        MsStats {
+            loaded: loaded.len(),
+            memory_usage: Self::measure(&mut loaded),
+            memory_budget: self.memory_budget,
+            shrunk: self.shrunk.load(Ordering::Relaxed),
+            unloaded: self.unloaded.load(Ordering::Relaxed),
+        }
+    }
+
+    fn load(&self, db: Arc<Mutex<Database>>) -> Loaded {
+        Loaded {
+            db,
+            used: self.uses.fetch_add(1, Ordering::Relaxed),
+            usage: 0,
+        }
+    }
+
+    /// Looks at the memory usage of every loaded database not in use
+    /// elsewhere, returning the total.
+    fn measure(loaded: &mut HashMap<String, Loaded>) -> u64 {
+        for entry in loaded.values_mut() {
+            if let Some(db) = try_lock(&entry.db) {
+                entry.usage = db.memory_usage();
+            }
+        }
+        loaded.values().map(|entry| entry.usage).sum()
+    }
+
+    /// Shrinks caches, then unloads databases, least recently used first,
+    /// until `loaded` is within the memory budget if there's one, see
+    /// [ManagementSystem::with_memory_budget].
+    fn keep_within_budget(&self, loaded: &mut HashMap<String, Loaded>) -> Result<(), Error> {
+        let Some(budget) = self.memory_budget else {
+            return Ok(());
+        };
+        let mut total = Self::measure(loaded);
+        let mut order = loaded
+            .iter()
+            .map(|(name, entry)| (entry.used, name.clone()))
+            .collect::<Vec<_>>();
+        order.sort();
+        for (_, name) in &order {
+            if total <= budget {
+                return Ok(());
+            }
+            let entry = loaded.get_mut(name).unwrap();
+            let Some(db) = try_lock(&entry.db) else {
+                continue;
+            };
+            let freed = db.shrink_cache(self.cache_floor);
+            if freed > 0 {
+                self.shrunk.fetch_add(1, Ordering::Relaxed);
+                entry.usage -= freed;
+                total -= freed;
+            }
+        }
+        for (_, name) in order {
+            if total <= budget {
+                break;
+            }
+            let entry = &loaded[&name];
+            // the system holds the only reference, which nobody can clone meanwhile
+            if Arc::strong_count(&entry.db) > 1 {
+                continue;
+            }
+            entry.db.lock_auto_clear_poison().flush()?;
+            total -= entry.usage;
+            loaded.remove(&name);
+            self.unloaded.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn require(&self, name: &str) -> Result<Arc<Mutex<Database>>, Error> {
+        self.get(name)?
+            .ok_or_else(|| Error::NotFound(name.to_string()))
+    }
+
+    /// Reads `ids` from `source` and pushes them into `target`,
+    /// or back into `source` if there's no other target.
+    fn transfer(
+        source: &mut Database,
+        target: Option<&mut Database>,
+        ids: &[DbIndex],
+    ) -> Result<Vec<DbIndex>, db::Error> {
+        let vectors = source
+            .get_many(ids)?
+            .into_iter()
+            .zip(ids)
+            .map(|(v, id)| v.ok_or(db::Error::Missing(*id)))
+            .collect::<Result<Vec<_>, db::Error>>()?;
+        let slices = vectors.iter().map(|v| v.as_slice()).collect::<Vec<_>>();
+        target.unwrap_or(source).push_many(&slices)
+    }
+}
+
+/// # Maintenance Thread
+/// Calls [ManagementSystem::maintain_all] on an interval in the background,
+/// see [ManagementSystem::spawn_maintenance]. Stops once dropped, or once the
+/// system is dropped everywhere else.
+#[cfg(feature = "background")]
+pub struct MaintenanceThread {
+    stop: Arc<(Mutex<bool>, std::sync::Condvar)>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+#[cfg(feature = "background")]
+impl<H: DbHandle + Send + Sync + 'static> ManagementSystem<H> {
+    /// Spawns a thread maintaining the loaded databases for up to `budget`
+    /// every `interval`. Failures are left for the next tick to retry.
+    pub fn spawn_maintenance(
+        self: &Arc<Self>,
+        interval: Duration,
+        budget: Duration,
+    ) -> MaintenanceThread {
+        let stop = Arc::new((Mutex::new(false), std::sync::Condvar::new()));
+        let system = Arc::downgrade(self);
+        let signal = stop.clone();
+        let thread = thread::spawn(move || loop {
+            let (stopped, wakeup) = &*signal;
+            let stopped = wakeup
+                .wait_timeout_while(stopped.lock_auto_clear_poison(), interval, |stopped| {
+                    !*stopped
+                })
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+            if *stopped {
+                return;
+            }
+            drop(stopped);
+            let Some(system) = system.upgrade() else {
+                return;
+            };
+            let _ = system.maintain_all(budget);
+        });
+        MaintenanceThread {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(feature = "background")]
+impl Drop for MaintenanceThread {
+    fn drop(&mut self) {
+        let (stopped, wakeup) = &*self.stop;
+        *stopped.lock_auto_clear_poison() = true;
+        wakeup.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db;
+    use crate::db::{CacheMode, Expectations};
+    use crate::metric::Metric;
+    use crate::ms::{Error, ManagementSystem};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn copy_works() {
+        let mut ms = ManagementSystem::new_mem();
+        let staging = ms.create("staging", 4).unwrap();
+        let production = ms.create("production", 4).unwrap();
+        for i in 0..5 {
+            staging.lock().unwrap().push([i as f32; 4]).unwrap();
+        }
+        production.lock().unwrap().push([9f32; 4]).unwrap();
+
+        let copied = ms.copy_vectors("staging", "production", &[3, 1]).unwrap();
+        assert_eq!(copied, vec![1, 2]);
+        let production = production.lock().unwrap();
+        assert_eq!(*production.get(1).unwrap().unwrap(), vec![3f32; 4]);
+        assert_eq!(*production.get(2).unwrap().unwrap(), vec![1f32; 4]);
+        assert_eq!(staging.lock().unwrap().count().unwrap(), 5);
+    }
+
+    #[test]
+    fn move_works() {
+        let mut ms = ManagementSystem::new_mem();
+        let staging = ms.create("staging", 4).unwrap();
+        let production = ms.create("production", 4).unwrap();
+        for i in 0..5 {
+            staging.lock().unwrap().push([i as f32; 4]).unwrap();
+        }
+
+        let moved = ms.move_vectors("staging", "production", &[0, 4]).unwrap();
+        assert_eq!(moved, vec![0, 1]);
+        let staging = staging.lock().unwrap();
+        assert_eq!(staging.count().unwrap(), 3);
+        assert!(staging.get(0).unwrap().is_none());
+        assert!(staging.get(4).unwrap().is_none());
+        let production = production.lock().unwrap();
+        assert_eq!(*production.get(1).unwrap().unwrap(), vec![4f32; 4]);
+    }
+
+    #[test]
+    fn copy_mismatch_fails() {
+        let mut ms = ManagementSystem::new_mem();
+        let staging = ms.create("staging", 4).unwrap();
+        let production = ms.create("production", 8).unwrap();
+        staging.lock().unwrap().push([0f32; 4]).unwrap();
+
+        assert!(matches!(
+            ms.move_vectors("staging", "production", &[0]),
+            Err(Error::DimensionMismatch(4, 8))
+        ));
+        assert_eq!(staging.lock().unwrap().count().unwrap(), 1);
+        assert_eq!(production.lock().unwrap().count().unwrap(), 0);
+    }
+
+    #[test]
+    fn copy_missing_writes_nothing() {
+        let mut ms = ManagementSystem::new_mem();
+        let staging = ms.create("staging", 4).unwrap();
+        let production = ms.create("production", 4).unwrap();
+        staging.lock().unwrap().push([0f32; 4]).unwrap();
+
+        assert!(ms.copy_vectors("staging", "production", &[0, 7]).is_err());
+        assert_eq!(production.lock().unwrap().count().unwrap(), 0);
+    }
+
+    #[test]
+    fn get_checked_works() {
+        let mut ms = ManagementSystem::new_mem();
+        ms.create("vectors", 512).unwrap();
+        let expectations = Expectations::default().dim(768).metric(Metric::Cosine);
+        // loaded already, so it's checked without being opened again
+        let Err(Error::Database(db::Error::SchemaMismatch(mismatches))) =
+            ms.get_checked("vectors", expectations)
+        else {
+            panic!("got despite the mismatches");
+        };
+        assert_eq!(mismatches.len(), 2);
+        let expectations = Expectations::default().dim(512);
+        assert!(ms.get_checked("vectors", expectations).unwrap().is_some());
+        assert!(ms.get_checked("missing", expectations).unwrap().is_none());
+    }
+
+    #[test]
+    fn maintain_all_works() {
+        let mut ms = ManagementSystem::new_mem();
+        let names = ["a", "b"];
+        let mut pending = vec![];
+        for name in names {
+            let db = ms.create(name, 4).unwrap();
+            let db = db.lock().unwrap();
+            for i in 0..100 {
+                pending.push((name, db.push_async_queued([i as f32; 4]).unwrap()));
+            }
+        }
+
+        // both take a step, however small the budget
+        let reports = ms.maintain_all(Duration::ZERO).unwrap();
+        for name in names {
+            assert_eq!(reports[name].steps, 1);
+            assert!(!reports[name].is_done());
+            assert_eq!(ms.get(name).unwrap().unwrap().lock().unwrap().queued(), 36);
+        }
+        for _ in 0..10 {
+            ms.maintain_all(Duration::ZERO).unwrap();
+        }
+        for (name, id) in pending {
+            let db = ms.get(name).unwrap().unwrap();
+            assert!(db.lock().unwrap().resolve(id).is_ok());
+            assert_eq!(db.lock().unwrap().queued(), 0);
+        }
+        let reports = ms.maintain_all(Duration::from_secs(60)).unwrap();
+        assert!(reports.values().all(|report| report.steps == 0));
+    }
+
+    #[test]
+    fn memory_budget_works() {
+        let mut ms = ManagementSystem::new_mem()
+            .with_memory_budget(2500)
+            .with_cache_floor(960);
+        let created = ["a", "b", "c"].map(|name| ms.create(name, 4).unwrap());
+        for (db, count) in created.into_iter().zip([100, 100, 10]) {
+            for i in 0..count {
+                db.lock().unwrap().push([i as f32; 4]).unwrap();
+            }
+        }
+        // least recently used first: b, a, c
+        let b = ms.get("b").unwrap().unwrap();
+        let a = Arc::downgrade(&ms.get("a").unwrap().unwrap());
+        let c = ms.get("c").unwrap().unwrap();
+        let stats = ms.stats();
+        assert_eq!((stats.loaded, stats.memory_usage), (3, 3360));
+
+        // shrinking b and a is enough
+        ms.maintain_all(Duration::ZERO).unwrap();
+        let stats = ms.stats();
+        assert_eq!((stats.shrunk, stats.unloaded, stats.memory_usage), (2, 0, 2080));
+        assert_eq!(b.lock().unwrap().cache_mode(), CacheMode::Bounded(960));
+        assert_eq!(c.lock().unwrap().cache_mode(), CacheMode::Unbounded);
+
+        for i in 10..100 {
+            c.lock().unwrap().push([i as f32; 4]).unwrap();
+        }
+        drop(c);
+        // c is shrunk too, then a is unloaded rather than b, which is held
+        ms.maintain_all(Duration::ZERO).unwrap();
+        let stats = ms.stats();
+        assert_eq!((stats.shrunk, stats.unloaded), (3, 1));
+        assert_eq!((stats.loaded, stats.memory_usage), (2, 1920));
+        assert!(a.upgrade().is_none());
+        assert_eq!(b.lock().unwrap().memory_usage(), 960);
+
+        let a = ms.get("a").unwrap().unwrap();
+        assert_eq!(a.lock().unwrap().count().unwrap(), 100);
+        assert!(ms.stats().memory_usage <= 2500);
+    }
+
+    #[cfg(feature = "background")]
+    #[test]
+    fn maintenance_thread_works() {
+        let mut ms = ManagementSystem::new_mem();
+        let db = ms.create("mem", 4).unwrap();
+        let ms = std::sync::Arc::new(ms);
+        for i in 0..100 {
+            db.lock().unwrap().push_async_queued([i as f32; 4]).unwrap();
+        }
+        let thread = ms.spawn_maintenance(Duration::from_millis(1), Duration::ZERO);
+        for _ in 0..1000 {
+            if db.lock().unwrap().queued() == 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
         }
+        drop(thread);
+        assert_eq!(db.lock().unwrap().queued(), 0);
+        assert_eq!(db.lock().unwrap().count().unwrap(), 100);
     }
 }
@@ -0,0 +1,56 @@
+use crate::db::{DbVector, DbVectorSlice};
+
+/// Component-wise sum of two vectors of the same dimension.
+pub fn add(a: DbVectorSlice, b: DbVectorSlice) -> DbVector {
+    a.iter().zip(b).map(|(x, y)| x + y).collect()
+}
+
+/// Component-wise difference of two vectors of the same dimension.
+pub fn sub(a: DbVectorSlice, b: DbVectorSlice) -> DbVector {
+    a.iter().zip(b).map(|(x, y)| x - y).collect()
+}
+
+/// Component-wise average, or an empty vector if there's nothing to average.
+pub fn mean(vectors: &[DbVectorSlice]) -> DbVector {
+    let Some(first) = vectors.first() else {
+        return vec![];
+    };
+    let mut sum = vec![0f32; first.len()];
+    for v in vectors {
+        for (s, x) in sum.iter_mut().zip(v.iter()) {
+            *s += x;
+        }
+    }
+    sum.iter().map(|s| s / vectors.len() as f32).collect()
+}
+
+/// Scales `vector` to unit length in place. Zero vectors are left untouched.
+pub fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0f32 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ops::{add, mean, normalize, sub};
+
+    #[test]
+    fn arithmetic_works() {
+        assert_eq!(add(&[1f32, 2f32], &[3f32, 4f32]), vec![4f32, 6f32]);
+        assert_eq!(sub(&[1f32, 2f32], &[3f32, 5f32]), vec![-2f32, -3f32]);
+        assert_eq!(
+            mean(&[&[1f32, 2f32], &[3f32, 6f32], &[5f32, 1f32]]),
+            vec![3f32, 3f32]
+        );
+        assert!(mean(&[]).is_empty());
+
+        let mut v = vec![3f32, 4f32];
+        normalize(&mut v);
+        assert_eq!(v, vec![0.6f32, 0.8f32]);
+        let mut zero = vec![0f32; 2];
+        normalize(&mut zero);
+        assert_eq!(zero, vec![0f32; 2]);
+    }
+}
@@ -1,16 +1,90 @@
+use std::fs::File;
 use std::io;
-use std::io::{Read, Seek, Write};
+use std::io::{Cursor, Read, Seek, Write};
 
+pub(crate) mod bloom;
+pub(crate) mod crc;
 pub(crate) mod layer;
 pub(crate) mod dbheader;
+pub(crate) mod keys;
+pub(crate) mod groups;
+pub(crate) mod levels;
+pub(crate) mod placeholders;
+pub mod format;
+mod inspect;
+mod migrate;
+pub(crate) mod varint;
 pub(crate) mod vector;
 
-pub(crate) trait RandomAccess: Read + Write + Seek {}
-impl<T: Read + Write + Seek> RandomAccess for T {}
+pub use layer::SkippedLayer;
+pub use inspect::{inspect, FileLayout, HeaderField, Inconsistency, LayerBlock, RecordEntry};
+pub use migrate::{migrate, MigrationReport, MigrationStep};
+
+pub trait RandomAccess: Read + Write + Seek + Truncate + SyncData + Send {}
+impl<T: Read + Write + Seek + Truncate + SyncData + Send> RandomAccess for T {}
+
+/// Storage that can make written bytes survive a power loss.
+/// Those with nothing to persist, like memory, keep the default no-op.
+pub trait SyncData {
+    fn sync_data(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SyncData for File {
+    fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+impl SyncData for Cursor<Vec<u8>> {}
+
+impl<T: SyncData + ?Sized> SyncData for Box<T> {
+    fn sync_data(&mut self) -> io::Result<()> {
+        (**self).sync_data()
+    }
+}
+
+impl<T: SyncData + ?Sized> SyncData for &mut T {
+    fn sync_data(&mut self) -> io::Result<()> {
+        (**self).sync_data()
+    }
+}
+
+/// Storage that can be cut short, so that removing
+/// records actually shrinks it.
+pub trait Truncate {
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl Truncate for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+}
+
+impl Truncate for Cursor<Vec<u8>> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().truncate(len as usize);
+        Ok(())
+    }
+}
+
+impl<T: Truncate + ?Sized> Truncate for Box<T> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        (**self).set_len(len)
+    }
+}
+
+impl<T: Truncate + ?Sized> Truncate for &mut T {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        (**self).set_len(len)
+    }
+}
 
 #[derive(Debug)]
-pub(crate) enum Error {
-    EOF,
+pub enum Error {
+    Eof,
     IO(io::Error),
 }
 
@@ -0,0 +1,35 @@
+use crate::ds::bloom::{BloomFilter, BloomParams};
+use crate::vio::format::{BLOOM_FRESH, BLOOM_STALE};
+use crate::vio::{Error, RandomAccess};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+
+
+/// Bytes taken by the block right after the header: a byte telling
+/// whether the filter is fresh, then its bits as big endian words.
+pub(crate) fn block_size(params: BloomParams) -> u64 {
+    1 + params.bits / 8
+}
+
+/// Reads the block at the current position, or `None` if the records
+/// have changed since it was written and the bits can't be trusted.
+pub(crate) fn read(fd: &mut dyn RandomAccess, params: BloomParams) -> Result<Option<BloomFilter>, Error> {
+    let fresh = fd.read_u8().map_err(Error::IO)? == BLOOM_FRESH;
+    let mut words = vec![0u64; params.words()];
+    fd.read_u64_into::<BigEndian>(&mut words).map_err(Error::IO)?;
+    Ok(fresh.then(|| BloomFilter::from_words(params, words)))
+}
+
+/// Writes `filter` as a fresh block at the current position.
+pub(crate) fn write(fd: &mut dyn RandomAccess, filter: &BloomFilter) -> io::Result<u64> {
+    fd.write_u8(BLOOM_FRESH)?;
+    for word in filter.words() {
+        fd.write_u64::<BigEndian>(*word)?;
+    }
+    Ok(block_size(filter.params()))
+}
+
+/// Flags the block at the current position as outdated.
+pub(crate) fn mark_stale(fd: &mut dyn RandomAccess) -> io::Result<()> {
+    fd.write_u8(BLOOM_STALE)
+}
@@ -0,0 +1,43 @@
+/// Remainders of every byte, for the reflected polynomial `0xEDB88320`.
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 of `parts` one after another, the same as zlib and PNG use.
+pub(crate) fn crc32(parts: &[&[u8]]) -> u32 {
+    parts.iter().fold(0, |crc, part| crc32_continue(crc, part))
+}
+
+/// CRC-32 of whatever `crc` was of followed by `bytes`.
+pub(crate) fn crc32_continue(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |crc, byte| {
+        TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vio::crc::{crc32, crc32_continue};
+
+    #[test]
+    fn crc32_works() {
+        assert_eq!(crc32(&[]), 0);
+        assert_eq!(crc32(&[b"123456789"]), 0xCBF43926);
+        assert_eq!(crc32(&[b"1234", b"", b"56789"]), 0xCBF43926);
+        assert_eq!(crc32_continue(crc32(&[b"1234"]), b"56789"), 0xCBF43926);
+    }
+}
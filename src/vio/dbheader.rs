@@ -1,29 +1,58 @@
-use crate::vio::RandomAccess;
+use crate::db::{Generation, HnswConfig, IdStrategy, KeyTableParams, Quota, SearchParams};
+use crate::ds::bloom::BloomParams;
+use crate::metric::Metric;
+use crate::vio::format::{
+    self, CURRENT_VERSION, FIELD_ID_STRATEGY, FIELD_PROPERTY_COUNT, HEADER_CHECKSUM_WIDTH,
+    HEADER_SEQUENCE_WIDTH, HEADER_SLOTS_SINCE, HEADER_SLOT_SIZE, PRODUCT, PROPERTY_ALIGNMENT,
+    PROPERTY_BLOOM, PROPERTY_GROUPS, PROPERTY_HISTORY, PROPERTY_HNSW, PROPERTY_KEYS,
+    PROPERTY_LEVELS, PROPERTY_MAX_BYTES, PROPERTY_MAX_VECTORS,
+    PROPERTY_LAYER_SECTION, PROPERTY_METRIC, PROPERTY_PLACEHOLDERS, PROPERTY_REUSE_SLOTS, PROPERTY_SEARCH,
+};
+use crate::vio::{bloom, crc, groups, keys, levels, placeholders, RandomAccess};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::fmt::Formatter;
-use std::io::Write;
-use std::str::FromStr;
+use std::io::{Cursor, Read, SeekFrom, Write};
 use std::{fmt, io};
 
+/// Bytes found in place of the product name shown at most.
+const PRODUCT_PREVIEW: usize = 16;
+
 #[derive(Debug)]
-pub(crate) enum ParseErrorReason {
-    ProductNameMismatch(String),
-    StringDecodeFailed,
+pub enum ParseErrorReason {
+    /// Holds the first bytes found in its place, fewer if the file ends first.
+    ProductNameMismatch(Vec<u8>),
+    UnknownIdStrategy(u8),
+    /// Neither header slot holds a header that checks out.
+    NoValidHeaderSlot,
+    /// Holds the version found, which is either newer than this build
+    /// or none there ever was.
+    UnsupportedVersion(u8),
 }
 
 impl fmt::Display for ParseErrorReason {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            ParseErrorReason::ProductNameMismatch(name) => {
-                write!(f, "unknown product name ({name})")
+            ParseErrorReason::ProductNameMismatch(found) if found.is_empty() => {
+                write!(f, "missing product name")
+            }
+            ParseErrorReason::ProductNameMismatch(found) => {
+                write!(f, "unknown product name (starting")?;
+                for b in found {
+                    write!(f, " {b:02x}")?;
+                }
+                write!(f, ")")
+            }
+            ParseErrorReason::UnknownIdStrategy(s) => write!(f, "unknown id strategy ({s})"),
+            ParseErrorReason::NoValidHeaderSlot => write!(f, "no valid header slot"),
+            ParseErrorReason::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {version} (up to {CURRENT_VERSION})")
             }
-            ParseErrorReason::StringDecodeFailed => write!(f, "string decode failed"),
         }
     }
 }
 
 #[derive(Debug)]
-pub(crate) enum Error {
+pub enum Error {
     IO(io::Error),
     Parse(ParseErrorReason),
 }
@@ -37,56 +66,723 @@ impl fmt::Display for Error {
     }
 }
 
-const PRODUCT: &str = "vectoriadb;version";
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IO(e) => Some(e),
+            Error::Parse(_) => None,
+        }
+    }
+}
+
 type VersionNumber = u8;
 type DimSize = u32;
 type DataSection = u64;
-pub(crate) const CURRENT_VERSION: VersionNumber = 1u8;
 
+#[derive(Clone)]
 pub(crate) struct DbHeader {
     pub version: VersionNumber,
     pub dim_size: DimSize,
     pub data_section: DataSection,
+    /// Since version 2. Older files always allocated monotonically.
+    pub id_strategy: IdStrategy,
+    pub quota: Quota,
+    pub bloom: Option<BloomParams>,
+    /// Horizon of the history if the database is append-only.
+    pub history: Option<Generation>,
+    pub alignment: Option<u32>,
+    /// Absent in files from before version 7, which keep the defaults.
+    pub search: Option<SearchParams>,
+    /// Absent in files from before version 8.
+    pub metric: Option<Metric>,
+    /// Whether the append-only records are written into free slots,
+    /// see [PROPERTY_REUSE_SLOTS]. Never in files from before version 11.
+    pub reuse_slots: bool,
+    /// Present if keys are kept along with the records,
+    /// see [PROPERTY_KEYS]. Never in files from before version 12.
+    pub keys: Option<KeyTableParams>,
+    /// Entries the level table holds if there's one,
+    /// see [PROPERTY_LEVELS]. Never in files from before version 13.
+    pub levels: Option<u32>,
+    /// Ids the window of the placeholder table holds if there's one,
+    /// see [PROPERTY_PLACEHOLDERS]. Never in files from before version 14.
+    pub placeholders: Option<u32>,
+    /// Entries the group table holds if there's one,
+    /// see [PROPERTY_GROUPS]. Never in files from before version 19.
+    pub groups: Option<u32>,
+    /// Bytes reserved for the layers if they are, see [PROPERTY_LAYER_SECTION].
+    /// Never in files from before version 15.
+    pub layer_section: Option<u64>,
+    /// What the index is built with, see [PROPERTY_HNSW]. Absent in files
+    /// from before version 16, which leave it to the options, and without
+    /// the level multiplier in those from before version 18.
+    pub hnsw: Option<HnswConfig>,
+}
+
+/// The version is the single byte right after the product name,
+/// see [encode_version].
+pub(crate) fn decode_version(raw: u8) -> VersionNumber {
+    raw.wrapping_sub(b'0')
+}
+
+/// Same single byte as the decimal digit up to version 9,
+/// and the characters following it beyond.
+pub(crate) fn encode_version(version: VersionNumber) -> u8 {
+    b'0'.wrapping_add(version)
 }
 
+/// Reads the header at the start of `fd`, from the slot in use since
+/// [HEADER_SLOTS_SINCE], leaving `fd` right after it.
 pub(crate) fn read(fd: &mut dyn RandomAccess) -> Result<DbHeader, Error> {
-    let mut product_buf = [0u8; PRODUCT.len()];
-    fd.read_exact(&mut product_buf).map_err(|e| Error::IO(e))?;
-    let product_name = std::str::from_utf8(&product_buf)
-        .map_err(|e| Error::Parse(ParseErrorReason::StringDecodeFailed))?;
+    // the product and version are the same in both slots,
+    // and overwritten with the same bytes, so a torn write can't touch them
+    fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+    let version = read_version(fd)?;
+    fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+    if version < HEADER_SLOTS_SINCE {
+        return read_fields(fd);
+    }
+    let slots = read_slots(fd).map_err(Error::IO)?;
+    let slot = active_slot(&slots).ok_or(Error::Parse(ParseErrorReason::NoValidHeaderSlot))?;
+    let header = read_fields(&mut Cursor::new(slot.bytes.clone()))?;
+    fd.seek(SeekFrom::Start(2 * HEADER_SLOT_SIZE))
+        .map_err(Error::IO)?;
+    Ok(header)
+}
 
-    if product_name != PRODUCT {
-        return Err(Error::Parse(ParseErrorReason::ProductNameMismatch(
-            String::from_str(product_name).unwrap(),
-        )));
+/// Reads the product name and the version after it, failing with
+/// [ParseErrorReason::UnsupportedVersion] unless this build knows the version.
+fn read_version(fd: &mut dyn RandomAccess) -> Result<VersionNumber, Error> {
+    read_product(fd)?;
+    let version = decode_version(fd.read_u8().map_err(Error::IO)?);
+    if !(1..=CURRENT_VERSION).contains(&version) {
+        return Err(Error::Parse(ParseErrorReason::UnsupportedVersion(version)));
     }
+    Ok(version)
+}
+
+/// Reads the [PRODUCT] name a header starts with, failing with
+/// [ParseErrorReason::ProductNameMismatch] on anything else,
+/// however short or binary it is.
+pub(crate) fn read_product(fd: &mut dyn RandomAccess) -> Result<(), Error> {
+    let mut found = Vec::with_capacity(PRODUCT.len());
+    (&mut *fd)
+        .take(PRODUCT.len() as u64)
+        .read_to_end(&mut found)
+        .map_err(Error::IO)?;
+    if found != PRODUCT.as_bytes() {
+        found.truncate(PRODUCT_PREVIEW);
+        return Err(Error::Parse(ParseErrorReason::ProductNameMismatch(found)));
+    }
+    Ok(())
+}
 
-    let version = fd.read_u8().map_err(|e| Error::IO(e))?;
-    let data_section = fd.read_u64::<BigEndian>().map_err(|e| Error::IO(e))?;
-    let dim_size = fd.read_u32::<BigEndian>().map_err(|e| Error::IO(e))?;
+/// # Header Slot
+/// One of the two copies of the header since [HEADER_SLOTS_SINCE].
+pub(crate) struct HeaderSlot {
+    pub offset: u64,
+    pub sequence: u64,
+    /// Whether it was written in whole, its checksum matching.
+    pub valid: bool,
+    /// The whole slot, checksum and sequence number included.
+    pub bytes: Vec<u8>,
+}
+
+/// Reads both header slots, either of which may be short or missing
+/// in a file cut short, which makes it invalid.
+pub(crate) fn read_slots(fd: &mut dyn RandomAccess) -> io::Result<[HeaderSlot; 2]> {
+    let mut read = |offset: u64| -> io::Result<HeaderSlot> {
+        fd.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![];
+        fd.take(HEADER_SLOT_SIZE).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != HEADER_SLOT_SIZE {
+            return Ok(HeaderSlot {
+                offset,
+                sequence: 0,
+                valid: false,
+                bytes,
+            });
+        }
+        let (body, trailer) = bytes.split_at((HEADER_SLOT_SIZE - SLOT_TRAILER) as usize);
+        let (checksum, sequence) = trailer.split_at(HEADER_CHECKSUM_WIDTH as usize);
+        let checksum = u32::from_be_bytes(checksum.try_into().unwrap());
+        let valid = crc::crc32(&[body, sequence]) == checksum;
+        let sequence = u64::from_be_bytes(sequence.try_into().unwrap());
+        Ok(HeaderSlot {
+            offset,
+            sequence,
+            valid: valid && sequence > 0,
+            bytes,
+        })
+    };
+    Ok([read(0)?, read(HEADER_SLOT_SIZE)?])
+}
+
+/// The valid slot with the higher sequence, which is the one in use.
+pub(crate) fn active_slot(slots: &[HeaderSlot; 2]) -> Option<&HeaderSlot> {
+    slots.iter().filter(|s| s.valid).max_by_key(|s| s.sequence)
+}
+
+/// Checksum and sequence number closing a header slot.
+const SLOT_TRAILER: u64 = HEADER_CHECKSUM_WIDTH + HEADER_SEQUENCE_WIDTH;
+
+fn read_fields(fd: &mut dyn RandomAccess) -> Result<DbHeader, Error> {
+    let version = read_version(fd)?;
+    let data_section = fd.read_u64::<BigEndian>().map_err(Error::IO)?;
+    let dim_size = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+    let id_strategy = if version >= FIELD_ID_STRATEGY.since {
+        let raw = fd.read_u8().map_err(Error::IO)?;
+        IdStrategy::from_byte(raw)
+            .ok_or(Error::Parse(ParseErrorReason::UnknownIdStrategy(raw)))?
+    } else {
+        IdStrategy::Monotonic
+    };
+    let mut quota = Quota::default();
+    let mut bloom = None;
+    let mut history = None;
+    let mut alignment = None;
+    let mut search = None;
+    let mut metric = None;
+    let mut reuse_slots = false;
+    let mut keys = None;
+    let mut levels = None;
+    let mut placeholders = None;
+    let mut groups = None;
+    let mut layer_section = None;
+    let mut hnsw = None;
+    if version >= FIELD_PROPERTY_COUNT.since {
+        for (tag, value) in read_properties(fd).map_err(Error::IO)? {
+            match (tag, value.try_into()) {
+                (PROPERTY_MAX_VECTORS, Ok(bytes)) => {
+                    quota.max_vectors = Some(u64::from_be_bytes(bytes))
+                }
+                (PROPERTY_MAX_BYTES, Ok(bytes)) => quota.max_bytes = Some(u64::from_be_bytes(bytes)),
+                (PROPERTY_BLOOM, Err(value)) => bloom = decode_bloom(&value),
+                (PROPERTY_HISTORY, Ok(bytes)) => history = Some(u64::from_be_bytes(bytes)),
+                (PROPERTY_ALIGNMENT, Err(value)) => alignment = decode_alignment(&value),
+                (PROPERTY_SEARCH, Err(value)) => search = decode_search(&value),
+                (PROPERTY_METRIC, Err(value)) if value.len() == 1 => {
+                    metric = Metric::from_byte(value[0])
+                }
+                (PROPERTY_REUSE_SLOTS, _) => reuse_slots = true,
+                (PROPERTY_KEYS, Err(value)) => keys = decode_keys(&value),
+                (PROPERTY_LEVELS, Err(value)) => {
+                    levels = value.try_into().ok().map(u32::from_be_bytes)
+                }
+                (PROPERTY_PLACEHOLDERS, Err(value)) => {
+                    placeholders = value.try_into().ok().map(u32::from_be_bytes)
+                }
+                (PROPERTY_GROUPS, Err(value)) => {
+                    groups = value.try_into().ok().map(u32::from_be_bytes)
+                }
+                (PROPERTY_LAYER_SECTION, Ok(bytes)) => {
+                    layer_section = Some(u64::from_be_bytes(bytes))
+                }
+                (PROPERTY_HNSW, Err(value)) => hnsw = decode_hnsw(&value),
+                _ => {}
+            }
+        }
+    }
     Ok(DbHeader {
         dim_size,
         data_section,
         version,
+        id_strategy,
+        quota,
+        bloom,
+        history,
+        alignment,
+        search,
+        metric,
+        reuse_slots,
+        keys,
+        levels,
+        placeholders,
+        groups,
+        layer_section,
+        hnsw,
     })
 }
 
+pub(crate) fn decode_alignment(value: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(value.try_into().ok()?)).filter(|a| *a > 0)
+}
+
+/// Rounds `offset` up to the next multiple of `alignment`, if any.
+pub(crate) fn align(offset: u64, alignment: Option<u32>) -> u64 {
+    match alignment {
+        None => offset,
+        Some(alignment) => offset.next_multiple_of(alignment as u64),
+    }
+}
+
+/// Unbounded `max_visited` is written as zero.
+pub(crate) fn encode_search(params: &SearchParams) -> Vec<u8> {
+    let mut value = Vec::from(params.ef_search.to_be_bytes());
+    value.extend(params.max_visited.unwrap_or(0).to_be_bytes());
+    value.extend(params.rerank_factor.to_be_bytes());
+    value
+}
+
+pub(crate) fn decode_search(value: &[u8]) -> Option<SearchParams> {
+    let (ef_search, rest) = value.split_first_chunk::<4>()?;
+    let (max_visited, rest) = rest.split_first_chunk::<8>()?;
+    let rerank_factor: [u8; 4] = rest.try_into().ok()?;
+    Some(SearchParams {
+        ef_search: u32::from_be_bytes(*ef_search),
+        max_visited: Some(u64::from_be_bytes(*max_visited)).filter(|m| *m > 0),
+        rerank_factor: u32::from_be_bytes(rerank_factor),
+    })
+}
+
+pub(crate) fn encode_hnsw(config: &HnswConfig) -> Vec<u8> {
+    let mut value = Vec::from(config.m.to_be_bytes());
+    value.extend(config.m0.unwrap_or(0).to_be_bytes());
+    value.extend(config.ef_construction.to_be_bytes());
+    value.push(config.diverse_neighbors as u8);
+    value.extend(config.level_multiplier.unwrap_or(0f32).to_be_bytes());
+    value
+}
+
+/// Leaves what isn't written to the defaults, including the level
+/// multiplier of files that recorded the rest without it.
+pub(crate) fn decode_hnsw(value: &[u8]) -> Option<HnswConfig> {
+    let (m, rest) = value.split_first_chunk::<4>()?;
+    let (m0, rest) = rest.split_first_chunk::<4>()?;
+    let (ef_construction, rest) = rest.split_first_chunk::<4>()?;
+    let (diverse, multiplier) = rest.split_first()?;
+    let level_multiplier = match multiplier {
+        [] => None,
+        multiplier => Some(f32::from_be_bytes(multiplier.try_into().ok()?)),
+    };
+    Some(HnswConfig {
+        m: u32::from_be_bytes(*m),
+        m0: Some(u32::from_be_bytes(*m0)).filter(|m0| *m0 > 0),
+        ef_construction: u32::from_be_bytes(*ef_construction),
+        diverse_neighbors: *diverse != 0,
+        level_multiplier: level_multiplier.filter(|multiplier| *multiplier != 0f32),
+        ..HnswConfig::default()
+    })
+}
+
+pub(crate) fn decode_bloom(value: &[u8]) -> Option<BloomParams> {
+    let (bits, hashes) = value.split_first_chunk::<8>()?;
+    match hashes {
+        [hashes] => Some(BloomParams {
+            bits: u64::from_be_bytes(*bits),
+            hashes: *hashes,
+        }),
+        _ => None,
+    }
+}
+
+pub(crate) fn decode_keys(value: &[u8]) -> Option<KeyTableParams> {
+    let (capacity, max_key_bytes) = value.split_first_chunk::<4>()?;
+    match max_key_bytes {
+        [max_key_bytes] => Some(KeyTableParams {
+            capacity: u32::from_be_bytes(*capacity),
+            max_key_bytes: *max_key_bytes,
+        }),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_properties(fd: &mut dyn RandomAccess) -> io::Result<Vec<(u8, Vec<u8>)>> {
+    let count = fd.read_u8()?;
+    (0..count)
+        .map(|_| {
+            let tag = fd.read_u8()?;
+            let mut value = vec![0u8; fd.read_u8()? as usize];
+            fd.read_exact(&mut value)?;
+            Ok((tag, value))
+        })
+        .collect()
+}
+
+/// Size of a header without any properties.
+pub(crate) fn header_size() -> u64 {
+    format::header_size(CURRENT_VERSION)
+}
+
 impl DbHeader {
-    pub(crate) fn new(dim_size: DimSize) -> DbHeader {
-        DbHeader {
+    pub(crate) fn new(dim_size: DimSize, id_strategy: IdStrategy, quota: Quota) -> DbHeader {
+        let mut header = DbHeader {
             version: CURRENT_VERSION,
             dim_size,
-            data_section: (PRODUCT.len()
-                + size_of::<VersionNumber>()
-                + size_of::<DimSize>()
-                + size_of::<DataSection>()) as u64,
+            data_section: 0,
+            id_strategy,
+            quota,
+            bloom: None,
+            history: None,
+            alignment: None,
+            search: Some(SearchParams::default()),
+            metric: None,
+            reuse_slots: false,
+            keys: None,
+            levels: None,
+            placeholders: None,
+            groups: None,
+            layer_section: None,
+            hnsw: Some(HnswConfig::default()),
+        };
+        header.data_section = header.size();
+        header
+    }
+
+    /// Reserves a bloom filter block between the header and the layers.
+    pub(crate) fn with_bloom(mut self, params: BloomParams) -> DbHeader {
+        self.bloom = Some(params);
+        self.locate_data_section();
+        self
+    }
+
+    /// Reserves a key table block between the bloom filter block and the layers.
+    pub(crate) fn with_keys(mut self, params: KeyTableParams) -> DbHeader {
+        self.keys = Some(params);
+        self.locate_data_section();
+        self
+    }
+
+    /// Reserves a level table block of `capacity` entries between the key table block and the layers.
+    pub(crate) fn with_levels(mut self, capacity: u32) -> DbHeader {
+        self.levels = Some(capacity);
+        self.locate_data_section();
+        self
+    }
+
+    /// Reserves a placeholder table block of `capacity` ids between the level table block and the layers.
+    pub(crate) fn with_placeholders(mut self, capacity: u32) -> DbHeader {
+        self.placeholders = Some(capacity);
+        self.locate_data_section();
+        self
+    }
+
+    /// Reserves a group table block of `capacity` entries between the
+    /// placeholder table block and the layers.
+    pub(crate) fn with_groups(mut self, capacity: u32) -> DbHeader {
+        self.groups = Some(capacity);
+        self.locate_data_section();
+        self
+    }
+
+    /// Reserves `bytes` for the layers between the group table block and the data section.
+    pub(crate) fn with_layer_section(mut self, bytes: u64) -> DbHeader {
+        self.layer_section = Some(bytes);
+        self.locate_data_section();
+        self
+    }
+
+    /// Makes the database append-only, with nothing compacted yet.
+    pub(crate) fn with_history(mut self) -> DbHeader {
+        self.history = Some(0);
+        self.locate_data_section();
+        self
+    }
+
+    /// Makes the append-only database write into the slots of records
+    /// no longer read, see [PROPERTY_REUSE_SLOTS].
+    pub(crate) fn with_reuse_slots(mut self) -> DbHeader {
+        self.reuse_slots = true;
+        self.locate_data_section();
+        self
+    }
+
+    /// Pads records to multiples of `alignment` bytes. Zero means no padding.
+    pub(crate) fn with_alignment(mut self, alignment: u32) -> DbHeader {
+        self.alignment = (alignment > 0).then_some(alignment);
+        self.locate_data_section();
+        self
+    }
+
+    pub(crate) fn with_metric(mut self, metric: Metric) -> DbHeader {
+        self.metric = Some(metric);
+        self.locate_data_section();
+        self
+    }
+
+    /// Puts the data section after the header, bloom filter, key table, level
+    /// table, placeholder table, group table and the layer section, aligned.
+    fn locate_data_section(&mut self) {
+        self.data_section = align(self.prefix_end() + self.layer_section.unwrap_or(0), self.alignment);
+    }
+
+    /// Where the header, bloom filter, key table, level table,
+    /// placeholder table and group table blocks end.
+    pub(crate) fn prefix_end(&self) -> u64 {
+        self.groups_offset() + self.groups.map_or(0, groups::block_size)
+    }
+
+    /// Where the group table block begins, right after the placeholder table block.
+    pub(crate) fn groups_offset(&self) -> u64 {
+        self.placeholders_offset() + self.placeholders.map_or(0, placeholders::block_size)
+    }
+
+    /// Where the placeholder table block begins, right after the level table block.
+    pub(crate) fn placeholders_offset(&self) -> u64 {
+        self.levels_offset() + self.levels.map_or(0, levels::block_size)
+    }
+
+    /// Where the level table block begins, right after the key table block.
+    pub(crate) fn levels_offset(&self) -> u64 {
+        self.keys_offset() + self.keys.map_or(0, keys::block_size)
+    }
+
+    /// Where the key table block begins, right after the bloom filter block.
+    pub(crate) fn keys_offset(&self) -> u64 {
+        self.size() + self.bloom.map_or(0, bloom::block_size)
+    }
+
+    fn properties(&self) -> Vec<(u8, Vec<u8>)> {
+        let mut properties = vec![];
+        if let Some(max) = self.quota.max_vectors {
+            properties.push((PROPERTY_MAX_VECTORS, Vec::from(max.to_be_bytes())));
+        }
+        if let Some(max) = self.quota.max_bytes {
+            properties.push((PROPERTY_MAX_BYTES, Vec::from(max.to_be_bytes())));
+        }
+        if let Some(params) = self.bloom {
+            let mut value = Vec::from(params.bits.to_be_bytes());
+            value.push(params.hashes);
+            properties.push((PROPERTY_BLOOM, value));
+        }
+        if let Some(horizon) = self.history {
+            properties.push((PROPERTY_HISTORY, Vec::from(horizon.to_be_bytes())));
+        }
+        if let Some(alignment) = self.alignment {
+            properties.push((PROPERTY_ALIGNMENT, Vec::from(alignment.to_be_bytes())));
+        }
+        if let Some(search) = &self.search {
+            properties.push((PROPERTY_SEARCH, encode_search(search)));
+        }
+        if let Some(metric) = self.metric {
+            properties.push((PROPERTY_METRIC, vec![metric.to_byte()]));
+        }
+        if self.reuse_slots {
+            properties.push((PROPERTY_REUSE_SLOTS, vec![]));
+        }
+        if let Some(params) = self.keys {
+            let mut value = Vec::from(params.capacity.to_be_bytes());
+            value.push(params.max_key_bytes);
+            properties.push((PROPERTY_KEYS, value));
         }
+        if let Some(capacity) = self.levels {
+            properties.push((PROPERTY_LEVELS, Vec::from(capacity.to_be_bytes())));
+        }
+        if let Some(capacity) = self.placeholders {
+            properties.push((PROPERTY_PLACEHOLDERS, Vec::from(capacity.to_be_bytes())));
+        }
+        if let Some(capacity) = self.groups {
+            properties.push((PROPERTY_GROUPS, Vec::from(capacity.to_be_bytes())));
+        }
+        if let Some(bytes) = self.layer_section {
+            properties.push((PROPERTY_LAYER_SECTION, Vec::from(bytes.to_be_bytes())));
+        }
+        if let Some(config) = &self.hnsw {
+            properties.push((PROPERTY_HNSW, encode_hnsw(config)));
+        }
+        properties
     }
 
+    /// Bytes the header takes in its version, both slots since [HEADER_SLOTS_SINCE].
+    pub(crate) fn size(&self) -> u64 {
+        if self.version >= HEADER_SLOTS_SINCE {
+            return 2 * HEADER_SLOT_SIZE;
+        }
+        if self.version < FIELD_PROPERTY_COUNT.since {
+            return format::header_size(self.version);
+        }
+        format::header_size(self.version)
+            + self
+                .properties()
+                .iter()
+                .map(|(_, value)| 2 + value.len() as u64)
+                .sum::<u64>()
+    }
+
+    /// Writes the header of a new file at the current position of `fd`,
+    /// into the first slot since [HEADER_SLOTS_SINCE], the second one left unwritten.
     pub(crate) fn write(&self, fd: &mut dyn RandomAccess) -> Result<(), Error> {
-        write!(fd, "{0}{1}", PRODUCT, self.version).map_err(|e| Error::IO(e))?;
-        fd.write_u64::<BigEndian>(self.data_section).map_err(|e| Error::IO(e))?;
-        fd.write_u32::<BigEndian>(self.dim_size).map_err(|e| Error::IO(e))?;
+        if self.version < HEADER_SLOTS_SINCE {
+            return self.write_fields(fd);
+        }
+        fd.write_all(&self.encode_slot(1)?).map_err(Error::IO)?;
+        fd.write_all(&[0u8; HEADER_SLOT_SIZE as usize])
+            .map_err(Error::IO)?;
         Ok(())
     }
+
+    /// Overwrites the header at the start of `fd`, returning the bytes written.
+    /// Since [HEADER_SLOTS_SINCE], that's the slot not in use, which is synced
+    /// before its sequence number is written to put it in use, so that the
+    /// previous header stays readable until then.
+    pub(crate) fn rewrite(&self, fd: &mut dyn RandomAccess) -> Result<u64, Error> {
+        if self.version < HEADER_SLOTS_SINCE {
+            fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+            self.write_fields(fd)?;
+            return Ok(self.size());
+        }
+        let slots = read_slots(fd).map_err(Error::IO)?;
+        let (offset, sequence) = match active_slot(&slots) {
+            Some(active) => (HEADER_SLOT_SIZE - active.offset, active.sequence),
+            None => (0, 0),
+        };
+        let slot = self.encode_slot(sequence + 1)?;
+        let (body, sequence) = slot.split_at((HEADER_SLOT_SIZE - HEADER_SEQUENCE_WIDTH) as usize);
+        fd.seek(SeekFrom::Start(offset)).map_err(Error::IO)?;
+        fd.write_all(body).map_err(Error::IO)?;
+        fd.sync_data().map_err(Error::IO)?;
+        fd.write_all(sequence).map_err(Error::IO)?;
+        Ok(HEADER_SLOT_SIZE)
+    }
+
+    /// The header zero padded, its checksum and `sequence`, filling a slot.
+    fn encode_slot(&self, sequence: u64) -> Result<Vec<u8>, Error> {
+        let mut slot = vec![];
+        self.write_fields(&mut slot)?;
+        if slot.len() as u64 > HEADER_SLOT_SIZE - SLOT_TRAILER {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "header outgrows its slot",
+            )));
+        }
+        slot.resize((HEADER_SLOT_SIZE - SLOT_TRAILER) as usize, 0);
+        let checksum = crc::crc32(&[&slot, &sequence.to_be_bytes()]);
+        slot.extend(checksum.to_be_bytes());
+        slot.extend(sequence.to_be_bytes());
+        Ok(slot)
+    }
+
+    fn write_fields(&self, fd: &mut dyn Write) -> Result<(), Error> {
+        fd.write_all(PRODUCT.as_bytes()).map_err(Error::IO)?;
+        fd.write_u8(encode_version(self.version)).map_err(Error::IO)?;
+        fd.write_u64::<BigEndian>(self.data_section).map_err(Error::IO)?;
+        fd.write_u32::<BigEndian>(self.dim_size).map_err(Error::IO)?;
+        if self.version < FIELD_ID_STRATEGY.since {
+            return Ok(());
+        }
+        fd.write_u8(self.id_strategy.to_byte()).map_err(Error::IO)?;
+        if self.version < FIELD_PROPERTY_COUNT.since {
+            return Ok(());
+        }
+        let properties = self.properties();
+        fd.write_u8(properties.len() as u8).map_err(Error::IO)?;
+        for (tag, value) in properties {
+            fd.write_u8(tag).map_err(Error::IO)?;
+            fd.write_u8(value.len() as u8).map_err(Error::IO)?;
+            fd.write_all(&value).map_err(Error::IO)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{HnswConfig, IdStrategy, Quota};
+    use crate::vio::dbheader::{
+        decode_hnsw, encode_hnsw, encode_version, read, DbHeader, Error, ParseErrorReason,
+    };
+    use crate::vio::format::{
+        CURRENT_VERSION, FIELD_VERSION, HEADER_SLOTS_SINCE, HEADER_SLOT_SIZE,
+    };
+    use std::io::Cursor;
+
+    fn reason(bytes: &[u8]) -> String {
+        match read(&mut Cursor::new(bytes.to_vec())) {
+            Err(Error::Parse(reason @ ParseErrorReason::ProductNameMismatch(_))) => {
+                reason.to_string()
+            }
+            Err(e) => panic!("expecting a product name mismatch, got {e}"),
+            Ok(_) => panic!("expecting a product name mismatch"),
+        }
+    }
+
+    #[test]
+    fn product_check_works() {
+        assert_eq!(reason(b""), "missing product name");
+        assert_eq!(
+            reason(b"id,0,1\n0,0.5,1\n"),
+            "unknown product name (starting 69 64 2c 30 2c 31 0a 30 2c 30 2e 35 2c 31 0a)"
+        );
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x10\0\0\0\x10\x08\x06";
+        assert_eq!(
+            reason(png),
+            "unknown product name (starting 89 50 4e 47 0d 0a 1a 0a 00 00 00 0d 49 48 44 52)"
+        );
+
+        let mut fd = Cursor::new(Vec::new());
+        DbHeader::new(4, IdStrategy::default(), Quota::default())
+            .write(&mut fd)
+            .unwrap();
+        let header = read(&mut Cursor::new(fd.into_inner())).unwrap();
+        assert_eq!(header.dim_size, 4);
+    }
+
+    #[test]
+    fn round_trip_works() {
+        for version in [1, 9, HEADER_SLOTS_SINCE, CURRENT_VERSION] {
+            let mut header = DbHeader::new(7, IdStrategy::default(), Quota::default());
+            header.version = version;
+            header.data_section = header.size();
+            let mut fd = Cursor::new(Vec::new());
+            header.write(&mut fd).unwrap();
+            // the version takes a single byte, leaving the fields after it in place
+            assert_eq!(fd.position(), header.size(), "version {version}");
+
+            let read = read(&mut Cursor::new(fd.into_inner())).unwrap();
+            assert_eq!(read.version, version);
+            assert_eq!(read.dim_size, 7);
+            assert_eq!(read.data_section, header.size(), "version {version}");
+        }
+        // laid out as created, the records right after the header
+        let header = DbHeader::new(7, IdStrategy::default(), Quota::default());
+        assert_eq!(header.data_section, header.size());
+
+        let header = header.with_layer_section(1000);
+        let mut fd = Cursor::new(Vec::new());
+        header.write(&mut fd).unwrap();
+        let read = read(&mut Cursor::new(fd.into_inner())).unwrap();
+        assert_eq!(read.layer_section, Some(1000));
+        assert_eq!(read.data_section, header.size() + 1000);
+    }
+
+    #[test]
+    fn hnsw_round_trip_works() {
+        let config = HnswConfig {
+            m0: Some(24),
+            level_multiplier: Some(0.4),
+            diverse_neighbors: false,
+            ..HnswConfig::default()
+        };
+        let value = encode_hnsw(&config);
+        assert_eq!(decode_hnsw(&value), Some(config));
+        // recorded before the level multiplier was
+        let older = decode_hnsw(&value[..13]).unwrap();
+        assert_eq!(older.level_multiplier, None);
+        assert_eq!(older.m0, config.m0);
+        assert_eq!(decode_hnsw(&value[..15]), None);
+    }
+
+    #[test]
+    fn unsupported_version_rejected() {
+        let mut fd = Cursor::new(Vec::new());
+        DbHeader::new(4, IdStrategy::default(), Quota::default())
+            .write(&mut fd)
+            .unwrap();
+        for version in [CURRENT_VERSION + 1, 0] {
+            let mut bytes = fd.get_ref().clone();
+            for slot in [0, HEADER_SLOT_SIZE] {
+                bytes[(slot + FIELD_VERSION.offset) as usize] = encode_version(version);
+            }
+            let read = read(&mut Cursor::new(bytes));
+            let Err(Error::Parse(reason @ ParseErrorReason::UnsupportedVersion(found))) = read
+            else {
+                panic!("version {version} not rejected");
+            };
+            assert_eq!(found, version);
+            assert_eq!(
+                reason.to_string(),
+                format!("unsupported version {version} (up to {CURRENT_VERSION})")
+            );
+        }
+    }
 }
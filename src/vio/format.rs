@@ -0,0 +1,405 @@
+use crate::db::{DatabaseOptions, DbIndex, Generation};
+use crate::ext::mem::SharedCursor;
+use crate::ext::rand::XorShift;
+
+/// Text every database file starts with, followed by its version as the
+/// single byte `b'0' + version`: a decimal digit up to 9, and the characters
+/// following it beyond, e.g. `'A'` for 17. See `dbheader::encode_version`.
+pub const PRODUCT: &str = "vectoriadb;version";
+pub const CURRENT_VERSION: u8 = 19;
+
+/// # Field
+/// Fixed-width part of the header, big endian unless noted.
+/// Offsets are from the start of the header slot since [HEADER_SLOTS_SINCE].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub offset: u64,
+    pub width: u64,
+    /// First version that has the field. Fields of later versions
+    /// are absent from older files, shifting nothing before them.
+    pub since: u8,
+}
+
+pub const FIELD_PRODUCT: Field = Field {
+    name: "product",
+    offset: 0,
+    width: PRODUCT.len() as u64,
+    since: 1,
+};
+/// Written as text, see [PRODUCT].
+pub const FIELD_VERSION: Field = Field {
+    name: "version",
+    offset: FIELD_PRODUCT.offset + FIELD_PRODUCT.width,
+    width: 1,
+    since: 1,
+};
+/// Where the records begin.
+pub const FIELD_DATA_SECTION: Field = Field {
+    name: "data_section",
+    offset: FIELD_VERSION.offset + FIELD_VERSION.width,
+    width: 8,
+    since: 1,
+};
+pub const FIELD_DIM_SIZE: Field = Field {
+    name: "dim_size",
+    offset: FIELD_DATA_SECTION.offset + FIELD_DATA_SECTION.width,
+    width: 4,
+    since: 1,
+};
+/// Zero for monotonic, one for reusing ids. Older files are monotonic.
+pub const FIELD_ID_STRATEGY: Field = Field {
+    name: "id_strategy",
+    offset: FIELD_DIM_SIZE.offset + FIELD_DIM_SIZE.width,
+    width: 1,
+    since: 2,
+};
+/// Number of [Property] entries that follow.
+pub const FIELD_PROPERTY_COUNT: Field = Field {
+    name: "property_count",
+    offset: FIELD_ID_STRATEGY.offset + FIELD_ID_STRATEGY.width,
+    width: 1,
+    since: 3,
+};
+
+/// Every fixed-width field of the header, in file order.
+pub const HEADER_FIELDS: [Field; 6] = [
+    FIELD_PRODUCT,
+    FIELD_VERSION,
+    FIELD_DATA_SECTION,
+    FIELD_DIM_SIZE,
+    FIELD_ID_STRATEGY,
+    FIELD_PROPERTY_COUNT,
+];
+
+/// Bytes the fixed-width fields of a `version` header take.
+pub const fn header_size(version: u8) -> u64 {
+    let mut size = 0;
+    let mut i = 0;
+    while i < HEADER_FIELDS.len() {
+        if HEADER_FIELDS[i].since <= version {
+            size += HEADER_FIELDS[i].width;
+        }
+        i += 1;
+    }
+    size
+}
+
+/// First version whose header is kept in two slots of [HEADER_SLOT_SIZE]
+/// bytes one after another, each holding the whole header, zero padded,
+/// followed by [HEADER_CHECKSUM_WIDTH] bytes of CRC-32 and the sequence
+/// number of the slot. Readers take the valid slot with the higher sequence,
+/// and writers overwrite the other one, its sequence number last, so that
+/// a write cut short leaves the previous header to read.
+/// The bloom filter block follows the second slot.
+pub const HEADER_SLOTS_SINCE: u8 = 10;
+pub const HEADER_SLOT_SIZE: u64 = 256;
+/// CRC-32 of the padded header followed by the sequence number.
+pub const HEADER_CHECKSUM_WIDTH: u64 = 4;
+/// Zero in a slot never written.
+pub const HEADER_SEQUENCE_WIDTH: u64 = 8;
+
+/// # Property
+/// Optional header entry, written as its tag, the width of its value
+/// and the value, so that readers skip the ones they don't know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Property {
+    pub tag: u8,
+    pub name: &'static str,
+    pub width: u8,
+    pub since: u8,
+}
+
+pub const PROPERTY_MAX_VECTORS: u8 = 1;
+pub const PROPERTY_MAX_BYTES: u8 = 2;
+/// Bits and hashes of the bloom filter block following the header.
+pub const PROPERTY_BLOOM: u8 = 3;
+/// Marks an append-only database, whose records carry a generation,
+/// and holds the generation its history was compacted up to.
+pub const PROPERTY_HISTORY: u8 = 4;
+/// Records and the data section start at multiples of it.
+pub const PROPERTY_ALIGNMENT: u8 = 5;
+/// Search parameters last set on the database, always written
+/// so that they can be updated in place.
+pub const PROPERTY_SEARCH: u8 = 6;
+/// Metric the database was created with, as a single byte.
+pub const PROPERTY_METRIC: u8 = 7;
+/// Marks an append-only database that writes into the slots of records no
+/// longer read instead of appending, taking their generations out of order.
+/// A record is no longer read once it's tagged removed and no older record
+/// of its id is left untagged. Has no value.
+pub const PROPERTY_REUSE_SLOTS: u8 = 8;
+/// Capacity and longest key of the key table block following
+/// the bloom filter block, see [KEYS_ENTRY_PREFIX_WIDTH].
+pub const PROPERTY_KEYS: u8 = 9;
+/// Capacity of the level table block following the key table block,
+/// see [LEVELS_ENTRY_MAX_WIDTH].
+pub const PROPERTY_LEVELS: u8 = 10;
+/// Capacity of the placeholder table block following the level table block,
+/// see [PLACEHOLDERS_BASE_WIDTH].
+pub const PROPERTY_PLACEHOLDERS: u8 = 11;
+/// Bytes reserved for the layers between the group table block
+/// and the data section, as u64.
+pub const PROPERTY_LAYER_SECTION: u8 = 12;
+/// Parameters the index is built with, always written
+/// so that they can be updated in place.
+pub const PROPERTY_HNSW: u8 = 13;
+/// Capacity of the group table block following the placeholder table block,
+/// see [GROUPS_ENTRY_MAX_WIDTH].
+pub const PROPERTY_GROUPS: u8 = 14;
+
+pub const PROPERTIES: [Property; 14] = [
+    Property {
+        tag: PROPERTY_MAX_VECTORS,
+        name: "max_vectors",
+        width: 8,
+        since: 3,
+    },
+    Property {
+        tag: PROPERTY_MAX_BYTES,
+        name: "max_bytes",
+        width: 8,
+        since: 3,
+    },
+    Property {
+        tag: PROPERTY_BLOOM,
+        name: "bloom",
+        // bits as u64, then hashes as u8
+        width: 9,
+        since: 4,
+    },
+    Property {
+        tag: PROPERTY_HISTORY,
+        name: "history",
+        width: 8,
+        since: 5,
+    },
+    Property {
+        tag: PROPERTY_ALIGNMENT,
+        name: "alignment",
+        width: 4,
+        since: 6,
+    },
+    Property {
+        tag: PROPERTY_SEARCH,
+        name: "search",
+        // ef u32, max visited u64 with zero for none, rerank factor u32
+        width: 16,
+        since: 7,
+    },
+    Property {
+        tag: PROPERTY_METRIC,
+        name: "metric",
+        width: 1,
+        since: 8,
+    },
+    Property {
+        tag: PROPERTY_REUSE_SLOTS,
+        name: "reuse_slots",
+        width: 0,
+        since: 11,
+    },
+    Property {
+        tag: PROPERTY_KEYS,
+        name: "keys",
+        // capacity u32, then the longest key as u8
+        width: 5,
+        since: 12,
+    },
+    Property {
+        tag: PROPERTY_LEVELS,
+        name: "levels",
+        width: 4,
+        since: 13,
+    },
+    Property {
+        tag: PROPERTY_PLACEHOLDERS,
+        name: "placeholders",
+        width: 4,
+        since: 14,
+    },
+    Property {
+        tag: PROPERTY_LAYER_SECTION,
+        name: "layer_section",
+        width: 8,
+        since: 15,
+    },
+    Property {
+        tag: PROPERTY_HNSW,
+        name: "hnsw",
+        // m u32, m0 u32 with zero for none, ef construction u32,
+        // whether neighbors are picked diverse as u8, then the level
+        // multiplier as f32 with zero for none, left out before version 18
+        width: 17,
+        since: 16,
+    },
+    Property {
+        tag: PROPERTY_GROUPS,
+        name: "groups",
+        width: 4,
+        since: 19,
+    },
+];
+
+/// First byte of the bloom filter block, telling whether the
+/// words that follow are up to date with the records.
+pub const BLOOM_FRESH: u8 = 1;
+pub const BLOOM_STALE: u8 = 0;
+
+/// The key table block is the number of entries as u32, then each entry as
+/// the id, the length of the key and its UTF-8 bytes, reserving room for
+/// as many of the longest keys as it holds.
+pub const KEYS_COUNT_WIDTH: u64 = 4;
+pub const KEYS_ENTRY_PREFIX_WIDTH: u64 = RECORD_ID_WIDTH + 1;
+
+/// The level table block is the number of entries as u32, then each entry
+/// in ascending order of id as the id less the one before it in a varint,
+/// the first one less zero, and the top layer of its node as u8, reserving
+/// room for as many of the widest entries as it holds.
+pub const LEVELS_COUNT_WIDTH: u64 = 4;
+/// Five bytes of varint for a whole id and one of level.
+pub const LEVELS_ENTRY_MAX_WIDTH: u64 = 5 + 1;
+
+/// The placeholder table block is the id its window begins at as u32, then
+/// a bit for each id of the window as many as it holds, the lowest first in
+/// the low bit of the first byte, set while the record of the id is a
+/// placeholder yet to be filled.
+pub const PLACEHOLDERS_BASE_WIDTH: u64 = 4;
+
+/// The group table block is the number of entries as u32, then each entry
+/// in ascending order of id as the id less the one before it in a varint,
+/// the first one less zero, and the group of the record as u64, reserving
+/// room for as many of the widest entries as it holds.
+pub const GROUPS_COUNT_WIDTH: u64 = 4;
+/// Five bytes of varint for a whole id and eight of group.
+pub const GROUPS_ENTRY_MAX_WIDTH: u64 = 5 + 8;
+
+/// Records are an id, in append-only databases a generation,
+/// then the components, padded to the alignment if there's one.
+pub const RECORD_ID_WIDTH: u64 = size_of::<DbIndex>() as u64;
+pub const RECORD_GENERATION_WIDTH: u64 = size_of::<Generation>() as u64;
+pub const COMPONENT_WIDTH: u64 = size_of::<f32>() as u64;
+/// Set in the generation of a record that removes its id.
+pub const GENERATION_REMOVED: Generation = 1 << 63;
+
+/// Bytes a record takes before padding.
+pub const fn record_size(dim_size: u32, append_only: bool) -> u64 {
+    let prefix = if append_only {
+        RECORD_ID_WIDTH + RECORD_GENERATION_WIDTH
+    } else {
+        RECORD_ID_WIDTH
+    };
+    prefix + dim_size as u64 * COMPONENT_WIDTH
+}
+
+/// First version whose layers are led by their level, an encoding tag and the
+/// length of their payload. Layers of unknown encodings are skipped.
+pub const LAYERS_TAGGED_SINCE: u8 = 9;
+/// First version whose components are all read as they were written.
+/// Before it, an infinity was taken for the end of the data.
+pub const COMPONENTS_VERBATIM_SINCE: u8 = LAYERS_TAGGED_SINCE;
+/// First version whose layer section is led by the number of layers in it,
+/// and whose dense layers are led by the number of their edges, levels
+/// being as they are with the base at zero. Before it, the base was at one,
+/// and a level of zero ended the layers.
+pub const LAYERS_COUNTED_SINCE: u8 = 17;
+pub const LAYER_COUNT_WIDTH: u64 = 4;
+/// Level, encoding and length before the payload of a layer.
+pub const LAYER_PREFIX_WIDTH: u64 = 4 + 1 + 4;
+/// Payload of the number of edges since [LAYERS_COUNTED_SINCE],
+/// then as many `(a, b, distance)` edges.
+pub const LAYER_ENCODING_DENSE: u8 = 0;
+/// Payload of the id mapping followed by the edges between slots, in varints.
+pub const LAYER_ENCODING_ANYCAST: u8 = 1;
+/// Set on the level of anycast layers before [LAYERS_TAGGED_SINCE],
+/// when dense layers were ended by `(0, 0)` instead of a length.
+pub const LAYER_ANYCAST: u32 = 1 << 31;
+pub const DENSE_COUNT_WIDTH: u64 = 4;
+pub const DENSE_EDGE_WIDTH: u64 = 12;
+
+/// Deterministic database of `dim_size` dimensions with components drawn by
+/// `seed`, for other implementations to check themselves against.
+/// The same arguments give the same bytes for as long as the format stays.
+pub fn golden_file(dim_size: u32, seed: u64) -> Vec<u8> {
+    let file = SharedCursor::new();
+    let mut db = DatabaseOptions::new(dim_size)
+        .create("golden", Box::new(file.reopen()))
+        .unwrap();
+    let mut rng = XorShift::new(seed);
+    for _ in 0..8 {
+        let vector = (0..dim_size).map(|_| rng.next_f32()).collect::<Vec<_>>();
+        db.push(&vector).unwrap();
+    }
+    db.remove(3).unwrap();
+    db.flush().unwrap();
+    file.bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::DatabaseOptions;
+    use crate::vio::dbheader;
+    use crate::vio::format::{golden_file, header_size, CURRENT_VERSION};
+    use crate::vio::inspect;
+    use std::io::Cursor;
+
+    const FIXTURES: [&[u8]; 19] = [
+        include_bytes!("fixtures/v1.db"),
+        include_bytes!("fixtures/v2.db"),
+        include_bytes!("fixtures/v3.db"),
+        include_bytes!("fixtures/v4.db"),
+        include_bytes!("fixtures/v5.db"),
+        include_bytes!("fixtures/v6.db"),
+        include_bytes!("fixtures/v7.db"),
+        include_bytes!("fixtures/v8.db"),
+        include_bytes!("fixtures/v9.db"),
+        include_bytes!("fixtures/v10.db"),
+        include_bytes!("fixtures/v11.db"),
+        include_bytes!("fixtures/v12.db"),
+        include_bytes!("fixtures/v13.db"),
+        include_bytes!("fixtures/v14.db"),
+        include_bytes!("fixtures/v15.db"),
+        include_bytes!("fixtures/v16.db"),
+        include_bytes!("fixtures/v17.db"),
+        include_bytes!("fixtures/v18.db"),
+        include_bytes!("fixtures/v19.db"),
+    ];
+
+    #[test]
+    fn golden_file_matches_fixture() {
+        assert_eq!(golden_file(4, 42), FIXTURES[CURRENT_VERSION as usize - 1]);
+        assert_eq!(header_size(CURRENT_VERSION), dbheader::header_size());
+    }
+
+    #[test]
+    fn every_version_reads() {
+        for (i, fixture) in FIXTURES.iter().enumerate() {
+            let version = i as u8 + 1;
+            let layout = inspect(&mut Cursor::new(fixture.to_vec())).unwrap();
+            assert!(layout.inconsistencies.is_empty(), "version {version}");
+            let field = layout.header.iter().find(|f| f.name == "version").unwrap();
+            assert_eq!(field.value, version.to_string());
+
+            // read as they are, then migrated to the current version
+            for migrate in [false, true] {
+                let db = DatabaseOptions::default()
+                    .read_only(!migrate)
+                    .migrate(migrate)
+                    .open("fixture", Box::new(Cursor::new(fixture.to_vec())))
+                    .unwrap();
+                // the newer ones are golden files
+                if version >= 7 {
+                    assert_eq!(db.count().unwrap(), 7, "version {version}");
+                    assert_eq!(db.recorded_metric().is_some(), version >= 8);
+                    continue;
+                }
+                // the older ones hold ids 0 and 2, id 1 having been removed
+                assert_eq!(db.dim_size(), 2, "version {version}");
+                assert_eq!(db.count().unwrap(), 2, "version {version}");
+                assert_eq!(*db.get(0).unwrap().unwrap(), vec![0.5f32, 1f32]);
+                assert!(db.get(1).unwrap().is_none());
+                assert_eq!(*db.get(2).unwrap().unwrap(), vec![1.5f32, 3f32]);
+            }
+        }
+    }
+}
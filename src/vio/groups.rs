@@ -0,0 +1,57 @@
+use crate::db::{DbIndex, GroupId};
+use crate::vio::format::{GROUPS_COUNT_WIDTH, GROUPS_ENTRY_MAX_WIDTH};
+use crate::vio::{varint, Error, RandomAccess};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+
+/// Bytes reserved by the block after the placeholder table's, holding
+/// `capacity` entries of the widest ids.
+pub(crate) fn block_size(capacity: u32) -> u64 {
+    GROUPS_COUNT_WIDTH + capacity as u64 * GROUPS_ENTRY_MAX_WIDTH
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+/// Reads the entries of the block at the current position, in ascending order of id.
+pub(crate) fn read(
+    fd: &mut dyn RandomAccess,
+    capacity: u32,
+) -> Result<Vec<(DbIndex, GroupId)>, Error> {
+    let count = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+    if count > capacity {
+        return Err(invalid("more groups than the table holds"));
+    }
+    let mut entries = Vec::with_capacity(count as usize);
+    let mut id = 0u64;
+    for i in 0..count {
+        let delta = varint::read(fd)?;
+        if i > 0 && delta == 0 {
+            return Err(invalid("group table repeats an id"));
+        }
+        id += delta;
+        let id = DbIndex::try_from(id).map_err(|_| invalid("id exceeds 32 bits"))?;
+        entries.push((id, fd.read_u64::<BigEndian>().map_err(Error::IO)?));
+    }
+    Ok(entries)
+}
+
+/// Writes `entries`, ascending by id, as the block at the current position,
+/// returning the bytes written, which leave whatever followed the last entry
+/// as it was.
+pub(crate) fn write(
+    fd: &mut dyn RandomAccess,
+    entries: impl ExactSizeIterator<Item = (DbIndex, GroupId)>,
+) -> io::Result<u64> {
+    let mut block = Vec::new();
+    block.write_u32::<BigEndian>(entries.len() as u32)?;
+    let mut last = 0;
+    for (id, group) in entries {
+        varint::write((id - last) as u64, &mut block)?;
+        block.write_u64::<BigEndian>(group)?;
+        last = id;
+    }
+    fd.write_all(&block)?;
+    Ok(block.len() as u64)
+}
@@ -0,0 +1,862 @@
+use crate::db::history;
+use crate::db::{DbIndex, Generation};
+use crate::db::IdStrategy;
+use crate::metric::Metric;
+use crate::vio::bloom;
+use crate::vio::keys;
+use crate::vio::groups;
+use crate::vio::levels;
+use crate::vio::placeholders;
+use crate::vio::varint;
+use crate::vio::dbheader;
+use crate::vio::format::{
+    self, CURRENT_VERSION, FIELD_DATA_SECTION, FIELD_DIM_SIZE, FIELD_ID_STRATEGY,
+    FIELD_PRODUCT, FIELD_PROPERTY_COUNT, FIELD_VERSION, HEADER_SLOTS_SINCE, HEADER_SLOT_SIZE,
+    PRODUCT,
+};
+use crate::vio::{Error, RandomAccess};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io;
+use std::io::SeekFrom;
+
+/// Number of leading components kept per record for a peek.
+const HEAD_COMPONENTS: usize = 4;
+
+/// # File Layout
+/// Best-effort view of a database file's raw structure,
+/// meant for debugging files that won't open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileLayout {
+    pub len: u64,
+    pub header: Vec<HeaderField>,
+    pub layers: Vec<LayerBlock>,
+    pub records: Vec<RecordEntry>,
+    /// Every region that didn't look right, in file order.
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderField {
+    pub name: &'static str,
+    pub offset: u64,
+    pub value: String,
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerBlock {
+    pub offset: u64,
+    /// As stored, which puts the base at one before
+    /// [LAYERS_COUNTED_SINCE](format::LAYERS_COUNTED_SINCE).
+    pub level: u32,
+    /// See [LAYER_ENCODING_DENSE](format::LAYER_ENCODING_DENSE) and the like.
+    pub encoding: u8,
+    /// Zero for encodings this build doesn't know.
+    pub edges: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordEntry {
+    pub offset: u64,
+    pub id: DbIndex,
+    /// Present in append-only databases.
+    pub generation: Option<Generation>,
+    /// Whether the record removes its id, only in append-only databases.
+    pub removed: bool,
+    pub head: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inconsistency {
+    pub offset: u64,
+    pub len: u64,
+    pub reason: String,
+}
+
+impl FileLayout {
+    fn flag(&mut self, offset: u64, len: u64, reason: String) {
+        self.inconsistencies.push(Inconsistency {
+            offset,
+            len,
+            reason,
+        })
+    }
+
+    fn field(&mut self, name: &'static str, offset: u64, value: String, valid: bool) {
+        self.header.push(HeaderField {
+            name,
+            offset,
+            value,
+            valid,
+        })
+    }
+}
+
+/// Walks through a database file, tolerating and flagging whatever
+/// doesn't add up instead of stopping at the first problem.
+/// Fails only if the file can't be read at all.
+pub fn inspect(fd: &mut dyn RandomAccess) -> Result<FileLayout, Error> {
+    let len = fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+    fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+    let mut layout = FileLayout {
+        len,
+        header: vec![],
+        layers: vec![],
+        records: vec![],
+        inconsistencies: vec![],
+    };
+
+    // the oldest headers are the shortest
+    let header_size = format::header_size(1);
+    if len < header_size {
+        layout.flag(
+            0,
+            len,
+            format!("file is shorter than a header ({header_size} bytes)"),
+        );
+        return Ok(layout);
+    }
+
+    let (product, product_valid) = match dbheader::read_product(fd) {
+        Ok(()) => (String::from(PRODUCT), true),
+        Err(dbheader::Error::Parse(reason)) => (reason.to_string(), false),
+        Err(dbheader::Error::IO(e)) => return Err(Error::IO(e)),
+    };
+    layout.field(FIELD_PRODUCT.name, FIELD_PRODUCT.offset, product, product_valid);
+    let version = dbheader::decode_version(fd.read_u8().map_err(Error::IO)?);
+    let version_valid = (1..=CURRENT_VERSION).contains(&version);
+    layout.field(
+        FIELD_VERSION.name,
+        FIELD_VERSION.offset,
+        version.to_string(),
+        version_valid,
+    );
+    // offset of the slot in use, whose fields are the ones shown
+    let mut base = 0;
+    let mut fixed_size = format::header_size(version);
+    if version >= HEADER_SLOTS_SINCE {
+        let slots = dbheader::read_slots(fd).map_err(Error::IO)?;
+        let active = dbheader::active_slot(&slots).map(|slot| slot.offset);
+        for (slot, name) in slots.iter().zip(["slot_a", "slot_b"]) {
+            let unwritten = slot.bytes.iter().all(|b| *b == 0);
+            let shown = match (slot.valid, Some(slot.offset) == active) {
+                (true, true) => format!("sequence {}, in use", slot.sequence),
+                (true, false) => format!("sequence {}", slot.sequence),
+                (false, _) if unwritten => String::from("unwritten"),
+                (false, _) => String::from("torn"),
+            };
+            layout.field(name, slot.offset, shown, slot.valid || unwritten);
+        }
+        let Some(active) = active else {
+            layout.flag(0, 2 * HEADER_SLOT_SIZE, String::from("no valid header slot"));
+            return Ok(layout);
+        };
+        base = active;
+        fixed_size = 2 * HEADER_SLOT_SIZE;
+        fd.seek(SeekFrom::Start(base + FIELD_DATA_SECTION.offset))
+            .map_err(Error::IO)?;
+    }
+    let data_section = fd.read_u64::<BigEndian>().map_err(Error::IO)?;
+    let data_section_valid = (fixed_size..=len).contains(&data_section);
+    layout.field(
+        FIELD_DATA_SECTION.name,
+        base + FIELD_DATA_SECTION.offset,
+        data_section.to_string(),
+        data_section_valid,
+    );
+    let dim_size = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+    layout.field(
+        FIELD_DIM_SIZE.name,
+        base + FIELD_DIM_SIZE.offset,
+        dim_size.to_string(),
+        dim_size > 0,
+    );
+    if version >= FIELD_ID_STRATEGY.since {
+        let raw = fd.read_u8().map_err(Error::IO)?;
+        let strategy = IdStrategy::from_byte(raw);
+        layout.field(
+            FIELD_ID_STRATEGY.name,
+            base + FIELD_ID_STRATEGY.offset,
+            strategy.map_or(raw.to_string(), |s| format!("{s:?}")),
+            strategy.is_some(),
+        );
+        if strategy.is_none() {
+            layout.flag(base + FIELD_ID_STRATEGY.offset, 1, String::from("unknown id strategy"));
+        }
+    }
+    let mut bloom = None;
+    let mut key_table = None;
+    let mut level_table = None;
+    let mut placeholder_table = None;
+    let mut group_table = None;
+    let mut append_only = false;
+    let mut reuse_slots = false;
+    let mut alignment = None;
+    if version >= FIELD_PROPERTY_COUNT.since {
+        let offset = base + FIELD_PROPERTY_COUNT.offset;
+        match dbheader::read_properties(fd) {
+            Ok(properties) => {
+                for (tag, value) in properties {
+                    let name = match tag {
+                        format::PROPERTY_MAX_VECTORS => "max_vectors",
+                        format::PROPERTY_MAX_BYTES => "max_bytes",
+                        format::PROPERTY_LAYER_SECTION => "layer_section",
+                        format::PROPERTY_ALIGNMENT => {
+                            alignment = dbheader::decode_alignment(&value);
+                            let shown = alignment.map_or(format!("{value:02x?}"), |a| a.to_string());
+                            layout.field("alignment", offset, shown, alignment.is_some());
+                            continue;
+                        }
+                        format::PROPERTY_SEARCH => {
+                            let search = dbheader::decode_search(&value);
+                            let shown = search.map_or(format!("{value:02x?}"), |s| {
+                                format!(
+                                    "ef {}, max visited {:?}, rerank {}",
+                                    s.ef_search, s.max_visited, s.rerank_factor
+                                )
+                            });
+                            layout.field("search", offset, shown, search.is_some());
+                            continue;
+                        }
+                        format::PROPERTY_HNSW => {
+                            let hnsw = dbheader::decode_hnsw(&value);
+                            let shown = hnsw.map_or(format!("{value:02x?}"), |c| {
+                                format!(
+                                    "m {}, m0 {:?}, ef construction {}, diverse {}",
+                                    c.m, c.m0, c.ef_construction, c.diverse_neighbors
+                                )
+                            });
+                            layout.field("hnsw", offset, shown, hnsw.is_some());
+                            continue;
+                        }
+                        format::PROPERTY_METRIC => {
+                            let metric = match value.as_slice() {
+                                [raw] => Metric::from_byte(*raw),
+                                _ => None,
+                            };
+                            let shown = metric.map_or(format!("{value:02x?}"), |m| format!("{m:?}"));
+                            layout.field("metric", offset, shown, metric.is_some());
+                            continue;
+                        }
+                        format::PROPERTY_REUSE_SLOTS => {
+                            reuse_slots = true;
+                            let shown = format!("{value:02x?}");
+                            layout.field("reuse_slots", offset, shown, value.is_empty());
+                            continue;
+                        }
+                        format::PROPERTY_KEYS => {
+                            key_table = dbheader::decode_keys(&value);
+                            let shown = key_table.map_or(format!("{value:02x?}"), |k| {
+                                format!("{} keys of up to {} bytes", k.capacity, k.max_key_bytes)
+                            });
+                            layout.field("keys", offset, shown, key_table.is_some());
+                            continue;
+                        }
+                        format::PROPERTY_LEVELS => {
+                            level_table = value.as_slice().try_into().ok().map(u32::from_be_bytes);
+                            let shown = level_table.map_or(format!("{value:02x?}"), |capacity| {
+                                format!("{capacity} levels")
+                            });
+                            layout.field("levels", offset, shown, level_table.is_some());
+                            continue;
+                        }
+                        format::PROPERTY_PLACEHOLDERS => {
+                            placeholder_table =
+                                value.as_slice().try_into().ok().map(u32::from_be_bytes);
+                            let shown = placeholder_table
+                                .map_or(format!("{value:02x?}"), |capacity| format!("{capacity} ids"));
+                            layout.field("placeholders", offset, shown, placeholder_table.is_some());
+                            continue;
+                        }
+                        format::PROPERTY_GROUPS => {
+                            group_table = value.as_slice().try_into().ok().map(u32::from_be_bytes);
+                            let shown = group_table.map_or(format!("{value:02x?}"), |capacity| {
+                                format!("{capacity} groups")
+                            });
+                            layout.field("groups", offset, shown, group_table.is_some());
+                            continue;
+                        }
+                        format::PROPERTY_HISTORY => {
+                            append_only = true;
+                            "history_horizon"
+                        }
+                        format::PROPERTY_BLOOM => {
+                            bloom = dbheader::decode_bloom(&value);
+                            let value = bloom.map_or(format!("{value:02x?}"), |b| {
+                                format!("{} bits, {} hashes", b.bits, b.hashes)
+                            });
+                            layout.field("bloom", offset, value, bloom.is_some());
+                            continue;
+                        }
+                        _ => "unknown property",
+                    };
+                    let valid = value.len() == 8;
+                    let value = match value.try_into() {
+                        Ok(bytes) => u64::from_be_bytes(bytes).to_string(),
+                        Err(value) => format!("{value:02x?}"),
+                    };
+                    layout.field(name, offset, value, valid);
+                }
+            }
+            Err(_) => layout.flag(offset, 1, String::from("truncated properties")),
+        }
+    }
+    let fields_end = fd.stream_position().map_err(Error::IO)?;
+    if !product_valid || !version_valid {
+        layout.flag(
+            0,
+            FIELD_DATA_SECTION.offset,
+            String::from("unknown product or version"),
+        );
+    }
+    if !data_section_valid || dim_size == 0 {
+        layout.flag(
+            base + FIELD_DATA_SECTION.offset,
+            fields_end - base - FIELD_DATA_SECTION.offset,
+            String::from("implausible data section or dimension"),
+        );
+        return Ok(layout);
+    }
+    let mut header_end = if version >= HEADER_SLOTS_SINCE {
+        fixed_size
+    } else {
+        fields_end
+    };
+
+    if let Some(params) = bloom {
+        let block = bloom::block_size(params);
+        if header_end + block > data_section {
+            layout.flag(
+                header_end,
+                data_section.saturating_sub(header_end),
+                String::from("bloom filter runs into data section"),
+            );
+            return Ok(layout);
+        }
+        let fresh = fd.read_u8().map_err(Error::IO)?;
+        layout.field("bloom_fresh", header_end, fresh.to_string(), fresh <= 1);
+        header_end += block;
+    }
+
+    if let Some(params) = key_table {
+        let block = keys::block_size(params);
+        if header_end + block > data_section {
+            layout.flag(
+                header_end,
+                data_section.saturating_sub(header_end),
+                String::from("key table runs into data section"),
+            );
+            return Ok(layout);
+        }
+        fd.seek(SeekFrom::Start(header_end)).map_err(Error::IO)?;
+        let count = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        let valid = count <= params.capacity;
+        layout.field("key_count", header_end, count.to_string(), valid);
+        if !valid {
+            layout.flag(header_end, block, String::from("more keys than the table holds"));
+        }
+        header_end += block;
+    }
+
+    if let Some(capacity) = level_table {
+        let block = levels::block_size(capacity);
+        if header_end + block > data_section {
+            layout.flag(
+                header_end,
+                data_section.saturating_sub(header_end),
+                String::from("level table runs into data section"),
+            );
+            return Ok(layout);
+        }
+        fd.seek(SeekFrom::Start(header_end)).map_err(Error::IO)?;
+        let count = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        let valid = count <= capacity;
+        layout.field("level_count", header_end, count.to_string(), valid);
+        if !valid {
+            layout.flag(header_end, block, String::from("more levels than the table holds"));
+        }
+        header_end += block;
+    }
+
+    if let Some(capacity) = placeholder_table {
+        let block = placeholders::block_size(capacity);
+        if header_end + block > data_section {
+            layout.flag(
+                header_end,
+                data_section.saturating_sub(header_end),
+                String::from("placeholder table runs into data section"),
+            );
+            return Ok(layout);
+        }
+        fd.seek(SeekFrom::Start(header_end)).map_err(Error::IO)?;
+        let (base, unfilled) = placeholders::read(fd, capacity)?;
+        layout.field("placeholder_base", header_end, base.to_string(), true);
+        layout.field(
+            "unfilled",
+            header_end + format::PLACEHOLDERS_BASE_WIDTH,
+            unfilled.len().to_string(),
+            true,
+        );
+        header_end += block;
+    }
+
+    if let Some(capacity) = group_table {
+        let block = groups::block_size(capacity);
+        if header_end + block > data_section {
+            layout.flag(
+                header_end,
+                data_section.saturating_sub(header_end),
+                String::from("group table runs into data section"),
+            );
+            return Ok(layout);
+        }
+        fd.seek(SeekFrom::Start(header_end)).map_err(Error::IO)?;
+        let count = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        let valid = count <= capacity;
+        layout.field("group_count", header_end, count.to_string(), valid);
+        if !valid {
+            layout.flag(header_end, block, String::from("more groups than the table holds"));
+        }
+        header_end += block;
+    }
+
+    if version < format::LAYERS_TAGGED_SINCE {
+        inspect_legacy_layers(fd, &mut layout, header_end, data_section)?;
+    } else {
+        inspect_layers(fd, &mut layout, version, header_end, data_section)?;
+    }
+    inspect_records(
+        fd,
+        &mut layout,
+        data_section,
+        dim_size,
+        append_only,
+        reuse_slots,
+        alignment,
+    )?;
+    Ok(layout)
+}
+
+fn inspect_layers(
+    fd: &mut dyn RandomAccess,
+    layout: &mut FileLayout,
+    version: u8,
+    begin: u64,
+    end: u64,
+) -> Result<(), Error> {
+    let mut pos = begin;
+    // either as many as lead them or up to a level of zero
+    let mut remaining = None;
+    if version >= format::LAYERS_COUNTED_SINCE && pos + format::LAYER_COUNT_WIDTH <= end {
+        fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+        let count = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        layout.field("layer_count", pos, count.to_string(), true);
+        remaining = Some(count);
+        pos += format::LAYER_COUNT_WIDTH;
+    }
+    while remaining != Some(0) {
+        if pos + 4 > end {
+            if let Some(remaining) = remaining {
+                let reason = format!("{remaining} layers counted past data section");
+                layout.flag(pos, end - pos, reason);
+            }
+            return Ok(());
+        }
+        fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+        let level = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        if level == 0 && remaining.is_none() {
+            return Ok(());
+        }
+        remaining = remaining.map(|remaining| remaining - 1);
+        let offset = pos;
+        if pos + format::LAYER_PREFIX_WIDTH > end {
+            layout.flag(offset, end - offset, format!("layer {level} runs into data section"));
+            return Ok(());
+        }
+        let encoding = fd.read_u8().map_err(Error::IO)?;
+        let len = fd.read_u32::<BigEndian>().map_err(Error::IO)? as u64;
+        pos += format::LAYER_PREFIX_WIDTH + len;
+        if pos > end {
+            layout.flag(offset, end - offset, format!("layer {level} runs into data section"));
+            return Ok(());
+        }
+        let edges = match encoding {
+            format::LAYER_ENCODING_DENSE if remaining.is_some() => {
+                match len.checked_sub(format::DENSE_COUNT_WIDTH) {
+                    Some(edges) => {
+                        let count = fd.read_u32::<BigEndian>().map_err(Error::IO)? as u64;
+                        (edges == count * format::DENSE_EDGE_WIDTH).then_some(count)
+                    }
+                    None => None,
+                }
+            }
+            format::LAYER_ENCODING_DENSE if len.is_multiple_of(format::DENSE_EDGE_WIDTH) => {
+                Some(len / format::DENSE_EDGE_WIDTH)
+            }
+            format::LAYER_ENCODING_DENSE => None,
+            format::LAYER_ENCODING_ANYCAST => match skip_anycast(fd) {
+                Ok(edges) if fd.stream_position().map_err(Error::IO)? == pos => edges,
+                Err(Error::IO(e)) if e.kind() != io::ErrorKind::UnexpectedEof => {
+                    return Err(Error::IO(e))
+                }
+                _ => None,
+            },
+            _ => Some(0),
+        };
+        match edges {
+            Some(edges) => layout.layers.push(LayerBlock {
+                offset,
+                level,
+                encoding,
+                edges,
+            }),
+            None => layout.flag(
+                offset,
+                pos - offset,
+                format!("layer {level} doesn't fill its length"),
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Same as [inspect_layers] for files older than [format::LAYERS_TAGGED_SINCE].
+fn inspect_legacy_layers(
+    fd: &mut dyn RandomAccess,
+    layout: &mut FileLayout,
+    begin: u64,
+    end: u64,
+) -> Result<(), Error> {
+    let mut pos = begin;
+    while pos + 4 <= end {
+        fd.seek(SeekFrom::Start(pos)).map_err(Error::IO)?;
+        let level = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        if level == 0 {
+            return Ok(());
+        }
+        let offset = pos;
+        pos += 4;
+        if level & format::LAYER_ANYCAST != 0 {
+            let level = level & !format::LAYER_ANYCAST;
+            let edges = match skip_anycast(fd) {
+                Ok(edges) => edges,
+                Err(Error::IO(e)) if e.kind() != io::ErrorKind::UnexpectedEof => {
+                    return Err(Error::IO(e))
+                }
+                Err(_) => None,
+            };
+            pos = fd.stream_position().map_err(Error::IO)?;
+            match edges {
+                Some(edges) if pos <= end => layout.layers.push(LayerBlock {
+                    offset,
+                    level,
+                    encoding: format::LAYER_ENCODING_ANYCAST,
+                    edges,
+                }),
+                _ => {
+                    layout.flag(
+                        offset,
+                        end - offset,
+                        format!("layer {level} runs into data section"),
+                    );
+                    return Ok(());
+                }
+            }
+            continue;
+        }
+        let mut edges = 0;
+        loop {
+            if pos + 8 > end {
+                layout.flag(
+                    offset,
+                    end - offset,
+                    format!("layer {level} runs into data section"),
+                );
+                return Ok(());
+            }
+            let (a, b) = (
+                fd.read_u32::<BigEndian>().map_err(Error::IO)?,
+                fd.read_u32::<BigEndian>().map_err(Error::IO)?,
+            );
+            pos += 8;
+            if a == 0 && b == 0 {
+                break;
+            }
+            fd.read_f32::<BigEndian>().map_err(Error::IO)?;
+            pos += 4;
+            edges += 1;
+        }
+        layout.layers.push(LayerBlock {
+            offset,
+            level,
+            encoding: format::LAYER_ENCODING_DENSE,
+            edges,
+        });
+    }
+    Ok(())
+}
+
+/// Reads past the mapping and edges of an anycast layer, returning how many
+/// edges there were, or `None` if the layer doesn't add up.
+fn skip_anycast(fd: &mut dyn RandomAccess) -> Result<Option<u64>, Error> {
+    let len = varint::read(fd)?;
+    for _ in 0..len {
+        let (_, slot) = (varint::read(fd)?, varint::read(fd)?);
+        if slot >= len {
+            return Ok(None);
+        }
+    }
+    let edges = varint::read(fd)?;
+    for _ in 0..edges {
+        varint::read(fd)?;
+        varint::read(fd)?;
+        fd.read_f32::<BigEndian>().map_err(Error::IO)?;
+    }
+    Ok(Some(edges))
+}
+
+fn inspect_records(
+    fd: &mut dyn RandomAccess,
+    layout: &mut FileLayout,
+    data_section: u64,
+    dim_size: u32,
+    append_only: bool,
+    reuse_slots: bool,
+    alignment: Option<u32>,
+) -> Result<(), Error> {
+    let prefix = if append_only {
+        history::RECORD_PREFIX
+    } else {
+        size_of::<DbIndex>() as u64
+    };
+    let unpadded = dim_size as u64 * size_of::<f32>() as u64 + prefix;
+    let unit = dbheader::align(unpadded, alignment);
+    let count = (layout.len - data_section) / unit;
+    let trailing = (layout.len - data_section) % unit;
+
+    let mut records = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        fd.seek(SeekFrom::Start(data_section + index * unit))
+            .map_err(Error::IO)?;
+        let id = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        let generation = if append_only {
+            Some(fd.read_u64::<BigEndian>().map_err(Error::IO)?)
+        } else {
+            None
+        };
+        let mut components = vec![0f32; dim_size as usize];
+        fd.read_f32_into::<BigEndian>(&mut components)
+            .map_err(Error::IO)?;
+        let finite = components.iter().all(|c| c.is_finite());
+        components.truncate(HEAD_COMPONENTS);
+        records.push((data_section + index * unit, id, generation, components, finite));
+    }
+
+    // plain records are sorted by id, append-only ones by generation
+    // unless written into the slots of others
+    let key = |(_, id, generation, _, _): &(u64, DbIndex, Option<Generation>, Vec<f32>, bool)| {
+        generation.map_or(*id as u64, |g| g & !history::REMOVED)
+    };
+    let mut last_valid = None;
+    for (index, record) in records.iter().enumerate() {
+        let (offset, id, generation, _, finite) = record;
+        let next = records.get(index + 1).map(key);
+        let mut reasons = vec![];
+        let current = key(record);
+        let misplaced = last_valid.is_some_and(|last| current <= last)
+            || next.is_some_and(|next| current >= next);
+        if misplaced && !reuse_slots {
+            match generation {
+                None => reasons.push(format!("id {id} is out of order")),
+                Some(_) => reasons.push(format!("generation {current} is out of order")),
+            }
+        } else {
+            last_valid = Some(current);
+        }
+        if !finite {
+            reasons.push(String::from("non-finite components"));
+        }
+        if !reasons.is_empty() {
+            layout.flag(*offset, unit, reasons.join(", "));
+        }
+    }
+    layout.records = records
+        .into_iter()
+        .map(|(offset, id, generation, head, _)| RecordEntry {
+            offset,
+            id,
+            generation: generation.map(|g| g & !history::REMOVED),
+            removed: generation.is_some_and(|g| g & history::REMOVED != 0),
+            head,
+        })
+        .collect();
+
+    if trailing > 0 {
+        layout.flag(
+            layout.len - trailing,
+            trailing,
+            format!("{trailing} trailing bytes don't form a record"),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, IdStrategy, Quota};
+    use crate::ds::graph::{AnyCastNdGraph, Graph, NdGraph};
+    use crate::ds::layer::HnswLayer;
+    use crate::ext::mem::SharedCursor;
+    use crate::vio::dbheader::DbHeader;
+    use crate::vio::inspect::inspect;
+    use crate::vio::layer;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+
+    fn healthy_file() -> Vec<u8> {
+        let buf = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4).create("mem", Box::new(buf.reopen())).unwrap();
+        for i in 0..10 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        buf.bytes()
+    }
+
+    #[test]
+    fn inspect_healthy_works() {
+        let mut fd = Cursor::new(healthy_file());
+        let layout = inspect(&mut fd).unwrap();
+        assert!(layout.header.iter().all(|f| f.valid));
+        assert!(layout.layers.is_empty());
+        assert!(layout.inconsistencies.is_empty());
+        assert_eq!(layout.records.len(), 10);
+        assert_eq!(layout.records[3].id, 3);
+        assert_eq!(layout.records[3].head, vec![3f32; 4]);
+    }
+
+    #[test]
+    fn inspect_bloom_works() {
+        let buf = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .bloom_filter(100, 0.01)
+            .create("mem", Box::new(buf.reopen()))
+            .unwrap();
+        for i in 0..10 {
+            db.push([i as f32; 4]).unwrap();
+        }
+        let layout = inspect(&mut Cursor::new(buf.bytes())).unwrap();
+        assert!(layout.inconsistencies.is_empty());
+        assert_eq!(layout.records.len(), 10);
+        let fresh = layout.header.iter().find(|f| f.name == "bloom_fresh").unwrap();
+        assert_eq!(fresh.value, "0");
+    }
+
+    #[test]
+    fn inspect_keys_works() {
+        let buf = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .key_table(10, 36)
+            .create("mem", Box::new(buf.reopen()))
+            .unwrap();
+        for key in ["a", "b"] {
+            db.push_keyed(key, [0f32; 4]).unwrap();
+        }
+        db.flush().unwrap();
+        let layout = inspect(&mut Cursor::new(buf.bytes())).unwrap();
+        assert!(layout.inconsistencies.is_empty());
+        let keys = layout.header.iter().find(|f| f.name == "keys").unwrap();
+        assert_eq!(keys.value, "10 keys of up to 36 bytes");
+        let count = layout.header.iter().find(|f| f.name == "key_count").unwrap();
+        assert_eq!(count.value, "2");
+    }
+
+    #[test]
+    fn inspect_history_works() {
+        let buf = SharedCursor::new();
+        let mut db = DatabaseOptions::new(4)
+            .history(true)
+            .create("mem", Box::new(buf.reopen()))
+            .unwrap();
+        db.push([0f32; 4]).unwrap();
+        db.update(0, [1f32; 4]).unwrap();
+        db.remove(0).unwrap();
+        let layout = inspect(&mut Cursor::new(buf.bytes())).unwrap();
+        assert!(layout.inconsistencies.is_empty());
+        assert_eq!(layout.records.len(), 3);
+        assert!(layout.records.iter().all(|r| r.id == 0));
+        assert_eq!(layout.records[1].generation, Some(2));
+        assert_eq!(layout.records[1].head, vec![1f32; 4]);
+        assert!(layout.records[2].removed);
+    }
+
+    #[test]
+    fn inspect_layers_works() {
+        let mut dense = NdGraph::with_capacity(3);
+        dense.push_many(3);
+        dense.connect(0, 2, 1f32).unwrap();
+        let mut anycast = AnyCastNdGraph::new();
+        anycast.connect(7, 4096, 0.5).unwrap();
+        anycast.connect(4096, 1_000_000, 1.5).unwrap();
+        let mut layers = Cursor::new(Vec::new());
+        layers.write_u32::<BigEndian>(3).unwrap();
+        layer::write(&HnswLayer::new(dense, 1), &mut layers).unwrap();
+        let unknown = layers.position();
+        // written by some newer build
+        layers.write_u32::<BigEndian>(3).unwrap();
+        layers.write_u8(0x7f).unwrap();
+        layers.write_u32::<BigEndian>(2).unwrap();
+        layers.write_all(&[0xff; 2]).unwrap();
+        layer::write(&HnswLayer::anycast(anycast, 2), &mut layers).unwrap();
+        let layers = layers.into_inner();
+
+        let mut header = DbHeader::new(4, IdStrategy::default(), Quota::default());
+        header.data_section = header.size() + layers.len() as u64;
+        let buf = SharedCursor::new();
+        let mut fd = buf.reopen();
+        header.write(&mut fd).unwrap();
+        fd.write_all(&layers).unwrap();
+        let mut db = DatabaseOptions::default()
+            .open("mem", Box::new(buf.reopen()))
+            .unwrap();
+        db.push([0f32; 4]).unwrap();
+
+        let layout = inspect(&mut Cursor::new(buf.bytes())).unwrap();
+        assert!(layout.inconsistencies.is_empty());
+        assert_eq!(layout.records.len(), 1);
+        let edges = layout
+            .layers
+            .iter()
+            .map(|l| (l.level, l.edges))
+            .collect::<Vec<_>>();
+        assert_eq!(edges, vec![(1, 1), (3, 0), (2, 2)]);
+        let skipped = db.skipped_layers();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].offset, header.data_section - layers.len() as u64 + unknown);
+        assert_eq!((skipped[0].level, skipped[0].encoding), (3, 0x7f));
+        let diag = db.index_diagnostics(10, 0).unwrap();
+        assert_eq!(diag[1].level, 2);
+        assert_eq!(diag[1].nodes, 3);
+        assert_eq!(diag[1].degrees, vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn inspect_corrupted_works() {
+        let mut fd = Cursor::new(healthy_file());
+        let bad = inspect(&mut fd).unwrap().records[5].offset;
+        fd.seek(SeekFrom::Start(bad)).unwrap();
+        fd.write_all(&[0xFF; 20]).unwrap();
+        fd.seek(SeekFrom::End(0)).unwrap();
+        fd.write_all(&[0; 3]).unwrap();
+
+        let layout = inspect(&mut fd).unwrap();
+        assert_eq!(layout.records.len(), 10);
+        assert_eq!(layout.inconsistencies.len(), 2);
+        assert_eq!(layout.inconsistencies[0].offset, bad);
+        assert_eq!(layout.inconsistencies[0].len, 20);
+        assert_eq!(
+            layout.inconsistencies[0].reason,
+            "id 4294967295 is out of order, non-finite components"
+        );
+        assert_eq!(layout.inconsistencies[1].len, 3);
+    }
+}
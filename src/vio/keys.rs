@@ -0,0 +1,56 @@
+use crate::db::{DbIndex, KeyTableParams};
+use crate::vio::format::{KEYS_COUNT_WIDTH, KEYS_ENTRY_PREFIX_WIDTH};
+use crate::vio::{Error, RandomAccess};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+
+/// Bytes reserved by the block after the bloom filter's, holding
+/// `params.capacity` entries of the longest keys.
+pub(crate) fn block_size(params: KeyTableParams) -> u64 {
+    KEYS_COUNT_WIDTH
+        + params.capacity as u64 * (KEYS_ENTRY_PREFIX_WIDTH + params.max_key_bytes as u64)
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+/// Reads the entries of the block at the current position.
+pub(crate) fn read(
+    fd: &mut dyn RandomAccess,
+    params: KeyTableParams,
+) -> Result<Vec<(DbIndex, String)>, Error> {
+    let count = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+    if count > params.capacity {
+        return Err(invalid("more keys than the table holds"));
+    }
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let id = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+        let mut key = vec![0u8; fd.read_u8().map_err(Error::IO)? as usize];
+        if key.len() > params.max_key_bytes as usize {
+            return Err(invalid("key longer than the table allows"));
+        }
+        fd.read_exact(&mut key).map_err(Error::IO)?;
+        let key = String::from_utf8(key).map_err(|_| invalid("key isn't UTF-8"))?;
+        entries.push((id, key));
+    }
+    Ok(entries)
+}
+
+/// Writes `entries` as the block at the current position, returning the
+/// bytes written, which leave whatever followed the last entry as it was.
+pub(crate) fn write<'a>(
+    fd: &mut dyn RandomAccess,
+    entries: impl ExactSizeIterator<Item = (DbIndex, &'a str)>,
+) -> io::Result<u64> {
+    let mut block = Vec::new();
+    block.write_u32::<BigEndian>(entries.len() as u32)?;
+    for (id, key) in entries {
+        block.write_u32::<BigEndian>(id)?;
+        block.write_u8(key.len() as u8)?;
+        block.extend_from_slice(key.as_bytes());
+    }
+    fd.write_all(&block)?;
+    Ok(block.len() as u64)
+}
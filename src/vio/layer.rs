@@ -1,27 +1,512 @@
-use crate::ds::graph::{Graph, NdGraph};
+use crate::ds::graph::{AnyCastNdGraph, Graph, NdGraph};
 use crate::ds::layer::HnswLayer;
-use crate::vio::{Error, RandomAccess};
-use byteorder::{BigEndian, ReadBytesExt};
+use crate::vio::format::{
+    CURRENT_VERSION, DENSE_COUNT_WIDTH, DENSE_EDGE_WIDTH, LAYERS_COUNTED_SINCE,
+    LAYERS_TAGGED_SINCE, LAYER_ANYCAST, LAYER_COUNT_WIDTH, LAYER_ENCODING_ANYCAST,
+    LAYER_ENCODING_DENSE, LAYER_PREFIX_WIDTH,
+};
+use crate::vio::{varint, Error, RandomAccess};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::io;
+use std::io::{SeekFrom, Write};
 
-pub(crate) fn read(fd: &mut dyn RandomAccess) -> Result<HnswLayer, Error> {
-    let level = fd.read_u32::<BigEndian>().map_err(|e| Error::IO(e))?;
-    if level == 0 {
-        return Err(Error::EOF);
+/// # Skipped Layer
+/// Layer written with an encoding newer than this build knows,
+/// left out of the index rather than failing the open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkippedLayer {
+    pub offset: u64,
+    pub level: u32,
+    pub encoding: u8,
+    /// Bytes of its payload.
+    pub len: u32,
+}
+
+pub(crate) enum Decoded {
+    Layer(HnswLayer),
+    Skipped(SkippedLayer),
+}
+
+/// # Layer Section
+/// Reads the layers of a section one after another, as many as lead it since
+/// [LAYERS_COUNTED_SINCE]. Before that, until a level of zero or its end.
+pub(crate) struct Section {
+    version: u8,
+    end: u64,
+    remaining: Option<u32>,
+}
+
+impl Section {
+    /// Starts on the section at the current position of a file of `version`,
+    /// ending at `end`. A section too short to lead with a count holds nothing.
+    pub(crate) fn begin(
+        fd: &mut dyn RandomAccess,
+        version: u8,
+        end: u64,
+    ) -> Result<Section, Error> {
+        let pos = fd.stream_position().map_err(Error::IO)?;
+        let remaining = match version >= LAYERS_COUNTED_SINCE {
+            true if pos + LAYER_COUNT_WIDTH <= end => {
+                let count = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+                // every layer takes its prefix at least
+                let room = end - pos - LAYER_COUNT_WIDTH;
+                if count as u64 * LAYER_PREFIX_WIDTH > room {
+                    return Err(invalid("more layers counted than the section holds"));
+                }
+                Some(count)
+            }
+            true => Some(0),
+            false => None,
+        };
+        Ok(Section {
+            version,
+            end,
+            remaining,
+        })
+    }
+
+    /// Reads the next layer, or fails with [Error::Eof] past the last one.
+    pub(crate) fn next(&mut self, fd: &mut dyn RandomAccess) -> Result<Decoded, Error> {
+        match &mut self.remaining {
+            Some(0) => return Err(Error::Eof),
+            Some(remaining) => *remaining -= 1,
+            None if fd.stream_position().map_err(Error::IO)? >= self.end => {
+                return Err(Error::Eof)
+            }
+            None => {}
+        }
+        read(fd, self.version)
+    }
+}
+
+/// Reads the layer at the current position of a file of `version`, or
+/// before [LAYERS_COUNTED_SINCE], fails with [Error::Eof] at the end of the layers.
+///
+/// Since version 9 each layer is its level, an encoding tag and the length
+/// of its payload, so that those of unknown encodings can be skipped.
+/// Before that, anycast layers were flagged on their level instead, and
+/// dense ones were ended by `(0, 0)`. Levels of files before
+/// [LAYERS_COUNTED_SINCE] are read one down, putting the base at zero.
+pub(crate) fn read(fd: &mut dyn RandomAccess, version: u8) -> Result<Decoded, Error> {
+    let offset = fd.stream_position().map_err(Error::IO)?;
+    let level = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+    let counted = version >= LAYERS_COUNTED_SINCE;
+    if level == 0 && !counted {
+        return Err(Error::Eof);
+    }
+    if version < LAYERS_TAGGED_SINCE {
+        return read_legacy(fd, level).map(Decoded::Layer);
+    }
+    let level = if counted { level } else { level - 1 };
+    let encoding = fd.read_u8().map_err(Error::IO)?;
+    let len = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+    let layer = match encoding {
+        LAYER_ENCODING_DENSE => {
+            let edges = match counted {
+                true => read_dense_count(fd, len)?,
+                false if (len as u64).is_multiple_of(DENSE_EDGE_WIDTH) => {
+                    len as u64 / DENSE_EDGE_WIDTH
+                }
+                false => return Err(invalid("dense layer of partial edges")),
+            };
+            let edges = (0..edges)
+                .map(|_| read_dense_edge(fd))
+                .collect::<Result<Vec<_>, _>>()?;
+            HnswLayer::new(NdGraph::from_adj_list(edges), level)
+        }
+        LAYER_ENCODING_ANYCAST => {
+            let layer = read_anycast(fd, level)?;
+            let end = fd.stream_position().map_err(Error::IO)?;
+            if end != offset + LAYER_PREFIX_WIDTH + len as u64 {
+                return Err(invalid("anycast layer doesn't fill its length"));
+            }
+            layer
+        }
+        _ => {
+            fd.seek(SeekFrom::Current(len as i64)).map_err(Error::IO)?;
+            return Ok(Decoded::Skipped(SkippedLayer {
+                offset,
+                level,
+                encoding,
+                len,
+            }));
+        }
+    };
+    Ok(Decoded::Layer(layer))
+}
+
+/// Reads the number of edges leading a dense payload of `len` bytes, failing
+/// unless they fill it, as the uncounted ones of older versions don't.
+fn read_dense_count(fd: &mut dyn RandomAccess, len: u32) -> Result<u64, Error> {
+    if (len as u64) < DENSE_COUNT_WIDTH {
+        return Err(invalid("dense layer without its count"));
+    }
+    let count = fd.read_u32::<BigEndian>().map_err(Error::IO)? as u64;
+    if DENSE_COUNT_WIDTH + count * DENSE_EDGE_WIDTH != len as u64 {
+        return Err(invalid("dense layer of other than the edges it counts"));
+    }
+    Ok(count)
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+fn read_dense_edge(fd: &mut dyn RandomAccess) -> Result<(u32, u32, f32), Error> {
+    Ok((
+        fd.read_u32::<BigEndian>().map_err(Error::IO)?,
+        fd.read_u32::<BigEndian>().map_err(Error::IO)?,
+        fd.read_f32::<BigEndian>().map_err(Error::IO)?,
+    ))
+}
+
+fn read_legacy(fd: &mut dyn RandomAccess, level: u32) -> Result<HnswLayer, Error> {
+    if level & LAYER_ANYCAST != 0 {
+        return read_anycast(fd, (level & !LAYER_ANYCAST).saturating_sub(1));
     }
 
     let mut adj_list = vec![];
     loop {
         let (a, b) = (
-            fd.read_u32::<BigEndian>().map_err(|e| Error::IO(e))?,
-            fd.read_u32::<BigEndian>().map_err(|e| Error::IO(e))?,
+            fd.read_u32::<BigEndian>().map_err(Error::IO)?,
+            fd.read_u32::<BigEndian>().map_err(Error::IO)?,
         );
         if a == 0 && b == 0 {
             break;
         }
-        let distance = fd.read_f32::<BigEndian>().map_err(|e| Error::IO(e))?;
+        let distance = fd.read_f32::<BigEndian>().map_err(Error::IO)?;
         adj_list.push((a, b, distance));
     }
 
     let graph = NdGraph::from_adj_list(adj_list);
-    Ok(HnswLayer::new(graph, level))
+    Ok(HnswLayer::new(graph, level - 1))
+}
+
+/// Reads the mapping as `(original, slot)` varint pairs and then the edges
+/// in slot space as varint nodes and a distance, each list led by its length
+/// as varint.
+fn read_anycast(fd: &mut dyn RandomAccess, level: u32) -> Result<HnswLayer, Error> {
+    let read_u32 = |fd: &mut dyn RandomAccess| {
+        u32::try_from(varint::read(fd)?).map_err(|_| invalid("node exceeds 32 bits"))
+    };
+
+    let len = read_u32(fd)?;
+    let mut mapping = HashMap::with_capacity(len as usize);
+    for _ in 0..len {
+        let (node, slot) = (read_u32(fd)?, read_u32(fd)?);
+        if slot >= len {
+            return Err(invalid("slot out of mapping"));
+        }
+        mapping.insert(node, slot);
+    }
+    let mut slots = NdGraph::with_capacity(len);
+    slots.push_many(len);
+    for _ in 0..varint::read(fd)? {
+        let (a, b) = (read_u32(fd)?, read_u32(fd)?);
+        let distance = fd.read_f32::<BigEndian>().map_err(Error::IO)?;
+        slots
+            .connect(a, b, distance)
+            .map_err(|_| invalid("edge out of mapping"))?;
+    }
+    Ok(HnswLayer::anycast(
+        AnyCastNdGraph::from_parts(mapping, slots),
+        level,
+    ))
+}
+
+/// Writes `layers` as a section of the current version, led by their
+/// number, returning the bytes written.
+pub(crate) fn write_section(layers: &[HnswLayer], fd: &mut dyn Write) -> Result<usize, io::Error> {
+    fd.write_u32::<BigEndian>(layers.len() as u32)?;
+    let mut written = LAYER_COUNT_WIDTH as usize;
+    for layer in layers {
+        written += write(layer, fd)?;
+    }
+    Ok(written)
+}
+
+/// Writes `layer` the way [read] takes it back in the current version,
+/// returning the bytes written. Edges go once, from the smaller node to the other.
+pub(crate) fn write(layer: &HnswLayer, fd: &mut dyn Write) -> Result<usize, io::Error> {
+    write_as(layer, CURRENT_VERSION, fd)
+}
+
+/// Same as [write] in `version`, which is no older than [LAYERS_TAGGED_SINCE].
+pub(crate) fn write_as(
+    layer: &HnswLayer,
+    version: u8,
+    fd: &mut dyn Write,
+) -> Result<usize, io::Error> {
+    let counted = version >= LAYERS_COUNTED_SINCE;
+    let mut payload = vec![];
+    let (level, encoding) = match layer {
+        HnswLayer::Dense { graph, level } => {
+            let edges = graph.edges().collect::<Vec<_>>();
+            if counted {
+                payload.write_u32::<BigEndian>(edges.len() as u32)?;
+            }
+            for (a, b, distance) in edges {
+                payload.write_u32::<BigEndian>(a)?;
+                payload.write_u32::<BigEndian>(b)?;
+                payload.write_f32::<BigEndian>(distance)?;
+            }
+            (*level, LAYER_ENCODING_DENSE)
+        }
+        HnswLayer::AnyCast { graph, level } => {
+            let mut mapping = graph.mapping().iter().collect::<Vec<_>>();
+            mapping.sort_by_key(|(_, slot)| **slot);
+            varint::write(mapping.len() as u64, &mut payload)?;
+            for (node, slot) in mapping {
+                varint::write(*node as u64, &mut payload)?;
+                varint::write(*slot as u64, &mut payload)?;
+            }
+            let edges = graph.slots().edges().collect::<Vec<_>>();
+            varint::write(edges.len() as u64, &mut payload)?;
+            for (a, b, distance) in edges {
+                varint::write(a as u64, &mut payload)?;
+                varint::write(b as u64, &mut payload)?;
+                payload.write_f32::<BigEndian>(distance)?;
+            }
+            (*level, LAYER_ENCODING_ANYCAST)
+        }
+    };
+    fd.write_u32::<BigEndian>(if counted { level } else { level + 1 })?;
+    fd.write_u8(encoding)?;
+    fd.write_u32::<BigEndian>(payload.len() as u32)?;
+    fd.write_all(&payload)?;
+    Ok(LAYER_PREFIX_WIDTH as usize + payload.len())
+}
+
+/// Bytes [write] takes at most for an anycast layer of `nodes` numbered
+/// below `bound`, with `edges` between them.
+pub(crate) fn anycast_len(nodes: u64, edges: u64, bound: u64) -> u64 {
+    let slot = varint::len(nodes.saturating_sub(1));
+    let mapping = varint::len(nodes) + nodes * (varint::len(bound.saturating_sub(1)) + slot);
+    let vertices = varint::len(edges) + edges * (2 * slot + size_of::<f32>() as u64);
+    LAYER_PREFIX_WIDTH + mapping + vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ds::graph::{AnyCastNdGraph, Graph, NdGraph};
+    use crate::ds::layer::HnswLayer;
+    use crate::ext::rand::XorShift;
+    use crate::vio::format::{CURRENT_VERSION, LAYERS_COUNTED_SINCE};
+    use crate::vio::layer::{
+        anycast_len, read, write, write_as, write_section, Decoded, Section, SkippedLayer,
+    };
+    use crate::vio::{Error, RandomAccess};
+    use byteorder::{BigEndian, WriteBytesExt};
+    use std::io::{Cursor, ErrorKind, Seek, SeekFrom, Write};
+
+    fn read_layer(fd: &mut dyn RandomAccess, version: u8) -> HnswLayer {
+        match read(fd, version) {
+            Ok(Decoded::Layer(layer)) => layer,
+            _ => panic!("no layer read"),
+        }
+    }
+
+    #[test]
+    fn anycast_round_trip_works() {
+        let mut graph = AnyCastNdGraph::new();
+        graph.connect(7, 4096, 0.5).unwrap();
+        graph.connect(4096, 1_000_000, 1.5).unwrap();
+        graph.connect(1_000_000, 7, 2.5).unwrap();
+        graph.get_mapping_or_insert(42);
+        let layer = HnswLayer::anycast(graph, 3);
+
+        let mut fd = Cursor::new(Vec::new());
+        let written = write(&layer, &mut fd).unwrap();
+        assert_eq!(written as u64, fd.stream_position().unwrap());
+        fd.seek(SeekFrom::Start(0)).unwrap();
+        let read = read_layer(&mut fd, CURRENT_VERSION);
+        assert_eq!(fd.stream_position().unwrap(), written as u64);
+
+        assert!(matches!(read, HnswLayer::AnyCast { level: 3, .. }));
+        assert_eq!(read.nodes(), vec![7, 42, 4096, 1_000_000]);
+        assert_eq!(read.vertices(7), vec![(4096, 0.5), (1_000_000, 2.5)]);
+        assert_eq!(read.vertices(4096), vec![(7, 0.5), (1_000_000, 1.5)]);
+        assert!(read.vertices(42).is_empty());
+        assert!(read.vertices(8).is_empty());
+    }
+
+    #[test]
+    fn dense_round_trip_works() {
+        let mut graph = NdGraph::with_capacity(4);
+        graph.push_many(4);
+        graph.connect(0, 3, 1f32).unwrap();
+        graph.connect(1, 2, 2f32).unwrap();
+        let layer = HnswLayer::new(graph, 1);
+
+        let mut fd = Cursor::new(Vec::new());
+        let written = write(&layer, &mut fd).unwrap();
+        fd.seek(SeekFrom::Start(0)).unwrap();
+        let read = read_layer(&mut fd, CURRENT_VERSION);
+        assert_eq!(fd.stream_position().unwrap(), written as u64);
+        assert!(matches!(read, HnswLayer::Dense { level: 1, .. }));
+        assert_eq!(read.vertices(3), vec![(0, 1f32)]);
+        assert_eq!(read.vertices(2), vec![(1, 2f32)]);
+    }
+
+    #[test]
+    fn random_layers_round_trip() {
+        let mut rng = XorShift::new(23);
+        for round in 0..200 {
+            // the first rounds are of no nodes and of nodes without vertices
+            let nodes = match round {
+                0 => 0,
+                _ => 1 + rng.next_u64() % 40,
+            } as u32;
+            let vertices = match round {
+                0 | 1 => 0,
+                _ => rng.next_u64() % (nodes as u64 * 4),
+            };
+            let level = 1 + (rng.next_u64() % 8) as u32;
+            let numbers = (0..nodes).map(|_| rng.next_u64() as u32 >> 1).collect::<Vec<_>>();
+            let (mut adj_list, mut anycast) = (vec![], AnyCastNdGraph::new());
+            for number in &numbers {
+                anycast.get_mapping_or_insert(*number);
+            }
+            for _ in 0..vertices {
+                let a = (rng.next_u64() % nodes as u64) as u32;
+                let b = (rng.next_u64() % nodes as u64) as u32;
+                let distance = rng.next_f32() * 10f32;
+                adj_list.push((a, b, distance));
+                anycast.connect(numbers[a as usize], numbers[b as usize], distance).unwrap();
+            }
+            let dense = NdGraph::from_adj_list(adj_list);
+
+            for layer in [HnswLayer::new(dense, level), HnswLayer::anycast(anycast, level)] {
+                let mut fd = Cursor::new(Vec::new());
+                let written = write(&layer, &mut fd).unwrap();
+                fd.seek(SeekFrom::Start(0)).unwrap();
+                let read = read_layer(&mut fd, CURRENT_VERSION);
+                assert_eq!(fd.stream_position().unwrap(), written as u64);
+                assert_eq!(read.level(), level);
+                assert_eq!(read.edges(), layer.edges(), "round {round}");
+                // dense layers are numbered up to their last connected node
+                if let HnswLayer::AnyCast { .. } = layer {
+                    assert_eq!(read.nodes(), layer.nodes(), "round {round}");
+                    let (edges, bound) = (layer.edges().len() as u64, 1u64 << 31);
+                    assert!(written as u64 <= anycast_len(nodes as u64, edges, bound));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn counted_section_round_trip() {
+        // the base at zero, node zero linked to itself and to others
+        let mut base = NdGraph::with_capacity(4);
+        base.push_many(4);
+        base.connect(0, 0, 0f32).unwrap();
+        base.connect(0, 3, 1f32).unwrap();
+        base.connect(1, 2, 2f32).unwrap();
+        let mut anycast = AnyCastNdGraph::new();
+        anycast.connect(0, 0, 0f32).unwrap();
+        anycast.connect(0, 5, 0.5f32).unwrap();
+        let layers = [
+            HnswLayer::new(base, 0),
+            HnswLayer::anycast(anycast, 0),
+            HnswLayer::new(NdGraph::new(), 1),
+        ];
+
+        let mut fd = Cursor::new(Vec::new());
+        let written = write_section(&layers, &mut fd).unwrap();
+        // room left past the layers reads as nothing more
+        fd.write_all(&[0; 16]).unwrap();
+        let end = fd.stream_position().unwrap();
+        fd.seek(SeekFrom::Start(0)).unwrap();
+        let mut section = Section::begin(&mut fd, CURRENT_VERSION, end).unwrap();
+        for layer in &layers {
+            let Ok(Decoded::Layer(read)) = section.next(&mut fd) else {
+                panic!("no layer read");
+            };
+            assert_eq!(read.level(), layer.level());
+            assert_eq!(read.edges(), layer.edges());
+        }
+        assert_eq!(fd.stream_position().unwrap(), written as u64);
+        assert!(matches!(section.next(&mut fd), Err(Error::Eof)));
+        assert_eq!(layers[0].edges()[0], (0, 0, 0f32));
+
+        // an empty section, and one too short to count its layers
+        for end in [4, 3] {
+            let mut fd = Cursor::new(vec![0; 4]);
+            let mut section = Section::begin(&mut fd, CURRENT_VERSION, end).unwrap();
+            assert!(matches!(section.next(&mut fd), Err(Error::Eof)));
+        }
+        // more counted than there's room for
+        let mut fd = Cursor::new(vec![0, 0, 0, 2, 0, 0, 0, 0]);
+        let counted = Section::begin(&mut fd, CURRENT_VERSION, 8);
+        assert!(matches!(counted, Err(Error::IO(e)) if e.kind() == ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn uncounted_layers_rejected() {
+        let mut graph = NdGraph::with_capacity(3);
+        graph.push_many(3);
+        graph.connect(0, 2, 1f32).unwrap();
+        graph.connect(1, 2, 2f32).unwrap();
+        let layer = HnswLayer::new(graph, 0);
+        let mut fd = Cursor::new(Vec::new());
+        write_as(&layer, LAYERS_COUNTED_SINCE - 1, &mut fd).unwrap();
+
+        // the base one up and its edges uncounted, as in the version before
+        fd.seek(SeekFrom::Start(0)).unwrap();
+        let older = read_layer(&mut fd, LAYERS_COUNTED_SINCE - 1);
+        assert_eq!(older.level(), 0);
+        assert_eq!(older.edges(), layer.edges());
+        fd.seek(SeekFrom::Start(0)).unwrap();
+        let current = read(&mut fd, CURRENT_VERSION);
+        assert!(matches!(current, Err(Error::IO(e)) if e.kind() == ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn legacy_layers_read() {
+        let mut fd = Cursor::new(Vec::new());
+        fd.write_u32::<BigEndian>(1).unwrap();
+        for (a, b, distance) in [(0, 3, 1f32), (1, 2, 2f32)] {
+            fd.write_u32::<BigEndian>(a).unwrap();
+            fd.write_u32::<BigEndian>(b).unwrap();
+            fd.write_f32::<BigEndian>(distance).unwrap();
+        }
+        fd.write_u64::<BigEndian>(0).unwrap();
+        fd.seek(SeekFrom::Start(0)).unwrap();
+        // the base was at one then
+        let read = read_layer(&mut fd, 8);
+        assert!(matches!(read, HnswLayer::Dense { level: 0, .. }));
+        assert_eq!(read.vertices(3), vec![(0, 1f32)]);
+        assert_eq!(read.vertices(2), vec![(1, 2f32)]);
+    }
+
+    #[test]
+    fn unknown_encoding_skipped() {
+        let mut graph = NdGraph::with_capacity(2);
+        graph.push_many(2);
+        graph.connect(0, 1, 1f32).unwrap();
+        let mut fd = Cursor::new(Vec::new());
+        // some encoding of the future
+        fd.write_u32::<BigEndian>(2).unwrap();
+        fd.write_u8(0x7f).unwrap();
+        fd.write_u32::<BigEndian>(5).unwrap();
+        fd.write_all(&[0xff; 5]).unwrap();
+        write(&HnswLayer::new(graph, 1), &mut fd).unwrap();
+        fd.seek(SeekFrom::Start(0)).unwrap();
+
+        let Ok(Decoded::Skipped(skipped)) = read(&mut fd, CURRENT_VERSION) else {
+            panic!("unknown encoding not skipped");
+        };
+        assert_eq!(
+            skipped,
+            SkippedLayer {
+                offset: 0,
+                level: 2,
+                encoding: 0x7f,
+                len: 5,
+            }
+        );
+        let read = read_layer(&mut fd, CURRENT_VERSION);
+        assert_eq!(read.vertices(1), vec![(0, 1f32)]);
+    }
 }
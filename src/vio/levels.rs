@@ -0,0 +1,54 @@
+use crate::db::DbIndex;
+use crate::vio::format::{LEVELS_COUNT_WIDTH, LEVELS_ENTRY_MAX_WIDTH};
+use crate::vio::{varint, Error, RandomAccess};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+
+/// Bytes reserved by the block after the key table's, holding
+/// `capacity` entries of the widest ids.
+pub(crate) fn block_size(capacity: u32) -> u64 {
+    LEVELS_COUNT_WIDTH + capacity as u64 * LEVELS_ENTRY_MAX_WIDTH
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+/// Reads the entries of the block at the current position, in ascending order of id.
+pub(crate) fn read(fd: &mut dyn RandomAccess, capacity: u32) -> Result<Vec<(DbIndex, u8)>, Error> {
+    let count = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+    if count > capacity {
+        return Err(invalid("more levels than the table holds"));
+    }
+    let mut entries = Vec::with_capacity(count as usize);
+    let mut id = 0u64;
+    for i in 0..count {
+        let delta = varint::read(fd)?;
+        if i > 0 && delta == 0 {
+            return Err(invalid("level table repeats an id"));
+        }
+        id += delta;
+        let id = DbIndex::try_from(id).map_err(|_| invalid("id exceeds 32 bits"))?;
+        entries.push((id, fd.read_u8().map_err(Error::IO)?));
+    }
+    Ok(entries)
+}
+
+/// Writes `entries`, ascending by id, as the block at the current position,
+/// returning the bytes written, which leave whatever followed the last entry
+/// as it was.
+pub(crate) fn write(
+    fd: &mut dyn RandomAccess,
+    entries: impl ExactSizeIterator<Item = (DbIndex, u8)>,
+) -> io::Result<u64> {
+    let mut block = Vec::new();
+    block.write_u32::<BigEndian>(entries.len() as u32)?;
+    let mut last = 0;
+    for (id, level) in entries {
+        varint::write((id - last) as u64, &mut block)?;
+        block.write_u8(level)?;
+        last = id;
+    }
+    fd.write_all(&block)?;
+    Ok(block.len() as u64)
+}
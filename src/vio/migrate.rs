@@ -0,0 +1,419 @@
+use crate::db::Error;
+use crate::ext::io::MoveContent;
+use crate::vio::dbheader::{self, DbHeader};
+use crate::vio::format::{CURRENT_VERSION, LAYERS_TAGGED_SINCE};
+use crate::vio::layer::{self, Decoded};
+use crate::vio::{self, bloom, RandomAccess};
+use std::cmp::min;
+use std::io::{self, Cursor, SeekFrom};
+
+/// Bytes copied at once while moving the records.
+const CHUNK_SIZE: usize = 4096;
+
+/// # Migration Step
+/// One version upgraded to the next, see [migrate].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationStep {
+    pub from: u8,
+    pub to: u8,
+    pub description: &'static str,
+}
+
+/// # Migration Report
+/// What [migrate] did to a file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// In the order they were applied, empty if the file was up to date.
+    pub steps: Vec<MigrationStep>,
+    /// Bytes of the migrated file, all of which were written anew.
+    pub bytes_rewritten: u64,
+}
+
+/// Everything before the records, which is all that changes between versions.
+struct Prefix {
+    header: DbHeader,
+    /// The bloom filter block as it was.
+    bloom: Vec<u8>,
+    /// Encoded in the version of the header.
+    layers: Vec<u8>,
+}
+
+struct Migration {
+    from: u8,
+    description: &'static str,
+    apply: fn(&mut Prefix) -> Result<(), Error>,
+}
+
+/// Version `from` to `from + 1` for every version before the current one.
+/// Most versions only added what older files lack anyway, whose steps
+/// have nothing to do beyond bumping the version.
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize - 1] = [
+    Migration {
+        from: 1,
+        description: "id strategy, monotonic as before",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 2,
+        description: "header properties",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 3,
+        description: "bloom filter",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 4,
+        description: "history",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 5,
+        description: "alignment",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 6,
+        description: "search parameters, recorded as the defaults",
+        apply: |prefix| {
+            prefix.header.search.get_or_insert_default();
+            Ok(())
+        },
+    },
+    Migration {
+        from: 7,
+        description: "metric, left unrecorded",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 8,
+        description: "layers tagged with their encoding and length",
+        apply: tag_layers,
+    },
+    Migration {
+        from: 9,
+        description: "header in two checksummed slots",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 10,
+        description: "reusable record slots, left off",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 11,
+        description: "key table, left off",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 12,
+        description: "level table, left off",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 13,
+        description: "placeholder table, left off",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 14,
+        description: "layer section, left off",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 15,
+        description: "hnsw parameters, left off",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 16,
+        description: "layers counted, the base at level zero",
+        apply: count_layers,
+    },
+    Migration {
+        from: 17,
+        description: "level multiplier of the hnsw parameters, left to the default",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 18,
+        description: "group table, left off",
+        apply: |_| Ok(()),
+    },
+];
+
+fn tag_layers(prefix: &mut Prefix) -> Result<(), Error> {
+    let mut legacy = Cursor::new(std::mem::take(&mut prefix.layers));
+    let len = legacy.get_ref().len() as u64;
+    while legacy.position() < len {
+        match layer::read(&mut legacy, LAYERS_TAGGED_SINCE - 1) {
+            Ok(Decoded::Layer(layer)) => {
+                layer::write_as(&layer, LAYERS_TAGGED_SINCE, &mut prefix.layers)
+                    .map_err(Error::IO)?;
+            }
+            Ok(Decoded::Skipped(_)) => unreachable!("legacy layers are never skipped"),
+            // alignment padding too short to read a level from
+            Err(vio::Error::IO(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(vio::Error::IO(e)) => return Err(Error::IO(e)),
+            Err(vio::Error::Eof) => break,
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the layers after the tables into a section led by their number,
+/// growing the layer section if there's one to hold it.
+fn count_layers(prefix: &mut Prefix) -> Result<(), Error> {
+    let begin = prefix.header.prefix_end() - prefix.header.keys_offset();
+    let begin = min(begin, prefix.layers.len() as u64) as usize;
+    let mut uncounted = Cursor::new(prefix.layers.split_off(begin));
+    let end = uncounted.get_ref().len() as u64;
+    let mut section = layer::Section::begin(&mut uncounted, prefix.header.version, end)
+        .map_err(|e| match e {
+            vio::Error::Eof => Error::Parse(),
+            vio::Error::IO(e) => Error::IO(e),
+        })?;
+    let mut layers = vec![];
+    loop {
+        match section.next(&mut uncounted) {
+            Ok(Decoded::Layer(layer)) => layers.push(layer),
+            // those of unknown encodings can't be rewritten, and are left out
+            Ok(Decoded::Skipped(_)) => {}
+            // alignment padding too short to read a level from
+            Err(vio::Error::IO(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(vio::Error::IO(e)) => return Err(Error::IO(e)),
+            Err(vio::Error::Eof) => break,
+        }
+    }
+    // nothing to count where there's neither a layer nor room for them
+    if layers.is_empty() && prefix.header.layer_section.is_none() {
+        return Ok(());
+    }
+    let written = layer::write_section(&layers, &mut prefix.layers).map_err(Error::IO)?;
+    if let Some(reserved) = prefix.header.layer_section {
+        let reserved = reserved.max(written as u64);
+        prefix.header.layer_section = Some(reserved);
+        prefix.layers.resize(begin + reserved as usize, 0);
+    }
+    Ok(())
+}
+
+/// Upgrades the file behind `fd` to `to_version` by the migrations from its
+/// version on, one version at a time. The records are kept byte for byte,
+/// while everything before them is rewritten.
+///
+/// The migrated file is written past the end of the original one first,
+/// which stays intact until it's complete, and then moved over it.
+/// Fails with [Error::NoMigration] if the file is newer than `to_version`
+/// or `to_version` is newer than this build.
+pub fn migrate(fd: &mut dyn RandomAccess, to_version: u8) -> Result<MigrationReport, Error> {
+    let len = fd.seek(SeekFrom::End(0)).map_err(Error::IO)?;
+    fd.seek(SeekFrom::Start(0)).map_err(Error::IO)?;
+    let header = dbheader::read(fd).map_err(Error::Header)?;
+    let mut report = MigrationReport::default();
+    if header.version > to_version || to_version > CURRENT_VERSION {
+        return Err(Error::NoMigration(header.version, to_version));
+    }
+    if header.version == to_version {
+        return Ok(report);
+    }
+
+    let records_begin = min(header.data_section, len);
+    let header_end = fd.stream_position().map_err(Error::IO)?;
+    let bloom_end = header.bloom.map_or(header_end, |params| {
+        min(header_end + bloom::block_size(params), records_begin)
+    });
+    let read_region = |fd: &mut dyn RandomAccess, end: u64| {
+        let begin = fd.stream_position()?;
+        let mut region = vec![0u8; end.saturating_sub(begin) as usize];
+        fd.read_exact(&mut region)?;
+        Ok(region)
+    };
+    let mut prefix = Prefix {
+        bloom: read_region(fd, bloom_end).map_err(Error::IO)?,
+        layers: read_region(fd, records_begin).map_err(Error::IO)?,
+        header,
+    };
+    for migration in &MIGRATIONS[prefix.header.version as usize - 1..to_version as usize - 1] {
+        (migration.apply)(&mut prefix)?;
+        prefix.header.version = migration.from + 1;
+        report.steps.push(MigrationStep {
+            from: migration.from,
+            to: migration.from + 1,
+            description: migration.description,
+        });
+    }
+
+    let prefix_len = prefix.header.size() + (prefix.bloom.len() + prefix.layers.len()) as u64;
+    prefix.header.data_section = dbheader::align(prefix_len, prefix.header.alignment);
+    let records = len - records_begin;
+    let migrated = prefix.header.data_section + records;
+    fd.seek(SeekFrom::Start(len)).map_err(Error::IO)?;
+    prefix.header.write(fd).map_err(Error::Header)?;
+    fd.write_all(&prefix.bloom).map_err(Error::IO)?;
+    fd.write_all(&prefix.layers).map_err(Error::IO)?;
+    fd.write_all(&vec![0u8; (prefix.header.data_section - prefix_len) as usize])
+        .map_err(Error::IO)?;
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    let mut copied = 0;
+    while copied < records {
+        let size = min(records - copied, CHUNK_SIZE as u64) as usize;
+        fd.seek(SeekFrom::Start(records_begin + copied))
+            .map_err(Error::IO)?;
+        fd.read_exact(&mut chunk[..size]).map_err(Error::IO)?;
+        fd.seek(SeekFrom::Start(len + prefix.header.data_section + copied))
+            .map_err(Error::IO)?;
+        fd.write_all(&chunk[..size]).map_err(Error::IO)?;
+        copied += size as u64;
+    }
+
+    fd.seek(SeekFrom::Start(len)).map_err(Error::IO)?;
+    fd.move_content(migrated as usize, -(len as isize), CHUNK_SIZE)
+        .map_err(Error::IO)?;
+    fd.set_len(migrated).map_err(Error::IO)?;
+    fd.sync_data().map_err(Error::IO)?;
+    report.bytes_rewritten = migrated;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{DatabaseOptions, Error, IdStrategy, Quota};
+    use crate::ds::graph::{AnyCastNdGraph, Graph, NdGraph};
+    use crate::ds::layer::HnswLayer;
+    use crate::vio::dbheader::DbHeader;
+    use crate::vio::format::{
+        CURRENT_VERSION, LAYERS_COUNTED_SINCE, LAYER_ENCODING_DENSE, PRODUCT,
+    };
+    use crate::vio::{inspect, layer, migrate};
+    use byteorder::{BigEndian, WriteBytesExt};
+    use std::io::{Cursor, Write};
+
+    /// Version 1 has the version as a digit of text, no id strategy
+    /// and no properties, followed by legacy layers and the records.
+    fn v1_file() -> Vec<u8> {
+        let mut file = vec![];
+        write!(file, "{PRODUCT}1").unwrap();
+        // the product, version, data section and dimension, then two layers
+        let data_section = PRODUCT.len() as u64 + 1 + 8 + 4 + 2 * (4 + 12 + 8);
+        file.write_u64::<BigEndian>(data_section).unwrap();
+        file.write_u32::<BigEndian>(2).unwrap();
+        for (level, a, b) in [(1, 0, 2), (2, 2, 3)] {
+            file.write_u32::<BigEndian>(level).unwrap();
+            file.write_u32::<BigEndian>(a).unwrap();
+            file.write_u32::<BigEndian>(b).unwrap();
+            file.write_f32::<BigEndian>(0.5).unwrap();
+            file.write_u64::<BigEndian>(0).unwrap();
+        }
+        assert_eq!(file.len() as u64, data_section);
+        for id in 0..4 {
+            file.write_u32::<BigEndian>(id).unwrap();
+            file.write_f32::<BigEndian>(id as f32).unwrap();
+            file.write_f32::<BigEndian>(-(id as f32)).unwrap();
+        }
+        file
+    }
+
+    #[test]
+    fn migration_works() {
+        let mut fd = Cursor::new(v1_file());
+        let report = migrate(&mut fd, CURRENT_VERSION).unwrap();
+        assert_eq!(report.steps.len(), CURRENT_VERSION as usize - 1);
+        assert_eq!((report.steps[0].from, report.steps[0].to), (1, 2));
+        assert_eq!(report.bytes_rewritten, fd.get_ref().len() as u64);
+
+        let layout = inspect(&mut fd).unwrap();
+        assert!(layout.inconsistencies.is_empty());
+        let version = layout.header.iter().find(|f| f.name == "version").unwrap();
+        assert_eq!(version.value, CURRENT_VERSION.to_string());
+        assert_eq!(
+            layout
+                .layers
+                .iter()
+                .map(|l| (l.level, l.encoding, l.edges))
+                .collect::<Vec<_>>(),
+            vec![(0, LAYER_ENCODING_DENSE, 1), (1, LAYER_ENCODING_DENSE, 1)]
+        );
+        assert_eq!(layout.records.len(), 4);
+
+        let db = DatabaseOptions::default()
+            .open("migrated", Box::new(fd.clone()))
+            .unwrap();
+        for id in 0..4 {
+            assert_eq!(*db.get(id).unwrap().unwrap(), vec![id as f32, -(id as f32)]);
+        }
+        // up to date already
+        assert_eq!(migrate(&mut fd, CURRENT_VERSION).unwrap().steps, vec![]);
+        assert!(matches!(
+            migrate(&mut fd, CURRENT_VERSION - 1),
+            Err(Error::NoMigration(_, _))
+        ));
+    }
+
+    #[test]
+    fn layers_counted_on_migration() {
+        // the base and a layer above it, put one up the way version 16 had them
+        let mut base = NdGraph::with_capacity(3);
+        base.push_many(3);
+        base.connect(0, 1, 0.5).unwrap();
+        base.connect(0, 2, 1.5).unwrap();
+        let mut upper = AnyCastNdGraph::new();
+        upper.connect(0, 2, 1.5).unwrap();
+        let version = LAYERS_COUNTED_SINCE - 1;
+        let mut header = DbHeader::new(2, IdStrategy::default(), Quota::default())
+            .with_layer_section(64);
+        header.version = version;
+        let mut fd = Cursor::new(vec![]);
+        header.write(&mut fd).unwrap();
+        fd.set_position(header.prefix_end());
+        layer::write_as(&HnswLayer::new(base, 0), version, &mut fd).unwrap();
+        layer::write_as(&HnswLayer::anycast(upper, 1), version, &mut fd).unwrap();
+        fd.get_mut().resize(header.data_section as usize, 0);
+        fd.set_position(header.data_section);
+        for id in 0..3 {
+            fd.write_u32::<BigEndian>(id).unwrap();
+            fd.write_f32::<BigEndian>(id as f32).unwrap();
+            fd.write_f32::<BigEndian>(-(id as f32)).unwrap();
+        }
+
+        let report = migrate(&mut fd, CURRENT_VERSION).unwrap();
+        assert_eq!(report.steps[0].from, version);
+        assert!(inspect(&mut fd).unwrap().inconsistencies.is_empty());
+        let db = DatabaseOptions::default()
+            .verify(true)
+            .open("migrated", Box::new(fd))
+            .unwrap();
+        let layers = db
+            .layers()
+            .map(|view| (view.level(), view.edges().collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layers,
+            vec![(0, vec![(0, 1, 0.5), (0, 2, 1.5)]), (1, vec![(0, 2, 1.5)])]
+        );
+        assert_eq!(*db.get(2).unwrap().unwrap(), vec![2f32, -2f32]);
+    }
+
+    #[test]
+    fn old_versions_need_migration() {
+        let file = v1_file();
+        let opened = DatabaseOptions::default().open("v1", Box::new(Cursor::new(file.clone())));
+        assert!(matches!(
+            opened,
+            Err(Error::NeedsMigration(1, CURRENT_VERSION))
+        ));
+        // reading alone leaves the file as it is
+        let db = DatabaseOptions::default()
+            .read_only(true)
+            .open("v1", Box::new(Cursor::new(file.clone())))
+            .unwrap();
+        assert_eq!(db.count().unwrap(), 4);
+
+        let db = DatabaseOptions::default()
+            .migrate(true)
+            .open("v1", Box::new(Cursor::new(file)))
+            .unwrap();
+        assert_eq!(*db.get(3).unwrap().unwrap(), vec![3f32, -3f32]);
+    }
+}
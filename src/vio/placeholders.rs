@@ -0,0 +1,61 @@
+use crate::db::DbIndex;
+use crate::vio::format::PLACEHOLDERS_BASE_WIDTH;
+use crate::vio::{Error, RandomAccess};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+
+/// Bytes reserved by the block after the level table's, a bit
+/// for each of the `capacity` ids of its window.
+pub(crate) fn block_size(capacity: u32) -> u64 {
+    PLACEHOLDERS_BASE_WIDTH + capacity.div_ceil(8) as u64
+}
+
+/// Where the byte holding the bit of the id `index` places past
+/// the beginning of the window is, from the beginning of the block.
+pub(crate) fn byte_offset(index: u32) -> u64 {
+    PLACEHOLDERS_BASE_WIDTH + (index / 8) as u64
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+/// Reads the block at the current position, returning where its window
+/// begins and the ids whose bits are set, in ascending order.
+pub(crate) fn read(
+    fd: &mut dyn RandomAccess,
+    capacity: u32,
+) -> Result<(DbIndex, Vec<DbIndex>), Error> {
+    let base = fd.read_u32::<BigEndian>().map_err(Error::IO)?;
+    let mut bits = vec![0u8; capacity.div_ceil(8) as usize];
+    fd.read_exact(&mut bits).map_err(Error::IO)?;
+    let mut ids = vec![];
+    for index in 0..capacity {
+        if bits[(index / 8) as usize] & (1 << (index % 8)) != 0 {
+            let id = base
+                .checked_add(index)
+                .ok_or_else(|| invalid("placeholder id exceeds 32 bits"))?;
+            ids.push(id);
+        }
+    }
+    Ok((base, ids))
+}
+
+/// Writes the block at the current position for a window beginning at
+/// `base` holding `capacity` ids, with the bits of `ids` set, which
+/// have to fall within it. Returns the bytes written.
+pub(crate) fn write(
+    fd: &mut dyn RandomAccess,
+    base: DbIndex,
+    capacity: u32,
+    ids: impl IntoIterator<Item = DbIndex>,
+) -> io::Result<u64> {
+    let mut block = Vec::with_capacity(block_size(capacity) as usize);
+    block.write_u32::<BigEndian>(base)?;
+    block.resize(block_size(capacity) as usize, 0);
+    for id in ids {
+        block[byte_offset(id - base) as usize] |= 1 << ((id - base) % 8);
+    }
+    fd.write_all(&block)?;
+    Ok(block.len() as u64)
+}
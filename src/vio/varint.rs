@@ -0,0 +1,60 @@
+use crate::vio::Error;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::io::{Read, Write};
+
+/// Writes `value` seven bits a byte, least significant first,
+/// with the high bit set on every byte but the last.
+pub(crate) fn write(value: u64, fd: &mut dyn Write) -> Result<usize, io::Error> {
+    let mut value = value;
+    let mut written = 1;
+    while value >= 0x80 {
+        fd.write_u8(value as u8 | 0x80)?;
+        value >>= 7;
+        written += 1;
+    }
+    fd.write_u8(value as u8)?;
+    Ok(written)
+}
+
+/// Bytes [write] takes for `value`.
+pub(crate) fn len(value: u64) -> u64 {
+    (64 - value.leading_zeros() as u64).div_ceil(7).max(1)
+}
+
+pub(crate) fn read(fd: &mut dyn Read) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = fd.read_u8().map_err(Error::IO)?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::IO(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint longer than 64 bits",
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vio::varint::{len, read, write};
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    #[test]
+    fn varint_works() {
+        let mut fd = Cursor::new(Vec::new());
+        let values = [0, 1, 127, 128, 300, 1_000_000, u64::MAX];
+        let written = values
+            .iter()
+            .map(|v| write(*v, &mut fd).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(written, vec![1, 1, 1, 2, 2, 3, 10]);
+        assert!(values.iter().zip(written).all(|(v, written)| len(*v) == written as u64));
+        fd.seek(SeekFrom::Start(0)).unwrap();
+        for value in values {
+            assert_eq!(read(&mut fd).unwrap(), value);
+        }
+    }
+}
@@ -1,29 +1,123 @@
 use crate::db::{DbVector, DbVectorSlice};
 use crate::vio::Error;
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt};
 use std::io;
 use std::io::{BufReader, Read, Write};
 
-pub(crate) fn read(dim_size: u32, fd: &mut dyn Read) -> Result<DbVector, Error> {
+/// Reads a vector of `dim_size` components. Unless `verbatim`, as files
+/// before [COMPONENTS_VERBATIM_SINCE](crate::vio::format::COMPONENTS_VERBATIM_SINCE) are read, an infinity ends the data
+/// with [Error::Eof]. Otherwise every component comes as it was written,
+/// to the bit.
+pub(crate) fn read(dim_size: u32, fd: &mut dyn Read, verbatim: bool) -> Result<DbVector, Error> {
+    #[cfg(feature = "bytemuck")]
+    if verbatim {
+        return packed::read(dim_size, fd).map_err(Error::IO);
+    }
     let mut buf_reader = BufReader::with_capacity(dim_size as usize * size_of::<f32>(), fd);
     let mut res = Vec::with_capacity(dim_size as usize);
     for _ in 0..dim_size {
         let component = buf_reader
             .read_f32::<BigEndian>()
-            .map_err(|e| Error::IO(e))?;
-        if component == f32::INFINITY {
-            return Err(Error::EOF);
+            .map_err(Error::IO)?;
+        if !verbatim && component == f32::INFINITY {
+            return Err(Error::Eof);
         }
         res.push(component);
     }
     Ok(res)
 }
 
+/// Same as [read] from `bytes` already read, decoding into `into`
+/// to spare allocating a vector each time.
+pub(crate) fn decode(bytes: &[u8], into: &mut DbVector, verbatim: bool) -> Result<(), Error> {
+    #[cfg(feature = "bytemuck")]
+    if verbatim && packed::decode(bytes, into) {
+        return Ok(());
+    }
+    into.clear();
+    for component in bytes.chunks_exact(size_of::<f32>()) {
+        let component = f32::from_be_bytes(component.try_into().unwrap());
+        if !verbatim && component == f32::INFINITY {
+            return Err(Error::Eof);
+        }
+        into.push(component);
+    }
+    Ok(())
+}
+
+/// Same as [decode] into an array of `D` components, `bytes` holding that many.
+#[inline]
+pub(crate) fn decode_array<const D: usize>(bytes: &[u8], verbatim: bool) -> Result<[f32; D], Error> {
+    let array: [f32; D] = std::array::from_fn(|i| {
+        let begin = i * size_of::<f32>();
+        f32::from_be_bytes(bytes[begin..begin + size_of::<f32>()].try_into().unwrap())
+    });
+    // checked apart from decoding for the latter to unroll
+    if !verbatim && array.contains(&f32::INFINITY) {
+        return Err(Error::Eof);
+    }
+    Ok(array)
+}
+
+#[cfg(not(feature = "bytemuck"))]
 pub(crate) fn write(vector: DbVectorSlice, fd: &mut dyn Write) -> Result<usize, io::Error> {
+    use byteorder::WriteBytesExt;
+
     for component in vector {
         fd.write_f32::<BigEndian>(*component)?;
     }
-    Ok(vector.len() * size_of::<f32>())
+    Ok(size_of_val(vector))
+}
+
+/// Same as the component by component write, in one go.
+#[cfg(feature = "bytemuck")]
+pub(crate) fn write(vector: DbVectorSlice, fd: &mut dyn Write) -> Result<usize, io::Error> {
+    packed::write(vector, fd)?;
+    Ok(size_of_val(vector))
+}
+
+/// Whole vectors reinterpreted from and to their bytes, which are swapped
+/// in bulk on little-endian hosts and left as they are on big-endian ones,
+/// rather than taken a component at a time.
+#[cfg(feature = "bytemuck")]
+mod packed {
+    use crate::db::{DbVector, DbVectorSlice};
+    use std::io;
+    use std::io::{Read, Write};
+
+    /// Reads straight into the vector, which is aligned for its components.
+    pub(super) fn read(dim_size: u32, fd: &mut dyn Read) -> io::Result<DbVector> {
+        let mut vector = vec![0f32; dim_size as usize];
+        fd.read_exact(bytemuck::cast_slice_mut(&mut vector))?;
+        if cfg!(target_endian = "little") {
+            for component in &mut vector {
+                *component = f32::from_bits(u32::from_be(component.to_bits()));
+            }
+        }
+        Ok(vector)
+    }
+
+    /// Decodes `bytes` into `into`, unless they're not aligned for
+    /// components, in which case it's left alone and `false` returned.
+    pub(super) fn decode(bytes: &[u8], into: &mut DbVector) -> bool {
+        let Ok(words) = bytemuck::try_cast_slice::<u8, u32>(bytes) else {
+            return false;
+        };
+        into.clear();
+        into.extend(words.iter().map(|word| f32::from_bits(u32::from_be(*word))));
+        true
+    }
+
+    pub(super) fn write(vector: DbVectorSlice, fd: &mut dyn Write) -> io::Result<()> {
+        if cfg!(target_endian = "big") {
+            return fd.write_all(bytemuck::cast_slice(vector));
+        }
+        let words = vector
+            .iter()
+            .map(|component| component.to_bits().to_be())
+            .collect::<Vec<u32>>();
+        fd.write_all(bytemuck::cast_slice(&words))
+    }
 }
 
 #[cfg(test)]
@@ -42,7 +136,7 @@ mod tests {
         
         assert_eq!(
             Vec::from_iter((1..=32).map(|i| 1f32 / i as f32)),
-            read(32, &mut fd).unwrap()
+            read(32, &mut fd, true).unwrap()
         )
     }
     
@@ -50,11 +144,106 @@ mod tests {
     fn write_works() {
         let v = Vec::from_iter((1..=32).map(|i| 1f32 / i as f32));
         let mut fd = Cursor::new(Vec::new());
-        write(&*v, &mut fd).unwrap();
+        write(&v, &mut fd).unwrap();
         fd.seek(SeekFrom::Start(0)).unwrap();
         assert_eq!(
             v,
-            read(32, &mut fd).unwrap()
+            read(32, &mut fd, true).unwrap()
         )
     }
+
+    /// Random vectors along with the components easiest to get wrong.
+    #[cfg(feature = "bytemuck")]
+    fn tricky_vectors(dim_size: usize) -> Vec<Vec<f32>> {
+        let mut rng = crate::ext::rand::XorShift::new(41);
+        let mut vectors = (0..64).map(|_| rng.vector(dim_size as u32)).collect::<Vec<_>>();
+        vectors.push(vec![
+            -0f32,
+            0f32,
+            f32::from_bits(1),
+            -f32::from_bits(0x007f_ffff),
+            f32::MIN_POSITIVE,
+            f32::MAX,
+            f32::NEG_INFINITY,
+            f32::from_bits(0x7fc0_1234),
+        ]);
+        vectors.last_mut().unwrap().resize(dim_size, -0f32);
+        vectors
+    }
+
+    #[cfg(feature = "bytemuck")]
+    fn bits(vector: &[f32]) -> Vec<u32> {
+        vector.iter().map(|x| x.to_bits()).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "bytemuck")]
+    fn packed_matches_bytewise() {
+        use crate::vio::vector::decode;
+
+        for vector in tricky_vectors(8) {
+            let mut expected = Vec::new();
+            for x in &vector {
+                expected.write_f32::<BigEndian>(*x).unwrap();
+            }
+            let mut written = Vec::new();
+            assert_eq!(write(&vector, &mut written).unwrap(), 32);
+            assert_eq!(written, expected);
+
+            // verbatim reads are packed, the others by component
+            let packed = read(8, &mut Cursor::new(&expected), true).unwrap();
+            let bytewise = read(8, &mut Cursor::new(&expected), false).unwrap();
+            assert_eq!(bits(&packed), bits(&vector));
+            assert_eq!(bits(&bytewise), bits(&vector));
+
+            // both aligned and not, the latter falling back
+            let mut buffer = vec![0u32; 9];
+            let bytes = bytemuck::cast_slice_mut::<u32, u8>(&mut buffer);
+            for offset in [0, 1] {
+                bytes[offset..offset + 32].copy_from_slice(&expected);
+                let mut decoded = vec![1f32];
+                decode(&bytes[offset..offset + 32], &mut decoded, true).unwrap();
+                assert_eq!(bits(&decoded), bits(&vector));
+            }
+        }
+
+        let mut infinite = Vec::new();
+        write(&[f32::INFINITY; 2], &mut infinite).unwrap();
+        assert_eq!(read(2, &mut Cursor::new(&infinite), true).unwrap(), [f32::INFINITY; 2]);
+        assert!(read(2, &mut Cursor::new(&infinite), false).is_err());
+        assert!(read(3, &mut Cursor::new(&infinite), true).is_err());
+    }
+
+    /// Run with `cargo test --release --features bytemuck -- --ignored --nocapture`
+    /// for the throughput of decoding either way.
+    #[test]
+    #[ignore]
+    #[cfg(feature = "bytemuck")]
+    fn packed_decode_scales() {
+        use std::time::Instant;
+
+        const DIM: usize = 1536;
+        let vectors = tricky_vectors(DIM);
+        let mut file = Vec::new();
+        for vector in &vectors {
+            write(vector, &mut file).unwrap();
+        }
+        let rounds = 2000;
+        for verbatim in [false, true] {
+            let begin = Instant::now();
+            let mut checksum = 0u32;
+            for _ in 0..rounds {
+                let mut fd = Cursor::new(&file);
+                for _ in &vectors {
+                    checksum = checksum.wrapping_add(read(DIM as u32, &mut fd, verbatim).unwrap()[0].to_bits());
+                }
+            }
+            let bytes = (rounds * file.len()) as f64;
+            let name = if verbatim { "packed" } else { "bytewise" };
+            println!(
+                "{name}: {:.0} MiB/s ({checksum:x})",
+                bytes / begin.elapsed().as_secs_f64() / (1 << 20) as f64
+            );
+        }
+    }
 }